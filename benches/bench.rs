@@ -1,6 +1,12 @@
 use criterion::*;
 use glam::Vec3A;
-use voxel_ray_tracer::ray_tracer::{dense::DenseStorage, octree::SparseStorage, Config, RayTracer};
+use voxel_ray_tracer::ray_tracer::{
+    dense::DenseStorage,
+    octree::SparseStorage,
+    sky::TimeOfDay,
+    types::{NEAR, ON_BOUNDARY},
+    Config, RayTracer,
+};
 
 fn bench_1080p(c: &mut Criterion) {
     let mut group = c.benchmark_group("storage-solution-1080p");
@@ -11,24 +17,44 @@ fn bench_1080p(c: &mut Criterion) {
             res_width: 1920,
             res_height: 1080,
             size: 50,
+            height: None,
+            world: None,
             camera_pos: 40.0 * Vec3A::ONE,
             debug: false,
+            lod: false,
+            packets: false,
+            beams: false,
+            cones: false,
+            near: NEAR,
+            far: f32::INFINITY,
+            epsilon: ON_BOUNDARY,
+            double: false,
+            antialias: false,
+            dither: false,
+            hdr: false,
+            shade: false,
+            hard_normals: false,
+            smooth_surface: false,
+            toon: false,
+            clouds: None,
+            time: 0.0,
+            time_of_day: TimeOfDay::Day,
+            path: None,
+            caustics: None,
+            subsurface: false,
+            fog: None,
+            transparency: false,
         };
 
+        let dense_ray_tracer = RayTracer::<DenseStorage>::new(config);
+        let sparse_ray_tracer = RayTracer::<SparseStorage>::new(config);
+
         group.bench_function("dense-50x", |b| {
-            b.iter_batched(
-                || RayTracer::<DenseStorage>::new(config),
-                |tracer| black_box(tracer.render()),
-                BatchSize::PerIteration,
-            );
+            b.iter(|| black_box(dense_ray_tracer.render()))
         });
 
         group.bench_function("sparse-50x", |b| {
-            b.iter_batched(
-                || RayTracer::<SparseStorage>::new(config),
-                |tracer| black_box(tracer.render()),
-                BatchSize::PerIteration,
-            );
+            b.iter(|| black_box(sparse_ray_tracer.render()))
         });
     }
 
@@ -38,24 +64,44 @@ fn bench_1080p(c: &mut Criterion) {
             res_width: 1920,
             res_height: 1080,
             size: 100,
+            height: None,
+            world: None,
             camera_pos: 90.0 * Vec3A::ONE,
             debug: false,
+            lod: false,
+            packets: false,
+            beams: false,
+            cones: false,
+            near: NEAR,
+            far: f32::INFINITY,
+            epsilon: ON_BOUNDARY,
+            double: false,
+            antialias: false,
+            dither: false,
+            hdr: false,
+            shade: false,
+            hard_normals: false,
+            smooth_surface: false,
+            toon: false,
+            clouds: None,
+            time: 0.0,
+            time_of_day: TimeOfDay::Day,
+            path: None,
+            caustics: None,
+            subsurface: false,
+            fog: None,
+            transparency: false,
         };
 
+        let dense_ray_tracer = RayTracer::<DenseStorage>::new(config);
+        let sparse_ray_tracer = RayTracer::<SparseStorage>::new(config);
+
         group.bench_function("dense-100x", |b| {
-            b.iter_batched(
-                || RayTracer::<DenseStorage>::new(config),
-                |tracer| black_box(tracer.render()),
-                BatchSize::PerIteration,
-            );
+            b.iter(|| black_box(dense_ray_tracer.render()))
         });
 
         group.bench_function("sparse-100x", |b| {
-            b.iter_batched(
-                || RayTracer::<SparseStorage>::new(config),
-                |tracer| black_box(tracer.render()),
-                BatchSize::PerIteration,
-            );
+            b.iter(|| black_box(sparse_ray_tracer.render()))
         });
     }
 
@@ -65,24 +111,44 @@ fn bench_1080p(c: &mut Criterion) {
             res_width: 1920,
             res_height: 1080,
             size: 250,
+            height: None,
+            world: None,
             camera_pos: 240.0 * Vec3A::ONE,
             debug: false,
+            lod: false,
+            packets: false,
+            beams: false,
+            cones: false,
+            near: NEAR,
+            far: f32::INFINITY,
+            epsilon: ON_BOUNDARY,
+            double: false,
+            antialias: false,
+            dither: false,
+            hdr: false,
+            shade: false,
+            hard_normals: false,
+            smooth_surface: false,
+            toon: false,
+            clouds: None,
+            time: 0.0,
+            time_of_day: TimeOfDay::Day,
+            path: None,
+            caustics: None,
+            subsurface: false,
+            fog: None,
+            transparency: false,
         };
 
+        let dense_ray_tracer = RayTracer::<DenseStorage>::new(config);
+        let sparse_ray_tracer = RayTracer::<SparseStorage>::new(config);
+
         group.bench_function("dense-250x", |b| {
-            b.iter_batched(
-                || RayTracer::<DenseStorage>::new(config),
-                |tracer| black_box(tracer.render()),
-                BatchSize::PerIteration,
-            );
+            b.iter(|| black_box(dense_ray_tracer.render()))
         });
 
         group.bench_function("sparse-250x", |b| {
-            b.iter_batched(
-                || RayTracer::<SparseStorage>::new(config),
-                |tracer| black_box(tracer.render()),
-                BatchSize::PerIteration,
-            );
+            b.iter(|| black_box(sparse_ray_tracer.render()))
         });
     }
 }
@@ -96,8 +162,33 @@ fn bench_4k(c: &mut Criterion) {
             res_width: 7680,
             res_height: 4320,
             size: 50,
+            height: None,
+            world: None,
             camera_pos: 40.0 * Vec3A::ONE,
             debug: false,
+            lod: false,
+            packets: false,
+            beams: false,
+            cones: false,
+            near: NEAR,
+            far: f32::INFINITY,
+            epsilon: ON_BOUNDARY,
+            double: false,
+            antialias: false,
+            dither: false,
+            hdr: false,
+            shade: false,
+            hard_normals: false,
+            smooth_surface: false,
+            toon: false,
+            clouds: None,
+            time: 0.0,
+            time_of_day: TimeOfDay::Day,
+            path: None,
+            caustics: None,
+            subsurface: false,
+            fog: None,
+            transparency: false,
         };
 
         let dense_ray_tracer = RayTracer::<DenseStorage>::new(config);
@@ -118,8 +209,33 @@ fn bench_4k(c: &mut Criterion) {
             res_width: 7680,
             res_height: 4320,
             size: 100,
+            height: None,
+            world: None,
             camera_pos: 90.0 * Vec3A::ONE,
             debug: false,
+            lod: false,
+            packets: false,
+            beams: false,
+            cones: false,
+            near: NEAR,
+            far: f32::INFINITY,
+            epsilon: ON_BOUNDARY,
+            double: false,
+            antialias: false,
+            dither: false,
+            hdr: false,
+            shade: false,
+            hard_normals: false,
+            smooth_surface: false,
+            toon: false,
+            clouds: None,
+            time: 0.0,
+            time_of_day: TimeOfDay::Day,
+            path: None,
+            caustics: None,
+            subsurface: false,
+            fog: None,
+            transparency: false,
         };
 
         let dense_ray_tracer = RayTracer::<DenseStorage>::new(config);
@@ -140,8 +256,33 @@ fn bench_4k(c: &mut Criterion) {
             res_width: 7680,
             res_height: 4320,
             size: 250,
+            height: None,
+            world: None,
             camera_pos: 240.0 * Vec3A::ONE,
             debug: false,
+            lod: false,
+            packets: false,
+            beams: false,
+            cones: false,
+            near: NEAR,
+            far: f32::INFINITY,
+            epsilon: ON_BOUNDARY,
+            double: false,
+            antialias: false,
+            dither: false,
+            hdr: false,
+            shade: false,
+            hard_normals: false,
+            smooth_surface: false,
+            toon: false,
+            clouds: None,
+            time: 0.0,
+            time_of_day: TimeOfDay::Day,
+            path: None,
+            caustics: None,
+            subsurface: false,
+            fog: None,
+            transparency: false,
         };
 
         let dense_ray_tracer = RayTracer::<DenseStorage>::new(config);
@@ -0,0 +1,66 @@
+;; Stone pillars on a grass plain, rendered with `--plugin plugins/pillars.wat` (needs `--features wasm`).
+;;
+;; Written in the WebAssembly text format so it needs no compiler, but any language that builds a module with
+;; the same exports works, e.g. Rust functions marked `#[no_mangle] pub extern "C"` built for wasm32-unknown-unknown.
+(module
+  (memory (export "memory") 1)
+  (global $seed (mut i32) (i32.const 0))
+  (global $size (mut i32) (i32.const 64))
+
+  (func (export "init") (param $seed i32) (param $size i32)
+    (global.set $seed (local.get $seed))
+    (global.set $size (local.get $size)))
+
+  ;; Height of the pillar in a column, or 0 between pillars.
+  (func $height (param $x i32) (param $z i32) (result i32)
+    (local $h i32)
+    ;; pillars fill the middle 4x4 of every 8x8 cell
+    (if (i32.or
+          (i32.ge_u (i32.sub (i32.and (local.get $x) (i32.const 7)) (i32.const 2)) (i32.const 4))
+          (i32.ge_u (i32.sub (i32.and (local.get $z) (i32.const 7)) (i32.const 2)) (i32.const 4)))
+      (then (return (i32.const 0))))
+    ;; hash the cell and the seed into a height up to the scene size
+    (local.set $h (i32.xor
+      (i32.mul (i32.shr_s (local.get $x) (i32.const 3)) (i32.const 73856093))
+      (i32.mul (i32.shr_s (local.get $z) (i32.const 3)) (i32.const 19349663))))
+    (local.set $h (i32.mul (i32.xor (local.get $h) (global.get $seed)) (i32.const 0x2c1b3c6d)))
+    (local.set $h (i32.xor (local.get $h) (i32.shr_u (local.get $h) (i32.const 16))))
+    (i32.rem_u (local.get $h) (i32.add (global.get $size) (i32.const 1))))
+
+  ;; Color of the voxel at a height in a column, or -1 if it is empty.
+  (func $voxel (param $y i32) (param $height i32) (result i32)
+    (if (i32.eq (local.get $y) (i32.const -1))
+      (then (return (i32.const 0x4caf50))))
+    (if (i32.and
+          (i32.ge_s (local.get $y) (i32.const 0))
+          (i32.lt_s (local.get $y) (local.get $height)))
+      (then (return (select (i32.const 0xe0e0e0) (i32.const 0x8a8a8a)
+        (i32.eq (local.get $y) (i32.sub (local.get $height) (i32.const 1)))))))
+    (i32.const -1))
+
+  (func (export "lookup") (param $x i32) (param $y i32) (param $z i32) (result i32)
+    (call $voxel (local.get $y) (call $height (local.get $x) (local.get $z))))
+
+  ;; Writes a column of colors to the start of memory.
+  (func (export "column") (param $x i32) (param $z i32) (param $y i32) (param $len i32) (result i32)
+    (local $height i32)
+    (local $i i32)
+    (local $pages i32)
+    ;; make room for the column
+    (local.set $pages (i32.sub
+      (i32.add (i32.shr_u (i32.shl (local.get $len) (i32.const 2)) (i32.const 16)) (i32.const 1))
+      (memory.size)))
+    (if (i32.gt_s (local.get $pages) (i32.const 0))
+      (then (drop (memory.grow (local.get $pages)))))
+
+    (local.set $height (call $height (local.get $x) (local.get $z)))
+    (block $done
+      (loop $next
+        (br_if $done (i32.ge_s (local.get $i) (local.get $len)))
+        (i32.store
+          (i32.shl (local.get $i) (i32.const 2))
+          (call $voxel (i32.add (local.get $y) (local.get $i)) (local.get $height)))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $next)))
+    (i32.const 0))
+)
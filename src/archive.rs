@@ -0,0 +1,385 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt, fs,
+    io::{self, BufReader, BufWriter, Read, Write},
+    ops::Range,
+    path::Path,
+};
+
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use glam::{IVec3, U8Vec3};
+
+use crate::{
+    ray_tracer::types::IAabb,
+    voxel::{Voxel, VoxelKind, VoxelSource},
+};
+
+const MAGIC: &[u8; 8] = b"VOXSCENE";
+/// Version 2 added voxel kinds to the palette, version 1 files are still read with unknown kinds.
+const VERSION: u32 = 2;
+
+/// A run of identical voxels along a column, where palette index 0 is empty.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Run {
+    len: u32,
+    index: u32,
+}
+
+/// Voxels of a scene's bounding box saved once and rendered again without regenerating them.
+///
+/// Voxels are kept as runs along the columns of the box, with each distinct voxel stored once in a palette.
+///
+/// Files start with a header (magic, version, bounding box) and the palette of colors and kinds,
+/// followed by the runs of every column compressed with zlib.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SceneArchive {
+    bb: IAabb,
+    palette: Vec<Voxel>,
+    /// Runs from the bottom of each column, indexed by `x + width * z`.
+    columns: Vec<Vec<Run>>,
+}
+
+impl SceneArchive {
+    /// Collects voxels inside of the bounding box from a source.
+    pub fn from_source<S: VoxelSource + ?Sized>(source: &S, bb: IAabb) -> Self {
+        let mut palette = Vec::new();
+        let mut indices = HashMap::new();
+        let mut columns = Vec::with_capacity(bb.width() * bb.length());
+        let mut column = vec![None; bb.height()];
+
+        for z in bb.iter_z() {
+            for x in bb.iter_x() {
+                source.column(x, z, bb.iter_y(), &mut column);
+
+                let mut runs: Vec<Run> = Vec::new();
+                for voxel in &column {
+                    let index = match voxel {
+                        Some(voxel) => *indices.entry(*voxel).or_insert_with(|| {
+                            palette.push(*voxel);
+                            palette.len() as u32
+                        }),
+                        None => 0,
+                    };
+                    match runs.last_mut() {
+                        Some(run) if run.index == index => run.len += 1,
+                        _ => runs.push(Run { len: 1, index }),
+                    }
+                }
+                columns.push(runs);
+            }
+        }
+
+        Self {
+            bb,
+            palette,
+            columns,
+        }
+    }
+
+    /// Bounding box the voxels were collected from.
+    pub fn bounds(&self) -> IAabb {
+        self.bb
+    }
+
+    /// Number of distinct voxels.
+    pub fn palette_len(&self) -> usize {
+        self.palette.len()
+    }
+
+    /// Saves the archive to a file.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), ArchiveError> {
+        let mut file = BufWriter::new(fs::File::create(path)?);
+        self.write(&mut file)?;
+        file.flush()?;
+        Ok(())
+    }
+
+    /// Loads an archive from a file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ArchiveError> {
+        Self::read(BufReader::new(fs::File::open(path)?))
+    }
+
+    /// Writes the archive in its file format.
+    pub fn write(&self, mut writer: impl Write) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        for v in [self.bb.origin, self.bb.extents] {
+            for axis in v.to_array() {
+                writer.write_all(&axis.to_le_bytes())?;
+            }
+        }
+
+        writer.write_all(&(self.palette.len() as u32).to_le_bytes())?;
+        for voxel in &self.palette {
+            writer.write_all(&voxel.color.to_array())?;
+            writer.write_all(&voxel.kind.0.to_le_bytes())?;
+        }
+
+        let mut data = Vec::new();
+        for runs in &self.columns {
+            write_varint(&mut data, runs.len() as u32);
+            for run in runs {
+                write_varint(&mut data, run.len);
+                write_varint(&mut data, run.index);
+            }
+        }
+        let mut encoder = ZlibEncoder::new(writer, Compression::default());
+        encoder.write_all(&data)?;
+        encoder.finish()?;
+        Ok(())
+    }
+
+    /// Reads an archive in its file format.
+    pub fn read(mut reader: impl Read) -> Result<Self, ArchiveError> {
+        let mut header = [0; 40];
+        reader.read_exact(&mut header)?;
+        if &header[..8] != MAGIC {
+            return Err(ArchiveError::Format("not a scene archive".into()));
+        }
+        let field = |i: usize| {
+            u32::from_le_bytes(header[8 + 4 * i..][..4].try_into().expect("took 4 bytes"))
+        };
+
+        let version = field(0);
+        if !(1..=VERSION).contains(&version) {
+            return Err(ArchiveError::Format(format!(
+                "unsupported version {version}"
+            )));
+        }
+        let origin = IVec3::new(field(1) as i32, field(2) as i32, field(3) as i32);
+        let extents = IVec3::new(field(4) as i32, field(5) as i32, field(6) as i32);
+        if extents.cmple(IVec3::ZERO).any() {
+            return Err(ArchiveError::Format("bounding box is empty".into()));
+        }
+        let bb = IAabb::new(origin, extents);
+
+        let palette_len = field(7);
+        let mut palette = Vec::new();
+        for _ in 0..palette_len {
+            let mut color = [0; 3];
+            reader.read_exact(&mut color)?;
+            let mut kind = [0; 2];
+            if version >= 2 {
+                reader.read_exact(&mut kind)?;
+            }
+            palette.push(Voxel::new(
+                U8Vec3::from_array(color),
+                VoxelKind(u16::from_le_bytes(kind)),
+            ));
+        }
+
+        let mut data = Vec::new();
+        ZlibDecoder::new(reader).read_to_end(&mut data)?;
+        let mut bytes = data.iter();
+        let mut varint = || {
+            read_varint(&mut bytes)
+                .ok_or_else(|| ArchiveError::Format("truncated voxel data".into()))
+        };
+
+        let mut columns = Vec::new();
+        for _ in 0..bb.width() * bb.length() {
+            let mut runs = Vec::new();
+            let mut height = 0;
+            for _ in 0..varint()? {
+                let run = Run {
+                    len: varint()?,
+                    index: varint()?,
+                };
+                if run.index > palette_len {
+                    return Err(ArchiveError::Format(format!(
+                        "palette index {} out of range",
+                        run.index
+                    )));
+                }
+                height += run.len as usize;
+                runs.push(run);
+            }
+            if height != bb.height() {
+                return Err(ArchiveError::Format("column height mismatch".into()));
+            }
+            columns.push(runs);
+        }
+
+        Ok(Self {
+            bb,
+            palette,
+            columns,
+        })
+    }
+
+    fn runs(&self, x: i32, z: i32) -> Option<&[Run]> {
+        let min = self.bb.min();
+        let max = self.bb.max();
+        if x < min.x || x >= max.x || z < min.z || z >= max.z {
+            return None;
+        }
+        let i = (x - min.x) as usize + self.bb.width() * (z - min.z) as usize;
+        Some(&self.columns[i])
+    }
+
+    fn voxel(&self, index: u32) -> Option<Voxel> {
+        index.checked_sub(1).map(|i| self.palette[i as usize])
+    }
+}
+
+impl VoxelSource for SceneArchive {
+    fn lookup(&self, pos: IVec3) -> Option<Voxel> {
+        let runs = self.runs(pos.x, pos.z)?;
+        let mut y = self.bb.min().y;
+        for run in runs {
+            y += run.len as i32;
+            if pos.y < y {
+                // positions below the box are also before the first run
+                return (pos.y >= self.bb.min().y)
+                    .then(|| self.voxel(run.index))
+                    .flatten();
+            }
+        }
+        None
+    }
+
+    fn column(&self, x: i32, z: i32, ys: Range<i32>, out: &mut [Option<Voxel>]) {
+        debug_assert_eq!(out.len(), ys.len(), "column length mismatch");
+
+        out.fill(None);
+        let Some(runs) = self.runs(x, z) else {
+            return;
+        };
+
+        let mut start = self.bb.min().y;
+        for run in runs {
+            let end = start + run.len as i32;
+            let voxel = self.voxel(run.index);
+            if voxel.is_some() {
+                for y in start.max(ys.start)..end.min(ys.end) {
+                    out[(y - ys.start) as usize] = voxel;
+                }
+            }
+            start = end;
+        }
+    }
+}
+
+/// Writes an unsigned LEB128 varint.
+fn write_varint(out: &mut Vec<u8>, mut value: u32) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// Reads an unsigned LEB128 varint.
+fn read_varint<'a>(bytes: &mut impl Iterator<Item = &'a u8>) -> Option<u32> {
+    let mut value = 0u32;
+    for shift in (0..32).step_by(7) {
+        let byte = *bytes.next()?;
+        value |= ((byte & 0x7f) as u32) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// Errors from loading a scene archive.
+#[derive(Debug)]
+pub enum ArchiveError {
+    Io(io::Error),
+    /// The file is not a valid archive.
+    Format(String),
+}
+
+impl fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArchiveError::Io(err) => write!(f, "failed to read archive: {err}"),
+            ArchiveError::Format(err) => write!(f, "invalid archive: {err}"),
+        }
+    }
+}
+
+impl Error for ArchiveError {}
+
+impl From<io::Error> for ArchiveError {
+    fn from(err: io::Error) -> Self {
+        ArchiveError::Io(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voxel::VoxelGenerator;
+
+    #[test]
+    fn round_trip() {
+        let generator = VoxelGenerator::new_from_seed(3).with_caves(Default::default());
+        let bb = IAabb::new(IVec3::new(4, 0, -2), IVec3::new(6, 20, 5));
+        let archive = SceneArchive::from_source(&generator, bb);
+
+        let mut file = Vec::new();
+        archive.write(&mut file).expect("failed to write");
+        let loaded = SceneArchive::read(file.as_slice()).expect("failed to read");
+        assert_eq!(loaded, archive);
+
+        for pos in bb.iter() {
+            assert_eq!(loaded.lookup(pos), generator.lookup(pos), "{pos}");
+        }
+        // nothing outside the box
+        assert_eq!(loaded.lookup(bb.min() - IVec3::Y), None);
+        assert_eq!(loaded.lookup(bb.max()), None);
+
+        // columns reaching past the box are cut off
+        let x = bb.min().x;
+        let z = bb.min().z;
+        let ys = bb.min().y - 3..bb.max().y + 3;
+        let mut out = vec![None; ys.len()];
+        loaded.column(x, z, ys.clone(), &mut out);
+        for (y, voxel) in ys.zip(out) {
+            assert_eq!(voxel, loaded.lookup(IVec3::new(x, y, z)));
+        }
+    }
+
+    #[test]
+    fn version_1() {
+        // a red 2x2x2 box, from before palettes had kinds
+        let mut data = Vec::new();
+        for _ in 0..4 {
+            // one run of two voxels per column
+            for value in [1, 2, 1] {
+                write_varint(&mut data, value);
+            }
+        }
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&data).unwrap();
+
+        let mut file = MAGIC.to_vec();
+        for field in [1, 0, 0, 0, 1, 1, 1, 1] {
+            file.extend(u32::to_le_bytes(field));
+        }
+        file.extend([255, 0, 0]);
+        file.extend(encoder.finish().unwrap());
+
+        let archive = SceneArchive::read(file.as_slice()).expect("failed to read");
+        assert_eq!(
+            archive.lookup(IVec3::ZERO),
+            Some(Voxel::new(U8Vec3::new(255, 0, 0), VoxelKind::UNKNOWN))
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(SceneArchive::read(b"NOTSCENE".as_slice()).is_err());
+
+        let bb = IAabb::new(IVec3::ZERO, IVec3::splat(4));
+        let mut file = Vec::new();
+        SceneArchive::from_source(&VoxelGenerator::new_from_seed(0), bb)
+            .write(&mut file)
+            .expect("failed to write");
+        for len in [10, 30, file.len() - 4] {
+            assert!(SceneArchive::read(&file[..len]).is_err());
+        }
+    }
+}
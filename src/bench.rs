@@ -0,0 +1,200 @@
+use std::time::{Duration, Instant};
+
+use glam::Vec3A;
+
+use crate::{
+    ray_tracer::{
+        dynamic::{Backend, DynScene},
+        Config, MemoryUsage, RayTracer, Scene,
+    },
+    voxel::VoxelGenerator,
+};
+
+/// A single entry of the benchmark matrix.
+#[derive(Debug, Clone, Copy)]
+pub struct BenchCase {
+    /// Label of the resolution used.
+    pub resolution: &'static str,
+    pub res_width: usize,
+    pub res_height: usize,
+    /// Scene size.
+    pub size: u32,
+    pub camera_pos: Vec3A,
+}
+
+impl BenchCase {
+    const fn new(
+        resolution: &'static str,
+        res_width: usize,
+        res_height: usize,
+        size: u32,
+        camera_offset: f32,
+    ) -> Self {
+        Self {
+            resolution,
+            res_width,
+            res_height,
+            size,
+            camera_pos: Vec3A::splat(size as f32 - camera_offset),
+        }
+    }
+
+    /// Creates a ray tracer config for this case.
+    pub fn config(&self, seed: u32) -> Config {
+        Config {
+            seed: Some(seed),
+            res_width: self.res_width,
+            res_height: self.res_height,
+            size: self.size,
+            camera_pos: self.camera_pos,
+            ..Default::default()
+        }
+    }
+}
+
+/// Fixed set of cases, matching the criterion benchmarks.
+pub const MATRIX: [BenchCase; 6] = [
+    BenchCase::new("1080p", 1920, 1080, 50, 10.0),
+    BenchCase::new("1080p", 1920, 1080, 100, 10.0),
+    BenchCase::new("1080p", 1920, 1080, 250, 10.0),
+    BenchCase::new("4k", 7680, 4320, 50, 10.0),
+    BenchCase::new("4k", 7680, 4320, 100, 10.0),
+    BenchCase::new("4k", 7680, 4320, 250, 10.0),
+];
+
+/// Timing statistics over a set of samples.
+#[derive(Debug, Clone)]
+pub struct Stats {
+    samples: Vec<Duration>,
+}
+
+impl Stats {
+    /// Creates statistics from samples (must not be empty).
+    pub fn new(mut samples: Vec<Duration>) -> Self {
+        assert!(!samples.is_empty(), "no samples were taken");
+        samples.sort();
+        Self { samples }
+    }
+
+    pub fn min(&self) -> Duration {
+        self.samples[0]
+    }
+
+    pub fn max(&self) -> Duration {
+        self.samples[self.samples.len() - 1]
+    }
+
+    pub fn mean(&self) -> Duration {
+        self.samples.iter().sum::<Duration>() / self.samples.len() as u32
+    }
+
+    pub fn median(&self) -> Duration {
+        // both indices are the same middle sample for odd lengths
+        let len = self.samples.len();
+        (self.samples[(len - 1) / 2] + self.samples[len / 2]) / 2
+    }
+
+    /// Sample standard deviation.
+    pub fn std_dev(&self) -> Duration {
+        if self.samples.len() < 2 {
+            return Duration::ZERO;
+        }
+
+        let mean = self.mean().as_secs_f64();
+        let variance = self
+            .samples
+            .iter()
+            .map(|s| (s.as_secs_f64() - mean).powi(2))
+            .sum::<f64>()
+            / (self.samples.len() - 1) as f64;
+
+        Duration::from_secs_f64(variance.sqrt())
+    }
+}
+
+/// Results of benchmarking a single case.
+#[derive(Debug, Clone)]
+pub struct BenchResult {
+    /// Time taken to construct the scene.
+    pub build: Duration,
+    /// Time taken per render.
+    pub render: Stats,
+    /// Memory held by the scene once built.
+    pub memory: MemoryUsage,
+}
+
+/// Benchmarks a case for a storage backend.
+///
+/// The scene is built once, then rendered `warmup` times before `samples` timed renders.
+pub fn run_case(
+    backend: Backend,
+    case: &BenchCase,
+    seed: u32,
+    warmup: usize,
+    samples: usize,
+) -> BenchResult {
+    let config = case.config(seed);
+    let generator = VoxelGenerator::new_from_seed(seed);
+    let start = Instant::now();
+    let scene = DynScene::build(backend, &generator, config.bounds());
+    let ray_tracer = RayTracer::from_scene(config, scene);
+    let build = start.elapsed();
+    let memory = ray_tracer.scene().memory_usage();
+
+    for _ in 0..warmup {
+        std::hint::black_box(ray_tracer.render());
+    }
+
+    let samples = (0..samples.max(1))
+        .map(|_| {
+            let start = Instant::now();
+            std::hint::black_box(ray_tracer.render());
+            start.elapsed()
+        })
+        .collect();
+
+    BenchResult {
+        build,
+        render: Stats::new(samples),
+        memory,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stats_odd() {
+        let stats = Stats::new(vec![
+            Duration::from_millis(30),
+            Duration::from_millis(10),
+            Duration::from_millis(20),
+        ]);
+
+        assert_eq!(stats.min(), Duration::from_millis(10));
+        assert_eq!(stats.max(), Duration::from_millis(30));
+        assert_eq!(stats.mean(), Duration::from_millis(20));
+        assert_eq!(stats.median(), Duration::from_millis(20));
+        assert_eq!(stats.std_dev(), Duration::from_millis(10));
+    }
+
+    #[test]
+    fn stats_even() {
+        let stats = Stats::new(vec![
+            Duration::from_millis(10),
+            Duration::from_millis(40),
+            Duration::from_millis(20),
+            Duration::from_millis(30),
+        ]);
+
+        assert_eq!(stats.median(), Duration::from_millis(25));
+        assert_eq!(stats.mean(), Duration::from_millis(25));
+    }
+
+    #[test]
+    fn stats_single() {
+        let stats = Stats::new(vec![Duration::from_millis(10)]);
+        assert_eq!(stats.std_dev(), Duration::ZERO);
+    }
+}
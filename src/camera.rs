@@ -1,18 +1,19 @@
 use crate::ray_tracer::types::Ray;
-use glam::Vec3A;
+use glam::{Vec2, Vec3A};
 
+/// Camera casting a ray through the center of every pixel.
+///
+/// The offsets from the camera to each column and row of pixels are worked out once, so a ray only adds one of
+/// each and normalizes the sum.
 pub struct Camera {
-    img_height: usize,
-    img_width: usize,
-    vertical_fov: f32,
-    lookfrom: Vec3A,
-    lookat: Vec3A,
-    cam_up: Vec3A,
-    focus_dist: f32,
     center: Vec3A,
-    pixel00_loc: Vec3A,
-    pixel_delta_u: Vec3A,
-    pixel_delta_v: Vec3A,
+    /// Offset from the camera to the center of the pixel in the top row of each column.
+    columns: Box<[Vec3A]>,
+    /// Offset of each row of pixels from the top row.
+    rows: Box<[Vec3A]>,
+    /// Offsets from one pixel to the next one right and the next one down.
+    delta: [Vec3A; 2],
+    spread: f32,
 }
 
 impl Default for Camera {
@@ -31,7 +32,12 @@ impl Default for Camera {
 
 impl Camera {
     pub fn from_res_and_pos(width: usize, height: usize, pos: Vec3A) -> Self {
-        Self::new(width, height, 90.0, pos, Vec3A::ZERO, Vec3A::Y, 10.0)
+        Self::looking_at(width, height, pos, Vec3A::ZERO)
+    }
+
+    /// Creates a camera at `pos` facing `target`, with the default field of view.
+    pub fn looking_at(width: usize, height: usize, pos: Vec3A, target: Vec3A) -> Self {
+        Self::new(width, height, 90.0, pos, target, Vec3A::Y, 10.0)
     }
 
     pub fn new(
@@ -65,29 +71,50 @@ impl Camera {
             + (0.5 * (pixel_delta_u + pixel_delta_v));
 
         Self {
-            img_height,
-            img_width,
-            vertical_fov,
-            lookfrom,
-            lookat,
-            cam_up,
-            focus_dist,
             center,
-            pixel00_loc,
-            pixel_delta_u,
-            pixel_delta_v,
+            columns: (0..img_width)
+                .map(|i| pixel00_loc - center + (i as f32) * pixel_delta_u)
+                .collect(),
+            rows: (0..img_height)
+                .map(|j| (j as f32) * pixel_delta_v)
+                .collect(),
+            delta: [pixel_delta_u, pixel_delta_v],
+            spread: pixel_delta_v.length() / focus_dist,
         }
     }
 
+    /// Width of the image in pixels.
+    pub fn width(&self) -> usize {
+        self.columns.len()
+    }
+
+    /// Height of the image in pixels.
+    pub fn height(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// Position the camera casts rays from.
+    pub fn position(&self) -> Vec3A {
+        self.center
+    }
+
+    /// Ray through the center of the pixel in column `i` and row `j`.
     pub fn get_ray(&self, i: usize, j: usize) -> Ray {
-        let pixel_sample = self.pixel00_loc
-            + ((i as f32) * self.pixel_delta_u)
-            + ((j as f32) * self.pixel_delta_v);
+        Ray::new(self.center, self.columns[i] + self.rows[j])
+    }
 
-        let ray_origin = self.center;
-        let ray_direction = (pixel_sample - ray_origin).normalize();
+    /// Ray through a point of the pixel in column `i` and row `j`, `offset` pixels right and down from its center.
+    pub fn get_ray_at(&self, i: usize, j: usize, offset: Vec2) -> Ray {
+        let [du, dv] = self.delta;
+        Ray::new(
+            self.center,
+            self.columns[i] + self.rows[j] + offset.x * du + offset.y * dv,
+        )
+    }
 
-        Ray::new(ray_origin, ray_direction)
+    /// Width of a pixel per unit of distance from the camera.
+    pub fn pixel_spread(&self) -> f32 {
+        self.spread
     }
 
     // Helper functions
@@ -95,3 +122,38 @@ impl Camera {
         degrees * std::f32::consts::PI / 180.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rays_through_pixel_centers() {
+        let pos = Vec3A::new(30.0, 20.0, 10.0);
+        let camera = Camera::looking_at(64, 48, pos, Vec3A::ZERO);
+
+        // the middle of the image looks at the target
+        let middle = (camera.get_ray(31, 23).dir + camera.get_ray(32, 24).dir).normalize();
+        assert!(middle.dot(-pos.normalize()) > 0.9999);
+
+        // columns step right and rows step down, by about a pixel's width at a distance of one in the middle
+        let ray = camera.get_ray(31, 23);
+        let right = camera.get_ray(32, 23);
+        let down = camera.get_ray(31, 24);
+        assert!(right.dir.cross(ray.dir).y > 0.0);
+        assert!(down.dir.y < ray.dir.y);
+        let step = ray.dir.distance(right.dir);
+        assert!((step - camera.pixel_spread()).abs() < 0.01 * step, "{step}");
+        assert_eq!(ray.origin, pos);
+
+        // half a pixel right of one center is half a pixel left of the next
+        let between = camera.get_ray_at(31, 23, Vec2::new(0.5, 0.0));
+        assert!(
+            between
+                .dir
+                .distance(camera.get_ray_at(32, 23, Vec2::new(-0.5, 0.0)).dir)
+                < 1e-6
+        );
+        assert_eq!(camera.get_ray_at(31, 23, Vec2::ZERO).dir, ray.dir);
+    }
+}
@@ -0,0 +1,452 @@
+use std::{collections::HashMap, error::Error, fmt, path::Path, path::PathBuf};
+
+use glam::{IVec3, Quat};
+
+use crate::{
+    import::{self, ImportError, ImportOptions},
+    ray_tracer::{
+        dynamic::{Backend, DynScene},
+        graph::{SceneGraph, Transform},
+        types::IAabb,
+        Config,
+    },
+    voxel::{
+        combinator::VoxelSourceExt,
+        grid::VoxelGrid,
+        layers::{Blend, Layers},
+        structure::Ground,
+        VoxelSource,
+    },
+};
+
+#[cfg(feature = "wasm")]
+use crate::voxel::plugin::{PluginError, PluginSource};
+#[cfg(feature = "scripting")]
+use crate::voxel::script::{ScriptError, ScriptSource};
+#[cfg(any(feature = "scripting", feature = "wasm"))]
+use rand::Rng;
+
+/// A model file and where it is placed in the scene.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjectFile {
+    pub path: PathBuf,
+    /// Middle of the model's base.
+    pub position: IVec3,
+    /// Degrees turned around the y-axis.
+    pub rotation: f32,
+}
+
+/// A source stacked on top of the scene, and how it changes the voxels below it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayerFile {
+    pub path: PathBuf,
+    /// Offset of the layer, where the middle of a model's base goes.
+    pub position: IVec3,
+    pub blend: LayerBlend,
+}
+
+/// A structure model file and where it can be placed on the terrain.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrefabFile {
+    pub path: PathBuf,
+    pub ground: Ground,
+    /// Largest difference in ground height under it, or the default of
+    /// [`Prefab::new`](crate::voxel::structure::Prefab::new).
+    pub max_slope: Option<i32>,
+    pub depth: i32,
+}
+
+/// How a layer changes the voxels below it
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum LayerBlend {
+    /// Solid voxels cover the ones below
+    #[default]
+    Cover,
+    /// Everything inside the layer's box is replaced, empty space included
+    Replace,
+    /// Solid voxels clear the ones below
+    Carve,
+}
+
+impl LayerBlend {
+    /// Every blend, in the order they are documented.
+    pub const ALL: [LayerBlend; 3] = [Self::Cover, Self::Replace, Self::Carve];
+
+    /// Blend with a name, ignoring case, see [`LayerBlend::name`].
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|blend| blend.name().eq_ignore_ascii_case(name))
+    }
+
+    /// Lower case name, as in scene files.
+    pub fn name(self) -> &'static str {
+        match self {
+            LayerBlend::Cover => "cover",
+            LayerBlend::Replace => "replace",
+            LayerBlend::Carve => "carve",
+        }
+    }
+}
+
+/// Places an imported model in the scene, centered on the ground in the middle of its bounds.
+///
+/// With explicit bounds the model keeps its own coordinates.
+pub fn place_model(grid: VoxelGrid, config: &Config) -> VoxelGrid {
+    match config.world {
+        Some(_) => grid,
+        None => grid.centered_on(config.bounds().origin),
+    }
+}
+
+/// Whether all of a model's voxels are inside the scene bounds.
+pub fn fits(grid: &VoxelGrid, config: &Config) -> bool {
+    let bounds = config.bounds();
+    let (min, max) = (grid.origin(), grid.origin() + grid.size());
+    min.cmpge(bounds.min()).all() && max.cmple(bounds.max()).all()
+}
+
+/// Loads a script that defines the scene's voxels.
+#[cfg(feature = "scripting")]
+pub fn load_script(path: &Path, config: &Config) -> Result<Box<dyn VoxelSource>, ComposeError> {
+    let seed = config.seed.unwrap_or_else(|| rand::rng().random());
+    Ok(Box::new(ScriptSource::load(path, seed, config.size)?))
+}
+
+#[cfg(not(feature = "scripting"))]
+pub fn load_script(_path: &Path, _config: &Config) -> Result<Box<dyn VoxelSource>, ComposeError> {
+    Err(ComposeError::Disabled("scripting"))
+}
+
+/// Loads a WebAssembly module that defines the scene's voxels.
+#[cfg(feature = "wasm")]
+pub fn load_plugin(path: &Path, config: &Config) -> Result<Box<dyn VoxelSource>, ComposeError> {
+    let seed = config.seed.unwrap_or_else(|| rand::rng().random());
+    Ok(Box::new(PluginSource::load(path, seed, config.size)?))
+}
+
+#[cfg(not(feature = "wasm"))]
+pub fn load_plugin(_path: &Path, _config: &Config) -> Result<Box<dyn VoxelSource>, ComposeError> {
+    Err(ComposeError::Disabled("wasm"))
+}
+
+/// Stacks layers on top of the source a scene is built from, in order.
+///
+/// Scripts and plugins fill the whole scene bounds, so a replacing one replaces everything below it. Models are
+/// placed like [`place_model`], moved by the layer's position.
+pub fn stack_layers(
+    base: Box<dyn VoxelSource>,
+    layers: &[LayerFile],
+    options: &ImportOptions,
+    config: &Config,
+) -> Result<Box<dyn VoxelSource>, ComposeError> {
+    if layers.is_empty() {
+        return Ok(base);
+    }
+
+    let bounds = config.bounds();
+    let mut stack = Layers::new(base);
+    for layer in layers {
+        let extension = layer.path.extension().and_then(|e| e.to_str());
+        let (source, min, max): (Box<dyn VoxelSource>, _, _) = match extension {
+            Some("rhai") => {
+                let script = load_script(&layer.path, config)?.translate(layer.position);
+                (Box::new(script), bounds.min(), bounds.max())
+            }
+            Some("wasm" | "wat") => {
+                let plugin = load_plugin(&layer.path, config)?.translate(layer.position);
+                (Box::new(plugin), bounds.min(), bounds.max())
+            }
+            _ => {
+                let grid = place_model(import::load(&layer.path, options)?, config);
+                let origin = grid.origin() + layer.position;
+                let grid = grid.with_origin(origin);
+                let (min, max) = (grid.origin(), grid.origin() + grid.size());
+                (Box::new(grid), min, max)
+            }
+        };
+        let blend = match layer.blend {
+            LayerBlend::Cover => Blend::Cover,
+            LayerBlend::Replace => Blend::Replace { min, max },
+            LayerBlend::Carve => Blend::Carve,
+        };
+        stack.push(source, blend);
+    }
+    Ok(Box::new(stack))
+}
+
+/// Models loaded to be placed in the scene, each loaded once however many times it is placed.
+#[derive(Default)]
+pub struct Objects {
+    /// Models with the middle of their base at their origin.
+    pub models: Vec<VoxelGrid>,
+    /// Index of the model of each instance, and where it goes.
+    pub instances: Vec<(usize, Transform)>,
+}
+
+impl Objects {
+    /// Loads the models placed in a scene.
+    pub fn load(objects: &[ObjectFile], options: &ImportOptions) -> Result<Self, ComposeError> {
+        let mut loaded = Self::default();
+        let mut indices = HashMap::new();
+        for object in objects {
+            let model = match indices.get(&object.path) {
+                Some(&model) => model,
+                None => {
+                    let grid = import::load(&object.path, options)?.centered();
+                    loaded.models.push(grid);
+                    indices.insert(&object.path, loaded.models.len() - 1);
+                    loaded.models.len() - 1
+                }
+            };
+            // quarter turns keep the voxels lined up with the grid
+            let turns = object.rotation / 90.0;
+            let transform = if turns.fract() == 0.0 {
+                Transform::quarter_turns(object.position, turns as i32)
+            } else {
+                Transform::new(
+                    object.position.as_vec3a(),
+                    Quat::from_rotation_y(object.rotation.to_radians()),
+                )
+            };
+            loaded.instances.push((model, transform));
+        }
+        Ok(loaded)
+    }
+
+    /// Number of placed instances.
+    pub fn len(&self) -> usize {
+        self.instances.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instances.is_empty()
+    }
+
+    /// Puts a scene that was already built in a scene graph with the models.
+    ///
+    /// `bounds` is the box around the scene's voxels, or `None` if it has no end. The models are built with the
+    /// given backend, once each, and every instance of a model shares its voxels.
+    pub fn graph(
+        &self,
+        scene: DynScene,
+        bounds: Option<IAabb>,
+        backend: Backend,
+    ) -> SceneGraph<DynScene> {
+        let mut graph = SceneGraph::new();
+        match bounds {
+            Some(bounds) => graph.add(scene, bounds, Transform::IDENTITY),
+            None => graph.add_unbounded(scene, Transform::IDENTITY),
+        };
+        let mut ids = vec![None; self.models.len()];
+        for &(model, transform) in &self.instances {
+            match ids[model] {
+                Some(id) => graph.place(id, transform),
+                None => {
+                    let grid = &self.models[model];
+                    let min = grid.origin();
+                    let bounds = IAabb::from_corners(min, min + grid.size() - 1);
+                    let scene = DynScene::build(backend, grid, bounds);
+                    ids[model] = Some(graph.add(scene, bounds, transform));
+                }
+            }
+        }
+        graph
+    }
+}
+
+/// Errors from putting a scene together.
+#[derive(Debug)]
+pub enum ComposeError {
+    Import(ImportError),
+    #[cfg(feature = "scripting")]
+    Script(ScriptError),
+    #[cfg(feature = "wasm")]
+    Plugin(PluginError),
+    /// The file needs a feature the program was built without.
+    Disabled(&'static str),
+}
+
+impl fmt::Display for ComposeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ComposeError::Import(err) => err.fmt(f),
+            #[cfg(feature = "scripting")]
+            ComposeError::Script(err) => err.fmt(f),
+            #[cfg(feature = "wasm")]
+            ComposeError::Plugin(err) => err.fmt(f),
+            ComposeError::Disabled(feature) => {
+                write!(
+                    f,
+                    "support for this file is disabled, rebuild with `--features {feature}`"
+                )
+            }
+        }
+    }
+}
+
+impl Error for ComposeError {}
+
+impl From<ImportError> for ComposeError {
+    fn from(err: ImportError) -> Self {
+        ComposeError::Import(err)
+    }
+}
+
+#[cfg(feature = "scripting")]
+impl From<ScriptError> for ComposeError {
+    fn from(err: ScriptError) -> Self {
+        ComposeError::Script(err)
+    }
+}
+
+#[cfg(feature = "wasm")]
+impl From<PluginError> for ComposeError {
+    fn from(err: PluginError) -> Self {
+        ComposeError::Plugin(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use glam::U8Vec3;
+
+    use super::*;
+    use crate::{
+        import::mesh::Fill,
+        voxel::{Voxel, VoxelKind},
+    };
+
+    /// Writes a cube mesh 4 units wide to a temporary file.
+    fn cube_file(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("{name}_{}.obj", std::process::id()));
+        let mut obj = String::new();
+        for i in 0..8 {
+            let corner = |bit: i32| if i & bit != 0 { 4.0 } else { 0.0 };
+            obj += &format!("v {} {} {}\n", corner(1), corner(2), corner(4));
+        }
+        for face in [
+            [1, 3, 4, 2],
+            [5, 6, 8, 7],
+            [1, 2, 6, 5],
+            [3, 7, 8, 4],
+            [1, 5, 7, 3],
+            [2, 4, 8, 6],
+        ] {
+            obj += &format!("f {} {} {} {}\n", face[0], face[1], face[2], face[3]);
+        }
+        fs::write(&path, obj).unwrap();
+        path
+    }
+
+    fn options() -> ImportOptions {
+        ImportOptions {
+            resolution: 4,
+            fill: Fill::Solid,
+            ..ImportOptions::default()
+        }
+    }
+
+    #[test]
+    fn models_are_placed_in_the_bounds() {
+        let config = Config {
+            size: 16,
+            ..Config::default()
+        };
+        let grid = place_model(VoxelGrid::new(IVec3::new(32, 8, 31)), &config);
+        assert_eq!(grid.origin(), IVec3::new(-16, 0, -15));
+        assert!(fits(&grid, &config));
+        assert!(!fits(&VoxelGrid::new(IVec3::splat(33)), &config));
+
+        // with explicit bounds the model is left where it is, even outside them
+        let config = Config {
+            world: Some(IAabb::from_corners(IVec3::splat(100), IVec3::splat(131))),
+            ..config
+        };
+        let grid = place_model(VoxelGrid::new(IVec3::splat(8)), &config);
+        assert_eq!(grid.origin(), IVec3::ZERO);
+        assert!(!fits(&grid, &config));
+    }
+
+    #[test]
+    fn layers_are_stacked() {
+        let path = cube_file("compose_layer");
+        let config = Config {
+            size: 16,
+            ..Config::default()
+        };
+        let stone = Voxel::new(U8Vec3::splat(100), VoxelKind::STONE);
+        let mut base = VoxelGrid::new(IVec3::splat(32));
+        base.set(IVec3::new(16, 2, 16), Some(stone));
+        base.set(IVec3::new(26, 2, 26), Some(stone));
+        let base = base.centered();
+
+        let layer = |blend| LayerFile {
+            path: path.clone(),
+            position: IVec3::new(0, 1, 0),
+            blend,
+        };
+        let covered = stack_layers(
+            Box::new(base.clone()),
+            &[layer(LayerBlend::Cover)],
+            &options(),
+            &config,
+        )
+        .unwrap();
+        // the cube sits on the middle of the ground, one voxel up
+        assert!(covered.lookup(IVec3::new(0, 0, 0)).is_none());
+        assert!(covered.lookup(IVec3::new(0, 2, 0)).unwrap().kind != VoxelKind::STONE);
+        assert_eq!(covered.lookup(IVec3::new(10, 2, 10)), Some(stone));
+
+        let carved = stack_layers(
+            Box::new(base),
+            &[layer(LayerBlend::Carve)],
+            &options(),
+            &config,
+        )
+        .unwrap();
+        assert_eq!(carved.lookup(IVec3::new(0, 2, 0)), None);
+        assert_eq!(carved.lookup(IVec3::new(10, 2, 10)), Some(stone));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn missing_layer_is_an_error() {
+        let layers = [LayerFile {
+            path: PathBuf::from("missing_layer.obj"),
+            position: IVec3::ZERO,
+            blend: LayerBlend::Cover,
+        }];
+        let base = Box::new(VoxelGrid::new(IVec3::ONE));
+        let result = stack_layers(base, &layers, &options(), &Config::default());
+        assert!(matches!(result, Err(ComposeError::Import(_))));
+    }
+
+    #[test]
+    fn objects_share_models() {
+        let path = cube_file("compose_object");
+        let object = |x, rotation| ObjectFile {
+            path: path.clone(),
+            position: IVec3::new(x, 0, 0),
+            rotation,
+        };
+        let objects = Objects::load(
+            &[object(0, 0.0), object(10, 90.0), object(20, 45.0)],
+            &options(),
+        )
+        .unwrap();
+        assert_eq!(objects.models.len(), 1);
+        assert_eq!(objects.len(), 3);
+        assert_eq!(objects.models[0].origin(), IVec3::new(-2, 0, -2));
+
+        let ground = VoxelGrid::new(IVec3::splat(8)).centered();
+        let bounds = IAabb::new(IVec3::ZERO, IVec3::splat(4));
+        let scene = DynScene::build(Backend::Dense, &ground, bounds);
+        let graph = objects.graph(scene, Some(bounds), Backend::Dense);
+        assert_eq!(graph.len(), 4);
+        assert_eq!(graph.model_count(), 2);
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -1,3 +1,4 @@
+use glam::Vec3A;
 use image::{Rgba, RgbaImage};
 use rayon::iter::plumbing::bridge;
 use rayon::iter::plumbing::Producer;
@@ -15,6 +16,8 @@ pub struct Framebuffer {
     width: usize,
     height: usize,
     pixels: Box<[AtomicU32]>,
+    /// Color of each pixel from 0 to 1 or more, as the bits of each channel's `f32`, if kept.
+    hdr: Option<Box<[[AtomicU32; 3]]>>,
 }
 
 impl Framebuffer {
@@ -32,13 +35,56 @@ impl Framebuffer {
             width,
             height,
             pixels,
+            hdr: None,
         }
     }
 
+    /// Framebuffer that also keeps the color of every pixel unclamped, so post-processing can tell how far past white
+    /// the brightest ones are, see [`Framebuffer::hdr`].
+    pub fn with_hdr(width: usize, height: usize) -> Framebuffer {
+        let hdr = (0..width * height)
+            .map(|_| [0; 3].map(AtomicU32::new))
+            .collect::<Vec<_>>()
+            .into_boxed_slice();
+        Self {
+            hdr: Some(hdr),
+            ..Self::new(width, height)
+        }
+    }
+
+    /// Width of the image in pixels.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Height of the image in pixels.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
     pub fn pixel_mut(&self, x: usize, y: usize) -> &AtomicU32 {
         let index = y * self.width + x;
         &self.pixels[index]
     }
+
+    /// Color of a pixel from 0 to 1, or more where it is brighter than white, if the framebuffer keeps it.
+    pub fn hdr(&self, x: usize, y: usize) -> Option<Vec3A> {
+        let channels = &self.hdr.as_ref()?[y * self.width + x];
+        Some(Vec3A::from_array(
+            channels
+                .each_ref()
+                .map(|c| f32::from_bits(c.load(Ordering::Acquire))),
+        ))
+    }
+
+    /// Sets the unclamped color of a pixel, if the framebuffer keeps it.
+    pub fn set_hdr(&self, x: usize, y: usize, color: Vec3A) {
+        if let Some(hdr) = &self.hdr {
+            for (channel, value) in hdr[y * self.width + x].iter().zip(color.to_array()) {
+                channel.store(value.to_bits(), Ordering::Release);
+            }
+        }
+    }
 }
 
 impl<'b> IntoParallelIterator for &'b Framebuffer {
@@ -167,7 +213,7 @@ impl<'b> IndexedParallelIterator for ParIter<'b> {
         callback: CB,
     ) -> CB::Output {
         callback.callback(ParIterProducer {
-            buffer: &self.buffer,
+            buffer: self.buffer,
             start: 0,
             end: self.len(),
         })
@@ -0,0 +1,533 @@
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use glam::{IVec2, IVec3, Vec3Swizzles};
+
+use super::{
+    nbt::{self, Tag},
+    palette::{legacy_name, Palette},
+    volume, ImportError,
+};
+use crate::voxel::{grid::VoxelGrid, Voxel};
+
+/// Width of a region in chunks.
+const REGION_CHUNKS: i32 = 32;
+
+/// Width of a chunk (and height of a chunk section) in blocks.
+const CHUNK_SIZE: i32 = 16;
+
+/// Size of a sector in a region file.
+const SECTOR: u64 = 4096;
+
+/// First data version (1.16) where block states no longer span two longs.
+const PACKED_VERSION: i64 = 2566;
+
+/// A box of world block coordinates to import.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Window {
+    /// Lowest corner (inclusive).
+    pub min: IVec3,
+    /// Highest corner (exclusive).
+    pub max: IVec3,
+}
+
+impl Window {
+    /// Creates a window from two opposite corners (both inclusive).
+    pub fn from_corners(a: IVec3, b: IVec3) -> Self {
+        Self {
+            min: a.min(b),
+            max: a.max(b) + 1,
+        }
+    }
+
+    /// Default window covering the middle of a region and the full build height.
+    fn around_region(region: IVec2) -> Self {
+        let center = (region * REGION_CHUNKS + REGION_CHUNKS / 2) * CHUNK_SIZE;
+        Self {
+            min: IVec3::new(center.x - 64, -64, center.y - 64),
+            max: IVec3::new(center.x + 64, 320, center.y + 64),
+        }
+    }
+
+    /// Size of the window in blocks, or `None` if a side is too long for an `i32`.
+    fn size(&self) -> Option<IVec3> {
+        IVec3::try_from(self.max.as_i64vec3() - self.min.as_i64vec3()).ok()
+    }
+}
+
+/// Loads the blocks inside a window from an Anvil region file (`r.X.Z.mca`) or a folder of them.
+///
+/// Only chunks that overlap the window are read and decompressed. The grid is cropped to the blocks found.
+pub fn load(
+    path: impl AsRef<Path>,
+    window: Option<Window>,
+    palette: &Palette,
+) -> Result<VoxelGrid, ImportError> {
+    let path = path.as_ref();
+
+    // a single region file, or a folder of them
+    let file = if path.is_dir() {
+        None
+    } else {
+        Some(region_coords(path)?)
+    };
+    let window = window.unwrap_or(Window::around_region(file.unwrap_or(IVec2::ZERO)));
+
+    let size = window.size().ok_or_else(|| {
+        ImportError::Format(format!(
+            "import window from {} to {} is too large",
+            window.min, window.max
+        ))
+    })?;
+    if size.cmple(IVec3::ZERO).any() {
+        return Err(ImportError::Format("import window is empty".into()));
+    }
+    volume(size)?;
+
+    let regions: Vec<(IVec2, PathBuf)> = match file {
+        Some(region) => vec![(region, path.to_path_buf())],
+        None => {
+            let min = window
+                .min
+                .xz()
+                .div_euclid(IVec2::splat(REGION_CHUNKS * CHUNK_SIZE));
+            let max = (window.max.xz() - 1).div_euclid(IVec2::splat(REGION_CHUNKS * CHUNK_SIZE));
+            (min.x..=max.x)
+                .flat_map(|x| (min.y..=max.y).map(move |z| IVec2::new(x, z)))
+                .map(|r| (r, path.join(format!("r.{}.{}.mca", r.x, r.y))))
+                .filter(|(_, path)| path.exists())
+                .collect()
+        }
+    };
+
+    // keep the world coordinates, in case the scene is placed around them instead of centered
+    let mut grid = VoxelGrid::new(size).with_origin(window.min);
+    for (region, path) in regions {
+        read_region(&path, region, window, palette, &mut grid)?;
+    }
+    Ok(grid.cropped())
+}
+
+/// Parses the region coordinates from a file name like `r.-1.2.mca`.
+fn region_coords(path: &Path) -> Result<IVec2, ImportError> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let parts: Vec<_> = name.split('.').collect();
+    match parts[..] {
+        ["r", x, z, "mca"] => match (x.parse(), z.parse()) {
+            (Ok(x), Ok(z)) => Ok(IVec2::new(x, z)),
+            _ => Err(ImportError::Format(format!("invalid region name `{name}`"))),
+        },
+        _ => Err(ImportError::Format(format!(
+            "region files must be named r.X.Z.mca, got `{name}`"
+        ))),
+    }
+}
+
+/// Copies the blocks of a region that lie inside the window into the grid.
+fn read_region(
+    path: &Path,
+    region: IVec2,
+    window: Window,
+    palette: &Palette,
+    grid: &mut VoxelGrid,
+) -> Result<(), ImportError> {
+    let mut file = File::open(path)?;
+    let mut header = [0u8; SECTOR as usize];
+    file.read_exact(&mut header)?;
+
+    for (i, location) in header.chunks_exact(4).enumerate() {
+        let offset = u32::from_be_bytes([0, location[0], location[1], location[2]]) as u64;
+        let sectors = location[3] as u64;
+        if offset == 0 {
+            // chunk hasn't been generated
+            continue;
+        }
+
+        let local = IVec2::new(i as i32 % REGION_CHUNKS, i as i32 / REGION_CHUNKS);
+        let chunk = region * REGION_CHUNKS + local;
+        let min = chunk * CHUNK_SIZE;
+        let max = min + CHUNK_SIZE;
+        if max.cmple(window.min.xz()).any() || min.cmpge(window.max.xz()).any() {
+            continue;
+        }
+
+        file.seek(SeekFrom::Start(offset * SECTOR))?;
+        let mut prefix = [0u8; 5];
+        file.read_exact(&mut prefix)?;
+        let len = u32::from_be_bytes(prefix[..4].try_into().unwrap()) as usize;
+        // the length counts the compression byte but not itself
+        if len as u64 + 4 > sectors * SECTOR {
+            return Err(ImportError::Format(format!(
+                "chunk {chunk} is {len} bytes long but only has {sectors} sectors"
+            )));
+        }
+        let mut data = vec![0; len.saturating_sub(1)];
+        file.read_exact(&mut data)?;
+
+        match prefix[4] {
+            // gzip, zlib, and uncompressed data are all detected when reading
+            1..=3 => {}
+            4 => {
+                return Err(ImportError::Format(
+                    "LZ4 compressed chunks are not supported".into(),
+                ))
+            }
+            n => {
+                return Err(ImportError::Format(format!(
+                    "unknown chunk compression {n}"
+                )))
+            }
+        }
+
+        let (_, tag) = nbt::read(&data)?;
+        read_chunk(&tag, min, window, palette, grid)?;
+    }
+
+    Ok(())
+}
+
+/// Copies the blocks of a chunk with its lowest corner at `min` (x, z) into the grid.
+fn read_chunk(
+    chunk: &Tag,
+    min: IVec2,
+    window: Window,
+    palette: &Palette,
+    grid: &mut VoxelGrid,
+) -> Result<(), ImportError> {
+    let version = chunk.get("DataVersion").and_then(Tag::as_int).unwrap_or(0);
+
+    // before 1.18 the chunk data was nested in a `Level` compound
+    let sections = chunk
+        .get("sections")
+        .or_else(|| chunk.get("Level")?.get("Sections"))
+        .ok_or_else(|| ImportError::Format("chunk has no sections".into()))?;
+
+    for section in sections.as_list().unwrap_or_default() {
+        let Some(y) = section.get("Y").and_then(Tag::as_int) else {
+            continue;
+        };
+        let base = IVec3::new(min.x, y as i32 * CHUNK_SIZE, min.y);
+        if base.y + CHUNK_SIZE <= window.min.y || base.y >= window.max.y {
+            continue;
+        }
+
+        let Some(voxels) = section_voxels(section, version, palette)? else {
+            continue;
+        };
+
+        // blocks are ordered by y, then z, then x
+        for (i, voxel) in voxels.into_iter().enumerate() {
+            if voxel.is_none() {
+                continue;
+            }
+            let i = i as i32;
+            let offset = IVec3::new(
+                i % CHUNK_SIZE,
+                i / (CHUNK_SIZE * CHUNK_SIZE),
+                (i / CHUNK_SIZE) % CHUNK_SIZE,
+            );
+            grid.set(base + offset - window.min, voxel);
+        }
+    }
+
+    Ok(())
+}
+
+/// Voxels of the 4096 blocks in a chunk section, or `None` for sections without blocks.
+fn section_voxels(
+    section: &Tag,
+    version: i64,
+    palette: &Palette,
+) -> Result<Option<Vec<Option<Voxel>>>, ImportError> {
+    const BLOCKS: usize = (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as usize;
+
+    // 1.18 and later
+    let (states, data) = if let Some(block_states) = section.get("block_states") {
+        (
+            block_states.get("palette"),
+            block_states.get("data").and_then(Tag::as_longs),
+        )
+    }
+    // 1.13 to 1.17
+    else if let Some(states) = section.get("Palette") {
+        (
+            Some(states),
+            section.get("BlockStates").and_then(Tag::as_longs),
+        )
+    }
+    // before 1.13, with numeric block ids
+    else if let Some(blocks) = section.get("Blocks").and_then(Tag::as_bytes) {
+        let data = section
+            .get("Data")
+            .and_then(Tag::as_bytes)
+            .unwrap_or_default();
+        let voxels = blocks
+            .iter()
+            .take(BLOCKS)
+            .enumerate()
+            .map(|(i, &id)| {
+                let nibble = data.get(i / 2).map_or(0, |d| (d >> ((i % 2) * 4)) & 0xf);
+                palette.voxel(&legacy_name(id as u16, nibble))
+            })
+            .collect();
+        return Ok(Some(voxels));
+    } else {
+        return Ok(None);
+    };
+
+    let states: Vec<_> = states
+        .and_then(Tag::as_list)
+        .unwrap_or_default()
+        .iter()
+        .map(|state| {
+            state
+                .get("Name")
+                .and_then(Tag::as_str)
+                .and_then(|name| palette.voxel(name))
+        })
+        .collect();
+
+    let voxels = match (states.len(), data) {
+        (0, _) => return Ok(None),
+        // a single state fills the whole section and has no data
+        (1, _) | (_, None) => vec![states[0]; BLOCKS],
+        (len, Some(data)) => {
+            let bits = (usize::BITS - (len - 1).leading_zeros()).max(4) as usize;
+            let spanning = version < PACKED_VERSION;
+            let indices = unpack(data, bits, spanning, BLOCKS);
+            if indices.len() < BLOCKS {
+                return Err(ImportError::Format("truncated block states".into()));
+            }
+            indices
+                .into_iter()
+                .map(|i| states.get(i).copied().flatten())
+                .collect()
+        }
+    };
+    Ok(Some(voxels))
+}
+
+/// Unpacks `count` integers of `bits` width from an array of longs.
+///
+/// Older versions pack the values back to back (`spanning` across longs), newer ones pad each long.
+fn unpack(data: &[i64], bits: usize, spanning: bool, count: usize) -> Vec<usize> {
+    let mask = (1u64 << bits) - 1;
+    let mut out = Vec::with_capacity(count);
+
+    if spanning {
+        for i in 0..count {
+            let bit = i * bits;
+            let (word, shift) = (bit / 64, bit % 64);
+            let Some(&low) = data.get(word) else {
+                break;
+            };
+            let mut value = (low as u64) >> shift;
+            if shift + bits > 64 {
+                value |= (*data.get(word + 1).unwrap_or(&0) as u64) << (64 - shift);
+            }
+            out.push((value & mask) as usize);
+        }
+    } else {
+        let per_long = 64 / bits;
+        'outer: for &long in data {
+            for j in 0..per_long {
+                if out.len() == count {
+                    break 'outer;
+                }
+                out.push(((long as u64 >> (j * bits)) & mask) as usize);
+            }
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{write::ZlibEncoder, Compression};
+
+    use super::*;
+    use crate::{import::nbt::compound, voxel::VoxelSource};
+
+    /// Packs values without spanning longs, the inverse of `unpack`.
+    fn pack(values: &[usize], bits: usize) -> Vec<i64> {
+        values
+            .chunks(64 / bits)
+            .map(|chunk| {
+                chunk
+                    .iter()
+                    .enumerate()
+                    .fold(0u64, |long, (j, &v)| long | (v as u64) << (j * bits))
+                    as i64
+            })
+            .collect()
+    }
+
+    /// Builds a region file with the given chunks (local chunk coordinates and chunk tag).
+    fn region_file(chunks: &[(IVec2, Tag)]) -> Vec<u8> {
+        let mut header = vec![0u8; 2 * SECTOR as usize];
+        let mut body = Vec::new();
+
+        for (pos, chunk) in chunks {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(&nbt::write("", chunk)).unwrap();
+            let data = encoder.finish().unwrap();
+
+            let mut sector = Vec::new();
+            sector.extend((data.len() as u32 + 1).to_be_bytes());
+            sector.push(2);
+            sector.extend(data);
+            sector.resize(sector.len().div_ceil(SECTOR as usize) * SECTOR as usize, 0);
+
+            let offset = 2 + body.len() / SECTOR as usize;
+            let count = sector.len() / SECTOR as usize;
+            let i = (pos.x + pos.y * REGION_CHUNKS) as usize * 4;
+            header[i..i + 3].copy_from_slice(&(offset as u32).to_be_bytes()[1..]);
+            header[i + 3] = count as u8;
+            body.extend(sector);
+        }
+
+        header.extend(body);
+        header
+    }
+
+    /// A modern chunk with a stone floor at y = 0 and a glass block above the floor's first corner.
+    fn chunk() -> Tag {
+        let mut indices = vec![0; 4096];
+        indices[..256].fill(1);
+        indices[256] = 2;
+
+        compound([
+            ("DataVersion", Tag::Int(3465)),
+            (
+                "sections",
+                Tag::List(vec![
+                    compound([
+                        ("Y", Tag::Byte(0)),
+                        (
+                            "block_states",
+                            compound([
+                                (
+                                    "palette",
+                                    Tag::List(vec![
+                                        compound([("Name", Tag::String("minecraft:air".into()))]),
+                                        compound([("Name", Tag::String("minecraft:stone".into()))]),
+                                        compound([("Name", Tag::String("minecraft:glass".into()))]),
+                                    ]),
+                                ),
+                                ("data", Tag::LongArray(pack(&indices, 4))),
+                            ]),
+                        ),
+                    ]),
+                    compound([
+                        ("Y", Tag::Byte(-1)),
+                        (
+                            "block_states",
+                            compound([(
+                                "palette",
+                                Tag::List(vec![compound([(
+                                    "Name",
+                                    Tag::String("minecraft:bedrock".into()),
+                                )])]),
+                            )]),
+                        ),
+                    ]),
+                ]),
+            ),
+        ])
+    }
+
+    #[test]
+    fn unpack_values() {
+        let values: Vec<usize> = (0..100).map(|i| i % 32).collect();
+        assert_eq!(unpack(&pack(&values, 5), 5, false, 100), values);
+
+        // 5-bit values packed back to back, the 13th value spans the first two longs
+        let mut data = [0u64; 2];
+        for (i, &v) in values[..25].iter().enumerate() {
+            let bit = i * 5;
+            data[bit / 64] |= (v as u64) << (bit % 64);
+            if bit % 64 + 5 > 64 {
+                data[bit / 64 + 1] |= (v as u64) >> (64 - bit % 64);
+            }
+        }
+        let data = data.map(|d| d as i64);
+        assert_eq!(unpack(&data, 5, true, 25), values[..25]);
+    }
+
+    #[test]
+    fn region() {
+        let dir = std::env::temp_dir().join(format!("anvil_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("r.-1.0.mca");
+        std::fs::write(&path, region_file(&[(IVec2::new(31, 0), chunk())])).unwrap();
+
+        // the chunk covers x = -16..0, z = 0..16
+        let window = Window::from_corners(IVec3::new(-20, -3, 0), IVec3::new(-9, 5, 3));
+        let palette = Palette::default();
+        let stone = palette.voxel("minecraft:stone");
+        let glass = palette.voxel("minecraft:glass");
+
+        for path in [&path, &dir] {
+            let grid = load(path, Some(window), &palette).expect("failed to load region");
+
+            // x = -16..=-9, y = -3..=1, z = 0..=3 with bedrock below the floor
+            assert_eq!(grid.size(), IVec3::new(8, 5, 4));
+            // the grid keeps world coordinates
+            assert_eq!(grid.origin(), IVec3::new(-16, -3, 0));
+            let at = |pos: IVec3| grid.lookup(pos);
+            assert_eq!(at(IVec3::new(-16, 0, 0)), stone);
+            assert_eq!(at(IVec3::new(-16, 1, 0)), glass);
+            assert_eq!(at(IVec3::new(-15, 1, 0)), None);
+            assert_eq!(
+                at(IVec3::new(-9, -3, 3)).map(|v| v.color),
+                palette.color("minecraft:bedrock")
+            );
+        }
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn invalid_regions() {
+        let dir = std::env::temp_dir().join(format!("anvil_invalid_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("r.0.0.mca");
+        let palette = Palette::default();
+
+        // a chunk claiming to be longer than its sectors
+        let mut file = region_file(&[(IVec2::ZERO, chunk())]);
+        let start = 2 * SECTOR as usize;
+        file[start..start + 4].copy_from_slice(&u32::MAX.to_be_bytes());
+        std::fs::write(&path, file).unwrap();
+        let window = Window::from_corners(IVec3::ZERO, IVec3::splat(3));
+        assert!(matches!(
+            load(&path, Some(window), &palette),
+            Err(ImportError::Format(_))
+        ));
+
+        // windows too large to hold in memory fail before reading anything
+        std::fs::write(&path, region_file(&[(IVec2::ZERO, chunk())])).unwrap();
+        for window in [
+            Window::from_corners(IVec3::splat(-1_000_000), IVec3::splat(1_000_000)),
+            Window {
+                min: IVec3::splat(i32::MIN),
+                max: IVec3::splat(i32::MAX),
+            },
+        ] {
+            for path in [&path, &dir] {
+                assert!(matches!(
+                    load(path, Some(window), &palette),
+                    Err(ImportError::Format(_))
+                ));
+            }
+        }
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,281 @@
+use std::path::Path;
+
+use ::gltf::{image, mesh::Mode, Document, Node};
+use glam::{Mat4, U8Vec3, Vec2, Vec3, Vec3A};
+
+use super::{
+    mesh::{self, Fill, Triangle},
+    ImportError,
+};
+use crate::voxel::grid::VoxelGrid;
+
+/// Points inside a triangle, as barycentric weights, where textures are sampled for its color.
+const SAMPLES: [Vec3; 4] = [
+    Vec3::splat(1.0 / 3.0),
+    Vec3::new(2.0 / 3.0, 1.0 / 6.0, 1.0 / 6.0),
+    Vec3::new(1.0 / 6.0, 2.0 / 3.0, 1.0 / 6.0),
+    Vec3::new(1.0 / 6.0, 1.0 / 6.0, 2.0 / 3.0),
+];
+
+/// Loads a glTF 2.0 scene (`.gltf` or `.glb`) and voxelizes it so its longest side spans `resolution` voxels.
+///
+/// Triangles are colored by their material's base color, multiplied by vertex colors and the base color texture
+/// sampled over the triangle.
+pub fn load(path: impl AsRef<Path>, resolution: u32, fill: Fill) -> Result<VoxelGrid, ImportError> {
+    let (document, buffers, images) = ::gltf::import(path).map_err(format_error)?;
+    Ok(mesh::voxelize(
+        &triangles(&document, &buffers, &images),
+        resolution,
+        fill,
+    ))
+}
+
+/// Parses a glTF scene from the contents of a `.glb` file or a `.gltf` file with embedded buffers.
+pub fn parse(bytes: &[u8], resolution: u32, fill: Fill) -> Result<VoxelGrid, ImportError> {
+    let (document, buffers, images) = ::gltf::import_slice(bytes).map_err(format_error)?;
+    Ok(mesh::voxelize(
+        &triangles(&document, &buffers, &images),
+        resolution,
+        fill,
+    ))
+}
+
+/// Collects the triangles of every mesh in the default scene, moved into place by their nodes.
+fn triangles(
+    document: &Document,
+    buffers: &[::gltf::buffer::Data],
+    images: &[image::Data],
+) -> Vec<Triangle> {
+    let mut triangles = Vec::new();
+    let scene = document
+        .default_scene()
+        .or_else(|| document.scenes().next());
+    if let Some(scene) = scene {
+        for node in scene.nodes() {
+            add_node(&node, Mat4::IDENTITY, buffers, images, &mut triangles);
+        }
+    }
+    triangles
+}
+
+fn add_node(
+    node: &Node,
+    parent: Mat4,
+    buffers: &[::gltf::buffer::Data],
+    images: &[image::Data],
+    triangles: &mut Vec<Triangle>,
+) {
+    let transform = parent * Mat4::from_cols_array_2d(&node.transform().matrix());
+
+    for primitive in node.mesh().iter().flat_map(|mesh| mesh.primitives()) {
+        if primitive.mode() != Mode::Triangles {
+            continue;
+        }
+        let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(|data| &**data));
+        let Some(positions) = reader.read_positions() else {
+            continue;
+        };
+        let positions: Vec<Vec3A> = positions
+            .map(|p| transform.transform_point3a(Vec3A::from(p)))
+            .collect();
+        let indices: Vec<u32> = match reader.read_indices() {
+            Some(indices) => indices.into_u32().collect(),
+            None => (0..positions.len() as u32).collect(),
+        };
+        let colors: Option<Vec<Vec3>> = reader
+            .read_colors(0)
+            .map(|colors| colors.into_rgb_f32().map(Vec3::from).collect());
+
+        let pbr = primitive.material().pbr_metallic_roughness();
+        let factor = Vec3::from_slice(&pbr.base_color_factor());
+        let texture = pbr.base_color_texture().and_then(|info| {
+            let image = images.get(info.texture().source().index())?;
+            let coords: Vec<Vec2> = reader
+                .read_tex_coords(info.tex_coord())?
+                .into_f32()
+                .map(Vec2::from)
+                .collect();
+            Some((image, coords))
+        });
+
+        for face in indices.chunks_exact(3) {
+            let face = [face[0], face[1], face[2]].map(|i| i as usize);
+            if face.iter().any(|&i| i >= positions.len()) {
+                continue;
+            }
+
+            let mut color = factor;
+            if let Some(colors) = &colors {
+                color *= face.iter().filter_map(|&i| colors.get(i)).sum::<Vec3>() / 3.0;
+            }
+            if let Some((image, coords)) = &texture {
+                let uv = face.map(|i| coords.get(i).copied().unwrap_or_default());
+                let texels = SAMPLES
+                    .iter()
+                    .filter_map(|w| texel(image, uv[0] * w.x + uv[1] * w.y + uv[2] * w.z));
+                let count = texels.clone().count();
+                if count > 0 {
+                    color *= texels.sum::<Vec3>() / count as f32;
+                }
+            }
+
+            triangles.push(Triangle {
+                vertices: face.map(|i| positions[i]),
+                color: to_srgb(color),
+            });
+        }
+    }
+
+    for child in node.children() {
+        add_node(&child, transform, buffers, images, triangles);
+    }
+}
+
+/// Linear color of the nearest texel to a texture coordinate, repeating the texture outside `0..1`.
+fn texel(image: &image::Data, uv: Vec2) -> Option<Vec3> {
+    let (channels, bytes) = match image.format {
+        image::Format::R8 => (1, 1),
+        image::Format::R8G8 => (2, 1),
+        image::Format::R8G8B8 => (3, 1),
+        image::Format::R8G8B8A8 => (4, 1),
+        image::Format::R16 => (1, 2),
+        image::Format::R16G16 => (2, 2),
+        image::Format::R16G16B16 => (3, 2),
+        image::Format::R16G16B16A16 => (4, 2),
+        _ => return None,
+    };
+    let size = Vec2::new(image.width as f32, image.height as f32);
+    let pixel = (uv.rem_euclid(Vec2::ONE) * size)
+        .floor()
+        .min(size - 1.0)
+        .as_uvec2();
+    let start = (pixel.x + pixel.y * image.width) as usize * channels * bytes;
+
+    // the most significant byte of each channel, with gray images copied to every channel
+    let channel = |c: usize| {
+        let c = if channels < 3 { 0 } else { c };
+        image.pixels.get(start + c * bytes + bytes - 1).copied()
+    };
+    let srgb = Vec3::new(channel(0)? as f32, channel(1)? as f32, channel(2)? as f32) / 255.0;
+    Some(srgb.powf(2.2))
+}
+
+fn to_srgb(color: Vec3) -> U8Vec3 {
+    (color.clamp(Vec3::ZERO, Vec3::ONE).powf(1.0 / 2.2) * 255.0)
+        .round()
+        .as_u8vec3()
+}
+
+fn format_error(err: ::gltf::Error) -> ImportError {
+    ImportError::Format(format!("gltf: {err}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::IVec3;
+
+    use super::*;
+
+    /// Builds a binary glTF file with one triangle per node, each given as a JSON node and its material.
+    fn glb(nodes: &[(&str, &str)]) -> Vec<u8> {
+        let positions: [f32; 9] = [0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0];
+        let bin: Vec<u8> = positions.iter().flat_map(|v| v.to_le_bytes()).collect();
+
+        let node_list: Vec<String> = nodes
+            .iter()
+            .enumerate()
+            .map(|(i, (node, _))| format!(r#"{{"mesh": {i}{node}}}"#))
+            .collect();
+        let meshes: Vec<String> = (0..nodes.len())
+            .map(|i| {
+                format!(
+                    r#"{{"primitives": [{{"attributes": {{"POSITION": 0}}, "material": {i}}}]}}"#
+                )
+            })
+            .collect();
+        let materials: Vec<&str> = nodes.iter().map(|(_, material)| *material).collect();
+        let scene: Vec<String> = (0..nodes.len()).map(|i| i.to_string()).collect();
+
+        let mut json = format!(
+            r#"{{
+                "asset": {{"version": "2.0"}},
+                "scene": 0,
+                "scenes": [{{"nodes": [{}]}}],
+                "nodes": [{}],
+                "meshes": [{}],
+                "materials": [{}],
+                "buffers": [{{"byteLength": {len}}}],
+                "bufferViews": [{{"buffer": 0, "byteLength": {len}}}],
+                "accessors": [{{
+                    "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                    "min": [0, 0, 0], "max": [1, 0, 1]
+                }}]
+            }}"#,
+            scene.join(","),
+            node_list.join(","),
+            meshes.join(","),
+            materials.join(","),
+            len = bin.len(),
+        )
+        .into_bytes();
+        json.resize(json.len().next_multiple_of(4), b' ');
+
+        let mut file = Vec::new();
+        file.extend(b"glTF");
+        file.extend(2u32.to_le_bytes());
+        file.extend(((12 + 8 + json.len() + 8 + bin.len()) as u32).to_le_bytes());
+        file.extend((json.len() as u32).to_le_bytes());
+        file.extend(b"JSON");
+        file.extend(json);
+        file.extend((bin.len() as u32).to_le_bytes());
+        file.extend(b"BIN\0");
+        file.extend(bin);
+        file
+    }
+
+    #[test]
+    fn materials_and_transforms() {
+        let file = glb(&[
+            (
+                "",
+                r#"{"pbrMetallicRoughness": {"baseColorFactor": [1, 0, 0, 1]}}"#,
+            ),
+            (
+                r#", "translation": [3, 0, 0]"#,
+                r#"{"pbrMetallicRoughness": {"baseColorFactor": [0, 0.2, 1, 1]}}"#,
+            ),
+        ]);
+
+        let grid = parse(&file, 8, Fill::Surface).expect("failed to parse");
+        // two triangles spanning 4 units in x
+        assert_eq!(grid.size(), IVec3::new(8, 1, 2));
+        assert_eq!(
+            grid.get(IVec3::ZERO).map(|v| v.color),
+            Some(U8Vec3::new(255, 0, 0))
+        );
+        assert_eq!(
+            grid.get(IVec3::new(6, 0, 0)).map(|v| v.color),
+            Some(U8Vec3::new(0, 123, 255))
+        );
+    }
+
+    #[test]
+    fn texels() {
+        // 2x1 RGB texture, red then blue
+        let image = image::Data {
+            pixels: vec![255, 0, 0, 0, 0, 255],
+            format: image::Format::R8G8B8,
+            width: 2,
+            height: 1,
+        };
+        assert_eq!(texel(&image, Vec2::new(0.25, 0.5)), Some(Vec3::X));
+        assert_eq!(texel(&image, Vec2::new(0.75, 0.5)), Some(Vec3::Z));
+        assert_eq!(texel(&image, Vec2::new(-0.25, 2.5)), Some(Vec3::Z));
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(parse(b"{}", 8, Fill::Surface).is_err());
+        assert!(parse(b"glTF", 8, Fill::Surface).is_err());
+    }
+}
@@ -0,0 +1,220 @@
+use glam::{IVec3, U8Vec3, Vec3A};
+
+use crate::voxel::{grid::VoxelGrid, Voxel};
+
+/// Color of triangles without a material.
+pub const DEFAULT_COLOR: U8Vec3 = U8Vec3::new(200, 200, 200);
+
+/// A triangle with a flat color.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Triangle {
+    pub vertices: [Vec3A; 3],
+    pub color: U8Vec3,
+}
+
+/// Which voxels of a mesh are filled.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Fill {
+    /// Only voxels touching a triangle.
+    #[default]
+    Surface,
+    /// The surface and everything inside it, for closed meshes.
+    Solid,
+}
+
+/// Rasterizes triangles into a grid, scaled so the longest side of the mesh is `resolution` voxels.
+pub fn voxelize(triangles: &[Triangle], resolution: u32, fill: Fill) -> VoxelGrid {
+    if triangles.is_empty() {
+        return VoxelGrid::new(IVec3::ZERO);
+    }
+    let vertices = triangles.iter().flat_map(|t| t.vertices);
+    let min = vertices.clone().fold(Vec3A::INFINITY, Vec3A::min);
+    let max = vertices.fold(Vec3A::NEG_INFINITY, Vec3A::max);
+
+    let extent = (max - min).max_element().max(f32::EPSILON);
+    let scale = resolution.max(1) as f32 / extent;
+    let size = ((max - min) * scale).ceil().as_ivec3().max(IVec3::ONE);
+
+    let triangles: Vec<_> = triangles
+        .iter()
+        .map(|t| Triangle {
+            vertices: t.vertices.map(|v| (v - min) * scale),
+            color: t.color,
+        })
+        .collect();
+
+    let mut grid = VoxelGrid::new(size);
+    for triangle in &triangles {
+        let (lo, hi) = voxel_bounds(triangle, size);
+        for x in lo.x..=hi.x {
+            for y in lo.y..=hi.y {
+                for z in lo.z..=hi.z {
+                    let pos = IVec3::new(x, y, z);
+                    if triangle_box_overlap(pos.as_vec3a() + 0.5, 0.5, triangle.vertices) {
+                        grid.set(pos, Some(Voxel::from(triangle.color)));
+                    }
+                }
+            }
+        }
+    }
+
+    if fill == Fill::Solid {
+        fill_inside(&mut grid, &triangles);
+    }
+
+    grid
+}
+
+/// Range of voxels covered by the bounding box of a triangle, clamped to the grid.
+fn voxel_bounds(triangle: &Triangle, size: IVec3) -> (IVec3, IVec3) {
+    let [a, b, c] = triangle.vertices;
+    let clamp = |v: Vec3A| v.floor().as_ivec3().clamp(IVec3::ZERO, size - 1);
+    let lo = clamp(a.min(b).min(c));
+    let hi = clamp(a.max(b).max(c));
+    (lo, hi)
+}
+
+/// Fills the voxels between pairs of surface crossings along vertical lines through each column.
+fn fill_inside(grid: &mut VoxelGrid, triangles: &[Triangle]) {
+    let size = grid.size();
+
+    // crossings of each column, found by visiting the columns under every triangle
+    let mut columns = vec![Vec::new(); (size.x * size.z) as usize];
+    for triangle in triangles {
+        let (lo, hi) = voxel_bounds(triangle, size);
+        for x in lo.x..=hi.x {
+            for z in lo.z..=hi.z {
+                // nudge off the voxel centers so lines don't run exactly through shared edges
+                let p = (x as f32 + 0.500_123, z as f32 + 0.500_271);
+                if let Some(y) = vertical_crossing(triangle.vertices, p) {
+                    columns[(x + size.x * z) as usize].push((y, triangle.color));
+                }
+            }
+        }
+    }
+
+    for x in 0..size.x {
+        for z in 0..size.z {
+            let crossings = &mut columns[(x + size.x * z) as usize];
+            crossings.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+            for pair in crossings.chunks_exact(2) {
+                let [(enter, color), (exit, _)] = [pair[0], pair[1]];
+                let start = (enter - 0.5).ceil().max(0.0) as i32;
+                let end = ((exit - 0.5).floor() as i32).min(size.y - 1);
+                for y in start..=end {
+                    let pos = IVec3::new(x, y, z);
+                    if grid.get(pos).is_none() {
+                        grid.set(pos, Some(Voxel::from(color)));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Height where a vertical line through `(x, z)` crosses a triangle.
+fn vertical_crossing([a, b, c]: [Vec3A; 3], (x, z): (f32, f32)) -> Option<f32> {
+    let edge = |p: Vec3A, q: Vec3A| (q.x - p.x) * (z - p.z) - (q.z - p.z) * (x - p.x);
+    let area = (b.x - a.x) * (c.z - a.z) - (b.z - a.z) * (c.x - a.x);
+    if area.abs() < f32::EPSILON {
+        return None;
+    }
+
+    // barycentric weights, all positive when the point is inside
+    let wa = edge(b, c) / area;
+    let wb = edge(c, a) / area;
+    let wc = edge(a, b) / area;
+    (wa >= 0.0 && wb >= 0.0 && wc >= 0.0).then_some(wa * a.y + wb * b.y + wc * c.y)
+}
+
+/// Checks if a triangle intersects an axis-aligned cube using the separating axis theorem.
+///
+/// See: https://fileadmin.cs.lth.se/cs/Personal/Tomas_Akenine-Moller/code/tribox_tam.pdf
+fn triangle_box_overlap(center: Vec3A, half: f32, triangle: [Vec3A; 3]) -> bool {
+    let v = triangle.map(|p| p - center);
+    let edges = [v[1] - v[0], v[2] - v[1], v[0] - v[2]];
+
+    let separated = |axis: Vec3A| {
+        let p = v.map(|p| p.dot(axis));
+        let r = half * axis.abs().element_sum();
+        p[0].min(p[1]).min(p[2]) > r || p[0].max(p[1]).max(p[2]) < -r
+    };
+
+    // the box's face normals
+    if [Vec3A::X, Vec3A::Y, Vec3A::Z].into_iter().any(separated) {
+        return false;
+    }
+
+    // the triangle's normal
+    let normal = edges[0].cross(edges[1]);
+    if separated(normal) {
+        return false;
+    }
+
+    // cross products of the edges with the box's axes
+    !edges.iter().any(|e| {
+        [Vec3A::X, Vec3A::Y, Vec3A::Z]
+            .into_iter()
+            .any(|axis| separated(axis.cross(*e)))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Closed unit cube made of 12 triangles.
+    fn cube() -> Vec<Triangle> {
+        let corner =
+            |i: usize| Vec3A::new((i & 1) as f32, (i >> 1 & 1) as f32, (i >> 2 & 1) as f32);
+        let faces = [
+            [0, 1, 3, 2],
+            [4, 6, 7, 5],
+            [0, 4, 5, 1],
+            [2, 3, 7, 6],
+            [0, 2, 6, 4],
+            [1, 5, 7, 3],
+        ];
+        faces
+            .iter()
+            .flat_map(|&[a, b, c, d]| [[a, b, c], [a, c, d]])
+            .map(|idx| Triangle {
+                vertices: idx.map(corner),
+                color: DEFAULT_COLOR,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn overlap() {
+        let tri = [Vec3A::ZERO, Vec3A::X * 4.0, Vec3A::Z * 4.0];
+        assert!(triangle_box_overlap(Vec3A::new(0.5, 0.5, 0.5), 0.5, tri));
+        assert!(triangle_box_overlap(Vec3A::new(1.5, 0.4, 1.5), 0.5, tri));
+        assert!(!triangle_box_overlap(Vec3A::new(0.5, 1.5, 0.5), 0.5, tri));
+        // past the hypotenuse
+        assert!(!triangle_box_overlap(Vec3A::new(3.5, 0.0, 3.5), 0.5, tri));
+    }
+
+    #[test]
+    fn surface_cube() {
+        let grid = voxelize(&cube(), 8, Fill::Surface);
+        assert_eq!(grid.size(), IVec3::splat(8));
+
+        // every face is covered and the middle is hollow
+        assert_eq!(grid.count(), 8 * 8 * 8 - 6 * 6 * 6);
+        assert!(grid.get(IVec3::new(0, 3, 4)).is_some());
+        assert!(grid.get(IVec3::new(4, 4, 4)).is_none());
+    }
+
+    #[test]
+    fn solid_cube() {
+        let grid = voxelize(&cube(), 8, Fill::Solid);
+        assert_eq!(grid.count(), 8 * 8 * 8);
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!(voxelize(&[], 8, Fill::Solid).count(), 0);
+    }
+}
@@ -0,0 +1,118 @@
+use std::{error::Error, fmt, io, path::Path};
+
+use glam::IVec3;
+
+use crate::voxel::grid::VoxelGrid;
+
+pub mod anvil;
+pub mod gltf;
+pub mod mesh;
+pub mod nbt;
+pub mod obj;
+pub mod palette;
+pub mod schematic;
+#[cfg(feature = "vdb")]
+pub mod vdb;
+pub mod vox;
+
+use anvil::Window;
+use mesh::Fill;
+use palette::Palette;
+
+/// Most voxels an imported model can have, 2 GiB of them, so a corrupt or oversized file fails to load instead of
+/// running out of memory.
+pub const MAX_VOXELS: usize = 1 << 28;
+
+/// Settings shared by the importers.
+#[derive(Clone, Debug)]
+pub struct ImportOptions {
+    /// Block colors for Minecraft files.
+    pub palette: Palette,
+    /// World coordinates to load from Minecraft worlds.
+    pub window: Option<Window>,
+    /// Number of voxels along the longest side of voxelized meshes.
+    pub resolution: u32,
+    /// Whether to fill the inside of voxelized meshes.
+    pub fill: Fill,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        Self {
+            palette: Palette::default(),
+            window: None,
+            resolution: 128,
+            fill: Fill::Surface,
+        }
+    }
+}
+
+/// Loads a model file into a voxel grid, picking the format from the file extension.
+///
+/// Folders are loaded as the `region` folder of a Minecraft world.
+pub fn load(path: impl AsRef<Path>, options: &ImportOptions) -> Result<VoxelGrid, ImportError> {
+    let path = path.as_ref();
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase());
+
+    if path.is_dir() {
+        return anvil::load(path, options.window, &options.palette);
+    }
+
+    match extension.as_deref() {
+        Some("schem" | "schematic") => schematic::load(path, &options.palette),
+        Some("mca") => anvil::load(path, options.window, &options.palette),
+        Some("obj") => obj::load(path, options.resolution, options.fill),
+        Some("gltf" | "glb") => gltf::load(path, options.resolution, options.fill),
+        Some("vox") => vox::load(path),
+        #[cfg(feature = "vdb")]
+        Some("nvdb") => vdb::load(path),
+        #[cfg(not(feature = "vdb"))]
+        Some("nvdb") => Err(ImportError::Format(
+            "NanoVDB support is disabled, rebuild with `--features vdb`".into(),
+        )),
+        _ => Err(ImportError::Format(format!(
+            "unsupported file type `{}`",
+            path.display()
+        ))),
+    }
+}
+
+/// Number of voxels in a model of a size, checked against [`MAX_VOXELS`] before the importers allocate its grid.
+fn volume(size: IVec3) -> Result<usize, ImportError> {
+    VoxelGrid::volume(size)
+        .filter(|&len| len <= MAX_VOXELS)
+        .ok_or_else(|| {
+            ImportError::Format(format!(
+                "model size {size} is larger than {MAX_VOXELS} voxels"
+            ))
+        })
+}
+
+/// Errors from importing a model.
+#[derive(Debug)]
+pub enum ImportError {
+    Io(io::Error),
+    /// The file is malformed or uses an unsupported feature.
+    Format(String),
+    Palette(toml::de::Error),
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImportError::Io(err) => write!(f, "failed to read file: {err}"),
+            ImportError::Format(err) => write!(f, "invalid file: {err}"),
+            ImportError::Palette(err) => write!(f, "failed to parse palette: {err}"),
+        }
+    }
+}
+
+impl Error for ImportError {}
+
+impl From<io::Error> for ImportError {
+    fn from(err: io::Error) -> Self {
+        ImportError::Io(err)
+    }
+}
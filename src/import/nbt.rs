@@ -0,0 +1,335 @@
+use std::{collections::HashMap, io::Read};
+
+use flate2::read::{GzDecoder, ZlibDecoder};
+
+use super::ImportError;
+
+/// A value in Minecraft's Named Binary Tag format.
+///
+/// See: https://minecraft.wiki/w/NBT_format
+#[derive(Clone, Debug, PartialEq)]
+pub enum Tag {
+    Byte(i8),
+    Short(i16),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    ByteArray(Vec<u8>),
+    String(String),
+    List(Vec<Tag>),
+    Compound(HashMap<String, Tag>),
+    IntArray(Vec<i32>),
+    LongArray(Vec<i64>),
+}
+
+impl Tag {
+    /// Gets a field of a compound tag.
+    pub fn get(&self, key: &str) -> Option<&Tag> {
+        match self {
+            Tag::Compound(fields) => fields.get(key),
+            _ => None,
+        }
+    }
+
+    /// Reads any integer tag as an `i64`.
+    pub fn as_int(&self) -> Option<i64> {
+        match *self {
+            Tag::Byte(v) => Some(v as i64),
+            Tag::Short(v) => Some(v as i64),
+            Tag::Int(v) => Some(v as i64),
+            Tag::Long(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Tag::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Tag::ByteArray(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_longs(&self) -> Option<&[i64]> {
+        match self {
+            Tag::LongArray(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_list(&self) -> Option<&[Tag]> {
+        match self {
+            Tag::List(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_compound(&self) -> Option<&HashMap<String, Tag>> {
+        match self {
+            Tag::Compound(v) => Some(v),
+            _ => None,
+        }
+    }
+}
+
+/// Decompresses gzip or zlib data, passing uncompressed data through.
+pub fn decompress(bytes: &[u8]) -> Result<Vec<u8>, ImportError> {
+    let mut out = Vec::new();
+    match bytes {
+        [0x1f, 0x8b, ..] => GzDecoder::new(bytes).read_to_end(&mut out)?,
+        [0x78, ..] => ZlibDecoder::new(bytes).read_to_end(&mut out)?,
+        _ => return Ok(bytes.to_vec()),
+    };
+    Ok(out)
+}
+
+/// Parses a (possibly compressed) NBT file, returning the root tag and its name.
+pub fn read(bytes: &[u8]) -> Result<(String, Tag), ImportError> {
+    let bytes = decompress(bytes)?;
+    let mut reader = Reader { bytes: &bytes };
+
+    let id = reader.u8()?;
+    if id != COMPOUND {
+        return Err(format_error("root tag is not a compound"));
+    }
+    let name = reader.string()?;
+    let root = reader.payload(id, 0)?;
+    Ok((name, root))
+}
+
+const END: u8 = 0;
+const COMPOUND: u8 = 10;
+
+/// Nesting limit so malformed files can't overflow the stack.
+const MAX_DEPTH: usize = 512;
+
+fn format_error(msg: &str) -> ImportError {
+    ImportError::Format(format!("nbt: {msg}"))
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], ImportError> {
+        if self.bytes.len() < n {
+            return Err(format_error("unexpected end of data"));
+        }
+        let (head, tail) = self.bytes.split_at(n);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], ImportError> {
+        Ok(self.take(N)?.try_into().expect("took N bytes"))
+    }
+
+    fn u8(&mut self) -> Result<u8, ImportError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn i32(&mut self) -> Result<i32, ImportError> {
+        Ok(i32::from_be_bytes(self.array()?))
+    }
+
+    fn len(&mut self) -> Result<usize, ImportError> {
+        let len = self.i32()?;
+        // every element takes at least a byte, which catches corrupt lengths before allocating
+        if len < 0 || len as usize > self.bytes.len() {
+            return Err(format_error("invalid length"));
+        }
+        Ok(len as usize)
+    }
+
+    fn string(&mut self) -> Result<String, ImportError> {
+        let len = u16::from_be_bytes(self.array()?) as usize;
+        Ok(String::from_utf8_lossy(self.take(len)?).into_owned())
+    }
+
+    fn payload(&mut self, id: u8, depth: usize) -> Result<Tag, ImportError> {
+        if depth > MAX_DEPTH {
+            return Err(format_error("tags are nested too deeply"));
+        }
+
+        let tag = match id {
+            1 => Tag::Byte(self.u8()? as i8),
+            2 => Tag::Short(i16::from_be_bytes(self.array()?)),
+            3 => Tag::Int(self.i32()?),
+            4 => Tag::Long(i64::from_be_bytes(self.array()?)),
+            5 => Tag::Float(f32::from_be_bytes(self.array()?)),
+            6 => Tag::Double(f64::from_be_bytes(self.array()?)),
+            7 => {
+                let len = self.len()?;
+                Tag::ByteArray(self.take(len)?.to_vec())
+            }
+            8 => Tag::String(self.string()?),
+            9 => {
+                let id = self.u8()?;
+                let len = self.len()?;
+                if id == END && len > 0 {
+                    return Err(format_error("list of end tags"));
+                }
+                let items = (0..len)
+                    .map(|_| self.payload(id, depth + 1))
+                    .collect::<Result<_, _>>()?;
+                Tag::List(items)
+            }
+            COMPOUND => {
+                let mut fields = HashMap::new();
+                loop {
+                    let id = self.u8()?;
+                    if id == END {
+                        break;
+                    }
+                    let name = self.string()?;
+                    fields.insert(name, self.payload(id, depth + 1)?);
+                }
+                Tag::Compound(fields)
+            }
+            11 => {
+                let len = self.len()?;
+                let items = self.take(len * 4)?.chunks_exact(4);
+                Tag::IntArray(
+                    items
+                        .map(|c| i32::from_be_bytes(c.try_into().unwrap()))
+                        .collect(),
+                )
+            }
+            12 => {
+                let len = self.len()?;
+                let items = self.take(len * 8)?.chunks_exact(8);
+                Tag::LongArray(
+                    items
+                        .map(|c| i64::from_be_bytes(c.try_into().unwrap()))
+                        .collect(),
+                )
+            }
+            id => return Err(format_error(&format!("unknown tag type {id}"))),
+        };
+        Ok(tag)
+    }
+}
+
+/// Encodes a named root tag, used to build test files.
+#[cfg(test)]
+pub(crate) fn write(name: &str, tag: &Tag) -> Vec<u8> {
+    fn id(tag: &Tag) -> u8 {
+        match tag {
+            Tag::Byte(_) => 1,
+            Tag::Short(_) => 2,
+            Tag::Int(_) => 3,
+            Tag::Long(_) => 4,
+            Tag::Float(_) => 5,
+            Tag::Double(_) => 6,
+            Tag::ByteArray(_) => 7,
+            Tag::String(_) => 8,
+            Tag::List(_) => 9,
+            Tag::Compound(_) => COMPOUND,
+            Tag::IntArray(_) => 11,
+            Tag::LongArray(_) => 12,
+        }
+    }
+
+    fn string(out: &mut Vec<u8>, s: &str) {
+        out.extend((s.len() as u16).to_be_bytes());
+        out.extend(s.as_bytes());
+    }
+
+    fn payload(out: &mut Vec<u8>, tag: &Tag) {
+        match tag {
+            Tag::Byte(v) => out.push(*v as u8),
+            Tag::Short(v) => out.extend(v.to_be_bytes()),
+            Tag::Int(v) => out.extend(v.to_be_bytes()),
+            Tag::Long(v) => out.extend(v.to_be_bytes()),
+            Tag::Float(v) => out.extend(v.to_be_bytes()),
+            Tag::Double(v) => out.extend(v.to_be_bytes()),
+            Tag::ByteArray(v) => {
+                out.extend((v.len() as i32).to_be_bytes());
+                out.extend(v);
+            }
+            Tag::String(s) => string(out, s),
+            Tag::List(items) => {
+                out.push(items.first().map_or(END, id));
+                out.extend((items.len() as i32).to_be_bytes());
+                items.iter().for_each(|item| payload(out, item));
+            }
+            Tag::Compound(fields) => {
+                for (name, field) in fields {
+                    out.push(id(field));
+                    string(out, name);
+                    payload(out, field);
+                }
+                out.push(END);
+            }
+            Tag::IntArray(v) => {
+                out.extend((v.len() as i32).to_be_bytes());
+                v.iter().for_each(|x| out.extend(x.to_be_bytes()));
+            }
+            Tag::LongArray(v) => {
+                out.extend((v.len() as i32).to_be_bytes());
+                v.iter().for_each(|x| out.extend(x.to_be_bytes()));
+            }
+        }
+    }
+
+    let mut out = vec![id(tag)];
+    string(&mut out, name);
+    payload(&mut out, tag);
+    out
+}
+
+/// Builds a compound tag from name and value pairs.
+#[cfg(test)]
+pub(crate) fn compound<const N: usize>(fields: [(&str, Tag); N]) -> Tag {
+    Tag::Compound(fields.into_iter().map(|(k, v)| (k.into(), v)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{write::GzEncoder, Compression};
+
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let tag = compound([
+            ("byte", Tag::Byte(-3)),
+            ("short", Tag::Short(300)),
+            ("long", Tag::Long(1 << 40)),
+            ("double", Tag::Double(0.5)),
+            ("name", Tag::String("stone".into())),
+            ("bytes", Tag::ByteArray(vec![1, 2, 3])),
+            ("list", Tag::List(vec![Tag::Int(1), Tag::Int(2)])),
+            ("empty", Tag::List(vec![])),
+            ("longs", Tag::LongArray(vec![-1, 7])),
+            ("nested", compound([("ints", Tag::IntArray(vec![4, 5]))])),
+        ]);
+
+        let bytes = write("root", &tag);
+        assert_eq!(read(&bytes).unwrap(), ("root".into(), tag.clone()));
+
+        // the same file compressed with gzip
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&bytes).unwrap();
+        let gzipped = encoder.finish().unwrap();
+        assert_eq!(read(&gzipped).unwrap().1, tag);
+    }
+
+    #[test]
+    fn truncated() {
+        let bytes = write("root", &compound([("name", Tag::String("stone".into()))]));
+        for len in 0..bytes.len() {
+            assert!(read(&bytes[..len]).is_err());
+        }
+    }
+}
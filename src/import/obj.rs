@@ -0,0 +1,127 @@
+use std::path::Path;
+
+use glam::{U8Vec3, Vec3, Vec3A};
+
+use super::{
+    mesh::{self, Fill, Triangle, DEFAULT_COLOR},
+    ImportError,
+};
+use crate::voxel::grid::VoxelGrid;
+
+/// Loads a Wavefront OBJ mesh and voxelizes it so its longest side spans `resolution` voxels.
+///
+/// Triangles are colored by their vertex colors if the file has them, otherwise by the diffuse color of their
+/// material from the accompanying `.mtl` file.
+pub fn load(path: impl AsRef<Path>, resolution: u32, fill: Fill) -> Result<VoxelGrid, ImportError> {
+    let (models, materials) =
+        tobj::load_obj(path.as_ref(), &load_options()).map_err(format_error)?;
+    // a missing material library only loses the colors
+    let materials = materials.unwrap_or_default();
+
+    Ok(mesh::voxelize(
+        &triangles(&models, &materials),
+        resolution,
+        fill,
+    ))
+}
+
+fn load_options() -> tobj::LoadOptions {
+    tobj::LoadOptions {
+        triangulate: true,
+        single_index: true,
+        ..Default::default()
+    }
+}
+
+/// Flattens the meshes of a file into colored triangles.
+fn triangles(models: &[tobj::Model], materials: &[tobj::Material]) -> Vec<Triangle> {
+    let mut triangles = Vec::new();
+    for model in models {
+        let mesh = &model.mesh;
+        let vertex = |i: u32| Vec3A::from_slice(&mesh.positions[3 * i as usize..]);
+        let vertex_color = |i: u32| Vec3::from_slice(&mesh.vertex_color[3 * i as usize..]);
+
+        let material_color = mesh
+            .material_id
+            .and_then(|id| materials.get(id))
+            .and_then(|material| material.diffuse)
+            .map(|diffuse| to_color(Vec3::from(diffuse)));
+
+        for face in mesh.indices.chunks_exact(3) {
+            let color = if mesh.vertex_color.is_empty() {
+                material_color.unwrap_or(DEFAULT_COLOR)
+            } else {
+                to_color(face.iter().map(|&i| vertex_color(i)).sum::<Vec3>() / 3.0)
+            };
+            triangles.push(Triangle {
+                vertices: [vertex(face[0]), vertex(face[1]), vertex(face[2])],
+                color,
+            });
+        }
+    }
+    triangles
+}
+
+fn to_color(color: Vec3) -> U8Vec3 {
+    (color.clamp(Vec3::ZERO, Vec3::ONE) * 255.0)
+        .round()
+        .as_u8vec3()
+}
+
+fn format_error(err: tobj::LoadError) -> ImportError {
+    ImportError::Format(format!("obj: {err}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::BufReader;
+
+    use super::*;
+
+    fn parse(obj: &str, mtl: &str) -> Vec<Triangle> {
+        let (models, materials) =
+            tobj::load_obj_buf(&mut BufReader::new(obj.as_bytes()), &load_options(), |_| {
+                tobj::load_mtl_buf(&mut BufReader::new(mtl.as_bytes()))
+            })
+            .expect("failed to parse");
+        triangles(&models, &materials.expect("failed to parse materials"))
+    }
+
+    #[test]
+    fn materials() {
+        let obj = "mtllib quad.mtl
+v 0 0 0
+v 1 0 0
+v 1 0 1
+v 0 0 1
+v 0 1 0
+usemtl red
+f 1 2 3 4
+usemtl missing
+f 1 2 5
+";
+        let mtl = "newmtl red
+Kd 1 0 0
+";
+        let triangles = parse(obj, mtl);
+
+        // the quad is split in two
+        assert_eq!(triangles.len(), 3);
+        assert_eq!(triangles[0].color, U8Vec3::new(255, 0, 0));
+        assert_eq!(triangles[1].color, U8Vec3::new(255, 0, 0));
+        assert_eq!(triangles[2].color, DEFAULT_COLOR);
+        assert_eq!(triangles[2].vertices[2], Vec3A::Y);
+    }
+
+    #[test]
+    fn vertex_colors() {
+        let obj = "v 0 0 0 1 0 0
+v 1 0 0 0 1 0
+v 0 1 0 0 0 1
+f 1 2 3
+";
+        let triangles = parse(obj, "");
+        assert_eq!(triangles.len(), 1);
+        assert_eq!(triangles[0].color, U8Vec3::splat(85));
+    }
+}
@@ -0,0 +1,583 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use glam::U8Vec3;
+use serde::Deserialize;
+
+use super::ImportError;
+use crate::voxel::{water::WATER, Voxel, VoxelKind};
+
+/// Maps Minecraft block names to voxel colors.
+///
+/// Custom palettes are TOML files that override or add to the built-in colors:
+///
+/// ```toml
+/// fallback = [200, 0, 200]
+///
+/// [blocks]
+/// "minecraft:stone" = [110, 110, 110]
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct Palette {
+    colors: HashMap<String, U8Vec3>,
+    /// Color of blocks that aren't in the palette.
+    fallback: U8Vec3,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PaletteFile {
+    fallback: Option<[u8; 3]>,
+    #[serde(default)]
+    blocks: HashMap<String, [u8; 3]>,
+}
+
+/// Blocks that are never turned into voxels.
+const AIR: [&str; 4] = [
+    "minecraft:air",
+    "minecraft:cave_air",
+    "minecraft:void_air",
+    "minecraft:structure_void",
+];
+
+/// Dye colors in the order of their legacy data values.
+const DYES: [(&str, [u8; 3]); 16] = [
+    ("white", [233, 236, 236]),
+    ("orange", [240, 118, 19]),
+    ("magenta", [189, 68, 179]),
+    ("light_blue", [58, 175, 217]),
+    ("yellow", [248, 197, 39]),
+    ("lime", [112, 185, 25]),
+    ("pink", [237, 141, 172]),
+    ("gray", [62, 68, 71]),
+    ("light_gray", [142, 142, 134]),
+    ("cyan", [21, 137, 145]),
+    ("purple", [121, 42, 172]),
+    ("blue", [53, 57, 157]),
+    ("brown", [114, 71, 40]),
+    ("green", [84, 109, 27]),
+    ("red", [160, 39, 34]),
+    ("black", [20, 21, 25]),
+];
+
+/// Blocks that come in every dye color (`minecraft:red_wool` etc).
+const DYED: [&str; 7] = [
+    "wool",
+    "carpet",
+    "concrete",
+    "concrete_powder",
+    "terracotta",
+    "stained_glass",
+    "stained_glass_pane",
+];
+
+/// Suffixes of block shapes that share the color of a full block (`oak_stairs` uses `oak_planks`).
+const SHAPES: [&str; 9] = [
+    "_stairs",
+    "_slab",
+    "_wall",
+    "_fence_gate",
+    "_fence",
+    "_door",
+    "_trapdoor",
+    "_button",
+    "_pressure_plate",
+];
+
+/// Average texture colors of common blocks.
+const COLORS: &[(&str, [u8; 3])] = &[
+    ("stone", [125, 125, 125]),
+    ("granite", [149, 103, 85]),
+    ("diorite", [188, 188, 188]),
+    ("andesite", [136, 136, 136]),
+    ("deepslate", [80, 80, 82]),
+    ("cobblestone", [127, 127, 127]),
+    ("mossy_cobblestone", [110, 118, 94]),
+    ("smooth_stone", [158, 158, 158]),
+    ("stone_bricks", [122, 121, 122]),
+    ("bedrock", [85, 85, 85]),
+    ("grass_block", [95, 159, 53]),
+    ("dirt", [134, 96, 67]),
+    ("coarse_dirt", [119, 85, 59]),
+    ("podzol", [91, 63, 24]),
+    ("mycelium", [111, 98, 101]),
+    ("farmland", [81, 44, 15]),
+    ("dirt_path", [148, 122, 65]),
+    ("mud", [60, 57, 60]),
+    ("clay", [160, 166, 179]),
+    ("sand", [219, 207, 163]),
+    ("red_sand", [190, 102, 33]),
+    ("gravel", [131, 127, 126]),
+    ("sandstone", [216, 203, 155]),
+    ("red_sandstone", [186, 99, 29]),
+    ("snow", [249, 254, 254]),
+    ("snow_block", [249, 254, 254]),
+    ("ice", [145, 183, 253]),
+    ("packed_ice", [141, 180, 250]),
+    ("blue_ice", [116, 167, 253]),
+    ("lava", [207, 92, 20]),
+    ("obsidian", [15, 10, 24]),
+    ("netherrack", [97, 38, 38]),
+    ("soul_sand", [81, 62, 50]),
+    ("glowstone", [171, 131, 84]),
+    ("end_stone", [219, 222, 158]),
+    ("oak_log", [109, 85, 50]),
+    ("spruce_log", [58, 37, 16]),
+    ("birch_log", [216, 215, 210]),
+    ("jungle_log", [85, 67, 25]),
+    ("acacia_log", [103, 96, 86]),
+    ("dark_oak_log", [60, 46, 26]),
+    ("oak_planks", [162, 130, 78]),
+    ("spruce_planks", [114, 84, 48]),
+    ("birch_planks", [192, 175, 121]),
+    ("jungle_planks", [160, 115, 80]),
+    ("acacia_planks", [168, 90, 50]),
+    ("dark_oak_planks", [66, 43, 20]),
+    ("oak_leaves", [60, 125, 40]),
+    ("spruce_leaves", [50, 90, 50]),
+    ("birch_leaves", [90, 130, 60]),
+    ("jungle_leaves", [50, 130, 30]),
+    ("acacia_leaves", [70, 120, 30]),
+    ("dark_oak_leaves", [45, 110, 25]),
+    ("short_grass", [90, 140, 50]),
+    ("tall_grass", [90, 140, 50]),
+    ("fern", [80, 125, 50]),
+    ("vine", [60, 110, 30]),
+    ("cactus", [85, 127, 43]),
+    ("sugar_cane", [148, 192, 101]),
+    ("pumpkin", [198, 118, 24]),
+    ("melon", [111, 145, 30]),
+    ("hay_block", [166, 136, 38]),
+    ("bricks", [150, 97, 83]),
+    ("nether_bricks", [44, 21, 26]),
+    ("bookshelf", [117, 94, 59]),
+    ("glass", [200, 220, 225]),
+    ("glass_pane", [200, 220, 225]),
+    ("quartz_block", [235, 229, 222]),
+    ("prismarine", [99, 156, 151]),
+    ("sea_lantern", [172, 199, 190]),
+    ("terracotta", [152, 94, 67]),
+    ("coal_ore", [105, 105, 105]),
+    ("iron_ore", [136, 129, 122]),
+    ("gold_ore", [145, 133, 106]),
+    ("diamond_ore", [121, 141, 140]),
+    ("coal_block", [16, 15, 15]),
+    ("iron_block", [220, 220, 220]),
+    ("gold_block", [246, 208, 61]),
+    ("diamond_block", [98, 237, 228]),
+    ("emerald_block", [42, 203, 87]),
+    ("lapis_block", [30, 67, 140]),
+    ("redstone_block", [175, 24, 5]),
+    ("torch", [255, 215, 90]),
+    ("crafting_table", [120, 73, 42]),
+    ("furnace", [110, 110, 110]),
+    ("chest", [162, 115, 44]),
+    ("tnt", [180, 60, 45]),
+    ("sponge", [195, 192, 74]),
+    ("ladder", [125, 97, 55]),
+    ("rail", [125, 110, 90]),
+    ("iron_bars", [136, 139, 135]),
+    ("slime_block", [111, 192, 91]),
+];
+
+impl Default for Palette {
+    fn default() -> Self {
+        let mut colors: HashMap<String, U8Vec3> = COLORS
+            .iter()
+            .map(|&(name, color)| (format!("minecraft:{name}"), U8Vec3::from_array(color)))
+            .collect();
+        colors.insert("minecraft:water".into(), WATER.color);
+
+        for (dye, color) in DYES {
+            for block in DYED {
+                colors.insert(
+                    format!("minecraft:{dye}_{block}"),
+                    U8Vec3::from_array(color),
+                );
+            }
+        }
+
+        Self {
+            colors,
+            fallback: U8Vec3::new(160, 160, 160),
+        }
+    }
+}
+
+impl Palette {
+    /// Reads a palette file, layering it over the built-in colors.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ImportError> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Parses a palette file from a string.
+    pub fn parse(contents: &str) -> Result<Self, ImportError> {
+        let file: PaletteFile = toml::from_str(contents).map_err(ImportError::Palette)?;
+
+        let mut palette = Self::default();
+        if let Some(fallback) = file.fallback {
+            palette.fallback = U8Vec3::from_array(fallback);
+        }
+        for (name, color) in file.blocks {
+            palette
+                .colors
+                .insert(namespaced(&name), U8Vec3::from_array(color));
+        }
+        Ok(palette)
+    }
+
+    /// Looks up the color of a block state such as `minecraft:oak_stairs[facing=east]`, or `None` for air.
+    pub fn color(&self, block: &str) -> Option<U8Vec3> {
+        // block properties don't change the color
+        let name = namespaced(block.split('[').next().unwrap_or(block));
+        if AIR.contains(&name.as_str()) {
+            return None;
+        }
+
+        if let Some(&color) = self.colors.get(&name) {
+            return Some(color);
+        }
+
+        // fall back to the full block for stairs, slabs, etc.
+        let base = SHAPES
+            .iter()
+            .find_map(|suffix| name.strip_suffix(suffix))
+            .and_then(|base| {
+                [
+                    format!("{base}_planks"),
+                    format!("{base}s"),
+                    base.to_string(),
+                ]
+                .into_iter()
+                .find_map(|name| self.colors.get(&name).copied())
+            });
+        Some(base.unwrap_or(self.fallback))
+    }
+
+    /// Looks up the voxel of a block state, with a kind guessed from the block's name, or `None` for air.
+    pub fn voxel(&self, block: &str) -> Option<Voxel> {
+        let color = self.color(block)?;
+        Some(Voxel::new(color, kind(block)))
+    }
+}
+
+/// Guesses the kind of a block from its name, `UNKNOWN` for blocks that aren't ground, plants or ores.
+fn kind(block: &str) -> VoxelKind {
+    let name = block.split('[').next().unwrap_or(block);
+    let name = name.rsplit(':').next().unwrap_or(name);
+    let name = SHAPES
+        .iter()
+        .find_map(|suffix| name.strip_suffix(suffix))
+        .unwrap_or(name);
+
+    match name {
+        "water" => VoxelKind::WATER,
+        "lava" => VoxelKind::LAVA,
+        "grass_block" | "moss_block" => VoxelKind::GRASS,
+        "dirt" | "coarse_dirt" | "rooted_dirt" | "podzol" | "mycelium" | "farmland"
+        | "dirt_path" | "mud" | "clay" => VoxelKind::DIRT,
+        "sand" | "red_sand" | "soul_sand" => VoxelKind::SAND,
+        "snow" | "snow_block" | "powder_snow" => VoxelKind::SNOW,
+        "gravel" => VoxelKind::GRAVEL,
+        "iron_ore" | "deepslate_iron_ore" => VoxelKind::IRON,
+        "gold_ore" | "deepslate_gold_ore" | "nether_gold_ore" => VoxelKind::GOLD,
+        "diamond_ore" | "deepslate_diamond_ore" | "amethyst_block" => VoxelKind::CRYSTAL,
+        "bricks" | "nether_bricks" | "stone_bricks" => VoxelKind::TILE,
+        _ if name.ends_with("_leaves") => VoxelKind::LEAVES,
+        _ if name.ends_with("glass") || name.ends_with("glass_pane") => VoxelKind::GLASS,
+        _ if name.ends_with("_planks") => VoxelKind::PLANKS,
+        _ if name.ends_with("_log") || name.ends_with("_wood") => VoxelKind::WOOD,
+        "stone" | "cobblestone" | "mossy_cobblestone" | "smooth_stone" | "granite" | "diorite"
+        | "andesite" | "deepslate" | "tuff" | "bedrock" | "sandstone" | "red_sandstone"
+        | "netherrack" | "end_stone" => VoxelKind::STONE,
+        _ => VoxelKind::UNKNOWN,
+    }
+}
+
+/// Adds the `minecraft:` namespace to names without one.
+fn namespaced(name: &str) -> String {
+    if name.contains(':') {
+        name.to_string()
+    } else {
+        format!("minecraft:{name}")
+    }
+}
+
+/// Names of pre-1.13 numeric block ids, indexed by id.
+const LEGACY: [&str; 175] = [
+    "air",
+    "stone",
+    "grass_block",
+    "dirt",
+    "cobblestone",
+    "oak_planks",
+    "oak_sapling",
+    "bedrock",
+    "water",
+    "water",
+    "lava",
+    "lava",
+    "sand",
+    "gravel",
+    "gold_ore",
+    "iron_ore",
+    "coal_ore",
+    "oak_log",
+    "oak_leaves",
+    "sponge",
+    "glass",
+    "lapis_ore",
+    "lapis_block",
+    "dispenser",
+    "sandstone",
+    "note_block",
+    "red_bed",
+    "powered_rail",
+    "detector_rail",
+    "sticky_piston",
+    "cobweb",
+    "short_grass",
+    "dead_bush",
+    "piston",
+    "piston_head",
+    "wool",
+    "moving_piston",
+    "dandelion",
+    "poppy",
+    "brown_mushroom",
+    "red_mushroom",
+    "gold_block",
+    "iron_block",
+    "smooth_stone",
+    "smooth_stone_slab",
+    "bricks",
+    "tnt",
+    "bookshelf",
+    "mossy_cobblestone",
+    "obsidian",
+    "torch",
+    "fire",
+    "spawner",
+    "oak_stairs",
+    "chest",
+    "redstone_wire",
+    "diamond_ore",
+    "diamond_block",
+    "crafting_table",
+    "wheat",
+    "farmland",
+    "furnace",
+    "furnace",
+    "oak_sign",
+    "oak_door",
+    "ladder",
+    "rail",
+    "cobblestone_stairs",
+    "oak_wall_sign",
+    "lever",
+    "stone_pressure_plate",
+    "iron_door",
+    "oak_pressure_plate",
+    "redstone_ore",
+    "redstone_ore",
+    "redstone_torch",
+    "redstone_torch",
+    "stone_button",
+    "snow",
+    "ice",
+    "snow_block",
+    "cactus",
+    "clay",
+    "sugar_cane",
+    "jukebox",
+    "oak_fence",
+    "pumpkin",
+    "netherrack",
+    "soul_sand",
+    "glowstone",
+    "nether_portal",
+    "jack_o_lantern",
+    "cake",
+    "repeater",
+    "repeater",
+    "stained_glass",
+    "oak_trapdoor",
+    "infested_stone",
+    "stone_bricks",
+    "brown_mushroom_block",
+    "red_mushroom_block",
+    "iron_bars",
+    "glass_pane",
+    "melon",
+    "pumpkin_stem",
+    "melon_stem",
+    "vine",
+    "oak_fence_gate",
+    "brick_stairs",
+    "stone_brick_stairs",
+    "mycelium",
+    "lily_pad",
+    "nether_bricks",
+    "nether_brick_fence",
+    "nether_brick_stairs",
+    "nether_wart",
+    "enchanting_table",
+    "brewing_stand",
+    "cauldron",
+    "end_portal",
+    "end_portal_frame",
+    "end_stone",
+    "dragon_egg",
+    "redstone_lamp",
+    "redstone_lamp",
+    "oak_planks",
+    "oak_slab",
+    "cocoa",
+    "sandstone_stairs",
+    "emerald_ore",
+    "ender_chest",
+    "tripwire_hook",
+    "tripwire",
+    "emerald_block",
+    "spruce_stairs",
+    "birch_stairs",
+    "jungle_stairs",
+    "command_block",
+    "beacon",
+    "cobblestone_wall",
+    "flower_pot",
+    "carrots",
+    "potatoes",
+    "oak_button",
+    "skeleton_skull",
+    "anvil",
+    "trapped_chest",
+    "light_weighted_pressure_plate",
+    "heavy_weighted_pressure_plate",
+    "comparator",
+    "comparator",
+    "daylight_detector",
+    "redstone_block",
+    "nether_quartz_ore",
+    "hopper",
+    "quartz_block",
+    "quartz_stairs",
+    "activator_rail",
+    "dropper",
+    "stained_terracotta",
+    "stained_glass_pane",
+    "acacia_leaves",
+    "acacia_log",
+    "acacia_stairs",
+    "dark_oak_stairs",
+    "slime_block",
+    "barrier",
+    "iron_trapdoor",
+    "prismarine",
+    "sea_lantern",
+    "hay_block",
+    "carpet",
+    "terracotta",
+    "coal_block",
+    "packed_ice",
+];
+
+/// Converts a pre-1.13 block id and data value to a block name.
+pub fn legacy_name(id: u16, data: u8) -> String {
+    let Some(name) = LEGACY.get(id as usize) else {
+        return format!("legacy:{id}");
+    };
+
+    // colored blocks keep their dye in the data value
+    let dye = DYES[(data & 0xf) as usize].0;
+    match *name {
+        "wool" | "carpet" | "stained_glass" | "stained_glass_pane" => format!("{dye}_{name}"),
+        "stained_terracotta" => format!("{dye}_terracotta"),
+        _ => name.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lookup() {
+        let palette = Palette::default();
+        let stone = U8Vec3::new(125, 125, 125);
+
+        assert_eq!(palette.color("minecraft:air"), None);
+        assert_eq!(palette.color("minecraft:stone"), Some(stone));
+        assert_eq!(palette.color("stone"), Some(stone));
+        assert_eq!(palette.color("minecraft:water[level=0]"), Some(WATER.color));
+        assert_eq!(
+            palette.color("minecraft:red_wool"),
+            Some(U8Vec3::new(160, 39, 34))
+        );
+
+        // shapes share the color of their full block
+        assert_eq!(
+            palette.color("minecraft:oak_stairs[facing=east]"),
+            palette.color("minecraft:oak_planks")
+        );
+        assert_eq!(
+            palette.color("minecraft:stone_brick_slab"),
+            palette.color("minecraft:stone_bricks")
+        );
+
+        assert_eq!(palette.color("mymod:thing"), Some(palette.fallback));
+    }
+
+    #[test]
+    fn kinds() {
+        let palette = Palette::default();
+        let kind = |block| palette.voxel(block).map(|v| v.kind);
+
+        assert_eq!(kind("minecraft:air"), None);
+        assert_eq!(kind("minecraft:water[level=0]"), Some(VoxelKind::WATER));
+        assert_eq!(kind("minecraft:lava[level=0]"), Some(VoxelKind::LAVA));
+        assert_eq!(kind("grass_block"), Some(VoxelKind::GRASS));
+        assert_eq!(kind("minecraft:cobblestone_stairs"), Some(VoxelKind::STONE));
+        assert_eq!(kind("minecraft:spruce_leaves"), Some(VoxelKind::LEAVES));
+        assert_eq!(kind("minecraft:glass"), Some(VoxelKind::GLASS));
+        assert_eq!(
+            kind("minecraft:red_stained_glass_pane"),
+            Some(VoxelKind::GLASS)
+        );
+        assert_eq!(kind("minecraft:red_wool"), Some(VoxelKind::UNKNOWN));
+        assert_eq!(
+            palette.voxel("minecraft:oak_log").map(|v| v.color),
+            palette.color("minecraft:oak_log")
+        );
+    }
+
+    #[test]
+    fn custom_palette() {
+        let palette = Palette::parse(
+            r#"
+            fallback = [1, 2, 3]
+
+            [blocks]
+            stone = [4, 5, 6]
+            "mymod:thing" = [7, 8, 9]
+            "#,
+        )
+        .expect("failed to parse");
+
+        assert_eq!(palette.color("minecraft:stone"), Some(U8Vec3::new(4, 5, 6)));
+        assert_eq!(palette.color("mymod:thing"), Some(U8Vec3::new(7, 8, 9)));
+        assert_eq!(palette.color("mymod:other"), Some(U8Vec3::new(1, 2, 3)));
+        assert_eq!(
+            palette.color("minecraft:dirt"),
+            Palette::default().color("minecraft:dirt")
+        );
+    }
+
+    #[test]
+    fn legacy_ids() {
+        assert_eq!(legacy_name(0, 0), "air");
+        assert_eq!(legacy_name(1, 0), "stone");
+        assert_eq!(legacy_name(35, 14), "red_wool");
+        assert_eq!(legacy_name(159, 0), "white_terracotta");
+        assert_eq!(legacy_name(1000, 0), "legacy:1000");
+    }
+}
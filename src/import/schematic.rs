@@ -0,0 +1,338 @@
+use std::{fs, path::Path};
+
+use glam::IVec3;
+
+use super::{
+    nbt::{self, Tag},
+    palette::{legacy_name, Palette},
+    volume, ImportError,
+};
+use crate::voxel::{grid::VoxelGrid, Voxel};
+
+/// Loads a Sponge (`.schem`) or MCEdit (`.schematic`) schematic file.
+pub fn load(path: impl AsRef<Path>, palette: &Palette) -> Result<VoxelGrid, ImportError> {
+    parse(&fs::read(path)?, palette)
+}
+
+/// Parses a schematic from the (possibly compressed) file contents.
+///
+/// See: https://github.com/SpongePowered/Schematic-Specification
+pub fn parse(bytes: &[u8], palette: &Palette) -> Result<VoxelGrid, ImportError> {
+    let (_, root) = nbt::read(bytes)?;
+
+    // version 3 nests everything in another compound
+    let schematic = root.get("Schematic").unwrap_or(&root);
+
+    let dim = |key| {
+        schematic
+            .get(key)
+            .and_then(Tag::as_int)
+            // sizes are unsigned shorts
+            .map(|v| v as u16 as i32)
+            .ok_or_else(|| missing(key))
+    };
+    let size = IVec3::new(dim("Width")?, dim("Height")?, dim("Length")?);
+    let len = volume(size)?;
+
+    let voxels = match schematic.get("Blocks") {
+        // MCEdit: byte block ids with 4-bit data values
+        Some(Tag::ByteArray(blocks)) => {
+            if blocks.len() != len {
+                return Err(ImportError::Format(format!(
+                    "schematic has {} blocks but its size is {size}",
+                    blocks.len()
+                )));
+            }
+            legacy_blocks(schematic, blocks, palette)?
+        }
+        // Sponge version 3
+        Some(blocks) => {
+            let data = blocks
+                .get("Data")
+                .and_then(Tag::as_bytes)
+                .ok_or_else(|| missing("Blocks.Data"))?;
+            let block_palette = blocks
+                .get("Palette")
+                .ok_or_else(|| missing("Blocks.Palette"))?;
+            palette_blocks(block_palette, data, len, palette)?
+        }
+        // Sponge version 1 and 2
+        None => {
+            let data = schematic
+                .get("BlockData")
+                .and_then(Tag::as_bytes)
+                .ok_or_else(|| missing("BlockData"))?;
+            let block_palette = schematic.get("Palette").ok_or_else(|| missing("Palette"))?;
+            palette_blocks(block_palette, data, len, palette)?
+        }
+    };
+
+    if voxels.len() < len {
+        return Err(ImportError::Format(format!(
+            "schematic has {} blocks but its size is {size}",
+            voxels.len()
+        )));
+    }
+
+    // blocks are ordered by y, then z, then x
+    let mut grid = VoxelGrid::new(size);
+    for y in 0..size.y {
+        for z in 0..size.z {
+            for x in 0..size.x {
+                grid.set(
+                    IVec3::new(x, y, z),
+                    voxels[(x + size.x * (z + size.z * y)) as usize],
+                );
+            }
+        }
+    }
+
+    Ok(grid)
+}
+
+fn missing(key: &str) -> ImportError {
+    ImportError::Format(format!("schematic is missing `{key}`"))
+}
+
+/// Voxels of blocks stored as legacy numeric ids.
+fn legacy_blocks(
+    schematic: &Tag,
+    blocks: &[u8],
+    palette: &Palette,
+) -> Result<Vec<Option<Voxel>>, ImportError> {
+    let data = schematic
+        .get("Data")
+        .and_then(Tag::as_bytes)
+        .ok_or_else(|| missing("Data"))?;
+    let add = schematic.get("AddBlocks").and_then(Tag::as_bytes);
+
+    let voxels = blocks
+        .iter()
+        .zip(data)
+        .enumerate()
+        .map(|(i, (&id, &data))| {
+            // ids above 255 keep their high bits in a nibble array
+            let high = add.map_or(0, |add| {
+                let nibble = add.get(i / 2).copied().unwrap_or(0);
+                if i % 2 == 0 {
+                    nibble >> 4
+                } else {
+                    nibble & 0xf
+                }
+            });
+            let id = id as u16 | (high as u16) << 8;
+            palette.voxel(&legacy_name(id, data))
+        })
+        .collect();
+    Ok(voxels)
+}
+
+/// Voxels of blocks stored as varint indices into a block state palette.
+fn palette_blocks(
+    block_palette: &Tag,
+    data: &[u8],
+    len: usize,
+    palette: &Palette,
+) -> Result<Vec<Option<Voxel>>, ImportError> {
+    let entries = block_palette
+        .as_compound()
+        .ok_or_else(|| missing("Palette"))?;
+
+    let mut states = vec![None; entries.len()];
+    for (name, index) in entries {
+        let index = index
+            .as_int()
+            .and_then(|i| usize::try_from(i).ok())
+            .filter(|&i| i < states.len())
+            .ok_or_else(|| ImportError::Format(format!("invalid palette index for `{name}`")))?;
+        states[index] = palette.voxel(name);
+    }
+
+    // every block takes at least one byte
+    if data.len() < len {
+        return Err(ImportError::Format(format!(
+            "schematic has {} bytes of block data for {len} blocks",
+            data.len()
+        )));
+    }
+
+    let mut voxels = Vec::with_capacity(len);
+    let mut bytes = data.iter();
+    while voxels.len() < len {
+        let index = read_varint(&mut bytes)
+            .ok_or_else(|| ImportError::Format("truncated block data".into()))?;
+        let state = states.get(index as usize).ok_or_else(|| {
+            ImportError::Format(format!("block palette index {index} out of range"))
+        })?;
+        voxels.push(*state);
+    }
+    Ok(voxels)
+}
+
+/// Reads an unsigned LEB128 varint.
+fn read_varint<'a>(bytes: &mut impl Iterator<Item = &'a u8>) -> Option<u32> {
+    let mut value = 0u32;
+    for shift in (0..35).step_by(7) {
+        let byte = *bytes.next()?;
+        value |= ((byte & 0x7f) as u32) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::import::nbt::compound;
+
+    fn stone() -> Option<Voxel> {
+        Palette::default().voxel("minecraft:stone")
+    }
+
+    #[test]
+    fn sponge_v2() {
+        // 2x1x2 with an air block and an index above 127 to exercise the varints
+        let mut block_palette: Vec<_> = (0..200)
+            .map(|i| (format!("mymod:block_{i}"), Tag::Int(i)))
+            .collect();
+        block_palette.push(("minecraft:air".into(), Tag::Int(200)));
+        block_palette.push(("minecraft:stone".into(), Tag::Int(201)));
+
+        let file = nbt::write(
+            "Schematic",
+            &compound([
+                ("Version", Tag::Int(2)),
+                ("Width", Tag::Short(2)),
+                ("Height", Tag::Short(1)),
+                ("Length", Tag::Short(2)),
+                (
+                    "Palette",
+                    Tag::Compound(block_palette.into_iter().collect()),
+                ),
+                (
+                    "BlockData",
+                    Tag::ByteArray(vec![0xc9, 0x01, 0xc8, 0x01, 0x05, 0xc9, 0x01]),
+                ),
+            ]),
+        );
+
+        let grid = parse(&file, &Palette::default()).expect("failed to parse");
+        assert_eq!(grid.size(), IVec3::new(2, 1, 2));
+        assert_eq!(grid.get(IVec3::new(0, 0, 0)), stone());
+        assert_eq!(grid.get(IVec3::new(1, 0, 0)), None);
+        assert!(grid.get(IVec3::new(0, 0, 1)).is_some());
+        assert_eq!(grid.get(IVec3::new(1, 0, 1)), stone());
+    }
+
+    #[test]
+    fn sponge_v3() {
+        let file = nbt::write(
+            "",
+            &compound([(
+                "Schematic",
+                compound([
+                    ("Version", Tag::Int(3)),
+                    ("Width", Tag::Short(1)),
+                    ("Height", Tag::Short(2)),
+                    ("Length", Tag::Short(1)),
+                    (
+                        "Blocks",
+                        compound([
+                            (
+                                "Palette",
+                                compound([
+                                    ("minecraft:air", Tag::Int(0)),
+                                    ("minecraft:stone", Tag::Int(1)),
+                                ]),
+                            ),
+                            ("Data", Tag::ByteArray(vec![1, 0])),
+                        ]),
+                    ),
+                ]),
+            )]),
+        );
+
+        let grid = parse(&file, &Palette::default()).expect("failed to parse");
+        assert_eq!(grid.get(IVec3::new(0, 0, 0)), stone());
+        assert_eq!(grid.get(IVec3::new(0, 1, 0)), None);
+    }
+
+    #[test]
+    fn mcedit() {
+        let file = nbt::write(
+            "Schematic",
+            &compound([
+                ("Width", Tag::Short(3)),
+                ("Height", Tag::Short(1)),
+                ("Length", Tag::Short(1)),
+                ("Materials", Tag::String("Alpha".into())),
+                ("Blocks", Tag::ByteArray(vec![1, 0, 35])),
+                ("Data", Tag::ByteArray(vec![0, 0, 14])),
+            ]),
+        );
+
+        let palette = Palette::default();
+        let grid = parse(&file, &palette).expect("failed to parse");
+        assert_eq!(grid.get(IVec3::new(0, 0, 0)), stone());
+        assert_eq!(grid.get(IVec3::new(1, 0, 0)), None);
+        assert_eq!(
+            grid.get(IVec3::new(2, 0, 0)).map(|v| v.color),
+            palette.color("minecraft:red_wool")
+        );
+    }
+
+    #[test]
+    fn missing_blocks() {
+        let file = nbt::write(
+            "Schematic",
+            &compound([
+                ("Width", Tag::Short(2)),
+                ("Height", Tag::Short(2)),
+                ("Length", Tag::Short(2)),
+                ("Blocks", Tag::ByteArray(vec![1, 1])),
+                ("Data", Tag::ByteArray(vec![0, 0])),
+            ]),
+        );
+        assert!(parse(&file, &Palette::default()).is_err());
+    }
+
+    #[test]
+    fn size_must_match_blocks() {
+        let mcedit = |size: i16, blocks| {
+            nbt::write(
+                "Schematic",
+                &compound([
+                    ("Width", Tag::Short(size)),
+                    ("Height", Tag::Short(size)),
+                    ("Length", Tag::Short(size)),
+                    ("Blocks", Tag::ByteArray(vec![1; blocks])),
+                    ("Data", Tag::ByteArray(vec![0; blocks])),
+                ]),
+            )
+        };
+        let palette = Palette::default();
+        assert!(parse(&mcedit(2, 8), &palette).is_ok());
+        assert!(parse(&mcedit(2, 9), &palette).is_err());
+        // 65535 wide on each side, too large to allocate
+        assert!(parse(&mcedit(-1, 8), &palette).is_err());
+
+        let sponge = |size: i16, data| {
+            nbt::write(
+                "Schematic",
+                &compound([
+                    ("Version", Tag::Int(2)),
+                    ("Width", Tag::Short(size)),
+                    ("Height", Tag::Short(size)),
+                    ("Length", Tag::Short(size)),
+                    ("Palette", compound([("minecraft:stone", Tag::Int(0))])),
+                    ("BlockData", Tag::ByteArray(vec![0; data])),
+                ]),
+            )
+        };
+        assert!(parse(&sponge(2, 8), &palette).is_ok());
+        assert!(parse(&sponge(2, 7), &palette).is_err());
+        assert!(parse(&sponge(-1, 8), &palette).is_err());
+    }
+}
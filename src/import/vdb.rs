@@ -0,0 +1,289 @@
+use std::{fs, path::Path};
+
+use glam::{IVec3, U8Vec3};
+
+use super::ImportError;
+use crate::voxel::{grid::VoxelGrid, Voxel};
+
+/// `NanoVDB0` as a little endian integer.
+const MAGIC: u64 = 0x3042_4456_6f6e_614e;
+
+/// Major version of the file layout this reader understands.
+const MAJOR_VERSION: u32 = 32;
+
+const FILE_HEADER_SIZE: usize = 16;
+const META_DATA_SIZE: usize = 176;
+const GRID_DATA_SIZE: usize = 672;
+
+/// Size of a leaf node of a float grid (8x8x8 values plus the header), padded to 32 bytes.
+const FLOAT_LEAF_SIZE: usize = 2144;
+
+const GRID_TYPE_FLOAT: u32 = 1;
+const GRID_CLASS_LEVEL_SET: u32 = 1;
+
+/// Color of the surface of level sets.
+const SURFACE_COLOR: U8Vec3 = U8Vec3::new(200, 200, 200);
+
+/// Loads the first grid of an uncompressed NanoVDB (`.nvdb`) file.
+///
+/// Float fog volumes become voxels shaded by their density, and level sets become the voxels inside the surface.
+/// Only leaf voxels are read, so large constant tiles are skipped.
+///
+/// See: https://www.openvdb.org/documentation/doxygen/NanoVDB_HowToBuild.html
+pub fn load(path: impl AsRef<Path>) -> Result<VoxelGrid, ImportError> {
+    parse(&fs::read(path)?)
+}
+
+/// Parses a NanoVDB file from its contents.
+pub fn parse(bytes: &[u8]) -> Result<VoxelGrid, ImportError> {
+    let mut reader = Reader { bytes, pos: 0 };
+
+    if reader.u64()? != MAGIC {
+        return Err(format_error(
+            "not a NanoVDB file (OpenVDB .vdb files can be converted with nanovdb_convert)",
+        ));
+    }
+    let version = reader.u32()?;
+    if version >> 21 != MAJOR_VERSION {
+        return Err(format_error(&format!(
+            "unsupported version {}",
+            version >> 21
+        )));
+    }
+    let grid_count = reader.u16()?;
+    if grid_count == 0 {
+        return Err(format_error("file has no grids"));
+    }
+
+    // metadata of the first grid
+    let meta = FILE_HEADER_SIZE;
+    let file_size = reader.at(meta + 8).u64()? as usize;
+    let grid_type = reader.at(meta + 32).u32()?;
+    let grid_class = reader.at(meta + 36).u32()?;
+    let name_size = reader.at(meta + 136).u32()? as usize;
+    let codec = reader.at(meta + 168).u16()?;
+
+    if codec != 0 {
+        return Err(format_error("compressed grids are not supported"));
+    }
+    if grid_type != GRID_TYPE_FLOAT {
+        return Err(format_error("only float grids are supported"));
+    }
+
+    let grid = meta + META_DATA_SIZE + name_size;
+    let grid = reader
+        .bytes
+        .get(grid..grid + file_size)
+        .ok_or_else(|| format_error("unexpected end of data"))?;
+    let mut reader = Reader {
+        bytes: grid,
+        pos: 0,
+    };
+
+    // tree header follows the grid header, with offsets relative to itself
+    let tree = GRID_DATA_SIZE;
+    let leaf_offset = reader.at(tree).u64()? as usize;
+    let leaf_count = reader.at(tree + 32).u32()? as usize;
+
+    let mut voxels = Vec::new();
+    let mut max_value = f32::MIN;
+    for leaf in 0..leaf_count {
+        let leaf = tree + leaf_offset + leaf * FLOAT_LEAF_SIZE;
+
+        let origin = IVec3::new(
+            reader.at(leaf).i32()?,
+            reader.at(leaf + 4).i32()?,
+            reader.at(leaf + 8).i32()?,
+        ) & !7;
+
+        for word in 0..8 {
+            let mask = reader.at(leaf + 16 + word * 8).u64()?;
+            for bit in (0..64).filter(|bit| mask & (1 << bit) != 0) {
+                let n = (word * 64 + bit) as i32;
+                let value = reader.at(leaf + 96 + n as usize * 4).f32()?;
+                let pos = origin + IVec3::new(n >> 6, (n >> 3) & 7, n & 7);
+
+                let solid = match grid_class {
+                    GRID_CLASS_LEVEL_SET => value <= 0.0,
+                    _ => value > 0.0,
+                };
+                if solid {
+                    max_value = max_value.max(value);
+                    voxels.push((pos, value));
+                }
+            }
+        }
+    }
+
+    if voxels.is_empty() {
+        return Err(format_error("grid has no solid voxels"));
+    }
+    let min = voxels
+        .iter()
+        .fold(IVec3::MAX, |min, &(pos, _)| min.min(pos));
+    let max = voxels
+        .iter()
+        .fold(IVec3::MIN, |max, &(pos, _)| max.max(pos));
+
+    let mut grid = VoxelGrid::new(max - min + 1).with_origin(min);
+    for (pos, value) in voxels {
+        let color = match grid_class {
+            GRID_CLASS_LEVEL_SET => SURFACE_COLOR,
+            // denser voxels are brighter
+            _ => U8Vec3::splat((60.0 + 195.0 * (value / max_value).clamp(0.0, 1.0)) as u8),
+        };
+        grid.set(pos - min, Some(Voxel::from(color)));
+    }
+    Ok(grid)
+}
+
+fn format_error(msg: &str) -> ImportError {
+    ImportError::Format(format!("nvdb: {msg}"))
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    /// Moves to an absolute position.
+    fn at(&mut self, pos: usize) -> &mut Self {
+        self.pos = pos;
+        self
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], ImportError> {
+        let bytes = self
+            .bytes
+            .get(self.pos..self.pos + N)
+            .ok_or_else(|| format_error("unexpected end of data"))?;
+        self.pos += N;
+        Ok(bytes.try_into().expect("took N bytes"))
+    }
+
+    fn u16(&mut self) -> Result<u16, ImportError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, ImportError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn i32(&mut self) -> Result<i32, ImportError> {
+        Ok(i32::from_le_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, ImportError> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    fn f32(&mut self) -> Result<f32, ImportError> {
+        Ok(f32::from_le_bytes(self.array()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a NanoVDB file with one float grid made of the given leaves (origin and active voxels).
+    fn nvdb(class: u32, leaves: &[(IVec3, &[(IVec3, f32)])]) -> Vec<u8> {
+        let put = |buf: &mut Vec<u8>, pos: usize, bytes: &[u8]| {
+            buf[pos..pos + bytes.len()].copy_from_slice(bytes);
+        };
+
+        // the tree header is followed directly by the leaves
+        let leaf_offset = 64;
+        let mut grid = vec![0u8; GRID_DATA_SIZE + leaf_offset + leaves.len() * FLOAT_LEAF_SIZE];
+        put(
+            &mut grid,
+            GRID_DATA_SIZE,
+            &(leaf_offset as u64).to_le_bytes(),
+        );
+        put(
+            &mut grid,
+            GRID_DATA_SIZE + 32,
+            &(leaves.len() as u32).to_le_bytes(),
+        );
+
+        for (i, (origin, voxels)) in leaves.iter().enumerate() {
+            let leaf = GRID_DATA_SIZE + leaf_offset + i * FLOAT_LEAF_SIZE;
+            for (axis, v) in origin.to_array().into_iter().enumerate() {
+                put(&mut grid, leaf + axis * 4, &v.to_le_bytes());
+            }
+            for &(pos, value) in voxels.iter() {
+                let n = (pos.x << 6 | pos.y << 3 | pos.z) as usize;
+                grid[leaf + 16 + n / 8] |= 1 << (n % 8);
+                put(&mut grid, leaf + 96 + n * 4, &value.to_le_bytes());
+            }
+        }
+
+        let mut file = vec![0u8; FILE_HEADER_SIZE + META_DATA_SIZE];
+        put(&mut file, 0, &MAGIC.to_le_bytes());
+        put(&mut file, 8, &(MAJOR_VERSION << 21).to_le_bytes());
+        put(&mut file, 12, &1u16.to_le_bytes());
+        let meta = FILE_HEADER_SIZE;
+        put(&mut file, meta, &(grid.len() as u64).to_le_bytes());
+        put(&mut file, meta + 8, &(grid.len() as u64).to_le_bytes());
+        put(&mut file, meta + 32, &GRID_TYPE_FLOAT.to_le_bytes());
+        put(&mut file, meta + 36, &class.to_le_bytes());
+        put(&mut file, meta + 136, &5u32.to_le_bytes());
+        file.extend(b"grid\0");
+        file.extend(grid);
+        file
+    }
+
+    #[test]
+    fn fog_volume() {
+        let file = nvdb(
+            2,
+            &[
+                (
+                    IVec3::new(8, 0, -8),
+                    &[(IVec3::new(1, 2, 3), 1.0), (IVec3::new(7, 7, 7), 0.5)],
+                ),
+                (IVec3::new(16, 0, -8), &[(IVec3::new(0, 0, 0), 0.0)]),
+            ],
+        );
+
+        let grid = parse(&file).expect("failed to parse");
+        assert_eq!(grid.count(), 2);
+        assert_eq!(grid.size(), IVec3::new(7, 6, 5));
+        assert_eq!(grid.get(IVec3::ZERO), Some(Voxel::from(U8Vec3::splat(255))));
+        assert!(grid.get(IVec3::new(6, 5, 4)).is_some());
+    }
+
+    #[test]
+    fn level_set() {
+        let file = nvdb(
+            GRID_CLASS_LEVEL_SET,
+            &[(
+                IVec3::ZERO,
+                &[(IVec3::new(0, 0, 0), -1.0), (IVec3::new(0, 0, 1), 1.0)],
+            )],
+        );
+
+        let grid = parse(&file).expect("failed to parse");
+        assert_eq!(grid.count(), 1);
+        assert_eq!(grid.get(IVec3::ZERO), Some(Voxel::from(SURFACE_COLOR)));
+    }
+
+    #[test]
+    fn empty() {
+        // fog with no density anywhere, and no leaves at all
+        let fog = nvdb(2, &[(IVec3::ZERO, &[(IVec3::ZERO, 0.0), (IVec3::X, -1.0)])]);
+        assert!(parse(&fog).is_err());
+        assert!(parse(&nvdb(2, &[])).is_err());
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(parse(b"#OpenVDB").is_err());
+
+        let file = nvdb(2, &[(IVec3::ZERO, &[(IVec3::ZERO, 1.0)])]);
+        for len in [20, FILE_HEADER_SIZE + META_DATA_SIZE + 10, file.len() - 1] {
+            assert!(parse(&file[..len]).is_err());
+        }
+    }
+}
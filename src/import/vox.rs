@@ -0,0 +1,225 @@
+use std::{fs, path::Path};
+
+use glam::{IVec3, U8Vec3};
+
+use super::ImportError;
+use crate::voxel::{grid::VoxelGrid, Voxel};
+
+/// Shades of the color ramps at the end of the default palette, from light to dark.
+const RAMP: [u8; 10] = [0xee, 0xdd, 0xbb, 0xaa, 0x88, 0x77, 0x55, 0x44, 0x22, 0x11];
+
+/// Loads the first model of a MagicaVoxel `.vox` file.
+pub fn load(path: impl AsRef<Path>) -> Result<VoxelGrid, ImportError> {
+    parse(&fs::read(path)?)
+}
+
+/// Parses the first model of a MagicaVoxel file.
+///
+/// MagicaVoxel's z-axis points up, so it is swapped with y.
+/// Voxels use the file's palette, or MagicaVoxel's default palette if it has none.
+pub fn parse(bytes: &[u8]) -> Result<VoxelGrid, ImportError> {
+    if bytes.len() < 8 || &bytes[..4] != b"VOX " {
+        return Err(format_error("not a MagicaVoxel file"));
+    }
+    let main = chunk(&bytes[8..])?;
+    if main.id != b"MAIN" {
+        return Err(format_error("missing MAIN chunk"));
+    }
+
+    let mut size = None;
+    let mut voxels = None;
+    let mut palette = None;
+    let mut children = main.children;
+    while !children.is_empty() {
+        let Chunk {
+            id, content, rest, ..
+        } = chunk(children)?;
+        match id {
+            // later models are skipped
+            b"SIZE" if size.is_none() => {
+                let axis = |i: usize| u32_at(content, 4 * i).map(|v| v as i32);
+                size = Some(IVec3::new(axis(0)?, axis(2)?, axis(1)?));
+            }
+            b"XYZI" if voxels.is_none() => {
+                let count = u32_at(content, 0)? as usize;
+                let list: Vec<[u8; 4]> = content[4..]
+                    .chunks_exact(4)
+                    .take(count)
+                    .map(|v| v.try_into().expect("took 4 bytes"))
+                    .collect();
+                voxels = Some(list);
+            }
+            b"RGBA" => {
+                palette = Some(
+                    content
+                        .chunks_exact(4)
+                        .map(|c| U8Vec3::new(c[0], c[1], c[2]))
+                        .collect::<Vec<_>>(),
+                );
+            }
+            _ => {}
+        }
+        children = rest;
+    }
+
+    let (Some(size), Some(voxels)) = (size, voxels) else {
+        return Err(format_error("file has no models"));
+    };
+    if size.cmplt(IVec3::ZERO).any() || size.max_element() > 256 {
+        return Err(format_error("invalid model size"));
+    }
+
+    let mut grid = VoxelGrid::new(size);
+    for [x, y, z, index] in voxels {
+        let color = match &palette {
+            // the file's palette starts at index 1
+            Some(palette) => index
+                .checked_sub(1)
+                .and_then(|i| palette.get(i as usize))
+                .copied()
+                .unwrap_or(U8Vec3::ZERO),
+            None => default_color(index),
+        };
+        grid.set(
+            IVec3::new(x as i32, z as i32, y as i32),
+            Some(Voxel::from(color)),
+        );
+    }
+    Ok(grid)
+}
+
+/// A chunk of a `.vox` file and the bytes following it.
+struct Chunk<'a> {
+    id: &'a [u8],
+    content: &'a [u8],
+    children: &'a [u8],
+    rest: &'a [u8],
+}
+
+/// Splits the chunk off the front of `bytes`.
+fn chunk(bytes: &[u8]) -> Result<Chunk<'_>, ImportError> {
+    let content = u32_at(bytes, 4)? as usize;
+    let children = u32_at(bytes, 8)? as usize;
+    let end = 12usize
+        .checked_add(content)
+        .and_then(|end| end.checked_add(children))
+        .filter(|&end| end <= bytes.len())
+        .ok_or_else(|| format_error("truncated chunk"))?;
+    Ok(Chunk {
+        id: &bytes[..4],
+        content: &bytes[12..12 + content],
+        children: &bytes[12 + content..end],
+        rest: &bytes[end..],
+    })
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> Result<u32, ImportError> {
+    bytes
+        .get(offset..offset + 4)
+        .map(|b| u32::from_le_bytes(b.try_into().expect("took 4 bytes")))
+        .ok_or_else(|| format_error("truncated chunk"))
+}
+
+/// Color of an index in MagicaVoxel's default palette.
+///
+/// The palette is a 6x6x6 color cube from white to (but not including) black,
+/// followed by ramps of red, green, blue and gray.
+fn default_color(index: u8) -> U8Vec3 {
+    let step = |i: u8| 255 - 51 * i;
+    match index {
+        0 => U8Vec3::ZERO,
+        1..=215 => {
+            let i = index - 1;
+            U8Vec3::new(step(i / 36), step(i / 6 % 6), step(i % 6))
+        }
+        _ => {
+            let i = (index - 216) as usize;
+            let shade = RAMP[i % 10];
+            match i / 10 {
+                0 => U8Vec3::new(shade, 0, 0),
+                1 => U8Vec3::new(0, shade, 0),
+                2 => U8Vec3::new(0, 0, shade),
+                _ => U8Vec3::splat(shade),
+            }
+        }
+    }
+}
+
+fn format_error(err: &str) -> ImportError {
+    ImportError::Format(format!("vox: {err}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(id: &[u8; 4], content: &[u8], children: &[u8]) -> Vec<u8> {
+        let mut out = id.to_vec();
+        out.extend((content.len() as u32).to_le_bytes());
+        out.extend((children.len() as u32).to_le_bytes());
+        out.extend(content);
+        out.extend(children);
+        out
+    }
+
+    /// Builds a file with a 2x3x4 model holding two voxels.
+    fn vox(palette: Option<&[[u8; 4]]>) -> Vec<u8> {
+        let size: Vec<u8> = [2u32, 3, 4].iter().flat_map(|v| v.to_le_bytes()).collect();
+        let mut voxels = 2u32.to_le_bytes().to_vec();
+        voxels.extend([0, 0, 0, 1, 1, 2, 3, 216]);
+
+        let mut children = chunk(b"SIZE", &size, &[]);
+        children.extend(chunk(b"XYZI", &voxels, &[]));
+        children.extend(chunk(b"nTRN", &[0; 8], &[]));
+        if let Some(palette) = palette {
+            children.extend(chunk(b"RGBA", palette.as_flattened(), &[]));
+        }
+
+        let mut file = b"VOX ".to_vec();
+        file.extend(150u32.to_le_bytes());
+        file.extend(chunk(b"MAIN", &[], &children));
+        file
+    }
+
+    #[test]
+    fn default_palette() {
+        let grid = parse(&vox(None)).expect("failed to parse");
+        // y and z are swapped
+        assert_eq!(grid.size(), IVec3::new(2, 4, 3));
+        assert_eq!(grid.count(), 2);
+        assert_eq!(grid.get(IVec3::ZERO).unwrap().color, U8Vec3::splat(255));
+        assert_eq!(
+            grid.get(IVec3::new(1, 3, 2)).unwrap().color,
+            U8Vec3::new(0xee, 0, 0)
+        );
+
+        assert_eq!(default_color(2), U8Vec3::new(255, 255, 204));
+        assert_eq!(default_color(215), U8Vec3::new(0, 0, 51));
+        assert_eq!(default_color(255), U8Vec3::splat(0x11));
+    }
+
+    #[test]
+    fn file_palette() {
+        let mut palette = [[0; 4]; 256];
+        palette[0] = [10, 20, 30, 255];
+        palette[215] = [40, 50, 60, 255];
+
+        let grid = parse(&vox(Some(&palette))).expect("failed to parse");
+        assert_eq!(
+            grid.get(IVec3::ZERO).unwrap().color,
+            U8Vec3::new(10, 20, 30)
+        );
+        assert_eq!(
+            grid.get(IVec3::new(1, 3, 2)).unwrap().color,
+            U8Vec3::new(40, 50, 60)
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(parse(b"VOX").is_err());
+        assert!(parse(b"PNG \x96\0\0\0").is_err());
+        let file = vox(None);
+        assert!(parse(&file[..file.len() - 3]).is_err());
+    }
+}
@@ -1,4 +1,12 @@
+pub mod archive;
+pub mod bench;
 pub mod camera;
+pub mod compose;
 pub mod export;
+pub mod import;
+pub mod post;
+#[cfg(feature = "trace")]
+pub mod profile;
 pub mod ray_tracer;
+pub mod scene_file;
 pub mod voxel;
@@ -1,140 +1,1700 @@
-use std::path::absolute;
+use std::{
+    collections::HashSet,
+    fs,
+    ops::RangeInclusive,
+    path::{absolute, Path, PathBuf},
+    thread,
+    time::Duration,
+};
 
-use clap::{Parser, ValueEnum};
-use glam::IVec3;
+use clap::{ArgAction, Args, Parser, Subcommand, ValueEnum};
+use glam::{IVec3, Vec3Swizzles};
+use rand::Rng;
 
 use voxel_ray_tracer::{
-    export::export_image,
-    ray_tracer::{dense::DenseStorage, octree::SparseStorage, Config, RayTracer},
+    archive::SceneArchive,
+    bench::{self, BenchCase, BenchResult},
+    compose::{self, LayerBlend, LayerFile, ObjectFile, Objects, PrefabFile},
+    export::{export_image, Framebuffer},
+    import::{self, anvil::Window, mesh::Fill, palette::Palette, ImportOptions},
+    post::Pipeline,
+    ray_tracer::{
+        dynamic::{Backend, DynScene},
+        emitter::{Emitters, Recorder},
+        infinite::InfiniteStorage,
+        light::Light,
+        sky::TimeOfDay,
+        streaming::StreamingStorage,
+        types::IAabb,
+        Config, RayTracer, Scene,
+    },
+    scene_file::{CloudSection, FogSection, PrefabSection, Preset, SceneFile, TerrainSection},
+    voxel::{
+        cellular::{CellularCaves, CellularSettings},
+        erosion::ErosionSettings,
+        islands::{IslandSettings, Islands},
+        ore::OreSettings,
+        planet::{Planet, PlanetSettings},
+        sdf::{self, SdfSource},
+        snow::{Snow, SnowSettings},
+        structure::{Prefab, StructureSettings, MAX_PREFAB_WIDTH},
+        vegetation::VegetationSettings,
+        water::WaterSettings,
+        wfc::{self, TileMap},
+        CaveSettings, FbmSettings, TerrainSettings, VoxelGenerator, VoxelSource, WarpSettings,
+    },
 };
 
+#[cfg(feature = "trace")]
+use std::sync::Arc;
 #[cfg(feature = "trace")]
 use tracing_subscriber::prelude::*;
+#[cfg(feature = "trace")]
+use voxel_ray_tracer::profile::Profile;
 
-/// Define possible storage modes
-#[derive(Debug, Clone, ValueEnum, Default)]
-enum StorageMode {
+/// Define possible voxel generators
+#[derive(Debug, Clone, Copy, ValueEnum, Default)]
+enum GeneratorKind {
+    /// Perlin noise terrain
     #[default]
-    Sparse,
-    Dense,
+    Terrain,
+    /// SDF primitives on a ground plane
+    SdfShapes,
+    /// SDF die with holes cut out using CSG
+    SdfCsg,
+    /// SDF blobs combined with smooth blends
+    SdfBlobs,
+    /// Caves grown with a 3D cellular automaton
+    Caves,
+    /// Town of road, house and tree tiles fitted together with wave function collapse
+    Wfc,
+    /// Round planet with terrain pushed out from a sphere
+    Planet,
+    /// Islands floating in the sky with rocky undersides hanging below them
+    Islands,
+}
+
+impl GeneratorKind {
+    /// Creates the voxel source for a scene.
+    fn source(
+        self,
+        settings: &Settings,
+    ) -> Result<Box<dyn VoxelSource>, Box<dyn std::error::Error>> {
+        let size = settings.config.size as f32;
+        Ok(match self {
+            GeneratorKind::Terrain => Box::new(terrain_generator(settings)?),
+            GeneratorKind::SdfShapes => Box::new(SdfSource::new(sdf::shapes(size))),
+            GeneratorKind::SdfCsg => Box::new(SdfSource::new(sdf::csg(size))),
+            GeneratorKind::SdfBlobs => Box::new(SdfSource::new(sdf::blobs(size))),
+            GeneratorKind::Caves => {
+                let bounds = settings.config.bounds();
+                let seed = settings.config.seed.unwrap_or_else(|| rand::rng().random());
+                Box::new(CellularCaves::new(
+                    seed,
+                    bounds.min(),
+                    bounds.max(),
+                    CellularSettings::default(),
+                ))
+            }
+            GeneratorKind::Wfc => {
+                let bounds = settings.config.bounds();
+                let seed = settings.config.seed.unwrap_or_else(|| rand::rng().random());
+                Box::new(TileMap::new(
+                    seed,
+                    wfc::town(),
+                    bounds.min().xz(),
+                    bounds.max().xz(),
+                )?)
+            }
+            GeneratorKind::Planet => {
+                let bounds = settings.config.bounds();
+                let seed = settings.config.seed.unwrap_or_else(|| rand::rng().random());
+                // in the middle of the scene, where the camera faces and orbits, with the tallest mountains just
+                // inside its bounds
+                let extent = bounds.extents.min_element() as f64;
+                let planet = PlanetSettings {
+                    sea: settings.water.is_some(),
+                    ..PlanetSettings::fitting(bounds.origin, extent)
+                };
+                Box::new(
+                    Planet::new(seed, planet)
+                        .with_terrain(settings.terrain)
+                        .with_fbm(settings.fbm),
+                )
+            }
+            GeneratorKind::Islands => {
+                let bounds = settings.config.bounds();
+                let seed = settings.config.seed.unwrap_or_else(|| rand::rng().random());
+                // centered in the height of the scene, where the camera faces and orbits
+                let islands = IslandSettings::around(bounds.origin.y, bounds.extents.y as f64);
+                Box::new(Islands::new(seed, islands))
+            }
+        })
+    }
+}
+
+/// Creates the terrain generator with the settings' features.
+fn terrain_generator(settings: &Settings) -> Result<VoxelGenerator, Box<dyn std::error::Error>> {
+    let mut generator = settings
+        .config
+        .seed
+        .map(VoxelGenerator::new_from_seed)
+        .unwrap_or_default();
+    if settings.caves {
+        generator = generator.with_caves(CaveSettings {
+            lava_level: settings.lava_level.unwrap_or_default(),
+            ..Default::default()
+        });
+    }
+    if settings.biomes {
+        generator = generator.with_biomes();
+    }
+    if settings.vegetation {
+        generator = generator.with_vegetation(VegetationSettings::default());
+    }
+    if settings.ores {
+        generator = generator.with_ores(OreSettings::default());
+    }
+    if let Some(water) = settings.water {
+        generator = generator.with_water(water);
+    }
+    if let Some(warp) = settings.warp {
+        generator = generator.with_warp(warp);
+    }
+    // snow piled on the ground replaces the band of snow colored by height
+    let terrain = match settings.snow {
+        Some(_) => TerrainSettings {
+            snow_level: f64::INFINITY,
+            ..settings.terrain
+        },
+        None => settings.terrain,
+    };
+    generator = generator.with_terrain(terrain).with_fbm(settings.fbm);
+    let bounds = settings.config.bounds();
+    if let Some(erosion) = settings.erosion {
+        // erode every column the scene can contain
+        generator = generator.with_erosion(erosion, bounds.min().xz(), bounds.max().xz());
+    }
+    if let Some(prefabs) = &settings.structures {
+        generator = generator.with_structures(StructureSettings::default(), load_prefabs(prefabs)?);
+    }
+    // vegetation and structures look at the surface of nearby columns too, so cache all of them
+    generator = generator.with_height_cache(bounds.min().xz(), bounds.max().xz());
+    Ok(generator)
+}
+
+/// Loads structure models, or the built-in hut and ruin if there are none.
+fn load_prefabs(files: &[PrefabFile]) -> Result<Vec<Prefab>, Box<dyn std::error::Error>> {
+    if files.is_empty() {
+        return Ok(vec![Prefab::hut(), Prefab::ruin()]);
+    }
+
+    let mut prefabs = Vec::new();
+    for file in files {
+        println!("Loading prefab {}...", file.path.display());
+        let grid = import::load(&file.path, &ImportOptions::default())?;
+        let prefab = Prefab::new(grid, file.ground);
+        let size = prefab.grid.size();
+        if size.x > MAX_PREFAB_WIDTH || size.z > MAX_PREFAB_WIDTH {
+            return Err(format!(
+                "Prefab {} is {}x{} voxels wide, structures can be at most {MAX_PREFAB_WIDTH}x{MAX_PREFAB_WIDTH}",
+                file.path.display(),
+                size.x,
+                size.z
+            )
+            .into());
+        }
+        prefabs.push(Prefab {
+            max_slope: file.max_slope.unwrap_or(prefab.max_slope),
+            depth: file.depth,
+            ..prefab
+        });
+    }
+    Ok(prefabs)
+}
+
+/// Resolved settings for a render.
+#[derive(Debug, Clone)]
+struct Settings {
+    backend: Backend,
+    generator: GeneratorKind,
+    /// Carve caves into the terrain.
+    caves: bool,
+    /// Height up to which caves are flooded with lava.
+    lava_level: Option<i32>,
+    /// Vary the terrain by biome.
+    biomes: bool,
+    /// Grow trees and bushes on the terrain.
+    vegetation: bool,
+    /// Scatter ore and crystals through the ground.
+    ores: bool,
+    /// Fill the terrain with seas, lakes, and rivers.
+    water: Option<WaterSettings>,
+    /// Warp the terrain into winding ridges and valleys.
+    warp: Option<WarpSettings>,
+    /// Height, roughness, and color bands of the terrain.
+    terrain: TerrainSettings,
+    /// Octaves of the terrain's height noise.
+    fbm: FbmSettings,
+    /// Wear the terrain down with simulated rain.
+    erosion: Option<ErosionSettings>,
+    /// Pile snow on open ground of whatever the scene is built from.
+    snow: Option<SnowSettings>,
+    /// Place structures on the terrain, built from these models or the built-in ones if empty.
+    structures: Option<Vec<PrefabFile>>,
+    config: Config,
+    output_path: PathBuf,
+    /// Effects applied to images before they are saved.
+    post: Pipeline,
+    /// Model file to render instead of the generator.
+    import: Option<PathBuf>,
+    /// Block color table for imported files.
+    palette: Option<PathBuf>,
+    /// World coordinates to import from Minecraft regions.
+    window: Option<Window>,
+    /// Voxels along the longest side of imported meshes.
+    resolution: u32,
+    /// Whether to fill the inside of imported meshes.
+    fill: Fill,
+    /// Script defining the voxels instead of the generator.
+    script: Option<PathBuf>,
+    /// WebAssembly module defining the voxels instead of the generator.
+    plugin: Option<PathBuf>,
+    /// Scene archive to render instead of generating voxels.
+    load_scene: Option<PathBuf>,
+    /// Where to save the generated voxels as a scene archive.
+    save_scene: Option<PathBuf>,
+    /// Distance rays reach in infinite terrain.
+    far: f32,
+    /// Bytes of chunks kept in memory by the streaming and infinite backends, or `None` to keep every chunk.
+    cache_budget: Option<usize>,
+    /// Models placed in the scene next to its voxels.
+    objects: Vec<ObjectFile>,
+    /// Sources stacked on top of the scene's voxels, in order.
+    layers: Vec<LayerFile>,
+    /// Lights the scene is lit by instead of the sun or moon, if any.
+    lights: Vec<Light>,
 }
 
 /// Command-line arguments structure
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
+#[command(disable_help_flag = true, args_conflicts_with_subcommands = true)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    render: RenderArgs,
+
+    /// Print help
+    #[arg(long, global = true, action = ArgAction::Help)]
+    help: Option<bool>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run a fixed matrix of sizes, resolutions and backends and report timings
+    Bench(BenchArgs),
+}
+
+/// Arguments for the benchmark subcommand
+#[derive(Args, Debug)]
+struct BenchArgs {
+    /// Untimed renders before sampling
+    #[arg(long, default_value_t = 1)]
+    warmup: usize,
+
+    /// Timed renders per case
+    #[arg(long, default_value_t = 5)]
+    samples: usize,
+
+    /// Terrain seed value
+    #[arg(short = 'r', long, default_value_t = 0)]
+    seed: u32,
+}
+
+/// Arguments for rendering a single image
+#[derive(Args, Debug)]
+struct RenderArgs {
+    /// Scene file (TOML) with render settings, overridden by any flags given
+    #[arg(long)]
+    scene: Option<PathBuf>,
+
+    /// Built-in scene to start from, overridden by the scene file and any flags given
+    #[arg(long, value_enum)]
+    preset: Option<Preset>,
+
+    /// Re-render at preview resolution whenever the scene file changes
+    #[arg(long, requires = "scene")]
+    watch: bool,
+
     /// Storage backend
     #[arg(short, long, value_enum)]
-    backend: Option<StorageMode>,
+    backend: Option<Backend>,
+
+    /// Voxel generator [default: terrain]
+    #[arg(short, long, value_enum)]
+    generator: Option<GeneratorKind>,
+
+    /// Model file to render instead of a generator (.schem, .schematic, .mca, .nvdb, .obj, .gltf, .glb, .vox, or a region folder)
+    #[arg(short, long)]
+    import: Option<PathBuf>,
+
+    /// Rhai script defining `lookup(x, y, z)` to render instead of a generator (needs the `scripting` feature)
+    #[arg(long, conflicts_with = "import")]
+    script: Option<PathBuf>,
+
+    /// WebAssembly module exporting `lookup(x, y, z)` to render instead of a generator (needs the `wasm` feature)
+    #[arg(long, conflicts_with_all = ["import", "script"])]
+    plugin: Option<PathBuf>,
+
+    /// Scene archive to render instead of a generator, saved before with --save-scene
+    #[arg(long, conflicts_with = "import")]
+    load_scene: Option<PathBuf>,
+
+    /// Save the scene's voxels to an archive so other views can be rendered without regenerating them
+    #[arg(long)]
+    save_scene: Option<PathBuf>,
+
+    /// Model, script (.rhai) or plugin (.wasm) whose solid voxels cover the scene's, can be repeated
+    #[arg(long)]
+    layer: Vec<PathBuf>,
+
+    /// Corners of the world to import from Minecraft regions (x1,y1,z1,x2,y2,z2)
+    #[arg(long, value_delimiter = ',', allow_hyphen_values = true)]
+    window: Option<Vec<i32>>,
+
+    /// Block color table (TOML) for imported Minecraft files
+    #[arg(long)]
+    palette: Option<PathBuf>,
+
+    /// Voxels along the longest side of imported meshes [default: 128]
+    #[arg(long)]
+    resolution: Option<u32>,
+
+    /// Fill the inside of imported meshes instead of only their surface
+    #[arg(long)]
+    solid: bool,
+
+    /// Carve caves and overhangs into the terrain
+    #[arg(long)]
+    caves: bool,
+
+    /// Flood caves with glowing lava up to this height, which lights them with --path
+    #[arg(long, value_name = "Y")]
+    lava_level: Option<i32>,
+
+    /// Blend desert, forest, tundra, and plains biomes into the terrain
+    #[arg(long)]
+    biomes: bool,
+
+    /// Grow trees and bushes on grassy terrain
+    #[arg(long)]
+    vegetation: bool,
+
+    /// Scatter iron, gold, and crystal clusters underground, best seen with --caves
+    #[arg(long)]
+    ores: bool,
+
+    /// Build huts on flat grass and ruins on mountains
+    #[arg(long)]
+    structures: bool,
+
+    /// Structure model (e.g. `.vox`) to place on flat grass instead of the built-in structures, can be repeated
+    #[arg(long)]
+    prefab: Vec<PathBuf>,
+
+    /// Flood the terrain below sea level and carve rivers
+    #[arg(long)]
+    water: bool,
+
+    /// Sea level for --water [default: 30]
+    #[arg(long)]
+    sea_level: Option<i32>,
+
+    /// Warp the terrain into winding ridges and valleys, pushing noise samples up to this many voxels
+    #[arg(long)]
+    warp: Option<f64>,
+
+    /// Max height of the terrain [default: 100]
+    #[arg(long)]
+    terrain_height: Option<i32>,
+
+    /// Roughness of the terrain, higher values make more hills [default: 1]
+    #[arg(long)]
+    roughness: Option<f64>,
+
+    /// Fraction of the terrain height below which the ground is water colored, and the default sea level [default: 0.3]
+    #[arg(long)]
+    water_level: Option<f64>,
+
+    /// Fraction of the terrain height where grass turns to mountain [default: 0.6]
+    #[arg(long)]
+    mountain_level: Option<f64>,
+
+    /// Fraction of the terrain height where mountain turns to snow [default: 0.8]
+    #[arg(long)]
+    snow_level: Option<f64>,
+
+    /// Pile snow on ground open to the sky above the snow level, instead of coloring the ground white by height
+    #[arg(long)]
+    snow: bool,
+
+    /// Height of the ground snow settles on [default: the snow level]
+    #[arg(long)]
+    snow_altitude: Option<i32>,
+
+    /// Voxels of snow piled on the ground [default: 1]
+    #[arg(long)]
+    snow_depth: Option<i32>,
+
+    /// Layers of height noise, each adding finer detail [default: 1]
+    #[arg(long)]
+    octaves: Option<u32>,
+
+    /// Frequency multiplier between noise octaves [default: 2]
+    #[arg(long)]
+    lacunarity: Option<f64>,
+
+    /// Amplitude multiplier between noise octaves [default: 0.5]
+    #[arg(long)]
+    persistence: Option<f64>,
 
-    /// Scene size
-    #[arg(short, long, default_value_t = 200)]
-    size: u32,
+    /// Erode the terrain with this many steps of simulated rain, carving gullies and filling valleys
+    #[arg(long)]
+    erosion: Option<u32>,
+
+    /// Rain falling on every column per erosion step, in voxels [default: 0.1]
+    #[arg(long)]
+    rain: Option<f64>,
+
+    /// Scene size [default: 200]
+    #[arg(short, long)]
+    size: Option<u32>,
+
+    /// Scene size along y, for flat scenes that don't need to be as tall as they are wide [default: the size]
+    #[arg(long)]
+    scene_height: Option<u32>,
+
+    /// Corners of the scene (x1,y1,z1,x2,y2,z2) instead of a box around the origin, keeping the coordinates of
+    /// imported models
+    #[arg(long, value_delimiter = ',', allow_hyphen_values = true)]
+    bounds: Option<Vec<i32>>,
 
     /// Scene position (x,y,z) e.g. 25,25,25
     #[arg(short, long, value_delimiter = ',')]
     position: Option<Vec<i32>>,
 
+    /// Place the camera this many degrees around the scene on an orbit that frames all of it
+    #[arg(long, conflicts_with = "position", allow_hyphen_values = true)]
+    orbit: Option<f64>,
+
     /// Terrain seed value
     #[arg(short = 'r', long)]
     seed: Option<u32>,
 
-    /// Image output path
-    #[arg(short, long, default_value = "render.png")]
-    out: String,
+    /// Render once per seed, e.g. 1..100, 1..=10 or 3,7,12..20
+    ///
+    /// `{seed}` in the output path is replaced by the seed, otherwise it is appended to the file name.
+    #[arg(long, value_parser = parse_seeds, conflicts_with_all = ["seed", "watch"])]
+    seeds: Option<SeedList>,
+
+    /// Image output path [default: render.png]
+    #[arg(short, long)]
+    out: Option<String>,
+
+    /// Color lookup table (.cube) grading the image before it is saved
+    #[arg(long)]
+    lut: Option<PathBuf>,
+
+    /// Add a glow this strong around the brightest parts of the image, for example 0.5
+    #[arg(long)]
+    bloom: Option<f32>,
+
+    /// Brightness from 0 to 1 that pixels have to pass to glow with --bloom [default: 0.8]
+    #[arg(long)]
+    bloom_threshold: Option<f32>,
+
+    /// Add lens flare this strong, ghosts and glare streaks from the brightest parts of the image, for example 0.5
+    #[arg(long)]
+    flare: Option<f32>,
 
-    /// Image resolution width
-    #[arg(short, long, default_value_t = 7680)]
-    width: usize,
+    /// Ghosts of each bright spot with --flare [default: 4]
+    #[arg(long)]
+    flare_ghosts: Option<usize>,
 
-    /// Image resolution height
-    #[arg(short, long, default_value_t = 4320)]
-    height: usize,
+    /// Stops to brighten (or darken, if negative) the image by before any other post-processing
+    #[arg(long, allow_hyphen_values = true)]
+    exposure: Option<f32>,
+
+    /// Color (r,g,b) that should come out white, to balance the image for it, e.g. 255,240,220
+    #[arg(long, value_delimiter = ',')]
+    white_balance: Option<Vec<u8>>,
+
+    /// Image resolution width [default: 7680]
+    #[arg(short, long)]
+    width: Option<usize>,
+
+    /// Image resolution height [default: 4320]
+    #[arg(short, long)]
+    height: Option<usize>,
 
     /// Enable octree debug mode
     #[arg(short, long)]
     debug: bool,
+
+    /// Stop tracing at octree nodes smaller than a pixel and use their average color
+    #[arg(long)]
+    lod: bool,
+
+    /// Trace 2x2 quads of pixels together as packets of rays, which the octree backends step through at once
+    #[arg(long)]
+    packets: bool,
+
+    /// Trace a beam around each 16x16 tile of pixels first, and start its rays where the beam reaches a voxel
+    #[arg(long)]
+    beams: bool,
+
+    /// Trace a cone as wide as each pixel, blending in the averages of octree nodes narrower than it, and shade it
+    /// with a soft shadow from a wider cone toward the sun
+    #[arg(long)]
+    cones: bool,
+
+    /// Distance past which rays hit nothing. Infinite terrain fades into fog over the second half of it [default: 4
+    /// times the size for infinite terrain, unlimited otherwise]
+    #[arg(long)]
+    far: Option<f32>,
+
+    /// Distance from the camera before which rays hit nothing [default: 0.01]
+    #[arg(long)]
+    near: Option<f32>,
+
+    /// Distance in voxels within which rays count as being on a boundary between voxels, widened for scenes far from
+    /// the origin [default: 0.0001]
+    #[arg(long)]
+    epsilon: Option<f32>,
+
+    /// Trace rays in double precision, for scenes or cameras so far from the origin that voxels go missing. Slower,
+    /// and ignores --packets and --beams
+    #[arg(long)]
+    double: bool,
+
+    /// Smooth the edges of voxels by tracing four more rays through each pixel colored differently from one next to
+    /// it and blending them
+    #[arg(long)]
+    antialias: bool,
+
+    /// Dither colors shaded by the ray tracer when rounding them to 8 bits, so smooth gradients don't band
+    #[arg(long)]
+    dither: bool,
+
+    /// Light voxels from the sun by the direction their surface faces, with normals smoothed across neighboring
+    /// voxels
+    #[arg(long)]
+    shade: bool,
+
+    /// Shade voxels by the faces of their cubes instead of smooth normals
+    #[arg(long)]
+    hard_normals: bool,
+
+    /// Draw a smooth surface through the voxels, where their occupancy interpolated between cell centers is a half,
+    /// instead of the faces of the cubes
+    #[arg(long)]
+    smooth_surface: bool,
+
+    /// Shade in a few flat bands of light and outline the edges of surfaces and the creases between them
+    #[arg(long)]
+    toon: bool,
+
+    /// Draw a layer of clouds covering this fraction (0 to 1) of the sky, which shadows the ground below it
+    #[arg(long, value_name = "COVERAGE")]
+    clouds: Option<f32>,
+
+    /// Height of the bottom of the clouds [default: top of the scene]
+    #[arg(long)]
+    cloud_altitude: Option<f32>,
+
+    /// Height of the layer of clouds [default: 24]
+    #[arg(long)]
+    cloud_thickness: Option<f32>,
+
+    /// Share (0 to 1) of the light a voxel of the thickest cloud stops [default: 0.15]
+    #[arg(long)]
+    cloud_density: Option<f32>,
+
+    /// Rough width of a cloud [default: 64]
+    #[arg(long)]
+    cloud_scale: Option<f32>,
+
+    /// Seconds into an animation, which moves the waves on water when shading; render frames at increasing times to
+    /// animate them [default: 0]
+    #[arg(long, value_name = "SECONDS")]
+    time: Option<f32>,
+
+    /// Light the scene by day, or dimly in blue by the moon under a sky of stars [default: day]
+    #[arg(long, value_enum)]
+    time_of_day: Option<TimeOfDay>,
+
+    /// Render by tracing this many paths through each pixel, bouncing around the scene and sampling its lights,
+    /// instead of shading, for example 16
+    #[arg(long, value_name = "SAMPLES")]
+    path: Option<u32>,
+
+    /// Surfaces a path can hit with --path before it is ended, though most end sooner at random once they carry
+    /// little light [default: 8]
+    #[arg(long, value_name = "BOUNCES")]
+    path_bounces: Option<u32>,
+
+    /// Draw the patterns of light the waves focus onto the beds of shallow water when shading, seeing through the
+    /// water to them, by shooting this many photons at each voxel of the scene's area, for example 64; path and cone
+    /// renders leave them out
+    #[arg(long, value_name = "PHOTONS")]
+    caustics: Option<u32>,
+
+    /// Distance in voxels within which --caustics gathers photons, larger for smoother but blurrier caustics
+    /// [default: 0.5]
+    #[arg(long)]
+    caustic_radius: Option<f32>,
+
+    /// Let light through thin layers of snow and water when shading, so their edges glow with the sun behind them
+    #[arg(long)]
+    subsurface: bool,
+
+    /// Fill the valleys with mist stopping this share (0 to 1) of the light in each voxel where it is thickest, and
+    /// the caves with thinner haze, lit by the light that reaches it, for example 0.04; path renders leave it out
+    #[arg(long, value_name = "DENSITY")]
+    fog: Option<f32>,
+
+    /// Height up to which mist fills the valleys, thinning out over the top half of it [default: 40]
+    #[arg(long)]
+    fog_altitude: Option<f32>,
+
+    /// Share (0 to 1) of the density of mist that haze in caves and under overhangs has [default: 0.5]
+    #[arg(long)]
+    fog_haze: Option<f32>,
+
+    /// Rough width of a bank of fog [default: 32]
+    #[arg(long)]
+    fog_scale: Option<f32>,
+
+    /// See through water, leaves and glass by how much of the light each voxel of them stops, and let light through
+    /// them tinted by their color into path-traced shadows and fog; path and cone renders don't see through them
+    #[arg(long)]
+    transparency: bool,
+
+    /// MiB of chunks the streaming and infinite backends keep in memory, dropping the least recently used ones past it
+    /// [default: unlimited]
+    #[arg(long)]
+    cache_budget: Option<usize>,
+
+    /// Render progressively and stop after this long, e.g. 30s, 500ms, 2m
+    #[arg(long, value_parser = parse_duration)]
+    time_budget: Option<Duration>,
 }
 
+/// Resolution is divided by this in watch mode.
+const PREVIEW_DIVISOR: usize = 4;
+
+/// How often the scene file is checked for changes in watch mode.
+const WATCH_INTERVAL: Duration = Duration::from_millis(250);
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Setup tracing scaffold.
     #[cfg(feature = "trace")]
+    let profile = Arc::new(Profile::default()); // sums up time per thread for the end of the run
+    #[cfg(feature = "trace")]
     {
         let fmt_layer = tracing_subscriber::fmt::layer(); // writes to stdout
         let tracy_layer = tracing_tracy::TracyLayer::default(); // writes to tracy port
-        let registry = tracing_subscriber::registry()
+        tracing_subscriber::registry()
             .with(fmt_layer)
             .with(tracy_layer)
+            .with(profile.layer())
             .init();
     };
 
-    let Cli {
-        backend,
-        size,
-        position,
-        seed,
-        out,
-        width,
-        height,
-        debug,
-    } = Cli::parse(); // Parses command-line arguments
+    let cli = Cli::parse(); // Parses command-line arguments
 
-    // Print parsed arguments
+    let result = match cli.command {
+        Some(Command::Bench(args)) => run_bench(args),
+        None if cli.render.watch => run_watch(cli.render),
+        None => run_render(&cli.render),
+    };
+
+    #[cfg(feature = "trace")]
+    print!("Profile:\n{profile}");
+
+    result
+}
+
+fn run_render(args: &RenderArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let scene_file = match &args.scene {
+        Some(path) => SceneFile::load(path)?,
+        None => SceneFile::default(),
+    };
 
-    let backend = backend.unwrap_or_default();
+    let settings = resolve_settings(args, &scene_file)?;
 
-    println!("Storage Backend: {backend:?}");
-    println!("Scene Size: {size}");
+    if let Some(seeds) = &args.seeds {
+        return run_batch(&settings, seeds, args.time_budget);
+    }
 
-    let position = match &position {
-        Some(pos) if pos.len() == 3 => {
-            println!("Scene Position: {:?}", pos);
-            IVec3::from_slice(pos)
+    let fb = render(&settings, args.time_budget)?;
+
+    // Export image.
+    post_process(&fb, &settings);
+    println!("Saving image...");
+    export_image(fb, settings.output_path).expect("failed to export image");
+
+    Ok(())
+}
+
+/// Renders one image per seed.
+///
+/// Each image is saved on a separate thread while the next seed renders.
+fn run_batch(
+    settings: &Settings,
+    seeds: &SeedList,
+    time_budget: Option<Duration>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut export: Option<thread::JoinHandle<image::ImageResult<()>>> = None;
+
+    for (i, seed) in seeds.iter().enumerate() {
+        println!("[{}/{}] Seed: {seed}", i + 1, seeds.len());
+
+        let mut settings = settings.clone();
+        settings.config.seed = Some(seed);
+        let fb = render(&settings, time_budget)?;
+
+        // Wait for the previous image before starting the next export.
+        if let Some(handle) = export.take() {
+            handle.join().expect("export thread panicked")?;
         }
-        Some(_) => return Err("Invalid position format! Use -p x,y,z".into()),
-        None => size as i32 * IVec3::ONE,
+
+        post_process(&fb, &settings);
+        let path = seed_output_path(&settings.output_path, seed);
+        println!("Saving {}...", path.display());
+        export = Some(thread::spawn(move || export_image(fb, path)));
+    }
+
+    if let Some(handle) = export {
+        handle.join().expect("export thread panicked")?;
+    }
+
+    Ok(())
+}
+
+/// Applies the post-processing effects of the settings, if any, to a rendered image.
+fn post_process(fb: &Framebuffer, settings: &Settings) {
+    if !settings.post.is_empty() {
+        println!("Post-processing...");
+        settings.post.apply(fb);
+    }
+}
+
+/// Substitutes `{seed}` in the output path, or appends the seed to the file name if missing.
+fn seed_output_path(path: &Path, seed: u32) -> PathBuf {
+    let templated = path.to_string_lossy();
+    if templated.contains("{seed}") {
+        return PathBuf::from(templated.replace("{seed}", &seed.to_string()));
+    }
+
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(ext) => format!("{stem}_{seed}.{}", ext.to_string_lossy()),
+        None => format!("{stem}_{seed}"),
     };
+    path.with_file_name(name)
+}
 
-    println!("Position: {position}");
+/// Renders a preview every time the scene file is modified.
+fn run_watch(args: RenderArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let path = args.scene.clone().expect("watch requires a scene file");
+    let modified = || fs::metadata(&path).and_then(|m| m.modified()).ok();
 
-    println!("Seed: {seed:?}");
+    let mut last_modified = None;
+    loop {
+        let current = modified();
+        if current.is_none() || current == last_modified {
+            thread::sleep(WATCH_INTERVAL);
+            continue;
+        }
+        last_modified = current;
+
+        println!("Rendering preview of {}...", path.display());
+        if let Err(err) = render_preview(&args) {
+            eprintln!("{err}");
+        }
+        println!("Watching {} for changes...", path.display());
+    }
+}
 
-    let output_path = absolute(out)?;
+fn render_preview(args: &RenderArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let scene_file = SceneFile::load(args.scene.as_ref().expect("watch requires a scene file"))?;
+    let mut settings = resolve_settings(args, &scene_file)?;
+
+    let config = &mut settings.config;
+    config.res_width = (config.res_width / PREVIEW_DIVISOR).max(1);
+    config.res_height = (config.res_height / PREVIEW_DIVISOR).max(1);
+    println!(
+        "Preview Resolution: {}x{}",
+        config.res_width, config.res_height
+    );
+
+    let fb = render(&settings, args.time_budget)?;
+
+    post_process(&fb, &settings);
+    println!("Saving image...");
+    export_image(fb, settings.output_path)?;
+
+    Ok(())
+}
 
-    println!("Output File: {}", output_path.display());
-    println!("Resolution: {width}x{height}");
+/// Merges command-line arguments with the scene file and prints the result.
+fn resolve_settings(
+    args: &RenderArgs,
+    scene_file: &SceneFile,
+) -> Result<Settings, Box<dyn std::error::Error>> {
+    let file = flag_scene_file(args)?.or(scene_file.clone());
+    let preset = file.preset()?;
+    if let Some(preset) = preset {
+        println!("Preset: {preset:?}");
+    }
+    let mut file = match preset {
+        Some(preset) => file.or(preset.scene_file()),
+        None => file,
+    };
+    // prefabs and layers from flags are added to the file's instead of replacing them
+    file.terrain.prefabs.splice(
+        0..0,
+        args.prefab.iter().map(|path| PrefabSection {
+            path: path.clone(),
+            ..Default::default()
+        }),
+    );
+    let mut layers = file.layers()?;
+    layers.extend(args.layer.iter().map(|path| LayerFile {
+        path: path.clone(),
+        position: IVec3::ZERO,
+        blend: LayerBlend::Cover,
+    }));
 
+    let generator = match (args.generator, &file.generator) {
+        (Some(generator), _) => generator,
+        (None, Some(name)) => GeneratorKind::from_str(name, true)
+            .map_err(|_| format!("Invalid generator `{name}` in scene file"))?,
+        (None, None) => GeneratorKind::default(),
+    };
+    let terrain = file.terrain.shape()?;
+    let post = file.post()?;
     let config = Config {
-        seed,
-        res_width: width,
-        res_height: height,
-        camera_pos: position.as_vec3a(),
-        size,
-        debug,
+        // post-processing such as bloom needs to know how far past white the brightest pixels are
+        hdr: !post.is_empty(),
+        ..file.config()?
+    };
+    let out = file.out.clone().unwrap_or_else(|| "render.png".into());
+
+    let settings = Settings {
+        backend: file.backend()?,
+        generator,
+        caves: file.terrain.caves.unwrap_or(false),
+        lava_level: file.terrain.lava_level()?,
+        biomes: file.terrain.biomes.unwrap_or(false),
+        vegetation: file.terrain.vegetation.unwrap_or(false),
+        ores: file.terrain.ores.unwrap_or(false),
+        water: file.terrain.water(&terrain),
+        warp: file.terrain.warp(),
+        terrain,
+        fbm: file.terrain.fbm()?,
+        erosion: file.terrain.erosion()?,
+        snow: file.terrain.snow(&terrain)?,
+        structures: file.terrain.structures()?,
+        output_path: absolute(out)?,
+        post,
+        import: file.import.clone(),
+        palette: file.palette.clone(),
+        window: file.window(),
+        resolution: file
+            .resolution
+            .unwrap_or(ImportOptions::default().resolution),
+        fill: if file.solid.unwrap_or(false) {
+            Fill::Solid
+        } else {
+            Fill::Surface
+        },
+        script: file.script.clone(),
+        plugin: file.plugin.clone(),
+        load_scene: file.load_scene.clone(),
+        save_scene: file.save_scene.clone(),
+        far: file.far.unwrap_or(4.0 * config.size as f32),
+        cache_budget: file.cache_budget.map(|mib| mib << 20),
+        objects: file.objects(),
+        layers,
+        lights: file.lights()?,
+        config,
+    };
+    print_settings(&settings, &file, args);
+    Ok(settings)
+}
+
+/// Settings given by flags, as a scene file to lay over the one loaded with [`SceneFile::or`].
+///
+/// Switches that aren't given are left to the file, so flags can only turn them on.
+fn flag_scene_file(args: &RenderArgs) -> Result<SceneFile, String> {
+    let switch = |on: bool| on.then_some(true);
+    let corners = |corners: &Option<Vec<i32>>, flag: &str| {
+        corners
+            .as_deref()
+            .map(|corners| {
+                corners
+                    .try_into()
+                    .map_err(|_| format!("Invalid {flag} format! Use --{flag} x1,y1,z1,x2,y2,z2"))
+            })
+            .transpose()
     };
 
-    let fb = match backend {
-        StorageMode::Sparse => {
-            // Create ray tracer.
-            println!("Constructing scene...");
-            let ray_tracer = RayTracer::<SparseStorage>::new(config);
-            // Run ray tracer.
-            println!("Running ray tracer...");
-            ray_tracer.render()
-        }
-        StorageMode::Dense => {
-            // Create ray tracer.
-            println!("Constructing scene...");
-            let ray_tracer = RayTracer::<DenseStorage>::new(config);
-            // Run ray tracer.
-            println!("Running ray tracer...");
-            ray_tracer.render()
+    Ok(SceneFile {
+        preset: args.preset.map(|preset| preset.name().into()),
+        backend: args.backend.map(|backend| backend.name().into()),
+        // resolved from the merged file in `resolve_settings`, since the names are only known to the command line
+        generator: None,
+        import: args.import.clone(),
+        palette: args.palette.clone(),
+        window: corners(&args.window, "window")?,
+        resolution: args.resolution,
+        solid: switch(args.solid),
+        script: args.script.clone(),
+        plugin: args.plugin.clone(),
+        load_scene: args.load_scene.clone(),
+        save_scene: args.save_scene.clone(),
+        size: args.size,
+        scene_height: args.scene_height,
+        bounds: corners(&args.bounds, "bounds")?,
+        position: args
+            .position
+            .as_deref()
+            .map(|pos| {
+                pos.try_into()
+                    .map_err(|_| "Invalid position format! Use -p x,y,z")
+            })
+            .transpose()?,
+        orbit: args.orbit,
+        seed: args.seed,
+        out: args.out.clone(),
+        lut: args.lut.clone(),
+        exposure: args.exposure,
+        white_balance: args
+            .white_balance
+            .as_deref()
+            .map(|white| {
+                white
+                    .try_into()
+                    .map_err(|_| "Invalid white balance format! Use --white-balance r,g,b")
+            })
+            .transpose()?,
+        bloom: args.bloom,
+        bloom_threshold: args.bloom_threshold,
+        flare: args.flare,
+        flare_ghosts: args.flare_ghosts,
+        width: args.width,
+        height: args.height,
+        debug: switch(args.debug),
+        lod: switch(args.lod),
+        packets: switch(args.packets),
+        beams: switch(args.beams),
+        cones: switch(args.cones),
+        far: args.far,
+        near: args.near,
+        epsilon: args.epsilon,
+        double: switch(args.double),
+        antialias: switch(args.antialias),
+        dither: switch(args.dither),
+        shade: switch(args.shade),
+        hard_normals: switch(args.hard_normals),
+        smooth_surface: switch(args.smooth_surface),
+        toon: switch(args.toon),
+        time: args.time,
+        time_of_day: args.time_of_day.map(|time| time.name().into()),
+        path: args.path,
+        path_bounces: args.path_bounces,
+        caustics: args.caustics,
+        caustic_radius: args.caustic_radius,
+        subsurface: switch(args.subsurface),
+        transparency: switch(args.transparency),
+        cache_budget: args.cache_budget,
+        terrain: TerrainSection {
+            caves: switch(args.caves),
+            lava_level: args.lava_level,
+            biomes: switch(args.biomes),
+            vegetation: switch(args.vegetation),
+            ores: switch(args.ores),
+            water: switch(args.water),
+            sea_level: args.sea_level,
+            warp: args.warp,
+            height: args.terrain_height,
+            roughness: args.roughness,
+            water_level: args.water_level,
+            mountain_level: args.mountain_level,
+            snow_level: args.snow_level,
+            snow: switch(args.snow),
+            snow_altitude: args.snow_altitude,
+            snow_depth: args.snow_depth,
+            octaves: args.octaves,
+            lacunarity: args.lacunarity,
+            persistence: args.persistence,
+            erosion: args.erosion,
+            rain: args.rain,
+            structures: switch(args.structures),
+            // added to the file's in `resolve_settings`
+            prefabs: Vec::new(),
+        },
+        clouds: CloudSection {
+            coverage: args.clouds,
+            altitude: args.cloud_altitude,
+            thickness: args.cloud_thickness,
+            density: args.cloud_density,
+            scale: args.cloud_scale,
+        },
+        fog: FogSection {
+            density: args.fog,
+            altitude: args.fog_altitude,
+            haze: args.fog_haze,
+            scale: args.fog_scale,
+        },
+        objects: Vec::new(),
+        layers: Vec::new(),
+        post: Vec::new(),
+        lights: Vec::new(),
+    })
+}
+
+/// Prints the settings a render was resolved to.
+fn print_settings(settings: &Settings, file: &SceneFile, args: &RenderArgs) {
+    let Settings { config, .. } = settings;
+
+    println!("Storage Backend: {:?}", settings.backend);
+    match (
+        &settings.load_scene,
+        &settings.import,
+        &settings.script,
+        &settings.plugin,
+    ) {
+        (Some(path), _, _, _) => println!("Scene Archive: {}", path.display()),
+        (None, Some(path), _, _) => println!("Import: {}", path.display()),
+        (None, None, Some(path), _) => println!("Script: {}", path.display()),
+        (None, None, None, Some(path)) => println!("Plugin: {}", path.display()),
+        (None, None, None, None) => println!("Generator: {:?}", settings.generator),
+    }
+    if settings.caves {
+        println!("Caves: enabled");
+    }
+    if let Some(level) = settings.lava_level {
+        println!("Lava Level: {level}");
+    }
+    if settings.biomes {
+        println!("Biomes: enabled");
+    }
+    if settings.vegetation {
+        println!("Vegetation: enabled");
+    }
+    if settings.ores {
+        println!("Ores: enabled");
+    }
+    match &settings.structures {
+        Some(prefabs) if prefabs.is_empty() => println!("Structures: built-in"),
+        Some(prefabs) => println!("Structures: {} prefabs", prefabs.len()),
+        None => {}
+    }
+    if !settings.layers.is_empty() {
+        println!("Layers: {}", settings.layers.len());
+    }
+    if !file.lights.is_empty() {
+        let names: Vec<&str> = file
+            .lights
+            .iter()
+            .map(|light| light.name.as_str())
+            .collect();
+        println!("Lights: {}", names.join(", "));
+    }
+    let terrain = settings.terrain;
+    if terrain != TerrainSettings::default() {
+        println!(
+            "Terrain: height {}, roughness {}, levels {}/{}/{}",
+            terrain.height,
+            terrain.roughness,
+            terrain.water_level,
+            terrain.mountain_level,
+            terrain.snow_level
+        );
+    }
+    if let Some(water) = settings.water {
+        println!("Sea Level: {}", water.sea_level);
+    }
+    if let Some(snow) = settings.snow {
+        println!("Snow Altitude: {}", snow.altitude);
+    }
+    if let Some(warp) = settings.warp {
+        println!("Warp Strength: {}", warp.strength);
+    }
+    let fbm = settings.fbm;
+    if fbm != FbmSettings::default() {
+        println!(
+            "Noise Octaves: {} (lacunarity {}, persistence {})",
+            fbm.octaves, fbm.lacunarity, fbm.persistence
+        );
+    }
+    if let Some(erosion) = settings.erosion {
+        println!(
+            "Erosion: {} iterations (rain {})",
+            erosion.iterations, erosion.rain
+        );
+    }
+    println!("Scene Size: {}", config.size);
+    if let Some(height) = config.height {
+        println!("Scene Height: {height}");
+    }
+    if let Some(world) = config.world {
+        println!("Scene Bounds: {} to {}", world.min(), world.max() - 1);
+    }
+    match (&args.position, file.position, file.orbit) {
+        (Some(pos), _, _) => println!("Scene Position: {pos:?}"),
+        (None, None, Some(degrees)) => println!("Orbit: {degrees} degrees"),
+        _ => {}
+    }
+    println!("Position: {}", config.camera_pos.as_ivec3());
+
+    match &args.seeds {
+        Some(seeds) => println!("Seeds: {} renders", seeds.len()),
+        None => println!("Seed: {:?}", config.seed),
+    }
+
+    println!("Output File: {}", settings.output_path.display());
+
+    if !settings.post.is_empty() {
+        println!("Post-processing: {}", settings.post.names().join(", "));
+    }
+    println!("Resolution: {}x{}", config.res_width, config.res_height);
+
+    if let Some(budget) = args.time_budget {
+        println!("Time Budget: {budget:?}");
+    }
+    if let Some(path) = config.path {
+        println!("Path Samples: {} ({} bounces)", path.samples, path.bounces);
+    }
+    if let Some(caustics) = config.caustics {
+        println!(
+            "Caustics: {} photons per voxel (radius {})",
+            caustics.photons, caustics.radius
+        );
+    }
+}
+
+fn render(
+    settings: &Settings,
+    time_budget: Option<Duration>,
+) -> Result<Framebuffer, Box<dyn std::error::Error>> {
+    let Settings {
+        backend,
+        generator,
+        config,
+        ..
+    } = *settings;
+
+    let mut loaded = HashSet::new();
+    for object in &settings.objects {
+        if loaded.insert(&object.path) {
+            println!("Loading object {}...", object.path.display());
+        }
+    }
+    let objects = Objects::load(&settings.objects, &import_options(settings)?)?;
+
+    // terrain goes on forever, so it is generated straight from the generator instead of a source cut to the size
+    let plain_terrain = settings.load_scene.is_none()
+        && settings.import.is_none()
+        && settings.script.is_none()
+        && settings.plugin.is_none()
+        && settings.save_scene.is_none()
+        && settings.layers.is_empty();
+    if let (Backend::Infinite, GeneratorKind::Terrain, true) = (backend, generator, plain_terrain) {
+        println!("Constructing scene...");
+        let height = config.bounds().extents.y;
+        let generator = terrain_generator(settings)?;
+        let scene = match settings.snow {
+            Some(snow) => {
+                InfiniteStorage::new(Snow::new(generator, snow), -height..height, settings.far)
+            }
+            None => InfiniteStorage::new(generator, -height..height, settings.far),
+        }
+        .with_cache_budget(settings.cache_budget);
+        return Ok(render_with_objects(
+            settings,
+            Emitters::default(),
+            DynScene::new(scene),
+            None,
+            &objects,
+            time_budget,
+        ));
+    }
+
+    let mut source = match (
+        &settings.load_scene,
+        &settings.import,
+        &settings.script,
+        &settings.plugin,
+    ) {
+        (Some(path), _, _, _) => {
+            println!("Loading scene archive {}...", path.display());
+            Box::new(SceneArchive::load(path)?)
+        }
+        (None, Some(path), _, _) => import_model(path, settings)?,
+        (None, None, Some(path), _) => {
+            println!("Loading script {}...", path.display());
+            compose::load_script(path, &config)?
         }
+        (None, None, None, Some(path)) => {
+            println!("Loading plugin {}...", path.display());
+            compose::load_plugin(path, &config)?
+        }
+        (None, None, None, None) => generator.source(settings)?,
     };
+    for layer in &settings.layers {
+        println!("Loading layer {}...", layer.path.display());
+    }
+    source = compose::stack_layers(
+        source,
+        &settings.layers,
+        &import_options(settings)?,
+        &config,
+    )?;
+    if let Some(snow) = settings.snow {
+        source = Box::new(Snow::new(source, snow));
+    }
 
-    // Export image.
-    println!("Saving image...");
-    export_image(fb, output_path).expect("failed to export image");
+    if let Some(path) = &settings.save_scene {
+        println!("Saving scene archive {}...", path.display());
+        let archive = SceneArchive::from_source(&*source, config.bounds());
+        archive.save(path)?;
+        // the archive holds the same voxels and is faster to read than most generators
+        source = Box::new(archive);
+    }
+
+    println!("Constructing scene...");
+    let bounds = config.bounds();
+    let recorder = Recorder::new(&*source);
+    let scene = match backend {
+        Backend::Streaming => DynScene::new(
+            StreamingStorage::from_voxels(&recorder, bounds)
+                .with_cache_budget(settings.cache_budget),
+        ),
+        Backend::Infinite => DynScene::new(
+            InfiniteStorage::from_voxels(&recorder, bounds)
+                .with_cache_budget(settings.cache_budget),
+        ),
+        backend => DynScene::build(backend, &recorder, bounds),
+    };
+    let emitters = recorder.into_emitters(&scene);
+    if !emitters.is_empty() {
+        println!("Emitters: {} glowing voxels", emitters.len());
+    }
+    Ok(render_with_objects(
+        settings,
+        emitters,
+        scene,
+        Some(bounds),
+        &objects,
+        time_budget,
+    ))
+}
+
+/// Settings for importing model files.
+fn import_options(settings: &Settings) -> Result<ImportOptions, Box<dyn std::error::Error>> {
+    Ok(ImportOptions {
+        palette: match &settings.palette {
+            Some(path) => Palette::load(path)?,
+            None => Palette::default(),
+        },
+        window: settings.window,
+        resolution: settings.resolution,
+        fill: settings.fill,
+    })
+}
+
+/// Loads a model file, centered on the ground of the scene.
+fn import_model(
+    path: &Path,
+    settings: &Settings,
+) -> Result<Box<dyn VoxelSource>, Box<dyn std::error::Error>> {
+    println!("Importing {}...", path.display());
+    let grid = import::load(path, &import_options(settings)?)?;
+    let grid = compose::place_model(grid, &settings.config);
+
+    let dims = grid.size();
+    println!(
+        "Imported {}x{}x{} model with {} voxels",
+        dims.x,
+        dims.y,
+        dims.z,
+        grid.count()
+    );
+    if !compose::fits(&grid, &settings.config) {
+        match settings.config.world {
+            Some(_) => {
+                println!("Warning: model reaches past the scene bounds, widen --bounds to fit")
+            }
+            None => {
+                println!("Warning: model is larger than the scene size, increase it with -s to fit")
+            }
+        }
+    }
+
+    Ok(Box::new(grid))
+}
+
+/// Renders a scene that was already built, in a scene graph with the placed models if there are any.
+///
+/// `bounds` is the box around the scene's voxels, or `None` if it has no end. The models are built with the same
+/// backend as the scene, and everything is lit by the settings' lights, or the sun or moon if there are none, and by
+/// the glowing voxels of the scene when path tracing.
+fn render_with_objects(
+    settings: &Settings,
+    emitters: Emitters,
+    scene: DynScene,
+    bounds: Option<IAabb>,
+    objects: &Objects,
+    time_budget: Option<Duration>,
+) -> Framebuffer {
+    let Settings {
+        config, backend, ..
+    } = *settings;
+    if objects.is_empty() {
+        let mut ray_tracer = RayTracer::from_scene(config, scene);
+        ray_tracer.set_lights(settings.lights.clone());
+        ray_tracer.set_emitters(emitters);
+        return run_ray_tracer(ray_tracer, time_budget);
+    }
+
+    let graph = objects.graph(scene, bounds, backend);
+    println!(
+        "Objects: {} instances of {} models",
+        graph.len() - 1,
+        graph.model_count() - 1
+    );
+    let mut ray_tracer = RayTracer::from_scene(config, graph);
+    ray_tracer.set_lights(settings.lights.clone());
+    ray_tracer.set_emitters(emitters);
+    run_ray_tracer(ray_tracer, time_budget)
+}
+
+/// Renders a scene that was already built.
+fn run_ray_tracer<T: Scene + Sync>(
+    ray_tracer: RayTracer<T>,
+    time_budget: Option<Duration>,
+) -> Framebuffer {
+    if let Some(voxel) = ray_tracer.camera_voxel() {
+        eprintln!(
+            "Warning: the camera is inside a {} voxel, so the whole image is its color; move it out with \
+             --position or --orbit",
+            voxel.kind
+        );
+    }
+
+    if let Some(photons) = ray_tracer.photons() {
+        println!("Photons: {} reached the beds of water", photons.len());
+    }
+
+    // Run ray tracer.
+    println!("Running ray tracer...");
+    let fb = match time_budget {
+        Some(budget) => {
+            let (fb, block) = ray_tracer.render_progressive(budget);
+            println!("Finest pass: {block}x{block} pixel blocks");
+            fb
+        }
+        None => ray_tracer.render(),
+    };
+
+    // after rendering, since some scenes only load the parts that rays reached
+    println!("Scene memory: {}", ray_tracer.scene().memory_usage());
+    if let Some(stats) = ray_tracer.scene().cache_stats() {
+        println!("Chunk cache: {stats}");
+    }
+    #[cfg(feature = "stats")]
+    println!("Traversal: {}", ray_tracer.traversal_stats());
+    fb
+}
+
+/// Parses a duration such as `30s`, `500ms`, `2m` or `1h` (plain numbers are seconds).
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (value, unit) = s.split_at(split);
+
+    let value: f64 = value
+        .parse()
+        .map_err(|_| format!("invalid duration `{s}`"))?;
+    let secs = match unit.trim() {
+        "ms" => value / 1000.0,
+        "" | "s" => value,
+        "m" => value * 60.0,
+        "h" => value * 3600.0,
+        unit => {
+            return Err(format!(
+                "unknown duration unit `{unit}` (use ms, s, m or h)"
+            ))
+        }
+    };
+
+    Duration::try_from_secs_f64(secs).map_err(|_| format!("duration `{s}` is too long"))
+}
+
+/// Seeds to render in batch mode, kept as ranges so long ones aren't collected up front.
+#[derive(Debug, Clone, PartialEq)]
+struct SeedList(Vec<RangeInclusive<u32>>);
+
+impl SeedList {
+    fn iter(&self) -> impl Iterator<Item = u32> + '_ {
+        self.0.iter().flat_map(|range| range.clone())
+    }
+
+    /// Number of seeds, which can be more than a `u32` holds.
+    fn len(&self) -> u64 {
+        self.0
+            .iter()
+            .map(|range| (range.end() - range.start()) as u64 + 1)
+            .sum()
+    }
+}
+
+/// Parses a comma separated list of seeds and ranges (`a..b` or `a..=b`).
+fn parse_seeds(s: &str) -> Result<SeedList, String> {
+    let parse = |v: &str| {
+        v.trim()
+            .parse::<u32>()
+            .map_err(|_| format!("invalid seed `{}`", v.trim()))
+    };
+
+    let mut seeds = Vec::new();
+    for item in s.split(',') {
+        let range = if let Some((start, end)) = item.split_once("..=") {
+            parse(start)?..=parse(end)?
+        } else if let Some((start, end)) = item.split_once("..") {
+            let end = parse(end)?
+                .checked_sub(1)
+                .ok_or_else(|| format!("seed range `{}` is empty", item.trim()))?;
+            parse(start)?..=end
+        } else {
+            let seed = parse(item)?;
+            seed..=seed
+        };
+        if range.is_empty() {
+            return Err(format!("seed range `{}` is empty", item.trim()));
+        }
+        seeds.push(range);
+    }
+
+    Ok(SeedList(seeds))
+}
+
+fn run_bench(args: BenchArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let BenchArgs {
+        warmup,
+        samples,
+        seed,
+    } = args;
+
+    println!("Seed: {seed}");
+    println!("Warmup: {warmup}, Samples: {samples}");
+    println!(
+        "{:<8} {:<6} {:>6} {:>10} {:>10} {:>10} {:>10} {:>10} {:>10}",
+        "backend", "res", "size", "build", "min", "median", "mean", "std dev", "memory"
+    );
+
+    for case in bench::MATRIX {
+        for backend in [
+            Backend::Dense,
+            Backend::Morton,
+            Backend::Chunked,
+            Backend::Hash,
+            Backend::Rle,
+            Backend::Brickmap,
+            Backend::Streaming,
+            Backend::Infinite,
+            Backend::Sparse,
+            Backend::Dag,
+        ] {
+            let result = bench::run_case(backend, &case, seed, warmup, samples);
+
+            print_bench_row(backend, &case, &result);
+        }
+    }
 
     Ok(())
 }
+
+fn print_bench_row(backend: Backend, case: &BenchCase, result: &BenchResult) {
+    let ms = |d: std::time::Duration| format!("{:.2}ms", d.as_secs_f64() * 1000.0);
+
+    let mib = |bytes: usize| format!("{:.1}MiB", bytes as f64 / (1024.0 * 1024.0));
+
+    println!(
+        "{:<8} {:<6} {:>6} {:>10} {:>10} {:>10} {:>10} {:>10} {:>10}",
+        backend.name(),
+        case.resolution,
+        case.size,
+        ms(result.build),
+        ms(result.render.min()),
+        ms(result.render.median()),
+        ms(result.render.mean()),
+        ms(result.render.std_dev()),
+        mib(result.memory.total_bytes()),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn value_names() {
+        // the names clap derives for the flags are the ones scene files use
+        for backend in Backend::ALL {
+            assert_eq!(Backend::from_str(backend.name(), false), Ok(backend));
+        }
+        for time in TimeOfDay::ALL {
+            assert_eq!(TimeOfDay::from_str(time.name(), false), Ok(time));
+        }
+        for preset in Preset::ALL {
+            assert_eq!(Preset::from_str(preset.name(), false), Ok(preset));
+        }
+    }
+
+    fn render_args(flags: &[&str]) -> RenderArgs {
+        let args = ["voxel_ray_tracer"].iter().chain(flags);
+        Cli::try_parse_from(args)
+            .expect("flags should parse")
+            .render
+    }
+
+    #[test]
+    fn flags_override_scene_file() {
+        let file = SceneFile::parse(
+            r#"
+            size = 100
+            seed = 3
+            shade = true
+            position = [1, 2, 3]
+            [terrain]
+            caves = true
+            [[terrain.prefabs]]
+            path = "hut.vox"
+            "#,
+        )
+        .unwrap();
+        let args = render_args(&[
+            "-s",
+            "50",
+            "--orbit",
+            "30",
+            "--backend",
+            "dag",
+            "--prefab",
+            "dock.vox",
+        ]);
+        let merged = flag_scene_file(&args).unwrap().or(file.clone());
+        assert_eq!(merged.size, Some(50));
+        assert_eq!(merged.seed, Some(3));
+        assert_eq!(merged.backend().unwrap(), Backend::Dag);
+        // an orbit on the command line replaces the file's position
+        assert_eq!((merged.position, merged.orbit), (None, Some(30.0)));
+        // switches left off don't turn off the file's
+        assert_eq!(merged.shade, Some(true));
+        assert_eq!(merged.terrain.caves, Some(true));
+
+        let settings = resolve_settings(&args, &file).unwrap();
+        assert_eq!(settings.config.size, 50);
+        assert!(settings.caves && settings.config.shade);
+        // prefabs on the command line are added to the file's
+        let prefabs = settings.structures.unwrap();
+        let paths: Vec<_> = prefabs.iter().map(|prefab| prefab.path.as_path()).collect();
+        assert_eq!(paths, [Path::new("dock.vox"), Path::new("hut.vox")]);
+    }
+
+    #[test]
+    fn invalid_flags() {
+        for (flags, message) in [
+            (
+                ["--window", "0,0,0,1,1"],
+                "Invalid window format! Use --window x1,y1,z1,x2,y2,z2",
+            ),
+            (
+                ["--bounds", "0,0,0,1,1,1,1"],
+                "Invalid bounds format! Use --bounds x1,y1,z1,x2,y2,z2",
+            ),
+            (["-p", "1,2"], "Invalid position format! Use -p x,y,z"),
+            (
+                ["--white-balance", "255,255"],
+                "Invalid white balance format! Use --white-balance r,g,b",
+            ),
+        ] {
+            assert_eq!(flag_scene_file(&render_args(&flags)), Err(message.into()));
+        }
+    }
+
+    #[test]
+    fn seeds() {
+        let seeds = |s| parse_seeds(s).map(|seeds| seeds.iter().collect::<Vec<_>>());
+        assert_eq!(seeds("7"), Ok(vec![7]));
+        assert_eq!(seeds("1..4"), Ok(vec![1, 2, 3]));
+        assert_eq!(seeds("1..=3, 9"), Ok(vec![1, 2, 3, 9]));
+        assert_eq!(seeds("3,7,12..14"), Ok(vec![3, 7, 12, 13]));
+
+        // long ranges are counted without being collected
+        let all = parse_seeds("0..4294967295").unwrap();
+        assert_eq!(all.len(), u32::MAX as u64);
+        assert_eq!(all.iter().nth(5), Some(5));
+        assert_eq!(parse_seeds("0..=4294967295").unwrap().len(), 1 << 32);
+    }
+
+    #[test]
+    fn invalid_seeds() {
+        for s in [
+            "", "a", "1,", "-1", "1..", "..3", "1..=x", "1...3", "5..5", "5..3", "5..=3", "0..0",
+        ] {
+            assert!(parse_seeds(s).is_err(), "{s}");
+        }
+    }
+
+    #[test]
+    fn seed_output_paths() {
+        let path = |path, seed| seed_output_path(Path::new(path), seed);
+        assert_eq!(
+            path("renders/{seed}/frame_{seed}.png", 42),
+            PathBuf::from("renders/42/frame_42.png")
+        );
+        // without `{seed}`, it goes after the file name
+        assert_eq!(path("out/render.png", 7), PathBuf::from("out/render_7.png"));
+        assert_eq!(path("render", 7), PathBuf::from("render_7"));
+        assert_eq!(path("a.b.jpg", 0), PathBuf::from("a.b_0.jpg"));
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("30s"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration(" 1.5 "), Ok(Duration::from_millis(1500)));
+        assert_eq!(parse_duration("2m"), Ok(Duration::from_secs(120)));
+        assert_eq!(parse_duration("1h"), Ok(Duration::from_secs(3600)));
+    }
+
+    #[test]
+    fn invalid_durations() {
+        for s in ["", "s", "-5s", "1.2.3s", "10d", "5 sec"] {
+            assert!(parse_duration(s).is_err(), "{s}");
+        }
+        // past what a duration can hold
+        assert!(parse_duration("99999999999999999999h").is_err());
+    }
+}
@@ -0,0 +1,180 @@
+use glam::Vec3A;
+use rayon::iter::{
+    IndexedParallelIterator, IntoParallelIterator, IntoParallelRefIterator,
+    IntoParallelRefMutIterator, ParallelIterator,
+};
+
+use super::{Image, PostProcess, LUMA};
+
+/// Glow spreading out from the brightest parts of an image.
+///
+/// Pixels brighter than the threshold are kept by how far they are past it, blurred with a Gaussian as wide as
+/// [`BLOOM_RADIUS`] of the image's height, once across and once down, and added back on top of the image. Glow
+/// spreading over pixels that hit nothing makes them partly opaque.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bloom {
+    /// Brightness, from 0 to 1, that pixels have to pass to glow.
+    pub threshold: f32,
+    /// How much of the blurred glow is added to the image.
+    pub intensity: f32,
+}
+
+impl Default for Bloom {
+    fn default() -> Self {
+        Self {
+            threshold: 0.8,
+            intensity: 0.5,
+        }
+    }
+}
+
+impl PostProcess for Bloom {
+    fn name(&self) -> &'static str {
+        "bloom"
+    }
+
+    fn apply(&self, image: &mut Image) {
+        let (width, height) = (image.width, image.height);
+        let radius = ((height as f32 * BLOOM_RADIUS).round() as usize).max(1);
+        let kernel = gaussian(radius);
+
+        let bright = bright(image, self.threshold);
+        let across = blur(&bright, &kernel, width, height, 1, width);
+        let glow = blur(&across, &kernel, width, height, width, height);
+        add(image, &glow, self.intensity);
+    }
+}
+
+/// Part of each pixel brighter than a threshold, kept by how far past it the pixel is and premultiplied by alpha,
+/// so pixels that hit nothing don't glow.
+pub(super) fn bright(image: &Image, threshold: f32) -> Vec<Vec3A> {
+    image
+        .pixels
+        .par_iter()
+        .map(|pixel| {
+            let rgb = Vec3A::from_vec4(*pixel);
+            let luma = rgb.dot(LUMA);
+            rgb * pixel.w * (luma - threshold).max(0.0) / luma.max(f32::MIN_POSITIVE)
+        })
+        .collect()
+}
+
+/// Adds light to an image at some strength, making pixels that hit nothing as opaque as the light over them.
+pub(super) fn add(image: &mut Image, glow: &[Vec3A], intensity: f32) {
+    image
+        .pixels
+        .par_iter_mut()
+        .zip(glow)
+        .for_each(|(pixel, &glow)| {
+            if glow == Vec3A::ZERO {
+                return;
+            }
+            let sum = Vec3A::from_vec4(*pixel) * pixel.w + intensity * glow;
+            let alpha = pixel.w.max(sum.max_element()).min(1.0);
+            *pixel = (sum / alpha).extend(alpha);
+        });
+}
+
+/// Weights of a Gaussian from `-radius` to `radius` pixels, with the radius at two standard deviations, adding up to
+/// one.
+pub(super) fn gaussian(radius: usize) -> Vec<f32> {
+    let sigma = radius as f32 / 2.0;
+    let weights: Vec<f32> = (0..=2 * radius)
+        .map(|i| {
+            let x = i as f32 - radius as f32;
+            (-x * x / (2.0 * sigma * sigma)).exp()
+        })
+        .collect();
+    let total: f32 = weights.iter().sum();
+    weights.into_iter().map(|weight| weight / total).collect()
+}
+
+/// Blurs an image along one axis with the weights of a kernel centered on each pixel, where neighbors along it are
+/// `stride` apart and there are `len` of them in a line. Pixels past the edge repeat the one at the edge.
+pub(super) fn blur(
+    image: &[Vec3A],
+    kernel: &[f32],
+    width: usize,
+    height: usize,
+    stride: usize,
+    len: usize,
+) -> Vec<Vec3A> {
+    let radius = kernel.len() / 2;
+    (0..width * height)
+        .into_par_iter()
+        .map(|i| {
+            let pos = i / stride % len;
+            let start = i - pos * stride;
+            kernel
+                .iter()
+                .enumerate()
+                .map(|(k, weight)| {
+                    let along = (pos + k).saturating_sub(radius).min(len - 1);
+                    *weight * image[start + along * stride]
+                })
+                .sum()
+        })
+        .collect()
+}
+
+/// Radius of the blur of [`Bloom`], as a fraction of the image's height.
+pub const BLOOM_RADIUS: f32 = 0.02;
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec4;
+
+    use super::*;
+
+    #[test]
+    fn bloom_spreads_from_bright_pixels() {
+        // a white pixel in the middle of a gray image, with nothing hit along the top row
+        let mut image = Image::new(9, 9);
+        for (i, pixel) in image.pixels.iter_mut().enumerate() {
+            *pixel = match (i % 9, i / 9) {
+                (_, 0) => Vec4::ZERO,
+                (4, 4) => Vec4::ONE,
+                _ => Vec4::new(0.25, 0.25, 0.25, 1.0),
+            };
+        }
+        let before = image.pixels.clone();
+
+        Bloom {
+            threshold: 0.5,
+            intensity: 1.0,
+        }
+        .apply(&mut image);
+        let pixel = |x: usize, y: usize| image.pixels[y * 9 + x];
+
+        // the radius is one pixel for images this small
+        assert!(pixel(3, 4).x > 0.25);
+        assert_eq!(pixel(3, 4), pixel(5, 4));
+        assert_eq!(pixel(3, 4), pixel(4, 5));
+        assert!(pixel(3, 3).x > 0.25 && pixel(3, 3).x < pixel(3, 4).x);
+        // glow isn't clamped, so later effects can still tell how bright it is
+        assert!(pixel(4, 4).x > 1.0);
+        for (i, &color) in before.iter().enumerate() {
+            let (x, y) = (i % 9, i / 9);
+            if x.abs_diff(4) > 1 || y.abs_diff(4) > 1 {
+                assert_eq!(pixel(x, y), color, "{x} {y}");
+            }
+        }
+
+        // glow over pixels that hit nothing makes them partly opaque
+        let mut image = Image::new(3, 2);
+        image.pixels[4] = Vec4::ONE;
+        Bloom::default().apply(&mut image);
+        let above = image.pixels[1];
+        assert!(above.w > 0.0 && above.w < 1.0);
+        assert!(Vec3A::from_vec4(above).abs_diff_eq(Vec3A::ONE, 1e-6));
+    }
+
+    #[test]
+    fn gaussian_adds_up_to_one() {
+        let kernel = gaussian(3);
+        assert_eq!(kernel.len(), 7);
+        assert!((kernel.iter().sum::<f32>() - 1.0).abs() < 1e-6);
+        assert_eq!(kernel[0], kernel[6]);
+        assert!(kernel[3] > kernel[2] && kernel[2] > kernel[1]);
+    }
+}
@@ -0,0 +1,187 @@
+use glam::{Vec2, Vec3A};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+use super::{
+    bloom::{add, blur, bright, gaussian, BLOOM_RADIUS},
+    Image, PostProcess,
+};
+
+/// Light scattered inside a camera's lens by the brightest parts of an image, such as the moon or snow in the sun.
+///
+/// Two kinds of light are added on top of the image:
+/// - ghosts, reflections between the lens's elements, which show as softened copies of bright spots along the line
+///   from them through the middle of the frame, fading toward its edges and tinted by the lens's coatings.
+/// - glare, streaks [`GLARE_LENGTH`] of the image's height long spreading across and down from each bright pixel,
+///   like those an aperture's blades leave.
+///
+/// Only the part of each pixel past the threshold makes light, as with [`Bloom`](super::bloom::Bloom).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Flare {
+    /// Brightness, from 0 to 1, that pixels have to pass to flare.
+    pub threshold: f32,
+    /// How much of the ghosts and glare is added to the image.
+    pub intensity: f32,
+    /// Ghosts of each bright spot.
+    pub ghosts: usize,
+}
+
+impl Default for Flare {
+    fn default() -> Self {
+        Self {
+            threshold: 0.8,
+            intensity: 0.5,
+            ghosts: 4,
+        }
+    }
+}
+
+impl PostProcess for Flare {
+    fn name(&self) -> &'static str {
+        "flare"
+    }
+
+    fn apply(&self, image: &mut Image) {
+        let (width, height) = (image.width, image.height);
+        let bright = bright(image, self.threshold);
+        if !bright.iter().any(|&light| light != Vec3A::ZERO) {
+            return;
+        }
+
+        let ghosts = self.ghosts(&bright, width, height);
+        let radius = ((height as f32 * BLOOM_RADIUS).round() as usize).max(1);
+        let soft = gaussian(radius);
+        let ghosts = blur(&ghosts, &soft, width, height, 1, width);
+        let ghosts = blur(&ghosts, &soft, width, height, width, height);
+
+        let length = ((height as f32 * GLARE_LENGTH).round() as usize).max(1);
+        let streak = streak(length);
+        let across = blur(&bright, &streak, width, height, 1, width);
+        let down = blur(&bright, &streak, width, height, width, height);
+
+        let light: Vec<Vec3A> = (0..width * height)
+            .into_par_iter()
+            .map(|i| ghosts[i] + GLARE * (across[i] + down[i]))
+            .collect();
+        add(image, &light, self.intensity);
+    }
+}
+
+impl Flare {
+    /// Ghosts of the bright parts of an image, not yet softened.
+    ///
+    /// Each pixel gathers light from points along the line through the middle of the frame from its mirror image
+    /// there, so a bright spot lights a row of ghosts from its mirror image toward the middle.
+    fn ghosts(&self, bright: &[Vec3A], width: usize, height: usize) -> Vec<Vec3A> {
+        let size = Vec2::new(width as f32, height as f32);
+        (0..width * height)
+            .into_par_iter()
+            .map(|i| {
+                let uv = (Vec2::new((i % width) as f32, (i / width) as f32) + 0.5) / size;
+                let mirror = 1.0 - uv;
+                let step = (0.5 - mirror) * GHOST_SPACING;
+                (0..self.ghosts)
+                    .map(|ghost| {
+                        let sample = mirror + ghost as f32 * step;
+                        if !(0.0..1.0).contains(&sample.x) || !(0.0..1.0).contains(&sample.y) {
+                            return Vec3A::ZERO;
+                        }
+                        // dimmer the further the spot is from the middle, where less of its light reaches the lens
+                        let falloff = (1.0 - (sample - 0.5).length() / 0.5f32.sqrt()).powi(2);
+                        let (x, y) = (sample * size).as_uvec2().into();
+                        let tint = GHOST_TINTS[ghost % GHOST_TINTS.len()];
+                        falloff * tint * bright[y as usize * width + x as usize]
+                    })
+                    .sum()
+            })
+            .collect()
+    }
+}
+
+/// Weights of a streak from `-length` to `length` pixels, fading exponentially away from the middle and adding up
+/// to one.
+fn streak(length: usize) -> Vec<f32> {
+    let weights: Vec<f32> = (0..=2 * length)
+        .map(|i| (-3.0 * (i as f32 - length as f32).abs() / length as f32).exp())
+        .collect();
+    let total: f32 = weights.iter().sum();
+    weights.into_iter().map(|weight| weight / total).collect()
+}
+
+/// Length of the streaks of [`Flare`]'s glare on each side of a bright pixel, as a fraction of the image's height.
+pub const GLARE_LENGTH: f32 = 0.1;
+
+/// Strength of the glare next to the ghosts.
+const GLARE: f32 = 2.0;
+
+/// Distance between ghosts along their line, as a fraction of the distance to the middle of the frame.
+const GHOST_SPACING: f32 = 0.4;
+
+/// Colors that the coatings of a lens tint its ghosts, in turn.
+const GHOST_TINTS: [Vec3A; 4] = [
+    Vec3A::new(1.0, 0.8, 0.5),
+    Vec3A::new(0.5, 0.9, 1.0),
+    Vec3A::new(0.8, 1.0, 0.6),
+    Vec3A::new(1.0, 0.6, 0.9),
+];
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec4;
+
+    use super::*;
+
+    #[test]
+    fn flare_streaks_and_ghosts_from_bright_pixels() {
+        // a white pixel toward the top left of a gray image
+        let gray = Vec4::new(0.25, 0.25, 0.25, 1.0);
+        let mut image = Image::new(41, 41);
+        for (i, pixel) in image.pixels.iter_mut().enumerate() {
+            *pixel = match (i % 41, i / 41) {
+                (8, 8) => Vec4::ONE,
+                _ => gray,
+            };
+        }
+        let before = image.clone();
+
+        let flare = Flare {
+            threshold: 0.5,
+            intensity: 1.0,
+            ghosts: 3,
+        };
+        flare.apply(&mut image);
+        let pixel = |image: &Image, x: usize, y: usize| image.pixels[y * 41 + x];
+
+        // glare streaks across and down from it, evenly
+        assert!(pixel(&image, 11, 8).x > gray.x);
+        assert!(pixel(&image, 11, 8).abs_diff_eq(pixel(&image, 8, 11), 1e-6));
+        assert!(pixel(&image, 11, 8).x > pixel(&image, 12, 8).x);
+        // a ghost lies across the middle, where its mirror image is
+        assert!(pixel(&image, 32, 32).x > gray.x);
+        // and it is tinted
+        assert!(pixel(&image, 32, 32).x > pixel(&image, 32, 32).z);
+        // away from them the image is left alone
+        assert_eq!(pixel(&image, 30, 5), gray);
+
+        // without ghosts, only the glare is left
+        let mut glare = before.clone();
+        Flare { ghosts: 0, ..flare }.apply(&mut glare);
+        assert!(pixel(&glare, 11, 8).x > gray.x);
+        assert_eq!(pixel(&glare, 32, 32), gray);
+
+        // and an image without bright pixels doesn't flare
+        let mut dim = Image::new(5, 5);
+        dim.pixels.fill(gray);
+        let unchanged = dim.clone();
+        flare.apply(&mut dim);
+        assert_eq!(dim, unchanged);
+    }
+
+    #[test]
+    fn streak_adds_up_to_one() {
+        let kernel = streak(4);
+        assert_eq!(kernel.len(), 9);
+        assert!((kernel.iter().sum::<f32>() - 1.0).abs() < 1e-6);
+        assert_eq!(kernel[0], kernel[8]);
+        assert!(kernel[4] > kernel[3] && kernel[3] > kernel[2]);
+    }
+}
@@ -0,0 +1,268 @@
+use std::{error::Error, fmt, fs, io, path::Path};
+
+use glam::Vec3A;
+use rayon::iter::{IntoParallelRefMutIterator, ParallelIterator};
+
+use super::{Image, PostProcess};
+
+/// 3D lookup table mapping colors to graded colors, loaded from an Adobe/Resolve `.cube` file.
+///
+/// The file lists the output color at every point of an evenly spaced grid over the input colors, with red changing
+/// fastest, and colors between the points are interpolated trilinearly:
+///
+/// ```text
+/// TITLE "warm"
+/// LUT_3D_SIZE 2
+/// 0.0 0.0 0.0
+/// 1.0 0.1 0.0
+/// ...
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lut {
+    /// Points along each side of the grid.
+    size: usize,
+    /// Input colors at the first and last points of the grid, from 0 to 1.
+    domain: [Vec3A; 2],
+    /// Output colors from 0 to 1, indexed by red, then green, then blue.
+    table: Box<[Vec3A]>,
+}
+
+impl Lut {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, LutError> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, LutError> {
+        let format = |message: String| LutError::Format(message);
+        let mut size = None;
+        let mut domain = [Vec3A::ZERO, Vec3A::ONE];
+        let mut table = Vec::new();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            let keyword = words.next().unwrap_or_default();
+            let floats = |words: std::str::SplitWhitespace| {
+                let values = words
+                    .map(str::parse)
+                    .collect::<Result<Vec<f32>, _>>()
+                    .map_err(|err| format!("line {}: {err}", number + 1))?;
+                match values[..] {
+                    [r, g, b] => Ok(Vec3A::new(r, g, b)),
+                    _ => Err(format!("line {}: expected 3 values", number + 1)),
+                }
+            };
+            match keyword {
+                "TITLE" => {}
+                "LUT_3D_SIZE" => {
+                    let value = words
+                        .next()
+                        .and_then(|word| word.parse().ok())
+                        .filter(|&value| value >= 2)
+                        .ok_or_else(|| format(format!("line {}: invalid size", number + 1)))?;
+                    size = Some(value);
+                }
+                "LUT_1D_SIZE" => return Err(format("1D lookup tables are not supported".into())),
+                "DOMAIN_MIN" => domain[0] = floats(words).map_err(format)?,
+                "DOMAIN_MAX" => domain[1] = floats(words).map_err(format)?,
+                "LUT_3D_INPUT_RANGE" => {
+                    let range = (words.next(), words.next());
+                    let (Some(Ok(min)), Some(Ok(max))) =
+                        (range.0.map(str::parse), range.1.map(str::parse))
+                    else {
+                        return Err(format(format!("line {}: invalid input range", number + 1)));
+                    };
+                    domain = [Vec3A::splat(min), Vec3A::splat(max)];
+                }
+                _ if keyword.starts_with(|c: char| c.is_ascii_alphabetic()) => {
+                    return Err(format(format!(
+                        "line {}: unknown keyword {keyword}",
+                        number + 1
+                    )));
+                }
+                _ => table.push(floats(line.split_whitespace()).map_err(format)?),
+            }
+        }
+
+        let size = size.ok_or_else(|| format("missing LUT_3D_SIZE".into()))?;
+        if table.len() != size * size * size {
+            return Err(format(format!(
+                "expected {} colors for size {size}, found {}",
+                size * size * size,
+                table.len()
+            )));
+        }
+        if domain[0].cmpge(domain[1]).any() {
+            return Err(format("DOMAIN_MIN must be below DOMAIN_MAX".into()));
+        }
+
+        Ok(Self {
+            size,
+            domain,
+            table: table.into_boxed_slice(),
+        })
+    }
+
+    /// Graded color of a color from 0 to 1, interpolated between the eight points of the grid around it. Colors
+    /// outside of the domain are clamped to its edges.
+    pub fn sample(&self, color: Vec3A) -> Vec3A {
+        let [min, max] = self.domain;
+        let last = (self.size - 1) as f32;
+        let pos = ((color - min) / (max - min)).clamp(Vec3A::ZERO, Vec3A::ONE) * last;
+        // the last cell is used for colors at the very top, with a fraction of one
+        let cell = pos.floor().min(Vec3A::splat(last - 1.0));
+        let t = pos - cell;
+        let [r, g, b] = cell.as_uvec3().to_array().map(|i| i as usize);
+
+        let at =
+            |i, j, k| self.table[(r + i) + (g + j) * self.size + (b + k) * self.size * self.size];
+        let lerp = |a: Vec3A, b: Vec3A, t: f32| a.lerp(b, t);
+        let front = lerp(
+            lerp(at(0, 0, 0), at(1, 0, 0), t.x),
+            lerp(at(0, 1, 0), at(1, 1, 0), t.x),
+            t.y,
+        );
+        let back = lerp(
+            lerp(at(0, 0, 1), at(1, 0, 1), t.x),
+            lerp(at(0, 1, 1), at(1, 1, 1), t.x),
+            t.y,
+        );
+        lerp(front, back, t.z)
+    }
+}
+
+impl PostProcess for Lut {
+    fn name(&self) -> &'static str {
+        "lut"
+    }
+
+    /// Grades every pixel, leaving alpha as it is and pixels that hit nothing empty.
+    fn apply(&self, image: &mut Image) {
+        image.pixels.par_iter_mut().for_each(|pixel| {
+            if pixel.w > 0.0 {
+                *pixel = self.sample(Vec3A::from_vec4(*pixel)).extend(pixel.w);
+            }
+        });
+    }
+}
+
+/// Errors from loading a lookup table.
+#[derive(Debug)]
+pub enum LutError {
+    Io(io::Error),
+    /// The file is not a valid `.cube` file.
+    Format(String),
+}
+
+impl fmt::Display for LutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LutError::Io(err) => write!(f, "failed to read lookup table: {err}"),
+            LutError::Format(err) => write!(f, "invalid lookup table: {err}"),
+        }
+    }
+}
+
+impl Error for LutError {}
+
+impl From<io::Error> for LutError {
+    fn from(err: io::Error) -> Self {
+        LutError::Io(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec4;
+
+    use super::*;
+
+    /// Table of a given size whose output is `grade` of each point's input.
+    fn cube(size: usize, grade: impl Fn(Vec3A) -> Vec3A) -> String {
+        let mut text = format!("# generated\nTITLE \"test\"\nLUT_3D_SIZE {size}\n\n");
+        let last = (size - 1) as f32;
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    let color = grade(Vec3A::new(r as f32, g as f32, b as f32) / last);
+                    text += &format!("{} {} {}\n", color.x, color.y, color.z);
+                }
+            }
+        }
+        text
+    }
+
+    #[test]
+    fn identity_keeps_colors() {
+        let lut = Lut::parse(&cube(17, |color| color)).unwrap();
+        for color in [Vec3A::ZERO, Vec3A::ONE, Vec3A::new(0.2, 0.55, 0.9)] {
+            assert!(lut.sample(color).abs_diff_eq(color, 1e-6));
+        }
+
+        let mut image = Image::new(2, 1);
+        let color = Vec4::new(0.2, 0.5, 0.75, 1.0);
+        image.pixels[0] = color;
+        lut.apply(&mut image);
+        assert!(image.pixels[0].abs_diff_eq(color, 1e-6));
+        assert_eq!(image.pixels[1], Vec4::ZERO);
+    }
+
+    #[test]
+    fn samples_trilinearly() {
+        // red and blue swapped and green squared, which is only matched exactly at the points of the grid
+        let lut = Lut::parse(&cube(3, |c| Vec3A::new(c.z, c.y * c.y, c.x))).unwrap();
+        assert!(lut
+            .sample(Vec3A::new(1.0, 0.5, 0.0))
+            .abs_diff_eq(Vec3A::new(0.0, 0.25, 1.0), 1e-6));
+        // halfway between the points at 0.5 and 1 of green
+        assert!(lut
+            .sample(Vec3A::new(0.25, 0.75, 0.5))
+            .abs_diff_eq(Vec3A::new(0.5, 0.625, 0.25), 1e-6));
+        // clamped to the domain
+        assert!(lut.sample(Vec3A::splat(2.0)).abs_diff_eq(Vec3A::ONE, 1e-6));
+
+        let mut image = Image::new(1, 1);
+        image.pixels[0] = Vec4::new(1.0, 0.0, 0.0, 0.5);
+        lut.apply(&mut image);
+        assert!(image.pixels[0].abs_diff_eq(Vec4::new(0.0, 0.0, 1.0, 0.5), 1e-6));
+    }
+
+    #[test]
+    fn domain_scales_inputs() {
+        let text = cube(2, |color| color).replace(
+            "LUT_3D_SIZE",
+            "DOMAIN_MIN 0 0 0\nDOMAIN_MAX 0.5 0.5 0.5\nLUT_3D_SIZE",
+        );
+        let lut = Lut::parse(&text).unwrap();
+        assert!(lut
+            .sample(Vec3A::splat(0.25))
+            .abs_diff_eq(Vec3A::splat(0.5), 1e-6));
+
+        let text = cube(2, |color| color).replace("TITLE", "LUT_3D_INPUT_RANGE 0 0.5\nTITLE");
+        assert_eq!(Lut::parse(&text).unwrap(), lut);
+    }
+
+    #[test]
+    fn rejects_invalid_files() {
+        let invalid = [
+            "0 0 0\n",
+            "LUT_1D_SIZE 4\n",
+            "LUT_3D_SIZE 2\n0 0 0\n",
+            "LUT_3D_SIZE 1\n0 0 0\n",
+            "LUT_3D_SIZE 2\n0 0 x\n",
+            "LUT_3D_SIZE 2\n0 0\n",
+            "LUT_3D_SIZE 2\nSHADOWS 0\n",
+        ];
+        for text in invalid {
+            assert!(
+                matches!(Lut::parse(text), Err(LutError::Format(_))),
+                "{text}"
+            );
+        }
+        let inverted = cube(2, |color| color).replace("TITLE", "DOMAIN_MIN 1 1 1\nTITLE");
+        assert!(Lut::parse(&inverted).is_err());
+    }
+}
@@ -0,0 +1,293 @@
+use std::{fmt, sync::atomic::Ordering, sync::Arc};
+
+use glam::{Vec3A, Vec4};
+use rayon::iter::{
+    IndexedParallelIterator, IntoParallelIterator, IntoParallelRefIterator, ParallelIterator,
+};
+
+#[cfg(feature = "trace")]
+use tracing::*;
+
+use crate::export::Framebuffer;
+
+pub mod bloom;
+pub mod flare;
+pub mod lut;
+pub mod tone;
+
+/// Effect applied to a rendered image before it is saved, as one stage of a [`Pipeline`].
+pub trait PostProcess: Send + Sync {
+    /// Name of the effect in scene files, and which stage [`Pipeline::set`] replaces.
+    fn name(&self) -> &'static str;
+
+    fn apply(&self, image: &mut Image);
+}
+
+/// Image being post-processed, with colors and alpha from 0 to 1 kept as floats so that effects can be chained
+/// without rounding in between.
+///
+/// Colors aren't premultiplied by alpha, and may go past 1 until the image is packed back into a framebuffer, so a
+/// later effect such as [`Tonemap`](tone::Tonemap) can still tell how bright they are. Images of framebuffers that
+/// keep unclamped colors start out with them, so [`Bloom`](bloom::Bloom) spreads light from what the renderer shaded
+/// past white, see [`Framebuffer::with_hdr`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    /// RGBA of each pixel, row by row.
+    pub pixels: Vec<Vec4>,
+}
+
+impl Image {
+    /// Transparent black image.
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![Vec4::ZERO; width * height],
+        }
+    }
+
+    /// Unpacks a framebuffer, taking the unclamped colors if it keeps them.
+    pub fn from_framebuffer(fb: &Framebuffer) -> Self {
+        let width = fb.width();
+        let pixels = (0..width * fb.height())
+            .into_par_iter()
+            .map(|i| {
+                let (x, y) = (i % width, i / width);
+                let pixel = unpack(fb.pixel_mut(x, y).load(Ordering::Acquire));
+                match fb.hdr(x, y) {
+                    Some(hdr) => hdr.extend(pixel.w),
+                    None => pixel,
+                }
+            })
+            .collect();
+        Self {
+            width,
+            height: fb.height(),
+            pixels,
+        }
+    }
+
+    /// Rounds every pixel to 8 bits per channel and stores it in a framebuffer of the same size, and its unclamped
+    /// color too if the framebuffer keeps it.
+    pub fn write(&self, fb: &Framebuffer) {
+        assert_eq!(
+            (fb.width(), fb.height()),
+            (self.width, self.height),
+            "framebuffer size does not match the image"
+        );
+        self.pixels.par_iter().enumerate().for_each(|(i, &pixel)| {
+            let (x, y) = (i % self.width, i / self.width);
+            fb.pixel_mut(x, y).store(pack(pixel), Ordering::Release);
+            fb.set_hdr(x, y, Vec3A::from_vec4(pixel));
+        });
+    }
+}
+
+/// Effects applied in order to rendered images.
+///
+/// The framebuffer is unpacked into an [`Image`] once, passed through every stage, and rounded back to 8 bits at
+/// the end, so an empty pipeline leaves it untouched.
+#[derive(Clone, Default)]
+pub struct Pipeline {
+    stages: Vec<Arc<dyn PostProcess>>,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an effect after the others.
+    pub fn push(&mut self, stage: impl PostProcess + 'static) {
+        self.stages.push(Arc::new(stage));
+    }
+
+    /// Replaces the stage with the same name as an effect, or adds it at the end, before any dithering, which only
+    /// works last.
+    pub fn set(&mut self, stage: impl PostProcess + 'static) {
+        let name = stage.name();
+        let stage = Arc::new(stage);
+        match self.stages.iter().position(|other| other.name() == name) {
+            Some(i) => self.stages[i] = stage,
+            None => match self.stages.last().filter(|last| last.name() == "dither") {
+                Some(_) => self.stages.insert(self.stages.len() - 1, stage),
+                None => self.stages.push(stage),
+            },
+        }
+    }
+
+    /// Replaces the stage with the same name as an effect, or adds it before the others.
+    pub fn set_front(&mut self, stage: impl PostProcess + 'static) {
+        let name = stage.name();
+        let stage = Arc::new(stage);
+        match self.stages.iter().position(|other| other.name() == name) {
+            Some(i) => self.stages[i] = stage,
+            None => self.stages.insert(0, stage),
+        }
+    }
+
+    /// Names of the stages, in order.
+    pub fn names(&self) -> Vec<&'static str> {
+        self.stages.iter().map(|stage| stage.name()).collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stages.is_empty()
+    }
+
+    /// Applies every stage to a framebuffer in place.
+    pub fn apply(&self, fb: &Framebuffer) {
+        #[cfg(feature = "trace")]
+        let _span = trace_span!("post_process").entered();
+
+        if self.is_empty() {
+            return;
+        }
+        let mut image = Image::from_framebuffer(fb);
+        for stage in &self.stages {
+            stage.apply(&mut image);
+        }
+        image.write(fb);
+    }
+}
+
+impl fmt::Debug for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.names()).finish()
+    }
+}
+
+/// Color and alpha of a pixel packed as RGBA, from 0 to 1.
+fn unpack(color: u32) -> Vec4 {
+    Vec4::new(
+        (color >> 24) as f32,
+        (color >> 16 & 0xff) as f32,
+        (color >> 8 & 0xff) as f32,
+        (color & 0xff) as f32,
+    ) / 255.0
+}
+
+/// Packs a color and alpha from 0 to 1 as RGBA, clamping values outside of that.
+fn pack(color: Vec4) -> u32 {
+    let color = (color * 255.0)
+        .round()
+        .clamp(Vec4::ZERO, Vec4::splat(255.0))
+        .as_uvec4();
+    color.x << 24 | color.y << 16 | color.z << 8 | color.w
+}
+
+/// Weights of the channels in the brightness of a color (Rec. 709).
+const LUMA: Vec3A = Vec3A::new(0.2126, 0.7152, 0.0722);
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        bloom::Bloom,
+        tone::{Dither, Exposure, Vignette},
+        *,
+    };
+
+    #[test]
+    fn empty_pipeline_keeps_framebuffer() {
+        let fb = Framebuffer::new(2, 2);
+        fb.pixel_mut(1, 0).store(0x12345678, Ordering::Relaxed);
+        Pipeline::new().apply(&fb);
+        assert_eq!(fb.pixel_mut(1, 0).load(Ordering::Relaxed), 0x12345678);
+
+        // nor does unpacking and packing it again change it
+        Image::from_framebuffer(&fb).write(&fb);
+        assert_eq!(fb.pixel_mut(1, 0).load(Ordering::Relaxed), 0x12345678);
+        assert_eq!(fb.pixel_mut(0, 1).load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn bloom_spreads_light_past_white() {
+        // a pixel shaded four times brighter than white among dark ones, packed as white either way
+        let glow = |fb: Framebuffer| {
+            for y in 0..9 {
+                for x in 0..9 {
+                    fb.pixel_mut(x, y).store(0x202020ff, Ordering::Relaxed);
+                    fb.set_hdr(x, y, Vec3A::splat(0.125));
+                }
+            }
+            fb.pixel_mut(4, 4).store(0xffffffff, Ordering::Relaxed);
+            fb.set_hdr(4, 4, Vec3A::splat(4.0));
+            let mut pipeline = Pipeline::new();
+            pipeline.push(Bloom::default());
+            pipeline.apply(&fb);
+            fb.pixel_mut(5, 4).load(Ordering::Relaxed) >> 24
+        };
+        let (clamped, hdr) = (
+            glow(Framebuffer::new(9, 9)),
+            glow(Framebuffer::with_hdr(9, 9)),
+        );
+        assert!(clamped > 0x20);
+        assert!(hdr > clamped + 0x10);
+    }
+
+    #[test]
+    fn stages_run_in_order() {
+        /// Sets every color to a value, or doubles it.
+        struct Step(Option<f32>);
+
+        impl PostProcess for Step {
+            fn name(&self) -> &'static str {
+                match self.0 {
+                    Some(_) => "set",
+                    None => "double",
+                }
+            }
+
+            fn apply(&self, image: &mut Image) {
+                for pixel in &mut image.pixels {
+                    *pixel = match self.0 {
+                        Some(value) => Vec3A::splat(value).extend(pixel.w),
+                        None => (2.0 * Vec3A::from_vec4(*pixel)).extend(pixel.w),
+                    };
+                }
+            }
+        }
+
+        let fb = Framebuffer::new(1, 1);
+        fb.pixel_mut(0, 0).store(0xff, Ordering::Relaxed);
+        let mut pipeline = Pipeline::new();
+        pipeline.push(Step(Some(0.2)));
+        pipeline.push(Step(None));
+        pipeline.apply(&fb);
+        assert_eq!(fb.pixel_mut(0, 0).load(Ordering::Relaxed), 0x666666ff);
+
+        // setting it again replaces it in place
+        pipeline.set(Step(Some(0.4)));
+        assert_eq!(pipeline.names(), ["set", "double"]);
+        pipeline.apply(&fb);
+        assert_eq!(fb.pixel_mut(0, 0).load(Ordering::Relaxed), 0xccccccff);
+    }
+
+    #[test]
+    fn set_keeps_dither_last() {
+        let mut pipeline = Pipeline::new();
+        pipeline.push(Bloom::default());
+        pipeline.push(Dither);
+        pipeline.set(Vignette::default());
+        pipeline.set(Bloom {
+            threshold: 0.5,
+            intensity: 1.0,
+        });
+        pipeline.set_front(Exposure::default());
+        pipeline.set_front(Exposure {
+            ev: 1.0,
+            ..Default::default()
+        });
+        assert_eq!(
+            pipeline.names(),
+            ["exposure", "bloom", "vignette", "dither"]
+        );
+        pipeline.stages.remove(0);
+        assert_eq!(
+            format!("{pipeline:?}"),
+            r#"["bloom", "vignette", "dither"]"#
+        );
+    }
+}
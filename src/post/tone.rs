@@ -0,0 +1,263 @@
+use glam::{Vec2, Vec3A, Vec4};
+use rayon::iter::{IndexedParallelIterator, IntoParallelRefMutIterator, ParallelIterator};
+
+use super::{Image, PostProcess};
+
+/// Brightens or darkens the image by stops, and balances its white, on linear light rather than the sRGB-encoded
+/// values that are stored, so it acts like a camera's exposure. It belongs before any
+/// [`Tonemap`], which then rolls off what it pushed past white.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Exposure {
+    /// Stops of exposure, each doubling the light.
+    pub ev: f32,
+    /// sRGB color, from 0 to 1, that comes out as neutral gray of the same brightest channel, so a warm light can be
+    /// set to white by picking its color. White leaves colors as they are.
+    pub white: Vec3A,
+}
+
+impl Default for Exposure {
+    fn default() -> Self {
+        Self {
+            ev: 0.0,
+            white: Vec3A::ONE,
+        }
+    }
+}
+
+impl Exposure {
+    /// Factor each channel of linear light is multiplied by.
+    pub fn gain(&self) -> Vec3A {
+        let white = self
+            .white
+            .max(Vec3A::splat(1.0 / 255.0))
+            .to_array()
+            .map(to_linear);
+        let white = Vec3A::from_array(white);
+        2f32.powf(self.ev) * white.max_element() / white
+    }
+}
+
+impl PostProcess for Exposure {
+    fn name(&self) -> &'static str {
+        "exposure"
+    }
+
+    fn apply(&self, image: &mut Image) {
+        let gain = self.gain();
+        image.pixels.par_iter_mut().for_each(|pixel| {
+            let rgb = Vec3A::from_vec4(*pixel).to_array().map(to_linear);
+            let rgb = (Vec3A::from_array(rgb) * gain).to_array().map(to_srgb);
+            *pixel = Vec4::new(rgb[0], rgb[1], rgb[2], pixel.w);
+        });
+    }
+}
+
+/// Linear light of an sRGB-encoded value.
+fn to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// sRGB encoding of linear light, which keeps going past 1 for light brighter than white.
+fn to_srgb(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Rolls off colors brighter than the knee so they approach white instead of clipping, such as the glow added by
+/// [`Bloom`](super::bloom::Bloom) on top of already bright pixels. Colors below the knee are unchanged.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tonemap {
+    /// Value, from 0 to 1, above which each channel is compressed.
+    pub knee: f32,
+}
+
+impl Default for Tonemap {
+    fn default() -> Self {
+        Self { knee: 0.8 }
+    }
+}
+
+impl Tonemap {
+    /// Compressed value of a channel, which rises from the knee as steeply as below it and eases toward 1.
+    pub fn map(&self, value: f32) -> f32 {
+        if value <= self.knee {
+            return value;
+        }
+        let range = 1.0 - self.knee;
+        self.knee + range * (1.0 - (-(value - self.knee) / range).exp())
+    }
+}
+
+impl PostProcess for Tonemap {
+    fn name(&self) -> &'static str {
+        "tonemap"
+    }
+
+    fn apply(&self, image: &mut Image) {
+        image.pixels.par_iter_mut().for_each(|pixel| {
+            let rgb = Vec3A::from_vec4(*pixel)
+                .to_array()
+                .map(|value| self.map(value));
+            *pixel = Vec4::new(rgb[0], rgb[1], rgb[2], pixel.w);
+        });
+    }
+}
+
+/// Darkens the image toward its corners.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Vignette {
+    /// How much of the color is taken away in the corners, growing with the square of the distance from the middle.
+    pub intensity: f32,
+}
+
+impl Default for Vignette {
+    fn default() -> Self {
+        Self { intensity: 0.3 }
+    }
+}
+
+impl PostProcess for Vignette {
+    fn name(&self) -> &'static str {
+        "vignette"
+    }
+
+    fn apply(&self, image: &mut Image) {
+        let width = image.width;
+        let half = Vec2::new(image.width as f32, image.height as f32) / 2.0;
+        image
+            .pixels
+            .par_iter_mut()
+            .enumerate()
+            .for_each(|(i, pixel)| {
+                let pos = Vec2::new((i % width) as f32, (i / width) as f32) + 0.5;
+                let distance = (pos - half).length_squared() / half.length_squared();
+                let rgb = Vec3A::from_vec4(*pixel) * (1.0 - self.intensity * distance).max(0.0);
+                *pixel = rgb.extend(pixel.w);
+            });
+    }
+}
+
+/// Offsets colors by a 4x4 Bayer matrix so that when they are rounded to 8 bits, a color between two values is drawn
+/// as a pattern of both instead of a band of one. Only works as the last effect, right before rounding.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Dither;
+
+impl PostProcess for Dither {
+    fn name(&self) -> &'static str {
+        "dither"
+    }
+
+    fn apply(&self, image: &mut Image) {
+        let width = image.width;
+        image
+            .pixels
+            .par_iter_mut()
+            .enumerate()
+            .for_each(|(i, pixel)| {
+                let offset = bayer(i % width, i / width) / 255.0;
+                *pixel = (Vec3A::from_vec4(*pixel) - offset).extend(pixel.w);
+            });
+    }
+}
+
+/// Fraction past a half at which a value of the pixel in column `x` and row `y` should round up, from -0.5 to 0.5,
+/// so that the pixels of every 4x4 block round up in turn as a value rises.
+pub fn bayer(x: usize, y: usize) -> f32 {
+    (BAYER[y % 4][x % 4] as f32 + 0.5) / 16.0 - 0.5
+}
+
+/// Order in which the pixels of each 4x4 block round up as a value rises.
+///
+/// Each step adds the pixel furthest from the ones before it, so the pixels rounded up are spread out at every
+/// level.
+const BAYER: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exposure_scales_linear_light() {
+        for value in [0.0, 0.02, 0.3, 0.9, 1.5] {
+            assert!((to_srgb(to_linear(value)) - value).abs() < 1e-5, "{value}");
+        }
+
+        // a stop up doubles linear light, so mid gray goes up by less than double
+        let mut image = Image::new(2, 1);
+        image.pixels = vec![Vec4::new(0.5, 0.5, 0.5, 1.0), Vec4::ZERO];
+        Exposure {
+            ev: 1.0,
+            ..Default::default()
+        }
+        .apply(&mut image);
+        let lighter = image.pixels[0];
+        assert!((to_linear(lighter.x) - 2.0 * to_linear(0.5)).abs() < 1e-5);
+        assert!(lighter.x < 1.0 && lighter.w == 1.0);
+        assert_eq!(image.pixels[1], Vec4::ZERO);
+
+        // balancing for a warm white turns it gray and cools everything else
+        let exposure = Exposure {
+            ev: 0.0,
+            white: Vec3A::new(1.0, 0.8, 0.6),
+        };
+        let gain = exposure.gain();
+        assert_eq!(gain.x, 1.0);
+        assert!(gain.z > gain.y && gain.y > 1.0);
+        let mut image = Image::new(1, 1);
+        image.pixels[0] = Vec4::new(1.0, 0.8, 0.6, 1.0);
+        exposure.apply(&mut image);
+        assert!(image.pixels[0].abs_diff_eq(Vec4::ONE, 1e-5));
+        assert_eq!(Exposure::default().gain(), Vec3A::ONE);
+    }
+
+    #[test]
+    fn tonemap_rolls_off_highlights() {
+        let tonemap = Tonemap::default();
+        assert_eq!(tonemap.map(0.5), 0.5);
+        assert_eq!(tonemap.map(0.8), 0.8);
+        assert!(tonemap.map(0.9) < 0.9 && tonemap.map(0.9) > 0.8);
+        assert!(tonemap.map(3.0) < 1.0 && tonemap.map(3.0) > tonemap.map(1.5));
+
+        let mut image = Image::new(1, 1);
+        image.pixels[0] = Vec4::new(0.2, 1.0, 2.0, 0.5);
+        tonemap.apply(&mut image);
+        assert_eq!(image.pixels[0].x, 0.2);
+        assert!(image.pixels[0].z < 1.0);
+        assert_eq!(image.pixels[0].w, 0.5);
+    }
+
+    #[test]
+    fn vignette_darkens_corners() {
+        let mut image = Image::new(9, 5);
+        image.pixels.fill(Vec4::ONE);
+        Vignette { intensity: 0.5 }.apply(&mut image);
+        let pixel = |x: usize, y: usize| image.pixels[y * 9 + x];
+        assert_eq!(pixel(4, 2), Vec4::ONE);
+        assert!(pixel(0, 0).x < pixel(2, 1).x && pixel(2, 1).x < 1.0);
+        assert!(pixel(0, 0).x > 0.5);
+        assert_eq!(pixel(0, 0), pixel(8, 4));
+        assert_eq!(pixel(0, 0).w, 1.0);
+    }
+
+    #[test]
+    fn dither_averages_out_over_blocks() {
+        for value in [10.0, 10.3, 10.5, 10.97, 254.8] {
+            let mut image = Image::new(4, 4);
+            image.pixels.fill(Vec4::new(value / 255.0, 0.0, 0.0, 1.0));
+            Dither.apply(&mut image);
+            let sum: f32 = image
+                .pixels
+                .iter()
+                .map(|pixel| (pixel.x * 255.0).round())
+                .sum();
+            assert!((sum / 16.0 - value).abs() <= 1.0 / 32.0 + 1e-4, "{value}");
+        }
+    }
+}
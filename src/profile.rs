@@ -0,0 +1,237 @@
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    fmt,
+    ops::AddAssign,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use tracing::{span, Subscriber};
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+
+/// Parts of a run that time is split into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    /// Collecting voxels into scenes, including chunks loaded or generated as rays reach them.
+    Build,
+    /// Stepping rays through scenes.
+    Trace,
+    /// Everything else done for a pixel or tile, such as casting its rays and storing its color.
+    Shade,
+    /// Saving images.
+    Export,
+}
+
+impl Phase {
+    const ALL: [Phase; 4] = [Phase::Build, Phase::Trace, Phase::Shade, Phase::Export];
+
+    /// Phase of the time spent in a span, by its name, or `None` for spans whose time counts toward whatever they
+    /// were entered from.
+    ///
+    /// Whole renders are left out, since the thread that starts one mostly waits on the others.
+    pub fn of(name: &str) -> Option<Phase> {
+        match name {
+            "export_image" | "post_process" => Some(Phase::Export),
+            "ray_tracer_new" | "octree_new" | "octree_collapse" | "octree_dedup"
+            | "octree_reorder" | "infinite_generate" | "streaming_create" | "streaming_load" => {
+                Some(Phase::Build)
+            }
+            "ray_tracer_render_pixel" | "ray_tracer_render_tile" | "ray_tracer_render_blocks" => {
+                Some(Phase::Shade)
+            }
+            _ if name.ends_with("from_voxels") => Some(Phase::Build),
+            _ if name.starts_with("ray_tracer_") => None,
+            _ if name.contains("trace") || name.ends_with("beam_start") => Some(Phase::Trace),
+            _ => None,
+        }
+    }
+}
+
+/// Time spent in each [`Phase`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Timings([Duration; 4]);
+
+impl Timings {
+    pub fn get(&self, phase: Phase) -> Duration {
+        self.0[phase as usize]
+    }
+
+    fn add(&mut self, phase: Phase, time: Duration) {
+        self.0[phase as usize] += time;
+    }
+
+    fn is_zero(&self) -> bool {
+        self.0.iter().all(Duration::is_zero)
+    }
+}
+
+impl AddAssign for Timings {
+    fn add_assign(&mut self, other: Self) {
+        for phase in Phase::ALL {
+            self.add(phase, other.get(phase));
+        }
+    }
+}
+
+/// Time each thread spent in each [`Phase`], collected from the spans entered while tracing by a [`ProfileLayer`].
+///
+/// Time in a span counts toward its phase until another span with a phase is entered inside of it, so an octree
+/// trace inside of a pixel counts as tracing rather than shading, and the phases add up to the time spent in them.
+#[derive(Debug, Default)]
+pub struct Profile {
+    /// Totals of each thread, by name, added to whenever a thread leaves its outermost span with a phase.
+    threads: Mutex<BTreeMap<String, Timings>>,
+}
+
+impl Profile {
+    /// Layer adding the time spent in spans to this profile.
+    pub fn layer(self: &Arc<Self>) -> ProfileLayer {
+        ProfileLayer {
+            profile: self.clone(),
+        }
+    }
+
+    /// Totals of each thread that spent time in a phase, by name.
+    pub fn threads(&self) -> BTreeMap<String, Timings> {
+        self.threads
+            .lock()
+            .expect("profile lock was poisoned")
+            .clone()
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let threads = self.threads();
+        let mut total = Timings::default();
+        writeln!(
+            f,
+            "{:<12} {:>10} {:>10} {:>10} {:>10}",
+            "Thread", "Build", "Trace", "Shade", "Export"
+        )?;
+        let row = |f: &mut fmt::Formatter<'_>, name: &str, timings: &Timings| {
+            let [build, trace, shade, export] = Phase::ALL.map(|phase| timings.get(phase));
+            writeln!(
+                f,
+                "{name:<12} {build:>10.2?} {trace:>10.2?} {shade:>10.2?} {export:>10.2?}"
+            )
+        };
+        for (name, timings) in &threads {
+            row(f, name, timings)?;
+            total += *timings;
+        }
+        row(f, "Total", &total)
+    }
+}
+
+thread_local! {
+    /// Phases of the spans entered on this thread, innermost last, with when the innermost was last entered or
+    /// resumed and the time not yet added to the profile.
+    static STACK: RefCell<(Vec<Phase>, Instant, Timings)> =
+        RefCell::new((Vec::new(), Instant::now(), Timings::default()));
+}
+
+/// Name of the current thread in a [`Profile`].
+fn thread_name() -> String {
+    match rayon::current_thread_index() {
+        Some(index) => format!("rayon {index}"),
+        None => thread::current().name().unwrap_or("unnamed").to_owned(),
+    }
+}
+
+/// [`Layer`] timing the spans of each thread for a [`Profile`], see [`Profile::layer`].
+pub struct ProfileLayer {
+    profile: Arc<Profile>,
+}
+
+impl<S> Layer<S> for ProfileLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_enter(&self, id: &span::Id, ctx: Context<'_, S>) {
+        let Some(phase) = ctx.span(id).and_then(|span| Phase::of(span.name())) else {
+            return;
+        };
+        STACK.with_borrow_mut(|(stack, since, timings)| {
+            let now = Instant::now();
+            if let Some(&outer) = stack.last() {
+                timings.add(outer, now - *since);
+            }
+            stack.push(phase);
+            *since = now;
+        });
+    }
+
+    fn on_exit(&self, id: &span::Id, ctx: Context<'_, S>) {
+        if ctx
+            .span(id)
+            .and_then(|span| Phase::of(span.name()))
+            .is_none()
+        {
+            return;
+        }
+        STACK.with_borrow_mut(|(stack, since, timings)| {
+            let now = Instant::now();
+            if let Some(phase) = stack.pop() {
+                timings.add(phase, now - *since);
+            }
+            *since = now;
+
+            // only once per outermost span, so threads don't wait on each other for every octree node
+            if stack.is_empty() && !timings.is_zero() {
+                let mut threads = self
+                    .profile
+                    .threads
+                    .lock()
+                    .expect("profile lock was poisoned");
+                *threads.entry(thread_name()).or_default() += std::mem::take(timings);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing::trace_span;
+    use tracing_subscriber::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn phases_from_span_names() {
+        assert_eq!(Phase::of("octree_from_voxels"), Some(Phase::Build));
+        assert_eq!(Phase::of("streaming_load"), Some(Phase::Build));
+        assert_eq!(Phase::of("node_trace_packet"), Some(Phase::Trace));
+        assert_eq!(Phase::of("octree_beam_start"), Some(Phase::Trace));
+        assert_eq!(Phase::of("ray_tracer_render_pixel"), Some(Phase::Shade));
+        assert_eq!(Phase::of("export_image"), Some(Phase::Export));
+        assert_eq!(Phase::of("ray_tracer_render"), None);
+        assert_eq!(Phase::of("pearson_hash"), None);
+    }
+
+    #[test]
+    fn nested_spans_count_toward_the_innermost_phase() {
+        let profile = Arc::new(Profile::default());
+        let subscriber = tracing_subscriber::registry().with(profile.layer());
+        tracing::subscriber::with_default(subscriber, || {
+            let _build = trace_span!("octree_from_voxels").entered();
+            thread::sleep(Duration::from_millis(20));
+            {
+                let _pixel = trace_span!("ray_tracer_render_pixel").entered();
+                let _trace = trace_span!("octree_trace").entered();
+                thread::sleep(Duration::from_millis(20));
+            }
+        });
+
+        let threads = profile.threads();
+        assert_eq!(threads.len(), 1);
+        let timings = threads.values().next().unwrap();
+        assert!(timings.get(Phase::Build) >= Duration::from_millis(20));
+        assert!(timings.get(Phase::Trace) >= Duration::from_millis(20));
+        assert!(timings.get(Phase::Shade) < timings.get(Phase::Trace));
+        assert_eq!(timings.get(Phase::Export), Duration::ZERO);
+        assert!(profile.to_string().starts_with("Thread"));
+    }
+}
@@ -0,0 +1,121 @@
+use std::io::{self, Read, Write};
+
+use glam::{IVec3, U8Vec3};
+
+use crate::{
+    archive::ArchiveError,
+    voxel::{Voxel, VoxelKind},
+};
+
+use super::{palette::VoxelPalette, types::IAabb};
+
+/// Writes the start of a saved storage: its magic, version and bounding box.
+pub(super) fn write_header(
+    writer: &mut impl Write,
+    magic: &[u8; 8],
+    version: u32,
+    bb: IAabb,
+) -> io::Result<()> {
+    writer.write_all(magic)?;
+    write_u32(writer, version)?;
+    for v in [bb.origin, bb.extents] {
+        for axis in v.to_array() {
+            writer.write_all(&axis.to_le_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+/// Reads the header written by [`write_header`], returning the version and the bounding box.
+///
+/// Versions from one up to `version` are accepted.
+pub(super) fn read_header(
+    reader: &mut impl Read,
+    magic: &[u8; 8],
+    version: u32,
+) -> Result<(u32, IAabb), ArchiveError> {
+    let mut found = [0; 8];
+    reader.read_exact(&mut found)?;
+    if &found != magic {
+        return Err(ArchiveError::Format(format!(
+            "expected a {} file",
+            String::from_utf8_lossy(magic)
+        )));
+    }
+
+    let found = read_u32(reader)?;
+    if !(1..=version).contains(&found) {
+        return Err(ArchiveError::Format(format!("unsupported version {found}")));
+    }
+
+    let mut axes = [0; 6];
+    for axis in &mut axes {
+        *axis = read_u32(reader)? as i32;
+    }
+    let origin = IVec3::new(axes[0], axes[1], axes[2]);
+    let extents = IVec3::new(axes[3], axes[4], axes[5]);
+    if extents.cmple(IVec3::ZERO).any() {
+        return Err(ArchiveError::Format("bounding box is empty".into()));
+    }
+    Ok((found, IAabb::new(origin, extents)))
+}
+
+/// Writes the voxels of a palette in the order of their indices, as in scene archives.
+pub(super) fn write_palette(writer: &mut impl Write, palette: &VoxelPalette) -> io::Result<()> {
+    write_u32(writer, palette.len() as u32)?;
+    for voxel in palette.voxels() {
+        writer.write_all(&voxel.color.to_array())?;
+        writer.write_all(&voxel.kind.0.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+/// Reads a palette written by [`write_palette`], keeping every voxel at the index it was saved with.
+pub(super) fn read_palette(reader: &mut impl Read) -> Result<VoxelPalette, ArchiveError> {
+    let len = read_u32(reader)?;
+    if len > u16::MAX as u32 {
+        return Err(ArchiveError::Format(format!(
+            "palette of {len} voxels is too big"
+        )));
+    }
+
+    let mut palette = VoxelPalette::new();
+    for i in 1..=len {
+        let mut color = [0; 3];
+        reader.read_exact(&mut color)?;
+        let kind = VoxelKind(read_u16(reader)?);
+        let index = palette.insert(Voxel::new(U8Vec3::from_array(color), kind));
+        if index.get() as u32 != i {
+            return Err(ArchiveError::Format("palette has duplicate voxels".into()));
+        }
+    }
+    Ok(palette)
+}
+
+pub(super) fn write_u32(writer: &mut impl Write, value: u32) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+pub(super) fn read_u8(reader: &mut impl Read) -> io::Result<u8> {
+    let mut bytes = [0; 1];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes[0])
+}
+
+pub(super) fn read_u16(reader: &mut impl Read) -> io::Result<u16> {
+    let mut bytes = [0; 2];
+    reader.read_exact(&mut bytes)?;
+    Ok(u16::from_le_bytes(bytes))
+}
+
+pub(super) fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+pub(super) fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
@@ -0,0 +1,194 @@
+use std::{mem, num::NonZeroU32};
+
+use glam::IVec3;
+
+#[cfg(feature = "trace")]
+use tracing::*;
+
+use crate::voxel::VoxelSource;
+
+use super::{
+    chunked::{chunk_index, march_chunks, march_chunks_f64},
+    palette::{PaletteIndex, VoxelPalette},
+    types::{DRay, Hit, IAabb, Ray},
+    MemoryUsage, Scene,
+};
+
+/// Width of a brick in voxels.
+pub const BRICK_SIZE: i32 = 8;
+
+const BRICK_VOLUME: usize = (BRICK_SIZE * BRICK_SIZE * BRICK_SIZE) as usize;
+
+/// Two-level brickmap: a coarse dense grid over the scene where each cell points to a dense 8x8x8 brick of voxels,
+/// or to nothing if the cell is empty.
+///
+/// Unlike [`ChunkedStorage`](super::chunked::ChunkedStorage), finding a brick is a single array lookup instead of
+/// hashing, at the cost of a pointer for every cell of the grid.
+pub struct BrickmapStorage {
+    /// Cells of the coarse grid with x changing fastest, holding one more than the index of their brick.
+    grid: Box<[Option<NonZeroU32>]>,
+    /// Number of cells along each axis.
+    cells: IVec3,
+    bricks: Vec<[Option<PaletteIndex>; BRICK_VOLUME]>,
+    palette: VoxelPalette,
+    bb: IAabb,
+}
+
+impl BrickmapStorage {
+    /// Number of bricks holding at least one voxel.
+    pub fn brick_count(&self) -> usize {
+        self.bricks.len()
+    }
+
+    fn cell_index(&self, cell: IVec3) -> usize {
+        (cell.x + self.cells.x * (cell.y + self.cells.y * cell.z)) as usize
+    }
+
+    fn brick(&self, cell: IVec3) -> Option<&[Option<PaletteIndex>; BRICK_VOLUME]> {
+        if cell.cmpge(self.cells).any() {
+            return None;
+        }
+        let id = self.grid[self.cell_index(cell)]?;
+        Some(&self.bricks[id.get() as usize - 1])
+    }
+}
+
+impl Scene for BrickmapStorage {
+    fn from_voxels<S: VoxelSource + ?Sized>(source: &S, bb: IAabb) -> Self {
+        #[cfg(feature = "trace")]
+        let _span = trace_span!("brickmap_from_voxels").entered();
+
+        let cells = (bb.max() - bb.min() + BRICK_SIZE - 1) / BRICK_SIZE;
+        let mut storage = Self {
+            grid: vec![None; cells.element_product() as usize].into(),
+            cells,
+            bricks: Vec::new(),
+            palette: VoxelPalette::new(),
+            bb,
+        };
+        let mut column = vec![None; bb.height()];
+
+        for (i, x) in bb.iter_x().enumerate() {
+            for (k, z) in bb.iter_z().enumerate() {
+                source.column(x, z, bb.iter_y(), &mut column);
+                for (cy, slice) in column.chunks(BRICK_SIZE as usize).enumerate() {
+                    if slice.iter().all(Option::is_none) {
+                        continue;
+                    }
+
+                    let pos = IVec3::new(i as i32, cy as i32 * BRICK_SIZE, k as i32);
+                    let cell = storage.cell_index(pos / BRICK_SIZE);
+                    let id = match storage.grid[cell] {
+                        Some(id) => id,
+                        None => {
+                            storage.bricks.push([None; BRICK_VOLUME]);
+                            let id = u32::try_from(storage.bricks.len())
+                                .ok()
+                                .and_then(NonZeroU32::new)
+                                .expect("too many bricks");
+                            storage.grid[cell] = Some(id);
+                            id
+                        }
+                    };
+
+                    let brick = &mut storage.bricks[id.get() as usize - 1];
+                    for (j, voxel) in slice.iter().enumerate() {
+                        let local = pos % BRICK_SIZE + IVec3::Y * j as i32;
+                        brick[chunk_index(local, BRICK_SIZE)] =
+                            voxel.map(|voxel| storage.palette.insert(voxel));
+                    }
+                }
+            }
+        }
+
+        #[cfg(feature = "trace")]
+        debug!("bricks" = storage.bricks.len());
+
+        storage
+    }
+
+    fn trace_hit(&self, ray: Ray, _debug: bool) -> Option<Hit> {
+        #[cfg(feature = "trace")]
+        let _span = trace_span!("brickmap_trace").entered();
+
+        march_chunks(self.bb, BRICK_SIZE, ray, |cell| {
+            let brick = self.brick(cell)?;
+            Some((&brick[..], &self.palette))
+        })
+        .map(|(hit, _)| hit)
+    }
+
+    fn trace_hit_f64(&self, ray: DRay, _debug: bool) -> Option<Hit> {
+        march_chunks_f64(self.bb, BRICK_SIZE, ray, |cell| {
+            let brick = self.brick(cell)?;
+            Some((&brick[..], &self.palette))
+        })
+        .map(|(hit, _)| hit)
+    }
+
+    fn memory_usage(&self) -> MemoryUsage {
+        let brick = mem::size_of::<[Option<PaletteIndex>; BRICK_VOLUME]>();
+        MemoryUsage {
+            nodes: self.bricks.len(),
+            voxel_bytes: self.bricks.len() * brick,
+            overhead_bytes: mem::size_of_val(&*self.grid)
+                + (self.bricks.capacity() - self.bricks.len()) * brick,
+            palette_bytes: self.palette.heap_bytes(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{U8Vec3, Vec3A};
+
+    use super::*;
+    use crate::{
+        ray_tracer::dense::DenseStorage,
+        voxel::{grid::VoxelGrid, Voxel, VoxelGenerator},
+    };
+
+    #[test]
+    fn matches_dense() {
+        let source = VoxelGenerator::new_from_seed(3);
+        let bb = IAabb::new(IVec3::ZERO, 20 * IVec3::ONE);
+        let brickmap = BrickmapStorage::from_voxels(&source, bb);
+        let dense = DenseStorage::from_voxels(&source, bb);
+
+        let mut hits = 0;
+        for i in 0..200 {
+            let angle = i as f32 * 0.1;
+            let origin = Vec3A::new(50.0 * angle.cos(), 30.0, 50.0 * angle.sin());
+            let target = Vec3A::new((i % 7) as f32 - 3.0, -4.0, (i % 5) as f32 - 2.0);
+            let ray = Ray::new(origin, target - origin);
+            let voxel = brickmap.trace(ray, false);
+            assert_eq!(voxel, dense.trace(ray, false), "ray {i}");
+            hits += voxel.is_some() as usize;
+        }
+        assert!(hits > 0);
+    }
+
+    #[test]
+    fn empty_cells() {
+        // a 20x12x10 box is 3x2x2 cells, with partial cells along every far edge
+        let size = IVec3::new(20, 12, 10);
+        let bb = IAabb::new(size / 2, size / 2);
+        let voxel = Voxel::from(U8Vec3::ONE);
+        let mut grid = VoxelGrid::new(size);
+        grid.set(IVec3::new(1, 1, 1), Some(voxel));
+        grid.set(IVec3::new(2, 2, 2), Some(voxel));
+        grid.set(IVec3::new(19, 11, 9), Some(voxel));
+
+        let storage = BrickmapStorage::from_voxels(&grid, bb);
+        assert_eq!(storage.cells, IVec3::new(3, 2, 2));
+        assert_eq!(storage.brick_count(), 2);
+        assert!(storage.brick(IVec3::new(1, 0, 0)).is_none());
+
+        let ray = Ray::new(Vec3A::new(30.0, 11.5, 9.5), Vec3A::NEG_X);
+        assert_eq!(storage.trace(ray, false), Some(voxel));
+        let ray = Ray::new(Vec3A::new(1.5, 1.5, -10.0), Vec3A::Z);
+        assert_eq!(storage.trace(ray, false), Some(voxel));
+        let ray = Ray::new(Vec3A::new(10.5, -10.0, 5.5), Vec3A::Y);
+        assert_eq!(storage.trace(ray, false), None);
+    }
+}
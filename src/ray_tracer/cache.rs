@@ -0,0 +1,303 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    hash::Hash,
+    mem,
+    sync::{Mutex, MutexGuard},
+};
+
+/// Marks the ends of the list of entries from most to least recently used.
+const NONE: usize = usize::MAX;
+
+/// Chunks loaded on demand, dropping the least recently used ones once they take more memory than a budget.
+///
+/// Used by the scenes that only load the chunks rays reach, so they can render scenes larger than memory without
+/// holding on to every chunk that was ever seen. Loading happens outside the lock, so several rays can load chunks at
+/// once; if two load the same chunk, the first one stored is kept.
+pub(super) struct ChunkCache<K, V> {
+    /// Bytes the cached values may take before old ones are dropped, or `None` to keep everything.
+    budget: Option<usize>,
+    inner: Mutex<CacheInner<K, V>>,
+}
+
+/// Values along with a list of entries linked from most to least recently used by their indices, so a lookup only
+/// has to relink one entry.
+struct CacheInner<K, V> {
+    /// Each key's value and the index of its entry.
+    values: HashMap<K, (V, usize)>,
+    /// Entries, with `None` for ones that were dropped and can be reused.
+    entries: Vec<Option<CacheEntry<K>>>,
+    free: Vec<usize>,
+    /// Most recently used entry.
+    head: usize,
+    /// Least recently used entry.
+    tail: usize,
+    bytes: usize,
+    stats: CacheStats,
+}
+
+struct CacheEntry<K> {
+    key: K,
+    bytes: usize,
+    /// Next more recently used entry.
+    prev: usize,
+    /// Next less recently used entry.
+    next: usize,
+}
+
+impl<K: Copy + Eq + Hash, V: Clone> ChunkCache<K, V> {
+    /// Bytes the cache spends keeping track of each value.
+    const ENTRY_BYTES: usize =
+        mem::size_of::<(K, (V, usize))>() + mem::size_of::<Option<CacheEntry<K>>>();
+
+    /// Creates an empty cache that keeps values up to a budget in bytes, or all of them without one.
+    pub(super) fn new(budget: Option<usize>) -> Self {
+        Self {
+            budget,
+            inner: Mutex::new(CacheInner {
+                values: HashMap::new(),
+                entries: Vec::new(),
+                free: Vec::new(),
+                head: NONE,
+                tail: NONE,
+                bytes: 0,
+                stats: CacheStats::default(),
+            }),
+        }
+    }
+
+    /// Returns the value for a key, loading it and dropping the least recently used values past the budget if it
+    /// isn't cached.
+    ///
+    /// `bytes` gives the memory held by a value outside of the cache's own tables, which are counted separately.
+    pub(super) fn get_or_load(
+        &self,
+        key: K,
+        load: impl FnOnce() -> V,
+        bytes: impl FnOnce(&V) -> usize,
+    ) -> V {
+        {
+            let mut inner = self.lock();
+            if let Some(value) = inner.touch(key, self.budget.is_some()) {
+                inner.stats.hits += 1;
+                return value;
+            }
+            inner.stats.misses += 1;
+        }
+
+        let value = load();
+        let bytes = bytes(&value) + Self::ENTRY_BYTES;
+
+        let mut inner = self.lock();
+        if let Some(value) = inner.touch(key, self.budget.is_some()) {
+            // another ray loaded it first
+            return value;
+        }
+        inner.insert(key, value.clone(), bytes);
+
+        // the value just loaded is always kept, even if it alone is over the budget
+        if let Some(budget) = self.budget {
+            while inner.bytes > budget && inner.values.len() > 1 {
+                inner.evict();
+            }
+        }
+        value
+    }
+
+    /// Number of values cached.
+    pub(super) fn len(&self) -> usize {
+        self.lock().values.len()
+    }
+
+    /// Every value cached, in no particular order.
+    pub(super) fn values(&self) -> Vec<V> {
+        self.lock()
+            .values
+            .values()
+            .map(|(value, _)| value.clone())
+            .collect()
+    }
+
+    /// Memory held by the cached values, including what the cache spends keeping track of them.
+    pub(super) fn bytes(&self) -> usize {
+        self.lock().bytes
+    }
+
+    /// Lookups and evictions since the cache was created.
+    pub(super) fn stats(&self) -> CacheStats {
+        self.lock().stats
+    }
+
+    fn lock(&self) -> MutexGuard<'_, CacheInner<K, V>> {
+        self.inner.lock().expect("chunk cache lock was poisoned")
+    }
+}
+
+impl<K: Copy + Eq + Hash, V: Clone> CacheInner<K, V> {
+    /// Returns a value if it's cached, marking it as just used if the order will be needed for evicting.
+    fn touch(&mut self, key: K, order: bool) -> Option<V> {
+        let (value, i) = self.values.get(&key)?;
+        let (value, i) = (value.clone(), *i);
+        if order && i != self.head {
+            self.unlink(i);
+            self.push_front(i);
+        }
+        Some(value)
+    }
+
+    /// Adds a value as the most recently used.
+    fn insert(&mut self, key: K, value: V, bytes: usize) {
+        let entry = Some(CacheEntry {
+            key,
+            bytes,
+            prev: NONE,
+            next: NONE,
+        });
+        let i = match self.free.pop() {
+            Some(i) => {
+                self.entries[i] = entry;
+                i
+            }
+            None => {
+                self.entries.push(entry);
+                self.entries.len() - 1
+            }
+        };
+        self.values.insert(key, (value, i));
+        self.push_front(i);
+        self.bytes += bytes;
+    }
+
+    /// Drops the least recently used value.
+    fn evict(&mut self) {
+        let i = self.tail;
+        self.unlink(i);
+        let entry = self.entries[i].take().expect("cache entry is missing");
+        self.values.remove(&entry.key);
+        self.free.push(i);
+        self.bytes -= entry.bytes;
+        self.stats.evictions += 1;
+    }
+
+    fn unlink(&mut self, i: usize) {
+        let CacheEntry { prev, next, .. } = *self.entry(i);
+        match prev {
+            NONE => self.head = next,
+            prev => self.entry_mut(prev).next = next,
+        }
+        match next {
+            NONE => self.tail = prev,
+            next => self.entry_mut(next).prev = prev,
+        }
+    }
+
+    fn push_front(&mut self, i: usize) {
+        let head = self.head;
+        let entry = self.entry_mut(i);
+        entry.prev = NONE;
+        entry.next = head;
+        match head {
+            NONE => self.tail = i,
+            head => self.entry_mut(head).prev = i,
+        }
+        self.head = i;
+    }
+
+    fn entry(&self, i: usize) -> &CacheEntry<K> {
+        self.entries[i].as_ref().expect("cache entry is missing")
+    }
+
+    fn entry_mut(&mut self, i: usize) -> &mut CacheEntry<K> {
+        self.entries[i].as_mut().expect("cache entry is missing")
+    }
+}
+
+/// How often a [`Scene`](super::Scene) found the chunks rays needed already loaded.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Lookups of chunks that were already loaded.
+    pub hits: u64,
+    /// Lookups that had to load the chunk.
+    pub misses: u64,
+    /// Chunks dropped to stay within the memory budget.
+    pub evictions: u64,
+}
+
+impl CacheStats {
+    /// Fraction of lookups that found the chunk loaded, or zero before any lookups.
+    pub fn hit_rate(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            lookups => self.hits as f64 / lookups as f64,
+        }
+    }
+}
+
+impl fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} hits, {} misses ({:.1}% hit rate), {} evicted",
+            self.hits,
+            self.misses,
+            100.0 * self.hit_rate(),
+            self.evictions
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENTRY: usize = ChunkCache::<u32, u32>::ENTRY_BYTES;
+
+    #[test]
+    fn counts_hits_and_misses() {
+        let cache = ChunkCache::new(None);
+        assert_eq!(cache.get_or_load(1, || 10, |_| 0), 10);
+        assert_eq!(cache.get_or_load(1, || unreachable!(), |_| 0), 10);
+        assert_eq!(cache.get_or_load(2, || 20, |_| 100), 20);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.bytes(), 100 + 2 * ENTRY);
+
+        let stats = cache.stats();
+        assert_eq!(
+            stats,
+            CacheStats {
+                hits: 1,
+                misses: 2,
+                evictions: 0
+            }
+        );
+        assert!((stats.hit_rate() - 1.0 / 3.0).abs() < 1e-9);
+        assert_eq!(
+            stats.to_string(),
+            "1 hits, 2 misses (33.3% hit rate), 0 evicted"
+        );
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let cache = ChunkCache::new(Some(3 * (100 + ENTRY)));
+        for key in 0..3 {
+            cache.get_or_load(key, || key, |_| 100);
+        }
+        // 0 is used again, so 1 is now the oldest
+        cache.get_or_load(0, || unreachable!(), |_| 100);
+        cache.get_or_load(3, || 3, |_| 100);
+
+        let mut keys = cache.values();
+        keys.sort();
+        assert_eq!(keys, [0, 2, 3]);
+        assert_eq!(cache.stats().evictions, 1);
+        assert_eq!(cache.bytes(), 3 * (100 + ENTRY));
+
+        // values over the budget on their own are still kept until the next one is loaded
+        cache.get_or_load(4, || 4, |_| 10_000);
+        assert_eq!(cache.values(), [4]);
+        cache.get_or_load(5, || 5, |_| 100);
+        assert_eq!(cache.values(), [5]);
+        assert_eq!(cache.stats().evictions, 5);
+    }
+}
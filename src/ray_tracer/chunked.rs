@@ -0,0 +1,271 @@
+use std::{collections::HashMap, mem, ops::Deref};
+
+use glam::IVec3;
+
+#[cfg(feature = "trace")]
+use tracing::*;
+
+use crate::voxel::{Voxel, VoxelSource};
+
+use super::{
+    dense::{march, march_f64, march_from},
+    hash_map_bytes,
+    palette::{PaletteIndex, VoxelPalette},
+    types::{DRay, Hit, IAabb, Ray},
+    MemoryUsage, Scene,
+};
+
+/// Width of a chunk in voxels.
+pub const CHUNK_SIZE: i32 = 16;
+
+const CHUNK_VOLUME: usize = (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as usize;
+
+/// Dense chunks of a fixed size, looked up by position in a hash map so empty chunks take no space.
+///
+/// Rays step through the grid of chunks first and only march through the voxels of chunks that exist, which skips
+/// open air much like an octree but with a single level.
+pub struct ChunkedStorage {
+    chunks: HashMap<IVec3, Box<[Option<PaletteIndex>]>>,
+    palette: VoxelPalette,
+    bb: IAabb,
+}
+
+impl ChunkedStorage {
+    /// Number of chunks holding at least one voxel.
+    pub fn chunk_count(&self) -> usize {
+        self.chunks.len()
+    }
+}
+
+impl Scene for ChunkedStorage {
+    fn from_voxels<S: VoxelSource + ?Sized>(source: &S, bb: IAabb) -> Self {
+        #[cfg(feature = "trace")]
+        let _span = trace_span!("chunked_from_voxels").entered();
+
+        let mut palette = VoxelPalette::new();
+        let mut chunks = HashMap::new();
+        let mut column = vec![None; bb.height()];
+
+        for (i, x) in bb.iter_x().enumerate() {
+            for (k, z) in bb.iter_z().enumerate() {
+                source.column(x, z, bb.iter_y(), &mut column);
+                for (cy, slice) in column.chunks(CHUNK_SIZE as usize).enumerate() {
+                    if slice.iter().all(Option::is_none) {
+                        continue;
+                    }
+
+                    let pos = IVec3::new(i as i32, (cy as i32) * CHUNK_SIZE, k as i32);
+                    let data = chunks
+                        .entry(pos.div_euclid(IVec3::splat(CHUNK_SIZE)))
+                        .or_insert_with(|| vec![None; CHUNK_VOLUME].into_boxed_slice());
+                    for (j, voxel) in slice.iter().enumerate() {
+                        let local = pos.rem_euclid(IVec3::splat(CHUNK_SIZE)) + IVec3::Y * j as i32;
+                        data[chunk_index(local, CHUNK_SIZE)] =
+                            voxel.map(|voxel| palette.insert(voxel));
+                    }
+                }
+            }
+        }
+
+        #[cfg(feature = "trace")]
+        debug!("chunks" = chunks.len());
+
+        Self {
+            chunks,
+            palette,
+            bb,
+        }
+    }
+
+    fn trace_hit(&self, ray: Ray, _debug: bool) -> Option<Hit> {
+        #[cfg(feature = "trace")]
+        let _span = trace_span!("chunked_trace").entered();
+
+        march_chunks(self.bb, CHUNK_SIZE, ray, |chunk| {
+            let data = self.chunks.get(&chunk)?;
+            Some((&data[..], &self.palette))
+        })
+        .map(|(hit, _)| hit)
+    }
+
+    fn trace_hit_f64(&self, ray: DRay, _debug: bool) -> Option<Hit> {
+        march_chunks_f64(self.bb, CHUNK_SIZE, ray, |chunk| {
+            let data = self.chunks.get(&chunk)?;
+            Some((&data[..], &self.palette))
+        })
+        .map(|(hit, _)| hit)
+    }
+
+    fn memory_usage(&self) -> MemoryUsage {
+        MemoryUsage {
+            nodes: self.chunks.len(),
+            voxel_bytes: self.chunks.len() * CHUNK_VOLUME * mem::size_of::<Option<PaletteIndex>>(),
+            overhead_bytes: hash_map_bytes(&self.chunks),
+            palette_bytes: self.palette.heap_bytes(),
+        }
+    }
+}
+
+/// Voxels of a chunk stepped through by [`march_chunks`].
+pub(super) trait ChunkVoxels {
+    /// Voxel at an index from [`chunk_index`], if any.
+    fn voxel(&self, index: usize) -> Option<Voxel>;
+}
+
+impl<D: Deref<Target = [Option<PaletteIndex>]>> ChunkVoxels for (D, &VoxelPalette) {
+    fn voxel(&self, index: usize) -> Option<Voxel> {
+        let (data, palette) = self;
+        data[index].map(|index| palette.get(index))
+    }
+}
+
+/// Steps a ray through a grid of dense chunks covering a bounding box, and then through the voxels of each chunk
+/// that `chunk` returns, so missing chunks are skipped whole.
+///
+/// Chunks are `size` voxels wide, indexed from the minimum corner of the box, and store voxels as in
+/// [`chunk_index`]. Chunks at the far edges are cut off at the box, so sizes of the box need to be even.
+///
+/// Returns the voxel that was hit along with the minimum corner of its cell.
+pub(super) fn march_chunks<C: ChunkVoxels>(
+    bb: IAabb,
+    size: i32,
+    ray: Ray,
+    mut chunk: impl FnMut(IVec3) -> Option<C>,
+) -> Option<(Hit, IVec3)> {
+    // march through the chunks in a space where each chunk is one unit wide, padding the grid to an even size
+    let counts = (bb.max() - bb.min() + size - 1) / size;
+    let padded = (counts + 1) / 2;
+    let grid = IAabb::new(padded, padded);
+    let scaled = ray.scaled(bb.min().as_vec3a(), size as f32);
+
+    let mut cell = IVec3::ZERO;
+    let (hit, _) = march(grid, scaled, |pos| {
+        if pos.cmpge(padded * 2).any() {
+            return None;
+        }
+        let Some(voxels) = chunk(pos) else {
+            return Some(None);
+        };
+
+        // the chunk's own box, cut off at the edge of the scene
+        let min = bb.min() + pos * size;
+        let extents = (bb.max() - min).min(IVec3::splat(size));
+        let chunk_bb = IAabb::new(min + extents / 2, extents / 2);
+
+        // start where the ray enters the chunk's box, rather than checking it again, since rounding can put a ray that
+        // the grid of chunks passes through an edge of the chunk just beside it
+        Some(
+            march_from(chunk_bb, ray, chunk_bb.entry(ray).max(ray.near), |local| {
+                if local.cmpge(extents).any() {
+                    return None;
+                }
+                cell = min + local;
+                Some(voxels.voxel(chunk_index(local, size)))
+            })
+            .map(|(voxel, distance)| Hit { voxel, distance }),
+        )
+    })?;
+    Some((hit, cell))
+}
+
+/// Same as [`march_chunks`] for a ray in double precision, which steps through the grid of chunks in `f64` and
+/// through each chunk in `f32` from where it enters it.
+pub(super) fn march_chunks_f64<C: ChunkVoxels>(
+    bb: IAabb,
+    size: i32,
+    ray: DRay,
+    mut chunk: impl FnMut(IVec3) -> Option<C>,
+) -> Option<(Hit, IVec3)> {
+    let counts = (bb.max() - bb.min() + size - 1) / size;
+    let padded = (counts + 1) / 2;
+    let grid = IAabb::new(padded, padded);
+    let scaled = ray.scaled(bb.min().as_dvec3(), size as f64);
+
+    let mut cell = IVec3::ZERO;
+    let (hit, _) = march_f64(grid, scaled, |pos| {
+        if pos.cmpge(padded * 2).any() {
+            return None;
+        }
+        let Some(voxels) = chunk(pos) else {
+            return Some(None);
+        };
+
+        let min = bb.min() + pos * size;
+        let extents = (bb.max() - min).min(IVec3::splat(size));
+        let chunk_bb = IAabb::new(min + extents / 2, extents / 2);
+
+        let start = chunk_bb.entry_f64(ray).max(ray.near);
+        let local = ray.relative(min.as_dvec3(), start);
+        Some(
+            march_from(chunk_bb.at_origin(), local, 0.0, |local| {
+                if local.cmpge(extents).any() {
+                    return None;
+                }
+                cell = min + local;
+                Some(voxels.voxel(chunk_index(local, size)))
+            })
+            .map(|(voxel, distance)| Hit { voxel, distance }.after(start)),
+        )
+    })?;
+    Some((hit, cell))
+}
+
+/// Index of a position inside a chunk `size` voxels wide, with x changing fastest.
+pub(super) fn chunk_index(pos: IVec3, size: i32) -> usize {
+    (pos.x + size * (pos.y + size * pos.z)) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{U8Vec3, Vec3A};
+
+    use super::*;
+    use crate::{
+        ray_tracer::dense::DenseStorage,
+        voxel::{grid::VoxelGrid, VoxelGenerator},
+    };
+
+    #[test]
+    fn matches_dense() {
+        let source = VoxelGenerator::new_from_seed(3);
+        let bb = IAabb::new(IVec3::ZERO, 20 * IVec3::ONE);
+        let chunked = ChunkedStorage::from_voxels(&source, bb);
+        let dense = DenseStorage::from_voxels(&source, bb);
+
+        let mut hits = 0;
+        for i in 0..200 {
+            let angle = i as f32 * 0.1;
+            let origin = Vec3A::new(50.0 * angle.cos(), 30.0, 50.0 * angle.sin());
+            let target = Vec3A::new((i % 7) as f32 - 3.0, -4.0, (i % 5) as f32 - 2.0);
+            let ray = Ray::new(origin, target - origin);
+            let voxel = chunked.trace(ray, false);
+            assert_eq!(voxel, dense.trace(ray, false), "ray {i}");
+            hits += voxel.is_some() as usize;
+        }
+        assert!(hits > 0);
+    }
+
+    #[test]
+    fn skips_empty_chunks() {
+        // two voxels in opposite corners of a 48 wide box, with a partial chunk at each far edge
+        let size = IVec3::new(48, 36, 40);
+        let bb = IAabb::new(size / 2, size / 2);
+        let red = Voxel::from(U8Vec3::new(255, 0, 0));
+        let blue = Voxel::from(U8Vec3::new(0, 0, 255));
+        let mut grid = VoxelGrid::new(size);
+        grid.set(IVec3::ZERO, Some(red));
+        grid.set(size - 1, Some(blue));
+
+        let storage = ChunkedStorage::from_voxels(&grid, bb);
+        assert_eq!(storage.chunk_count(), 2);
+
+        let ray = Ray::new(Vec3A::new(0.5, 0.5, -10.0), Vec3A::Z);
+        assert_eq!(storage.trace(ray, false), Some(red));
+        let ray = Ray::new(Vec3A::new(47.5, 35.5, 50.0), Vec3A::NEG_Z);
+        assert_eq!(storage.trace(ray, false), Some(blue));
+        let ray = Ray::new(Vec3A::new(60.0, 0.5, 0.5), Vec3A::NEG_X);
+        assert_eq!(storage.trace(ray, false), Some(red));
+        let ray = Ray::new(Vec3A::new(20.5, -10.0, 20.5), Vec3A::Y);
+        assert_eq!(storage.trace(ray, false), None);
+    }
+}
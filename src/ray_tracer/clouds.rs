@@ -0,0 +1,254 @@
+use glam::{DVec3, Vec3A};
+use noise::{NoiseFn, Perlin};
+
+use super::types::Ray;
+
+/// Settings for a layer of clouds over the scene.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CloudSettings {
+    /// Height of the bottom of the layer in voxels.
+    pub altitude: f32,
+    /// Height of the layer in voxels.
+    pub thickness: f32,
+    /// Fraction (0 to 1) of the sky covered by clouds.
+    pub coverage: f32,
+    /// Share of the light that a voxel of the thickest cloud stops, from 0 to 1.
+    pub density: f32,
+    /// Rough width of a cloud in voxels.
+    pub scale: f32,
+}
+
+impl Default for CloudSettings {
+    fn default() -> Self {
+        Self {
+            altitude: 100.0,
+            thickness: 24.0,
+            coverage: 0.4,
+            density: 0.15,
+            scale: 64.0,
+        }
+    }
+}
+
+/// Clouds of 3D noise in a band of altitudes, traced through rather than stored as voxels.
+///
+/// Rays are marched through the band in [`STEPS`] steps, blending the cloud in front of whatever they hit, and the
+/// ground is shadowed by how much cloud lies between it and the sun.
+#[derive(Clone)]
+pub struct Clouds {
+    settings: CloudSettings,
+    noise: Perlin,
+}
+
+/// Cloud found along a ray, to be blended over what it hit.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CloudHit {
+    /// Color of the cloud from 0 to 255, premultiplied by its opacity.
+    pub color: Vec3A,
+    /// How much of what lies behind the cloud it hides, from 0 to 1.
+    pub opacity: f32,
+}
+
+impl CloudHit {
+    /// Blends the cloud over a color from 0 to 255 with an opacity from 0 to 1, giving the color, no longer
+    /// premultiplied, and opacity of both together.
+    pub fn over(self, color: Vec3A, opacity: f32) -> (Vec3A, f32) {
+        let behind = 1.0 - self.opacity;
+        let total = self.opacity + behind * opacity;
+        if total == 0.0 {
+            return (Vec3A::ZERO, 0.0);
+        }
+        ((self.color + behind * opacity * color) / total, total)
+    }
+}
+
+impl Clouds {
+    pub fn new(seed: u32, settings: CloudSettings) -> Self {
+        Self {
+            settings,
+            noise: Perlin::new(seed.wrapping_add(CLOUD_SEED)),
+        }
+    }
+
+    pub fn settings(&self) -> CloudSettings {
+        self.settings
+    }
+
+    /// Share of the light a voxel of cloud at a point stops, zero outside of the clouds.
+    ///
+    /// Clouds are where two octaves of noise are in the top `coverage` part of their range, thickest in the middle
+    /// of the band and thinning out toward its bottom and top.
+    pub fn density(&self, point: Vec3A) -> f32 {
+        let CloudSettings {
+            altitude,
+            thickness,
+            coverage,
+            density,
+            scale,
+        } = self.settings;
+        let height = (point.y - altitude) / thickness;
+        if !(0.0..=1.0).contains(&height) || coverage <= 0.0 {
+            return 0.0;
+        }
+
+        // stretched out sideways, so clouds are wider than they are tall
+        let p = (point / scale).as_dvec3() * DVec3::new(1.0, 2.0, 1.0);
+        let noise =
+            self.noise.get(p.to_array()) + 0.5 * self.noise.get((2.0 * p + 17.0).to_array());
+        let noise = (noise / 1.5 + 1.0) as f32 / 2.0;
+        let cover = ((noise - (1.0 - coverage)) / coverage.min(0.5)).clamp(0.0, 1.0);
+        density * cover * 4.0 * height * (1.0 - height)
+    }
+
+    /// Cloud along a ray up to `far`, such as where it hit the scene.
+    ///
+    /// Clouds are lit from above, fading to [`UNDERSIDE`] of white at the bottom of the band.
+    pub fn trace(&self, ray: &Ray, far: f32) -> CloudHit {
+        let Some((start, end)) = self.band(ray, ray.near, far) else {
+            return CloudHit::default();
+        };
+        let step = (end - start) / STEPS as f32;
+        let mut hit = CloudHit::default();
+        for i in 0..STEPS {
+            let point = ray.origin + (start + (i as f32 + 0.5) * step) * ray.dir;
+            let density = self.density(point);
+            if density == 0.0 {
+                continue;
+            }
+            let height = (point.y - self.settings.altitude) / self.settings.thickness;
+            let brightness = UNDERSIDE + (1.0 - UNDERSIDE) * height;
+            let opacity = (1.0 - (1.0 - density).powf(step)) * (1.0 - hit.opacity);
+            hit.color += Vec3A::splat(255.0 * brightness * opacity);
+            hit.opacity += opacity;
+            if hit.opacity > OPAQUE {
+                break;
+            }
+        }
+        hit
+    }
+
+    /// Share of the light that gets through the clouds from direction `dir` to a point, such as from the sun to the
+    /// ground.
+    pub fn transmittance(&self, point: Vec3A, dir: Vec3A) -> f32 {
+        let ray = Ray::new(point, dir);
+        let Some((start, end)) = self.band(&ray, 0.0, f32::INFINITY) else {
+            return 1.0;
+        };
+        let step = (end - start) / SHADOW_STEPS as f32;
+        (0..SHADOW_STEPS)
+            .map(|i| {
+                let density = self.density(point + (start + (i as f32 + 0.5) * step) * dir);
+                (1.0 - density).powf(step)
+            })
+            .product()
+    }
+
+    /// Distances from `near` to `far` along a ray where it is inside the band of altitudes, if anywhere.
+    fn band(&self, ray: &Ray, near: f32, far: f32) -> Option<(f32, f32)> {
+        let bottom = self.settings.altitude;
+        let top = bottom + self.settings.thickness;
+        let (start, end) = match ray.dir.y {
+            0.0 if (bottom..=top).contains(&ray.origin.y) => (near, far),
+            0.0 => return None,
+            dy => {
+                let (a, b) = ((bottom - ray.origin.y) / dy, (top - ray.origin.y) / dy);
+                (a.min(b).max(near), a.max(b).min(far))
+            }
+        };
+        // rays along the band would otherwise step through it forever
+        let end = end.min(start + MAX_LENGTH * self.settings.thickness);
+        (start < end).then_some((start, end))
+    }
+}
+
+/// Steps along a ray through the clouds.
+const STEPS: usize = 32;
+
+/// Steps along the way to the sun for cloud shadows.
+const SHADOW_STEPS: usize = 8;
+
+/// Longest a ray's path through the band of clouds is followed, in thicknesses of the band.
+const MAX_LENGTH: f32 = 16.0;
+
+/// Opacity past which a ray stops marching through the clouds.
+const OPAQUE: f32 = 0.99;
+
+/// Brightness of the bottom of the clouds, which are white at the top.
+const UNDERSIDE: f32 = 0.7;
+
+/// Offset of the cloud noise's seed from the terrain's, so clouds don't follow the hills.
+const CLOUD_SEED: u32 = 7;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clouds_stay_in_their_band() {
+        let clouds = Clouds::new(3, CloudSettings::default());
+        let CloudSettings {
+            altitude,
+            thickness,
+            ..
+        } = clouds.settings();
+        let mut inside = 0;
+        for i in 0..400 {
+            let point = Vec3A::new(i as f32 * 7.3, 0.0, i as f32 * 3.1);
+            assert_eq!(clouds.density(point + (altitude - 1.0) * Vec3A::Y), 0.0);
+            assert_eq!(
+                clouds.density(point + (altitude + thickness + 1.0) * Vec3A::Y),
+                0.0
+            );
+            let density = clouds.density(point + (altitude + thickness / 2.0) * Vec3A::Y);
+            assert!((0.0..=0.15).contains(&density));
+            inside += (density > 0.0) as usize;
+        }
+        // roughly the coverage of the sky is cloudy in the middle of the band
+        assert!((80..240).contains(&inside), "{inside}");
+
+        let clear = Clouds::new(
+            3,
+            CloudSettings {
+                coverage: 0.0,
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            clear.density(Vec3A::new(0.0, altitude + thickness / 2.0, 0.0)),
+            0.0
+        );
+    }
+
+    #[test]
+    fn clouds_hide_and_shadow() {
+        let settings = CloudSettings {
+            coverage: 1.0,
+            density: 0.5,
+            ..Default::default()
+        };
+        let clouds = Clouds::new(3, settings);
+
+        // a ray looking up through overcast sky sees white
+        let up = Ray::new(Vec3A::ZERO, Vec3A::Y);
+        let hit = clouds.trace(&up, f32::INFINITY);
+        assert!(hit.opacity > 0.9);
+        let color = hit.color / hit.opacity;
+        assert!(color.x == color.z && color.x > 255.0 * UNDERSIDE && color.x <= 255.0);
+        // but not past what it hit below them
+        assert_eq!(
+            clouds.trace(&up, settings.altitude - 1.0),
+            CloudHit::default()
+        );
+        // nor looking down from below
+        assert_eq!(
+            clouds.trace(&Ray::new(Vec3A::ZERO, -Vec3A::Y), f32::INFINITY),
+            CloudHit::default()
+        );
+
+        let shade = clouds.transmittance(Vec3A::ZERO, Vec3A::Y);
+        assert!(shade < 0.1);
+        assert_eq!(clouds.transmittance(Vec3A::ZERO, -Vec3A::Y), 1.0);
+        let above = Vec3A::new(0.0, settings.altitude + settings.thickness + 1.0, 0.0);
+        assert_eq!(clouds.transmittance(above, Vec3A::Y), 1.0);
+    }
+}
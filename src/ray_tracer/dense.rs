@@ -1,23 +1,62 @@
-use glam::Vec3A;
+use std::{
+    fs,
+    io::{self, BufReader, BufWriter, Read, Write},
+    mem,
+    path::Path,
+};
+
+use glam::{DVec3, IVec3, Vec3A};
 
+#[cfg(feature = "stats")]
+use super::stats;
 #[cfg(feature = "trace")]
 use tracing::*;
 
-use crate::voxel::{Voxel, VoxelGenerator};
+use crate::{
+    archive::ArchiveError,
+    voxel::{Voxel, VoxelSource},
+};
 
 use super::{
-    types::{IAabb, Ray},
-    Scene,
+    binary::{read_header, read_palette, read_u16, write_header, write_palette},
+    distance::DistanceField,
+    palette::{PaletteIndex, VoxelPalette},
+    types::{DRay, Hit, IAabb, Ray},
+    MemoryUsage, Scene, SceneMut,
 };
 
+const MAGIC: &[u8; 8] = b"VOXCHUNK";
+/// Version of the chunk file format.
+const VERSION: u32 = 1;
+
 pub struct DenseStorage {
     chunk: Chunk,
 }
 
+impl DenseStorage {
+    /// Chunk holding the scene's voxels, for saving it with [`Chunk::save`].
+    pub fn chunk(&self) -> &Chunk {
+        &self.chunk
+    }
+}
+
 impl Scene for DenseStorage {
-    fn from_voxels(generator: &VoxelGenerator, bb: IAabb) -> Self {
-        let data = bb.iter().map(|pos| generator.lookup(pos)).collect();
-        let chunk = Chunk { data, bb };
+    fn from_voxels<S: VoxelSource + ?Sized>(source: &S, bb: IAabb) -> Self {
+        let mut palette = VoxelPalette::new();
+        let mut data = vec![None; bb.width() * bb.height() * bb.length()];
+        let mut column = vec![None; bb.height()];
+
+        for (i, x) in bb.iter_x().enumerate() {
+            for (k, z) in bb.iter_z().enumerate() {
+                source.column(x, z, bb.iter_y(), &mut column);
+                for (j, voxel) in column.iter().enumerate() {
+                    data[k + bb.length() * (j + bb.height() * i)] =
+                        voxel.map(|voxel| palette.insert(voxel));
+                }
+            }
+        }
+
+        let chunk = Chunk::from_indices(data, palette, bb);
 
         #[cfg(feature = "trace")]
         debug!("length" = chunk.len());
@@ -25,19 +64,70 @@ impl Scene for DenseStorage {
         Self { chunk }
     }
 
-    fn trace(&self, ray: Ray, _debug: bool) -> Option<Voxel> {
-        self.chunk.trace(ray)
+    fn trace_hit(&self, ray: Ray, _debug: bool) -> Option<Hit> {
+        self.chunk.trace_hit(ray)
+    }
+
+    fn trace_hit_f64(&self, ray: DRay, _debug: bool) -> Option<Hit> {
+        self.chunk.trace_hit_f64(ray)
+    }
+
+    fn memory_usage(&self) -> MemoryUsage {
+        MemoryUsage {
+            nodes: 1,
+            voxel_bytes: mem::size_of_val(&*self.chunk.data),
+            overhead_bytes: self.chunk.field.heap_bytes(),
+            palette_bytes: self.chunk.palette.heap_bytes(),
+        }
+    }
+}
+
+impl SceneMut for DenseStorage {
+    fn set(&mut self, pos: IVec3, voxel: Option<Voxel>) -> Option<Voxel> {
+        let chunk = &mut self.chunk;
+        if !chunk.bb.contains(pos) {
+            return None;
+        }
+
+        let local = (pos - chunk.bb.min()).as_uvec3();
+        let i = local.z as usize
+            + chunk.bb.length() * (local.y as usize + chunk.bb.height() * local.x as usize);
+        let old = chunk.data[i].map(|index| chunk.palette.get(index));
+        chunk.data[i] = voxel.map(|voxel| chunk.palette.insert(voxel));
+        if voxel.is_some() {
+            chunk.field.fill(local.as_ivec3());
+        }
+        old
     }
 }
 
 /// This storage will be a temporary alternative to an octree until that is implemented.
+///
+/// Voxels are stored as indices into the chunk's palette, and a coarse distance field lets rays skip through the
+/// empty space far from any of them.
 pub struct Chunk {
-    data: Box<[Option<Voxel>]>,
+    data: Box<[Option<PaletteIndex>]>,
+    palette: VoxelPalette,
     bb: IAabb,
+    field: DistanceField,
 }
 
 impl Chunk {
     pub fn new(data: impl Into<Box<[Option<Voxel>]>>, bb: IAabb) -> Self {
+        let mut palette = VoxelPalette::new();
+        let data: Vec<_> = data
+            .into()
+            .iter()
+            .map(|voxel| voxel.map(|voxel| palette.insert(voxel)))
+            .collect();
+        Self::from_indices(data, palette, bb)
+    }
+
+    fn from_indices(
+        data: impl Into<Box<[Option<PaletteIndex>]>>,
+        palette: VoxelPalette,
+        bb: IAabb,
+    ) -> Self {
         #[cfg(feature = "trace")]
         let _span = trace_span!("chunk_from_voxels").entered();
 
@@ -49,112 +139,338 @@ impl Chunk {
             "aabb size was not equal to data length"
         );
 
-        Self { data, bb }
+        let size = bb.max() - bb.min();
+        let field = DistanceField::new(size, |cell| {
+            data[(cell.z + size.z * (cell.y + size.y * cell.x)) as usize].is_some()
+        });
+
+        Self {
+            data,
+            palette,
+            bb,
+            field,
+        }
+    }
+
+    /// Distinct voxels in the chunk.
+    pub fn palette(&self) -> &VoxelPalette {
+        &self.palette
     }
 
     pub fn len(&self) -> usize {
         self.data.iter().filter(|i| i.is_some()).count()
     }
 
-    fn trace(&self, ray: Ray) -> Option<Voxel> {
-        #[cfg(feature = "trace")]
-        let _span = trace_span!("chunk_trace").entered();
+    pub fn is_empty(&self) -> bool {
+        self.data.iter().all(|i| i.is_none())
+    }
 
-        // See (for basic impl): https://github.com/cgyurgyik/fast-voxel-traversal-algorithm/blob/master/overview/FastVoxelTraversalOverview.md
-        // See (for DRY impl): https://m4xc.dev/articles/amanatides-and-woo/
+    /// Saves the chunk to a file.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), ArchiveError> {
+        let mut file = BufWriter::new(fs::File::create(path)?);
+        self.write(&mut file)?;
+        file.flush()?;
+        Ok(())
+    }
 
-        let range = self.bb.intersection(ray, 0.01..f32::INFINITY)?;
+    /// Loads a chunk from a file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ArchiveError> {
+        Self::read(BufReader::new(fs::File::open(path)?))
+    }
+
+    /// Writes the chunk in its file format.
+    ///
+    /// Files start with a header (magic, version, bounding box) and the palette, followed by the palette index of
+    /// every voxel in memory order, where zero is empty.
+    pub fn write(&self, mut writer: impl Write) -> io::Result<()> {
+        write_header(&mut writer, MAGIC, VERSION, self.bb)?;
+        write_palette(&mut writer, &self.palette)?;
+        for index in &self.data {
+            writer.write_all(&index.map_or(0, |index| index.get()).to_le_bytes())?;
+        }
+        Ok(())
+    }
 
-        let ray_start = ray.origin + ray.dir * (range.start + 0.0001);
+    /// Reads a chunk in its file format.
+    pub fn read(mut reader: impl Read) -> Result<Self, ArchiveError> {
+        let (_, bb) = read_header(&mut reader, MAGIC, VERSION)?;
+        let palette = read_palette(&mut reader)?;
+
+        let len = bb.width() * bb.height() * bb.length();
+        // the size comes from the file, so only trust it so far before anything is read
+        let mut data = Vec::with_capacity(len.min(1 << 20));
+        for _ in 0..len {
+            let index = read_u16(&mut reader)?;
+            if index as usize > palette.len() {
+                return Err(ArchiveError::Format(format!(
+                    "palette index {index} out of range"
+                )));
+            }
+            data.push(PaletteIndex::new(index));
+        }
 
-        let max = self.bb.max().as_vec3a();
-        let min = self.bb.min().as_vec3a();
+        Ok(Self::from_indices(data, palette, bb))
+    }
 
-        let entry_pos = ray_start - min;
+    #[cfg(test)]
+    fn trace(&self, ray: Ray) -> Option<Voxel> {
+        self.trace_hit(ray).map(|hit| hit.voxel)
+    }
 
-        let step = ray.dir.signum();
-        let delta = (1.0 / ray.dir).abs();
+    fn trace_hit(&self, ray: Ray) -> Option<Hit> {
+        #[cfg(feature = "trace")]
+        let _span = trace_span!("chunk_trace").entered();
 
-        let size = max - min;
-        let pos = entry_pos.floor().clamp(Vec3A::ZERO, size - Vec3A::ONE);
+        self.trace_hit_in(self.bb, ray)
+    }
 
-        let mut tmax = (pos - entry_pos + step / 2.0) / ray.dir;
+    /// Same as [`Chunk::trace_hit`] for a ray in double precision, which is traced in `f32` from where it enters the
+    /// chunk with the chunk moved to the origin.
+    fn trace_hit_f64(&self, ray: DRay) -> Option<Hit> {
+        #[cfg(feature = "trace")]
+        let _span = trace_span!("chunk_trace_f64").entered();
 
-        let mut curr_idx = pos.as_ivec3();
-        let step = step.as_ivec3();
+        let (start, ray) = ray.enter(self.bb)?;
+        self.trace_hit_in(self.bb.at_origin(), ray)
+            .map(|hit| hit.after(start))
+    }
 
-        // use conditions to iterate over voxel spaces
+    /// Traces a ray through the chunk as if it filled `bb`, a box of the same size.
+    fn trace_hit_in(&self, bb: IAabb, ray: Ray) -> Option<Hit> {
+        let size = bb.max() - bb.min();
+        let mut ray = ray;
+        let mut skipped = 0.0;
         loop {
-            let voxel_entry = self.data.get(
-                curr_idx.z as usize
-                    + size.z as usize
-                        * (curr_idx.y as usize + size.y as usize * curr_idx.x as usize),
-            )?;
-
-            if voxel_entry.is_some() {
-                return *voxel_entry;
-            }
-
-            if tmax.x < tmax.y && tmax.x < tmax.z {
-                curr_idx.x += step.x;
-                if curr_idx.x < 0 {
-                    break;
+            // cells far from any voxel stop the march, which picks up again past the empty space around them
+            let (step, distance) = march(bb, ray, |pos| {
+                let local = ray.origin - bb.min().as_vec3a();
+                // march starts a little past the origin, so stop that far short of the exit to pick up right at it
+                let skip = self
+                    .field
+                    .skip(pos, local, ray.dir)
+                    .map(|skip| skip - ray.near);
+                if let Some(skip) = skip.filter(|skip| *skip > 0.0) {
+                    return Some(Some(Err(skip)));
                 }
-                tmax.x += delta.x;
-            } else if tmax.y < tmax.z {
-                curr_idx.y += step.y;
-                if curr_idx.y < 0 {
-                    break;
+                let voxel_entry = self.data.get(
+                    pos.z as usize
+                        + size.z as usize * (pos.y as usize + size.y as usize * pos.x as usize),
+                )?;
+                Some(voxel_entry.map(|index| Ok(self.palette.get(index))))
+            })?;
+            match step {
+                Ok(voxel) => {
+                    return Some(Hit {
+                        voxel,
+                        distance: skipped + distance,
+                    })
                 }
-                tmax.y += delta.y;
-            } else {
-                curr_idx.z += step.z;
-                if curr_idx.z < 0 {
-                    break;
+                Err(skip) => {
+                    ray.origin += skip * ray.dir;
+                    ray.far -= skip;
+                    skipped += skip;
                 }
-                tmax.z += delta.z;
             }
         }
+    }
+}
 
-        return None;
+/// Steps a ray through the cells of a grid filling a bounding box, returning the first value found by `lookup` along
+/// with the distance along the ray to where it enters that cell.
+///
+/// `lookup` is given the position of each cell relative to the minimum corner of the box, and can return `None` to
+/// stop early. Rays stop on their own once they leave the box or pass [`Ray::far`].
+pub(super) fn march<T>(
+    bb: IAabb,
+    ray: Ray,
+    lookup: impl FnMut(IVec3) -> Option<Option<T>>,
+) -> Option<(T, f32)> {
+    let range = bb.intersection(ray, ray.near..ray.far)?;
+    march_from(bb, ray, range.start, lookup)
+}
+
+/// Same as [`march`], starting from a distance along the ray where it is already known to enter the box.
+///
+/// The ray starts in the cell closest to that point, so a ray found to enter the box by a coarser grid around it still
+/// steps through it when rounding puts it just outside.
+pub(super) fn march_from<T>(
+    bb: IAabb,
+    ray: Ray,
+    start: f32,
+    mut lookup: impl FnMut(IVec3) -> Option<Option<T>>,
+) -> Option<(T, f32)> {
+    // See (for basic impl): https://github.com/cgyurgyik/fast-voxel-traversal-algorithm/blob/master/overview/FastVoxelTraversalOverview.md
+    // See (for DRY impl): https://m4xc.dev/articles/amanatides-and-woo/
+
+    let ray_start = ray.origin + ray.dir * start;
+
+    let max = bb.max().as_vec3a();
+    let min = bb.min().as_vec3a();
+
+    let entry_pos = ray_start - min;
+
+    let step = ray.dir.signum();
+    let delta = (1.0 / ray.dir).abs();
+
+    let size = max - min;
+    let pos = ray.cell(entry_pos).clamp(Vec3A::ZERO, size - Vec3A::ONE);
+
+    // distance to the first boundary on each axis, which is the far side of the entry cell, and may be a little behind
+    // the start when it lies on a boundary
+    let mut tmax = Vec3A::select(
+        ray.dir.cmpeq(Vec3A::ZERO),
+        Vec3A::INFINITY,
+        (pos + (step + 1.0) / 2.0 - entry_pos) / ray.dir,
+    );
+
+    let mut curr_idx = pos.as_ivec3();
+    let cells = size.as_ivec3();
+    let step = step.as_ivec3();
+    // distance from the start to where the ray entered the current cell
+    let mut entered: f32 = 0.0;
+    let far = ray.far - start;
+
+    // use conditions to iterate over voxel spaces
+    loop {
+        if entered > far {
+            break;
+        }
+        #[cfg(feature = "stats")]
+        stats::step();
+        if let Some(value) = lookup(curr_idx)? {
+            return Some((value, start + entered.max(0.0)));
+        }
+
+        if tmax.x < tmax.y && tmax.x < tmax.z {
+            curr_idx.x += step.x;
+            if !(0..cells.x).contains(&curr_idx.x) {
+                break;
+            }
+            entered = tmax.x;
+            tmax.x += delta.x;
+        } else if tmax.y < tmax.z {
+            curr_idx.y += step.y;
+            if !(0..cells.y).contains(&curr_idx.y) {
+                break;
+            }
+            entered = tmax.y;
+            tmax.y += delta.y;
+        } else {
+            curr_idx.z += step.z;
+            if !(0..cells.z).contains(&curr_idx.z) {
+                break;
+            }
+            entered = tmax.z;
+            tmax.z += delta.z;
+        }
+    }
+
+    None
+}
+
+/// Same as [`march`] for a ray in double precision, stepping through the cells in `f64` so rays don't build up
+/// rounding across large grids far from the origin.
+pub(super) fn march_f64<T>(
+    bb: IAabb,
+    ray: DRay,
+    mut lookup: impl FnMut(IVec3) -> Option<Option<T>>,
+) -> Option<(T, f64)> {
+    let range = bb.intersection_f64(ray, ray.near..ray.far)?;
+    let start = range.start;
+
+    let min = bb.min().as_dvec3();
+    let entry_pos = ray.origin + ray.dir * start - min;
+
+    let step = ray.dir.signum();
+    let delta = (1.0 / ray.dir).abs();
+
+    let size = bb.max().as_dvec3() - min;
+    let pos = ray.cell(entry_pos).clamp(DVec3::ZERO, size - DVec3::ONE);
+
+    let mut tmax = DVec3::select(
+        ray.dir.cmpeq(DVec3::ZERO),
+        DVec3::INFINITY,
+        (pos + (step + 1.0) / 2.0 - entry_pos) / ray.dir,
+    );
+
+    let mut curr_idx = pos.as_ivec3();
+    let cells = size.as_ivec3();
+    let step = step.as_ivec3();
+    let mut entered: f64 = 0.0;
+    let far = ray.far - start;
+
+    loop {
+        if entered > far {
+            break;
+        }
+        #[cfg(feature = "stats")]
+        stats::step();
+        if let Some(value) = lookup(curr_idx)? {
+            return Some((value, start + entered.max(0.0)));
+        }
+
+        if tmax.x < tmax.y && tmax.x < tmax.z {
+            curr_idx.x += step.x;
+            if !(0..cells.x).contains(&curr_idx.x) {
+                break;
+            }
+            entered = tmax.x;
+            tmax.x += delta.x;
+        } else if tmax.y < tmax.z {
+            curr_idx.y += step.y;
+            if !(0..cells.y).contains(&curr_idx.y) {
+                break;
+            }
+            entered = tmax.y;
+            tmax.y += delta.y;
+        } else {
+            curr_idx.z += step.z;
+            if !(0..cells.z).contains(&curr_idx.z) {
+                break;
+            }
+            entered = tmax.z;
+            tmax.z += delta.z;
+        }
     }
+
+    None
 }
 
 #[cfg(test)]
 mod tests {
-    use glam::{IVec3, U8Vec3, Vec3A};
+    use glam::{DVec3, IVec3, U8Vec3, Vec3A};
 
     use crate::{
-        ray_tracer::types::{IAabb, Ray},
+        ray_tracer::types::{DRay, IAabb, Ray},
         voxel::Voxel,
     };
 
-    use super::Chunk;
+    use super::{march, Chunk};
 
     #[test]
     fn get_voxel_full() {
-        let data = vec![Some(Voxel { color: U8Vec3::ONE }); 2 * 2 * 2];
+        let data = vec![Some(Voxel::from(U8Vec3::ONE)); 2 * 2 * 2];
         let chunk = Chunk::new(data, IAabb::new(IVec3::ZERO, IVec3::ONE));
 
         {
             let ray = Ray::new(Vec3A::new(0.0, -5.0, 0.0), Vec3A::Y);
             assert!(chunk.bb.intersection(ray, 0.01..f32::INFINITY).is_some());
             let voxel = chunk.trace(ray).expect("voxel not found");
-            assert_eq!(voxel, Voxel { color: U8Vec3::ONE });
+            assert_eq!(voxel, Voxel::from(U8Vec3::ONE));
         }
     }
 
     #[test]
     fn get_voxel_one() {
         let mut data = vec![None; 2 * 2 * 2];
-        data[0] = Some(Voxel { color: U8Vec3::ONE });
+        data[0] = Some(Voxel::from(U8Vec3::ONE));
         let chunk = Chunk::new(data, IAabb::new(IVec3::ZERO, IVec3::ONE));
 
         {
             let ray = Ray::new(Vec3A::new(-0.5, -5.0, -0.5), Vec3A::Y);
             assert!(chunk.bb.intersection(ray, 0.01..f32::INFINITY).is_some());
             let voxel = chunk.trace(ray).expect("voxel not found");
-            assert_eq!(voxel, Voxel { color: U8Vec3::ONE });
+            assert_eq!(voxel, Voxel::from(U8Vec3::ONE));
         }
 
         {
@@ -178,33 +494,67 @@ mod tests {
         }
     }
 
+    #[test]
+    fn rays_stop_at_far_sides() {
+        // the voxels after the last one along z and y in the rows below, which an index past the end would land on
+        let mut data = vec![None; 4 * 4 * 4];
+        data[4] = Some(Voxel::from(U8Vec3::ONE));
+        data[17] = Some(Voxel::from(U8Vec3::ONE));
+        let chunk = Chunk::new(data, IAabb::new(IVec3::ZERO, IVec3::splat(2)));
+
+        let ray = Ray::new(Vec3A::new(-1.5, -1.5, 0.5), Vec3A::Z);
+        assert_eq!(chunk.trace(ray), None);
+        let ray = Ray::new(Vec3A::new(-1.5, -1.5, -1.5), Vec3A::Y);
+        assert_eq!(chunk.trace(ray), Some(Voxel::from(U8Vec3::ONE)));
+        let ray = Ray::new(Vec3A::new(-1.5, 0.5, -0.5), Vec3A::Y);
+        assert_eq!(chunk.trace(ray), None);
+        let ray = Ray::new(Vec3A::new(-0.5, -5.0, -0.5), Vec3A::Y);
+        assert_eq!(chunk.trace(ray), Some(Voxel::from(U8Vec3::ONE)));
+        // past the end of the data altogether
+        let ray = Ray::new(Vec3A::new(0.5, 1.5, 1.5), Vec3A::X);
+        assert_eq!(chunk.trace(ray), None);
+
+        // and the same for rays cast from outside, leaving through the far faces
+        for dir in [Vec3A::X, Vec3A::Y, Vec3A::Z] {
+            let origin = Vec3A::splat(1.5) - dir * 10.0;
+            let ray = Ray::new(origin, dir);
+            assert_eq!(chunk.trace(ray), None, "{dir}");
+            let ray = DRay::new(origin.as_dvec3(), dir.as_dvec3());
+            assert_eq!(chunk.trace_hit_f64(ray), None, "{dir}");
+        }
+    }
+
+    #[test]
+    fn rays_stop_at_far_distance() {
+        let mut data = vec![None; 4 * 4 * 4];
+        data[63] = Some(Voxel::from(U8Vec3::ONE));
+        let chunk = Chunk::new(data, IAabb::new(IVec3::ZERO, IVec3::splat(2)));
+
+        // the voxel is entered 10 voxels along the ray
+        let ray = Ray::new(Vec3A::new(-9.0, 1.5, 1.5), Vec3A::X);
+        assert_eq!(chunk.trace_hit(ray).map(|hit| hit.distance), Some(10.0));
+        for (far, hit) in [(9.5, false), (10.5, true)] {
+            let ray = Ray { far, ..ray };
+            assert_eq!(chunk.trace(ray).is_some(), hit, "{far}");
+            let ray = DRay {
+                far: far as f64,
+                ..DRay::new(DVec3::new(-9.0, 1.5, 1.5), DVec3::X)
+            };
+            assert_eq!(chunk.trace_hit_f64(ray).is_some(), hit, "{far}");
+        }
+    }
+
     #[test]
     fn get_voxel_dirs() {
         let data = vec![
-            Some(Voxel {
-                color: U8Vec3::new(0, 0, 0),
-            }),
-            Some(Voxel {
-                color: U8Vec3::new(0, 0, 1),
-            }),
-            Some(Voxel {
-                color: U8Vec3::new(0, 1, 0),
-            }),
-            Some(Voxel {
-                color: U8Vec3::new(0, 1, 1),
-            }),
-            Some(Voxel {
-                color: U8Vec3::new(1, 0, 0),
-            }),
-            Some(Voxel {
-                color: U8Vec3::new(1, 0, 1),
-            }),
-            Some(Voxel {
-                color: U8Vec3::new(1, 1, 0),
-            }),
-            Some(Voxel {
-                color: U8Vec3::new(1, 1, 1),
-            }),
+            Some(Voxel::from(U8Vec3::new(0, 0, 0))),
+            Some(Voxel::from(U8Vec3::new(0, 0, 1))),
+            Some(Voxel::from(U8Vec3::new(0, 1, 0))),
+            Some(Voxel::from(U8Vec3::new(0, 1, 1))),
+            Some(Voxel::from(U8Vec3::new(1, 0, 0))),
+            Some(Voxel::from(U8Vec3::new(1, 0, 1))),
+            Some(Voxel::from(U8Vec3::new(1, 1, 0))),
+            Some(Voxel::from(U8Vec3::new(1, 1, 1))),
         ];
         let chunk = Chunk::new(data, IAabb::new(IVec3::ZERO, IVec3::ONE));
 
@@ -212,96 +562,131 @@ mod tests {
             let ray = Ray::new(Vec3A::new(-0.5, -5.0, -0.5), Vec3A::Y);
             assert!(chunk.bb.intersection(ray, 0.01..f32::INFINITY).is_some());
             let voxel = chunk.trace(ray).expect("voxel not found");
-            assert_eq!(
-                voxel,
-                Voxel {
-                    color: U8Vec3::new(0, 0, 0)
-                }
-            );
+            assert_eq!(voxel, Voxel::from(U8Vec3::new(0, 0, 0)));
         }
 
         {
             let ray = Ray::new(Vec3A::new(-5.0, -0.5, 0.5), Vec3A::X);
             assert!(chunk.bb.intersection(ray, 0.01..f32::INFINITY).is_some());
             let voxel = chunk.trace(ray).expect("voxel not found");
-            assert_eq!(
-                voxel,
-                Voxel {
-                    color: U8Vec3::new(0, 0, 1)
-                }
-            );
+            assert_eq!(voxel, Voxel::from(U8Vec3::new(0, 0, 1)));
         }
 
         {
             let ray = Ray::new(Vec3A::new(-0.5, 5.0, -0.5), Vec3A::NEG_Y);
             assert!(chunk.bb.intersection(ray, 0.01..f32::INFINITY).is_some());
             let voxel = chunk.trace(ray).expect("voxel not found");
-            assert_eq!(
-                voxel,
-                Voxel {
-                    color: U8Vec3::new(0, 1, 0)
-                }
-            );
+            assert_eq!(voxel, Voxel::from(U8Vec3::new(0, 1, 0)));
         }
 
         {
             let ray = Ray::new(Vec3A::new(-0.5, 5.0, 0.5), Vec3A::NEG_Y);
             assert!(chunk.bb.intersection(ray, 0.01..f32::INFINITY).is_some());
             let voxel = chunk.trace(ray).expect("voxel not found");
-            assert_eq!(
-                voxel,
-                Voxel {
-                    color: U8Vec3::new(0, 1, 1)
-                }
-            );
+            assert_eq!(voxel, Voxel::from(U8Vec3::new(0, 1, 1)));
         }
 
         {
             let ray = Ray::new(Vec3A::new(5.0, -0.5, -0.5), Vec3A::NEG_X);
             assert!(chunk.bb.intersection(ray, 0.01..f32::INFINITY).is_some());
             let voxel = chunk.trace(ray).expect("voxel not found");
-            assert_eq!(
-                voxel,
-                Voxel {
-                    color: U8Vec3::new(1, 0, 0)
-                }
-            );
+            assert_eq!(voxel, Voxel::from(U8Vec3::new(1, 0, 0)));
         }
 
         {
             let ray = Ray::new(Vec3A::new(5.0, -0.5, 0.5), Vec3A::NEG_X);
             assert!(chunk.bb.intersection(ray, 0.01..f32::INFINITY).is_some());
             let voxel = chunk.trace(ray).expect("voxel not found");
-            assert_eq!(
-                voxel,
-                Voxel {
-                    color: U8Vec3::new(1, 0, 1)
-                }
-            );
+            assert_eq!(voxel, Voxel::from(U8Vec3::new(1, 0, 1)));
         }
 
         {
             let ray = Ray::new(Vec3A::new(0.5, 0.5, -5.0), Vec3A::Z);
             assert!(chunk.bb.intersection(ray, 0.01..f32::INFINITY).is_some());
             let voxel = chunk.trace(ray).expect("voxel not found");
-            assert_eq!(
-                voxel,
-                Voxel {
-                    color: U8Vec3::new(1, 1, 0)
-                }
-            );
+            assert_eq!(voxel, Voxel::from(U8Vec3::new(1, 1, 0)));
         }
 
         {
             let ray = Ray::new(Vec3A::new(0.5, 0.5, 5.0), Vec3A::NEG_Z);
             assert!(chunk.bb.intersection(ray, 0.01..f32::INFINITY).is_some());
             let voxel = chunk.trace(ray).expect("voxel not found");
-            assert_eq!(
-                voxel,
-                Voxel {
-                    color: U8Vec3::new(1, 1, 1)
+            assert_eq!(voxel, Voxel::from(U8Vec3::new(1, 1, 1)));
+        }
+    }
+
+    #[test]
+    fn skips_find_the_same_voxels() {
+        let size = 64;
+        let mut data = vec![None; size * size * size];
+        for (i, cell) in [[3, 60, 7], [40, 5, 33], [20, 20, 20], [61, 50, 2]]
+            .into_iter()
+            .enumerate()
+        {
+            data[cell[2] + size * (cell[1] + size * cell[0])] =
+                Some(Voxel::from(U8Vec3::splat(i as u8)));
+        }
+        let chunk = Chunk::new(data, IAabb::new(IVec3::ZERO, IVec3::splat(32)));
+
+        // rays from in and around the chunk toward points inside each voxel
+        for target in [[3, 60, 7], [40, 5, 33], [20, 20, 20], [61, 50, 2]] {
+            for i in 0..64 {
+                let origin = Vec3A::new(
+                    (i % 4) as f32 * 30.0 - 44.0,
+                    (i / 4 % 4) as f32 * 30.0 - 44.0,
+                    (i / 16) as f32 * 30.0 - 44.0,
+                );
+                let offset = Vec3A::new(0.3, -0.2, 0.1) * (i % 3 - 1) as f32 + 0.5 - 32.0;
+                let target = IVec3::from(target).as_vec3a() + offset;
+                let ray = Ray::new(origin, (target - origin).normalize());
+
+                let expected = march(chunk.bb, ray, |pos| {
+                    let i = pos.z + 64 * (pos.y + 64 * pos.x);
+                    let voxel = chunk.data.get(i as usize)?;
+                    Some(voxel.map(|index| chunk.palette.get(index)))
+                });
+                let hit = chunk.trace_hit(ray);
+                assert_eq!(
+                    hit.map(|hit| hit.voxel),
+                    expected.map(|hit| hit.0),
+                    "{ray:?}"
+                );
+                if let (Some(hit), Some(expected)) = (hit, expected) {
+                    assert!((hit.distance - expected.1).abs() < 0.01, "{ray:?}");
                 }
-            );
+            }
         }
     }
+
+    #[test]
+    fn save_and_load() {
+        let red = Voxel::from(U8Vec3::new(255, 0, 0));
+        let blue = Voxel::from(U8Vec3::new(0, 0, 255));
+        let data = [
+            Some(red),
+            None,
+            Some(blue),
+            None,
+            None,
+            Some(red),
+            None,
+            Some(blue),
+        ];
+        let chunk = Chunk::new(data, IAabb::new(IVec3::ZERO, IVec3::ONE));
+
+        let mut file = Vec::new();
+        chunk.write(&mut file).expect("failed to write");
+        let loaded = Chunk::read(file.as_slice()).expect("failed to read");
+        assert_eq!(loaded.data, chunk.data);
+        assert_eq!(loaded.palette, chunk.palette);
+        assert_eq!(loaded.bb, chunk.bb);
+
+        assert!(Chunk::read(b"NOTCHUNK".as_slice()).is_err());
+        assert!(Chunk::read(&file[..file.len() - 1]).is_err());
+        // a palette index past the two voxels of the palette
+        let mut broken = file.clone();
+        let last = broken.len() - 2;
+        broken[last] = 3;
+        assert!(Chunk::read(broken.as_slice()).is_err());
+    }
 }
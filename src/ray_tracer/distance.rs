@@ -0,0 +1,191 @@
+use glam::{IVec3, Vec3A};
+
+/// Side length of the blocks of cells the distance field measures in.
+const BLOCK: i32 = 4;
+
+/// Largest distance stored, in blocks, so a new voxel only has to update the blocks near it.
+const MAX_DISTANCE: u8 = 16;
+
+/// Distance from each block of a grid to the nearest block holding a voxel, so rays can cross empty space in large
+/// steps instead of cell by cell.
+///
+/// Distances are counted in blocks along the axis where they are furthest apart, so a block at distance `d` is
+/// surrounded by empty blocks out to `d - 1` blocks away on every side. Edits only ever lower distances, which keeps
+/// them safe to skip by: clearing voxels leaves the field as it was, so it may skip less than it could.
+pub(super) struct DistanceField {
+    /// Number of blocks along each axis.
+    blocks: IVec3,
+    /// Distance of each block, with x changing fastest.
+    distances: Box<[u8]>,
+}
+
+impl DistanceField {
+    /// Measures the distances in a grid of cells, given whether the cell at each position holds a voxel.
+    pub(super) fn new(size: IVec3, occupied: impl Fn(IVec3) -> bool) -> Self {
+        let blocks = (size + BLOCK - 1) / BLOCK;
+        let mut field = Self {
+            blocks,
+            distances: vec![MAX_DISTANCE; blocks.element_product() as usize].into(),
+        };
+        for z in 0..size.z {
+            for y in 0..size.y {
+                for x in 0..size.x {
+                    let cell = IVec3::new(x, y, z);
+                    if occupied(cell) {
+                        let i = field.index(cell / BLOCK);
+                        field.distances[i] = 0;
+                    }
+                }
+            }
+        }
+
+        // one pass forward and one backward, each taking distances from the neighbours already passed
+        let order = |i: usize| {
+            let i = i as i32;
+            IVec3::new(
+                i % blocks.x,
+                i / blocks.x % blocks.y,
+                i / (blocks.x * blocks.y),
+            )
+        };
+        let len = field.distances.len();
+        for i in 0..len {
+            field.relax(order(i), before);
+        }
+        for i in (0..len).rev() {
+            field.relax(order(i), |offset| !before(offset));
+        }
+        field
+    }
+
+    /// Lowers the distance of a block to one more than that of the neighbours `pick` accepts the offset of.
+    fn relax(&mut self, block: IVec3, pick: impl Fn(IVec3) -> bool) {
+        let mut distance = self.distances[self.index(block)];
+        for offset in neighbours().filter(|offset| pick(*offset)) {
+            let next = block + offset;
+            if next.cmpge(IVec3::ZERO).all() && next.cmplt(self.blocks).all() {
+                distance = distance.min(self.distances[self.index(next)] + 1);
+            }
+        }
+        let i = self.index(block);
+        self.distances[i] = distance;
+    }
+
+    fn index(&self, block: IVec3) -> usize {
+        (block.x + self.blocks.x * (block.y + self.blocks.y * block.z)) as usize
+    }
+
+    /// Records a voxel added to a cell, lowering the distances of the blocks around it.
+    pub(super) fn fill(&mut self, cell: IVec3) {
+        let block = cell / BLOCK;
+        if self.distances[self.index(block)] == 0 {
+            return;
+        }
+
+        let reach = IVec3::splat(MAX_DISTANCE as i32 - 1);
+        let min = (block - reach).max(IVec3::ZERO);
+        let max = (block + reach).min(self.blocks - 1);
+        for z in min.z..=max.z {
+            for y in min.y..=max.y {
+                for x in min.x..=max.x {
+                    let next = IVec3::new(x, y, z);
+                    let distance = (next - block).abs().max_element() as u8;
+                    let i = self.index(next);
+                    self.distances[i] = self.distances[i].min(distance);
+                }
+            }
+        }
+    }
+
+    /// Distance along a ray, given in the grid's cells, to where it leaves the empty space around a cell it is in,
+    /// if that space reaches at least a block past the cell's own block.
+    pub(super) fn skip(&self, cell: IVec3, origin: Vec3A, dir: Vec3A) -> Option<f32> {
+        let block = cell / BLOCK;
+        let distance = self.distances[self.index(block)] as i32;
+        if distance < 2 {
+            return None;
+        }
+
+        let min = ((block - (distance - 1)) * BLOCK).as_vec3a();
+        let max = ((block + distance) * BLOCK).as_vec3a();
+        let exits = Vec3A::select(
+            dir.cmpeq(Vec3A::ZERO),
+            Vec3A::INFINITY,
+            (Vec3A::select(dir.cmpgt(Vec3A::ZERO), max, min) - origin) / dir,
+        );
+        Some(exits.min_element())
+    }
+
+    /// Bytes held by the distances.
+    pub(super) fn heap_bytes(&self) -> usize {
+        self.distances.len()
+    }
+}
+
+/// Offsets to the 26 blocks around a block.
+fn neighbours() -> impl Iterator<Item = IVec3> {
+    (0..27)
+        .map(|i| IVec3::new(i % 3, i / 3 % 3, i / 9) - 1)
+        .filter(|offset| *offset != IVec3::ZERO)
+}
+
+/// Whether a neighbour comes before its block in memory order, where x changes fastest.
+fn before(offset: IVec3) -> bool {
+    (offset.z, offset.y, offset.x) < (0, 0, 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distances_to_nearest_voxel() {
+        let size = IVec3::new(40, 12, 24);
+        let voxels = [IVec3::new(1, 2, 3), IVec3::new(30, 10, 20)];
+        let field = DistanceField::new(size, |cell| voxels.contains(&cell));
+
+        for z in 0..field.blocks.z {
+            for y in 0..field.blocks.y {
+                for x in 0..field.blocks.x {
+                    let block = IVec3::new(x, y, z);
+                    let nearest = voxels
+                        .iter()
+                        .map(|voxel| (*voxel / BLOCK - block).abs().max_element())
+                        .min()
+                        .unwrap()
+                        .min(MAX_DISTANCE as i32);
+                    assert_eq!(
+                        field.distances[field.index(block)] as i32,
+                        nearest,
+                        "{block}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn skips_stay_clear_of_voxels() {
+        let size = IVec3::splat(64);
+        let mut field = DistanceField::new(size, |cell| cell == IVec3::new(60, 2, 60));
+        field.fill(IVec3::new(20, 40, 20));
+
+        let voxels = [IVec3::new(60, 2, 60), IVec3::new(20, 40, 20)];
+        let origin = Vec3A::new(1.5, 60.5, 2.5);
+        let dir = Vec3A::new(0.6, -0.5, 0.62).normalize();
+        let cell = origin.floor().as_ivec3();
+        let skip = field.skip(cell, origin, dir).expect("far from every voxel");
+        assert!(skip >= BLOCK as f32);
+
+        // the ray passes no block holding a voxel before the skip ends
+        for step in 0..(skip * 10.0) as usize {
+            let block = (origin + step as f32 * 0.1 * dir).floor().as_ivec3() / BLOCK;
+            assert!(
+                voxels.iter().all(|voxel| *voxel / BLOCK != block),
+                "{block}"
+            );
+        }
+        // and a cell next to a voxel can't skip
+        assert_eq!(field.skip(IVec3::new(21, 40, 20), origin, dir), None);
+    }
+}
@@ -0,0 +1,409 @@
+use glam::IVec3;
+
+use crate::voxel::VoxelSource;
+
+use super::{
+    brickmap::BrickmapStorage,
+    cache::CacheStats,
+    chunked::ChunkedStorage,
+    dense::DenseStorage,
+    hash::HashStorage,
+    infinite::InfiniteStorage,
+    morton::MortonStorage,
+    octree::{DagStorage, SparseStorage},
+    rle::RleStorage,
+    streaming::StreamingStorage,
+    types::{Beam, ConeHit, DRay, Hit, IAabb, Ray, PACKET},
+    MemoryUsage, Scene,
+};
+
+/// Storages a [`DynScene`] can be built with, named as on the command line and in scene files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Backend {
+    #[default]
+    Sparse,
+    Dense,
+    /// Sparse octree with identical subtrees merged
+    Dag,
+    /// Dense grid in Morton order
+    Morton,
+    /// Dense chunks in a hash map, without the empty ones
+    Chunked,
+    /// Hash map of single voxels, a slow reference for the octree
+    Hash,
+    /// Columns of run-length encoded voxels
+    Rle,
+    /// Coarse grid pointing to dense 8x8x8 bricks
+    Brickmap,
+    /// Dense chunks written to a temporary file and read back as rays reach them
+    Streaming,
+    /// Chunks generated as rays reach them, with terrain going on past the scene size
+    Infinite,
+}
+
+impl Backend {
+    /// Every backend, in the order they are listed on the command line.
+    pub const ALL: [Backend; 10] = [
+        Self::Sparse,
+        Self::Dense,
+        Self::Dag,
+        Self::Morton,
+        Self::Chunked,
+        Self::Hash,
+        Self::Rle,
+        Self::Brickmap,
+        Self::Streaming,
+        Self::Infinite,
+    ];
+
+    /// Backend with a name, ignoring case, see [`Backend::name`].
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|backend| backend.name().eq_ignore_ascii_case(name))
+    }
+
+    /// Lower case name, as accepted by [`Backend::from_name`].
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Sparse => "sparse",
+            Self::Dense => "dense",
+            Self::Dag => "dag",
+            Self::Morton => "morton",
+            Self::Chunked => "chunked",
+            Self::Hash => "hash",
+            Self::Rle => "rle",
+            Self::Brickmap => "brickmap",
+            Self::Streaming => "streaming",
+            Self::Infinite => "infinite",
+        }
+    }
+}
+
+/// A scene in any of the storages, picked while the program runs instead of by a type parameter.
+///
+/// Every call goes through a trait object, which costs a little per ray but means callers such as the renderer only
+/// need one copy of their code instead of one per storage.
+pub struct DynScene {
+    scene: Box<dyn Scene + Send + Sync>,
+}
+
+impl DynScene {
+    /// Collects the voxels inside of a bounding box from a source into a storage.
+    pub fn build<S: VoxelSource + ?Sized>(backend: Backend, source: &S, bb: IAabb) -> Self {
+        match backend {
+            Backend::Sparse => Self::new(SparseStorage::from_voxels(source, bb)),
+            Backend::Dense => Self::new(DenseStorage::from_voxels(source, bb)),
+            Backend::Dag => Self::new(DagStorage::from_voxels(source, bb)),
+            Backend::Morton => Self::new(MortonStorage::from_voxels(source, bb)),
+            Backend::Chunked => Self::new(ChunkedStorage::from_voxels(source, bb)),
+            Backend::Hash => Self::new(HashStorage::from_voxels(source, bb)),
+            Backend::Rle => Self::new(RleStorage::from_voxels(source, bb)),
+            Backend::Brickmap => Self::new(BrickmapStorage::from_voxels(source, bb)),
+            Backend::Streaming => Self::new(StreamingStorage::from_voxels(source, bb)),
+            Backend::Infinite => Self::new(InfiniteStorage::from_voxels(source, bb)),
+        }
+    }
+
+    /// Wraps a scene that was already built, such as one with settings [`DynScene::build`] leaves at their defaults.
+    pub fn new(scene: impl Scene + Send + Sync + 'static) -> Self {
+        Self {
+            scene: Box::new(scene),
+        }
+    }
+}
+
+impl Scene for DynScene {
+    /// Collects voxels into the default storage, the sparse octree.
+    fn from_voxels<S: VoxelSource + ?Sized>(source: &S, bb: IAabb) -> Self {
+        Self::build(Backend::default(), source, bb)
+    }
+
+    fn trace_hit(&self, ray: Ray, debug: bool) -> Option<Hit> {
+        self.scene.trace_hit(ray, debug)
+    }
+
+    fn trace_hit_from(&self, ray: Ray, start: f32, debug: bool) -> Option<Hit> {
+        self.scene.trace_hit_from(ray, start, debug)
+    }
+
+    fn trace_hit_f64(&self, ray: DRay, debug: bool) -> Option<Hit> {
+        self.scene.trace_hit_f64(ray, debug)
+    }
+
+    fn trace_packet(&self, rays: &[Ray; PACKET], start: f32, debug: bool) -> [Option<Hit>; PACKET] {
+        self.scene.trace_packet(rays, start, debug)
+    }
+
+    fn beam_start(&self, beam: Beam) -> f32 {
+        self.scene.beam_start(beam)
+    }
+
+    fn trace_cone(&self, ray: Ray, debug: bool) -> ConeHit {
+        self.scene.trace_cone(ray, debug)
+    }
+
+    fn cell(&self, pos: IVec3) -> IVec3 {
+        self.scene.cell(pos)
+    }
+
+    fn memory_usage(&self) -> MemoryUsage {
+        self.scene.memory_usage()
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        self.scene.cache_stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{U8Vec3, Vec3A};
+
+    use std::sync::atomic::Ordering;
+
+    use super::*;
+    use crate::{
+        ray_tracer::{Config, RayTracer},
+        voxel::{grid::VoxelGrid, Voxel, VoxelGenerator, VoxelKind},
+    };
+
+    #[test]
+    fn matches_storages() {
+        let source = VoxelGenerator::new_from_seed(3);
+        let bb = IAabb::new(IVec3::ZERO, IVec3::splat(16));
+        let dense = DenseStorage::from_voxels(&source, bb);
+        let sparse = SparseStorage::from_voxels(&source, bb);
+
+        for backend in Backend::ALL {
+            let scene = DynScene::build(backend, &source, bb);
+            assert_eq!(
+                Backend::from_name(&backend.name().to_uppercase()),
+                Some(backend)
+            );
+
+            // storages differ in which side of each position its voxel fills, like the octree or like the grid
+            let reference: &dyn Scene = match backend {
+                Backend::Sparse | Backend::Dag | Backend::Hash => &sparse,
+                _ => &dense,
+            };
+            for i in 0..50 {
+                let angle = i as f32 * 0.4;
+                let origin = Vec3A::new(40.0 * angle.cos(), 25.0, 40.0 * angle.sin());
+                let target = Vec3A::new((i % 7) as f32 - 3.2, -4.0, (i % 5) as f32 - 2.2);
+                let ray = Ray::new(origin, target - origin);
+                assert_eq!(
+                    scene.trace(ray, false),
+                    reference.trace(ray, false),
+                    "{backend:?} ray {i}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn camera_on_the_corner_is_outside() {
+        let voxel = Voxel::new(U8Vec3::new(0, 255, 0), VoxelKind::STONE);
+        let mut grid = VoxelGrid::new(IVec3::splat(16)).with_origin(IVec3::splat(-8));
+        for x in 0..16 {
+            for y in 0..16 {
+                for z in 0..16 {
+                    grid.set(IVec3::new(x, y, z), Some(voxel));
+                }
+            }
+        }
+        let config = Config {
+            size: 8,
+            camera_pos: Vec3A::splat(8.0),
+            res_width: 16,
+            res_height: 9,
+            ..Config::default()
+        };
+
+        for backend in Backend::ALL {
+            // rays from the corner of the bounds start on the edge of the voxels, which is not inside them
+            let scene = DynScene::build(backend, &grid, config.bounds());
+            let corner = RayTracer::from_scene(config, scene);
+            assert_eq!(corner.camera_voxel(), None, "{backend:?}");
+            let fb = corner.render();
+            let pixel = |x, y| fb.pixel_mut(x, y).load(Ordering::Relaxed);
+            assert_ne!(pixel(0, 0), pixel(8, 4), "{backend:?}");
+
+            let scene = DynScene::build(backend, &grid, config.bounds());
+            let inside = Config {
+                camera_pos: Vec3A::new(0.5, 2.5, 0.5),
+                ..config
+            };
+            let inside = RayTracer::from_scene(inside, scene);
+            assert_eq!(inside.camera_voxel(), Some(voxel), "{backend:?}");
+        }
+    }
+
+    #[test]
+    fn near_and_far_limit_hits() {
+        let voxel = Voxel::new(U8Vec3::ONE, VoxelKind::default());
+        let bb = IAabb::new(IVec3::ZERO, IVec3::splat(16));
+        let mut grid = VoxelGrid::new(IVec3::splat(16)).with_origin(IVec3::splat(-8));
+        for x in 0..16 {
+            for y in 0..16 {
+                for z in 0..16 {
+                    grid.set(IVec3::new(x, y, z), Some(voxel));
+                }
+            }
+        }
+
+        for backend in Backend::ALL {
+            let scene = DynScene::build(backend, &grid, bb);
+            for i in 0..20 {
+                let angle = i as f32 * 0.3;
+                let origin = Vec3A::new(30.0 * angle.cos(), 12.0, 30.0 * angle.sin());
+                let ray = Ray::new(origin, Vec3A::new(0.3, -0.2, 0.1) * (i % 3) as f32 - origin);
+                let hit = scene.trace_hit(ray, false).unwrap();
+
+                // nothing is hit past the far distance, however close
+                let short = Ray {
+                    far: hit.distance - 0.5,
+                    ..ray
+                };
+                assert_eq!(scene.trace_hit(short, false), None, "{backend:?} ray {i}");
+                let long = Ray {
+                    far: hit.distance + 0.5,
+                    ..ray
+                };
+                assert_eq!(
+                    scene.trace_hit(long, false),
+                    Some(hit),
+                    "{backend:?} ray {i}"
+                );
+
+                // and the block is solid, so starting inside it hits straight away
+                let near = hit.distance + 3.0;
+                let inside = scene.trace_hit(Ray { near, ..ray }, false).unwrap();
+                assert!((inside.distance - near).abs() < 1e-3, "{backend:?} ray {i}");
+            }
+        }
+    }
+
+    #[test]
+    fn double_matches_single() {
+        let source = VoxelGenerator::new_from_seed(3);
+        let bb = IAabb::new(IVec3::ZERO, IVec3::splat(16));
+
+        for backend in Backend::ALL {
+            let scene = DynScene::build(backend, &source, bb);
+            for i in 0..50 {
+                let angle = i as f32 * 0.4;
+                let origin = Vec3A::new(40.0 * angle.cos(), 25.0, 40.0 * angle.sin());
+                let target = Vec3A::new((i % 7) as f32 - 3.2, -4.0, (i % 5) as f32 - 2.2);
+                let ray = Ray::new(origin, target - origin);
+                let single = scene.trace_hit(ray, false);
+                let double = scene.trace_hit_f64(DRay::from(ray), false);
+                assert_eq!(
+                    double.map(|hit| hit.voxel),
+                    single.map(|hit| hit.voxel),
+                    "{backend:?} ray {i}"
+                );
+                if let (Some(single), Some(double)) = (single, double) {
+                    assert!(
+                        (single.distance - double.distance).abs() < 1e-3,
+                        "{backend:?} ray {i}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn double_traces_distant_scenes() {
+        // the same voxels around the origin and ten million voxels from it, where f32 positions are a voxel apart
+        let offset = IVec3::new(10_000_000, -3_000_000, 7_000_000);
+        let mut near = VoxelGrid::new(IVec3::splat(16)).with_origin(IVec3::splat(-8));
+        let mut far = VoxelGrid::new(IVec3::splat(16)).with_origin(offset - 8);
+        for x in 0..16 {
+            for y in 0..16 {
+                for z in 0..16 {
+                    if (7 * x + 3 * y + 5 * z) % 11 < 3 {
+                        let pos = IVec3::new(x, y, z);
+                        let voxel = Voxel::new((pos * 16).as_u8vec3(), VoxelKind::default());
+                        near.set(pos, Some(voxel));
+                        far.set(pos, Some(voxel));
+                    }
+                }
+            }
+        }
+
+        for backend in Backend::ALL {
+            let near_scene =
+                DynScene::build(backend, &near, IAabb::new(IVec3::ZERO, IVec3::splat(16)));
+            let far_scene = DynScene::build(backend, &far, IAabb::new(offset, IVec3::splat(16)));
+            let mut misses = 0;
+            for i in 0..50 {
+                let angle = i as f32 * 0.4;
+                let origin = Vec3A::new(40.0 * angle.cos(), 25.0, 40.0 * angle.sin());
+                let target = Vec3A::new((i % 7) as f32 - 3.2, -4.0, (i % 5) as f32 - 2.2);
+                let ray = Ray::new(origin, target - origin);
+                let expected = near_scene.trace(ray, false);
+
+                let distant = DRay {
+                    origin: ray.origin.as_dvec3() + offset.as_dvec3(),
+                    ..DRay::from(ray)
+                };
+                let hit = far_scene.trace_hit_f64(distant, false);
+                assert_eq!(hit.map(|hit| hit.voxel), expected, "{backend:?} ray {i}");
+
+                let rounded = Ray {
+                    origin: distant.origin.as_vec3a(),
+                    epsilon: 1.0,
+                    ..ray
+                };
+                misses += (far_scene.trace(rounded, false) != expected) as usize;
+            }
+            // while tracing in f32 gets some of them wrong
+            assert!(misses > 0, "{backend:?}");
+        }
+    }
+
+    #[test]
+    fn diagonals_have_no_pinholes() {
+        let voxel = Voxel::new(U8Vec3::ONE, VoxelKind::default());
+        let bb = IAabb::new(IVec3::ZERO, IVec3::splat(32));
+        // walls one voxel thick running diagonally across the xy plane, whose voxels only touch along their edges,
+        // offset so the edges fall on the boundaries of bricks, chunks and nodes as well as between them
+        for offset in [0, 1, 4, 16] {
+            let mut grid = VoxelGrid::new(IVec3::splat(64)).with_origin(IVec3::splat(-32));
+            for i in 0..64 {
+                for z in 0..64 {
+                    grid.set(IVec3::new(i, i - offset, z), Some(voxel));
+                }
+            }
+
+            for backend in Backend::ALL {
+                let scene = DynScene::build(backend, &grid, bb);
+                // rays from either side through the edges between the voxels, including ones lying on the plane
+                // between two layers of voxels and ones starting right next to the edge
+                for x in offset - 28..28 {
+                    for z in [-20.5, 7.3, 13.0] {
+                        let edge = Vec3A::new(x as f32, (x - offset) as f32, z);
+                        for dir in [
+                            Vec3A::new(1.0, -1.0, 0.0),
+                            Vec3A::new(-1.0, 1.0, 0.0),
+                            Vec3A::new(1.0, -1.0, 0.3),
+                            Vec3A::new(-1.0, 3.0, 0.0),
+                            Vec3A::new(2.0, -1.0, -0.1),
+                        ] {
+                            let dir = dir.normalize();
+                            for back in [60.0, 3.0, 0.5] {
+                                let ray = Ray::new(edge - back * dir, dir);
+                                assert!(
+                                    scene.trace(ray, false).is_some(),
+                                    "{backend:?} offset {offset} through {edge} along {dir} from {back}"
+                                );
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,289 @@
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+    sync::Mutex,
+};
+
+use glam::{IVec3, Vec3A};
+
+use super::Scene;
+use crate::voxel::{Voxel, VoxelKind, VoxelSource};
+
+/// How many times brighter than their color lava voxels are.
+const GLOW: f32 = 1.5;
+
+/// How many times brighter than their color crystal voxels are, dimmer than lava.
+const CRYSTAL_GLOW: f32 = 0.6;
+
+/// Width of the cells of the grid glowing voxels are grouped into, so the ones near a point can be found quickly.
+const CELL: i32 = 16;
+
+/// Cell of the grid of glowing voxels a voxel is in.
+fn cell(pos: IVec3) -> IVec3 {
+    pos.div_euclid(IVec3::splat(CELL))
+}
+
+/// Light given off by a voxel, from 0 to 1 or more, if it glows.
+pub fn emission(voxel: Voxel) -> Option<Vec3A> {
+    let glow = match voxel.kind {
+        VoxelKind::LAVA => GLOW,
+        VoxelKind::CRYSTAL => CRYSTAL_GLOW,
+        _ => return None,
+    };
+    Some(glow * voxel.color.as_vec3a() / 255.0)
+}
+
+/// The glowing voxels of a scene, which path tracing lights surfaces by, see
+/// [`RayTracer::set_emitters`](super::RayTracer::set_emitters).
+///
+/// Voxels buried in other glowing voxels can't light anything and are left out. The rest are grouped by the cells
+/// of a grid [`CELL`] voxels wide.
+#[derive(Clone, Debug, Default)]
+pub struct Emitters {
+    /// Glowing voxels, sorted by cell.
+    voxels: Vec<Emitter>,
+    positions: HashSet<IVec3>,
+    cells: HashMap<IVec3, Cell>,
+}
+
+/// A glowing voxel.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(super) struct Emitter {
+    pub pos: IVec3,
+    /// Light it gives off from every face, see [`emission`].
+    pub light: Vec3A,
+}
+
+/// Glowing voxels in a cell of the grid.
+#[derive(Clone, Debug, PartialEq)]
+pub(super) struct Cell {
+    /// Range of [`Emitters::voxels`] in the cell.
+    pub voxels: Range<usize>,
+    /// Corners of the box around the voxels.
+    pub min: Vec3A,
+    pub max: Vec3A,
+    /// Light given off by all of the voxels, added up over the channels.
+    pub power: f32,
+}
+
+impl Emitters {
+    /// Collects the glowing voxels out of a list of voxels and the cells they fill.
+    pub fn new(voxels: impl IntoIterator<Item = (IVec3, Voxel)>) -> Self {
+        let lights: HashMap<IVec3, Vec3A> = voxels
+            .into_iter()
+            .filter_map(|(pos, voxel)| Some((pos, emission(voxel)?)))
+            .collect();
+        let mut voxels: Vec<Emitter> = lights
+            .iter()
+            .filter(|(&pos, _)| {
+                [IVec3::X, IVec3::Y, IVec3::Z].into_iter().any(|axis| {
+                    !lights.contains_key(&(pos + axis)) || !lights.contains_key(&(pos - axis))
+                })
+            })
+            .map(|(&pos, &light)| Emitter { pos, light })
+            .collect();
+        voxels.sort_by_key(|emitter| {
+            let cell = cell(emitter.pos);
+            (
+                cell.x,
+                cell.y,
+                cell.z,
+                emitter.pos.x,
+                emitter.pos.y,
+                emitter.pos.z,
+            )
+        });
+
+        let mut cells = HashMap::new();
+        let mut start = 0;
+        for chunk in voxels.chunk_by(|a, b| cell(a.pos) == cell(b.pos)) {
+            let (min, max) = chunk.iter().fold(
+                (Vec3A::INFINITY, Vec3A::NEG_INFINITY),
+                |(min, max), emitter| {
+                    let pos = emitter.pos.as_vec3a();
+                    (min.min(pos), max.max(pos + 1.0))
+                },
+            );
+            let group = Cell {
+                voxels: start..start + chunk.len(),
+                min,
+                max,
+                power: chunk
+                    .iter()
+                    .map(|emitter| emitter.light.element_sum())
+                    .sum(),
+            };
+            cells.insert(cell(chunk[0].pos), group);
+            start += chunk.len();
+        }
+
+        Self {
+            positions: voxels.iter().map(|emitter| emitter.pos).collect(),
+            voxels,
+            cells,
+        }
+    }
+
+    /// Number of glowing voxels that can light anything.
+    pub fn len(&self) -> usize {
+        self.voxels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.voxels.is_empty()
+    }
+
+    /// Cells with glowing voxels in or next to the cell a point is in, with the position of each cell in the grid.
+    pub(super) fn near(&self, point: Vec3A) -> impl Iterator<Item = (IVec3, &Cell)> {
+        let center = cell(point.floor().as_ivec3());
+        let cells = (!self.cells.is_empty()).then_some(&self.cells);
+        (-1..=1)
+            .flat_map(|x| (-1..=1).flat_map(move |y| (-1..=1).map(move |z| IVec3::new(x, y, z))))
+            .filter_map(move |offset| {
+                let cell = center + offset;
+                Some((cell, cells?.get(&cell)?))
+            })
+    }
+
+    /// Cell of the grid a glowing voxel is in, if it is one of them.
+    pub(super) fn cell_of(&self, pos: IVec3) -> Option<(IVec3, &Cell)> {
+        if !self.positions.contains(&pos) {
+            return None;
+        }
+        let cell = cell(pos);
+        Some((cell, &self.cells[&cell]))
+    }
+
+    /// Cell at a position in the grid, see [`Emitters::near`].
+    pub(super) fn cell(&self, cell: IVec3) -> &Cell {
+        &self.cells[&cell]
+    }
+
+    /// Glowing voxels in a cell.
+    pub(super) fn voxels(&self, cell: &Cell) -> &[Emitter] {
+        &self.voxels[cell.voxels.clone()]
+    }
+}
+
+/// A source that keeps track of the glowing voxels looked up in another while a scene is built from it, to light
+/// the scene by them with [`Emitters`].
+pub struct Recorder<S> {
+    source: S,
+    found: Mutex<Vec<(IVec3, Voxel)>>,
+}
+
+impl<S: VoxelSource> Recorder<S> {
+    pub fn new(source: S) -> Self {
+        Self {
+            source,
+            found: Mutex::default(),
+        }
+    }
+
+    /// Glowing voxels found so far, in the cells of a scene built from the source, see [`Scene::cell`].
+    pub fn into_emitters(self, scene: &impl Scene) -> Emitters {
+        let found = self.found.into_inner().expect("recorder lock poisoned");
+        Emitters::new(
+            found
+                .into_iter()
+                .map(|(pos, voxel)| (scene.cell(pos), voxel)),
+        )
+    }
+}
+
+impl<S: VoxelSource> VoxelSource for Recorder<S> {
+    fn lookup(&self, pos: IVec3) -> Option<Voxel> {
+        let voxel = self.source.lookup(pos);
+        if let Some(voxel) = voxel.filter(|&voxel| emission(voxel).is_some()) {
+            self.found
+                .lock()
+                .expect("recorder lock poisoned")
+                .push((pos, voxel));
+        }
+        voxel
+    }
+
+    fn column(&self, x: i32, z: i32, ys: Range<i32>, out: &mut [Option<Voxel>]) {
+        self.source.column(x, z, ys.clone(), out);
+        let mut glowing = ys
+            .zip(out.iter())
+            .filter_map(|(y, voxel)| {
+                Some((
+                    IVec3::new(x, y, z),
+                    voxel.filter(|&voxel| emission(voxel).is_some())?,
+                ))
+            })
+            .peekable();
+        if glowing.peek().is_some() {
+            self.found
+                .lock()
+                .expect("recorder lock poisoned")
+                .extend(glowing);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::U8Vec3;
+
+    use super::*;
+    use crate::{
+        ray_tracer::{dense::DenseStorage, octree::SparseStorage, types::IAabb},
+        voxel::{grid::VoxelGrid, ore::CRYSTAL, LAVA},
+    };
+
+    #[test]
+    fn lava_and_crystals_glow() {
+        let lava = emission(LAVA).unwrap();
+        let crystal = emission(CRYSTAL).unwrap();
+        assert!(crystal.element_sum() > 0.0 && crystal.max_element() < lava.max_element());
+        // bluer than lava, like their color
+        assert!(crystal.z > crystal.x && lava.x > lava.z);
+        assert_eq!(emission(Voxel::new(U8Vec3::MAX, VoxelKind::GOLD)), None);
+    }
+
+    #[test]
+    fn emitters_are_recorded_while_building() {
+        // a pool of lava 4 wide and 3 deep next to stone
+        let stone = Voxel::new(U8Vec3::splat(100), VoxelKind::STONE);
+        let mut grid = VoxelGrid::new(IVec3::splat(20));
+        for x in 0..8 {
+            for z in 0..4 {
+                for y in 0..3 {
+                    let voxel = if x < 4 { LAVA } else { stone };
+                    grid.set(IVec3::new(x, y, z), Some(voxel));
+                }
+            }
+        }
+        let bb = IAabb::new(IVec3::splat(10), IVec3::splat(10));
+        let recorder = Recorder::new(&grid);
+        let dense = DenseStorage::from_voxels(&recorder, bb);
+        let emitters = recorder.into_emitters(&dense);
+
+        // the lava voxel surrounded by lava on every side is left out
+        assert_eq!(emitters.len(), 4 * 3 * 4 - 4);
+        assert!(emitters.cell_of(IVec3::new(1, 1, 1)).is_none());
+        let (cell, lava) = emitters.cell_of(IVec3::new(0, 0, 0)).unwrap();
+        assert_eq!(cell, IVec3::ZERO);
+        assert_eq!(lava.min, Vec3A::ZERO);
+        assert_eq!(lava.max, Vec3A::new(4.0, 3.0, 4.0));
+        assert!(emitters.cell_of(IVec3::new(5, 0, 0)).is_none());
+        assert_eq!(emitters.voxels(lava).len(), emitters.len());
+
+        // it is found from nearby cells but not far ones
+        assert_eq!(emitters.near(Vec3A::splat(20.0)).count(), 1);
+        assert_eq!(emitters.near(Vec3A::splat(40.0)).count(), 0);
+        assert_eq!(emitters.near(Vec3A::splat(-5.0)).count(), 1);
+
+        // storages that fill the cell below each position have them there
+        let recorder = Recorder::new(&grid);
+        let sparse = SparseStorage::from_voxels(&recorder, bb);
+        let emitters = recorder.into_emitters(&sparse);
+        assert!(emitters.cell_of(IVec3::splat(-1)).is_some());
+        assert_eq!(
+            emitters.cell_of(IVec3::splat(-1)).unwrap().1.min,
+            Vec3A::NEG_ONE
+        );
+    }
+}
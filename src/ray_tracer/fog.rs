@@ -0,0 +1,370 @@
+use std::ops::{Add, Mul};
+
+use glam::{DVec3, IVec3, Vec3A};
+use noise::{NoiseFn, Perlin};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+use super::{
+    clouds::CloudHit,
+    types::{IAabb, Ray},
+    RayTracer, Scene, AMBIENT,
+};
+
+/// Settings for fog lying in the scene, mist in its valleys and haze in its caves.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FogSettings {
+    /// Share of the light that a voxel of the thickest mist stops, from 0 to 1.
+    pub density: f32,
+    /// Height in voxels up to which mist fills the open air, thinning out over the top quarter of it.
+    pub altitude: f32,
+    /// Share of the density of mist that haze has, filling the air under a roof, such as in caves, at any height.
+    pub haze: f32,
+    /// Rough width of a bank of fog in voxels.
+    pub scale: f32,
+}
+
+impl Default for FogSettings {
+    fn default() -> Self {
+        Self {
+            density: 0.04,
+            altitude: 48.0,
+            haze: 0.5,
+            scale: 32.0,
+        }
+    }
+}
+
+/// Fog of 3D noise held in a grid of cells [`CELL`] voxels wide over the scene, traced through rather than stored as
+/// voxels.
+///
+/// Each cell has a density, interpolated between their centers, and the light reaching it from each of the scene's
+/// lights, found once for the grid rather than for every ray. Rays are marched through it, with each step hiding some
+/// of what lies behind and adding the light it scatters toward the ray.
+#[derive(Clone, Debug)]
+pub struct Fog {
+    settings: FogSettings,
+    /// Corner of the grid in the space rays are traced in.
+    min: IVec3,
+    /// Cells along each side of the grid.
+    size: IVec3,
+    /// Box the grid covers in the space rays are traced in.
+    bounds: IAabb,
+    /// Share of the light a voxel of fog in each cell stops, with the cells ordered by height, then z, then x.
+    density: Vec<f32>,
+    /// Whether the voxel at the center of each cell is empty, where the light reaching it is found, as solid cells
+    /// take the light of the cell above.
+    open: Vec<bool>,
+    /// Light reaching each cell, from 0 to 1 or more for each channel, times its density, so it is blended between
+    /// cells by how much fog they hold.
+    light: Vec<Vec3A>,
+}
+
+impl Fog {
+    pub fn settings(&self) -> FogSettings {
+        self.settings
+    }
+
+    /// Share of the light a voxel of fog at a point stops, zero outside of the grid.
+    pub fn density(&self, point: Vec3A) -> f32 {
+        self.interpolate(&self.density, point)
+    }
+
+    /// Share of the light that gets through the fog from direction `dir` to a point, such as from the sun to the fog
+    /// below it.
+    pub fn transmittance(&self, point: Vec3A, dir: Vec3A) -> f32 {
+        let ray = Ray::new(point, dir);
+        let Some(range) = self.bounds.intersection(ray, 0.0..f32::INFINITY) else {
+            return 1.0;
+        };
+        let steps = ((range.end - range.start) / CELL as f32).ceil();
+        let step = (range.end - range.start) / steps;
+        (0..steps as usize)
+            .map(|i| {
+                let density = self.density(point + (range.start + (i as f32 + 0.5) * step) * dir);
+                (1.0 - density).powf(step)
+            })
+            .product()
+    }
+
+    /// Fog along a ray up to `far`, such as where it hit the scene, with the light each step of it scatters toward the
+    /// ray evenly in every direction.
+    pub fn trace(&self, ray: &Ray, far: f32) -> CloudHit {
+        let Some(range) = self.bounds.intersection(*ray, ray.near..far) else {
+            return CloudHit::default();
+        };
+        let steps = ((range.end - range.start) / STEP).ceil();
+        let step = (range.end - range.start) / steps;
+        let mut hit = CloudHit::default();
+        for i in 0..steps as usize {
+            let point = ray.origin + (range.start + (i as f32 + 0.5) * step) * ray.dir;
+            let density = self.density(point);
+            if density == 0.0 {
+                continue;
+            }
+            let opacity = (1.0 - (1.0 - density).powf(step)) * (1.0 - hit.opacity);
+            let light = self.interpolate(&self.light, point) / density;
+            hit.color += light * FOG_COLOR * opacity;
+            hit.opacity += opacity;
+            if hit.opacity > OPAQUE {
+                break;
+            }
+        }
+        hit
+    }
+
+    /// Position in the grid of cell `cell`, ordered by height, then z, then x.
+    fn index(&self, cell: IVec3) -> usize {
+        ((cell.y * self.size.z + cell.z) * self.size.x + cell.x) as usize
+    }
+
+    /// Value at a point of one held for each cell, blended between the centers of the eight cells around it, with
+    /// cells outside of the grid counting as the default.
+    fn interpolate<V>(&self, values: &[V], point: Vec3A) -> V
+    where
+        V: Copy + Default + Add<Output = V> + Mul<f32, Output = V>,
+    {
+        let pos = (point - self.min.as_vec3a()) / CELL as f32 - 0.5;
+        let corner = pos.floor();
+        let t = pos - corner;
+        let corner = corner.as_ivec3();
+        let mut sum = V::default();
+        for i in 0..8 {
+            let offset = IVec3::new(i & 1, (i >> 1) & 1, i >> 2);
+            let cell = corner + offset;
+            if cell.cmplt(IVec3::ZERO).any() || cell.cmpge(self.size).any() {
+                continue;
+            }
+            let weight = 1.0 - (offset.as_vec3a() - t).abs();
+            sum = sum + values[self.index(cell)] * weight.x * weight.y * weight.z;
+        }
+        sum
+    }
+}
+
+impl<T: Scene + Sync> RayTracer<T> {
+    /// Builds the fog for settings over the scene's bounds, lit by the scene's lights, see [`Fog`].
+    ///
+    /// Each column of cells is walked down from the top, looking up the voxel at the center of each cell. Empty cells
+    /// under open sky hold mist up to [`FogSettings::altitude`], and those under a solid one hold haze. Both come in
+    /// patches where noise is high. Solid cells take the density of the cell above them, so the fog doesn't thin
+    /// out toward the ground as it is blended between their centers.
+    pub(super) fn build_fog(&self, settings: FogSettings) -> Fog {
+        let bounds = self.config.bounds();
+        let size = (bounds.max() - bounds.min() + CELL - 1) / CELL;
+        let noise = Perlin::new(self.config.seed.unwrap_or_default().wrapping_add(FOG_SEED));
+        let columns: Vec<Vec<(f32, bool)>> = (0..size.x * size.z)
+            .into_par_iter()
+            .map(|i| {
+                let column = IVec3::new(i % size.x, 0, i / size.x);
+                let mut roofed = false;
+                let mut density = 0.0;
+                let mut cells: Vec<_> = (0..size.y)
+                    .rev()
+                    .map(|y| {
+                        let center = bounds.min() + column.with_y(y) * CELL + CELL / 2;
+                        let open = self.scene.voxel_at(self.scene.cell(center)).is_none();
+                        if open {
+                            let center = center.as_vec3a();
+                            let thickness = if roofed {
+                                settings.haze
+                            } else {
+                                ((settings.altitude - center.y) / (settings.altitude / 4.0))
+                                    .clamp(0.0, 1.0)
+                            };
+                            density =
+                                settings.density * thickness * patch(&noise, &settings, center);
+                        }
+                        roofed |= !open;
+                        (density, open)
+                    })
+                    .collect();
+                cells.reverse();
+                cells
+            })
+            .collect();
+
+        // only the heights with fog in empty cells are kept, and one more cell above and below to blend into
+        let foggy = |y: i32| {
+            columns.iter().any(|cells| {
+                let (density, open) = cells[y as usize];
+                density > 0.0 && open
+            })
+        };
+        let low = (0..size.y)
+            .find(|&y| foggy(y))
+            .map_or(0, |y| (y - 1).max(0));
+        let high = (0..size.y)
+            .rev()
+            .find(|&y| foggy(y))
+            .map_or(0, |y| (y + 2).min(size.y));
+        let size = size.with_y((high - low).max(1));
+        let (density, open) = (low..low + size.y)
+            .flat_map(|y| columns.iter().map(move |cells| cells[y as usize]))
+            .unzip();
+        let min = self.scene.cell(bounds.min()) + IVec3::Y * low * CELL;
+        let half = size * CELL / 2;
+        let fog = Fog {
+            settings,
+            min,
+            size,
+            bounds: IAabb::new(min + half, half),
+            density,
+            open,
+            light: Vec::new(),
+        };
+        self.light_fog(fog)
+    }
+
+    /// Finds the light reaching each cell of fog from the scene's lights, such as after they change.
+    ///
+    /// Each light is traced to from the center of the cell, darkened by the fog in between, and only [`AMBIENT`] of
+    /// it reaches cells the scene hides it from, as with faces turned away from it when shading, see
+    /// [`RayTracer::light_through`]. Clouds shadow directional lights, as they do the ground.
+    pub(super) fn light_fog(&self, mut fog: Fog) -> Fog {
+        let layer = (fog.size.x * fog.size.z) as usize;
+        let mut light: Vec<Vec3A> = (0..fog.density.len())
+            .into_par_iter()
+            .map(|i| {
+                if !fog.open[i] || fog.density[i] == 0.0 {
+                    return Vec3A::ZERO;
+                }
+                let cell = IVec3::new(
+                    (i % layer) as i32 % fog.size.x,
+                    (i / layer) as i32,
+                    (i % layer) as i32 / fog.size.x,
+                );
+                let point = (fog.min + cell * CELL + CELL / 2).as_vec3a() + 0.5;
+                self.incoming(point)
+                    .map(|incoming| {
+                        let mut lit = self.light_through(point, incoming.dir, incoming.distance);
+                        if lit != Vec3A::ZERO {
+                            lit *= fog.transmittance(point, incoming.dir);
+                        }
+                        (AMBIENT + (1.0 - AMBIENT) * lit)
+                            * self.cloud_light(point, &incoming)
+                            * incoming.color
+                    })
+                    .sum::<Vec3A>()
+                    * fog.density[i]
+            })
+            .collect();
+
+        // solid cells take the light of the cell above them, as they took its density
+        for i in (0..light.len().saturating_sub(layer)).rev() {
+            if !fog.open[i] {
+                light[i] = light[i + layer];
+            }
+        }
+        fog.light = light;
+        fog
+    }
+}
+
+/// How much of its density fog has at a point, from 0 to 1, in patches where two octaves of noise are high, so banks
+/// of fog have gaps between them.
+fn patch(noise: &Perlin, settings: &FogSettings, point: Vec3A) -> f32 {
+    // stretched out sideways, so banks of fog are wider than they are tall
+    let p = (point / settings.scale).as_dvec3() * DVec3::new(1.0, 2.0, 1.0);
+    let noise = noise.get(p.to_array()) + 0.5 * noise.get((2.0 * p + 31.0).to_array());
+    let noise = (noise / 1.5 + 1.0) as f32 / 2.0;
+    ((noise - PATCHY) / (1.0 - 2.0 * PATCHY)).clamp(0.0, 1.0)
+}
+
+/// Width, in voxels, of the cells of the fog's grid.
+const CELL: i32 = 4;
+
+/// Length, in voxels, of a step along a ray through the fog.
+const STEP: f32 = 2.0;
+
+/// Opacity past which a ray stops marching through the fog.
+const OPAQUE: f32 = 0.99;
+
+/// Color, from 0 to 255, of fog in full light.
+const FOG_COLOR: Vec3A = Vec3A::new(225.0, 230.0, 240.0);
+
+/// Value of the fog's noise, from 0 to 1, below which there is no fog, and above one less than which there is all of
+/// it.
+const PATCHY: f32 = 0.4;
+
+/// Offset of the fog's noise's seed from the terrain's, so fog doesn't follow the hills.
+const FOG_SEED: u32 = 13;
+
+#[cfg(test)]
+mod tests {
+    use glam::U8Vec3;
+
+    use super::*;
+    use crate::{
+        ray_tracer::{dense::DenseStorage, light::Light, Config},
+        voxel::{grid::VoxelGrid, Voxel, VoxelKind},
+    };
+
+    /// Fog over ground below -8, with a roof from 0 to 4 over the half of the scene where x is negative, so there is
+    /// a cave under it, lit from straight above.
+    fn fog(haze: f32) -> Fog {
+        let stone = Voxel::new(U8Vec3::splat(128), VoxelKind::STONE);
+        let mut grid = VoxelGrid::new(IVec3::splat(32)).with_origin(IVec3::splat(-16));
+        for x in -16..16 {
+            for z in -16..16 {
+                for y in -16..-8 {
+                    grid.set(IVec3::new(x, y, z) + 16, Some(stone));
+                }
+                for y in (0..4).filter(|_| x < 0) {
+                    grid.set(IVec3::new(x, y, z) + 16, Some(stone));
+                }
+            }
+        }
+        let config = Config {
+            size: 16,
+            fog: Some(FogSettings {
+                density: 0.2,
+                altitude: 8.0,
+                haze,
+                // so wide that the noise is the same everywhere
+                scale: 1e6,
+            }),
+            ..Default::default()
+        };
+        let mut ray_tracer = RayTracer::<DenseStorage>::from_source(config, &grid);
+        ray_tracer.set_lights(vec![Light::directional(Vec3A::Y)]);
+        ray_tracer.fog.unwrap()
+    }
+
+    #[test]
+    fn mist_fills_valleys_and_haze_fills_caves() {
+        let fog = fog(0.25);
+        let mist = fog.density(Vec3A::new(10.0, -6.0, 2.0));
+        assert!(mist > 0.0 && mist <= 0.2);
+        assert!((fog.density(Vec3A::new(-10.0, -6.0, 2.0)) - 0.25 * mist).abs() < 1e-6);
+
+        // mist thins out toward its altitude, and none is left above it
+        let thin = fog.density(Vec3A::new(10.0, 8.0, 2.0));
+        assert!((thin - 0.5 * mist).abs() < 1e-6);
+        assert_eq!(fog.density(Vec3A::new(10.0, 14.0, 2.0)), 0.0);
+        assert_eq!(fog.density(Vec3A::new(10.0, -6.0, 40.0)), 0.0);
+        assert!(fog.transmittance(Vec3A::new(10.0, -6.0, 2.0), Vec3A::Y) < 1.0);
+    }
+
+    #[test]
+    fn fog_hides_what_is_behind_it_and_is_darker_in_caves() {
+        // as thick in the cave as outside, but only lit by the ambient share of the light there
+        let fog = fog(1.0);
+        let trace = |x: f32| {
+            let ray = Ray::new(Vec3A::new(x, -6.0, -16.0), Vec3A::Z);
+            fog.trace(&ray, 32.0)
+        };
+        let (open, cave) = (trace(10.0), trace(-10.0));
+        assert!(open.opacity > 0.5 && open.opacity < 1.0);
+        assert!((cave.opacity - open.opacity).abs() < 1e-4);
+        let brightness = |hit: CloudHit| hit.color.x / hit.opacity;
+        assert!((brightness(cave) - AMBIENT * FOG_COLOR.x).abs() < 1.0);
+        // the fog above takes some of the light on the way down
+        assert!(brightness(open) > brightness(cave) + 10.0 && brightness(open) < FOG_COLOR.x);
+
+        // and nothing beyond where the ray hit
+        assert_eq!(
+            fog.trace(&Ray::new(Vec3A::new(10.0, -6.0, -16.0), Vec3A::Z), 0.0),
+            CloudHit::default()
+        );
+    }
+}
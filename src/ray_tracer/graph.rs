@@ -0,0 +1,594 @@
+use std::{mem, sync::OnceLock};
+
+use glam::{BVec3A, IVec3, Mat3A, Quat, Vec3A};
+
+use crate::voxel::VoxelSource;
+
+use super::{
+    cache::CacheStats,
+    types::{Hit, IAabb, Ray},
+    MemoryUsage, Scene,
+};
+
+/// Placement of an object in a [`SceneGraph`]: a rotation about the object's origin followed by a translation.
+///
+/// Transforms are rigid, so distances along a ray are the same in the scene and in each object.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform {
+    /// Rotation from the scene into the object, the inverse of the object's rotation.
+    inverse: Mat3A,
+    translation: Vec3A,
+}
+
+impl Transform {
+    /// Leaves the object where its voxels are.
+    pub const IDENTITY: Self = Self {
+        inverse: Mat3A::IDENTITY,
+        translation: Vec3A::ZERO,
+    };
+
+    /// Moves the object by a number of voxels and turns it by any rotation.
+    pub fn new(translation: Vec3A, rotation: Quat) -> Self {
+        Self {
+            inverse: Mat3A::from_quat(rotation.normalize().inverse()),
+            translation,
+        }
+    }
+
+    /// Moves the object by a number of voxels and turns it by multiples of 90° around the y-axis, counterclockwise
+    /// seen from above.
+    ///
+    /// Unlike [`Transform::new`] the rotation is exact, so voxels stay lined up with the grid.
+    pub fn quarter_turns(translation: IVec3, turns: i32) -> Self {
+        let (sin, cos) =
+            [(0.0, 1.0), (1.0, 0.0), (0.0, -1.0), (-1.0, 0.0)][turns.rem_euclid(4) as usize];
+        // the inverse turns the other way
+        Self {
+            inverse: Mat3A::from_cols(
+                Vec3A::new(cos, 0.0, sin),
+                Vec3A::Y,
+                Vec3A::new(-sin, 0.0, cos),
+            ),
+            translation: translation.as_vec3a(),
+        }
+    }
+
+    /// Box in the scene around a box in the object's coordinates.
+    fn scene_bounds(self, bounds: IAabb) -> IAabb {
+        let rotation = self.inverse.transpose();
+        let (min, max) = (bounds.min().as_vec3a(), bounds.max().as_vec3a());
+        let corners = (0..8).map(|i| {
+            let corner = Vec3A::select(BVec3A::new(i & 1 != 0, i & 2 != 0, i & 4 != 0), max, min);
+            rotation * corner + self.translation
+        });
+        let (low, high) = corners.fold(
+            (Vec3A::INFINITY, Vec3A::NEG_INFINITY),
+            |(low, high), corner| (low.min(corner), high.max(corner)),
+        );
+        IAabb::from_corners(low.floor().as_ivec3(), high.ceil().as_ivec3() - 1)
+    }
+
+    /// Moves a ray from the scene into the object's own coordinates.
+    fn object_ray(self, ray: Ray) -> Ray {
+        Ray {
+            origin: self.inverse * (ray.origin - self.translation),
+            dir: self.inverse * ray.dir,
+            ..ray
+        }
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+/// Several scenes placed side by side or inside each other, each moved and turned by its own [`Transform`].
+///
+/// Used to put imported props into generated terrain without collecting them into one storage. Each scene is a model
+/// that can be placed any number of times, so a forest of one tree model only stores the tree once. Rays are moved
+/// into the coordinates of every instance whose bounding box they pass through and traced there, and the nearest hit
+/// is kept. Instances are found through a bounding volume hierarchy built the first time a ray is traced, and ones
+/// entered past a hit that was already found are skipped.
+pub struct SceneGraph<T> {
+    models: Vec<Model<T>>,
+    instances: Vec<Instance>,
+    /// Built from the instances with bounds when first needed, and dropped whenever an instance is added.
+    bvh: OnceLock<Bvh>,
+}
+
+/// Handle to a scene added to a [`SceneGraph`], for placing more instances of it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ModelId(usize);
+
+struct Model<T> {
+    scene: T,
+    /// Box around every cell the scene's voxels may fill in its own coordinates, or `None` for scenes without an
+    /// end.
+    bounds: Option<IAabb>,
+}
+
+#[derive(Clone, Copy)]
+struct Instance {
+    model: usize,
+    transform: Transform,
+}
+
+impl<T: Scene> SceneGraph<T> {
+    /// Creates a scene without any objects.
+    pub fn new() -> Self {
+        Self {
+            models: Vec::new(),
+            instances: Vec::new(),
+            bvh: OnceLock::new(),
+        }
+    }
+
+    /// Adds a scene built from the voxels inside of a bounding box, and places it by a transform.
+    pub fn add(&mut self, scene: T, bb: IAabb, transform: Transform) -> ModelId {
+        // storages differ in which side of each position its voxel fills, so allow for both
+        let bounds = Some(IAabb::new(bb.origin, bb.extents + 1));
+        self.add_model(Model { scene, bounds }, transform)
+    }
+
+    /// Adds a scene that reaches past any bounding box, such as infinite terrain, so every ray is traced through it.
+    pub fn add_unbounded(&mut self, scene: T, transform: Transform) -> ModelId {
+        self.add_model(
+            Model {
+                scene,
+                bounds: None,
+            },
+            transform,
+        )
+    }
+
+    /// Builds a scene from the voxels of a source inside of a bounding box, and adds it placed by a transform.
+    pub fn add_voxels<S: VoxelSource + ?Sized>(
+        &mut self,
+        source: &S,
+        bb: IAabb,
+        transform: Transform,
+    ) -> ModelId {
+        self.add(T::from_voxels(source, bb), bb, transform)
+    }
+
+    /// Places another instance of a scene that was already added, sharing its voxels.
+    pub fn place(&mut self, model: ModelId, transform: Transform) {
+        self.instances.push(Instance {
+            model: model.0,
+            transform,
+        });
+        self.bvh = OnceLock::new();
+    }
+
+    fn add_model(&mut self, model: Model<T>, transform: Transform) -> ModelId {
+        self.models.push(model);
+        let id = ModelId(self.models.len() - 1);
+        self.place(id, transform);
+        id
+    }
+
+    /// Number of instances in the scene.
+    pub fn len(&self) -> usize {
+        self.instances.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instances.is_empty()
+    }
+
+    /// Number of distinct scenes the instances share.
+    pub fn model_count(&self) -> usize {
+        self.models.len()
+    }
+
+    /// Traces a ray through one instance, keeping its hit if it is nearer than the nearest so far.
+    fn trace_instance(&self, instance: Instance, ray: Ray, debug: bool, nearest: &mut Option<Hit>) {
+        let model = &self.models[instance.model];
+        let local = instance.transform.object_ray(ray);
+        let entry = match model.bounds {
+            Some(bounds) => match bounds.intersection(local, ray.near..ray.far) {
+                Some(range) => range.start,
+                None => return,
+            },
+            None => 0.0,
+        };
+        if nearest.is_some_and(|hit| hit.distance <= entry) {
+            return;
+        }
+
+        let Some(hit) = model.scene.trace_hit(local, debug) else {
+            return;
+        };
+        if nearest.is_none_or(|nearest| hit.distance < nearest.distance) {
+            *nearest = Some(hit);
+        }
+    }
+
+    fn bvh(&self) -> &Bvh {
+        self.bvh.get_or_init(|| {
+            let bounded = self
+                .instances
+                .iter()
+                .enumerate()
+                .filter_map(|(i, instance)| {
+                    let bounds = self.models[instance.model].bounds?;
+                    Some((i as u32, instance.transform.scene_bounds(bounds)))
+                });
+            Bvh::new(bounded.collect())
+        })
+    }
+}
+
+impl<T: Scene> Default for SceneGraph<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Scene> Scene for SceneGraph<T> {
+    fn from_voxels<S: VoxelSource + ?Sized>(source: &S, bb: IAabb) -> Self {
+        let mut graph = Self::new();
+        graph.add_voxels(source, bb, Transform::IDENTITY);
+        graph
+    }
+
+    fn trace_hit(&self, ray: Ray, debug: bool) -> Option<Hit> {
+        let mut nearest = None;
+        for &instance in &self.instances {
+            if self.models[instance.model].bounds.is_none() {
+                self.trace_instance(instance, ray, debug, &mut nearest);
+            }
+        }
+
+        let bvh = self.bvh();
+        let Some(root) = bvh.nodes.first() else {
+            return nearest;
+        };
+        let Some(range) = root.bounds.intersection(ray, ray.near..ray.far) else {
+            return nearest;
+        };
+
+        // nodes to visit along with where the ray enters them, nearest on top
+        let mut stack = [(0, 0.0); BVH_STACK];
+        stack[0] = (0, range.start);
+        let mut len = 1;
+        while len > 0 {
+            len -= 1;
+            let (i, entry) = stack[len];
+            if nearest.is_some_and(|hit: Hit| hit.distance <= entry) {
+                continue;
+            }
+
+            let node = bvh.nodes[i];
+            if node.count > 0 {
+                let instances = &bvh.instances[node.start as usize..][..node.count as usize];
+                for &instance in instances {
+                    self.trace_instance(
+                        self.instances[instance as usize],
+                        ray,
+                        debug,
+                        &mut nearest,
+                    );
+                }
+                continue;
+            }
+
+            let children = [node.start as usize, node.start as usize + 1].map(|child| {
+                let range = bvh.nodes[child].bounds.intersection(ray, ray.near..ray.far);
+                (child, range.map(|range| range.start))
+            });
+            let [near, far] = match (children[0].1, children[1].1) {
+                (Some(first), Some(second)) if second < first => [children[1], children[0]],
+                _ => children,
+            };
+            for (child, entry) in [far, near] {
+                if let Some(entry) = entry {
+                    stack[len] = (child, entry);
+                    len += 1;
+                }
+            }
+        }
+        nearest
+    }
+
+    fn memory_usage(&self) -> MemoryUsage {
+        let bvh = self.bvh();
+        let graph = MemoryUsage {
+            overhead_bytes: self.models.capacity() * mem::size_of::<Model<T>>()
+                + self.instances.capacity() * mem::size_of::<Instance>()
+                + bvh.nodes.capacity() * mem::size_of::<BvhNode>()
+                + bvh.instances.capacity() * mem::size_of::<u32>(),
+            ..Default::default()
+        };
+        // each model counts once, however many times it is placed
+        self.models
+            .iter()
+            .map(|model| model.scene.memory_usage())
+            .fold(graph, |total, usage| MemoryUsage {
+                nodes: total.nodes + usage.nodes,
+                voxel_bytes: total.voxel_bytes + usage.voxel_bytes,
+                overhead_bytes: total.overhead_bytes + usage.overhead_bytes,
+                palette_bytes: total.palette_bytes + usage.palette_bytes,
+            })
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        self.models
+            .iter()
+            .filter_map(|model| model.scene.cache_stats())
+            .reduce(|total, stats| CacheStats {
+                hits: total.hits + stats.hits,
+                misses: total.misses + stats.misses,
+                evictions: total.evictions + stats.evictions,
+            })
+    }
+}
+
+/// Most instances in a leaf of the hierarchy.
+const BVH_LEAF: usize = 4;
+/// Nodes a traversal can have waiting, enough for the depth of any hierarchy of halved instance lists.
+const BVH_STACK: usize = 64;
+
+/// Bounding volume hierarchy over the instances of a [`SceneGraph`] that have bounds.
+///
+/// Built by splitting the instances in half along the longest axis of their centers, until few enough are left for
+/// a leaf.
+struct Bvh {
+    /// Nodes with the root first and the children of each branch next to each other.
+    nodes: Vec<BvhNode>,
+    /// Indices of instances, with the ones in each leaf next to each other.
+    instances: Vec<u32>,
+}
+
+#[derive(Clone, Copy)]
+struct BvhNode {
+    /// Box around every instance below the node, in the scene's coordinates.
+    bounds: IAabb,
+    /// First child of a branch, or first instance of a leaf.
+    start: u32,
+    /// Number of instances of a leaf, or zero for branches.
+    count: u32,
+}
+
+impl Bvh {
+    /// Builds a hierarchy over instances and their boxes in the scene.
+    fn new(mut instances: Vec<(u32, IAabb)>) -> Self {
+        let mut bvh = Self {
+            nodes: Vec::new(),
+            instances: Vec::with_capacity(instances.len()),
+        };
+        if !instances.is_empty() {
+            bvh.nodes.push(BvhNode {
+                bounds: instances[0].1,
+                start: 0,
+                count: 0,
+            });
+            bvh.split(0, &mut instances);
+        }
+        bvh
+    }
+
+    /// Fills in a node for a list of instances, splitting it into two children if it is too long for a leaf.
+    fn split(&mut self, node: usize, instances: &mut [(u32, IAabb)]) {
+        let (min, max) = instances
+            .iter()
+            .fold((IVec3::MAX, IVec3::MIN), |(min, max), (_, bounds)| {
+                (min.min(bounds.min()), max.max(bounds.max()))
+            });
+        self.nodes[node].bounds = IAabb::from_corners(min, max - 1);
+
+        if instances.len() <= BVH_LEAF {
+            self.nodes[node].start = self.instances.len() as u32;
+            self.nodes[node].count = instances.len() as u32;
+            self.instances.extend(instances.iter().map(|(i, _)| *i));
+            return;
+        }
+
+        // the origins are the centers of the boxes, doubled to stay whole
+        let (low, high) = instances
+            .iter()
+            .fold((IVec3::MAX, IVec3::MIN), |(low, high), (_, bounds)| {
+                (low.min(bounds.origin), high.max(bounds.origin))
+            });
+        let spread = high - low;
+        let axis = if spread.x >= spread.y && spread.x >= spread.z {
+            0
+        } else if spread.y >= spread.z {
+            1
+        } else {
+            2
+        };
+        let half = instances.len() / 2;
+        instances.select_nth_unstable_by_key(half, |(_, bounds)| bounds.origin[axis]);
+
+        let first = self.nodes.len();
+        self.nodes[node].start = first as u32;
+        self.nodes.extend([self.nodes[node]; 2]);
+        let (left, right) = instances.split_at_mut(half);
+        self.split(first, left);
+        self.split(first + 1, right);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_2;
+
+    use glam::U8Vec3;
+
+    use super::*;
+    use crate::{
+        ray_tracer::{dense::DenseStorage, octree::SparseStorage},
+        voxel::{grid::VoxelGrid, Voxel},
+    };
+
+    /// A bar of two voxels along x, at the origin.
+    fn bar(voxel: Voxel) -> VoxelGrid {
+        let mut grid = VoxelGrid::new(IVec3::new(2, 1, 1));
+        grid.set(IVec3::ZERO, Some(voxel));
+        grid.set(IVec3::X, Some(voxel));
+        grid
+    }
+
+    fn bar_bounds() -> IAabb {
+        IAabb::new(IVec3::splat(2), IVec3::splat(2))
+    }
+
+    #[test]
+    fn keeps_nearest_hit() {
+        let red = Voxel::from(U8Vec3::new(255, 0, 0));
+        let blue = Voxel::from(U8Vec3::new(0, 0, 255));
+        let mut graph = SceneGraph::<DenseStorage>::new();
+        graph.add_voxels(&bar(red), bar_bounds(), Transform::IDENTITY);
+        graph.add_voxels(
+            &bar(blue),
+            bar_bounds(),
+            Transform::quarter_turns(IVec3::new(10, 0, 0), 0),
+        );
+        assert_eq!(graph.len(), 2);
+
+        // the bars cover x = 0..2 and x = 10..12, and each side sees the nearer one
+        let ray = Ray::new(Vec3A::new(20.0, 0.5, 0.5), Vec3A::NEG_X);
+        let hit = graph.trace_hit(ray, false).expect("voxel not found");
+        assert_eq!(hit.voxel, blue);
+        assert!((hit.distance - 8.0).abs() < 1e-3);
+
+        let ray = Ray::new(Vec3A::new(-10.0, 0.5, 0.5), Vec3A::X);
+        let hit = graph.trace_hit(ray, false).expect("voxel not found");
+        assert_eq!(hit.voxel, red);
+        assert!((hit.distance - 10.0).abs() < 1e-3);
+
+        let ray = Ray::new(Vec3A::new(5.0, 5.0, 0.5), Vec3A::NEG_Y);
+        assert_eq!(graph.trace_hit(ray, false), None);
+    }
+
+    #[test]
+    fn rotates_objects() {
+        let red = Voxel::from(U8Vec3::new(255, 0, 0));
+        let down = |x: f32, z: f32| Ray::new(Vec3A::new(x, 5.0, z), Vec3A::NEG_Y);
+
+        // a quarter turn points the bar from +x to -z
+        let mut graph = SceneGraph::<DenseStorage>::new();
+        graph.add_voxels(
+            &bar(red),
+            bar_bounds(),
+            Transform::quarter_turns(IVec3::ZERO, 1),
+        );
+        assert_eq!(graph.trace(down(0.5, -1.5), false), Some(red));
+        assert_eq!(graph.trace(down(1.5, 0.5), false), None);
+
+        // and a general rotation by the same angle puts it in the same place
+        let mut turned = SceneGraph::<DenseStorage>::new();
+        turned.add_voxels(
+            &bar(red),
+            bar_bounds(),
+            Transform::new(Vec3A::ZERO, Quat::from_rotation_y(FRAC_PI_2)),
+        );
+        for (x, z) in [(0.5, -1.5), (0.5, -0.5), (1.5, 0.5), (-0.5, 0.5)] {
+            assert_eq!(
+                turned.trace(down(x, z), false),
+                graph.trace(down(x, z), false)
+            );
+        }
+
+        // four turns come back around
+        let mut around = SceneGraph::<DenseStorage>::new();
+        around.add_voxels(
+            &bar(red),
+            bar_bounds(),
+            Transform::quarter_turns(IVec3::ZERO, 4),
+        );
+        assert_eq!(around.trace(down(1.5, 0.5), false), Some(red));
+    }
+
+    #[test]
+    fn matches_single_scene() {
+        let source = crate::voxel::VoxelGenerator::new_from_seed(3);
+        let bb = IAabb::new(IVec3::ZERO, IVec3::splat(16));
+        let graph = SceneGraph::<SparseStorage>::from_voxels(&source, bb);
+        let sparse = SparseStorage::from_voxels(&source, bb);
+
+        for i in 0..100 {
+            let angle = i as f32 * 0.2;
+            let origin = Vec3A::new(40.0 * angle.cos(), 30.0, 40.0 * angle.sin());
+            let ray = Ray::new(origin, -origin);
+            assert_eq!(
+                graph.trace_hit(ray, false),
+                sparse.trace_hit(ray, false),
+                "ray {i}"
+            );
+        }
+    }
+
+    #[test]
+    fn shares_instanced_models() {
+        let red = Voxel::from(U8Vec3::new(255, 0, 0));
+        let mut graph = SceneGraph::<DenseStorage>::new();
+        let model = graph.add_voxels(&bar(red), bar_bounds(), Transform::IDENTITY);
+        for z in 1..4 {
+            graph.place(model, Transform::quarter_turns(IVec3::new(0, 0, 10 * z), z));
+        }
+        assert_eq!(graph.len(), 4);
+        assert_eq!(graph.model_count(), 1);
+
+        let single = DenseStorage::from_voxels(&bar(red), bar_bounds());
+        assert_eq!(
+            graph.memory_usage().voxel_bytes,
+            single.memory_usage().voxel_bytes
+        );
+
+        // every instance is drawn, each turned its own way
+        let down = |x: f32, z: f32| Ray::new(Vec3A::new(x, 5.0, z), Vec3A::NEG_Y);
+        assert_eq!(graph.trace(down(1.5, 0.5), false), Some(red));
+        assert_eq!(graph.trace(down(0.5, 8.5), false), Some(red));
+        assert_eq!(graph.trace(down(-1.5, 19.5), false), Some(red));
+        assert_eq!(graph.trace(down(-0.5, 31.5), false), Some(red));
+        assert_eq!(graph.trace(down(1.5, 10.5), false), None);
+    }
+
+    #[test]
+    fn hierarchy_matches_every_instance() {
+        let source = crate::voxel::VoxelGenerator::new_from_seed(5);
+        let bb = IAabb::new(IVec3::ZERO, IVec3::splat(8));
+        let mut graph = SceneGraph::<SparseStorage>::new();
+        let model = graph.add_voxels(&source, bb, Transform::IDENTITY);
+        for i in 1..200 {
+            let translation = Vec3A::new(
+                (i * 37 % 300) as f32 - 150.0,
+                (i * 11 % 40) as f32,
+                (i * 53 % 300) as f32 - 150.0,
+            );
+            let rotation =
+                Quat::from_rotation_y(i as f32 * 0.7) * Quat::from_rotation_x(i as f32 * 0.3);
+            graph.place(model, Transform::new(translation, rotation));
+        }
+
+        let mut hits = 0;
+        for i in 0..300 {
+            let angle = i as f32 * 0.05;
+            let origin = Vec3A::new(250.0 * angle.cos(), 60.0, 250.0 * angle.sin());
+            let target = Vec3A::new(
+                (i % 13) as f32 * 20.0 - 120.0,
+                10.0,
+                (i % 7) as f32 * 30.0 - 90.0,
+            );
+            let ray = Ray::new(origin, target - origin);
+
+            // the nearest hit of tracing every instance on its own
+            let mut nearest: Option<Hit> = None;
+            for instance in &graph.instances {
+                let local = instance.transform.object_ray(ray);
+                if let Some(hit) = graph.models[instance.model].scene.trace_hit(local, false) {
+                    if nearest.is_none_or(|nearest| hit.distance < nearest.distance) {
+                        nearest = Some(hit);
+                    }
+                }
+            }
+            assert_eq!(graph.trace_hit(ray, false), nearest, "ray {i}");
+            hits += nearest.is_some() as usize;
+        }
+        assert!(hits > 50, "only {hits} rays hit");
+    }
+}
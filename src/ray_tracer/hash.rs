@@ -0,0 +1,169 @@
+use std::{collections::HashMap, mem};
+
+use glam::IVec3;
+
+#[cfg(feature = "trace")]
+use tracing::*;
+
+use crate::voxel::{Voxel, VoxelSource};
+
+use super::{
+    dense::march,
+    hash_map_bytes,
+    types::{DRay, Hit, IAabb, Ray},
+    MemoryUsage, Scene,
+};
+
+/// Sparse storage in a plain hash map from positions to voxels, traced by stepping through every cell.
+///
+/// This is meant as a reference for the octree rather than a fast backend, so voxels sit in the same cells as in
+/// the octree: the voxel at `p` fills `p - 1` to `p` on each axis.
+pub struct HashStorage {
+    voxels: HashMap<IVec3, Voxel>,
+    /// Cells holding the voxels of the scene's bounding box.
+    cells: IAabb,
+}
+
+impl HashStorage {
+    /// Voxel at a position, if any.
+    pub fn get(&self, pos: IVec3) -> Option<Voxel> {
+        self.voxels.get(&pos).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.voxels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.voxels.is_empty()
+    }
+
+    /// Traces a ray through the cells as if they filled `bb`, a box of the same size.
+    fn trace_hit_in(&self, bb: IAabb, ray: Ray) -> Option<Hit> {
+        let size = bb.max() - bb.min();
+        // the cell at the minimum corner holds the voxel one step further along every axis
+        let offset = self.cells.min() + IVec3::ONE;
+        let (voxel, distance) = march(bb, ray, |pos| {
+            if pos.cmpge(size).any() {
+                return None;
+            }
+            Some(self.get(offset + pos))
+        })?;
+        Some(Hit { voxel, distance })
+    }
+}
+
+impl Scene for HashStorage {
+    fn from_voxels<S: VoxelSource + ?Sized>(source: &S, bb: IAabb) -> Self {
+        #[cfg(feature = "trace")]
+        let _span = trace_span!("hash_from_voxels").entered();
+
+        let mut voxels = HashMap::new();
+        let mut column = vec![None; bb.height()];
+
+        for x in bb.iter_x() {
+            for z in bb.iter_z() {
+                source.column(x, z, bb.iter_y(), &mut column);
+                for (y, voxel) in bb.iter_y().zip(&column) {
+                    if let Some(voxel) = voxel {
+                        voxels.insert(IVec3::new(x, y, z), *voxel);
+                    }
+                }
+            }
+        }
+
+        #[cfg(feature = "trace")]
+        debug!("length" = voxels.len());
+
+        Self {
+            voxels,
+            cells: IAabb::new(bb.origin - IVec3::ONE, bb.extents),
+        }
+    }
+
+    fn trace_hit(&self, ray: Ray, _debug: bool) -> Option<Hit> {
+        #[cfg(feature = "trace")]
+        let _span = trace_span!("hash_trace").entered();
+
+        self.trace_hit_in(self.cells, ray)
+    }
+
+    fn trace_hit_f64(&self, ray: DRay, _debug: bool) -> Option<Hit> {
+        let (start, ray) = ray.enter(self.cells)?;
+        self.trace_hit_in(self.cells.at_origin(), ray)
+            .map(|hit| hit.after(start))
+    }
+
+    fn cell(&self, pos: IVec3) -> IVec3 {
+        pos - 1
+    }
+
+    fn voxel_at(&self, cell: IVec3) -> Option<Voxel> {
+        self.get(cell + 1)
+    }
+
+    fn memory_usage(&self) -> MemoryUsage {
+        // the voxels sit in the table next to their positions
+        let voxel_bytes = self.voxels.len() * mem::size_of::<Voxel>();
+        MemoryUsage {
+            nodes: self.voxels.len(),
+            voxel_bytes,
+            overhead_bytes: hash_map_bytes(&self.voxels) - voxel_bytes,
+            palette_bytes: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{U8Vec3, Vec3A};
+
+    use super::*;
+    use crate::{
+        ray_tracer::octree::{DagStorage, SparseStorage},
+        voxel::{grid::VoxelGrid, VoxelGenerator},
+    };
+
+    #[test]
+    fn matches_octree() {
+        let source = VoxelGenerator::new_from_seed(3);
+        let bb = IAabb::new(IVec3::ZERO, 16 * IVec3::ONE);
+        let hash = HashStorage::from_voxels(&source, bb);
+        let sparse = SparseStorage::from_voxels(&source, bb);
+        let dag = DagStorage::from_voxels(&source, bb);
+
+        let mut hits = 0;
+        for i in 0..200 {
+            let angle = i as f32 * 0.1;
+            let origin = Vec3A::new(40.0 * angle.cos(), 25.0, 40.0 * angle.sin());
+            let target = Vec3A::new((i % 7) as f32 - 3.2, -4.0, (i % 5) as f32 - 2.2);
+            let ray = Ray::new(origin, target - origin);
+            let voxel = hash.trace(ray, false);
+            assert_eq!(voxel, sparse.trace(ray, false), "ray {i}");
+            assert_eq!(voxel, dag.trace(ray, false), "ray {i}");
+            hits += voxel.is_some() as usize;
+        }
+        assert!(hits > 0);
+    }
+
+    #[test]
+    fn cells() {
+        let size = IVec3::new(4, 2, 6);
+        let bb = IAabb::new(size / 2, size / 2);
+        let voxel = Voxel::from(U8Vec3::ONE);
+        let mut grid = VoxelGrid::new(size);
+        grid.set(IVec3::new(3, 1, 5), Some(voxel));
+
+        let storage = HashStorage::from_voxels(&grid, bb);
+        assert_eq!(storage.len(), 1);
+        assert_eq!(storage.get(IVec3::new(3, 1, 5)), Some(voxel));
+
+        // the voxel fills 2..3, 0..1 and 4..5
+        let ray = Ray::new(Vec3A::new(2.5, 0.5, -10.0), Vec3A::Z);
+        assert_eq!(storage.trace(ray, false), Some(voxel));
+        let ray = Ray::new(Vec3A::new(2.5, 10.0, 4.5), Vec3A::NEG_Y);
+        assert_eq!(storage.trace(ray, false), Some(voxel));
+        let ray = Ray::new(Vec3A::new(3.5, 0.5, -10.0), Vec3A::Z);
+        assert_eq!(storage.trace(ray, false), None);
+    }
+}
@@ -0,0 +1,353 @@
+use std::{mem, ops::Range, sync::Arc};
+
+use glam::{IVec3, U8Vec3, Vec3A};
+
+#[cfg(feature = "trace")]
+use tracing::*;
+
+use crate::{
+    archive::SceneArchive,
+    voxel::{Voxel, VoxelSource},
+};
+
+use super::{
+    cache::{CacheStats, ChunkCache},
+    chunked::{chunk_index, march_chunks, march_chunks_f64, ChunkVoxels, CHUNK_SIZE},
+    palette::{PaletteIndex, VoxelPalette},
+    types::{DRay, Hit, IAabb, Ray},
+    MemoryUsage, Scene,
+};
+
+const CHUNK_VOLUME: usize = (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as usize;
+
+/// Color that distant voxels fade into.
+const FOG_COLOR: U8Vec3 = U8Vec3::new(200, 215, 230);
+
+/// Fraction of the far distance where fog starts.
+const FOG_START: f32 = 0.5;
+
+/// Voxels of a generated chunk, with a palette of its own so chunks can be generated in parallel.
+struct GeneratedChunk {
+    data: Box<[Option<PaletteIndex>]>,
+    palette: VoxelPalette,
+}
+
+impl GeneratedChunk {
+    /// Memory held by the chunk's voxels and palette.
+    fn bytes(&self) -> usize {
+        mem::size_of::<Self>() + mem::size_of_val(&*self.data) + self.palette.heap_bytes()
+    }
+}
+
+impl ChunkVoxels for Arc<GeneratedChunk> {
+    fn voxel(&self, index: usize) -> Option<Voxel> {
+        self.data[index].map(|index| self.palette.get(index))
+    }
+}
+
+/// Dense chunks generated the first time a ray enters them, so terrain can reach as far as the camera sees instead
+/// of filling a fixed box.
+///
+/// Voxels only lie within a range of heights, but go on forever horizontally. Rays stop at the far distance, and
+/// voxels fade into fog before it so the end of the terrain isn't a hard edge. Generated chunks are kept in a cache,
+/// along with the ones that turned out empty so they aren't generated again, and can be given a memory budget to
+/// drop the least recently used chunks.
+pub struct InfiniteStorage {
+    source: Box<dyn VoxelSource + Send + Sync>,
+    /// Heights holding voxels, rounded out to whole chunks.
+    heights: Range<i32>,
+    /// Box holding every voxel, rounded out to whole chunks, for sources that don't go on forever.
+    bounds: Option<IAabb>,
+    far: f32,
+    chunks: ChunkCache<IVec3, Option<Arc<GeneratedChunk>>>,
+}
+
+impl InfiniteStorage {
+    /// Creates a storage generating voxels between two heights from a source as rays reach them, out to the far
+    /// distance.
+    pub fn new(
+        source: impl VoxelSource + Send + Sync + 'static,
+        heights: Range<i32>,
+        far: f32,
+    ) -> Self {
+        let start = heights.start.div_euclid(CHUNK_SIZE) * CHUNK_SIZE;
+        let end = (heights.end + CHUNK_SIZE - 1).div_euclid(CHUNK_SIZE) * CHUNK_SIZE;
+        Self {
+            source: Box::new(source),
+            heights: start..end.max(start + CHUNK_SIZE),
+            bounds: None,
+            far,
+            chunks: ChunkCache::new(None),
+        }
+    }
+
+    /// Keeps at most this many bytes of chunks in memory, dropping the least recently used ones past it, or every
+    /// chunk without a budget.
+    pub fn with_cache_budget(mut self, budget: Option<usize>) -> Self {
+        self.chunks = ChunkCache::new(budget);
+        self
+    }
+
+    /// Number of chunks generated that hold voxels and are still in memory.
+    pub fn generated_count(&self) -> usize {
+        self.chunks.values().into_iter().flatten().count()
+    }
+
+    /// Box of whole chunks to step a ray through, reaching the far distance around where it starts.
+    fn march_box(&self, origin: Vec3A) -> IAabb {
+        if let Some(bb) = self.bounds {
+            return bb;
+        }
+
+        let reach = (self.far / CHUNK_SIZE as f32).ceil() as i32 + 1;
+        let center = (origin / CHUNK_SIZE as f32).floor().as_ivec3();
+        let min = IVec3::new(center.x - reach, 0, center.z - reach) * CHUNK_SIZE;
+        let max = IVec3::new(center.x + reach, 0, center.z + reach) * CHUNK_SIZE;
+        let min = min.with_y(self.heights.start);
+        let max = max.with_y(self.heights.end);
+        IAabb::new((min + max) / 2, (max - min) / 2)
+    }
+
+    /// Voxels of a chunk, generating them if they aren't in memory.
+    fn chunk(&self, cell: IVec3) -> Option<Arc<GeneratedChunk>> {
+        self.chunks.get_or_load(
+            cell,
+            || self.generate(cell).map(Arc::new),
+            |chunk| chunk.as_ref().map_or(0, |chunk| chunk.bytes()),
+        )
+    }
+
+    fn generate(&self, cell: IVec3) -> Option<GeneratedChunk> {
+        #[cfg(feature = "trace")]
+        let _span = trace_span!("infinite_generate").entered();
+
+        let min = cell * CHUNK_SIZE;
+        let mut palette = VoxelPalette::new();
+        let mut data = vec![None; CHUNK_VOLUME];
+        let mut column = [None; CHUNK_SIZE as usize];
+
+        for lz in 0..CHUNK_SIZE {
+            for lx in 0..CHUNK_SIZE {
+                let ys = min.y..min.y + CHUNK_SIZE;
+                self.source.column(min.x + lx, min.z + lz, ys, &mut column);
+                for (ly, voxel) in column.iter().enumerate() {
+                    if let Some(voxel) = voxel {
+                        let local = IVec3::new(lx, ly as i32, lz);
+                        data[chunk_index(local, CHUNK_SIZE)] = Some(palette.insert(*voxel));
+                    }
+                }
+            }
+        }
+
+        (!palette.is_empty()).then(|| GeneratedChunk {
+            data: data.into(),
+            palette,
+        })
+    }
+
+    /// Blends a voxel into the fog, from none at [`FOG_START`] of the far distance to all of it at the far distance.
+    fn fog(&self, voxel: Voxel, distance: f32) -> Voxel {
+        let amount = ((distance / self.far - FOG_START) / (1.0 - FOG_START)).clamp(0.0, 1.0);
+        let color = voxel
+            .color
+            .as_vec3a()
+            .lerp(FOG_COLOR.as_vec3a(), amount)
+            .round();
+        Voxel::new(color.as_u8vec3(), voxel.kind)
+    }
+}
+
+impl Scene for InfiniteStorage {
+    /// Keeps the voxels of the box in a [`SceneArchive`], since the source can't be kept, and generates chunks from
+    /// it as rays reach them. Nothing lies outside of the box and there is no far distance.
+    fn from_voxels<S: VoxelSource + ?Sized>(source: &S, bb: IAabb) -> Self {
+        #[cfg(feature = "trace")]
+        let _span = trace_span!("infinite_from_voxels").entered();
+
+        let size = IVec3::splat(CHUNK_SIZE);
+        let min = bb.min().div_euclid(size) * size;
+        let max = (bb.max() + size - 1).div_euclid(size) * size;
+        let bounds = IAabb::new((min + max) / 2, (max - min) / 2);
+
+        Self {
+            bounds: Some(bounds),
+            ..Self::new(
+                SceneArchive::from_source(source, bb),
+                bb.min().y..bb.max().y,
+                f32::INFINITY,
+            )
+        }
+    }
+
+    fn trace_hit(&self, ray: Ray, _debug: bool) -> Option<Hit> {
+        #[cfg(feature = "trace")]
+        let _span = trace_span!("infinite_trace").entered();
+
+        let bb = self.march_box(ray.origin);
+        let offset = bb.min().div_euclid(IVec3::splat(CHUNK_SIZE));
+        let (hit, cell) = march_chunks(bb, CHUNK_SIZE, ray, |pos| {
+            // leave chunks past the far distance ungenerated
+            let min = ((offset + pos) * CHUNK_SIZE).as_vec3a();
+            let nearest = ray.origin.clamp(min, min + CHUNK_SIZE as f32);
+            if nearest.distance(ray.origin) > self.far {
+                return None;
+            }
+            self.chunk(offset + pos)
+        })?;
+
+        let distance = cell_distance(ray, cell);
+        (distance <= self.far).then(|| Hit {
+            voxel: self.fog(hit.voxel, distance),
+            ..hit
+        })
+    }
+
+    fn trace_hit_f64(&self, ray: DRay, _debug: bool) -> Option<Hit> {
+        #[cfg(feature = "trace")]
+        let _span = trace_span!("infinite_trace").entered();
+
+        let bb = self.march_box(ray.origin.as_vec3a());
+        let offset = bb.min().div_euclid(IVec3::splat(CHUNK_SIZE));
+        let far = self.far as f64;
+        let (hit, _) = march_chunks_f64(bb, CHUNK_SIZE, ray, |pos| {
+            let min = ((offset + pos) * CHUNK_SIZE).as_dvec3();
+            let nearest = ray.origin.clamp(min, min + CHUNK_SIZE as f64);
+            if nearest.distance(ray.origin) > far {
+                return None;
+            }
+            self.chunk(offset + pos)
+        })?;
+
+        // the hit's distance is already measured from the true origin, so it is as close as the cell's own
+        (hit.distance <= self.far).then(|| Hit {
+            voxel: self.fog(hit.voxel, hit.distance),
+            ..hit
+        })
+    }
+
+    /// Counts the chunks in memory, which grow as more of the terrain is rendered up to the cache budget.
+    fn memory_usage(&self) -> MemoryUsage {
+        let chunks = self.chunks.values();
+        let generated = chunks.iter().flatten();
+        let voxel_bytes = generated
+            .clone()
+            .map(|chunk| mem::size_of_val(&*chunk.data))
+            .sum();
+        let palette_bytes = generated
+            .clone()
+            .map(|chunk| chunk.palette.heap_bytes())
+            .sum();
+        MemoryUsage {
+            nodes: generated.count(),
+            voxel_bytes,
+            overhead_bytes: self.chunks.bytes() - voxel_bytes - palette_bytes,
+            palette_bytes,
+        }
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        Some(self.chunks.stats())
+    }
+}
+
+/// Distance along a ray to where it enters the unit cell with its minimum corner at `cell`.
+fn cell_distance(ray: Ray, cell: IVec3) -> f32 {
+    let min = (cell.as_vec3a() - ray.origin) / ray.dir;
+    let max = (cell.as_vec3a() + 1.0 - ray.origin) / ray.dir;
+    // the ray is inside the cell's slab on axes it doesn't move along, or it wouldn't have hit the cell
+    let entry = Vec3A::select(
+        ray.dir.cmpeq(Vec3A::ZERO),
+        Vec3A::NEG_INFINITY,
+        min.min(max),
+    );
+    entry.max_element().max(0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ray_tracer::chunked::ChunkedStorage, voxel::VoxelGenerator};
+
+    #[test]
+    fn matches_chunked() {
+        let source = VoxelGenerator::new_from_seed(3);
+        let bb = IAabb::new(IVec3::ZERO, 20 * IVec3::ONE);
+        let infinite = InfiniteStorage::from_voxels(&source, bb);
+        let chunked = ChunkedStorage::from_voxels(&source, bb);
+        assert_eq!(infinite.generated_count(), 0);
+
+        let mut hits = 0;
+        for i in 0..200 {
+            let angle = i as f32 * 0.1;
+            let origin = Vec3A::new(50.0 * angle.cos(), 30.0, 50.0 * angle.sin());
+            let target = Vec3A::new((i % 7) as f32 - 3.0, -4.0, (i % 5) as f32 - 2.0);
+            let ray = Ray::new(origin, target - origin);
+            let voxel = infinite.trace(ray, false);
+            assert_eq!(voxel, chunked.trace(ray, false), "ray {i}");
+            hits += voxel.is_some() as usize;
+        }
+        assert!(hits > 0);
+        assert!(infinite.generated_count() > 0);
+    }
+
+    /// Stone below zero, going on forever.
+    struct Floor;
+
+    impl VoxelSource for Floor {
+        fn lookup(&self, pos: IVec3) -> Option<Voxel> {
+            (pos.y < 0).then(|| Voxel::from(U8Vec3::splat(100)))
+        }
+    }
+
+    #[test]
+    fn far_terrain() {
+        let generator = VoxelGenerator::new_from_seed(3);
+        let storage = InfiniteStorage::new(generator.clone(), -40..40, 300.0);
+
+        // straight down, far outside any box a scene would be built in
+        let ray = Ray::new(Vec3A::new(5000.5, 60.0, -7000.5), Vec3A::NEG_Y);
+        let expected = (-40..40)
+            .rev()
+            .find_map(|y| generator.lookup(IVec3::new(5000, y, -7001)));
+        assert!(expected.is_some());
+        assert_eq!(storage.trace(ray, false), expected);
+    }
+
+    #[test]
+    fn far_distance() {
+        let storage = InfiniteStorage::new(Floor, -16..16, 300.0);
+        let origin = Vec3A::new(0.5, 10.0, 0.5);
+
+        // reaching the floor about 200 away, a third of the way into the fog
+        let voxel = storage.trace(Ray::new(origin, Vec3A::new(1.0, -0.05, 0.0)), false);
+        let color = voxel.expect("floor was hit").color;
+        assert!(color.cmpgt(U8Vec3::splat(100)).all() && color.cmplt(FOG_COLOR).all());
+
+        // past the far distance
+        assert_eq!(
+            storage.trace(Ray::new(origin, Vec3A::new(1.0, -0.02, 0.0)), false),
+            None
+        );
+        assert_eq!(storage.trace(Ray::new(origin, Vec3A::X), false), None);
+        // only chunks within reach were generated
+        assert!(storage.generated_count() <= 2 * (300 / CHUNK_SIZE as usize + 2));
+    }
+
+    #[test]
+    fn fog() {
+        let storage = InfiniteStorage::new(VoxelGenerator::new_from_seed(0), 0..16, 100.0);
+        let voxel = Voxel::from(U8Vec3::new(0, 0, 0));
+        assert_eq!(storage.fog(voxel, 10.0), voxel);
+        assert_eq!(storage.fog(voxel, 50.0), voxel);
+        assert_eq!(storage.fog(voxel, 100.0).color, FOG_COLOR);
+        assert_eq!(storage.fog(voxel, 75.0).color, U8Vec3::new(100, 108, 115));
+    }
+
+    #[test]
+    fn distance_to_cell() {
+        let ray = Ray::new(Vec3A::new(0.5, 0.5, 0.5), Vec3A::X);
+        assert_eq!(cell_distance(ray, IVec3::new(4, 0, 0)), 3.5);
+        let ray = Ray::new(Vec3A::new(0.0, 10.0, 0.0), Vec3A::new(1.0, -1.0, 0.0));
+        assert!((cell_distance(ray, IVec3::new(5, 4, 0)) - 5.0 * 2f32.sqrt()).abs() < 1e-4);
+    }
+}
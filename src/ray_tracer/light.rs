@@ -0,0 +1,159 @@
+use glam::Vec3A;
+
+/// Share of a spot light's angle, at the edge of its cone, over which it fades out.
+const SPOT_SOFTNESS: f32 = 0.2;
+
+/// Where a [`Light`] shines from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LightKind {
+    /// Light from so far away that it comes from the same direction everywhere, like the sun's.
+    Directional {
+        /// Direction toward the light (normalized).
+        dir: Vec3A,
+    },
+    /// Light spreading out from a point in every direction, like a lamp's.
+    Point { position: Vec3A },
+    /// Light from a point shining in a cone, like a torch's.
+    Spot {
+        position: Vec3A,
+        /// Direction the light shines in (normalized).
+        dir: Vec3A,
+        /// Angle from the middle of the cone to its edge, in radians.
+        angle: f32,
+    },
+}
+
+/// A light the scene is lit by, see [`RayTracer::set_lights`](super::RayTracer::set_lights).
+///
+/// Point and spot lights fade with the square of the distance from them, and spot lights also fade out over the
+/// outer [`SPOT_SOFTNESS`] of their cone's angle.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Light {
+    pub kind: LightKind,
+    /// Color from 0 to 1, which multiplies the color of everything the light reaches.
+    pub color: Vec3A,
+    /// Brightness, which for point and spot lights is how bright they are within a voxel of them.
+    pub intensity: f32,
+    /// Cast soft shadows in cone renders, see [`Config::cones`](super::Config::cones).
+    pub shadows: bool,
+}
+
+/// Light from a [`Light`] reaching a point.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Incoming {
+    /// Direction toward the light (normalized).
+    pub dir: Vec3A,
+    /// Distance to the light, infinite for directional lights.
+    pub distance: f32,
+    /// Color of the light reaching the point, its color times its brightness there.
+    pub color: Vec3A,
+}
+
+impl Light {
+    /// White light from a direction (normalized), casting shadows.
+    pub const fn directional(dir: Vec3A) -> Self {
+        Self::white(LightKind::Directional { dir })
+    }
+
+    /// White light from a point, casting shadows.
+    pub const fn point(position: Vec3A) -> Self {
+        Self::white(LightKind::Point { position })
+    }
+
+    /// White light from a point shining in direction `dir` (normalized), in a cone `angle` radians wide on each
+    /// side, casting shadows.
+    pub const fn spot(position: Vec3A, dir: Vec3A, angle: f32) -> Self {
+        Self::white(LightKind::Spot {
+            position,
+            dir,
+            angle,
+        })
+    }
+
+    const fn white(kind: LightKind) -> Self {
+        Self {
+            kind,
+            color: Vec3A::ONE,
+            intensity: 1.0,
+            shadows: true,
+        }
+    }
+
+    /// Light reaching a point from this light, if any does.
+    pub fn incoming(&self, point: Vec3A) -> Option<Incoming> {
+        let (position, cone) = match self.kind {
+            LightKind::Directional { dir } => {
+                return Some(Incoming {
+                    dir,
+                    distance: f32::INFINITY,
+                    color: self.intensity * self.color,
+                })
+            }
+            LightKind::Point { position } => (position, None),
+            LightKind::Spot {
+                position,
+                dir,
+                angle,
+            } => (position, Some((dir, angle))),
+        };
+
+        let offset = position - point;
+        let distance = offset.length();
+        let dir = offset.try_normalize()?;
+        let fade = match cone {
+            Some((forward, angle)) => {
+                let (edge, inner) = (angle.cos(), (angle * (1.0 - SPOT_SOFTNESS)).cos());
+                ((-dir.dot(forward) - edge) / (inner - edge).max(f32::MIN_POSITIVE)).clamp(0.0, 1.0)
+            }
+            None => 1.0,
+        };
+        // no brighter than a voxel away, so surfaces right next to the light don't blow out
+        let brightness = fade * self.intensity / (distance * distance).max(1.0);
+        (brightness > 0.0).then_some(Incoming {
+            dir,
+            distance,
+            color: brightness * self.color,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lights_fade_with_distance_and_angle() {
+        let sun = Light {
+            intensity: 2.0,
+            ..Light::directional(Vec3A::Y)
+        };
+        let incoming = sun.incoming(Vec3A::new(5.0, -100.0, 3.0)).unwrap();
+        assert_eq!(incoming.dir, Vec3A::Y);
+        assert_eq!(incoming.color, Vec3A::splat(2.0));
+
+        // a point light fades with the square of the distance, from a voxel away
+        let lamp = Light {
+            color: Vec3A::new(1.0, 0.5, 0.25),
+            intensity: 16.0,
+            ..Light::point(Vec3A::ZERO)
+        };
+        let incoming = lamp.incoming(Vec3A::new(4.0, 0.0, 0.0)).unwrap();
+        assert_eq!(incoming.dir, -Vec3A::X);
+        assert_eq!(incoming.distance, 4.0);
+        assert_eq!(incoming.color, Vec3A::new(1.0, 0.5, 0.25));
+        assert_eq!(
+            lamp.incoming(Vec3A::new(0.0, 0.5, 0.0)).unwrap().color,
+            16.0 * lamp.color
+        );
+        assert_eq!(lamp.incoming(Vec3A::ZERO), None);
+
+        // a spot light only reaches inside its cone, fading out at the edge
+        let torch = Light::spot(Vec3A::ZERO, -Vec3A::Y, 0.5);
+        let below = |x: f32| torch.incoming(Vec3A::new(x, -1.0, 0.0));
+        assert_eq!(below(0.0).unwrap().color, Vec3A::ONE);
+        let edge = below(0.5f32.tan() - 0.02).unwrap().color.x;
+        assert!(edge > 0.0 && edge < below(0.2).unwrap().color.x);
+        assert_eq!(below(0.6), None);
+        assert_eq!(torch.incoming(Vec3A::Y), None);
+    }
+}
@@ -1,90 +1,1149 @@
-use std::sync::atomic::Ordering;
+use std::{
+    collections::HashMap,
+    fmt, mem, slice,
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
 
-use glam::{IVec3, Vec3A};
-use rayon::iter::{IntoParallelIterator, ParallelIterator};
-use types::{IAabb, Ray};
+use cache::CacheStats;
+use clouds::{CloudSettings, Clouds};
+use emitter::{Emitters, Recorder};
+use fog::{Fog, FogSettings};
+use glam::{DVec3, IVec3, UVec3, Vec2, Vec3A, Vec4};
+use light::{Incoming, Light};
+use path::PathSettings;
+use photon::{CausticSettings, PhotonMap};
+use rayon::iter::{IndexedParallelIterator, IntoParallelIterator, ParallelIterator};
+use sky::TimeOfDay;
+use toon::GBuffer;
+use types::{Beam, ConeHit, DRay, Hit, IAabb, Ray, NEAR, ON_BOUNDARY, PACKET};
+use waves::Waves;
 
+#[cfg(feature = "stats")]
+use stats::TraversalStats;
+#[cfg(feature = "stats")]
+use std::sync::Mutex;
 #[cfg(feature = "trace")]
 use tracing::*;
 
 use crate::{
     camera::Camera,
     export::{Framebuffer, PixelRef},
-    voxel::{Voxel, VoxelGenerator},
+    post::tone::bayer,
+    voxel::{Voxel, VoxelGenerator, VoxelKind, VoxelSource},
 };
 
+mod binary;
+pub mod brickmap;
+pub mod cache;
+pub mod chunked;
+pub mod clouds;
 pub mod dense;
+mod distance;
+pub mod dynamic;
+pub mod emitter;
+pub mod fog;
+pub mod graph;
+pub mod hash;
+pub mod infinite;
+pub mod light;
+pub mod morton;
+mod normal;
 pub mod octree;
+pub mod palette;
+pub mod path;
+pub mod photon;
+pub mod rle;
+mod scatter;
+pub mod sky;
+#[cfg(feature = "stats")]
+pub mod stats;
+pub mod streaming;
+mod surface;
+pub mod toon;
+mod transparency;
 pub mod types;
+mod waves;
 
 pub struct RayTracer<T: Scene + Sync> {
     config: Config,
     scene: T,
     camera: Camera,
+    /// [`Ray::epsilon`] of every ray cast, from [`Config::ray_epsilon`].
+    epsilon: f32,
+    /// Clouds built from [`Config::clouds`].
+    clouds: Option<Clouds>,
+    /// Waves on the surface of water, seeded like the terrain.
+    waves: Waves,
+    /// Lights the scene is lit by, the sun or moon of [`Config::time_of_day`] unless set.
+    lights: Vec<Light>,
+    /// Glowing voxels that path tracing lights surfaces by.
+    emitters: Emitters,
+    /// Photons lighting the beds of water, shot from [`Config::caustics`] when shading.
+    photons: Option<PhotonMap>,
+    /// Fog built from [`Config::fog`], lit by `lights`.
+    fog: Option<Fog>,
+    /// Work done tracing rays in the last render.
+    #[cfg(feature = "stats")]
+    stats: Mutex<TraversalStats>,
 }
 
 impl<T: Scene + Sync> RayTracer<T> {
-    /// Creates a ray tracer from a config.
+    /// Creates a ray tracer from a config, generating terrain from the configured seed.
+    ///
+    /// Same as building the scene with [`Scene::build`] and rendering it with [`RayTracer::from_scene`].
     pub fn new(config: Config) -> Self {
+        Self::from_scene(config, T::build(&config))
+    }
+
+    /// Creates a ray tracer from a config with voxels from any source, keeping track of the glowing voxels in it.
+    pub fn from_source<S: VoxelSource + ?Sized>(config: Config, source: &S) -> Self {
         #[cfg(feature = "trace")]
         let _span = trace_span!("ray_tracer_new").entered();
 
-        let bb = IAabb::new(IVec3::ZERO, config.size as i32 * IVec3::ONE);
-        let generator = config
-            .seed
-            .map(VoxelGenerator::new_from_seed)
-            .unwrap_or_else(VoxelGenerator::new);
+        let recorder = Recorder::new(source);
+        let scene = T::from_voxels(&recorder, config.bounds());
+        let emitters = recorder.into_emitters(&scene);
+        let mut ray_tracer = Self::from_scene(config, scene);
+        ray_tracer.emitters = emitters;
+        ray_tracer
+    }
 
-        Self {
+    /// Creates a ray tracer for a scene that was already built, with a camera at the configured position facing the
+    /// center of the scene.
+    pub fn from_scene(config: Config, scene: T) -> Self {
+        let camera = Camera::looking_at(
+            config.res_width,
+            config.res_height,
+            config.camera_pos,
+            config.bounds().origin.as_vec3a(),
+        );
+        Self::with_scene(scene, camera, config)
+    }
+
+    /// Creates a ray tracer rendering a scene that was already built, such as with [`Scene::build`], from any camera.
+    ///
+    /// The image size and camera position come from the camera rather than the config.
+    pub fn with_scene(scene: T, camera: Camera, config: Config) -> Self {
+        let config = Config {
+            res_width: camera.width(),
+            res_height: camera.height(),
+            camera_pos: camera.position(),
+            ..config
+        };
+        let mut ray_tracer = Self {
             config,
-            scene: T::from_voxels(&generator, bb),
-            camera: Camera::from_res_and_pos(
-                config.res_width,
-                config.res_height,
-                config.camera_pos,
-            ),
+            scene,
+            camera,
+            epsilon: config.ray_epsilon(),
+            clouds: config
+                .clouds
+                .map(|settings| Clouds::new(config.seed.unwrap_or_default(), settings)),
+            waves: Waves::new(config.seed.unwrap_or_default()),
+            lights: vec![config.time_of_day.light()],
+            emitters: Emitters::default(),
+            photons: None,
+            fog: None,
+            #[cfg(feature = "stats")]
+            stats: Mutex::default(),
+        };
+        ray_tracer.photons = ray_tracer.caustics();
+        ray_tracer.fog = config.fog.map(|settings| ray_tracer.build_fog(settings));
+        ray_tracer
+    }
+
+    /// Renders from another camera from now on, keeping the scene.
+    pub fn set_camera(&mut self, camera: Camera) {
+        self.config.res_width = camera.width();
+        self.config.res_height = camera.height();
+        self.config.camera_pos = camera.position();
+        self.epsilon = self.config.ray_epsilon();
+        self.camera = camera;
+    }
+
+    /// Lights the scene by these lights from now on instead of the sun or moon, or by the sun or moon again if there
+    /// are none.
+    ///
+    /// Each light is added up, with a share of it, [`AMBIENT`], falling on faces turned away from it as well.
+    /// Clouds shadow directional lights, and lights casting shadows cast them in cone renders. Caustics are shot
+    /// again from the new lights, and fog is lit by them again.
+    pub fn set_lights(&mut self, lights: Vec<Light>) {
+        let lights = if lights.is_empty() {
+            vec![self.config.time_of_day.light()]
+        } else {
+            lights
+        };
+        if lights != self.lights {
+            self.lights = lights;
+            self.photons = self.caustics();
+            self.fog = self.fog.take().map(|fog| self.light_fog(fog));
         }
     }
 
+    /// Lights path-traced renders by these glowing voxels from now on, such as those a [`Recorder`] found while the
+    /// scene was built.
+    ///
+    /// Glowing voxels are always bright, but only light the voxels around them when path tracing, see
+    /// [`Config::path`].
+    pub fn set_emitters(&mut self, emitters: Emitters) {
+        self.emitters = emitters;
+    }
+
+    /// Photons shot for [`Config::caustics`] that reached the beds of water, if drawing them.
+    pub fn photons(&self) -> Option<&PhotonMap> {
+        self.photons.as_ref()
+    }
+
+    /// Gives back the scene, to render it with another config.
+    pub fn into_scene(self) -> T {
+        self.scene
+    }
+
+    /// Voxels being rendered.
+    pub fn scene(&self) -> &T {
+        &self.scene
+    }
+
+    /// Voxels being rendered, for scenes that can be changed between renders.
+    pub fn scene_mut(&mut self) -> &mut T {
+        &mut self.scene
+    }
+
+    /// Voxel the camera is inside of, if any, looked up in the cell the camera is in, see [`Scene::voxel_at`].
+    ///
+    /// Every ray from inside a voxel hits it straight away, so renders from there are filled with its color without
+    /// tracing them. Cameras on the edge of the scene's bounds or outside of them are never inside one, since rays
+    /// starting on the edge of some storages hit the voxels there even when they leave them.
+    pub fn camera_voxel(&self) -> Option<Voxel> {
+        let position = self.camera.position();
+        let bounds = self.config.bounds();
+        let inside = position.cmpgt(bounds.min().as_vec3a()).all()
+            && position.cmplt(bounds.max().as_vec3a()).all();
+        if !inside {
+            return None;
+        }
+        self.scene.voxel_at(position.floor().as_ivec3())
+    }
+
+    /// Work done tracing rays in the last render, counted per ray and summed up per tile and for the whole frame.
+    #[cfg(feature = "stats")]
+    pub fn traversal_stats(&self) -> TraversalStats {
+        *self.stats.lock().expect("stats lock was poisoned")
+    }
+
+    /// Adds the work done rendering a tile to the frame's.
+    #[cfg(feature = "stats")]
+    fn record(&self, tile: TraversalStats) {
+        self.stats
+            .lock()
+            .expect("stats lock was poisoned")
+            .add_tile(tile);
+    }
+
     pub fn render(&self) -> Framebuffer {
+        let fb = self.framebuffer();
+        self.render_into(&fb);
+        fb
+    }
+
+    /// Renders into a framebuffer that was already allocated, overwriting every pixel, so a loop rendering frame
+    /// after frame can keep using the same one.
+    ///
+    /// With [`Config::antialias`], pixels colored differently from one next to them are then traced four more times
+    /// through points spread over the pixel, and set to the average of the five colors.
+    ///
+    /// Panics if the framebuffer's size isn't the configured resolution.
+    pub fn render_into(&self, fb: &Framebuffer) {
         #[cfg(feature = "trace")]
         let _span = trace_span!("ray_tracer_render").entered();
 
-        let fb = Framebuffer::new(self.config.res_width, self.config.res_height);
+        assert_eq!(
+            (fb.width(), fb.height()),
+            (self.config.res_width, self.config.res_height),
+            "framebuffer size does not match the resolution"
+        );
 
-        fb.into_par_iter().for_each(|pixel| {
-            self.render_pixel(pixel);
+        #[cfg(feature = "stats")]
+        self.record_start();
+
+        if let Some(voxel) = self.camera_voxel() {
+            self.fill(fb, voxel);
+            return;
+        }
+
+        // cones, double-precision rays and paths are traced one pixel at a time
+        if (self.config.packets || self.config.beams)
+            && !self.config.cones
+            && !self.config.double
+            && self.path().is_none()
+        {
+            self.render_tiles(fb);
+        } else {
+            fb.into_par_iter().for_each(|pixel| {
+                #[cfg(feature = "stats")]
+                stats::take();
+                self.render_pixel(fb, pixel);
+                #[cfg(feature = "stats")]
+                {
+                    let mut tile = TraversalStats::default();
+                    tile.add_rays(1, stats::take());
+                    self.record(tile);
+                }
+            });
+        }
+
+        // paths are already traced through points spread over each pixel
+        if self.config.antialias && self.path().is_none() {
+            self.smooth_edges(fb);
+        }
+        if self.config.toon && !self.config.debug {
+            self.outline(fb);
+        }
+    }
+
+    /// Depth and normal of what the ray through the center of every pixel hits, such as to find outlines.
+    ///
+    /// Normals are the ones shading lights surfaces by, see [`Config::hard_normals`].
+    pub fn render_gbuffer(&self) -> GBuffer {
+        #[cfg(feature = "trace")]
+        let _span = trace_span!("ray_tracer_render_gbuffer").entered();
+
+        let width = self.config.res_width;
+        let (depth, normals) = (0..width * self.config.res_height)
+            .into_par_iter()
+            .map(|i| {
+                let ray = self.pixel_ray(i % width, i / width);
+                match self.sample_hit(ray) {
+                    Some(hit) => {
+                        let point = ray.origin + hit.distance * ray.dir;
+                        (hit.distance, self.normal(point, ray.dir))
+                    }
+                    None => (f32::INFINITY, Vec3A::ZERO),
+                }
+            })
+            .unzip();
+        GBuffer {
+            width,
+            height: self.config.res_height,
+            depth,
+            normals,
+        }
+    }
+
+    /// Darkens the pixels on outlines, found from the depth and normals of a [`GBuffer`] rendered for them.
+    fn outline(&self, fb: &Framebuffer) {
+        #[cfg(feature = "trace")]
+        let _span = trace_span!("ray_tracer_outline").entered();
+
+        let width = self.config.res_width;
+        let outlines = self.render_gbuffer().outlines();
+        outlines
+            .into_par_iter()
+            .enumerate()
+            .for_each(|(i, outline)| {
+                if !outline {
+                    return;
+                }
+                let (x, y) = (i % width, i / width);
+                let pixel = fb.pixel_mut(x, y);
+                let color = pixel.load(Ordering::Acquire);
+                let rgb = UVec3::new(color >> 24, color >> 16 & 0xff, color >> 8 & 0xff);
+                let rgb = (rgb.as_vec3a() * toon::OUTLINE).round().as_uvec3();
+                let color = rgb.x << 24 | rgb.y << 16 | rgb.z << 8 | color & 0xff;
+                pixel.store(color, Ordering::Release);
+                if let Some(hdr) = fb.hdr(x, y) {
+                    fb.set_hdr(x, y, hdr * toon::OUTLINE);
+                }
+            });
+    }
+
+    /// Traces a few more rays through each pixel on the edge of a voxel and blends their colors, so silhouettes
+    /// are smoothed without tracing every pixel several times.
+    ///
+    /// Edges are pixels colored differently from a pixel next to them, found before any are blended so that the
+    /// blended ones don't spread.
+    fn smooth_edges(&self, fb: &Framebuffer) {
+        #[cfg(feature = "trace")]
+        let _span = trace_span!("ray_tracer_smooth_edges").entered();
+
+        let width = self.config.res_width;
+        let height = self.config.res_height;
+        let color = |x: usize, y: usize| fb.pixel_mut(x, y).load(Ordering::Acquire);
+        let edges: Vec<_> = (0..width * height)
+            .into_par_iter()
+            .map(|i| (i % width, i / width))
+            .filter(|&(x, y)| {
+                let center = color(x, y);
+                (x > 0 && color(x - 1, y) != center)
+                    || (x + 1 < width && color(x + 1, y) != center)
+                    || (y > 0 && color(x, y - 1) != center)
+                    || (y + 1 < height && color(x, y + 1) != center)
+            })
+            .map(|(x, y)| (x, y, shade_at(fb, x, y)))
+            .collect();
+
+        edges.into_par_iter().for_each(|(x, y, center)| {
+            #[cfg(feature = "trace")]
+            let _span = trace_span!("ray_tracer_render_edge").entered();
+            #[cfg(feature = "stats")]
+            stats::take();
+
+            let mut samples = [center; EDGE_SAMPLES.len() + 1];
+            for (sample, offset) in samples.iter_mut().zip(EDGE_SAMPLES) {
+                *sample = self.sample_color(x, y, offset);
+            }
+            self.store(fb, x, y, blend(&samples));
+
+            #[cfg(feature = "stats")]
+            {
+                let mut tile = TraversalStats::default();
+                tile.add_rays(EDGE_SAMPLES.len() as u64, stats::take());
+                self.record(tile);
+            }
         });
+    }
 
-        fb
+    /// Clears the work counted in the last render, and what was traced outside of a render on this thread.
+    #[cfg(feature = "stats")]
+    fn record_start(&self) {
+        *self.stats.lock().expect("stats lock was poisoned") = TraversalStats::default();
+        stats::take();
+    }
+
+    /// Renders tiles of the image in parallel, tracing each 2x2 quad of pixels in a tile as one packet of rays if
+    /// packets are enabled, and starting every ray of a tile where its beam first reaches a voxel if beams are.
+    fn render_tiles(&self, fb: &Framebuffer) {
+        #[cfg(feature = "trace")]
+        let _span = trace_span!("ray_tracer_render_tiles").entered();
+
+        let width = self.config.res_width;
+        let height = self.config.res_height;
+        let columns = width.div_ceil(TILE);
+        let debug = self.config.debug;
+
+        (0..columns * height.div_ceil(TILE))
+            .into_par_iter()
+            .for_each(|tile| {
+                #[cfg(feature = "trace")]
+                let _span = trace_span!("ray_tracer_render_tile").entered();
+
+                let (tx, ty) = (tile % columns * TILE, tile / columns * TILE);
+                let (xs, ys) = (tx..(tx + TILE).min(width), ty..(ty + TILE).min(height));
+                #[cfg(feature = "stats")]
+                stats::take();
+                #[cfg(feature = "stats")]
+                let mut counts = TraversalStats::default();
+                let start = if self.config.beams {
+                    self.scene.beam_start(Beam::around(&[
+                        self.camera.get_ray(xs.start, ys.start),
+                        self.camera.get_ray(xs.end - 1, ys.start),
+                        self.camera.get_ray(xs.start, ys.end - 1),
+                        self.camera.get_ray(xs.end - 1, ys.end - 1),
+                    ]))
+                } else {
+                    0.0
+                };
+                // the beam is traced for every ray of the tile, so its work counts toward the tile but not as a ray
+                #[cfg(feature = "stats")]
+                counts.add_rays(0, stats::take());
+
+                if !self.config.packets {
+                    for y in ys {
+                        for x in xs.clone() {
+                            let ray = self.pixel_ray(x, y);
+                            let hit = self.scene.trace_hit_from(ray, start, debug);
+                            let hit = self.surface_hit(&ray, hit);
+                            self.store(fb, x, y, self.hit_color(&ray, hit));
+                            #[cfg(feature = "stats")]
+                            counts.add_rays(1, stats::take());
+                        }
+                    }
+                    #[cfg(feature = "stats")]
+                    self.record(counts);
+                    return;
+                }
+
+                for y in ys.step_by(2) {
+                    for x in xs.clone().step_by(2) {
+                        // quads past the edge of the image trace its last row or column twice
+                        let quad = [(0, 0), (1, 0), (0, 1), (1, 1)]
+                            .map(|(i, j)| ((x + i).min(width - 1), (y + j).min(height - 1)));
+                        let rays = quad.map(|(x, y)| self.pixel_ray(x, y));
+                        let hits = self.scene.trace_packet(&rays, start, debug);
+                        for (((x, y), ray), hit) in quad.into_iter().zip(&rays).zip(hits) {
+                            let hit = self.surface_hit(ray, hit);
+                            self.store(fb, x, y, self.hit_color(ray, hit));
+                        }
+                        #[cfg(feature = "stats")]
+                        counts.add_rays(PACKET as u64, stats::take());
+                    }
+                }
+                #[cfg(feature = "stats")]
+                self.record(counts);
+            });
+    }
+
+    /// Renders progressively from coarse blocks down to single pixels until the budget runs out.
+    ///
+    /// The coarsest pass always completes so there is something to export.
+    /// Returns the framebuffer along with the finest block size that was fully rendered (1 is full quality).
+    pub fn render_progressive(&self, budget: Duration) -> (Framebuffer, usize) {
+        #[cfg(feature = "trace")]
+        let _span = trace_span!("ray_tracer_render_progressive").entered();
+
+        #[cfg(feature = "stats")]
+        self.record_start();
+
+        let deadline = Instant::now() + budget;
+        let fb = self.framebuffer();
+
+        if let Some(voxel) = self.camera_voxel() {
+            self.fill(&fb, voxel);
+            return (fb, 1);
+        }
+
+        let mut block = PROGRESSIVE_BLOCK;
+        self.render_pass(&fb, block, None);
+
+        while block > 1 {
+            let next = block / 2;
+            if Instant::now() >= deadline || !self.render_pass(&fb, next, Some(deadline)) {
+                break;
+            }
+            block = next;
+        }
+
+        (fb, block)
     }
 
-    fn render_pixel(&self, pixel: PixelRef<'_>) {
+    /// Traces one pixel per block and fills the block with its color.
+    ///
+    /// Blocks whose pixel was traced by the previous (twice as large) pass are skipped.
+    /// Returns false if the deadline was hit before the pass finished.
+    fn render_pass(&self, fb: &Framebuffer, block: usize, deadline: Option<Instant>) -> bool {
+        #[cfg(feature = "trace")]
+        let _span = trace_span!("ray_tracer_render_pass").entered();
+
+        let width = self.config.res_width;
+        let height = self.config.res_height;
+        let first = block == PROGRESSIVE_BLOCK;
+
+        (0..height.div_ceil(block))
+            .into_par_iter()
+            .map(|by| {
+                #[cfg(feature = "trace")]
+                let _span = trace_span!("ray_tracer_render_blocks").entered();
+
+                if deadline.is_some_and(|d| Instant::now() >= d) {
+                    return false;
+                }
+
+                #[cfg(feature = "stats")]
+                stats::take();
+                #[cfg(feature = "stats")]
+                let mut counts = TraversalStats::default();
+
+                let y = by * block;
+                for x in (0..width).step_by(block) {
+                    if !first && x % (2 * block) == 0 && y % (2 * block) == 0 {
+                        continue;
+                    }
+
+                    let shade = self.pixel_color(x, y);
+                    for j in y..(y + block).min(height) {
+                        for i in x..(x + block).min(width) {
+                            self.store(fb, i, j, shade);
+                        }
+                    }
+                    #[cfg(feature = "stats")]
+                    counts.add_rays(1, stats::take());
+                }
+
+                #[cfg(feature = "stats")]
+                self.record(counts);
+                true
+            })
+            .reduce(|| true, |a, b| a && b)
+    }
+
+    /// Fills every pixel with the color of a voxel.
+    fn fill(&self, fb: &Framebuffer, voxel: Voxel) {
+        let shade = voxel_shade(Some(voxel));
+        fb.into_par_iter().for_each(|pixel| {
+            self.store(fb, pixel.x, pixel.y, shade);
+        });
+    }
+
+    fn render_pixel(&self, fb: &Framebuffer, pixel: PixelRef<'_>) {
         #[cfg(feature = "trace")]
         let _span = trace_span!("ray_tracer_render_pixel").entered();
 
-        let ray = self.camera.get_ray(pixel.x, pixel.y);
+        self.store(fb, pixel.x, pixel.y, self.pixel_color(pixel.x, pixel.y));
+    }
 
-        let Some(voxel) = self.scene.trace(ray, self.config.debug) else {
-            return;
+    /// Framebuffer the size of the image, keeping unclamped colors if [`Config::hdr`] is set.
+    fn framebuffer(&self) -> Framebuffer {
+        let (width, height) = (self.config.res_width, self.config.res_height);
+        if self.config.hdr {
+            Framebuffer::with_hdr(width, height)
+        } else {
+            Framebuffer::new(width, height)
+        }
+    }
+
+    /// Packs a shaded color from 0 to 255 with its opacity from 0 to 1 into a pixel, rounding it at the pixel's
+    /// threshold, see [`pack_shaded`], and keeps it unclamped as well if the framebuffer does.
+    fn store(&self, fb: &Framebuffer, x: usize, y: usize, shade: Vec4) {
+        let color = Vec3A::from_vec4(shade);
+        let packed = pack_shaded(color, shade.w, self.threshold(x, y));
+        fb.pixel_mut(x, y).store(packed, Ordering::Release);
+        fb.set_hdr(x, y, color / 255.0);
+    }
+
+    /// Traces a pixel, returning its shaded color from 0 to 255 with its opacity from 0 to 1 (zero if nothing was
+    /// hit).
+    fn pixel_color(&self, x: usize, y: usize) -> Vec4 {
+        if let Some(settings) = self.path() {
+            return self.path_color(x, y, settings);
+        }
+        self.sample_color(x, y, Vec2::ZERO)
+    }
+
+    /// Traces a point of a pixel, `offset` pixels right and down from its center, like [`Self::pixel_color`].
+    fn sample_color(&self, x: usize, y: usize, offset: Vec2) -> Vec4 {
+        let ray = self.sample_ray(x, y, offset);
+        if self.config.cones {
+            return self.cone_color(ray);
+        }
+        self.hit_color(&ray, self.sample_hit(ray))
+    }
+
+    /// Traces a ray from the camera, in double precision if configured, and moves its hit onto the smooth surface if
+    /// that is drawn instead of the cubes.
+    fn sample_hit(&self, ray: Ray) -> Option<Hit> {
+        let hit = if self.config.double {
+            self.scene.trace_hit_f64(DRay::from(ray), self.config.debug)
+        } else {
+            self.scene.trace_hit(ray, self.config.debug)
+        };
+        self.surface_hit(&ray, hit)
+    }
+
+    /// Moves a ray's hit onto the smooth surface around the voxels if [`Config::smooth_surface`] is set, searching
+    /// the ray from [`SURFACE_MARGIN`] before the hit to as far past it.
+    ///
+    /// Where the surface rounds off the corner that was hit, the ray is traced again from past the margin, a few
+    /// times before the hit is kept as it is.
+    fn surface_hit(&self, ray: &Ray, hit: Option<Hit>) -> Option<Hit> {
+        if !self.config.smooth_surface || self.config.debug {
+            return hit;
+        }
+        let mut hit = hit?;
+        for _ in 0..SURFACE_RETRIES {
+            let start = (hit.distance - SURFACE_MARGIN).max(ray.near);
+            let end = (hit.distance + SURFACE_MARGIN).min(ray.far);
+            if let Some(hit) = surface::intersect(ray, start, end, |cell| self.scene.voxel_at(cell))
+            {
+                return Some(hit);
+            }
+            let past = Ray { near: end, ..*ray };
+            hit = self.scene.trace_hit(past, false)?;
+        }
+        Some(hit)
+    }
+
+    /// Color from 0 to 255 or more of the voxel a ray hit, or of the sky if nothing was hit, lit by the scene's
+    /// lights and behind any clouds in front of it, with its opacity from 0 to 1.
+    fn hit_color(&self, ray: &Ray, hit: Option<Hit>) -> Vec4 {
+        let time = self.time_of_day();
+        if !self.shading()
+            && self.clouds().is_none()
+            && self.fog().is_none()
+            && !self.transparency()
+            && time == TimeOfDay::Day
+        {
+            return voxel_shade(hit.map(|hit| hit.voxel));
+        }
+        let Some(hit) = hit else {
+            let (sky, opacity) = time.sky(ray.dir);
+            return self.shade_behind_clouds(ray, ray.far, sky, opacity);
+        };
+        let (color, opacity) = if self.transparency() {
+            self.see_through(ray, hit)
+        } else {
+            let point = ray.origin + hit.distance * ray.dir;
+            (self.hit_light(hit.voxel, point, ray.dir), 1.0)
+        };
+        self.shade_behind_clouds(ray, hit.distance, color, opacity)
+    }
+
+    /// Color from 0 to 255 of a voxel where a ray going in direction `dir` reached it at a point, shaded if shading,
+    /// or else lit by the light reaching it.
+    fn hit_light(&self, voxel: Voxel, point: Vec3A, dir: Vec3A) -> Vec3A {
+        if self.shading() {
+            self.shade(voxel, point, dir)
+        } else {
+            self.incoming(point)
+                .map(|incoming| self.cloud_light(point, &incoming) * incoming.color)
+                .sum::<Vec3A>()
+                * voxel.color.as_vec3a()
+        }
+    }
+
+    /// Blends a shaded color from 0 to 255 with an opacity from 0 to 1 behind any fog and then any clouds along a
+    /// ray up to `far`, and returns it with its opacity.
+    fn shade_behind_clouds(&self, ray: &Ray, far: f32, color: Vec3A, opacity: f32) -> Vec4 {
+        let (color, opacity) = self.behind_fog(ray, far, color, opacity);
+        let (color, opacity) = self.behind_clouds(ray, far, color, opacity);
+        color.extend(opacity)
+    }
+
+    /// Blends any fog along a ray up to `far` over a color from 0 to 255 with an opacity from 0 to 1, see
+    /// [`Fog::trace`].
+    fn behind_fog(&self, ray: &Ray, far: f32, color: Vec3A, opacity: f32) -> (Vec3A, f32) {
+        match self.fog() {
+            Some(fog) => fog.trace(ray, far).over(color, opacity),
+            None => (color, opacity),
+        }
+    }
+
+    /// Blends any clouds along a ray up to `far` over a color from 0 to 255 with an opacity from 0 to 1, see
+    /// [`CloudHit::over`](clouds::CloudHit::over).
+    fn behind_clouds(&self, ray: &Ray, far: f32, color: Vec3A, opacity: f32) -> (Vec3A, f32) {
+        let Some(clouds) = self.clouds() else {
+            return (color, opacity);
         };
+        let mut cloud = clouds.trace(ray, far);
+        cloud.color *= self.time_of_day().light_color();
+        cloud.over(color, opacity)
+    }
 
-        let raw_color = voxel.color.as_uvec3();
-        let color = raw_color.x << 24 | raw_color.y << 16 | raw_color.z << 8 | 0xff;
+    /// Settings for path tracing, which debug renders leave out.
+    fn path(&self) -> Option<PathSettings> {
+        self.config.path.filter(|_| !self.config.debug)
+    }
 
-        pixel.value.store(color, Ordering::Release);
+    /// Clouds drawn in renders, which debug renders leave out.
+    fn clouds(&self) -> Option<&Clouds> {
+        self.clouds.as_ref().filter(|_| !self.config.debug)
     }
+
+    /// Fog drawn in renders, which debug renders leave out.
+    fn fog(&self) -> Option<&Fog> {
+        self.fog.as_ref().filter(|_| !self.config.debug)
+    }
+
+    /// Whether renders see through voxels that let light through, which debug renders leave out.
+    fn transparency(&self) -> bool {
+        self.config.transparency && !self.config.debug
+    }
+
+    /// Time of day renders are lit at, which is always day for debug renders.
+    fn time_of_day(&self) -> TimeOfDay {
+        if self.config.debug {
+            TimeOfDay::Day
+        } else {
+            self.config.time_of_day
+        }
+    }
+
+    /// Lights the scene is lit by, which is always the sun for debug renders.
+    fn lights(&self) -> &[Light] {
+        if self.config.debug {
+            slice::from_ref(&DAYLIGHT)
+        } else {
+            &self.lights
+        }
+    }
+
+    /// Light reaching a point from each of the scene's lights that reaches it.
+    fn incoming(&self, point: Vec3A) -> impl Iterator<Item = Incoming> + '_ {
+        self.lights()
+            .iter()
+            .filter_map(move |light| light.incoming(point))
+    }
+
+    /// How much of a light gets through the clouds to a point, from 1 down to `1 -` [`CLOUD_SHADOW`] under the
+    /// thickest of them, which only directional lights high above the scene are shadowed by.
+    fn cloud_light(&self, point: Vec3A, incoming: &Incoming) -> f32 {
+        match (self.clouds(), incoming.distance) {
+            (Some(clouds), f32::INFINITY) => {
+                let light = clouds.transmittance(point, incoming.dir);
+                1.0 - CLOUD_SHADOW * (1.0 - light)
+            }
+            _ => 1.0,
+        }
+    }
+
+    /// Photons for [`Config::caustics`], shot only if shading, since nothing else draws them.
+    fn caustics(&self) -> Option<PhotonMap> {
+        let settings = self.config.caustics.filter(|_| self.shading())?;
+        Some(self.shoot_photons(settings))
+    }
+
+    /// Whether hits are lit from their normals, which debug renders leave out so their colors can still be read.
+    fn shading(&self) -> bool {
+        (self.config.shade || self.config.toon) && !self.config.debug
+    }
+
+    /// Color of a voxel where a ray going in direction `dir` reached it, lit by each light by the direction its
+    /// surface faces, with the top of water bent by [`Waves`] moving with [`Config::time`] and glinting in the light,
+    /// see [`RayTracer::shade_water`], and metal ores shining in it, see [`highlight`].
+    fn shade(&self, voxel: Voxel, point: Vec3A, dir: Vec3A) -> Vec3A {
+        let color = voxel.color.as_vec3a();
+        if voxel.kind == VoxelKind::WATER && normal::hard(point, dir, self.epsilon) == Vec3A::Y {
+            return self.shade_water(color, point, dir);
+        }
+        let normal = self.normal(point, dir);
+        self.incoming(point)
+            .map(|incoming| {
+                let surface = self.surface_light(voxel.kind, point, dir, normal, incoming.dir);
+                let lit = (surface + highlight(voxel.kind, normal, dir, incoming.dir)) * color;
+                self.cloud_light(point, &incoming) * incoming.color * lit
+            })
+            .sum()
+    }
+
+    /// How much of a light from direction `light` falls on a surface with a normal, from [`AMBIENT`] on surfaces
+    /// turned away from it up to all of it on surfaces turned straight toward it, in a few flat bands for toon
+    /// renders.
+    fn light(&self, normal: Vec3A, light: Vec3A) -> f32 {
+        let light = AMBIENT + (1.0 - AMBIENT) * normal.dot(light).max(0.0);
+        if self.config.toon {
+            toon::band(light, AMBIENT)
+        } else {
+            light
+        }
+    }
+
+    /// Normal of the surface of a voxel where a ray going in direction `dir` reached it, see [`Config::shade`].
+    fn normal(&self, point: Vec3A, dir: Vec3A) -> Vec3A {
+        let hard = normal::hard(point, dir, self.epsilon);
+        // smooth surfaces run between the faces of the cubes, so only have smooth normals
+        if self.config.hard_normals && !self.config.smooth_surface {
+            hard
+        } else {
+            // normals leaning away from the ray, such as in a narrow gap, would light faces it can't see
+            normal::smooth(point, |cell| self.scene.voxel_at(cell).is_some())
+                .filter(|normal| normal.dot(hard) > 0.0)
+                .unwrap_or(hard)
+        }
+    }
+
+    /// Traces a cone as wide as a pixel around a ray from the camera, with edges of the scene that only partly
+    /// cover it drawn partly over the sky, and lights it by each light, darkened by how much of a wider cone toward
+    /// the light is blocked if it casts shadows, and by the direction the surface faces if shading.
+    fn cone_color(&self, ray: Ray) -> Vec4 {
+        let ray = Ray {
+            spread: self.camera.pixel_spread(),
+            ..ray
+        };
+        let cone = self.scene.trace_cone(ray, self.config.debug);
+        let time = self.time_of_day();
+        let (sky, sky_opacity) = time.sky(ray.dir);
+        if cone.opacity == 0.0 {
+            return self.shade_behind_clouds(&ray, ray.far, sky, sky_opacity);
+        }
+
+        let point = ray.origin + cone.distance * ray.dir;
+        let normal = self.shading().then(|| self.normal(point, ray.dir));
+        let light: Vec3A = self
+            .lights()
+            .iter()
+            .filter_map(|light| Some((light.shadows, light.incoming(point)?)))
+            .map(|(shadows, incoming)| {
+                let mut light = 1.0;
+                if shadows {
+                    // from just in front of the surface, so the cone doesn't start inside the voxel it found
+                    let shadow = Ray {
+                        origin: ray.origin + (cone.distance - ray.near) * ray.dir,
+                        dir: incoming.dir,
+                        spread: SHADOW_SPREAD,
+                        far: ray.far.min(incoming.distance),
+                        ..ray
+                    };
+                    light -= SHADOW * self.scene.trace_cone(shadow, self.config.debug).opacity;
+                }
+                light *= self.cloud_light(point, &incoming);
+                if let Some(normal) = normal {
+                    light *= self.light(normal, incoming.dir);
+                }
+                light * incoming.color
+            })
+            .sum();
+        let color = light * cone.average().as_vec3a();
+        let opacity = if cone.is_opaque() { 1.0 } else { cone.opacity };
+        let behind = (1.0 - opacity) * sky_opacity;
+        let color = (opacity * color + behind * sky) / (opacity + behind);
+        self.shade_behind_clouds(&ray, cone.distance, color, opacity + behind)
+    }
+
+    /// Fraction at which shaded colors of a pixel round up to the next whole value, offset from a half by
+    /// a [`bayer`] matrix if dithering so neighboring pixels of a smooth gradient round differently.
+    fn threshold(&self, x: usize, y: usize) -> f32 {
+        if self.config.dither {
+            bayer(x, y)
+        } else {
+            0.0
+        }
+    }
+
+    /// Ray through a pixel, covering the pixel's width if the scene may draw averages of smaller details.
+    fn pixel_ray(&self, x: usize, y: usize) -> Ray {
+        self.sample_ray(x, y, Vec2::ZERO)
+    }
+
+    /// Ray through a point of a pixel, `offset` pixels right and down from its center, set up like
+    /// [`Self::pixel_ray`].
+    fn sample_ray(&self, x: usize, y: usize, offset: Vec2) -> Ray {
+        let mut ray = Ray {
+            near: self.config.near,
+            far: self.config.far,
+            epsilon: self.epsilon,
+            ..self.camera.get_ray_at(x, y, offset)
+        };
+        if self.config.lod {
+            ray.spread = self.camera.pixel_spread();
+        }
+        ray
+    }
+}
+
+/// Packs the color of a voxel as RGBA (zero if nothing was hit).
+fn pack_color(voxel: Option<Voxel>) -> u32 {
+    let Some(voxel) = voxel else {
+        return 0;
+    };
+
+    let raw_color = voxel.color.as_uvec3();
+    raw_color.x << 24 | raw_color.y << 16 | raw_color.z << 8 | 0xff
+}
+
+/// Color from 0 to 255 of a voxel with full opacity (zero if nothing was hit), see [`RayTracer::hit_color`].
+fn voxel_shade(voxel: Option<Voxel>) -> Vec4 {
+    match voxel {
+        Some(voxel) => voxel.color.as_vec3a().extend(1.0),
+        None => Vec4::ZERO,
+    }
+}
+
+/// Shaded color of a pixel that was already rendered, from its unclamped color if the framebuffer keeps it.
+fn shade_at(fb: &Framebuffer, x: usize, y: usize) -> Vec4 {
+    let color = fb.pixel_mut(x, y).load(Ordering::Acquire);
+    let opacity = (color & 0xff) as f32 / 255.0;
+    match fb.hdr(x, y) {
+        Some(hdr) => (255.0 * hdr).extend(opacity),
+        None => UVec3::new(color >> 24, color >> 16 & 0xff, color >> 8 & 0xff)
+            .as_vec3a()
+            .extend(opacity),
+    }
+}
+
+/// Light from direction `light` that a surface of a kind with a normal reflects like a mirror toward a ray going in
+/// direction `dir`, as a share of the light tinted by the voxel's color, brightest where the reflected ray points
+/// straight at the light, see [`VoxelKind::shine`].
+fn highlight(kind: VoxelKind, normal: Vec3A, dir: Vec3A, light: Vec3A) -> f32 {
+    if kind.shine() == 0.0 {
+        return 0.0;
+    }
+    let reflected = dir - 2.0 * dir.dot(normal) * normal;
+    kind.shine() * reflected.dot(light).max(0.0).powf(SHININESS)
+}
+
+/// Packs a shaded color from 0 to 255 with an opacity from 0 to 1 as RGBA, rounding the color to whole channels at
+/// `threshold`, see [`quantize`].
+fn pack_shaded(color: Vec3A, opacity: f32, threshold: f32) -> u32 {
+    let color = quantize(color, threshold);
+    let alpha = (opacity * 255.0).round() as u32;
+    color.x << 24 | color.y << 16 | color.z << 8 | alpha
+}
+
+/// Averages shaded colors with their opacities, weighting each color by its opacity so that samples that missed
+/// only make the result more transparent, not darker.
+fn blend(shades: &[Vec4]) -> Vec4 {
+    let (sum, opacity) = shades
+        .iter()
+        .fold((Vec3A::ZERO, 0.0), |(sum, opacity), &shade| {
+            (sum + Vec3A::from_vec4(shade) * shade.w, opacity + shade.w)
+        });
+    if opacity == 0.0 {
+        return Vec4::ZERO;
+    }
+    (sum / opacity).extend(opacity / shades.len() as f32)
+}
+
+/// Rounds a color to whole values from 0 to 255, rounding each channel up past `threshold` above a half instead of
+/// at a half.
+///
+/// Thresholds from [`RayTracer::threshold`] average out to a half over every 4x4 block of pixels, so a gradient
+/// between two values is drawn as a pattern of both rather than a band of each.
+fn quantize(color: Vec3A, threshold: f32) -> UVec3 {
+    (color + (0.5 - threshold))
+        .floor()
+        .clamp(Vec3A::ZERO, Vec3A::splat(255.0))
+        .as_uvec3()
 }
 
+/// Offsets from the center of an edge pixel, in pixels, of the extra rays traced through it when anti-aliasing.
+///
+/// They sit on a grid rotated so that no two share a row or column, which covers horizontal and vertical edges,
+/// the most common ones between voxels, at four different heights.
+const EDGE_SAMPLES: [Vec2; 4] = [
+    Vec2::new(-0.125, -0.375),
+    Vec2::new(0.375, -0.125),
+    Vec2::new(0.125, 0.375),
+    Vec2::new(-0.375, 0.125),
+];
+
+/// Block size of the first pass of a progressive render.
+const PROGRESSIVE_BLOCK: usize = 16;
+
+/// Side length of the tiles rendered in parallel when tracing packets of rays.
+const TILE: usize = 16;
+
+/// Direction toward the sun (normalized), which lights scenes by day unless they are given lights of their own.
+const SUN: Vec3A = Vec3A::new(0.36, 0.8, 0.48);
+
+/// Light of the sun, which debug renders are lit by.
+const DAYLIGHT: Light = Light::directional(SUN);
+
+/// Width of shadow cones per unit of distance, which sets how soft shadows are.
+const SHADOW_SPREAD: f32 = 0.05;
+
+/// How much of the light a fully blocked shadow cone takes away.
+const SHADOW: f32 = 0.5;
+
+/// How much of a light clouds that let none of it through take away.
+const CLOUD_SHADOW: f32 = 0.6;
+
+/// Distance, in voxels, before and after the hit of a ray that [`RayTracer::surface_hit`] searches for the smooth
+/// surface, which lies within a voxel of the faces of the cubes but can be further along a ray that grazes them.
+const SURFACE_MARGIN: f32 = 2.0;
+
+/// Times [`RayTracer::surface_hit`] traces a ray again past a corner the smooth surface rounded off.
+const SURFACE_RETRIES: usize = 4;
+
+/// Share of each light that shading leaves on faces turned away from it.
+const AMBIENT: f32 = 0.5;
+
+/// How tightly the highlights of shiny voxels gather around the reflection of a light, looser than the glints of
+/// water as ore is rough.
+const SHININESS: f32 = 12.0;
+
+/// Steps of rounding at a coordinate that [`Config::ray_epsilon`] covers, since finding where a ray crosses a
+/// boundary takes a few operations that each round.
+const ROUNDING_STEPS: f32 = 4.0;
+
+/// Most [`Config::ray_epsilon`] widens to, since at half a voxel rays would count as being on a boundary everywhere
+/// and stop stepping.
+const MAX_EPSILON: f32 = 0.25;
+
 #[derive(Debug, Clone, Copy)]
 /// Ray tracer configuration.
 pub struct Config {
     pub seed: Option<u32>,
     pub size: u32,
+    /// Size along y instead of `size`, so flat scenes can be kept in boxes that aren't as tall as they are wide.
+    pub height: Option<u32>,
+    /// Bounding box of the scene instead of the box around the origin given by `size` and `height`, which the camera
+    /// faces the center of.
+    pub world: Option<IAabb>,
     pub camera_pos: Vec3A,
     pub res_width: usize,
     pub res_height: usize,
     pub debug: bool,
+    /// Let scenes stop tracing at details smaller than a pixel and return their average instead.
+    pub lod: bool,
+    /// Trace 2x2 quads of pixels together as packets of rays, see [`Scene::trace_packet`].
+    pub packets: bool,
+    /// Trace a beam around each tile of pixels first to find where its rays can start, see [`Scene::beam_start`].
+    pub beams: bool,
+    /// Trace a cone as wide as each pixel instead of a ray, see [`Scene::trace_cone`], and shade it with a soft
+    /// shadow.
+    pub cones: bool,
+    /// Distance along rays from the camera before which nothing is hit, see [`Ray::near`].
+    pub near: f32,
+    /// Distance along rays from the camera past which nothing is hit, see [`Ray::far`].
+    pub far: f32,
+    /// Distance, in voxels, within which rays count as being on a boundary between cells, see [`Ray::epsilon`].
+    pub epsilon: f32,
+    /// Trace rays in `f64` instead of `f32`, see [`Scene::trace_hit_f64`], for scenes or cameras far enough from the
+    /// origin that `f32` misses voxels. Cones are still traced in `f32`, and packets and beams are left off.
+    pub double: bool,
+    /// Trace more rays through pixels on the edges of voxels and blend them, see [`RayTracer::render_into`].
+    pub antialias: bool,
+    /// Dither shaded colors when rounding them to whole values, so smooth gradients don't turn into bands. Only
+    /// colors shaded by the ray tracer, by shading, cones or anti-aliasing, are dithered.
+    pub dither: bool,
+    /// Keep the shaded color of every pixel unclamped next to its 8-bit color, so post-processing can tell how far
+    /// past white the brightest ones are, see [`Framebuffer::with_hdr`].
+    pub hdr: bool,
+    /// Light voxels from the sun by the direction their surface faces, with normals that turn smoothly across
+    /// neighboring voxels, see [`Scene::voxel_at`].
+    pub shade: bool,
+    /// Light shaded voxels by the faces of their cubes instead of smooth normals.
+    pub hard_normals: bool,
+    /// Draw the surface where the occupancy of the cells, interpolated between their centers, is a half instead of the
+    /// faces of the cubes, which rounds off the terrain's steps. Cones still draw the cubes.
+    pub smooth_surface: bool,
+    /// Shade in a few flat bands of light and outline the edges of surfaces and the creases between them, from a
+    /// [`GBuffer`] rendered after the image, for an illustrated look.
+    pub toon: bool,
+    /// Draw a layer of clouds in the sky, which shadows the ground below it.
+    pub clouds: Option<CloudSettings>,
+    /// Seconds into an animation, which moves the waves drawn on water when shading.
+    pub time: f32,
+    /// Light the scene by day, or dimly by the moon under a night sky with stars.
+    pub time_of_day: TimeOfDay,
+    /// Render by tracing paths bouncing around the scene instead of shading, see [`path`].
+    pub path: Option<PathSettings>,
+    /// Draw the patterns of light the waves focus onto the beds of water when shading, seeing through the top of
+    /// shallow water to them, see [`photon`].
+    pub caustics: Option<CausticSettings>,
+    /// Let light through thin layers of snow and water when shading, so their edges glow with the sun behind them
+    /// instead of looking painted on, see [`RayTracer::surface_light`].
+    pub subsurface: bool,
+    /// Fill the valleys of the scene with mist and its caves with haze, lit by the lights that reach it, see [`fog`].
+    /// Path renders leave it out.
+    pub fog: Option<FogSettings>,
+    /// See through voxels that let light through, such as water, leaves and glass, by how much of it each voxel's width
+    /// of them stops, see [`VoxelKind::opacity`], and let the light through them tinted by their color where shadows
+    /// are cast, see [`RayTracer::light_through`]. Path and cone renders don't see through them.
+    pub transparency: bool,
+}
+
+impl Config {
+    /// Bounding box of the voxels collected into the scene.
+    pub fn bounds(&self) -> IAabb {
+        if let Some(world) = self.world {
+            return world;
+        }
+        let size = self.size as i32;
+        let height = self.height.map_or(size, |height| height as i32);
+        IAabb::new(IVec3::ZERO, IVec3::new(size, height, size))
+    }
+
+    /// [`Config::epsilon`], or more for scenes or cameras far enough from the origin that rounding their coordinates
+    /// takes more than that, up to a quarter of a voxel.
+    ///
+    /// Rays traced in `f64` are moved next to the box they enter before being stepped through it in `f32`, so only
+    /// the size of the scene counts for them.
+    pub fn ray_epsilon(&self) -> f32 {
+        let bounds = self.bounds();
+        let scale = if self.double {
+            2.0 * bounds.extents.max_element() as f32
+        } else {
+            bounds
+                .min()
+                .abs()
+                .max(bounds.max().abs())
+                .as_vec3a()
+                .max(self.camera_pos.abs())
+                .max_element()
+        };
+        self.epsilon
+            .max((scale * ROUNDING_STEPS * f32::EPSILON).min(MAX_EPSILON))
+    }
 }
 
 impl Default for Config {
@@ -92,10 +1151,35 @@ impl Default for Config {
         Self {
             seed: None,
             size: 100,
+            height: None,
+            world: None,
             camera_pos: 100.0 * Vec3A::ONE,
             res_width: 1920,
             res_height: 1080,
             debug: false,
+            lod: false,
+            packets: false,
+            beams: false,
+            cones: false,
+            near: NEAR,
+            far: f32::INFINITY,
+            epsilon: ON_BOUNDARY,
+            double: false,
+            antialias: false,
+            dither: false,
+            hdr: false,
+            shade: false,
+            hard_normals: false,
+            smooth_surface: false,
+            toon: false,
+            clouds: None,
+            time: 0.0,
+            time_of_day: TimeOfDay::Day,
+            path: None,
+            caustics: None,
+            subsurface: false,
+            fog: None,
+            transparency: false,
         }
     }
 }
@@ -103,13 +1187,1185 @@ impl Default for Config {
 /// A scene is a data structure for the voxel data.
 ///
 /// Since there is overlap between the data structures,
-/// we can abstract the functionality into a trait.
+/// we can abstract the functionality into a trait. Scenes can be used as trait objects, which is how
+/// [`dynamic::DynScene`] picks a storage at runtime.
 pub trait Scene {
-    /// Collects voxels from a generator.
-    fn from_voxels(generator: &VoxelGenerator, bb: IAabb) -> Self;
+    /// Collects voxels inside of the bounding box from a source.
+    fn from_voxels<S: VoxelSource + ?Sized>(source: &S, bb: IAabb) -> Self
+    where
+        Self: Sized;
 
-    /// Trace a ray into the scene to get voxel information.
+    /// Builds the scene a config describes, with terrain generated from its seed inside of its bounds.
+    ///
+    /// Building takes much longer than rendering, so a scene built once can be rendered any number of times, from any
+    /// number of cameras, with [`RayTracer::with_scene`].
+    fn build(config: &Config) -> Self
+    where
+        Self: Sized,
+    {
+        let generator = config
+            .seed
+            .map(VoxelGenerator::new_from_seed)
+            .unwrap_or_default();
+        Self::from_voxels(&generator, config.bounds())
+    }
+
+    /// Trace a ray into the scene to get the voxel it hits and how far along the ray it is.
     ///
     /// `debug` flag enables an alternative debug render mode, if available.
-    fn trace(&self, ray: Ray, debug: bool) -> Option<Voxel>;
+    fn trace_hit(&self, ray: Ray, debug: bool) -> Option<Hit>;
+
+    /// Trace a ray into the scene to get voxel information.
+    fn trace(&self, ray: Ray, debug: bool) -> Option<Voxel> {
+        self.trace_hit(ray, debug).map(|hit| hit.voxel)
+    }
+
+    /// Trace a ray in double precision, for scenes or cameras too far from the origin for `f32`, see [`DRay`].
+    ///
+    /// Scenes that can find where it enters them and step through their grids in `f64` override this, and the rest
+    /// trace it rounded to `f32`.
+    fn trace_hit_f64(&self, ray: DRay, debug: bool) -> Option<Hit> {
+        self.trace_hit(ray.relative(DVec3::ZERO, 0.0), debug)
+    }
+
+    /// Trace a ray that can't hit anything closer than `start`, such as one of the rays of a [`Scene::beam_start`].
+    ///
+    /// Scenes that can skip ahead to `start` override this, and the rest trace the whole ray.
+    fn trace_hit_from(&self, ray: Ray, start: f32, debug: bool) -> Option<Hit> {
+        let _ = start;
+        self.trace_hit(ray, debug)
+    }
+
+    /// Trace a packet of rays cast close together, such as through neighbouring pixels, giving the same hits as
+    /// tracing each of them from `start`.
+    ///
+    /// Scenes that can share work between coherent rays override this, and the rest trace the rays one by one.
+    fn trace_packet(&self, rays: &[Ray; PACKET], start: f32, debug: bool) -> [Option<Hit>; PACKET] {
+        rays.map(|ray| self.trace_hit_from(ray, start, debug))
+    }
+
+    /// Distance along every ray of a beam before which none of them can hit anything, so they can all be traced
+    /// from there with [`Scene::trace_hit_from`].
+    ///
+    /// Zero for scenes that can't tell, which leaves the rays to be traced from the start.
+    fn beam_start(&self, beam: Beam) -> f32 {
+        let _ = beam;
+        0.0
+    }
+
+    /// Trace a cone, a ray whose spread sets how wide it gets, blending the colors it passes through by how much of
+    /// it they cover until it is opaque.
+    ///
+    /// Scenes that average their voxels override this to blend in details narrower than the cone, and the rest give
+    /// the hit of the cone's axis, covering all of it.
+    fn trace_cone(&self, ray: Ray, debug: bool) -> ConeHit {
+        self.trace_hit(ray, debug)
+            .map(ConeHit::opaque)
+            .unwrap_or_default()
+    }
+
+    /// Voxel filling the cell from `cell` to `cell + 1` in the space rays are traced in, found by tracing a ray
+    /// from the middle of the cell that ends a boundary's width later.
+    ///
+    /// Shading looks up the cells around every point it lights, see [`Config::shade`], so scenes that can find a
+    /// voxel by its position without tracing override this.
+    fn voxel_at(&self, cell: IVec3) -> Option<Voxel> {
+        let ray = Ray {
+            near: 0.0,
+            far: ON_BOUNDARY,
+            ..Ray::new(cell.as_vec3a() + 0.5, Vec3A::Y)
+        };
+        self.trace(ray, false)
+    }
+
+    /// Cell in the space rays are traced in that the voxel at a position of the source the scene was built from
+    /// fills.
+    ///
+    /// Storages differ in which side of each position its voxel fills. Most fill the cell from the position on, and
+    /// those like the octree, which fill the cell below it, override this.
+    fn cell(&self, pos: IVec3) -> IVec3 {
+        pos
+    }
+
+    /// Memory held by the scene, split up to compare storages.
+    fn memory_usage(&self) -> MemoryUsage;
+
+    /// Lookups of the chunks loaded as rays reach them, for scenes that load them on demand.
+    fn cache_stats(&self) -> Option<CacheStats> {
+        None
+    }
+}
+
+/// Memory held by a [`Scene`], counted from the sizes and capacities of its allocations.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    /// Number of pieces the voxels are split into, such as octree branches and bricks, chunks or runs.
+    pub nodes: usize,
+    /// Bytes holding voxels or their palette indices.
+    pub voxel_bytes: usize,
+    /// Bytes spent on finding the voxels rather than holding them, such as branches, grids of pointers, hash tables
+    /// and spare capacity.
+    pub overhead_bytes: usize,
+    /// Bytes of the palette of distinct voxels.
+    pub palette_bytes: usize,
+}
+
+impl MemoryUsage {
+    /// Bytes used in all.
+    pub fn total_bytes(&self) -> usize {
+        self.voxel_bytes + self.overhead_bytes + self.palette_bytes
+    }
+}
+
+impl fmt::Display for MemoryUsage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mib = |bytes: usize| bytes as f64 / (1024.0 * 1024.0);
+        write!(
+            f,
+            "{:.2} MiB in {} nodes ({:.2} MiB voxels, {:.2} MiB overhead, {:.2} MiB palette)",
+            mib(self.total_bytes()),
+            self.nodes,
+            mib(self.voxel_bytes),
+            mib(self.overhead_bytes),
+            mib(self.palette_bytes),
+        )
+    }
+}
+
+/// Bytes of a hash map's table, which holds a control byte next to every slot.
+fn hash_map_bytes<K, V>(map: &HashMap<K, V>) -> usize {
+    map.capacity() * (mem::size_of::<(K, V)>() + 1)
+}
+
+/// A scene whose voxels can be changed after it was built, without collecting it again.
+///
+/// Positions are the same as in the source the scene was built from.
+pub trait SceneMut: Scene {
+    /// Sets or clears a voxel, returning the voxel that was there before.
+    ///
+    /// Positions outside the scene are ignored.
+    fn set(&mut self, pos: IVec3, voxel: Option<Voxel>) -> Option<Voxel>;
+
+    /// Clears a voxel, returning it if there was one.
+    fn remove(&mut self, pos: IVec3) -> Option<Voxel> {
+        self.set(pos, None)
+    }
+
+    /// Replaces every voxel inside a bounding box with the voxels from a source.
+    fn update_region<S: VoxelSource + ?Sized>(&mut self, source: &S, bb: IAabb) {
+        let mut column = vec![None; bb.height()];
+        for x in bb.iter_x() {
+            for z in bb.iter_z() {
+                source.column(x, z, bb.iter_y(), &mut column);
+                for (y, voxel) in bb.iter_y().zip(&column) {
+                    self.set(IVec3::new(x, y, z), *voxel);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use glam::{U8Vec3, Vec3A};
+
+    use super::{
+        dense::DenseStorage,
+        hash::HashStorage,
+        octree::{DagStorage, SparseStorage},
+        *,
+    };
+    use crate::voxel::{grid::VoxelGrid, water::WATER, LAVA};
+
+    fn config() -> Config {
+        Config {
+            seed: Some(0),
+            size: 10,
+            camera_pos: 20.0 * Vec3A::ONE,
+            res_width: 40,
+            res_height: 30,
+            ..Default::default()
+        }
+    }
+
+    fn pixels(fb: &Framebuffer, config: &Config) -> Vec<u32> {
+        (0..config.res_height)
+            .flat_map(|y| (0..config.res_width).map(move |x| (x, y)))
+            .map(|(x, y)| fb.pixel_mut(x, y).load(Ordering::Relaxed))
+            .collect()
+    }
+
+    #[test]
+    fn scenes_render_from_many_cameras() {
+        let config = config();
+        let mut ray_tracer = RayTracer::with_scene(
+            SparseStorage::build(&config),
+            Camera::looking_at(20, 10, 15.0 * Vec3A::X, Vec3A::ZERO),
+            config,
+        );
+        assert_eq!(ray_tracer.config.res_width, 20);
+        let side = ray_tracer.render();
+
+        // moving the camera gives the same image as building the scene again for it
+        ray_tracer.set_camera(Camera::looking_at(
+            config.res_width,
+            config.res_height,
+            config.camera_pos,
+            Vec3A::ZERO,
+        ));
+        let moved = ray_tracer.render();
+        let built = RayTracer::<SparseStorage>::new(config).render();
+        assert_eq!(pixels(&moved, &config), pixels(&built, &config));
+
+        let side_config = Config {
+            res_width: 20,
+            res_height: 10,
+            camera_pos: 15.0 * Vec3A::X,
+            ..config
+        };
+        let rebuilt = RayTracer::from_scene(side_config, ray_tracer.into_scene()).render();
+        assert_eq!(pixels(&rebuilt, &side_config), pixels(&side, &side_config));
+    }
+
+    #[test]
+    fn render_into_reuses_framebuffer() {
+        let config = config();
+        let fb = Framebuffer::new(config.res_width, config.res_height);
+        let mut ray_tracer = RayTracer::<SparseStorage>::new(config);
+        ray_tracer.render_into(&fb);
+        assert_eq!(pixels(&fb, &config), pixels(&ray_tracer.render(), &config));
+
+        // every pixel of the last frame is overwritten, including ones that now miss
+        ray_tracer.set_camera(Camera::looking_at(
+            config.res_width,
+            config.res_height,
+            Vec3A::new(30.0, 0.0, 0.0),
+            Vec3A::new(60.0, 0.0, 0.0),
+        ));
+        ray_tracer.render_into(&fb);
+        assert!(pixels(&fb, &config).iter().all(|&color| color == 0));
+    }
+
+    #[test]
+    #[should_panic(expected = "framebuffer size")]
+    fn render_into_checks_size() {
+        let config = config();
+        RayTracer::<DenseStorage>::new(config).render_into(&Framebuffer::new(1, 1));
+    }
+
+    #[test]
+    fn hdr_keeps_colors_past_white() {
+        let config = Config {
+            shade: true,
+            hdr: true,
+            ..config()
+        };
+        let mut ray_tracer = RayTracer::<SparseStorage>::new(config);
+        ray_tracer.set_lights(vec![Light {
+            intensity: 4.0,
+            ..Light::directional(SUN)
+        }]);
+        let fb = ray_tracer.render();
+        let mut past_white = 0;
+        for (i, color) in pixels(&fb, &config).into_iter().enumerate() {
+            let hdr = fb.hdr(i % config.res_width, i / config.res_width).unwrap();
+            let rgb = UVec3::new(color >> 24, color >> 16 & 0xff, color >> 8 & 0xff);
+            // the 8-bit colors are the unclamped ones rounded
+            let rounded = (255.0 * hdr)
+                .round()
+                .clamp(Vec3A::ZERO, Vec3A::splat(255.0));
+            assert!((rgb.as_vec3a() - rounded).abs().max_element() <= 1.0);
+            if hdr.max_element() > 1.5 {
+                past_white += 1;
+                assert_eq!(rgb.max_element(), 255);
+            }
+        }
+        assert!(past_white > 0);
+
+        // framebuffers only keep them when asked to
+        let ray_tracer = RayTracer::<SparseStorage>::new(Config {
+            hdr: false,
+            ..config
+        });
+        assert_eq!(ray_tracer.render().hdr(0, 0), None);
+    }
+
+    #[test]
+    fn antialias_blends_only_edges() {
+        let config = config();
+        let sharp = RayTracer::<SparseStorage>::new(config).render();
+        let smooth = RayTracer::<SparseStorage>::new(Config {
+            antialias: true,
+            ..config
+        })
+        .render();
+        let (sharp, smooth) = (pixels(&sharp, &config), pixels(&smooth, &config));
+
+        let width = config.res_width;
+        let is_edge = |i: usize| {
+            let (x, y) = (i % width, i / width);
+            [(-1, 0), (1, 0), (0, -1), (0, 1)].iter().any(|&(dx, dy)| {
+                let (nx, ny) = (x as isize + dx, y as isize + dy);
+                (0..width as isize).contains(&nx)
+                    && (0..config.res_height as isize).contains(&ny)
+                    && sharp[ny as usize * width + nx as usize] != sharp[i]
+            })
+        };
+        let mut blended = 0;
+        for i in 0..sharp.len() {
+            if is_edge(i) {
+                blended += (smooth[i] != sharp[i]) as usize;
+            } else {
+                assert_eq!(smooth[i], sharp[i]);
+            }
+        }
+        // some edges only partly cover the sky, so are drawn partly transparent
+        assert!(blended > 0);
+        assert!(smooth
+            .iter()
+            .any(|&color| !matches!(color & 0xff, 0 | 0xff)));
+    }
+
+    #[test]
+    fn blend_weights_colors_by_alpha() {
+        let color = Vec4::new(32.0, 64.0, 96.0, 1.0);
+        assert_eq!(blend(&[color; 3]), color);
+        assert_eq!(blend(&[Vec4::ZERO; 2]), Vec4::ZERO);
+        // misses make the color more transparent rather than darker
+        assert_eq!(
+            blend(&[color, Vec4::ZERO, color, Vec4::ZERO]),
+            Vec4::new(32.0, 64.0, 96.0, 0.5)
+        );
+        assert_eq!(
+            blend(&[Vec4::W, Vec4::new(255.0, 0.0, 0.0, 1.0)]),
+            Vec4::new(127.5, 0.0, 0.0, 1.0)
+        );
+        // colors past white are averaged before they are clamped
+        assert_eq!(
+            blend(&[Vec4::W, Vec4::new(510.0, 0.0, 0.0, 1.0)]),
+            Vec4::new(255.0, 0.0, 0.0, 1.0)
+        );
+    }
+
+    #[test]
+    fn dither_averages_out_over_blocks() {
+        let ray_tracer = RayTracer::<DenseStorage>::new(Config {
+            dither: true,
+            ..config()
+        });
+        for value in [10.0, 10.3, 10.5, 10.97, 254.8] {
+            let sum: u32 = (0..4)
+                .flat_map(|y| (0..4).map(move |x| (x, y)))
+                .map(|(x, y)| quantize(Vec3A::splat(value), ray_tracer.threshold(x, y)).x)
+                .sum();
+            assert!((sum as f32 / 16.0 - value).abs() <= 1.0 / 32.0, "{value}");
+        }
+
+        // without dithering every pixel rounds to the nearest value
+        let ray_tracer = RayTracer::<DenseStorage>::new(config());
+        assert_eq!(ray_tracer.threshold(1, 2), 0.0);
+        assert_eq!(
+            quantize(Vec3A::new(10.3, 10.5, 300.0), 0.0),
+            UVec3::new(10, 11, 255)
+        );
+    }
+
+    #[test]
+    fn dither_changes_shading_by_at_most_one() {
+        let config = Config {
+            cones: true,
+            ..config()
+        };
+        let plain = RayTracer::<SparseStorage>::new(config).render();
+        let dithered = RayTracer::<SparseStorage>::new(Config {
+            dither: true,
+            ..config
+        })
+        .render();
+        let (plain, dithered) = (pixels(&plain, &config), pixels(&dithered, &config));
+        for (a, b) in plain.into_iter().zip(dithered) {
+            for shift in [8, 16, 24] {
+                let (a, b) = ((a >> shift & 0xff) as i32, (b >> shift & 0xff) as i32);
+                assert!((a - b).abs() <= 1);
+            }
+            assert_eq!(a & 0xff, b & 0xff);
+        }
+    }
+
+    #[test]
+    fn shading_darkens_faces_away_from_sun() {
+        let config = config();
+        let flat = pixels(&RayTracer::<SparseStorage>::new(config).render(), &config);
+        let shaded = |config: Config| {
+            let ray_tracer = RayTracer::<SparseStorage>::new(Config {
+                shade: true,
+                ..config
+            });
+            pixels(&ray_tracer.render(), &config)
+        };
+        let smooth = shaded(config);
+        let hard = shaded(Config {
+            hard_normals: true,
+            ..config
+        });
+
+        for (colors, flat) in [&smooth, &hard]
+            .into_iter()
+            .flat_map(|colors| colors.iter().zip(&flat))
+        {
+            assert_eq!(colors & 0xff, flat & 0xff);
+            for shift in [8, 16, 24] {
+                let (color, flat) = (colors >> shift & 0xff, flat >> shift & 0xff);
+                assert!(color <= flat && color as f32 >= (AMBIENT * flat as f32).floor());
+            }
+        }
+        assert!(smooth.iter().zip(&flat).any(|(a, b)| a != b));
+        assert!(smooth.iter().zip(&hard).any(|(a, b)| a != b));
+
+        // tiles light the same hits the same way
+        let tiled = shaded(Config {
+            packets: true,
+            beams: true,
+            ..config
+        });
+        assert_eq!(tiled, smooth);
+    }
+
+    #[test]
+    fn toon_outlines_edges() {
+        let config = config();
+        let flat = pixels(&RayTracer::<SparseStorage>::new(config).render(), &config);
+        let ray_tracer = RayTracer::<SparseStorage>::new(Config {
+            toon: true,
+            ..config
+        });
+        let buffer = ray_tracer.render_gbuffer();
+        for (depth, color) in buffer.depth.iter().zip(&flat) {
+            assert_eq!(depth.is_infinite(), *color == 0);
+        }
+
+        let toon = pixels(&ray_tracer.render(), &config);
+        let outlines = buffer.outlines();
+        assert!(outlines.iter().any(|&outline| outline));
+        for ((color, flat), outline) in toon.iter().zip(&flat).zip(outlines) {
+            assert_eq!(color & 0xff, flat & 0xff);
+            for shift in [8, 16, 24] {
+                let (color, flat) = (
+                    (color >> shift & 0xff) as f32,
+                    (flat >> shift & 0xff) as f32,
+                );
+                if outline {
+                    assert!(color <= (toon::OUTLINE * flat).round());
+                } else {
+                    assert!(color >= (AMBIENT * flat).floor());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn clouds_shadow_and_hide_the_ground() {
+        let config = config();
+        let clear = pixels(&RayTracer::<SparseStorage>::new(config).render(), &config);
+        let cloudy = |altitude: f32| {
+            let config = Config {
+                clouds: Some(CloudSettings {
+                    altitude,
+                    thickness: 4.0,
+                    coverage: 1.0,
+                    density: 0.5,
+                    scale: 4.0,
+                }),
+                ..config
+            };
+            pixels(&RayTracer::<SparseStorage>::new(config).render(), &config)
+        };
+        let channels = |color: u32| [24, 16, 8].map(|shift| color >> shift & 0xff);
+
+        // above the camera, clouds shadow the ground and show in the sky
+        let above = cloudy(30.0);
+        for (color, clear) in above.iter().zip(&clear) {
+            if *clear == 0 {
+                continue;
+            }
+            assert_eq!(color & 0xff, 0xff);
+            assert!(channels(*color)
+                .iter()
+                .zip(channels(*clear))
+                .all(|(a, b)| *a <= b));
+        }
+        assert!(above.iter().zip(&clear).any(|(a, b)| a != b && *b != 0));
+        assert!(above
+            .iter()
+            .zip(&clear)
+            .any(|(a, b)| *a & 0xff > 0 && *b == 0));
+
+        // below it, they cover the ground seen through them in white
+        let below = cloudy(12.0);
+        let brightness = |colors: &[u32]| {
+            colors
+                .iter()
+                .map(|&c| channels(c).iter().sum::<u32>())
+                .sum::<u32>()
+        };
+        assert!(brightness(&below) > brightness(&clear));
+
+        // and debug renders leave them out
+        let debug = Config {
+            debug: true,
+            ..config
+        };
+        let debug_clouds = Config {
+            clouds: Some(CloudSettings::default()),
+            ..debug
+        };
+        assert_eq!(
+            pixels(&RayTracer::<SparseStorage>::new(debug).render(), &debug),
+            pixels(
+                &RayTracer::<SparseStorage>::new(debug_clouds).render(),
+                &debug
+            )
+        );
+    }
+
+    #[test]
+    fn night_is_dark_and_blue() {
+        let config = config();
+        let render =
+            |config: Config| pixels(&RayTracer::<SparseStorage>::new(config).render(), &config);
+        let night = |config: Config| {
+            render(Config {
+                time_of_day: TimeOfDay::Night,
+                ..config
+            })
+        };
+        let channels = |color: u32| [24, 16, 8, 0].map(|shift| color >> shift & 0xff);
+        for cones in [false, true] {
+            let config = Config { cones, ..config };
+            let (day, night) = (render(config), night(config));
+            for (day, night) in day.iter().zip(&night) {
+                // the sky is drawn behind the scene
+                let [r, g, b, a] = channels(*night);
+                assert_eq!(a, 0xff);
+                // which is darker, and bluer
+                let [day_r, day_g, day_b, day_a] = channels(*day);
+                if day_a == 0xff {
+                    assert!(r < day_r.max(1) && g < day_g.max(1) && b <= day_b);
+                    assert!(b * day_r >= r * day_b, "{night:08x} {day:08x}");
+                }
+            }
+        }
+
+        // shading lights faces by how they face the moon instead of the sun, so moonlight isn't just dimmer
+        let shade = |config: Config| Config {
+            shade: true,
+            hard_normals: true,
+            ..config
+        };
+        let (lit, moonlit) = (render(shade(config)), night(shade(config)));
+        let ratios = lit
+            .iter()
+            .zip(&moonlit)
+            .filter(|(lit, _)| *lit & 0xff == 0xff && channels(**lit)[2] > 0)
+            .map(|(lit, moonlit)| channels(*moonlit)[2] as f32 / channels(*lit)[2] as f32);
+        let (min, max) = ratios.fold((f32::INFINITY, 0.0f32), |(min, max), ratio| {
+            (min.min(ratio), max.max(ratio))
+        });
+        assert!(max < 1.0 && max > 1.1 * min, "{min} {max}");
+
+        // and debug renders are left as they are
+        let debug = Config {
+            debug: true,
+            ..config
+        };
+        assert_eq!(render(debug), night(debug));
+    }
+
+    #[test]
+    fn lights_replace_the_sun() {
+        let config = Config {
+            shade: true,
+            ..config()
+        };
+        let render = |lights: Vec<Light>, config: Config| {
+            let mut ray_tracer = RayTracer::<SparseStorage>::new(config);
+            ray_tracer.set_lights(lights);
+            pixels(&ray_tracer.render(), &config)
+        };
+        let channels = |color: u32| [24, 16, 8, 0].map(|shift| color >> shift & 0xff);
+
+        // the sun as a light of its own lights the scene as before, as do two of it at half the brightness, and
+        // without lights the sun comes back
+        let sun = render(vec![], config);
+        assert_eq!(render(vec![Light::directional(SUN)], config), sun);
+        let half = Light {
+            intensity: 0.5,
+            ..Light::directional(SUN)
+        };
+        assert_eq!(render(vec![half, half], config), sun);
+
+        // a red lamp over the scene lights it red
+        let lamp = Light {
+            color: Vec3A::X,
+            intensity: 400.0,
+            ..Light::point(Vec3A::new(0.0, 15.0, 0.0))
+        };
+        let red = render(vec![lamp], config);
+        for (red, sun) in red.iter().zip(&sun) {
+            let [_, g, b, a] = channels(*red);
+            assert_eq!((g, b, a), (0, 0, sun & 0xff));
+        }
+        assert!(red.iter().any(|color| channels(*color)[0] > 0));
+        // and a spot light turned away from it leaves it dark
+        let torch = Light::spot(Vec3A::new(0.0, 15.0, 0.0), Vec3A::Y, 0.5);
+        for (dark, sun) in render(vec![torch], config).iter().zip(&sun) {
+            assert_eq!(*dark, sun & 0xff);
+        }
+
+        // only lights casting shadows shadow cone renders, here from low over the hills
+        let cones = Config {
+            cones: true,
+            ..config
+        };
+        let low = Light::directional(Vec3A::new(-1.0, 0.3, -0.2).normalize());
+        let shadowed = render(vec![low], cones);
+        let unshadowed = render(
+            vec![Light {
+                shadows: false,
+                ..low
+            }],
+            cones,
+        );
+        for (shadowed, unshadowed) in shadowed.iter().zip(&unshadowed) {
+            let (shadowed, unshadowed) = (channels(*shadowed), channels(*unshadowed));
+            assert!((0..4).all(|i| shadowed[i] <= unshadowed[i]));
+        }
+        assert_ne!(shadowed, unshadowed);
+    }
+
+    #[test]
+    fn path_renders_repeat_and_follow_the_lights() {
+        let config = Config {
+            path: Some(PathSettings {
+                samples: 4,
+                ..Default::default()
+            }),
+            ..config()
+        };
+        let render = |lights: Vec<Light>| {
+            let mut ray_tracer = RayTracer::<SparseStorage>::new(config);
+            ray_tracer.set_lights(lights);
+            pixels(&ray_tracer.render(), &config)
+        };
+        let brightness = |pixels: &[u32]| -> u32 {
+            pixels
+                .iter()
+                .map(|color| (color >> 24) + (color >> 16 & 0xff) + (color >> 8 & 0xff))
+                .sum()
+        };
+
+        // the same pixels and seed trace the same paths
+        let sun = render(vec![]);
+        assert_eq!(render(vec![]), sun);
+        let shaded = Config {
+            shade: true,
+            path: None,
+            ..config
+        };
+        assert_ne!(
+            sun,
+            pixels(&RayTracer::<SparseStorage>::new(shaded).render(), &shaded)
+        );
+
+        // a brighter sun brightens the scene
+        let bright = render(vec![Light {
+            intensity: 2.0,
+            ..Light::directional(SUN)
+        }]);
+        assert!(brightness(&bright) > brightness(&sun));
+    }
+
+    #[test]
+    fn path_bounces_light_corners() {
+        // a floor with a wall along one side, both gray
+        let gray = Voxel::new(U8Vec3::splat(160), VoxelKind::STONE);
+        let mut grid = VoxelGrid::new(IVec3::splat(10));
+        for x in 0..10 {
+            for z in 0..10 {
+                grid.set(IVec3::new(x, 0, z), Some(gray));
+                grid.set(IVec3::new(0, x, z), Some(gray));
+            }
+        }
+        let render = |bounces: u32| {
+            let config = Config {
+                path: Some(PathSettings {
+                    samples: 8,
+                    bounces,
+                }),
+                ..config()
+            };
+            let pixels = pixels(
+                &RayTracer::<SparseStorage>::from_source(config, &grid).render(),
+                &config,
+            );
+            pixels.iter().map(|color| color >> 24).sum::<u32>()
+        };
+
+        // light bounced between the floor and the wall brightens them, even though most paths end early
+        let direct = render(1);
+        assert!(render(8) > direct);
+        assert!(render(2) > direct);
+    }
+
+    #[test]
+    fn lava_lights_path_renders() {
+        // a gray floor with a block of lava in the middle, lit by nothing else
+        let gray = Voxel::new(U8Vec3::splat(160), VoxelKind::STONE);
+        let mut grid = VoxelGrid::new(IVec3::splat(10));
+        for x in 0..10 {
+            for z in 0..10 {
+                grid.set(IVec3::new(x, 0, z), Some(gray));
+            }
+        }
+        for x in 4..6 {
+            for z in 4..6 {
+                grid.set(IVec3::new(x, 1, z), Some(LAVA));
+            }
+        }
+        let day = Config {
+            camera_pos: Vec3A::new(12.0, 9.0, 12.0),
+            res_width: 80,
+            res_height: 60,
+            ..config()
+        };
+        let night = Config {
+            time_of_day: TimeOfDay::Night,
+            ..day
+        };
+
+        // pixels of the floor away from the edges of the lava, so every point spread over them hits the floor
+        let flat = pixels(
+            &RayTracer::<SparseStorage>::from_source(day, &grid).render(),
+            &day,
+        );
+        let (width, height) = (day.res_width, day.res_height);
+        let floor: Vec<usize> = (width..width * (height - 1))
+            .filter(|&i| {
+                [i - width, i - 1, i, i + 1, i + width]
+                    .iter()
+                    .all(|&j| flat[j] == pack_color(Some(gray)))
+            })
+            .collect();
+        let render = |samples: u32, emitters: bool| {
+            let config = Config {
+                path: Some(PathSettings {
+                    samples,
+                    ..Default::default()
+                }),
+                ..night
+            };
+            let mut ray_tracer = RayTracer::<SparseStorage>::from_source(config, &grid);
+            ray_tracer.set_lights(vec![Light {
+                intensity: 0.0,
+                ..Light::directional(SUN)
+            }]);
+            if !emitters {
+                ray_tracer.set_emitters(Emitters::default());
+            }
+            let pixels = pixels(&ray_tracer.render(), &config);
+            floor
+                .iter()
+                .map(|&i| (pixels[i] >> 24) as f32)
+                .collect::<Vec<f32>>()
+        };
+        let total = |pixels: &[f32]| pixels.iter().sum::<f32>();
+        let error = |pixels: &[f32], reference: &[f32]| -> f32 {
+            pixels
+                .iter()
+                .zip(reference)
+                .map(|(a, b)| (a - b).abs())
+                .sum()
+        };
+
+        // picking points on the lava lights the floor as brightly as finding it by bouncing, with less noise
+        let reference = render(128, true);
+        let (picked, bounced) = (render(8, true), render(8, false));
+        assert!(floor.len() > 200);
+        assert!((total(&picked) / total(&reference) - 1.0).abs() < 0.1);
+        assert!((total(&bounced) / total(&reference) - 1.0).abs() < 0.1);
+        assert!(error(&picked, &reference) < 0.5 * error(&bounced, &reference));
+    }
+
+    #[test]
+    fn water_moves_with_time() {
+        let grass = Voxel::new(U8Vec3::new(40, 160, 40), VoxelKind::GRASS);
+        let mut grid = VoxelGrid::new(IVec3::splat(10));
+        for x in 0..10 {
+            for z in 0..10 {
+                for y in 0..5 {
+                    let voxel = if x < 5 { WATER } else { grass };
+                    grid.set(IVec3::new(x, y, z), Some(voxel));
+                }
+            }
+        }
+        let render = |config: Config| {
+            pixels(
+                &RayTracer::<SparseStorage>::from_source(config, &grid).render(),
+                &config,
+            )
+        };
+        let flat = render(config());
+        let shaded = |time: f32| {
+            render(Config {
+                shade: true,
+                time,
+                ..config()
+            })
+        };
+        let (before, after) = (shaded(0.0), shaded(3.0));
+
+        // only the top of the water changes
+        let water = pack_color(Some(WATER));
+        let mut moved = 0;
+        for ((flat, before), after) in flat.iter().zip(&before).zip(&after) {
+            if *flat == water {
+                moved += (before != after) as usize;
+            } else {
+                assert_eq!(before, after);
+            }
+        }
+        assert!(moved > 0);
+        assert_eq!(before, shaded(0.0));
+    }
+
+    #[test]
+    fn caustics_show_the_bed_through_water() {
+        let sand = Voxel::new(U8Vec3::new(220, 200, 150), VoxelKind::SAND);
+        let grass = Voxel::new(U8Vec3::new(40, 160, 40), VoxelKind::GRASS);
+        let mut grid = VoxelGrid::new(IVec3::splat(10));
+        for x in 0..10 {
+            for z in 0..10 {
+                grid.set(IVec3::new(x, 0, z), Some(sand));
+                for y in 1..3 {
+                    let voxel = if x >= 5 { WATER } else { grass };
+                    grid.set(IVec3::new(x, y, z), Some(voxel));
+                }
+            }
+        }
+        let render = |caustics: Option<CausticSettings>| {
+            let config = Config {
+                camera_pos: Vec3A::new(3.0, 8.0, -3.0),
+                shade: true,
+                caustics,
+                ..config()
+            };
+            pixels(
+                &RayTracer::<DenseStorage>::from_source(config, &grid).render(),
+                &config,
+            )
+        };
+        let flat = render(None);
+        let caustics = render(Some(CausticSettings::default()));
+
+        // only water changes, taking on the color of the sand below it
+        let red = |color: u32| (color >> 24) as f32;
+        let (mut before, mut after, mut changed) = (0.0, 0.0, 0);
+        for (flat, caustics) in flat.iter().zip(&caustics) {
+            if flat != caustics {
+                before += red(*flat);
+                after += red(*caustics);
+                changed += 1;
+            }
+        }
+        assert!(changed > 50);
+        assert!(after > 1.5 * before);
+        assert_eq!(caustics, render(Some(CausticSettings::default())));
+    }
+
+    #[test]
+    fn smooth_surface_rounds_off_steps() {
+        let config = config();
+        let blocky = pixels(&RayTracer::<SparseStorage>::new(config).render(), &config);
+        let smooth = |config: Config| {
+            let ray_tracer = RayTracer::<SparseStorage>::new(Config {
+                smooth_surface: true,
+                ..config
+            });
+            pixels(&ray_tracer.render(), &config)
+        };
+        let rounded = smooth(config);
+
+        // the same voxels are drawn, a little further in or out
+        let changed = rounded.iter().zip(&blocky).filter(|(a, b)| a != b).count();
+        assert!(changed > 0 && changed < blocky.len() / 4, "{changed}");
+        let covered = |colors: &[u32]| colors.iter().filter(|&&color| color != 0).count();
+        assert!(covered(&rounded).abs_diff(covered(&blocky)) < blocky.len() / 20);
+
+        let tiled = smooth(Config {
+            packets: true,
+            beams: true,
+            ..config
+        });
+        assert_eq!(tiled, rounded);
+    }
+
+    #[test]
+    fn voxel_at_matches_tracing() {
+        fn check<T: Scene>(config: &Config) {
+            let scene = T::build(config);
+            let bounds = config.bounds();
+            let mut filled = 0;
+            for x in bounds.min().x - 1..=bounds.max().x {
+                for y in bounds.min().y - 1..=bounds.max().y {
+                    for z in bounds.min().z - 1..=bounds.max().z {
+                        let cell = IVec3::new(x, y, z);
+                        let ray = Ray {
+                            near: 0.0,
+                            far: ON_BOUNDARY,
+                            ..Ray::new(cell.as_vec3a() + 0.5, Vec3A::Y)
+                        };
+                        let voxel = scene.voxel_at(cell);
+                        assert_eq!(voxel, scene.trace(ray, false), "{cell}");
+                        filled += voxel.is_some() as usize;
+                    }
+                }
+            }
+            assert!(filled > 0);
+        }
+
+        // a box that isn't a cube is split into several octrees
+        let config = Config {
+            height: Some(4),
+            ..config()
+        };
+        check::<SparseStorage>(&config);
+        check::<DagStorage>(&config);
+        check::<HashStorage>(&config);
+    }
+
+    #[test]
+    fn progressive_matches_full_render() {
+        let config = config();
+        let ray_tracer = RayTracer::<DenseStorage>::new(config);
+
+        let full = ray_tracer.render();
+        let (progressive, block) = ray_tracer.render_progressive(Duration::from_secs(3600));
+
+        assert_eq!(block, 1);
+        assert_eq!(pixels(&full, &config), pixels(&progressive, &config));
+    }
+
+    #[test]
+    fn tiles_match_full_render() {
+        let config = config();
+
+        // an odd size leaves quads hanging over the edge
+        for (res_width, res_height) in [(config.res_width, config.res_height), (41, 29)] {
+            let config = Config {
+                res_width,
+                res_height,
+                ..config
+            };
+            let single = RayTracer::<SparseStorage>::new(config).render();
+            for (packets, beams) in [(true, false), (false, true), (true, true)] {
+                let tiled = RayTracer::<SparseStorage>::new(Config {
+                    packets,
+                    beams,
+                    ..config
+                })
+                .render();
+                assert_eq!(pixels(&tiled, &config), pixels(&single, &config));
+            }
+        }
+    }
+
+    #[test]
+    fn progressive_zero_budget_is_coarse() {
+        let config = config();
+        let ray_tracer = RayTracer::<DenseStorage>::new(config);
+
+        let full = ray_tracer.render();
+        let (progressive, block) = ray_tracer.render_progressive(Duration::ZERO);
+
+        assert_eq!(block, PROGRESSIVE_BLOCK);
+
+        // every pixel takes the color of the top-left pixel of its block
+        for y in 0..config.res_height {
+            for x in 0..config.res_width {
+                let (bx, by) = (x - x % block, y - y % block);
+                assert_eq!(
+                    progressive.pixel_mut(x, y).load(Ordering::Relaxed),
+                    full.pixel_mut(bx, by).load(Ordering::Relaxed)
+                );
+            }
+        }
+    }
+
+    fn check_edits<T: SceneMut + Sync>() {
+        let config = config();
+        let terrain = VoxelGenerator::new_from_seed(0);
+        let empty = VoxelGrid::new(IVec3::ZERO);
+        let full = RayTracer::<T>::new(config).render();
+
+        // clearing everything matches an empty scene, and filling it again matches the terrain
+        let mut ray_tracer = RayTracer::<T>::new(config);
+        ray_tracer
+            .scene_mut()
+            .update_region(&empty, config.bounds());
+        let cleared = RayTracer::<T>::from_source(config, &empty).render();
+        assert_eq!(
+            pixels(&ray_tracer.render(), &config),
+            pixels(&cleared, &config)
+        );
+
+        ray_tracer
+            .scene_mut()
+            .update_region(&terrain, config.bounds());
+        assert_eq!(
+            pixels(&ray_tracer.render(), &config),
+            pixels(&full, &config)
+        );
+
+        let scene = ray_tracer.scene_mut();
+        let pos = IVec3::new(0, 9, 0);
+        let voxel = Voxel::from(U8Vec3::new(255, 0, 0));
+        assert_eq!(scene.set(pos, Some(voxel)), terrain.lookup(pos));
+        assert_eq!(scene.remove(pos), Some(voxel));
+        assert_eq!(scene.remove(pos), None);
+        assert_eq!(scene.set(IVec3::splat(10), Some(voxel)), None);
+        assert_eq!(scene.remove(IVec3::splat(10)), None);
+    }
+
+    #[test]
+    fn dense_edits() {
+        check_edits::<DenseStorage>();
+    }
+
+    #[test]
+    fn sparse_edits() {
+        check_edits::<SparseStorage>();
+    }
+
+    #[test]
+    fn camera_inside_voxel_fills_frame() {
+        let voxel = Voxel::from(U8Vec3::new(0, 255, 0));
+        let mut grid = VoxelGrid::new(IVec3::splat(10)).with_origin(IVec3::splat(-5));
+        for x in 0..10 {
+            for z in 0..10 {
+                for y in 0..5 {
+                    grid.set(IVec3::new(x, y, z), Some(voxel));
+                }
+            }
+        }
+
+        // below the surface, and just above it looking down
+        let buried = Config {
+            camera_pos: Vec3A::new(0.5, -2.5, 0.5),
+            ..config()
+        };
+        let above = Config {
+            camera_pos: Vec3A::new(0.5, 3.5, 0.5),
+            ..config()
+        };
+        let buried = RayTracer::<SparseStorage>::from_source(buried, &grid);
+        let above = RayTracer::<SparseStorage>::from_source(above, &grid);
+        assert_eq!(buried.camera_voxel(), Some(voxel));
+        assert_eq!(above.camera_voxel(), None);
+
+        let fb = buried.render();
+        let color = pack_color(Some(voxel));
+        assert!(pixels(&fb, &buried.config).iter().all(|&c| c == color));
+        let (fb, block) = buried.render_progressive(Duration::ZERO);
+        assert_eq!(block, 1);
+        assert!(pixels(&fb, &buried.config).iter().all(|&c| c == color));
+    }
+
+    #[cfg(feature = "stats")]
+    #[test]
+    fn traversal_stats_count_every_ray() {
+        let config = config();
+        let pixels = (config.res_width * config.res_height) as u64;
+        for (packets, tiles) in [(false, pixels), (true, 6)] {
+            let ray_tracer = RayTracer::<SparseStorage>::new(Config { packets, ..config });
+            ray_tracer.render();
+            let stats = ray_tracer.traversal_stats();
+            assert_eq!(stats.rays, pixels);
+            assert_eq!(stats.tiles, tiles);
+            assert!(stats.nodes > 0 && stats.steps > 0 && stats.depth > 0);
+
+            // each render starts counting over
+            ray_tracer.render();
+            assert_eq!(ray_tracer.traversal_stats(), stats);
+        }
+    }
+
+    #[test]
+    fn ray_epsilon_covers_rounding() {
+        let config = config();
+        assert_eq!(config.ray_epsilon(), config.epsilon);
+
+        // a scene far from the origin needs more than the default to cover a step of rounding at its coordinates
+        let distant = Config {
+            world: Some(IAabb::new(IVec3::splat(100_000), IVec3::splat(10))),
+            camera_pos: Vec3A::splat(100_020.0),
+            ..config
+        };
+        let step = 100_020f32.next_up() - 100_020.0;
+        assert!(step > config.epsilon);
+        assert!(distant.ray_epsilon() > step);
+
+        // but never so much that every position is on a boundary
+        let farther = Config {
+            world: Some(IAabb::new(IVec3::splat(10_000_000), IVec3::splat(10))),
+            camera_pos: Vec3A::splat(10_000_020.0),
+            ..config
+        };
+        assert!(farther.ray_epsilon() < 0.5);
+
+        // rays traced in f64 only round coordinates within the scene
+        let double = Config {
+            double: true,
+            ..farther
+        };
+        assert_eq!(double.ray_epsilon(), config.epsilon);
+    }
+
+    #[test]
+    fn memory_usage() {
+        let config = Config {
+            size: 32,
+            ..config()
+        };
+        let dense = RayTracer::<DenseStorage>::new(config);
+        let sparse = RayTracer::<SparseStorage>::new(config);
+        let dense = dense.scene().memory_usage();
+        let sparse = sparse.scene().memory_usage();
+
+        // a palette index for every cell of the 64 wide box
+        assert_eq!(dense.voxel_bytes, 2 * 64usize.pow(3));
+        assert_eq!(dense.nodes, 1);
+        assert!(dense.palette_bytes > 0);
+
+        assert!(sparse.nodes > 1);
+        assert!(sparse.overhead_bytes > 0);
+        assert!(sparse.total_bytes() < dense.total_bytes());
+        assert_eq!(
+            sparse.total_bytes(),
+            sparse.voxel_bytes + sparse.overhead_bytes + sparse.palette_bytes
+        );
+    }
+
+    #[test]
+    fn metal_ores_shine() {
+        // seen along the reflection of the sun off the top of a voxel
+        let dir = Vec3A::new(SUN.x, -SUN.y, SUN.z);
+        let gold = highlight(VoxelKind::GOLD, Vec3A::Y, dir, SUN);
+        assert!((gold - VoxelKind::GOLD.shine()).abs() < 1e-4);
+        assert!(highlight(VoxelKind::IRON, Vec3A::Y, dir, SUN) < gold);
+        assert_eq!(highlight(VoxelKind::STONE, Vec3A::Y, dir, SUN), 0.0);
+        // and not from the side
+        assert!(highlight(VoxelKind::GOLD, Vec3A::Y, Vec3A::new(-0.6, -0.6, 0.5), SUN) < 0.01);
+    }
 }
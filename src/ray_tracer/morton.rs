@@ -0,0 +1,217 @@
+use std::mem;
+
+use glam::UVec3;
+
+#[cfg(feature = "trace")]
+use tracing::*;
+
+use crate::voxel::VoxelSource;
+
+use super::{
+    dense::march,
+    palette::{PaletteIndex, VoxelPalette},
+    types::{DRay, Hit, IAabb, Ray},
+    MemoryUsage, Scene,
+};
+
+/// Dense storage with voxels in Morton (Z-curve) order, so neighbouring voxels in any direction tend to be close
+/// in memory.
+///
+/// The order is only defined for cubes with power of two sides, so the grid is padded up to one. Voxels past the
+/// last one inside the bounding box are never stored, but boxes much smaller than their padded cube still waste
+/// space on the gaps.
+pub struct MortonStorage {
+    data: Box<[Option<PaletteIndex>]>,
+    palette: VoxelPalette,
+    bb: IAabb,
+}
+
+impl MortonStorage {
+    /// Traces a ray through the grid as if it filled `bb`, a box of the same size.
+    fn trace_hit_in(&self, bb: IAabb, ray: Ray) -> Option<Hit> {
+        let size = bb.max() - bb.min();
+        let (voxel, distance) = march(bb, ray, |pos| {
+            // positions are never negative, but can run past the end of any axis
+            if pos.cmpge(size).any() {
+                return None;
+            }
+            let index = self.data[morton_encode(pos.as_uvec3())];
+            Some(index.map(|index| self.palette.get(index)))
+        })?;
+        Some(Hit { voxel, distance })
+    }
+}
+
+impl Scene for MortonStorage {
+    fn from_voxels<S: VoxelSource + ?Sized>(source: &S, bb: IAabb) -> Self {
+        #[cfg(feature = "trace")]
+        let _span = trace_span!("morton_from_voxels").entered();
+
+        let size = (bb.max() - bb.min()).as_uvec3();
+        let len = size.cmpgt(UVec3::ZERO).all() as usize * (morton_encode(size - 1) + 1);
+
+        let mut palette = VoxelPalette::new();
+        let mut data = vec![None; len];
+        let mut column = vec![None; bb.height()];
+
+        for (i, x) in bb.iter_x().enumerate() {
+            for (k, z) in bb.iter_z().enumerate() {
+                source.column(x, z, bb.iter_y(), &mut column);
+                for (j, voxel) in column.iter().enumerate() {
+                    let pos = UVec3::new(i as u32, j as u32, k as u32);
+                    data[morton_encode(pos)] = voxel.map(|voxel| palette.insert(voxel));
+                }
+            }
+        }
+
+        #[cfg(feature = "trace")]
+        debug!("length" = data.iter().filter(|i| i.is_some()).count());
+
+        Self {
+            data: data.into(),
+            palette,
+            bb,
+        }
+    }
+
+    fn trace_hit(&self, ray: Ray, _debug: bool) -> Option<Hit> {
+        #[cfg(feature = "trace")]
+        let _span = trace_span!("morton_trace").entered();
+
+        self.trace_hit_in(self.bb, ray)
+    }
+
+    fn trace_hit_f64(&self, ray: DRay, _debug: bool) -> Option<Hit> {
+        let (start, ray) = ray.enter(self.bb)?;
+        self.trace_hit_in(self.bb.at_origin(), ray)
+            .map(|hit| hit.after(start))
+    }
+
+    fn memory_usage(&self) -> MemoryUsage {
+        MemoryUsage {
+            nodes: 1,
+            voxel_bytes: mem::size_of_val(&*self.data),
+            overhead_bytes: 0,
+            palette_bytes: self.palette.heap_bytes(),
+        }
+    }
+}
+
+/// Index of a position in Morton order, which interleaves the bits of its coordinates (x in the lowest bit).
+///
+/// Coordinates can use up to 21 bits.
+pub fn morton_encode(pos: UVec3) -> usize {
+    (spread_bits(pos.x) | spread_bits(pos.y) << 1 | spread_bits(pos.z) << 2) as usize
+}
+
+/// Position of an index in Morton order.
+pub fn morton_decode(index: usize) -> UVec3 {
+    let index = index as u64;
+    UVec3::new(
+        compact_bits(index),
+        compact_bits(index >> 1),
+        compact_bits(index >> 2),
+    )
+}
+
+/// Moves the lowest 21 bits of a coordinate to every third bit.
+fn spread_bits(coord: u32) -> u64 {
+    let mut bits = coord as u64 & 0x1f_ffff;
+    bits = (bits | bits << 32) & 0x001f_0000_0000_ffff;
+    bits = (bits | bits << 16) & 0x001f_0000_ff00_00ff;
+    bits = (bits | bits << 8) & 0x100f_00f0_0f00_f00f;
+    bits = (bits | bits << 4) & 0x10c3_0c30_c30c_30c3;
+    bits = (bits | bits << 2) & 0x1249_2492_4924_9249;
+    bits
+}
+
+/// Gathers every third bit back into a coordinate, undoing [`spread_bits`].
+fn compact_bits(index: u64) -> u32 {
+    let mut bits = index & 0x1249_2492_4924_9249;
+    bits = (bits | bits >> 2) & 0x10c3_0c30_c30c_30c3;
+    bits = (bits | bits >> 4) & 0x100f_00f0_0f00_f00f;
+    bits = (bits | bits >> 8) & 0x001f_0000_ff00_00ff;
+    bits = (bits | bits >> 16) & 0x001f_0000_0000_ffff;
+    bits = (bits | bits >> 32) & 0x1f_ffff;
+    bits as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{IVec3, U8Vec3, Vec3A};
+
+    use super::*;
+    use crate::{
+        ray_tracer::dense::DenseStorage,
+        voxel::{grid::VoxelGrid, Voxel, VoxelGenerator},
+    };
+
+    #[test]
+    fn encode() {
+        assert_eq!(morton_encode(UVec3::ZERO), 0);
+        assert_eq!(morton_encode(UVec3::X), 1);
+        assert_eq!(morton_encode(UVec3::Y), 2);
+        assert_eq!(morton_encode(UVec3::Z), 4);
+        assert_eq!(morton_encode(UVec3::ONE), 7);
+        assert_eq!(morton_encode(UVec3::new(2, 0, 0)), 8);
+        assert_eq!(morton_encode(UVec3::new(3, 3, 3)), 63);
+        assert_eq!(morton_encode(UVec3::splat(0x1f_ffff)), (1 << 63) - 1);
+    }
+
+    #[test]
+    fn decode() {
+        for pos in [
+            UVec3::ZERO,
+            UVec3::new(5, 0, 9),
+            UVec3::new(1000, 3, 77),
+            UVec3::splat(0x1f_ffff),
+        ] {
+            assert_eq!(morton_decode(morton_encode(pos)), pos);
+        }
+        for index in 0..4096 {
+            assert_eq!(morton_encode(morton_decode(index)), index);
+        }
+    }
+
+    #[test]
+    fn matches_dense() {
+        let source = VoxelGenerator::new_from_seed(3);
+        let bb = IAabb::new(IVec3::ZERO, 12 * IVec3::ONE);
+        let morton = MortonStorage::from_voxels(&source, bb);
+        let dense = DenseStorage::from_voxels(&source, bb);
+
+        let mut hits = 0;
+        for i in 0..200 {
+            let angle = i as f32 * 0.1;
+            let origin = Vec3A::new(30.0 * angle.cos(), 20.0, 30.0 * angle.sin());
+            let target = Vec3A::new((i % 7) as f32 - 3.0, -4.0, (i % 5) as f32 - 2.0);
+            let ray = Ray::new(origin, target - origin);
+            let voxel = morton.trace(ray, false);
+            assert_eq!(voxel, dense.trace(ray, false), "ray {i}");
+            hits += voxel.is_some() as usize;
+        }
+        assert!(hits > 0);
+    }
+
+    #[test]
+    fn odd_sizes() {
+        // a 6x2x4 box, whose padded cube is 8 wide
+        let size = IVec3::new(6, 2, 4);
+        let bb = IAabb::new(size / 2, size / 2);
+        let voxel = Voxel::from(U8Vec3::ONE);
+        let mut grid = VoxelGrid::new(size);
+        for pos in bb.iter() {
+            grid.set(pos, Some(voxel));
+        }
+
+        let storage = MortonStorage::from_voxels(&grid, bb);
+        assert_eq!(storage.data.len(), 104);
+        assert_eq!(storage.data.iter().filter(|i| i.is_some()).count(), 48);
+
+        // across the longest side
+        let ray = Ray::new(Vec3A::new(-5.0, 1.5, 3.5), Vec3A::X);
+        assert_eq!(storage.trace(ray, false), Some(voxel));
+        let ray = Ray::new(Vec3A::new(10.0, 1.5, 3.5), Vec3A::NEG_X);
+        assert_eq!(storage.trace(ray, false), Some(voxel));
+    }
+}
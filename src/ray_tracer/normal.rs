@@ -0,0 +1,103 @@
+use glam::{IVec3, Vec3A};
+
+/// Normal of the face of a cube that a ray going in direction `dir` entered at `point`, the face of its cell that
+/// the point is closest to.
+///
+/// The point is moved `epsilon` along the ray first, so that it is inside the cell it entered rather than on the
+/// boundary with the one before.
+pub(super) fn hard(point: Vec3A, dir: Vec3A, epsilon: f32) -> Vec3A {
+    let local = point - (point + epsilon * dir).floor();
+    let to_face = local.min(1.0 - local);
+    let axis = match to_face.x <= to_face.y {
+        true if to_face.x <= to_face.z => 0,
+        false if to_face.y <= to_face.z => 1,
+        _ => 2,
+    };
+    let mut normal = Vec3A::ZERO;
+    normal[axis] = -dir[axis].signum();
+    normal
+}
+
+/// Normal at a point from how the cells around it are filled, given whether the cell at each position holds a
+/// voxel, or `None` where they are filled evenly on every side.
+///
+/// The gradient of the occupancy is taken at the centers of the 8 cells around the point, as the difference
+/// between the neighbors on either side along each axis, and blended by how close the point is to each. Normals
+/// turn gradually from one voxel to the next instead of at their edges, so a staircase of voxels is lit like the
+/// slope it stands for.
+pub(super) fn smooth(point: Vec3A, occupied: impl Fn(IVec3) -> bool) -> Option<Vec3A> {
+    let corner = point - 0.5;
+    let t = corner - corner.floor();
+    // cells from one before the 8 around the point to one after, skipping the corners and edges of the block,
+    // which no difference reaches
+    let min = corner.floor().as_ivec3() - 1;
+    let mut filled = [[[0.0; 4]; 4]; 4];
+    for (x, plane) in filled.iter_mut().enumerate() {
+        for (y, row) in plane.iter_mut().enumerate() {
+            for (z, cell) in row.iter_mut().enumerate() {
+                let outside = [x, y, z].iter().filter(|&&i| i == 0 || i == 3).count();
+                if outside <= 1 && occupied(min + IVec3::new(x as i32, y as i32, z as i32)) {
+                    *cell = 1.0;
+                }
+            }
+        }
+    }
+    let filled = |cell: IVec3| filled[cell.x as usize][cell.y as usize][cell.z as usize];
+
+    let mut gradient = Vec3A::ZERO;
+    for i in 0..8 {
+        let offset = IVec3::new(i & 1, i >> 1 & 1, i >> 2 & 1);
+        let side = offset.as_vec3a();
+        let weight = (side * t + (1.0 - side) * (1.0 - t)).element_product();
+        let cell = offset + 1;
+        let difference = Vec3A::new(
+            filled(cell + IVec3::X) - filled(cell - IVec3::X),
+            filled(cell + IVec3::Y) - filled(cell - IVec3::Y),
+            filled(cell + IVec3::Z) - filled(cell - IVec3::Z),
+        );
+        gradient += weight * difference;
+    }
+    (-gradient).try_normalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hard_normals_face_the_ray() {
+        let normal = hard(Vec3A::new(2.3, 5.0, -1.6), Vec3A::new(0.2, -0.9, 0.1), 1e-4);
+        assert_eq!(normal, Vec3A::Y);
+        let normal = hard(Vec3A::new(2.0, 4.5, -1.6), Vec3A::new(1.0, 0.0, 0.0), 1e-4);
+        assert_eq!(normal, -Vec3A::X);
+        let normal = hard(Vec3A::new(0.5, 0.9, 3.0), Vec3A::new(0.0, 0.6, -0.8), 1e-4);
+        assert_eq!(normal, Vec3A::Z);
+    }
+
+    #[test]
+    fn smooth_normals_follow_the_surface() {
+        // flat ground is lit straight from above anywhere on it
+        let ground = |cell: IVec3| cell.y < 0;
+        for point in [Vec3A::new(0.5, 0.0, 0.5), Vec3A::new(3.1, 0.0, -7.9)] {
+            let normal = smooth(point, ground).unwrap();
+            assert!(normal.abs_diff_eq(Vec3A::Y, 1e-6), "{normal}");
+        }
+        assert_eq!(smooth(Vec3A::new(0.5, -4.0, 0.5), ground), None);
+
+        // on the top of a lone voxel, normals lean out toward its edges and turn without a seam
+        let voxel = |cell: IVec3| cell == IVec3::ZERO;
+        let normal = |x: f32| smooth(Vec3A::new(x, 1.0, 0.5), voxel).unwrap();
+        assert!(normal(0.5).abs_diff_eq(Vec3A::Y, 1e-6));
+        assert!(normal(0.1).x < 0.0 && normal(0.9).x > 0.0);
+        assert!(normal(0.9).x > normal(0.7).x);
+        assert!(normal(0.5 - 1e-4).abs_diff_eq(normal(0.5 + 1e-4), 1e-3));
+
+        // steps of a staircase lean toward the slope they climb
+        let stairs = |cell: IVec3| cell.y < -cell.x;
+        let normal = smooth(Vec3A::new(0.5, 0.0, 0.5), stairs).unwrap();
+        assert!(
+            normal.x > 0.2 && normal.y > 0.2 && normal.z == 0.0,
+            "{normal}"
+        );
+    }
+}
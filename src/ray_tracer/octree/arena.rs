@@ -0,0 +1,69 @@
+use std::ops::{Index, IndexMut};
+
+/// Storage for the branches or bricks of an octree, which refer to their children by index instead of by pointer.
+#[derive(Debug)]
+pub(super) struct Arena<T> {
+    items: Vec<T>,
+}
+
+impl<T> Arena<T> {
+    /// Creates an arena with room for `capacity` items.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            items: Vec::with_capacity(capacity.max(1)),
+        }
+    }
+
+    /// Adds an item, returning its id.
+    pub fn alloc(&mut self, item: T) -> u32 {
+        let id = u32::try_from(self.items.len()).expect("too many octree nodes");
+        self.items.push(item);
+        id
+    }
+
+    /// Makes room for at least `total` items, with some headroom so growing estimates don't reallocate every time.
+    pub fn reserve_total(&mut self, total: usize) {
+        if total > self.capacity() {
+            let total = total + total / 8;
+            self.items.reserve_exact(total - self.items.len());
+        }
+    }
+
+    /// Extrapolates the final size of the arena while filling `total` slices of a scene, if the next slices might
+    /// not fit.
+    ///
+    /// `before` is the length before the last few slices, and `done` is the number of slices filled so far.
+    pub fn reserve_ahead(&mut self, before: usize, done: usize, total: usize) {
+        let added = self.items.len() - before;
+        if done < total && self.items.len() + added > self.capacity() {
+            let expected = self.items.len() * total / done;
+            self.reserve_total(expected.max(self.items.len() + 2 * added));
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.items.capacity()
+    }
+
+    pub fn shrink_to_fit(&mut self) {
+        self.items.shrink_to_fit();
+    }
+}
+
+impl<T> Index<usize> for Arena<T> {
+    type Output = T;
+
+    fn index(&self, idx: usize) -> &T {
+        &self.items[idx]
+    }
+}
+
+impl<T> IndexMut<usize> for Arena<T> {
+    fn index_mut(&mut self, idx: usize) -> &mut T {
+        &mut self.items[idx]
+    }
+}
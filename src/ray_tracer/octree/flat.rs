@@ -0,0 +1,386 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fs,
+    io::{self, BufWriter, Write},
+    mem,
+    path::Path,
+};
+
+use glam::{IVec3, U8Vec3};
+
+use crate::{
+    archive::ArchiveError,
+    ray_tracer::{
+        binary::{write_header, write_u32},
+        pack_color,
+        types::IAabb,
+    },
+    voxel::{Voxel, VoxelKind},
+};
+
+use super::{brick_index, has, is_brick, occupied, Brick, Lod, Octree};
+
+const MAGIC: &[u8; 8] = b"VOXFLATO";
+/// Version of the flattened octree file format.
+const VERSION: u32 = 1;
+/// Bytes taken by the header of a file, so the branches after it start on a 64-byte boundary.
+const HEADER_SIZE: usize = 64;
+
+/// An octree flattened into arrays of fixed-size branches and bricks, ready to be uploaded to GPU buffers or
+/// memory-mapped from a file.
+///
+/// Branches are laid out breadth first with the root at index zero, and children refer to each other by index
+/// into the arrays. Every branch and brick reachable from the root appears once, so branches and bricks shared by a
+/// deduplicated octree stay shared. Everything is stored in 32-bit words, since shaders have no smaller integers.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FlatOctree {
+    /// Bounding box of the root branch.
+    bb: IAabb,
+    nodes: Vec<FlatNode>,
+    bricks: Vec<FlatBrick>,
+    /// Color (packed as RGBA) and kind of the voxel at each palette index, with an empty slot at index zero.
+    palette: Vec<[u32; 2]>,
+    /// Whether the averages of branches and bricks are filled in.
+    averaged: bool,
+}
+
+/// A branch of a [`FlatOctree`], which takes up exactly one 64-byte cache line.
+#[repr(C, align(64))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FlatNode {
+    /// Child in each octant: the index of a branch or brick, or the palette index of the voxel filling a solid
+    /// octant. Empty octants hold zero.
+    pub children: [u32; 8],
+    /// Occupied octants in the lowest byte, the ones of those filled with a single voxel in the next byte and the
+    /// ones holding bricks in the third.
+    pub masks: u32,
+    /// Average color of the branch packed as RGB, with how much of it is occupied (out of 255) as alpha.
+    pub average: u32,
+    /// Kind occupying most of the branch.
+    pub kind: u32,
+}
+
+impl FlatNode {
+    /// Occupied octants.
+    pub fn mask(&self) -> u8 {
+        self.masks as u8
+    }
+
+    /// Octants filled with a single voxel.
+    pub fn solid(&self) -> u8 {
+        (self.masks >> 8) as u8
+    }
+
+    /// Octants holding bricks.
+    pub fn bricks(&self) -> u8 {
+        (self.masks >> 16) as u8
+    }
+}
+
+/// A 4x4x4 brick of a [`FlatOctree`], with voxels indexed by `x + 4 * (y + 4 * z)` like in the octree.
+#[repr(C, align(64))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FlatBrick {
+    /// Occupied voxels, with the first 32 in the first word.
+    pub mask: [u32; 2],
+    /// Palette indices of the voxels two to a word, with the lower index in the lower half, or zero where empty.
+    pub voxels: [u32; 32],
+    /// Average color of the brick packed like [`FlatNode::average`].
+    pub average: u32,
+    /// Kind occupying most of the brick.
+    pub kind: u32,
+}
+
+impl From<&Octree> for FlatOctree {
+    fn from(octree: &Octree) -> Self {
+        let root = octree.root();
+        let mut nodes = Vec::with_capacity(octree.nodes.len());
+        let mut bricks = Vec::with_capacity(octree.bricks.len());
+
+        // new indices of the branches and bricks queued so far, where branches are told apart by size as well
+        // since a deduplicated branch could turn up at several levels
+        let mut node_ids = HashMap::from([((0, root.extents.x), 0)]);
+        let mut brick_ids = HashMap::new();
+        let mut queue = VecDeque::from([(0, root)]);
+        while let Some((idx, bb)) = queue.pop_front() {
+            let node = &octree.nodes[idx];
+            let (average, kind) = pack_lod(node.lod);
+            let mut flat = FlatNode {
+                average,
+                kind,
+                ..FlatNode::default()
+            };
+            let mut in_bricks = 0;
+            for local_idx in occupied(node.mask) {
+                let child = node.children[local_idx];
+                let next_bb = bb.octant(local_idx);
+                flat.children[local_idx] = if has(node.solid, local_idx) {
+                    child
+                } else if is_brick(next_bb) {
+                    in_bricks |= 1 << local_idx;
+                    *brick_ids.entry(child).or_insert_with(|| {
+                        bricks.push(FlatBrick::from(&octree.bricks[child as usize]));
+                        bricks.len() as u32 - 1
+                    })
+                } else {
+                    let next_id = node_ids.len() as u32;
+                    *node_ids
+                        .entry((child, next_bb.extents.x))
+                        .or_insert_with(|| {
+                            queue.push_back((child as usize, next_bb));
+                            next_id
+                        })
+                };
+            }
+            flat.masks = u32::from_le_bytes([node.mask, node.solid & node.mask, in_bricks, 0]);
+            nodes.push(flat);
+        }
+
+        let palette = std::iter::once([0; 2])
+            .chain(
+                octree
+                    .palette
+                    .voxels()
+                    .iter()
+                    .map(|voxel| [pack_color(Some(*voxel)), voxel.kind.0 as u32]),
+            )
+            .collect();
+
+        Self {
+            bb: root,
+            nodes,
+            bricks,
+            palette,
+            averaged: octree.averaged,
+        }
+    }
+}
+
+impl From<&Brick> for FlatBrick {
+    fn from(brick: &Brick) -> Self {
+        let (average, kind) = pack_lod(brick.lod);
+        let mut flat = Self {
+            mask: [brick.mask as u32, (brick.mask >> 32) as u32],
+            average,
+            kind,
+            ..Self::default()
+        };
+        for i in 0..brick.voxels.len() {
+            if let Some(voxel) = brick.get(i) {
+                flat.voxels[i / 2] |= (voxel.get() as u32) << (i % 2 * 16);
+            }
+        }
+        flat
+    }
+}
+
+impl FlatOctree {
+    /// Bounding box of the root branch, which holds the voxels from one past its minimum corner up to its maximum
+    /// corner.
+    pub fn bb(&self) -> IAabb {
+        self.bb
+    }
+
+    /// Branches, with the root at index zero.
+    pub fn nodes(&self) -> &[FlatNode] {
+        &self.nodes
+    }
+
+    pub fn bricks(&self) -> &[FlatBrick] {
+        &self.bricks
+    }
+
+    /// Color (packed as RGBA) and kind of the voxel at each palette index, starting with an empty slot at index
+    /// zero.
+    pub fn palette(&self) -> &[[u32; 2]] {
+        &self.palette
+    }
+
+    /// Looks up a voxel by walking down the arrays, the same way a shader reading them would.
+    pub fn get(&self, pos: IVec3) -> Option<Voxel> {
+        self.bb.index_of(pos)?;
+
+        let mut node = &self.nodes[0];
+        let mut bb = self.bb;
+        loop {
+            let idx = bb.index_of(pos).expect("voxel is in the root");
+            bb = bb.octant(idx);
+
+            let child = node.children[idx] as usize;
+            if !has(node.mask(), idx) {
+                return None;
+            }
+            if has(node.solid(), idx) {
+                return Some(self.voxel(child));
+            }
+            if has(node.bricks(), idx) {
+                let i = brick_index(pos - bb.min() - IVec3::ONE);
+                let index = self.bricks[child].voxels[i / 2] >> (i % 2 * 16) & 0xffff;
+                return (index != 0).then(|| self.voxel(index as usize));
+            }
+            node = &self.nodes[child];
+        }
+    }
+
+    /// Unpacks the voxel at a palette index.
+    fn voxel(&self, index: usize) -> Voxel {
+        let [color, kind] = self.palette[index];
+        let [r, g, b, _] = color.to_be_bytes();
+        Voxel::new(U8Vec3::new(r, g, b), VoxelKind(kind as u16))
+    }
+
+    /// Saves the flattened octree to a file.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), ArchiveError> {
+        let mut file = BufWriter::new(fs::File::create(path)?);
+        self.write(&mut file)?;
+        file.flush()?;
+        Ok(())
+    }
+
+    /// Writes the flattened octree laid out the same as in memory, so the file can be memory-mapped.
+    ///
+    /// Files start with a header (magic, version, bounding box of the root, the numbers of branches, bricks and
+    /// palette entries and whether the averages are filled in) padded to 64 bytes. The branches, bricks and palette
+    /// follow as little-endian words, with branches and bricks padded to their size in memory so every one of them
+    /// starts on a 64-byte boundary.
+    pub fn write(&self, mut writer: impl Write) -> io::Result<()> {
+        let mut header = Vec::with_capacity(HEADER_SIZE);
+        write_header(&mut header, MAGIC, VERSION, self.bb)?;
+        for count in [self.nodes.len(), self.bricks.len(), self.palette.len()] {
+            write_u32(&mut header, count as u32)?;
+        }
+        write_u32(&mut header, self.averaged as u32)?;
+        header.resize(HEADER_SIZE, 0);
+        writer.write_all(&header)?;
+
+        for node in &self.nodes {
+            let words = node
+                .children
+                .into_iter()
+                .chain([node.masks, node.average, node.kind]);
+            write_padded(&mut writer, words, mem::size_of::<FlatNode>())?;
+        }
+        for brick in &self.bricks {
+            let words = brick
+                .mask
+                .into_iter()
+                .chain(brick.voxels)
+                .chain([brick.average, brick.kind]);
+            write_padded(&mut writer, words, mem::size_of::<FlatBrick>())?;
+        }
+        for entry in &self.palette {
+            write_padded(&mut writer, *entry, mem::size_of_val(entry))?;
+        }
+        Ok(())
+    }
+}
+
+/// Packs the average of a branch or brick into its color and kind words.
+fn pack_lod(lod: Lod) -> (u32, u32) {
+    let [r, g, b] = lod.color.to_array();
+    (
+        u32::from_be_bytes([r, g, b, lod.coverage]),
+        lod.kind.0 as u32,
+    )
+}
+
+/// Writes words followed by zeros up to `size` bytes.
+fn write_padded(
+    writer: &mut impl Write,
+    words: impl IntoIterator<Item = u32>,
+    size: usize,
+) -> io::Result<()> {
+    let mut written = 0;
+    for word in words {
+        write_u32(writer, word)?;
+        written += mem::size_of::<u32>();
+    }
+    writer.write_all(&vec![0; size - written])
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::voxel::VoxelGenerator;
+
+    use super::*;
+
+    #[test]
+    fn nodes_fill_cache_lines() {
+        assert_eq!(mem::size_of::<FlatNode>(), 64);
+        assert_eq!(mem::align_of::<FlatNode>(), 64);
+        assert_eq!(mem::size_of::<FlatBrick>() % 64, 0);
+        assert_eq!(mem::align_of::<FlatBrick>(), 64);
+    }
+
+    #[test]
+    fn flattens_every_voxel() {
+        let source = VoxelGenerator::new_from_seed(3);
+        let bb = IAabb::new(IVec3::ZERO, 12 * IVec3::ONE);
+        let mut octree = Octree::from_voxels(&source, bb);
+
+        for step in 0..3 {
+            match step {
+                1 => octree.collapse(),
+                2 => octree.dedup(),
+                _ => {}
+            }
+
+            let flat = FlatOctree::from(&octree);
+            assert!(flat.nodes.len() <= octree.nodes.len(), "step {step}");
+            assert!(flat.bricks.len() <= octree.bricks.len(), "step {step}");
+            assert_eq!(flat.nodes.as_ptr() as usize % 64, 0);
+            for pos in flat.bb.iter() {
+                assert_eq!(flat.get(pos), octree.get(pos), "step {step}, {pos}");
+            }
+        }
+    }
+
+    #[test]
+    fn shared_branches_stay_shared() {
+        let mut octree = Octree::new(IAabb::new(IVec3::ZERO, 8 * IVec3::ONE));
+        let water = Voxel::from(U8Vec3::new(0, 0, 200));
+        for x in -15..=16 {
+            for z in -15..=16 {
+                octree.insert(IVec3::new(x, 0, z), water);
+            }
+        }
+        octree.collapse();
+        octree.dedup();
+
+        // one branch for each level, each shared by all of the octants of the level above along the water
+        let flat = FlatOctree::from(&octree);
+        assert_eq!(flat.nodes.len(), 3);
+        assert_eq!(flat.bricks.len(), 1);
+        assert_eq!(flat.nodes[0].mask(), 0b0011_0011);
+        assert_eq!(flat.nodes[0].bricks(), 0);
+        assert_eq!(flat.nodes[0].children, [1, 1, 0, 0, 1, 1, 0, 0]);
+        assert_eq!(flat.nodes[1].children, [0, 0, 2, 2, 0, 0, 2, 2]);
+        assert_eq!(flat.nodes[2].bricks(), 0b1100_1100);
+        assert_eq!(flat.get(IVec3::new(3, 0, -7)), Some(water));
+        assert_eq!(flat.get(IVec3::new(3, 1, -7)), None);
+    }
+
+    #[test]
+    fn writes_aligned_arrays() {
+        let source = VoxelGenerator::new_from_seed(3);
+        let mut octree = Octree::from_voxels(&source, IAabb::new(IVec3::ZERO, 8 * IVec3::ONE));
+        octree.collapse();
+        let flat = FlatOctree::from(&octree);
+
+        let mut file = Vec::new();
+        flat.write(&mut file).expect("failed to write");
+        let bricks_start = HEADER_SIZE + flat.nodes.len() * 64;
+        let palette_start = bricks_start + flat.bricks.len() * mem::size_of::<FlatBrick>();
+        assert_eq!(file.len(), palette_start + flat.palette.len() * 8);
+        assert_eq!(&file[..8], MAGIC);
+
+        let word = |at: usize| u32::from_le_bytes(file[at..at + 4].try_into().unwrap());
+        let root = flat.nodes[0];
+        for (i, child) in root.children.into_iter().enumerate() {
+            assert_eq!(word(HEADER_SIZE + 4 * i), child);
+        }
+        assert_eq!(word(HEADER_SIZE + 32), root.masks);
+        assert_eq!(word(bricks_start + 8), flat.bricks[0].voxels[0]);
+        assert_eq!(word(palette_start + 8), flat.palette[1][0]);
+    }
+}
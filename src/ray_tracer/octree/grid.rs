@@ -0,0 +1,266 @@
+use glam::{DVec3, IVec3};
+
+use crate::voxel::{Voxel, VoxelSource};
+
+use super::{
+    super::{
+        dense::{march, march_f64},
+        types::{Beam, ConeHit, DRay, Hit, IAabb, Ray, PACKET},
+        MemoryUsage,
+    },
+    Octree, BRICK_SIZE,
+};
+
+/// Octrees lying side by side to cover a bounding box that isn't a cube.
+///
+/// A single octree would be a cube as wide as the longest side of the box, which wastes levels on empty space for
+/// flat and wide scenes such as terrain. Instead the box is split into cubes as wide as its shortest side (rounded up
+/// to a power of two), and rays step through the grid of cubes in order, tracing each octree they pass through until
+/// one is hit. Boxes that are already cubes keep a single octree.
+pub(super) struct OctreeGrid {
+    /// Octrees with x changing fastest.
+    octrees: Box<[Octree]>,
+    /// Number of octrees along each axis.
+    cells: IVec3,
+    /// Minimum corner of the root of the first octree.
+    min: IVec3,
+    /// Side length of every octree.
+    side: i32,
+}
+
+impl OctreeGrid {
+    /// Collects the voxels inside of a bounding box into as many octrees as it takes to cover it with cubes.
+    pub(super) fn from_voxels<S: VoxelSource + ?Sized>(source: &S, bb: IAabb) -> Self {
+        if bb.extents.cmpeq(IVec3::splat(bb.extents.x)).all() {
+            let octree = Octree::from_voxels(source, bb);
+            let root = octree.root();
+            return Self {
+                octrees: Box::new([octree]),
+                cells: IVec3::ONE,
+                min: root.min(),
+                side: root.width() as i32,
+            };
+        }
+
+        // the root is always a branch, so cubes can't be smaller than two bricks
+        let side = (2 * bb.extents.min_element()).max(2 * BRICK_SIZE) as u32;
+        let side = side.next_power_of_two() as i32;
+        let cells = (bb.max() - bb.min() + side - 1) / side;
+
+        let mut octrees = Vec::with_capacity(cells.element_product() as usize);
+        for z in 0..cells.z {
+            for y in 0..cells.y {
+                for x in 0..cells.x {
+                    let min = bb.min() + side * IVec3::new(x, y, z);
+                    let max = (min + side).min(bb.max());
+                    let part = IAabb::new((min + max) / 2, (max - min) / 2);
+                    // octrees hold the voxel at each position in the cell below it
+                    let root = IAabb::new(min - 1 + side / 2, IVec3::splat(side / 2));
+                    octrees.push(Octree::from_voxels_in(root, source, part));
+                }
+            }
+        }
+
+        Self {
+            octrees: octrees.into(),
+            cells,
+            min: bb.min() - 1,
+            side,
+        }
+    }
+
+    /// Every octree, with x changing fastest, then y and then z.
+    pub(super) fn octrees(&self) -> &[Octree] {
+        &self.octrees
+    }
+
+    pub(super) fn octrees_mut(&mut self) -> &mut [Octree] {
+        &mut self.octrees
+    }
+
+    /// Octree holding the voxel at a position, if any.
+    pub(super) fn octree_mut(&mut self, pos: IVec3) -> Option<&mut Octree> {
+        let i = self.index(pos)?;
+        Some(&mut self.octrees[i])
+    }
+
+    /// Voxel at a position, if any.
+    pub(super) fn get(&self, pos: IVec3) -> Option<Voxel> {
+        self.octrees[self.index(pos)?].get(pos)
+    }
+
+    /// Index of the octree holding the voxel at a position, if any.
+    fn index(&self, pos: IVec3) -> Option<usize> {
+        let cell = (pos - 1 - self.min).div_euclid(IVec3::splat(self.side));
+        if cell.cmplt(IVec3::ZERO).any() || cell.cmpge(self.cells).any() {
+            return None;
+        }
+        Some((cell.x + self.cells.x * (cell.y + self.cells.y * cell.z)) as usize)
+    }
+
+    /// Traces a ray through each octree it passes in turn, or draws the edges of their branches if `debug` is set.
+    pub(super) fn trace(&self, ray: Ray, debug: bool) -> Option<Hit> {
+        self.trace_from(ray, 0.0, debug)
+    }
+
+    /// Traces a ray that can't hit anything before `start`, which the debug render ignores.
+    pub(super) fn trace_from(&self, ray: Ray, start: f32, debug: bool) -> Option<Hit> {
+        self.march(ray, |octree| {
+            if debug {
+                octree.debug_trace(ray)
+            } else {
+                octree.trace_hit_from(ray, start)
+            }
+        })
+    }
+
+    /// Traces a ray in `f64` through each octree it passes in turn, or draws the edges of their branches in `f32` if
+    /// `debug` is set.
+    pub(super) fn trace_f64(&self, ray: DRay, debug: bool) -> Option<Hit> {
+        if debug {
+            return self.trace(ray.relative(DVec3::ZERO, 0.0), debug);
+        }
+        if let [octree] = &*self.octrees {
+            return octree.trace_hit_f64(ray);
+        }
+
+        // same as `march`
+        let padded = (self.cells + 1) / 2;
+        let grid = IAabb::new(padded, padded);
+        let scaled = ray.scaled(self.min.as_dvec3(), self.side as f64);
+        let (hit, _) = march_f64(grid, scaled, |cell| {
+            if cell.cmpge(self.cells).any() {
+                return cell.cmplt(grid.max()).all().then_some(None);
+            }
+            let i = cell.x + self.cells.x * (cell.y + self.cells.y * cell.z);
+            Some(self.octrees[i as usize].trace_hit_f64(ray))
+        })?;
+        Some(hit)
+    }
+
+    /// Blends the colors a cone passes through each octree it reaches in turn, until it is opaque.
+    pub(super) fn trace_cone(&self, ray: Ray) -> ConeHit {
+        let mut cone = ConeHit::default();
+        self.march(ray, |octree| {
+            octree.trace_cone(ray, &mut cone);
+            cone.is_opaque().then_some(())
+        });
+        cone
+    }
+
+    /// Visits the octrees a ray passes through in order, until `visit` returns a value.
+    fn march<T>(&self, ray: Ray, mut visit: impl FnMut(&Octree) -> Option<T>) -> Option<T> {
+        if let [octree] = &*self.octrees {
+            return visit(octree);
+        }
+
+        // march through the octrees in a space where each one is a unit wide, padding the grid to an even size
+        let padded = (self.cells + 1) / 2;
+        let grid = IAabb::new(padded, padded);
+        let scaled = ray.scaled(self.min.as_vec3a(), self.side as f32);
+        let (value, _) = march(grid, scaled, |cell| {
+            if cell.cmpge(self.cells).any() {
+                // the padding is empty, and past it the ray has left the grid
+                return cell.cmplt(grid.max()).all().then_some(None);
+            }
+            let i = cell.x + self.cells.x * (cell.y + self.cells.y * cell.z);
+            Some(visit(&self.octrees[i as usize]))
+        })?;
+        Some(value)
+    }
+
+    /// Traces a packet of rays together through a single octree, or one ray at a time through a grid of them.
+    pub(super) fn trace_packet(
+        &self,
+        rays: &[Ray; PACKET],
+        start: f32,
+        debug: bool,
+    ) -> [Option<Hit>; PACKET] {
+        match &*self.octrees {
+            [octree] if !debug => octree.trace_packet(rays, start),
+            _ => rays.map(|ray| self.trace_from(ray, start, debug)),
+        }
+    }
+
+    /// Distance along every ray of a beam before which none of them can hit a voxel in any of the octrees.
+    pub(super) fn beam_start(&self, beam: Beam) -> f32 {
+        self.octrees
+            .iter()
+            .map(|octree| octree.beam_start(beam))
+            .fold(f32::INFINITY, f32::min)
+    }
+
+    /// Memory held by all of the octrees together.
+    pub(super) fn memory_usage(&self) -> MemoryUsage {
+        self.octrees.iter().map(Octree::memory_usage).fold(
+            MemoryUsage::default(),
+            |total, usage| MemoryUsage {
+                nodes: total.nodes + usage.nodes,
+                voxel_bytes: total.voxel_bytes + usage.voxel_bytes,
+                overhead_bytes: total.overhead_bytes + usage.overhead_bytes,
+                palette_bytes: total.palette_bytes + usage.palette_bytes,
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{U8Vec3, Vec3A};
+
+    use super::*;
+    use crate::{
+        ray_tracer::{octree::SparseStorage, Scene, SceneMut},
+        voxel::{grid::VoxelGrid, Voxel, VoxelGenerator},
+    };
+
+    #[test]
+    fn matches_one_octree() {
+        let source = VoxelGenerator::new_from_seed(3);
+        let bb = IAabb::new(IVec3::new(0, 4, 0), IVec3::new(40, 12, 24));
+        let mut storage = SparseStorage::from_voxels(&source, bb);
+        let mut octree = Octree::from_voxels(&source, bb);
+
+        // split into 32 wide cubes, instead of one 128 wide cube
+        assert_eq!(storage.octrees.cells, IVec3::new(3, 1, 2));
+        assert!(storage.octrees().iter().all(|o| o.root().width() == 32));
+        assert_eq!(octree.root().width(), 128);
+        assert_eq!(
+            storage.octrees().iter().map(Octree::len).sum::<usize>(),
+            octree.len()
+        );
+
+        // an edit to the top of the scene, in the last cube
+        let red = Voxel::from(U8Vec3::new(255, 0, 0));
+        let pos = IVec3::new(30, 15, 20);
+        storage.set(pos, Some(red));
+        octree.set(pos, Some(red));
+        let ray = Ray::new(Vec3A::new(29.5, 40.0, 19.5), Vec3A::NEG_Y);
+        assert_eq!(storage.trace(ray, false), Some(red));
+
+        let mut hits = 0;
+        for i in 0..200 {
+            let angle = i as f32 * 0.1;
+            let origin = Vec3A::new(80.0 * angle.cos(), 40.0, 80.0 * angle.sin());
+            let target = Vec3A::new((i % 7) as f32 * 5.0, 0.0, (i % 5) as f32 * 4.0);
+            let ray = Ray::new(origin, target - origin);
+            let voxel = storage.trace(ray, false);
+            assert_eq!(voxel, octree.trace(ray), "ray {i}");
+            hits += voxel.is_some() as usize;
+        }
+        assert!(hits > 0);
+    }
+
+    #[test]
+    fn leaves_through_far_sides() {
+        let empty = VoxelGrid::new(IVec3::ONE);
+        let storage =
+            SparseStorage::from_voxels(&empty, IAabb::new(IVec3::ZERO, IVec3::new(40, 12, 24)));
+        assert_eq!(storage.octrees.cells, IVec3::new(3, 1, 2));
+
+        for dir in [Vec3A::X, Vec3A::Y, Vec3A::Z, Vec3A::new(1.0, 0.2, 0.3)] {
+            let ray = Ray::new(Vec3A::new(-0.5, -0.5, -0.5) - dir * 50.0, dir);
+            assert_eq!(storage.trace(ray, false), None, "{dir}");
+        }
+    }
+}
@@ -1,46 +1,233 @@
-use std::{fmt, num::NonZeroUsize};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, fs,
+    io::{self, BufReader, BufWriter, Read, Write},
+    mem,
+    path::Path,
+};
 
-use glam::{IVec3, U8Vec3};
+use glam::{IVec3, U8Vec3, Vec3A};
 
-use crate::voxel::{Voxel, VoxelGenerator};
+use crate::{
+    archive::ArchiveError,
+    voxel::{Voxel, VoxelKind, VoxelSource},
+};
 
+#[cfg(feature = "stats")]
+use super::stats;
 #[cfg(feature = "trace")]
 use tracing::*;
 
+mod arena;
+mod flat;
+mod grid;
 mod lookup_table;
 
+use arena::Arena;
+pub use flat::{FlatBrick, FlatNode, FlatOctree};
+use grid::OctreeGrid;
+
 use super::{
-    types::{IAabb, Ray},
-    Scene,
+    binary::{
+        read_header, read_palette, read_u16, read_u32, read_u64, read_u8, write_header,
+        write_palette, write_u32,
+    },
+    palette::{PaletteIndex, VoxelPalette},
+    types::{Beam, ConeHit, DRay, Hit, IAabb, Ray, PACKET},
+    MemoryUsage, Scene, SceneMut,
 };
 
+/// Side length of the bricks at the bottom of the octree.
+const BRICK_SIZE: i32 = 4;
+
+const MAGIC: &[u8; 8] = b"VOXOCTRE";
+/// Version of the octree file format.
+const VERSION: u32 = 1;
+
+/// Least coverage (out of 255) of a branch or brick smaller than a pixel for its average to be drawn, so rays still
+/// pass through mostly empty space.
+const LOD_COVERAGE: u8 = 128;
+
 pub struct SparseStorage {
-    octree: Octree,
+    octrees: OctreeGrid,
+    /// Bounds of the scene, which the octrees round up to cubes.
+    bb: IAabb,
+}
+
+impl SparseStorage {
+    /// Octrees holding the scene's voxels, for saving them with [`Octree::save`].
+    ///
+    /// Scenes that are cubes have a single octree, and others have one for each cube they are split into.
+    pub fn octrees(&self) -> &[Octree] {
+        self.octrees.octrees()
+    }
 }
 
 impl Scene for SparseStorage {
-    fn from_voxels(generator: &VoxelGenerator, bb: IAabb) -> Self {
-        let octree = Octree::from_voxels(generator, bb);
+    fn from_voxels<S: VoxelSource + ?Sized>(source: &S, bb: IAabb) -> Self {
+        let mut octrees = OctreeGrid::from_voxels(source, bb);
+        for octree in octrees.octrees_mut() {
+            octree.collapse();
+            octree.reorder();
+        }
 
         #[cfg(feature = "trace")]
-        debug!("length" = octree.len());
+        debug!("length" = octrees.octrees().iter().map(Octree::len).sum::<usize>());
+
+        Self { octrees, bb }
+    }
+
+    fn trace_hit(&self, ray: Ray, debug: bool) -> Option<Hit> {
+        self.octrees.trace(ray, debug)
+    }
 
-        Self { octree }
+    fn trace_hit_from(&self, ray: Ray, start: f32, debug: bool) -> Option<Hit> {
+        self.octrees.trace_from(ray, start, debug)
     }
 
-    fn trace(&self, ray: Ray, debug: bool) -> Option<Voxel> {
+    fn trace_hit_f64(&self, ray: DRay, debug: bool) -> Option<Hit> {
+        self.octrees.trace_f64(ray, debug)
+    }
+
+    fn trace_packet(&self, rays: &[Ray; PACKET], start: f32, debug: bool) -> [Option<Hit>; PACKET] {
+        self.octrees.trace_packet(rays, start, debug)
+    }
+
+    fn beam_start(&self, beam: Beam) -> f32 {
+        self.octrees.beam_start(beam)
+    }
+
+    fn trace_cone(&self, ray: Ray, debug: bool) -> ConeHit {
         if debug {
-            self.octree.debug_trace(ray)
+            self.trace_hit(ray, debug)
+                .map(ConeHit::opaque)
+                .unwrap_or_default()
         } else {
-            self.octree.trace(ray)
+            self.octrees.trace_cone(ray)
+        }
+    }
+
+    fn cell(&self, pos: IVec3) -> IVec3 {
+        pos - 1
+    }
+
+    fn voxel_at(&self, cell: IVec3) -> Option<Voxel> {
+        self.octrees.get(cell + 1)
+    }
+
+    fn memory_usage(&self) -> MemoryUsage {
+        self.octrees.memory_usage()
+    }
+}
+
+impl SceneMut for SparseStorage {
+    fn set(&mut self, pos: IVec3, voxel: Option<Voxel>) -> Option<Voxel> {
+        if !self.bb.contains(pos) {
+            return None;
         }
+        self.octrees.octree_mut(pos)?.set(pos, voxel)
+    }
+}
+
+/// Sparse storage with identical subtrees merged, which uses less memory for repetitive scenes.
+pub struct DagStorage {
+    octrees: OctreeGrid,
+}
+
+impl DagStorage {
+    /// Deduplicated octrees holding the scene's voxels, for saving them with [`Octree::save`].
+    ///
+    /// Subtrees are only merged within each octree.
+    pub fn octrees(&self) -> &[Octree] {
+        self.octrees.octrees()
+    }
+}
+
+impl Scene for DagStorage {
+    fn from_voxels<S: VoxelSource + ?Sized>(source: &S, bb: IAabb) -> Self {
+        let mut octrees = OctreeGrid::from_voxels(source, bb);
+        for octree in octrees.octrees_mut() {
+            octree.collapse();
+            octree.dedup();
+            octree.reorder();
+        }
+
+        #[cfg(feature = "trace")]
+        debug!(
+            "nodes" = octrees
+                .octrees()
+                .iter()
+                .map(|o| o.nodes.len())
+                .sum::<usize>(),
+            "bricks" = octrees
+                .octrees()
+                .iter()
+                .map(|o| o.bricks.len())
+                .sum::<usize>()
+        );
+
+        Self { octrees }
+    }
+
+    fn trace_hit(&self, ray: Ray, debug: bool) -> Option<Hit> {
+        self.octrees.trace(ray, debug)
+    }
+
+    fn trace_hit_from(&self, ray: Ray, start: f32, debug: bool) -> Option<Hit> {
+        self.octrees.trace_from(ray, start, debug)
+    }
+
+    fn trace_hit_f64(&self, ray: DRay, debug: bool) -> Option<Hit> {
+        self.octrees.trace_f64(ray, debug)
+    }
+
+    fn trace_packet(&self, rays: &[Ray; PACKET], start: f32, debug: bool) -> [Option<Hit>; PACKET] {
+        self.octrees.trace_packet(rays, start, debug)
+    }
+
+    fn beam_start(&self, beam: Beam) -> f32 {
+        self.octrees.beam_start(beam)
+    }
+
+    fn trace_cone(&self, ray: Ray, debug: bool) -> ConeHit {
+        if debug {
+            self.trace_hit(ray, debug)
+                .map(ConeHit::opaque)
+                .unwrap_or_default()
+        } else {
+            self.octrees.trace_cone(ray)
+        }
+    }
+
+    fn cell(&self, pos: IVec3) -> IVec3 {
+        pos - 1
+    }
+
+    fn voxel_at(&self, cell: IVec3) -> Option<Voxel> {
+        self.octrees.get(cell + 1)
+    }
+
+    fn memory_usage(&self) -> MemoryUsage {
+        self.octrees.memory_usage()
     }
 }
 
 /// Simple octree implementation with fixed size.
+///
+/// Branches end in 4x4x4 bricks of voxels, which are traversed with a DDA instead of more levels of branches.
+/// Bricks store indices into the octree's palette. Regions filled with a single voxel can be collapsed into solid
+/// octants with [`Octree::collapse`], which also averages every branch and brick for rays that cover more than a
+/// pixel's worth of them.
 pub struct Octree {
     bb: IAabb,
-    nodes: Vec<Node>,
+    /// Branches, with the root at index zero.
+    nodes: Arena<Node>,
+    bricks: Arena<Brick>,
+    palette: VoxelPalette,
+    /// Whether branches and bricks may have more than one parent, after [`Octree::dedup`].
+    shared: bool,
+    /// Whether the averages of branches and bricks match their voxels, after [`Octree::collapse`].
+    averaged: bool,
 }
 
 impl fmt::Debug for Octree {
@@ -48,42 +235,32 @@ impl fmt::Debug for Octree {
         let mut set = f.debug_set();
 
         fn fmt_node(
+            octree: &Octree,
             idx: usize,
             bb: IAabb,
-            nodes: &[Node],
             set: &mut fmt::DebugSet<'_, '_>,
         ) -> fmt::Result {
-            match nodes[idx] {
-                Node::Branch(branches) => {
-                    for z in 0..2 {
-                        for y in 0..2 {
-                            for x in 0..2 {
-                                let local_idx = x | y << 1 | z << 2;
-                                let Some(next_idx) = branches[local_idx] else {
-                                    continue;
-                                };
-                                let next_bb = bb.octant(local_idx);
-                                fmt_node(next_idx.get(), next_bb, nodes, set)?;
-                            }
-                        }
+            let node = &octree.nodes[idx];
+            for local_idx in occupied(node.mask) {
+                let next_idx = node.children[local_idx] as usize;
+                let next_bb = bb.octant(local_idx);
+                if has(node.solid, local_idx) {
+                    let voxel = octree.palette.get(solid_index(node.children[local_idx]));
+                    for pos in next_bb.iter() {
+                        set.entry(&(pos + IVec3::ONE, voxel));
                     }
+                    continue;
                 }
-                Node::Leaf(leaves) => {
-                    for z in 0..2 {
-                        for y in 0..2 {
-                            for x in 0..2 {
-                                let local_idx = x | y << 1 | z << 2;
-                                let Some(leaf) = leaves[local_idx] else {
-                                    continue;
-                                };
-                                let pos = IVec3::new(
-                                    x as i32 + bb.origin.x,
-                                    y as i32 + bb.origin.x,
-                                    z as i32 + bb.origin.x,
-                                );
-                                set.entry(&(pos, leaf));
-                            }
-                        }
+                if !is_brick(next_bb) {
+                    fmt_node(octree, next_idx, next_bb, set)?;
+                    continue;
+                }
+
+                let brick = &octree.bricks[next_idx];
+                for i in 0..brick.voxels.len() {
+                    if let Some(voxel) = brick.get(i) {
+                        let pos = next_bb.min() + IVec3::ONE + brick_pos(i);
+                        set.entry(&(pos, octree.palette.get(voxel)));
                     }
                 }
             }
@@ -91,7 +268,7 @@ impl fmt::Debug for Octree {
             Ok(())
         }
 
-        fmt_node(0, self.bb, &self.nodes, &mut set)?;
+        fmt_node(self, 0, self.root(), &mut set)?;
 
         set.finish()
     }
@@ -99,277 +276,1281 @@ impl fmt::Debug for Octree {
 
 impl Octree {
     pub fn new(bb: IAabb) -> Self {
+        Self::with_capacity(bb, 1)
+    }
+
+    /// Creates an octree with room for `capacity` bricks before it has to reallocate.
+    pub fn with_capacity(bb: IAabb, capacity: usize) -> Self {
+        // Octrees are cubes with sides of power of two length, so make sure we have a cube that can store the requested space.
+        Self::with_root(bb.next_pow2(), capacity)
+    }
+
+    /// Creates an octree whose root is a cube with sides of power of two length, which holds the voxels at positions
+    /// from one past its minimum corner up to its maximum corner.
+    fn with_root(bb: IAabb, capacity: usize) -> Self {
         #[cfg(feature = "trace")]
         let _span = trace_span!("octree_new").entered();
 
-        // Octrees are cubes with sides of power of two length, so make sure we have a cube that can store the requested space.
-        let bb = bb.next_pow2();
+        // a branch for every few bricks
+        let mut nodes = Arena::with_capacity(capacity / 3 + 1);
+        nodes.alloc(Node::default());
+
         Self {
             bb,
-            nodes: vec![Node::from_aabb(bb)], // always will be branches, but this handles an edge case of extents being zero
+            nodes,
+            bricks: Arena::with_capacity(capacity),
+            palette: VoxelPalette::new(),
+            shared: false,
+            averaged: false,
         }
     }
 
-    pub fn from_voxels(generator: &VoxelGenerator, bb: IAabb) -> Self {
+    pub fn from_voxels<S: VoxelSource + ?Sized>(source: &S, bb: IAabb) -> Self {
+        Self::from_voxels_in(bb.next_pow2(), source, bb)
+    }
+
+    /// Collects the voxels inside of a bounding box into an octree with the given root, which has to hold the box.
+    fn from_voxels_in<S: VoxelSource + ?Sized>(root: IAabb, source: &S, bb: IAabb) -> Self {
         #[cfg(feature = "trace")]
         let _span = trace_span!("octree_from_voxels").entered();
 
-        let mut octree = Self::new(bb);
-        bb.iter().for_each(|pos| {
-            assert!(
-                octree.set(pos, generator.lookup(pos)),
-                "voxel was out of bounds"
-            )
-        });
+        let mut octree = Self::with_root(root, estimate_bricks(source, bb));
+        let mut column = vec![None; bb.height()];
+
+        let mut before = (octree.nodes.len(), octree.bricks.len());
+        for (i, x) in bb.iter_x().enumerate() {
+            for z in bb.iter_z() {
+                source.column(x, z, bb.iter_y(), &mut column);
+                for (y, voxel) in bb.iter_y().zip(&column) {
+                    let pos = IVec3::new(x, y, z);
+                    if let Some(voxel) = voxel {
+                        assert!(octree.insert(pos, *voxel), "voxel was out of bounds");
+                    }
+                }
+            }
+
+            // if the estimate was too low, extrapolate from the slices so far instead of growing bit by bit
+            // (checked once per row of bricks, since a slice starting a new row allocates all of its bricks)
+            if (i + 1) % BRICK_SIZE as usize == 0 {
+                octree.nodes.reserve_ahead(before.0, i + 1, bb.width());
+                octree.bricks.reserve_ahead(before.1, i + 1, bb.width());
+                before = (octree.nodes.len(), octree.bricks.len());
+            }
+        }
+
         octree
     }
 
-    /// Sets a new voxel (returns true if none).
-    pub fn set(&mut self, pos: IVec3, voxel: Option<Voxel>) -> bool {
+    /// Sets or clears a voxel, returning the voxel that was there before.
+    ///
+    /// Positions outside the octree are ignored.
+    pub fn set(&mut self, pos: IVec3, voxel: Option<Voxel>) -> Option<Voxel> {
         match voxel {
-            Some(voxel) => self.insert(pos, voxel),
-            None => true,
+            Some(voxel) => {
+                let old = self.get(pos);
+                self.insert(pos, voxel);
+                old
+            }
+            None => self.remove(pos),
         }
     }
 
     /// Returns the number of voxels in the scene.
     pub fn len(&self) -> usize {
-        self.nodes[0].len(&self.nodes)
+        fn count(octree: &Octree, idx: usize, bb: IAabb) -> usize {
+            let node = &octree.nodes[idx];
+            occupied(node.mask)
+                .map(|local_idx| {
+                    let next_idx = node.children[local_idx] as usize;
+                    let next_bb = bb.octant(local_idx);
+                    if has(node.solid, local_idx) {
+                        next_bb.width().pow(3)
+                    } else if is_brick(next_bb) {
+                        octree.bricks[next_idx].mask.count_ones() as usize
+                    } else {
+                        count(octree, next_idx, next_bb)
+                    }
+                })
+                .sum()
+        }
+
+        count(self, 0, self.root())
+    }
+
+    /// Checks if the scene has no voxels.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
     }
 
     /// Inserts a new voxel or returns false if out of bounds.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the octree was deduplicated, since changing a shared child would change every copy of it.
     pub fn insert(&mut self, pos: IVec3, voxel: Voxel) -> bool {
+        assert!(!self.shared, "deduplicated octrees can't be changed");
+        if self.bb.index_of(pos).is_none() {
+            return false;
+        }
+        self.averaged = false;
+
         let mut curr_idx = 0;
-        let mut bb = self.bb;
-        // find a leaf node for the voxel
+        let mut bb = self.root();
+        // find a brick for the voxel
         loop {
-            // get an index for the current aabb and check bounds (should return some for all nodes under the root)
-            let Some(idx) = bb.index_of(pos) else {
-                return false;
+            let idx = bb.index_of(pos).expect("voxel is in the root");
+            bb = bb.octant(idx);
+
+            let node = &self.nodes[curr_idx];
+            let next_idx = if has(node.solid, idx) {
+                self.split(curr_idx, idx, bb)
+            } else if has(node.mask, idx) {
+                node.children[idx]
+            } else {
+                // create a new node or brick if one doesn't already exist
+                let new_idx = if is_brick(bb) {
+                    self.bricks.alloc(Brick::default())
+                } else {
+                    self.nodes.alloc(Node::default())
+                };
+                let node = &mut self.nodes[curr_idx];
+                node.mask |= 1 << idx;
+                node.children[idx] = new_idx;
+                new_idx
             };
 
-            let new_idx = self.nodes.len();
-
-            match &mut self.nodes[curr_idx] {
-                Node::Branch(branches) => {
-                    bb = bb.octant(idx);
-                    curr_idx = match branches[idx] {
-                        Some(i) => i.get(),
-                        None => {
-                            // create a new node if one doesn't already exist
-                            branches[idx] =
-                                Some(NonZeroUsize::new(new_idx).expect("should be nonzero"));
-                            self.nodes.push(Node::from_aabb(bb));
-                            new_idx
-                        }
-                    };
+            if is_brick(bb) {
+                let i = brick_index(pos - bb.min() - IVec3::ONE);
+                let voxel = self.palette.insert(voxel);
+                self.bricks[next_idx as usize].set(i, voxel);
+                return true;
+            }
+            curr_idx = next_idx as usize;
+        }
+    }
+
+    /// Clears a voxel, returning it if there was one.
+    ///
+    /// Branches and bricks left empty are unlinked from their parents, up to the root. They stay in the arenas
+    /// until the next [`Octree::collapse`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the octree was deduplicated, since changing a shared child would change every copy of it.
+    pub fn remove(&mut self, pos: IVec3) -> Option<Voxel> {
+        assert!(!self.shared, "deduplicated octrees can't be changed");
+        self.bb.index_of(pos)?;
+
+        // branches on the way down, with the octant taken at each
+        let mut path = Vec::new();
+        let mut curr_idx = 0;
+        let mut bb = self.root();
+        let brick_idx = loop {
+            let idx = bb.index_of(pos).expect("voxel is in the root");
+            bb = bb.octant(idx);
+            path.push((curr_idx, idx));
+
+            let node = &self.nodes[curr_idx];
+            let next_idx = if has(node.solid, idx) {
+                self.split(curr_idx, idx, bb)
+            } else {
+                has(node.mask, idx).then_some(node.children[idx])?
+            };
+            if is_brick(bb) {
+                break next_idx as usize;
+            }
+            curr_idx = next_idx as usize;
+        };
+
+        let i = brick_index(pos - bb.min() - IVec3::ONE);
+        let brick = &mut self.bricks[brick_idx];
+        let voxel = brick.get(i)?;
+        brick.mask &= !(1 << i);
+        self.averaged = false;
+
+        // prune the brick and every branch that no longer has children
+        if brick.mask == 0 {
+            for (node_idx, idx) in path.into_iter().rev() {
+                let node = &mut self.nodes[node_idx];
+                node.mask &= !(1 << idx);
+                if node.mask != 0 {
+                    break;
                 }
-                Node::Leaf(leaves) => {
-                    leaves[idx] = Some(voxel);
-                    return true;
+            }
+        }
+
+        Some(self.palette.get(voxel))
+    }
+
+    /// Replaces a solid octant of a branch with a full brick or branch, so that its voxels can be changed again.
+    fn split(&mut self, node_idx: usize, idx: usize, bb: IAabb) -> u32 {
+        let voxel = solid_index(self.nodes[node_idx].children[idx]);
+        let new_idx = if is_brick(bb) {
+            self.bricks.alloc(Brick {
+                mask: u64::MAX,
+                voxels: [voxel; 64],
+                ..Default::default()
+            })
+        } else {
+            self.nodes.alloc(Node {
+                mask: u8::MAX,
+                solid: u8::MAX,
+                children: [voxel.get() as u32; 8],
+                ..Default::default()
+            })
+        };
+        let node = &mut self.nodes[node_idx];
+        node.solid &= !(1 << idx);
+        node.children[idx] = new_idx;
+        new_idx
+    }
+
+    /// Merges every region filled with a single voxel into a solid octant of its parent branch, so traversal stops
+    /// as soon as it reaches the region, and drops the branches and bricks that are no longer used.
+    ///
+    /// Also updates the average voxel of every branch and brick.
+    pub fn collapse(&mut self) {
+        #[cfg(feature = "trace")]
+        let _span = trace_span!("octree_collapse").entered();
+
+        let mut nodes = Arena::with_capacity(self.nodes.len());
+        let mut bricks = Arena::with_capacity(self.bricks.len());
+        // children are added before their parents, so save a spot for the root
+        nodes.alloc(Node::default());
+        nodes[0] = self.collapse_node(0, self.root(), &mut nodes, &mut bricks);
+
+        nodes.shrink_to_fit();
+        bricks.shrink_to_fit();
+        self.nodes = nodes;
+        self.bricks = bricks;
+        self.averaged = true;
+    }
+
+    /// Copies a branch and everything under it into new arenas, returning the averaged branch with solid octants in
+    /// place of the children that are filled with a single voxel.
+    fn collapse_node(
+        &self,
+        idx: usize,
+        bb: IAabb,
+        nodes: &mut Arena<Node>,
+        bricks: &mut Arena<Brick>,
+    ) -> Node {
+        let mut node = self.nodes[idx];
+        let mut parts = Vec::with_capacity(8);
+        for local_idx in occupied(node.mask) {
+            let next_idx = node.children[local_idx] as usize;
+            let next_bb = bb.octant(local_idx);
+            let volume = next_bb.width().pow(3) as f32;
+            if has(node.solid, local_idx) {
+                let voxel = self.palette.get(solid_index(node.children[local_idx]));
+                parts.push((Lod::from(voxel), volume));
+                continue;
+            }
+
+            let (child, lod) = if is_brick(next_bb) {
+                let mut brick = self.bricks[next_idx].clone();
+                brick.lod = brick.average(&self.palette);
+                let lod = brick.lod;
+                (brick.solid().ok_or_else(|| bricks.alloc(brick)), lod)
+            } else {
+                let child = self.collapse_node(next_idx, next_bb, nodes, bricks);
+                (child.solid().ok_or_else(|| nodes.alloc(child)), child.lod)
+            };
+            parts.push((lod, volume));
+            node.children[local_idx] = match child {
+                Ok(voxel) => {
+                    node.solid |= 1 << local_idx;
+                    voxel.get() as u32
                 }
+                Err(new_idx) => new_idx,
+            };
+        }
+        node.lod = average(parts, bb.width().pow(3) as f32);
+        node
+    }
+
+    /// Merges identical branches and bricks, so each is stored once and shared by every parent that refers to it.
+    ///
+    /// This turns the tree into a directed acyclic graph, which traverses the same way but can no longer be changed.
+    /// Works best after [`Octree::collapse`], since solid octants compare equal by their voxel alone.
+    pub fn dedup(&mut self) {
+        #[cfg(feature = "trace")]
+        let _span = trace_span!("octree_dedup").entered();
+
+        let mut dag = Dag {
+            nodes: Arena::with_capacity(self.nodes.len()),
+            bricks: Arena::with_capacity(self.bricks.len()),
+            node_ids: HashMap::new(),
+            brick_ids: HashMap::new(),
+        };
+        // children are added before their parents, so save a spot for the root
+        dag.nodes.alloc(Node::default());
+        dag.nodes[0] = self.dedup_node(0, self.root(), &mut dag);
+
+        dag.nodes.shrink_to_fit();
+        dag.bricks.shrink_to_fit();
+        self.nodes = dag.nodes;
+        self.bricks = dag.bricks;
+        self.shared = true;
+    }
+
+    /// Copies a branch into a DAG, returning the branch with its children replaced by their shared copies.
+    fn dedup_node(&self, idx: usize, bb: IAabb, dag: &mut Dag) -> Node {
+        let mut node = self.nodes[idx];
+        for local_idx in occupied(node.mask & !node.solid) {
+            let next_idx = node.children[local_idx] as usize;
+            let next_bb = bb.octant(local_idx);
+            node.children[local_idx] = if is_brick(next_bb) {
+                let brick = &self.bricks[next_idx];
+                *dag.brick_ids
+                    .entry(brick.clone())
+                    .or_insert_with(|| dag.bricks.alloc(brick.clone()))
+            } else {
+                let child = self.dedup_node(next_idx, next_bb, dag);
+                *dag.node_ids
+                    .entry(child)
+                    .or_insert_with(|| dag.nodes.alloc(child))
+            };
+        }
+        node
+    }
+
+    /// Renumbers the branches and bricks breadth first from the root, so the children of every branch sit next to
+    /// each other in memory and each level of the tree follows the one above it.
+    ///
+    /// [`Octree::collapse`] and [`Octree::dedup`] leave children before their parents in the order the octree was
+    /// filled, so a ray stepping through the octants of a branch would jump around in memory. Also drops branches
+    /// and bricks that are no longer reachable.
+    pub fn reorder(&mut self) {
+        #[cfg(feature = "trace")]
+        let _span = trace_span!("octree_reorder").entered();
+
+        let mut nodes = Arena::with_capacity(self.nodes.len());
+        let mut bricks = Arena::with_capacity(self.bricks.len());
+        // new ids of the copies made so far, where branches are told apart by size as well since a deduplicated
+        // branch could turn up at several levels
+        let mut node_ids = HashMap::from([((0, self.root().extents.x), 0)]);
+        let mut brick_ids = HashMap::new();
+        let mut bbs = vec![self.root()];
+        nodes.alloc(self.nodes[0]);
+
+        // the new arena doubles as the queue, with the children of the copies still pointing into the old one
+        let mut idx = 0;
+        while idx < nodes.len() {
+            let mut node = nodes[idx];
+            for local_idx in occupied(node.mask & !node.solid) {
+                let child = node.children[local_idx];
+                let next_bb = bbs[idx].octant(local_idx);
+                node.children[local_idx] = if is_brick(next_bb) {
+                    *brick_ids
+                        .entry(child)
+                        .or_insert_with(|| bricks.alloc(self.bricks[child as usize].clone()))
+                } else {
+                    *node_ids
+                        .entry((child, next_bb.extents.x))
+                        .or_insert_with(|| {
+                            bbs.push(next_bb);
+                            nodes.alloc(self.nodes[child as usize])
+                        })
+                };
             }
+            nodes[idx] = node;
+            idx += 1;
         }
+
+        self.nodes = nodes;
+        self.bricks = bricks;
     }
 
     pub fn get(&self, pos: IVec3) -> Option<Voxel> {
+        self.bb.index_of(pos)?;
+
         let mut curr_idx = 0;
-        let mut bb = self.bb;
+        let mut bb = self.root();
         loop {
-            let idx = bb.index_of(pos)?;
-            let octant_bb = bb.octant(idx);
+            let idx = bb.index_of(pos).expect("voxel is in the root");
+            bb = bb.octant(idx);
 
-            match &self.nodes[curr_idx] {
-                Node::Branch(branches) => {
-                    bb = octant_bb;
-                    curr_idx = branches[idx]?.get();
-                }
-                Node::Leaf(leaves) => {
-                    return leaves[idx];
-                }
+            let node = &self.nodes[curr_idx];
+            let next_idx = has(node.mask, idx).then_some(node.children[idx])? as usize;
+            if has(node.solid, idx) {
+                return Some(self.palette.get(solid_index(node.children[idx])));
+            }
+            if is_brick(bb) {
+                let i = brick_index(pos - bb.min() - IVec3::ONE);
+                return self.bricks[next_idx]
+                    .get(i)
+                    .map(|index| self.palette.get(index));
             }
+            curr_idx = next_idx;
         }
     }
 
+    #[cfg(test)]
     fn trace(&self, ray: Ray) -> Option<Voxel> {
+        self.trace_hit(ray).map(|hit| hit.voxel)
+    }
+
+    #[cfg(test)]
+    fn trace_hit(&self, ray: Ray) -> Option<Hit> {
+        self.trace_hit_from(ray, 0.0)
+    }
+
+    /// Traces a ray that can't hit anything before `start`, skipping the space up to there.
+    fn trace_hit_from(&self, ray: Ray, start: f32) -> Option<Hit> {
         #[cfg(feature = "trace")]
         let _span = trace_span!("octree_trace").entered();
 
         // check if ray is in branch aabb
-        let range = self.bb.intersection(ray, 0.01..f32::INFINITY)?;
+        let range = self.bb.intersection(ray, start.max(ray.near)..ray.far)?;
 
-        let start_ray = Ray::new(ray.origin + range.start * ray.dir, ray.dir);
+        let start_ray = Ray {
+            origin: ray.origin + range.start * ray.dir,
+            ..ray
+        };
 
-        self.nodes[0].trace(&self.nodes, self.bb, start_ray)
+        self.nodes[0]
+            .trace(self, self.root(), start_ray, ray.origin)
+            .filter(|hit| hit.distance <= ray.far)
     }
 
-    fn debug_trace(&self, ray: Ray) -> Option<Voxel> {
+    /// Traces a ray in `f64`, starting it where it enters the octree relative to the root's minimum corner so the
+    /// octants are stepped through in `f32` without losing precision far from the origin.
+    fn trace_hit_f64(&self, ray: DRay) -> Option<Hit> {
         #[cfg(feature = "trace")]
-        let _span = trace_span!("octree_debug_trace").entered();
+        let _span = trace_span!("octree_trace").entered();
 
-        // check if ray is in branch aabb
-        let range = self.bb.intersection(ray, 0.01..f32::INFINITY)?;
+        let range = self.bb.intersection_f64(ray, ray.near..ray.far)?;
+        let min = self.root().min().as_dvec3();
+        let start_ray = ray.relative(min, range.start);
+        // the eye only sets distances and levels of detail, which are fine rounded to f32
+        let eye = (ray.origin - min).as_vec3a();
+
+        self.nodes[0]
+            .trace(self, self.root().at_origin(), start_ray, eye)
+            .filter(|hit| hit.distance as f64 <= ray.far)
+    }
+
+    /// Traces a packet of rays together, giving the same hits as [`Octree::trace_hit_from`] for each.
+    fn trace_packet(&self, rays: &[Ray; PACKET], start: f32) -> [Option<Hit>; PACKET] {
+        #[cfg(feature = "trace")]
+        let _span = trace_span!("octree_trace_packet").entered();
+
+        let mut start_rays = *rays;
+        let mut active = 0;
+        for (i, (ray, start_ray)) in rays.iter().zip(&mut start_rays).enumerate() {
+            if let Some(range) = self.bb.intersection(*ray, start.max(ray.near)..ray.far) {
+                start_ray.origin = ray.origin + range.start * ray.dir;
+                active |= 1 << i;
+            }
+        }
+
+        let mut hits = [None; PACKET];
+        if active != 0 {
+            let eyes = rays.map(|ray| ray.origin);
+            self.nodes[0].trace_packet(self, self.root(), &start_rays, active, &eyes, &mut hits);
+        }
+        for (hit, ray) in hits.iter_mut().zip(rays) {
+            *hit = hit.filter(|hit| hit.distance <= ray.far);
+        }
+        hits
+    }
+
+    /// Distance along every ray of a beam before which none of them can hit a voxel, or infinity if none of them
+    /// hit anything.
+    fn beam_start(&self, beam: Beam) -> f32 {
+        #[cfg(feature = "trace")]
+        let _span = trace_span!("octree_beam_start").entered();
+
+        let mut start = f32::INFINITY;
+        if beam.near(self.root()).is_some() {
+            self.nodes[0].beam_start(self, self.root(), beam, &mut start);
+        }
+        // a voxel short, so rounding never lets a ray start past the voxel it would have hit
+        start - 1.0
+    }
+
+    /// Blends the colors a cone passes through into `cone`, until it is opaque.
+    ///
+    /// The cone is a ray whose spread sets how wide it gets, and branches and bricks narrower than it where it enters
+    /// them are blended in by their averages instead of being traced through.
+    fn trace_cone(&self, ray: Ray, cone: &mut ConeHit) {
+        #[cfg(feature = "trace")]
+        let _span = trace_span!("octree_trace_cone").entered();
+
+        let Some(range) = self.bb.intersection(ray, ray.near..ray.far) else {
+            return;
+        };
+        let start_ray = Ray {
+            origin: ray.origin + range.start * ray.dir,
+            ..ray
+        };
+        self.nodes[0].trace_cone(self, self.root(), start_ray, ray.origin, cone);
+    }
+
+    /// Average voxel of a branch or brick, if it is smaller than what a ray covers where it enters it.
+    ///
+    /// `eye` is where the ray was cast from.
+    fn lod(&self, lod: Lod, bb: IAabb, ray: Ray, eye: Vec3A) -> Option<Voxel> {
+        (lod.coverage >= LOD_COVERAGE && self.covers(bb, ray, eye))
+            .then(|| Voxel::new(lod.color, lod.kind))
+    }
+
+    /// Checks if a ray covers the whole width of a branch or brick where it enters it, so its average can stand in
+    /// for it.
+    fn covers(&self, bb: IAabb, ray: Ray, eye: Vec3A) -> bool {
+        let small =
+            || (bb.width() as f32).powi(2) <= ray.origin.distance_squared(eye) * ray.spread.powi(2);
+        self.averaged && ray.spread > 0.0 && small()
+    }
+
+    /// Counts a branch or brick entered by the ray being traced, see [`stats::node`].
+    #[cfg(feature = "stats")]
+    fn count(&self, bb: IAabb) {
+        stats::node((self.root().width() / bb.width()).ilog2());
+    }
+
+    /// Bounding box of the root branch, which is always a branch so it is at least twice the size of a brick.
+    fn root(&self) -> IAabb {
+        IAabb::new(
+            self.bb.origin,
+            self.bb.extents.max(IVec3::splat(BRICK_SIZE)),
+        )
+    }
+
+    /// Distinct voxels in the octree.
+    pub fn palette(&self) -> &VoxelPalette {
+        &self.palette
+    }
 
-        let start_ray = Ray::new(ray.origin + range.start * ray.dir, ray.dir);
+    /// Memory held by the branches, bricks and palette, where branches and spare capacity count as overhead.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        let brick = mem::size_of::<Brick>();
+        MemoryUsage {
+            nodes: self.nodes.len() + self.bricks.len(),
+            voxel_bytes: self.bricks.len() * brick,
+            overhead_bytes: self.nodes.capacity() * mem::size_of::<Node>()
+                + (self.bricks.capacity() - self.bricks.len()) * brick,
+            palette_bytes: self.palette.heap_bytes(),
+        }
+    }
 
-        self.nodes[0].debug_trace(&self.nodes, self.bb, start_ray)
+    /// Saves the octree to a file.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), ArchiveError> {
+        let mut file = BufWriter::new(fs::File::create(path)?);
+        self.write(&mut file)?;
+        file.flush()?;
+        Ok(())
+    }
+
+    /// Loads an octree from a file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ArchiveError> {
+        Self::read(BufReader::new(fs::File::open(path)?))
+    }
+
+    /// Writes the octree in its file format.
+    ///
+    /// Files start with a header (magic, version, bounding box), the shared and averaged flags and the palette,
+    /// followed by the branches and then the bricks as they are laid out in memory. Nothing is compressed, so every
+    /// branch and brick has a fixed size and could be read in place.
+    pub fn write(&self, mut writer: impl Write) -> io::Result<()> {
+        write_header(&mut writer, MAGIC, VERSION, self.bb)?;
+        writer.write_all(&[self.shared as u8 | (self.averaged as u8) << 1])?;
+        write_palette(&mut writer, &self.palette)?;
+
+        write_u32(&mut writer, self.nodes.len() as u32)?;
+        for idx in 0..self.nodes.len() {
+            let node = &self.nodes[idx];
+            writer.write_all(&[node.mask, node.solid])?;
+            node.lod.write(&mut writer)?;
+            for child in node.children {
+                write_u32(&mut writer, child)?;
+            }
+        }
+
+        write_u32(&mut writer, self.bricks.len() as u32)?;
+        for idx in 0..self.bricks.len() {
+            let brick = &self.bricks[idx];
+            writer.write_all(&brick.mask.to_le_bytes())?;
+            for voxel in brick.voxels {
+                writer.write_all(&voxel.get().to_le_bytes())?;
+            }
+            brick.lod.write(&mut writer)?;
+        }
+        Ok(())
+    }
+
+    /// Reads an octree in its file format.
+    pub fn read(mut reader: impl Read) -> Result<Self, ArchiveError> {
+        let (_, bb) = read_header(&mut reader, MAGIC, VERSION)?;
+        if bb.extents != IVec3::splat(bb.extents.x) || !(bb.extents.x as u32).is_power_of_two() {
+            return Err(ArchiveError::Format(
+                "octree is not a cube with sides of a power of two".into(),
+            ));
+        }
+        let flags = read_u8(&mut reader)?;
+        let palette = read_palette(&mut reader)?;
+
+        // counts come from the file, so only trust them so far before anything is read
+        let node_count = read_u32(&mut reader)? as usize;
+        let mut nodes = Arena::with_capacity(node_count.min(1 << 16));
+        for _ in 0..node_count {
+            let mask = read_u8(&mut reader)?;
+            let solid = read_u8(&mut reader)?;
+            let lod = Lod::read(&mut reader)?;
+            let mut children = [0; 8];
+            for child in &mut children {
+                *child = read_u32(&mut reader)?;
+            }
+            nodes.alloc(Node {
+                mask,
+                solid,
+                lod,
+                children,
+            });
+        }
+        if nodes.len() == 0 {
+            return Err(ArchiveError::Format("octree has no root".into()));
+        }
+
+        let brick_count = read_u32(&mut reader)? as usize;
+        let mut bricks = Arena::with_capacity(brick_count.min(1 << 16));
+        for _ in 0..brick_count {
+            let mask = read_u64(&mut reader)?;
+            let mut voxels = [PaletteIndex::MIN; 64];
+            for voxel in &mut voxels {
+                *voxel = PaletteIndex::new(read_u16(&mut reader)?)
+                    .ok_or_else(|| ArchiveError::Format("brick holds palette index 0".into()))?;
+            }
+            let lod = Lod::read(&mut reader)?;
+            bricks.alloc(Brick { mask, voxels, lod });
+        }
+
+        let octree = Self {
+            bb,
+            nodes,
+            bricks,
+            palette,
+            shared: flags & 1 != 0,
+            averaged: flags & 2 != 0,
+        };
+        octree.check_node(0, octree.root(), &mut HashSet::new())?;
+        Ok(octree)
+    }
+
+    /// Checks that every child under a branch read from a file is in range, so lookups and traces can't panic.
+    ///
+    /// Branches that were already checked at the same size are skipped, since children of deduplicated octrees are
+    /// shared.
+    fn check_node(
+        &self,
+        idx: usize,
+        bb: IAabb,
+        checked: &mut HashSet<(usize, i32)>,
+    ) -> Result<(), ArchiveError> {
+        if !checked.insert((idx, bb.extents.x)) {
+            return Ok(());
+        }
+
+        let out_of_range =
+            |what: &str| ArchiveError::Format(format!("branch {idx} has a {what} out of range"));
+        let node = &self.nodes[idx];
+        for local_idx in occupied(node.mask) {
+            let child = node.children[local_idx] as usize;
+            let next_bb = bb.octant(local_idx);
+            if has(node.solid, local_idx) {
+                if child == 0 || child > self.palette.len() {
+                    return Err(out_of_range("solid voxel"));
+                }
+            } else if is_brick(next_bb) {
+                if child >= self.bricks.len() {
+                    return Err(out_of_range("brick"));
+                }
+                let brick = &self.bricks[child];
+                if (0..brick.voxels.len())
+                    .filter_map(|i| brick.get(i))
+                    .any(|voxel| voxel.get() as usize > self.palette.len())
+                {
+                    return Err(out_of_range("voxel in its brick"));
+                }
+            } else {
+                if child >= self.nodes.len() {
+                    return Err(out_of_range("branch"));
+                }
+                self.check_node(child, next_bb, checked)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Traces a ray drawing the edges of branches, which are all placed where the ray enters the octree.
+    fn debug_trace(&self, ray: Ray) -> Option<Hit> {
+        #[cfg(feature = "trace")]
+        let _span = trace_span!("octree_debug_trace").entered();
+
+        // check if ray is in branch aabb
+        let range = self.bb.intersection(ray, ray.near..ray.far)?;
+
+        let start_ray = Ray {
+            origin: ray.origin + range.start * ray.dir,
+            ..ray
+        };
+
+        let voxel = self.nodes[0].debug_trace(self, self.root(), start_ray)?;
+        Some(Hit {
+            voxel,
+            distance: range.start,
+        })
     }
 }
 
-#[derive(Debug)]
-enum Node {
-    Branch([Option<NonZeroUsize>; 8]),
-    Leaf([Option<Voxel>; 8]),
+/// A branch of the octree, whose children are either branches or bricks depending on its size.
+///
+/// The mask has a bit set for every occupied octant, and only the children of those octants are meaningful.
+/// Octants that are also set in the solid mask are filled with a single voxel, and their child is its palette
+/// index instead. Children are indexed by octant rather than packed by the mask, so every branch has the same size
+/// and can be hashed by value when deduplicating, and read in place from files.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+struct Node {
+    mask: u8,
+    solid: u8,
+    lod: Lod,
+    children: [u32; 8],
 }
 
 impl Node {
-    /// Creates a new node based on the size of the aabb.
-    pub fn from_aabb(bb: IAabb) -> Self {
-        if bb.is_unit() {
-            Self::Leaf(Default::default())
-        } else {
-            Self::Branch(Default::default())
-        }
-    }
-
-    /// Returns the number of voxels for this node.
-    pub fn len(&self, nodes: &[Node]) -> usize {
-        let mut count = 0;
-        match self {
-            Node::Branch(branches) => {
-                for z in 0..2 {
-                    for y in 0..2 {
-                        for x in 0..2 {
-                            let local_idx = x | y << 1 | z << 2;
-                            let Some(next_idx) = branches[local_idx] else {
-                                continue;
-                            };
-                            count += nodes[next_idx.get()].len(nodes);
-                        }
-                    }
+    /// Palette index of the voxel filling the whole branch, if any.
+    fn solid(&self) -> Option<PaletteIndex> {
+        let first = self.children[0];
+        (self.solid == u8::MAX && self.children.iter().all(|child| *child == first))
+            .then(|| solid_index(first))
+    }
+
+    /// Trace a ray cast from `eye` inside of this node.
+    fn trace(&self, octree: &Octree, bb: IAabb, ray: Ray, eye: Vec3A) -> Option<Hit> {
+        #[cfg(feature = "trace")]
+        let _span = trace_span!("node_trace").entered();
+        #[cfg(feature = "stats")]
+        octree.count(bb);
+
+        let mut start_ray = ray;
+        for (idx, entered) in bb.octants(ray) {
+            if !has(self.mask, idx) {
+                continue;
+            }
+            start_ray.origin = ray.origin + entered * ray.dir;
+
+            let next_idx = self.children[idx] as usize;
+            let next_bb = bb.octant(idx);
+            let at_start = |voxel| Hit {
+                voxel,
+                distance: eye.distance(start_ray.origin),
+            };
+            let hit = if has(self.solid, idx) {
+                // the ray starts on the edge of the octant, so it hits straight away
+                Some(at_start(
+                    octree.palette.get(solid_index(self.children[idx])),
+                ))
+            } else if is_brick(next_bb) {
+                let brick = &octree.bricks[next_idx];
+                #[cfg(feature = "stats")]
+                octree.count(next_bb);
+                match octree.lod(brick.lod, next_bb, start_ray, eye) {
+                    Some(voxel) => Some(at_start(voxel)),
+                    None => brick.trace(next_bb, start_ray).and_then(|(i, distance)| {
+                        Some(Hit {
+                            voxel: octree.palette.get(brick.get(i)?),
+                            distance: eye.distance(start_ray.origin) + distance,
+                        })
+                    }),
                 }
+            } else {
+                let node = &octree.nodes[next_idx];
+                match octree.lod(node.lod, next_bb, start_ray, eye) {
+                    Some(voxel) => Some(at_start(voxel)),
+                    None => node.trace(octree, next_bb, start_ray, eye),
+                }
+            };
+            if hit.is_some() {
+                return hit;
             }
-            Node::Leaf(leaves) => {
-                for z in 0..2 {
-                    for y in 0..2 {
-                        for x in 0..2 {
-                            let local_idx = x | y << 1 | z << 2;
-                            if leaves[local_idx].is_some() {
-                                count += 1;
-                            }
-                        }
+        }
+        None
+    }
+
+    /// Trace the rays of a packet whose bits are set in `active` inside of this node, filling in their hits.
+    ///
+    /// Rays heading the same way along every axis visit the octants in the same order, so the packet steps through
+    /// them together and only descends into octants that some ray passes through. Other packets fall back to
+    /// tracing each ray on its own.
+    fn trace_packet(
+        &self,
+        octree: &Octree,
+        bb: IAabb,
+        rays: &[Ray; PACKET],
+        mut active: u8,
+        eyes: &[Vec3A; PACKET],
+        hits: &mut [Option<Hit>; PACKET],
+    ) {
+        #[cfg(feature = "trace")]
+        let _span = trace_span!("node_trace_packet").entered();
+        #[cfg(feature = "stats")]
+        octree.count(bb);
+
+        let signs = rays.map(|ray| ray.dir.is_negative_bitmask() as usize);
+        let flip = signs[occupied(active).next().unwrap_or_default()];
+        if occupied(active).any(|i| signs[i] != flip) {
+            for i in occupied(active) {
+                hits[i] = self.trace(octree, bb, rays[i], eyes[i]);
+            }
+            return;
+        }
+
+        // the octants each ray passes through in order, with where it enters them
+        let octants = bb.packet_octants(rays);
+        let paths = std::array::from_fn::<_, PACKET, _>(|i| {
+            // a ray crosses each middle plane at most once, so it passes through at most four octants
+            let mut path = [(usize::MAX, rays[i].origin); 4];
+            for (step, (idx, entered)) in path.iter_mut().zip(octants[i]) {
+                *step = (idx, rays[i].origin + entered * rays[i].dir);
+            }
+            path
+        });
+
+        // flipping the octant index by the signs of the direction makes every step go to a higher index
+        let mut start_rays = *rays;
+        for step in 0..8 {
+            if active == 0 {
+                return;
+            }
+            let idx = step ^ flip;
+            if !has(self.mask, idx) {
+                continue;
+            }
+
+            let mut visiting = 0;
+            for i in occupied(active) {
+                if let Some((_, origin)) = paths[i].iter().find(|(octant, _)| *octant == idx) {
+                    start_rays[i].origin = *origin;
+                    visiting |= 1 << i;
+                }
+            }
+            if visiting == 0 {
+                continue;
+            }
+
+            let next_idx = self.children[idx] as usize;
+            let next_bb = bb.octant(idx);
+            let at_start = |i: usize, voxel| Hit {
+                voxel,
+                distance: eyes[i].distance(start_rays[i].origin),
+            };
+            let mut descending = 0;
+            for i in occupied(visiting) {
+                hits[i] = if has(self.solid, idx) {
+                    Some(at_start(
+                        i,
+                        octree.palette.get(solid_index(self.children[idx])),
+                    ))
+                } else if is_brick(next_bb) {
+                    let brick = &octree.bricks[next_idx];
+                    #[cfg(feature = "stats")]
+                    octree.count(next_bb);
+                    match octree.lod(brick.lod, next_bb, start_rays[i], eyes[i]) {
+                        Some(voxel) => Some(at_start(i, voxel)),
+                        None => brick
+                            .trace(next_bb, start_rays[i])
+                            .and_then(|(j, distance)| {
+                                Some(Hit {
+                                    voxel: octree.palette.get(brick.get(j)?),
+                                    distance: eyes[i].distance(start_rays[i].origin) + distance,
+                                })
+                            }),
                     }
+                } else {
+                    let node = &octree.nodes[next_idx];
+                    let lod = octree.lod(node.lod, next_bb, start_rays[i], eyes[i]);
+                    if lod.is_none() {
+                        descending |= 1 << i;
+                    }
+                    lod.map(|voxel| at_start(i, voxel))
+                };
+            }
+            if descending != 0 {
+                octree.nodes[next_idx].trace_packet(
+                    octree,
+                    next_bb,
+                    &start_rays,
+                    descending,
+                    eyes,
+                    hits,
+                );
+            }
+
+            for i in occupied(visiting) {
+                if hits[i].is_some() {
+                    active &= !(1 << i);
                 }
             }
         }
-        count
     }
 
-    /// Trace a ray inside of this node.
-    pub fn trace(&self, nodes: &[Node], bb: IAabb, ray: Ray) -> Option<Voxel> {
-        #[cfg(feature = "trace")]
-        let _span = trace_span!("node_trace").entered();
+    /// Blends the colors a cone cast from `eye` passes inside of this node into `cone`, until it is opaque.
+    fn trace_cone(&self, octree: &Octree, bb: IAabb, ray: Ray, eye: Vec3A, cone: &mut ConeHit) {
+        #[cfg(feature = "stats")]
+        octree.count(bb);
 
         let mut start_ray = ray;
-        let mut idx = ray.origin.cmpgt(bb.origin.as_vec3a()).bitmask() as usize;
-        let tests = bb.plane_intersections(ray);
-        let mut dirs = sort_dirs(tests);
-
-        match self {
-            Node::Branch(branches) => loop {
-                let Some(next_node) = branches[idx] else {
-                    let next_dir = dirs.next()?;
-                    idx ^= 1 << next_dir;
-                    start_ray.origin = ray.origin + tests[next_dir].unwrap() * ray.dir;
-                    continue;
-                };
+        for (idx, entered) in bb.octants(ray) {
+            if !has(self.mask, idx) {
+                continue;
+            }
+            start_ray.origin = ray.origin + entered * ray.dir;
 
-                let next_bb = bb.octant(idx);
+            let next_idx = self.children[idx] as usize;
+            let next_bb = bb.octant(idx);
+            let distance = eye.distance(start_ray.origin);
+            if distance > ray.far {
+                return;
+            }
+            let average = |cone: &mut ConeHit, lod: Lod| {
+                cone.add(lod.color, lod.coverage as f32 / u8::MAX as f32, distance)
+            };
+            if has(self.solid, idx) {
+                let voxel = octree.palette.get(solid_index(self.children[idx]));
+                cone.add(voxel.color, 1.0, distance);
+            } else if is_brick(next_bb) {
+                let brick = &octree.bricks[next_idx];
+                #[cfg(feature = "stats")]
+                octree.count(next_bb);
+                if octree.covers(next_bb, start_ray, eye) {
+                    average(cone, brick.lod);
+                } else if let Some((i, entered)) = brick
+                    .trace(next_bb, start_ray)
+                    .filter(|(_, entered)| distance + entered <= ray.far)
+                {
+                    let voxel = octree.palette.get(brick.voxels[i]);
+                    cone.add(voxel.color, 1.0, distance + entered);
+                }
+            } else {
+                let node = &octree.nodes[next_idx];
+                if octree.covers(next_bb, start_ray, eye) {
+                    average(cone, node.lod);
+                } else {
+                    node.trace_cone(octree, next_bb, start_ray, eye, cone);
+                }
+            }
+            if cone.is_opaque() {
+                return;
+            }
+        }
+    }
 
-                let Some(voxel) = nodes[next_node.get()].trace(nodes, next_bb, start_ray) else {
-                    let next_dir = dirs.next()?;
-                    idx ^= 1 << next_dir;
-                    start_ray.origin = ray.origin + tests[next_dir].unwrap() * ray.dir;
-                    continue;
-                };
+    /// Lowers `start` to the closest distance at which a beam might reach an occupied octant of this node.
+    ///
+    /// Octants are searched closest first, and the search stops at solid octants, bricks and branches narrower
+    /// than the beam, since every ray of the beam could hit anywhere in them.
+    fn beam_start(&self, octree: &Octree, bb: IAabb, beam: Beam, start: &mut f32) {
+        #[cfg(feature = "stats")]
+        octree.count(bb);
+
+        let mut octants = [(0, 0.0); 8];
+        let mut len = 0;
+        for idx in occupied(self.mask) {
+            if let Some(near) = beam.near(bb.octant(idx)).filter(|near| near < start) {
+                octants[len] = (idx, near);
+                len += 1;
+            }
+        }
+        octants[..len].sort_by(|(_, a), (_, b)| a.total_cmp(b));
 
-                return Some(voxel);
-            },
-            Node::Leaf(leaves) => loop {
-                let Some(voxel) = leaves[idx] else {
-                    let next_dir = dirs.next()?;
-                    idx ^= 1 << next_dir;
-                    start_ray.origin = ray.origin + tests[next_dir].unwrap() * ray.dir;
-                    continue;
-                };
-                return Some(voxel);
-            },
+        for &(idx, near) in &octants[..len] {
+            if near >= *start {
+                return;
+            }
+            let next_bb = bb.octant(idx);
+            if has(self.solid, idx)
+                || is_brick(next_bb)
+                || next_bb.width() as f32 <= 2.0 * beam.spread * near
+            {
+                *start = near;
+            } else {
+                octree.nodes[self.children[idx] as usize].beam_start(octree, next_bb, beam, start);
+            }
         }
     }
 
     /// Trace a ray inside of this node, rendering the edges of branches.
-    pub fn debug_trace(&self, nodes: &[Node], bb: IAabb, ray: Ray) -> Option<Voxel> {
+    fn debug_trace(&self, octree: &Octree, bb: IAabb, ray: Ray) -> Option<Voxel> {
         #[cfg(feature = "trace")]
         let _span = trace_span!("node_debug_trace").entered();
 
-        let mut start_ray = ray;
-        let mut idx = ray.origin.cmpgt(bb.origin.as_vec3a()).bitmask() as usize;
-        let tests = bb.plane_intersections(ray);
-        let mut dirs = sort_dirs(tests);
-
-        match self {
-            Node::Branch(branches) => {
-                if bb.intersects_edge(ray) {
-                    let color = pearson_hash(bb.origin);
-                    return Some(Voxel { color });
-                }
+        if bb.intersects_edge(ray) {
+            let color = pearson_hash(bb.origin);
+            return Some(Voxel::from(color));
+        }
 
-                loop {
-                    let Some(next_node) = branches[idx] else {
-                        let next_dir = dirs.next()?;
-                        idx ^= 1 << next_dir;
-                        start_ray.origin = ray.origin + tests[next_dir].unwrap() * ray.dir;
-                        continue;
-                    };
-
-                    let next_bb = bb.octant(idx);
-
-                    let Some(voxel) = nodes[next_node.get()].debug_trace(nodes, next_bb, start_ray)
-                    else {
-                        let next_dir = dirs.next()?;
-                        idx ^= 1 << next_dir;
-                        start_ray.origin = ray.origin + tests[next_dir].unwrap() * ray.dir;
-                        continue;
-                    };
-
-                    return Some(voxel);
-                }
+        let mut start_ray = ray;
+        for (idx, entered) in bb.octants(ray) {
+            if !has(self.mask, idx) {
+                continue;
             }
-            Node::Leaf(leaves) => loop {
-                let Some(_) = leaves[idx] else {
-                    let next_dir = dirs.next()?;
-                    idx ^= 1 << next_dir;
-                    start_ray.origin = ray.origin + tests[next_dir].unwrap() * ray.dir;
-                    continue;
+            start_ray.origin = ray.origin + entered * ray.dir;
+
+            let next_idx = self.children[idx] as usize;
+            let next_bb = bb.octant(idx);
+            let voxel = if has(self.solid, idx) {
+                // solid octants are drawn like branches without children
+                let color = if next_bb.intersects_edge(start_ray) {
+                    pearson_hash(next_bb.origin)
+                } else {
+                    U8Vec3::ZERO
                 };
-                return Some(Voxel {
-                    color: U8Vec3::ZERO,
-                });
-            },
+                Some(Voxel::from(color))
+            } else if is_brick(next_bb) {
+                octree.bricks[next_idx]
+                    .trace(next_bb, start_ray)
+                    .map(|_| Voxel::from(U8Vec3::ZERO))
+            } else {
+                octree.nodes[next_idx].debug_trace(octree, next_bb, start_ray)
+            };
+            if voxel.is_some() {
+                return voxel;
+            }
         }
+        None
     }
 }
 
-/// Sorts and filters the directions to toggle.
-fn sort_dirs(tests: [Option<f32>; 3]) -> impl Iterator<Item = usize> {
-    #[cfg(feature = "trace")]
-    let _span = trace_span!("sort_dirs").entered();
+/// A 4x4x4 block of voxels at the bottom of the octree, indexed by `x + 4 * (y + 4 * z)`.
+///
+/// The mask has a bit set for every occupied voxel, and only the slots of those voxels are meaningful.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct Brick {
+    mask: u64,
+    voxels: [PaletteIndex; 64],
+    lod: Lod,
+}
+
+impl Default for Brick {
+    fn default() -> Self {
+        Self {
+            mask: 0,
+            voxels: [PaletteIndex::MIN; 64],
+            lod: Lod::default(),
+        }
+    }
+}
+
+impl Brick {
+    /// Palette index of the voxel filling the whole brick, if any.
+    fn solid(&self) -> Option<PaletteIndex> {
+        let first = self.voxels[0];
+        (self.mask == u64::MAX && self.voxels.iter().all(|voxel| *voxel == first)).then_some(first)
+    }
+
+    /// Averages the brick's voxels.
+    fn average(&self, palette: &VoxelPalette) -> Lod {
+        let voxels = (0..self.voxels.len()).filter_map(|i| self.get(i));
+        average(
+            voxels.map(|voxel| (Lod::from(palette.get(voxel)), 1.0)),
+            self.voxels.len() as f32,
+        )
+    }
+
+    fn get(&self, i: usize) -> Option<PaletteIndex> {
+        (self.mask & (1 << i) != 0).then(|| self.voxels[i])
+    }
+
+    fn set(&mut self, i: usize, voxel: PaletteIndex) {
+        self.mask |= 1 << i;
+        self.voxels[i] = voxel;
+    }
+
+    /// Steps a ray through the brick's voxels, returning the index of the first one it hits and the distance from
+    /// the start of the ray to where it enters it.
+    ///
+    /// The ray should start on the edge of or inside the brick's bounding box.
+    fn trace(&self, bb: IAabb, ray: Ray) -> Option<(usize, f32)> {
+        // See: https://m4xc.dev/articles/amanatides-and-woo/
+        let pos = ray.origin - bb.min().as_vec3a();
+        let mut cell = ray
+            .cell(pos)
+            .clamp(Vec3A::ZERO, Vec3A::splat((BRICK_SIZE - 1) as f32))
+            .as_ivec3();
+
+        let step = ray.dir.signum().as_ivec3();
+        let delta = (1.0 / ray.dir).abs();
+        let next_edge = (cell + step.max(IVec3::ZERO)).as_vec3a();
+        let mut tmax = Vec3A::select(
+            ray.dir.cmpeq(Vec3A::ZERO),
+            Vec3A::INFINITY,
+            (next_edge - pos) / ray.dir,
+        );
+
+        let mut entered: f32 = 0.0;
+        loop {
+            #[cfg(feature = "stats")]
+            stats::step();
+            let i = brick_index(cell);
+            if self.mask & (1 << i) != 0 {
+                return Some((i, entered.max(0.0)));
+            }
+
+            let axis = if tmax.x < tmax.y && tmax.x < tmax.z {
+                0
+            } else if tmax.y < tmax.z {
+                1
+            } else {
+                2
+            };
+            cell[axis] += step[axis];
+            if !(0..BRICK_SIZE).contains(&cell[axis]) {
+                return None;
+            }
+            entered = tmax[axis];
+            tmax[axis] += delta[axis];
+        }
+    }
+}
 
-    let mut axes = [(0, tests[0]), (1, tests[1]), (2, tests[2])];
-    axes.sort_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap());
-    axes.into_iter()
-        .filter(move |(_, s)| s.is_some())
-        .map(|(i, _)| i)
+/// Average of the voxels in a branch or brick, which stands in for them once they are smaller than a pixel.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+struct Lod {
+    color: U8Vec3,
+    kind: VoxelKind,
+    /// Fraction of the region that is occupied, out of 255.
+    coverage: u8,
+}
+
+impl Lod {
+    fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        writer.write_all(&self.color.to_array())?;
+        writer.write_all(&self.kind.0.to_le_bytes())?;
+        writer.write_all(&[self.coverage])
+    }
+
+    fn read(reader: &mut impl Read) -> io::Result<Self> {
+        let mut color = [0; 3];
+        reader.read_exact(&mut color)?;
+        Ok(Self {
+            color: U8Vec3::from_array(color),
+            kind: VoxelKind(read_u16(reader)?),
+            coverage: read_u8(reader)?,
+        })
+    }
+}
+
+impl From<Voxel> for Lod {
+    /// A region filled with a voxel.
+    fn from(voxel: Voxel) -> Self {
+        Self {
+            color: voxel.color,
+            kind: voxel.kind,
+            coverage: u8::MAX,
+        }
+    }
+}
+
+/// Averages the parts of a region, given with their volumes, weighting each part's color by how much of it is
+/// occupied. The average takes the kind that occupies the most of the region.
+fn average(parts: impl IntoIterator<Item = (Lod, f32)>, volume: f32) -> Lod {
+    let mut color = Vec3A::ZERO;
+    let mut filled = 0.0;
+    let mut kinds: Vec<(VoxelKind, f32)> = Vec::new();
+    for (part, part_volume) in parts {
+        let weight = part_volume * part.coverage as f32 / u8::MAX as f32;
+        color += weight * part.color.as_vec3a();
+        filled += weight;
+        match kinds.iter_mut().find(|(kind, _)| *kind == part.kind) {
+            Some((_, total)) => *total += weight,
+            None => kinds.push((part.kind, weight)),
+        }
+    }
+
+    let kind = kinds
+        .into_iter()
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map_or(VoxelKind::UNKNOWN, |(kind, _)| kind);
+    Lod {
+        color: (color / filled.max(f32::MIN_POSITIVE)).round().as_u8vec3(),
+        kind,
+        coverage: (filled / volume * u8::MAX as f32).round() as u8,
+    }
+}
+
+/// New arenas of an octree being deduplicated, with the id of every distinct branch and brick.
+struct Dag {
+    nodes: Arena<Node>,
+    bricks: Arena<Brick>,
+    node_ids: HashMap<Node, u32>,
+    brick_ids: HashMap<Brick, u32>,
+}
+
+/// Checks if a bounding box in the octree holds a brick rather than a branch.
+fn is_brick(bb: IAabb) -> bool {
+    bb.extents.x * 2 == BRICK_SIZE
+}
+
+/// Palette index stored in place of the child of a solid octant.
+fn solid_index(child: u32) -> PaletteIndex {
+    u16::try_from(child)
+        .ok()
+        .and_then(PaletteIndex::new)
+        .expect("solid octants hold a palette index")
+}
+
+/// Index of a voxel in a brick from its position in the brick.
+fn brick_index(pos: IVec3) -> usize {
+    (pos.x + BRICK_SIZE * (pos.y + BRICK_SIZE * pos.z)) as usize
+}
+
+/// Position of a voxel in a brick from its index.
+fn brick_pos(i: usize) -> IVec3 {
+    let i = i as i32;
+    IVec3::new(
+        i % BRICK_SIZE,
+        i / BRICK_SIZE % BRICK_SIZE,
+        i / (BRICK_SIZE * BRICK_SIZE),
+    )
+}
+
+/// Checks if an octant's bit is set in a node's occupancy mask.
+fn has(mask: u8, idx: usize) -> bool {
+    mask & (1 << idx) != 0
+}
+
+/// Indices of the occupied octants in a mask, in order.
+fn occupied(mut mask: u8) -> impl Iterator<Item = usize> {
+    std::iter::from_fn(move || {
+        let idx = mask.trailing_zeros() as usize;
+        mask &= mask.checked_sub(1)?;
+        Some(idx)
+    })
+}
+
+/// Columns sampled along each axis to estimate how full a scene is.
+const ESTIMATE_SAMPLES: usize = 8;
+
+/// Estimates the number of bricks needed for the voxels of a source from a few sampled columns.
+///
+/// Assumes voxels are clustered into terrain, so most bricks are full apart from the ones along the surface.
+fn estimate_bricks<S: VoxelSource + ?Sized>(source: &S, bb: IAabb) -> usize {
+    let mut column = vec![None; bb.height()];
+    let mut filled = 0;
+    for i in 0..ESTIMATE_SAMPLES {
+        for k in 0..ESTIMATE_SAMPLES {
+            let x = bb.min().x + ((2 * i + 1) * bb.width() / (2 * ESTIMATE_SAMPLES)) as i32;
+            let z = bb.min().z + ((2 * k + 1) * bb.length() / (2 * ESTIMATE_SAMPLES)) as i32;
+            source.column(x, z, bb.iter_y(), &mut column);
+            filled += column.iter().filter(|voxel| voxel.is_some()).count();
+        }
+    }
+
+    let columns = bb.width() * bb.length();
+    let voxels = filled * columns / (ESTIMATE_SAMPLES * ESTIMATE_SAMPLES);
+    // partly full bricks along the surface come to about one for every six columns of terrain
+    voxels / (BRICK_SIZE as usize).pow(3) + columns / 6
 }
 
 /// Pearson hashing of a position to a color.
@@ -399,22 +1580,22 @@ fn pearson_hash(pos: IVec3) -> U8Vec3 {
         }
     }
 
-    return U8Vec3::new(r, g, b);
+    U8Vec3::new(r, g, b)
 }
 
 #[cfg(test)]
-
 mod tests {
     use glam::U8Vec3;
 
     use super::*;
+    use crate::voxel::VoxelGenerator;
 
     #[test]
     fn test_octree_insert_and_get_one() {
         let mut octree = Octree::new(IAabb::new(IVec3::ZERO, IVec3::ONE));
 
         {
-            let inserted = octree.insert(IVec3::ONE, Voxel { color: U8Vec3::ONE });
+            let inserted = octree.insert(IVec3::ONE, Voxel::from(U8Vec3::ONE));
             assert!(inserted);
         }
 
@@ -423,16 +1604,11 @@ mod tests {
 
         {
             let got = octree.get(IVec3::ONE);
-            assert_eq!(got, Some(Voxel { color: U8Vec3::ONE }));
+            assert_eq!(got, Some(Voxel::from(U8Vec3::ONE)));
         }
 
         {
-            let inserted = octree.insert(
-                IVec3::ZERO,
-                Voxel {
-                    color: 2 * U8Vec3::ONE,
-                },
-            );
+            let inserted = octree.insert(IVec3::ZERO, Voxel::from(2 * U8Vec3::ONE));
             assert!(inserted);
         }
 
@@ -440,12 +1616,7 @@ mod tests {
         debug!("{:?}", octree.nodes);
 
         {
-            let inserted = octree.insert(
-                2 * IVec3::NEG_ONE,
-                Voxel {
-                    color: 3 * U8Vec3::ONE,
-                },
-            );
+            let inserted = octree.insert(2 * IVec3::NEG_ONE, Voxel::from(3 * U8Vec3::ONE));
             assert!(!inserted);
         }
 
@@ -454,12 +1625,7 @@ mod tests {
 
         {
             let got = octree.get(IVec3::ZERO);
-            assert_eq!(
-                got,
-                Some(Voxel {
-                    color: 2 * U8Vec3::ONE
-                })
-            );
+            assert_eq!(got, Some(Voxel::from(2 * U8Vec3::ONE)));
         }
 
         {
@@ -474,16 +1640,11 @@ mod tests {
 
         {
             let got = octree.get(IVec3::ONE);
-            assert_eq!(got, Some(Voxel { color: U8Vec3::ONE }));
+            assert_eq!(got, Some(Voxel::from(U8Vec3::ONE)));
         }
 
         {
-            let inserted = octree.insert(
-                IVec3::ONE,
-                Voxel {
-                    color: 4 * U8Vec3::ONE,
-                },
-            );
+            let inserted = octree.insert(IVec3::ONE, Voxel::from(4 * U8Vec3::ONE));
             assert!(inserted);
         }
 
@@ -492,21 +1653,11 @@ mod tests {
 
         {
             let got = octree.get(IVec3::ONE);
-            assert_eq!(
-                got,
-                Some(Voxel {
-                    color: 4 * U8Vec3::ONE
-                })
-            );
+            assert_eq!(got, Some(Voxel::from(4 * U8Vec3::ONE)));
         }
 
         {
-            let inserted = octree.insert(
-                IVec3::new(1, 0, 1),
-                Voxel {
-                    color: U8Vec3::new(0, 1, 0),
-                },
-            );
+            let inserted = octree.insert(IVec3::new(1, 0, 1), Voxel::from(U8Vec3::new(0, 1, 0)));
             assert!(inserted);
         }
 
@@ -515,12 +1666,7 @@ mod tests {
 
         {
             let got = octree.get(IVec3::new(1, 0, 1));
-            assert_eq!(
-                got,
-                Some(Voxel {
-                    color: U8Vec3::new(0, 1, 0)
-                })
-            );
+            assert_eq!(got, Some(Voxel::from(U8Vec3::new(0, 1, 0))));
         }
     }
 
@@ -532,7 +1678,7 @@ mod tests {
         debug!("{:?}", octree.nodes);
 
         {
-            let inserted = octree.insert(IVec3::ONE, Voxel { color: U8Vec3::ONE });
+            let inserted = octree.insert(IVec3::ONE, Voxel::from(U8Vec3::ONE));
             assert!(inserted);
         }
 
@@ -541,16 +1687,11 @@ mod tests {
 
         {
             let got = octree.get(IVec3::ONE);
-            assert_eq!(got, Some(Voxel { color: U8Vec3::ONE }));
+            assert_eq!(got, Some(Voxel::from(U8Vec3::ONE)));
         }
 
         {
-            let inserted = octree.insert(
-                IVec3::ZERO,
-                Voxel {
-                    color: 2 * U8Vec3::ONE,
-                },
-            );
+            let inserted = octree.insert(IVec3::ZERO, Voxel::from(2 * U8Vec3::ONE));
             assert!(inserted);
         }
 
@@ -558,12 +1699,7 @@ mod tests {
         debug!("{:?}", octree.nodes);
 
         {
-            let inserted = octree.insert(
-                IVec3::NEG_ONE,
-                Voxel {
-                    color: 3 * U8Vec3::ONE,
-                },
-            );
+            let inserted = octree.insert(IVec3::NEG_ONE, Voxel::from(3 * U8Vec3::ONE));
             assert!(inserted);
         }
 
@@ -572,36 +1708,21 @@ mod tests {
 
         {
             let got = octree.get(IVec3::ZERO);
-            assert_eq!(
-                got,
-                Some(Voxel {
-                    color: 2 * U8Vec3::ONE
-                })
-            );
+            assert_eq!(got, Some(Voxel::from(2 * U8Vec3::ONE)));
         }
 
         {
             let got = octree.get(IVec3::NEG_ONE);
-            assert_eq!(
-                got,
-                Some(Voxel {
-                    color: 3 * U8Vec3::ONE
-                })
-            );
+            assert_eq!(got, Some(Voxel::from(3 * U8Vec3::ONE)));
         }
 
         {
             let got = octree.get(IVec3::ONE);
-            assert_eq!(got, Some(Voxel { color: U8Vec3::ONE }));
+            assert_eq!(got, Some(Voxel::from(U8Vec3::ONE)));
         }
 
         {
-            let inserted = octree.insert(
-                IVec3::ONE,
-                Voxel {
-                    color: 4 * U8Vec3::ONE,
-                },
-            );
+            let inserted = octree.insert(IVec3::ONE, Voxel::from(4 * U8Vec3::ONE));
             assert!(inserted);
         }
 
@@ -610,14 +1731,421 @@ mod tests {
 
         {
             let got = octree.get(IVec3::ONE);
-            assert_eq!(
-                got,
-                Some(Voxel {
-                    color: 4 * U8Vec3::ONE
+            assert_eq!(got, Some(Voxel::from(4 * U8Vec3::ONE)));
+        }
+    }
+
+    #[test]
+    fn collapse_solid_regions() {
+        let mut octree = Octree::new(IAabb::new(IVec3::ZERO, 4 * IVec3::ONE));
+        let stone = Voxel::from(U8Vec3::splat(100));
+        let grass = Voxel::from(U8Vec3::new(0, 200, 0));
+        // a solid 8x8x8 branch of stone next to a brick with a grass voxel on top
+        let stone_bb = IAabb::new(IVec3::splat(-4), IVec3::splat(4));
+        for pos in stone_bb.iter() {
+            octree.insert(pos + IVec3::ONE, stone);
+        }
+        octree.insert(IVec3::new(5, 1, 1), stone);
+        octree.insert(IVec3::new(5, 2, 1), grass);
+
+        let (len, nodes, bricks) = (octree.len(), octree.nodes.len(), octree.bricks.len());
+        octree.collapse();
+        assert_eq!(octree.len(), len);
+        assert_eq!(octree.nodes[0].solid.count_ones(), 1);
+        assert!(octree.nodes.len() < nodes);
+        assert_eq!(octree.bricks.len(), 1);
+        assert!(bricks > 1);
+
+        let ray = Ray::new(Vec3A::new(-0.5, 10.0, -0.5), Vec3A::NEG_Y);
+        assert_eq!(octree.trace(ray), Some(stone));
+        let ray = Ray::new(Vec3A::new(4.5, 10.0, 0.5), Vec3A::NEG_Y);
+        assert_eq!(octree.trace(ray), Some(grass));
+        assert_eq!(octree.get(IVec3::splat(-2)), Some(stone));
+
+        // changing a voxel splits the solid region again
+        octree.insert(IVec3::ZERO, grass);
+        assert_eq!(octree.get(IVec3::ZERO), Some(grass));
+        assert_eq!(octree.get(IVec3::new(0, -1, 0)), Some(stone));
+        assert_eq!(octree.len(), len);
+    }
+
+    #[test]
+    fn remove_voxels() {
+        let mut octree = Octree::new(IAabb::new(IVec3::ZERO, 4 * IVec3::ONE));
+        let stone = Voxel::from(U8Vec3::splat(100));
+        let grass = Voxel::from(U8Vec3::new(0, 200, 0));
+        octree.insert(IVec3::new(5, 5, 5), grass);
+        octree.insert(IVec3::new(-3, 2, -3), stone);
+
+        assert_eq!(octree.remove(IVec3::new(6, 5, 5)), None);
+        assert_eq!(octree.remove(IVec3::splat(100)), None);
+        assert_eq!(octree.set(IVec3::new(5, 5, 5), None), Some(grass));
+        assert_eq!(octree.get(IVec3::new(5, 5, 5)), None);
+        assert_eq!(octree.len(), 1);
+
+        // the empty brick and branches are pruned up to the root
+        assert_eq!(octree.nodes[0].mask.count_ones(), 1);
+        let ray = Ray::new(Vec3A::new(4.5, 20.0, 4.5), Vec3A::NEG_Y);
+        assert_eq!(octree.trace(ray), None);
+
+        assert_eq!(octree.set(IVec3::new(-3, 2, -3), Some(grass)), Some(stone));
+        assert_eq!(octree.remove(IVec3::new(-3, 2, -3)), Some(grass));
+        assert!(octree.is_empty());
+        assert_eq!(octree.nodes[0].mask, 0);
+
+        // clearing a voxel of a solid region splits it first
+        let stone_bb = IAabb::new(IVec3::splat(-4), IVec3::splat(4));
+        for pos in stone_bb.iter() {
+            octree.insert(pos + IVec3::ONE, stone);
+        }
+        octree.collapse();
+        assert_eq!(octree.nodes[0].solid.count_ones(), 1);
+        assert_eq!(octree.remove(IVec3::splat(-2)), Some(stone));
+        assert_eq!(octree.get(IVec3::splat(-2)), None);
+        assert_eq!(octree.get(IVec3::splat(-3)), Some(stone));
+        assert_eq!(octree.len(), 8 * 8 * 8 - 1);
+
+        // pruned branches and bricks are dropped by the next collapse
+        octree.collapse();
+        assert_eq!(octree.len(), 8 * 8 * 8 - 1);
+        assert_eq!(octree.bricks.len(), 1);
+    }
+
+    #[test]
+    fn dedup_repeated_subtrees() {
+        let mut octree = Octree::new(IAabb::new(IVec3::ZERO, 8 * IVec3::ONE));
+        let water = Voxel::from(U8Vec3::new(0, 0, 200));
+        let sand = Voxel::from(U8Vec3::new(200, 200, 100));
+        // a flat water plane, with the same pillar of sand in the middle of every brick
+        for x in -15..=16 {
+            for z in -15..=16 {
+                octree.insert(IVec3::new(x, 0, z), water);
+                if x.rem_euclid(4) == 2 && z.rem_euclid(4) == 2 {
+                    octree.insert(IVec3::new(x, 1, z), sand);
+                }
+            }
+        }
+
+        octree.collapse();
+        let (len, nodes, bricks) = (octree.len(), octree.nodes.len(), octree.bricks.len());
+        let rays = [
+            Ray::new(Vec3A::new(-11.5, 20.0, 5.5), Vec3A::NEG_Y),
+            Ray::new(Vec3A::new(5.5, 20.0, -10.5), Vec3A::NEG_Y),
+            Ray::new(Vec3A::new(-20.0, 0.5, 5.5), Vec3A::X),
+        ];
+        let hits = rays.map(|ray| octree.trace(ray));
+        let memory = octree.memory_usage();
+
+        octree.dedup();
+        assert_eq!(octree.len(), len);
+        assert!(octree.memory_usage().total_bytes() < memory.total_bytes());
+        // one brick for the top of the water and one for the bottoms of the pillars
+        assert_eq!(octree.bricks.len(), 2);
+        assert!(
+            octree.nodes.len() < nodes / 4,
+            "{} of {nodes} nodes left",
+            octree.nodes.len()
+        );
+        assert!(bricks > 1);
+        assert_eq!(rays.map(|ray| octree.trace(ray)), hits);
+        assert_eq!(hits[0], Some(water));
+        assert_eq!(hits[1], Some(sand));
+        assert_eq!(octree.get(IVec3::new(6, 1, -10)), Some(sand));
+    }
+
+    #[test]
+    fn reorder_breadth_first() {
+        let source = VoxelGenerator::new_from_seed(3);
+        let bb = IAabb::new(IVec3::ZERO, 12 * IVec3::ONE);
+        let mut octree = Octree::from_voxels(&source, bb);
+        octree.collapse();
+        let rays: Vec<_> = (0..50)
+            .map(|i| {
+                let angle = i as f32 * 0.3;
+                let origin = Vec3A::new(30.0 * angle.cos(), 20.0, 30.0 * angle.sin());
+                Ray::new(origin, Vec3A::new(0.0, -3.0, 0.0) - origin)
+            })
+            .collect();
+        let hits: Vec<_> = rays.iter().map(|ray| octree.trace_hit(*ray)).collect();
+        let voxels = format!("{octree:?}");
+        let (nodes, bricks) = (octree.nodes.len(), octree.bricks.len());
+
+        octree.reorder();
+        assert_eq!(format!("{octree:?}"), voxels);
+        assert_eq!(
+            rays.iter()
+                .map(|ray| octree.trace_hit(*ray))
+                .collect::<Vec<_>>(),
+            hits
+        );
+        assert_eq!((octree.nodes.len(), octree.bricks.len()), (nodes, bricks));
+
+        // every branch's children come right after the children of the branches before it
+        let mut next_node = 1;
+        let mut next_brick = 0;
+        let mut bbs = vec![octree.root()];
+        for idx in 0..octree.nodes.len() {
+            let node = &octree.nodes[idx];
+            for local_idx in occupied(node.mask & !node.solid) {
+                let next_bb = bbs[idx].octant(local_idx);
+                let next = if is_brick(next_bb) {
+                    &mut next_brick
+                } else {
+                    bbs.push(next_bb);
+                    &mut next_node
+                };
+                assert_eq!(node.children[local_idx], *next, "branch {idx}");
+                *next += 1;
+            }
+        }
+
+        octree.dedup();
+        let shared = octree.nodes.len();
+        octree.reorder();
+        assert_eq!(format!("{octree:?}"), voxels);
+        assert_eq!(octree.nodes.len(), shared);
+        assert_eq!(
+            rays.iter()
+                .map(|ray| octree.trace_hit(*ray))
+                .collect::<Vec<_>>(),
+            hits
+        );
+    }
+
+    #[test]
+    fn lod_averages_small_nodes() {
+        let black = Voxel::new(U8Vec3::ZERO, VoxelKind::STONE);
+        let white = Voxel::new(U8Vec3::splat(200), VoxelKind::SNOW);
+        // a slab, a quarter of it white, filling the layers of bricks from y = -3 up to `top`
+        let slab = |top: i32| {
+            let mut octree = Octree::new(IAabb::new(IVec3::ZERO, 8 * IVec3::ONE));
+            for x in -15..=16 {
+                for y in -3..=top {
+                    for z in -15..=16 {
+                        let voxel = if x % 2 == 0 && z % 2 == 0 {
+                            white
+                        } else {
+                            black
+                        };
+                        octree.insert(IVec3::new(x, y, z), voxel);
+                    }
+                }
+            }
+            octree.collapse();
+            octree
+        };
+
+        // where the ray meets the slab a pixel is wider than a brick, but not a branch
+        let ray = Ray::new(Vec3A::new(0.5, 100.0, 0.5), Vec3A::NEG_Y);
+        let wide = Ray {
+            spread: 0.05,
+            ..ray
+        };
+
+        let mut octree = slab(0);
+        assert_eq!(octree.trace(ray), Some(black));
+        let average = Voxel::new(U8Vec3::splat(50), VoxelKind::STONE);
+        assert_eq!(octree.trace(wide), Some(average));
+
+        // averages are out of date once the octree changes
+        octree.insert(IVec3::new(1, 0, 1), white);
+        assert_eq!(octree.trace(wide), Some(white));
+
+        // mostly empty bricks are traced through
+        let octree = slab(-3);
+        assert_eq!(octree.trace(wide), Some(black));
+    }
+
+    #[test]
+    fn packets_match_single_rays() {
+        let source = VoxelGenerator::new_from_seed(4);
+        let mut octree = Octree::from_voxels(&source, IAabb::new(IVec3::ZERO, IVec3::splat(32)));
+        octree.collapse();
+
+        let eye = Vec3A::new(70.0, 60.0, 50.0);
+        let mut hit_count = 0;
+        for i in 0..200 {
+            // neighbouring rays toward the terrain, and a packet with one ray heading elsewhere
+            let target = Vec3A::new(
+                (i % 20) as f32 * 3.1 - 30.0,
+                -5.0,
+                (i / 20) as f32 * 6.3 - 30.0,
+            );
+            let mut rays = [0.0, 0.02, 0.04, 0.06].map(|offset| Ray {
+                spread: if i % 3 == 0 { 0.01 } else { 0.0 },
+                ..Ray::new(eye, target + offset * Vec3A::new(1.0, 0.5, -1.0) - eye)
+            });
+            if i % 7 == 0 {
+                rays[2].dir = -rays[2].dir;
+            }
+
+            let hits = octree.trace_packet(&rays, 0.0);
+            for (ray, hit) in rays.into_iter().zip(hits) {
+                assert_eq!(hit, octree.trace_hit(ray), "ray {i}");
+                hit_count += hit.is_some() as usize;
+            }
+        }
+        assert!(hit_count > 400, "{hit_count}");
+    }
+
+    #[test]
+    fn rays_from_planes_skip_octants_behind() {
+        let mut octree = Octree::new(IAabb::new(IVec3::ZERO, IVec3::splat(8)));
+        let behind = Voxel::from(U8Vec3::new(200, 0, 0));
+        let ahead = Voxel::from(U8Vec3::new(0, 200, 0));
+        // voxels fill the cells below and to the left of their positions, so this one ends on the middle plane
+        octree.insert(IVec3::new(0, 1, 1), behind);
+        octree.insert(IVec3::new(5, 1, 1), ahead);
+
+        for collapse in [false, true] {
+            if collapse {
+                octree.collapse();
+            }
+            // starting on the plane between the two and heading away from the one behind
+            let ray = Ray::new(Vec3A::new(-20.0, 0.5, 0.5), Vec3A::X);
+            let hit = octree.trace_hit_from(ray, 20.0).expect("voxel ahead");
+            assert_eq!((hit.voxel, hit.distance), (ahead, 24.0));
+
+            let ray = Ray::new(Vec3A::new(20.0, 0.5, 0.5), Vec3A::NEG_X);
+            let hit = octree.trace_hit_from(ray, 20.0).expect("voxel behind");
+            assert_eq!((hit.voxel, hit.distance), (behind, 20.0));
+        }
+    }
+
+    #[test]
+    fn beams_skip_empty_space() {
+        let source = VoxelGenerator::new_from_seed(4);
+        let mut octree = Octree::from_voxels(&source, IAabb::new(IVec3::ZERO, IVec3::splat(32)));
+        octree.collapse();
+
+        let eye = Vec3A::new(70.0, 60.0, 50.0);
+        for i in 0..25 {
+            // a 4x4 tile of rays toward the terrain
+            let corner = Vec3A::new(
+                (i % 5) as f32 * 12.0 - 30.0,
+                -5.0,
+                (i / 5) as f32 * 12.0 - 30.0,
+            );
+            let rays: Vec<_> = (0..16)
+                .map(|j| {
+                    // aimed between voxel edges, so no ray grazes one and could hit either side of it
+                    let offset = Vec3A::new((j % 4) as f32 + 0.5, 0.0, (j / 4) as f32 + 0.5);
+                    Ray::new(eye, corner + offset - eye)
                 })
+                .collect();
+            let start = octree.beam_start(Beam::around(&[rays[0], rays[3], rays[12], rays[15]]));
+            let root = octree.bb.intersection(rays[0], 0.0..f32::INFINITY).unwrap();
+            assert!(start > root.start, "tile {i} starts at {start}");
+
+            for ray in rays {
+                let hit = octree.trace_hit(ray);
+                assert_eq!(
+                    octree.trace_hit_from(ray, start).map(|hit| hit.voxel),
+                    hit.map(|hit| hit.voxel)
+                );
+                assert!(hit.is_none_or(|hit| hit.distance >= start));
+            }
+        }
+
+        // beams that miss start past everything
+        let up =
+            [Vec3A::new(0.1, 1.0, 0.0), Vec3A::new(-0.1, 1.0, 0.1)].map(|dir| Ray::new(eye, dir));
+        assert_eq!(octree.beam_start(Beam::around(&up)), f32::INFINITY);
+    }
+
+    #[test]
+    fn cones_blend_averages() {
+        let white = Voxel::new(U8Vec3::splat(200), VoxelKind::SNOW);
+        // a sparse grid of voxels in one layer, filling a sixteenth of the bricks it lies in
+        let mut octree = Octree::new(IAabb::new(IVec3::ZERO, 8 * IVec3::ONE));
+        for x in (-15..=16).step_by(2) {
+            for z in (-15..=16).step_by(2) {
+                octree.insert(IVec3::new(x, 0, z), white);
+            }
+        }
+        octree.collapse();
+
+        // a cone as thin as a ray finds the same voxel
+        let ray = Ray::new(Vec3A::new(0.5, 100.0, 0.5), Vec3A::NEG_Y);
+        let mut cone = ConeHit::default();
+        octree.trace_cone(ray, &mut cone);
+        assert_eq!(cone, ConeHit::opaque(octree.trace_hit(ray).unwrap()));
+
+        // a wide one takes the brick's average, which only covers part of it
+        let wide = Ray {
+            spread: 0.05,
+            ..ray
+        };
+        let mut cone = ConeHit::default();
+        octree.trace_cone(wide, &mut cone);
+        assert!(cone.opacity > 0.0 && cone.opacity < 0.5, "{cone:?}");
+        assert_eq!(cone.average(), white.color);
+    }
+
+    #[test]
+    fn occupancy_masks() {
+        assert_eq!(occupied(0b1010_0001).collect::<Vec<_>>(), [0, 5, 7]);
+        assert_eq!(occupied(0).count(), 0);
+        assert_eq!(occupied(u8::MAX).count(), 8);
+        assert!(has(0b100, 2));
+        assert!(!has(0b100, 1));
+    }
+
+    #[test]
+    fn save_and_load() {
+        let source = crate::voxel::VoxelGenerator::new_from_seed(3);
+        let bb = IAabb::new(IVec3::ZERO, 12 * IVec3::ONE);
+        let mut octree = Octree::from_voxels(&source, bb);
+
+        for step in 0..3 {
+            match step {
+                1 => octree.collapse(),
+                2 => octree.dedup(),
+                _ => {}
+            }
+
+            let mut file = Vec::new();
+            octree.write(&mut file).expect("failed to write");
+            let loaded = Octree::read(file.as_slice()).expect("failed to read");
+            assert_eq!(format!("{loaded:?}"), format!("{octree:?}"), "step {step}");
+            assert_eq!(loaded.nodes.len(), octree.nodes.len());
+            assert_eq!(loaded.bricks.len(), octree.bricks.len());
+            assert_eq!(loaded.palette, octree.palette);
+            assert_eq!(
+                (loaded.shared, loaded.averaged),
+                (octree.shared, octree.averaged)
             );
+
+            for i in 0..50 {
+                let angle = i as f32 * 0.3;
+                let origin = Vec3A::new(30.0 * angle.cos(), 20.0, 30.0 * angle.sin());
+                let ray = Ray::new(origin, Vec3A::new(0.0, -3.0, 0.0) - origin);
+                assert_eq!(loaded.trace(ray), octree.trace(ray), "step {step}, ray {i}");
+            }
         }
     }
+
+    #[test]
+    fn load_invalid() {
+        assert!(Octree::read(b"NOTOCTRE".as_slice()).is_err());
+
+        let mut octree = Octree::new(IAabb::new(IVec3::ZERO, 8 * IVec3::ONE));
+        octree.insert(IVec3::ONE, Voxel::from(U8Vec3::ONE));
+        let mut file = Vec::new();
+        octree.write(&mut file).expect("failed to write");
+        for len in [10, 40, file.len() - 4] {
+            assert!(Octree::read(&file[..len]).is_err());
+        }
+
+        // point the root's children past the end of the branches
+        let children = 8 + 4 + 24 + 1 + 4 + 5 + 4 + 2 + 6;
+        let mut broken = file.clone();
+        for child in broken[children..children + 32].chunks_mut(4) {
+            child.copy_from_slice(&100u32.to_le_bytes());
+        }
+        assert!(Octree::read(broken.as_slice()).is_err());
+        assert!(Octree::read(file.as_slice()).is_ok());
+    }
 }
 
 #[cfg(test)]
@@ -634,33 +2162,33 @@ mod trace_tests {
     #[test]
     fn get_voxel_full() {
         let mut octree = Octree::new(IAabb::new(IVec3::ZERO, IVec3::ONE));
-        octree.insert(IVec3::new(0, 0, 0), Voxel { color: U8Vec3::ONE });
-        octree.insert(IVec3::new(1, 0, 0), Voxel { color: U8Vec3::ONE });
-        octree.insert(IVec3::new(0, 1, 0), Voxel { color: U8Vec3::ONE });
-        octree.insert(IVec3::new(1, 1, 0), Voxel { color: U8Vec3::ONE });
-        octree.insert(IVec3::new(0, 0, 1), Voxel { color: U8Vec3::ONE });
-        octree.insert(IVec3::new(1, 0, 1), Voxel { color: U8Vec3::ONE });
-        octree.insert(IVec3::new(0, 1, 1), Voxel { color: U8Vec3::ONE });
-        octree.insert(IVec3::new(1, 1, 1), Voxel { color: U8Vec3::ONE });
+        octree.insert(IVec3::new(0, 0, 0), Voxel::from(U8Vec3::ONE));
+        octree.insert(IVec3::new(1, 0, 0), Voxel::from(U8Vec3::ONE));
+        octree.insert(IVec3::new(0, 1, 0), Voxel::from(U8Vec3::ONE));
+        octree.insert(IVec3::new(1, 1, 0), Voxel::from(U8Vec3::ONE));
+        octree.insert(IVec3::new(0, 0, 1), Voxel::from(U8Vec3::ONE));
+        octree.insert(IVec3::new(1, 0, 1), Voxel::from(U8Vec3::ONE));
+        octree.insert(IVec3::new(0, 1, 1), Voxel::from(U8Vec3::ONE));
+        octree.insert(IVec3::new(1, 1, 1), Voxel::from(U8Vec3::ONE));
 
         {
             let ray = Ray::new(Vec3A::new(0.0, -5.0, 0.0), Vec3A::Y);
             assert!(octree.bb.intersection(ray, 0.01..f32::INFINITY).is_some());
             let voxel = octree.trace(ray).expect("voxel not found");
-            assert_eq!(voxel, Voxel { color: U8Vec3::ONE });
+            assert_eq!(voxel, Voxel::from(U8Vec3::ONE));
         }
     }
 
     #[test]
     fn get_voxel_one() {
         let mut octree = Octree::new(IAabb::new(IVec3::ZERO, IVec3::ONE));
-        octree.insert(IVec3::new(0, 0, 0), Voxel { color: U8Vec3::ONE });
+        octree.insert(IVec3::new(0, 0, 0), Voxel::from(U8Vec3::ONE));
 
         {
             let ray = Ray::new(Vec3A::new(-0.5, -5.0, -0.5), Vec3A::Y);
             assert!(octree.bb.intersection(ray, 0.01..f32::INFINITY).is_some());
             let voxel = octree.trace(ray).expect("voxel not found");
-            assert_eq!(voxel, Voxel { color: U8Vec3::ONE });
+            assert_eq!(voxel, Voxel::from(U8Vec3::ONE));
         }
 
         {
@@ -683,6 +2211,64 @@ mod trace_tests {
         }
     }
 
+    #[test]
+    fn from_voxels_reserves_nodes() {
+        use crate::voxel::VoxelGenerator;
+
+        for size in [20, 50] {
+            let bb = IAabb::new(IVec3::ZERO, size * IVec3::ONE);
+            let octree = Octree::from_voxels(&VoxelGenerator::new_from_seed(1), bb);
+            let (len, capacity) = (octree.bricks.len(), octree.bricks.capacity());
+            assert!(
+                capacity < len * 5 / 4,
+                "{capacity} reserved for {len} bricks"
+            );
+        }
+    }
+
+    #[test]
+    fn trace_through_brick() {
+        let mut octree = Octree::new(IAabb::new(IVec3::ZERO, 4 * IVec3::ONE));
+        let near = Voxel::from(U8Vec3::new(1, 0, 0));
+        let far = Voxel::from(U8Vec3::new(2, 0, 0));
+        // both in the brick spanning 0 to 4 on each axis
+        octree.insert(IVec3::new(2, 3, 1), near);
+        octree.insert(IVec3::new(4, 3, 4), far);
+
+        // straight through, hitting the voxel filling 1..2 on x
+        let ray = Ray::new(Vec3A::new(-5.0, 2.5, 0.5), Vec3A::X);
+        assert_eq!(octree.trace(ray), Some(near));
+
+        // same row in the other direction
+        let ray = Ray::new(Vec3A::new(5.0, 2.5, 0.5), Vec3A::NEG_X);
+        assert_eq!(octree.trace(ray), Some(near));
+
+        // diagonally across the brick, missing the near voxel
+        let ray = Ray::new(
+            Vec3A::new(0.1, 2.5, 0.2),
+            Vec3A::new(1.0, 0.0, 1.0).normalize(),
+        );
+        assert_eq!(octree.trace(ray), Some(far));
+
+        // one row over
+        let ray = Ray::new(Vec3A::new(-5.0, 2.5, 1.5), Vec3A::X);
+        assert_eq!(octree.trace(ray), None);
+    }
+
+    #[test]
+    fn palette_is_shared() {
+        let mut octree = Octree::new(IAabb::new(IVec3::ZERO, 2 * IVec3::ONE));
+        let voxel = Voxel::from(U8Vec3::ONE);
+        for pos in [IVec3::ZERO, IVec3::ONE, IVec3::new(-2, 1, 0)] {
+            assert!(octree.insert(pos, voxel));
+        }
+        octree.insert(IVec3::NEG_ONE, Voxel::from(U8Vec3::ZERO));
+
+        assert_eq!(octree.len(), 4);
+        assert_eq!(octree.palette().len(), 2);
+        assert_eq!(octree.get(IVec3::new(-2, 1, 0)), Some(voxel));
+    }
+
     #[test]
     fn get_voxel_dirs() {
         let mut octree = Octree::new(IAabb::new(IVec3::ZERO, 2 * IVec3::ONE));
@@ -690,7 +2276,7 @@ mod trace_tests {
         macro_rules! add {
             ($x:expr, $y:expr, $z:expr) => {{
                 let color = U8Vec3::new($x, $y, $z);
-                octree.insert(color.as_ivec3(), Voxel { color });
+                octree.insert(color.as_ivec3(), Voxel::from(color));
             }};
         }
 
@@ -707,96 +2293,56 @@ mod trace_tests {
             let ray = Ray::new(Vec3A::new(-0.5, -5.0, -0.5), Vec3A::Y);
             assert!(octree.bb.intersection(ray, 0.01..f32::INFINITY).is_some());
             let voxel = octree.trace(ray).expect("voxel not found");
-            assert_eq!(
-                voxel,
-                Voxel {
-                    color: U8Vec3::new(0, 0, 0)
-                }
-            );
+            assert_eq!(voxel, Voxel::from(U8Vec3::new(0, 0, 0)));
         }
 
         {
             let ray = Ray::new(Vec3A::new(-5.0, -0.5, 0.5), Vec3A::X);
             assert!(octree.bb.intersection(ray, 0.01..f32::INFINITY).is_some());
             let voxel = octree.trace(ray).expect("voxel not found");
-            assert_eq!(
-                voxel,
-                Voxel {
-                    color: U8Vec3::new(0, 0, 1)
-                }
-            );
+            assert_eq!(voxel, Voxel::from(U8Vec3::new(0, 0, 1)));
         }
 
         {
             let ray = Ray::new(Vec3A::new(-0.5, 5.0, -0.5), Vec3A::NEG_Y);
             assert!(octree.bb.intersection(ray, 0.01..f32::INFINITY).is_some());
             let voxel = octree.trace(ray).expect("voxel not found");
-            assert_eq!(
-                voxel,
-                Voxel {
-                    color: U8Vec3::new(0, 1, 0)
-                }
-            );
+            assert_eq!(voxel, Voxel::from(U8Vec3::new(0, 1, 0)));
         }
 
         {
             let ray = Ray::new(Vec3A::new(-0.5, 5.0, 0.5), Vec3A::NEG_Y);
             assert!(octree.bb.intersection(ray, 0.01..f32::INFINITY).is_some());
             let voxel = octree.trace(ray).expect("voxel not found");
-            assert_eq!(
-                voxel,
-                Voxel {
-                    color: U8Vec3::new(0, 1, 1)
-                }
-            );
+            assert_eq!(voxel, Voxel::from(U8Vec3::new(0, 1, 1)));
         }
 
         {
             let ray = Ray::new(Vec3A::new(5.0, -0.5, -0.5), Vec3A::NEG_X);
             assert!(octree.bb.intersection(ray, 0.01..f32::INFINITY).is_some());
             let voxel = octree.trace(ray).expect("voxel not found");
-            assert_eq!(
-                voxel,
-                Voxel {
-                    color: U8Vec3::new(1, 0, 0)
-                }
-            );
+            assert_eq!(voxel, Voxel::from(U8Vec3::new(1, 0, 0)));
         }
 
         {
             let ray = Ray::new(Vec3A::new(5.0, -0.5, 0.5), Vec3A::NEG_X);
             assert!(octree.bb.intersection(ray, 0.01..f32::INFINITY).is_some());
             let voxel = octree.trace(ray).expect("voxel not found");
-            assert_eq!(
-                voxel,
-                Voxel {
-                    color: U8Vec3::new(1, 0, 1)
-                }
-            );
+            assert_eq!(voxel, Voxel::from(U8Vec3::new(1, 0, 1)));
         }
 
         {
             let ray = Ray::new(Vec3A::new(0.5, 0.5, -5.0), Vec3A::Z);
             assert!(octree.bb.intersection(ray, 0.01..f32::INFINITY).is_some());
             let voxel = octree.trace(ray).expect("voxel not found");
-            assert_eq!(
-                voxel,
-                Voxel {
-                    color: U8Vec3::new(1, 1, 0)
-                }
-            );
+            assert_eq!(voxel, Voxel::from(U8Vec3::new(1, 1, 0)));
         }
 
         {
             let ray = Ray::new(Vec3A::new(0.5, 0.5, 5.0), Vec3A::NEG_Z);
             assert!(octree.bb.intersection(ray, 0.01..f32::INFINITY).is_some());
             let voxel = octree.trace(ray).expect("voxel not found");
-            assert_eq!(
-                voxel,
-                Voxel {
-                    color: U8Vec3::new(1, 1, 1)
-                }
-            );
+            assert_eq!(voxel, Voxel::from(U8Vec3::new(1, 1, 1)));
         }
     }
 }
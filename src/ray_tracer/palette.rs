@@ -0,0 +1,117 @@
+use std::{collections::HashMap, mem, num::NonZeroU16};
+
+use crate::voxel::Voxel;
+
+/// Index of a voxel in a [`VoxelPalette`], so `Option<PaletteIndex>` fits in two bytes.
+pub type PaletteIndex = NonZeroU16;
+
+/// Distinct voxels of a scene, so storages keep a small index for each voxel instead of the whole voxel.
+///
+/// The palette holds up to `u16::MAX` voxels. Once it is full, new voxels share the index of the closest
+/// color of the same kind (or any kind if there is none).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VoxelPalette {
+    voxels: Vec<Voxel>,
+    indices: HashMap<Voxel, PaletteIndex>,
+}
+
+impl VoxelPalette {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Index of a voxel, adding it to the palette if it is new.
+    pub fn insert(&mut self, voxel: Voxel) -> PaletteIndex {
+        if let Some(&index) = self.indices.get(&voxel) {
+            return index;
+        }
+
+        let index = match u16::try_from(self.voxels.len() + 1) {
+            Ok(index) => {
+                self.voxels.push(voxel);
+                PaletteIndex::new(index).expect("index is at least one")
+            }
+            Err(_) => self.closest(voxel),
+        };
+        self.indices.insert(voxel, index);
+        index
+    }
+
+    /// Voxel stored at an index.
+    pub fn get(&self, index: PaletteIndex) -> Voxel {
+        self.voxels[index.get() as usize - 1]
+    }
+
+    /// Voxels in the order of their indices, starting at index one.
+    pub fn voxels(&self) -> &[Voxel] {
+        &self.voxels
+    }
+
+    pub fn len(&self) -> usize {
+        self.voxels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.voxels.is_empty()
+    }
+
+    /// Bytes allocated for the voxels and the table of their indices.
+    pub fn heap_bytes(&self) -> usize {
+        self.voxels.capacity() * mem::size_of::<Voxel>() + super::hash_map_bytes(&self.indices)
+    }
+
+    /// Index of the existing voxel with the closest color, preferring voxels of the same kind.
+    fn closest(&self, voxel: Voxel) -> PaletteIndex {
+        let (i, _) = self
+            .voxels
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, other)| {
+                let distance = other
+                    .color
+                    .as_ivec3()
+                    .distance_squared(voxel.color.as_ivec3());
+                (other.kind != voxel.kind, distance)
+            })
+            .expect("palette is full");
+        PaletteIndex::new(i as u16 + 1).expect("index is at least one")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::U8Vec3;
+
+    use super::*;
+    use crate::voxel::VoxelKind;
+
+    #[test]
+    fn insert() {
+        let mut palette = VoxelPalette::new();
+        let red = Voxel::from(U8Vec3::new(255, 0, 0));
+        let water = Voxel::new(U8Vec3::new(0, 0, 255), VoxelKind::WATER);
+
+        let a = palette.insert(red);
+        let b = palette.insert(water);
+        assert_ne!(a, b);
+        assert_eq!(palette.insert(red), a);
+        assert_eq!(palette.get(a), red);
+        assert_eq!(palette.get(b), water);
+        assert_eq!(palette.voxels(), [red, water]);
+    }
+
+    #[test]
+    fn full() {
+        let mut palette = VoxelPalette::new();
+        for i in 0..u16::MAX {
+            let [r, g] = i.to_le_bytes();
+            palette.insert(Voxel::from(U8Vec3::new(r, g, 0)));
+        }
+        assert_eq!(palette.len(), u16::MAX as usize);
+
+        // shares the closest color instead of growing
+        let index = palette.insert(Voxel::from(U8Vec3::new(10, 20, 1)));
+        assert_eq!(palette.len(), u16::MAX as usize);
+        assert_eq!(palette.get(index), Voxel::from(U8Vec3::new(10, 20, 0)));
+    }
+}
@@ -0,0 +1,475 @@
+use std::f32::consts::PI;
+
+use glam::{IVec3, Vec2, Vec3A, Vec4};
+
+use super::{
+    emitter::emission,
+    light::{Incoming, LightKind},
+    normal,
+    types::{Hit, Ray},
+    RayTracer, Scene,
+};
+
+/// Settings for rendering by path tracing instead of shading.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PathSettings {
+    /// Paths traced through points spread over each pixel, whose colors are averaged.
+    pub samples: u32,
+    /// Surfaces a path can hit before it is ended, 1 lighting them only by the lights and the sky.
+    pub bounces: u32,
+}
+
+impl Default for PathSettings {
+    fn default() -> Self {
+        Self {
+            samples: 16,
+            bounces: 8,
+        }
+    }
+}
+
+/// Surfaces a path hits before it may be ended at random, so the light bounced off the first few is never lost.
+const ROULETTE_AFTER: u32 = 2;
+
+/// Highest chance of a path going on past [`ROULETTE_AFTER`], so paths between bright surfaces still end.
+const MAX_SURVIVAL: f32 = 0.95;
+
+/// Angle from the middle of a directional light's disk to its edge, in radians, about four times the real sun's so
+/// the shadows it casts are a little soft.
+const SUN_RADIUS: f32 = 0.02;
+
+/// Distance along the normal of the face a path bounced off that the rays leaving it start from, so they don't hit
+/// it again.
+const BOUNCE_OFFSET: f32 = 0.01;
+
+/// Something giving off light that may be sampled at a point of a path, and how likely it is to be picked.
+#[derive(Clone, Copy, Debug)]
+struct Choice {
+    source: Source,
+    /// Chance of picking this out of all of them.
+    chance: f32,
+}
+
+/// What a [`Choice`] picks.
+#[derive(Clone, Copy, Debug)]
+enum Source {
+    /// One of the lights, with the light from it reaching the point.
+    Light {
+        incoming: Incoming,
+        /// Whether light comes from a disk of directions [`SUN_RADIUS`] wide instead of a single point.
+        directional: bool,
+        /// Share of the light that gets through the clouds.
+        clouds: f32,
+    },
+    /// The glowing voxels in a cell of the grid they are grouped into, one of which is picked at random, see
+    /// [`Emitters`](super::emitter::Emitters).
+    Emitters(IVec3),
+}
+
+impl<T: Scene + Sync> RayTracer<T> {
+    /// Traces paths through points spread over a pixel, as many as [`PathSettings::samples`], and returns the average
+    /// of their colors from 0 to 255 or more, with the share of them that hit anything or the sky as the opacity.
+    ///
+    /// Paths bounce off every surface they hit as if it were matte, up to [`PathSettings::bounces`] times. At every
+    /// bounce one of the lights is picked, with a chance of how much of it reaches the surface, and a ray is cast
+    /// toward it to see whether it is blocked. Rays bouncing off in any direction can also reach a directional light's
+    /// disk and the sky, so light from a disk is counted both ways, weighted by multiple importance sampling against
+    /// each other. Glowing voxels are picked like the lights, a cell of them at a time, and counted both ways as well.
+    pub(super) fn path_color(&self, x: usize, y: usize, settings: PathSettings) -> Vec4 {
+        let mut rng = Rng::new(x, y, self.config.seed.unwrap_or_default());
+        let mut choices = Vec::with_capacity(self.lights().len());
+        let (mut sum, mut alpha) = (Vec3A::ZERO, 0.0);
+        for _ in 0..settings.samples {
+            let offset = Vec2::new(rng.next(), rng.next()) - 0.5;
+            let ray = self.sample_ray(x, y, offset);
+            let (color, opacity) = match self.sample_hit(ray) {
+                Some(hit) => {
+                    let color =
+                        255.0 * self.radiance(ray, hit, settings.bounces, &mut rng, &mut choices);
+                    self.behind_clouds(&ray, hit.distance, color, 1.0)
+                }
+                None => {
+                    let (sky, opacity) = self.time_of_day().sky(ray.dir);
+                    self.behind_clouds(&ray, ray.far, sky, opacity)
+                }
+            };
+            sum += opacity * color;
+            alpha += opacity;
+        }
+        if alpha == 0.0 {
+            return Vec4::ZERO;
+        }
+        (sum / alpha).extend(alpha / settings.samples as f32)
+    }
+
+    /// Light, from 0 to 1 or more, carried back along a ray that hit the scene by a path bouncing on from there.
+    ///
+    /// Past [`ROULETTE_AFTER`] surfaces, paths are ended at random by Russian roulette, going on with a chance of
+    /// how much light they still carry and counting for that much more if they do. That keeps the average right while
+    /// dark paths, which add little, end early, instead of every path stopping at the same depth.
+    fn radiance(
+        &self,
+        ray: Ray,
+        hit: Hit,
+        bounces: u32,
+        rng: &mut Rng,
+        choices: &mut Vec<Choice>,
+    ) -> Vec3A {
+        let (mut ray, mut hit) = (ray, hit);
+        let mut throughput = Vec3A::ONE;
+        let mut total = emission(hit.voxel).unwrap_or_default();
+        for bounce in 0..bounces {
+            let point = ray.origin + hit.distance * ray.dir;
+            let albedo = hit.voxel.color.as_vec3a() / 255.0;
+            let normal = self.normal(point, ray.dir);
+            let face = normal::hard(point, ray.dir, self.epsilon);
+            let origin = point + BOUNCE_OFFSET * face;
+
+            self.choose_lights(origin, normal, choices);
+            total += throughput * albedo * self.sample_light(origin, normal, choices, rng);
+
+            let dir = cosine_weighted(normal, rng);
+            // smooth normals lean away from the face, so some directions would go into the voxel
+            if dir.dot(face) <= 0.0 {
+                break;
+            }
+            // matte surfaces reflect light by the cosine, which is how often directions are picked
+            throughput *= albedo;
+            if bounce + 1 >= ROULETTE_AFTER {
+                let survival = throughput.max_element().min(MAX_SURVIVAL);
+                if rng.next() >= survival {
+                    break;
+                }
+                throughput /= survival;
+            }
+            let next = Ray {
+                origin,
+                dir,
+                near: 0.0,
+                spread: 0.0,
+                ..ray
+            };
+            match self.scene.trace_hit(next, false) {
+                Some(next_hit) => {
+                    if let Some(light) = emission(next_hit.voxel) {
+                        let weight =
+                            self.emitter_weight(origin, normal, dir, next_hit.distance, choices);
+                        total += throughput * weight * light;
+                    }
+                    (ray, hit) = (next, next_hit);
+                }
+                None => {
+                    total += throughput * self.escaped(dir, normal, choices);
+                    break;
+                }
+            }
+        }
+        total
+    }
+
+    /// Fills in the lights and cells of glowing voxels that reach a point with a normal, each with a chance of being
+    /// picked of how much of its light falls on the point if nothing is in the way.
+    fn choose_lights(&self, point: Vec3A, normal: Vec3A, choices: &mut Vec<Choice>) {
+        choices.clear();
+        for light in self.lights() {
+            let Some(incoming) = light.incoming(point) else {
+                continue;
+            };
+            let directional = matches!(light.kind, LightKind::Directional { .. });
+            let clouds = self.cloud_light(point, &incoming);
+            // the edge of a directional light's disk can still reach a surface turned just past it
+            let facing = normal.dot(incoming.dir) + if directional { SUN_RADIUS } else { 0.0 };
+            choices.push(Choice {
+                source: Source::Light {
+                    incoming,
+                    directional,
+                    clouds,
+                },
+                chance: clouds * facing.max(0.0) * incoming.color.dot(Vec3A::ONE),
+            });
+        }
+        for (cell, emitters) in self.emitters.near(point) {
+            // the corner of the box around the voxels furthest along the normal, behind which all of them are
+            let furthest = Vec3A::select(normal.cmpgt(Vec3A::ZERO), emitters.max, emitters.min);
+            if normal.dot(furthest - point) <= 0.0 {
+                continue;
+            }
+            let nearest = point.clamp(emitters.min, emitters.max);
+            choices.push(Choice {
+                source: Source::Emitters(cell),
+                chance: emitters.power / (PI * point.distance_squared(nearest).max(1.0)),
+            });
+        }
+        let total: f32 = choices.iter().map(|choice| choice.chance).sum();
+        choices.retain(|choice| choice.chance > 0.0);
+        for choice in choices.iter_mut() {
+            choice.chance /= total;
+        }
+    }
+
+    /// Light reflected by a matte white surface with a normal from one of the lights picked at random, divided by
+    /// the chance it was picked, or nothing if it is blocked.
+    ///
+    /// Shading lights a face straight toward a light with the light's color, so a light's color is what falls on
+    /// such a face over π, the same for a point light as for the whole disk of a directional light.
+    fn sample_light(
+        &self,
+        origin: Vec3A,
+        normal: Vec3A,
+        choices: &[Choice],
+        rng: &mut Rng,
+    ) -> Vec3A {
+        let Some(choice) = pick(choices, rng.next()) else {
+            return Vec3A::ZERO;
+        };
+        let (incoming, directional, clouds) = match choice.source {
+            Source::Light {
+                incoming,
+                directional,
+                clouds,
+            } => (incoming, directional, clouds),
+            Source::Emitters(cell) => {
+                return self.sample_emitter(origin, normal, cell, choice.chance, rng)
+            }
+        };
+        let dir = if directional {
+            in_cone(incoming.dir, SUN_RADIUS, rng)
+        } else {
+            incoming.dir
+        };
+        let cos = normal.dot(dir);
+        if cos <= 0.0 {
+            return Vec3A::ZERO;
+        }
+        let light =
+            clouds * cos * self.light_through(origin, dir, incoming.distance) * incoming.color
+                / choice.chance;
+        if directional {
+            // bouncing rays could have found the disk as well
+            mis(choice.chance / cone_solid_angle(), cos / PI) * light
+        } else {
+            light
+        }
+    }
+
+    /// Light reflected by a matte white surface with a normal from a point picked at random on one of the glowing
+    /// voxels in a cell, divided by the chance it was picked, or nothing if it is blocked.
+    ///
+    /// The voxel is picked evenly out of those in the cell, and the point evenly over its faces turned toward the
+    /// surface. Bouncing rays could have hit the voxel as well, so its light is weighted against that.
+    fn sample_emitter(
+        &self,
+        origin: Vec3A,
+        normal: Vec3A,
+        cell: IVec3,
+        chance: f32,
+        rng: &mut Rng,
+    ) -> Vec3A {
+        let emitters = self.emitters.voxels(self.emitters.cell(cell));
+        let emitter = emitters[index(emitters.len(), rng.next())];
+        let (faces, count) = facing(emitter.pos, origin);
+        if count == 0 {
+            return Vec3A::ZERO;
+        }
+        let face = faces[index(count, rng.next())];
+        let axis = (0..3).find(|&axis| face[axis] != 0.0).unwrap_or_default();
+        let mut target = emitter.pos.as_vec3a() + 0.5 + 0.5 * face;
+        target[(axis + 1) % 3] += rng.next() - 0.5;
+        target[(axis + 2) % 3] += rng.next() - 0.5;
+
+        let offset = target - origin;
+        let distance = offset.length();
+        let dir = offset / distance;
+        let (cos, emitter_cos) = (normal.dot(dir), -dir.dot(face));
+        if cos <= 0.0 || emitter_cos <= 0.0 {
+            return Vec3A::ZERO;
+        }
+        let through = self.light_through(origin, dir, distance - BOUNCE_OFFSET);
+        // chance of picking the direction the point is in, out of all directions
+        let area_chance = chance / (emitters.len() * count) as f32;
+        let dir_chance = area_chance * distance * distance / emitter_cos;
+        mis(dir_chance, cos / PI) * cos * through * emitter.light / (PI * dir_chance)
+    }
+
+    /// Weight of the light from a glowing voxel that a path bouncing off a surface at `origin` with a normal, in
+    /// direction `dir`, hit `distance` later, against picking a point on it at random out of the choices there.
+    fn emitter_weight(
+        &self,
+        origin: Vec3A,
+        normal: Vec3A,
+        dir: Vec3A,
+        distance: f32,
+        choices: &[Choice],
+    ) -> f32 {
+        let point = origin + distance * dir;
+        let face = normal::hard(point, dir, self.epsilon);
+        let pos = (point - 0.5 * face).floor().as_ivec3();
+        let Some((cell, emitters)) = self.emitters.cell_of(pos) else {
+            return 1.0;
+        };
+        let chance = choices.iter().find_map(|choice| match choice.source {
+            Source::Emitters(other) if other == cell => Some(choice.chance),
+            _ => None,
+        });
+        let (Some(chance), (_, count)) = (chance, facing(pos, origin)) else {
+            return 1.0;
+        };
+        let area_chance = chance / (emitters.voxels.len() * count) as f32;
+        let dir_chance = area_chance * distance * distance / -dir.dot(face);
+        mis(normal.dot(dir) / PI, dir_chance)
+    }
+
+    /// Light from the sky and from the disks of directional lights reaching a surface with a normal along a path
+    /// that bounced off it in direction `dir` and hit nothing, out of the lights that could have been picked there.
+    fn escaped(&self, dir: Vec3A, normal: Vec3A, choices: &[Choice]) -> Vec3A {
+        let mut light = self.time_of_day().sky_light();
+        let bounce = normal.dot(dir) / PI;
+        for choice in choices {
+            let Source::Light {
+                incoming,
+                directional: true,
+                clouds,
+            } = choice.source
+            else {
+                continue;
+            };
+            if dir.dot(incoming.dir) < SUN_RADIUS.cos() {
+                continue;
+            }
+            let radiance = clouds * PI * incoming.color / cone_solid_angle();
+            light += mis(bounce, choice.chance / cone_solid_angle()) * radiance;
+        }
+        light
+    }
+}
+
+/// Weight of a sample picked with one chance density, out of that and another way of picking it, by the power
+/// heuristic, so samples either way is unlikely to pick count for little.
+fn mis(chance: f32, other: f32) -> f32 {
+    let (chance, other) = (chance * chance, other * other);
+    chance / (chance + other)
+}
+
+/// Solid angle of the disk of a directional light.
+fn cone_solid_angle() -> f32 {
+    2.0 * PI * (1.0 - SUN_RADIUS.cos())
+}
+
+/// Choice that a number from 0 to 1 lands on, going through them by their chances.
+fn pick(choices: &[Choice], mut u: f32) -> Option<&Choice> {
+    for choice in choices {
+        if u < choice.chance {
+            return Some(choice);
+        }
+        u -= choice.chance;
+    }
+    // rounding can leave a sliver past the last
+    choices.last()
+}
+
+/// Index out of `len` that a number from 0 to 1 lands on.
+fn index(len: usize, u: f32) -> usize {
+    ((u * len as f32) as usize).min(len - 1)
+}
+
+/// Normals of the faces of the voxel at `pos` turned toward a point, and how many of them there are.
+fn facing(pos: IVec3, point: Vec3A) -> ([Vec3A; 3], usize) {
+    let (min, max) = (pos.as_vec3a(), pos.as_vec3a() + 1.0);
+    let mut faces = [Vec3A::ZERO; 3];
+    let mut count = 0;
+    for axis in 0..3 {
+        let side = match point[axis] {
+            p if p < min[axis] => -1.0,
+            p if p > max[axis] => 1.0,
+            _ => continue,
+        };
+        faces[count][axis] = side;
+        count += 1;
+    }
+    (faces, count)
+}
+
+/// Random direction around a normal, more likely the closer it is to the normal by the cosine between them.
+fn cosine_weighted(normal: Vec3A, rng: &mut Rng) -> Vec3A {
+    let (u, v) = (rng.next(), rng.next());
+    let (r, angle) = (u.sqrt(), 2.0 * PI * v);
+    let (tangent, bitangent) = normal.any_orthonormal_pair();
+    (r * angle.cos() * tangent + r * angle.sin() * bitangent + (1.0 - u).sqrt() * normal)
+        .normalize()
+}
+
+/// Random direction within `radius` radians of another, evenly spread over the disk it covers.
+fn in_cone(dir: Vec3A, radius: f32, rng: &mut Rng) -> Vec3A {
+    let (u, v) = (rng.next(), rng.next());
+    let cos = 1.0 - u * (1.0 - radius.cos());
+    let sin = (1.0 - cos * cos).max(0.0).sqrt();
+    let angle = 2.0 * PI * v;
+    let (tangent, bitangent) = dir.any_orthonormal_pair();
+    (sin * angle.cos() * tangent + sin * angle.sin() * bitangent + cos * dir).normalize()
+}
+
+/// Random numbers for the paths through a pixel, the same for the same pixel and seed so renders can be repeated.
+///
+/// A permuted congruential generator, small and fast enough to keep one per pixel.
+pub(super) struct Rng(u32);
+
+impl Rng {
+    pub(super) fn new(x: usize, y: usize, seed: u32) -> Self {
+        let mut rng = Self(
+            (x as u32).wrapping_mul(0x9e37_79b9) ^ (y as u32).wrapping_mul(0x85eb_ca6b) ^ seed,
+        );
+        rng.next();
+        rng
+    }
+
+    /// Next number from 0 up to 1.
+    pub(super) fn next(&mut self) -> f32 {
+        self.0 = self.0.wrapping_mul(747_796_405).wrapping_add(2_891_336_453);
+        let word = ((self.0 >> ((self.0 >> 28) + 4)) ^ self.0).wrapping_mul(277_803_737);
+        // as many bits as an f32 holds, so it never rounds up to 1
+        (((word >> 22) ^ word) >> 8) as f32 / (1 << 24) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples_spread_as_expected() {
+        let mut rng = Rng::new(3, 5, 0);
+        let normal = Vec3A::new(1.0, 2.0, -0.5).normalize();
+        let count = 10_000;
+
+        // cosine weighted directions stay above the surface, two thirds of the way up on average
+        let mut cos = 0.0;
+        for _ in 0..count {
+            let dir = cosine_weighted(normal, &mut rng);
+            assert!(dir.dot(normal) >= 0.0);
+            cos += dir.dot(normal);
+        }
+        assert!((cos / count as f32 - 2.0 / 3.0).abs() < 0.02);
+
+        // directions in a cone stay in it
+        for _ in 0..count {
+            assert!(in_cone(normal, SUN_RADIUS, &mut rng).dot(normal) >= SUN_RADIUS.cos() - 1e-6);
+        }
+
+        // the weights of the two ways of sampling add up to one
+        assert!((mis(0.3, 2.0) + mis(2.0, 0.3) - 1.0).abs() < 1e-6);
+        assert!(mis(2.0, 0.3) > 0.9);
+    }
+
+    #[test]
+    fn lights_are_picked_by_their_chances() {
+        let choice = |chance| Choice {
+            source: Source::Emitters(IVec3::ZERO),
+            chance,
+        };
+        let choices = [choice(0.25), choice(0.75)];
+        assert_eq!(pick(&choices, 0.1).unwrap().chance, 0.25);
+        assert_eq!(pick(&choices, 0.3).unwrap().chance, 0.75);
+        assert_eq!(pick(&choices, 0.999_999_9).unwrap().chance, 0.75);
+        assert!(pick(&[], 0.5).is_none());
+    }
+}
@@ -0,0 +1,362 @@
+use std::{
+    collections::{HashMap, HashSet},
+    f32::consts::PI,
+    ops::Range,
+};
+
+use glam::{IVec2, IVec3, Vec2, Vec3A};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+use super::{
+    normal,
+    path::Rng,
+    types::{Hit, Ray},
+    waves::glint,
+    RayTracer, Scene, AMBIENT,
+};
+use crate::voxel::{Voxel, VoxelKind};
+
+/// Settings for drawing caustics, the patterns of light the waves focus onto the beds of shallow water.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CausticSettings {
+    /// Photons shot at each voxel of the scene's area from each directional light, rounded up to a square number.
+    pub photons: u32,
+    /// Distance, in voxels, within which photons are gathered at a point of a bed, larger for smoother but blurrier
+    /// caustics.
+    pub radius: f32,
+}
+
+impl CausticSettings {
+    /// Photons along each side of the square grid shot at each voxel.
+    fn side(&self) -> u32 {
+        (self.photons as f32).sqrt().ceil() as u32
+    }
+}
+
+impl Default for CausticSettings {
+    fn default() -> Self {
+        Self {
+            photons: 64,
+            radius: 0.5,
+        }
+    }
+}
+
+/// How much faster light goes in air than in water, which bends it at the surface.
+const WATER_IOR: f32 = 1.33;
+
+/// Distance, in voxels, over which water takes away all but a third of the light going through it, so the beds of
+/// deep water fade into the color of the water.
+const CLARITY: f32 = 6.0;
+
+/// Distance, in voxels, a ray goes under water before the bed is taken to be out of sight, past which less than 2%
+/// of the light gets through, see [`CLARITY`].
+const MAX_DEPTH: f32 = 24.0;
+
+/// Light given off by a photon as it lands on a bed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Photon {
+    pos: Vec3A,
+    /// Light it carries, its share of the light of the whole scene's area.
+    power: Vec3A,
+}
+
+/// Photons that lit the beds of water through its surface, grouped into the cells of a grid as wide as the radius
+/// they are gathered within, so the ones near a point can be found quickly.
+#[derive(Clone, Debug, Default)]
+pub struct PhotonMap {
+    /// Photons, sorted by cell.
+    photons: Vec<Photon>,
+    /// Range of `photons` in each cell.
+    cells: HashMap<IVec3, Range<usize>>,
+    radius: f32,
+}
+
+impl PhotonMap {
+    fn new(mut photons: Vec<Photon>, radius: f32) -> Self {
+        let cell = |photon: &Photon| (photon.pos / radius).floor().as_ivec3();
+        photons.sort_by_key(|photon| cell(photon).to_array());
+        let mut cells = HashMap::new();
+        let mut start = 0;
+        for chunk in photons.chunk_by(|a, b| cell(a) == cell(b)) {
+            cells.insert(cell(&chunk[0]), start..start + chunk.len());
+            start += chunk.len();
+        }
+        Self {
+            photons,
+            cells,
+            radius,
+        }
+    }
+
+    /// Number of photons that reached a bed.
+    pub fn len(&self) -> usize {
+        self.photons.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.photons.is_empty()
+    }
+
+    /// Light falling on a point of a bed, from 0 to 1 or more, from the photons within the radius of it spread over
+    /// the disk they landed in.
+    fn gather(&self, point: Vec3A) -> Vec3A {
+        let center = (point / self.radius).floor().as_ivec3();
+        let mut sum = Vec3A::ZERO;
+        for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    let Some(range) = self.cells.get(&(center + IVec3::new(x, y, z))) else {
+                        continue;
+                    };
+                    sum += self.photons[range.clone()]
+                        .iter()
+                        .filter(|photon| photon.pos.distance_squared(point) < self.radius.powi(2))
+                        .map(|photon| photon.power)
+                        .sum::<Vec3A>();
+                }
+            }
+        }
+        sum / (PI * self.radius.powi(2))
+    }
+}
+
+impl<T: Scene + Sync> RayTracer<T> {
+    /// Shoots photons down from each directional light onto the scene, [`CausticSettings::photons`] at each voxel of
+    /// its area, and keeps those that went through the top of water, bent by the waves at
+    /// [`Config::time`](super::Config::time), and landed on the bed below.
+    ///
+    /// Photons that land anywhere else are dropped, since shading lights those surfaces straight from the lights, so
+    /// a single one is shot through the middle of each voxel first, and the rest only around those that hit water.
+    pub(super) fn shoot_photons(&self, settings: CausticSettings) -> PhotonMap {
+        let bounds = self.config.bounds();
+        let top = (bounds.max().y + 1) as f32;
+        let seed = self.config.seed.unwrap_or_default();
+        let photons = self
+            .incoming(Vec3A::ZERO)
+            .filter(|incoming| incoming.distance == f32::INFINITY && incoming.dir.y > 0.0)
+            .flat_map(|incoming| {
+                let ray = |x: f32, z: f32| Ray {
+                    epsilon: self.epsilon,
+                    ..Ray::new(Vec3A::new(x, top, z), -incoming.dir)
+                };
+                let wet: HashSet<IVec2> = bounds
+                    .iter_x()
+                    .into_par_iter()
+                    .flat_map_iter(|x| {
+                        bounds
+                            .iter_z()
+                            .filter(move |&z| {
+                                let hit = self
+                                    .scene
+                                    .trace_hit(ray(x as f32 + 0.5, z as f32 + 0.5), false);
+                                hit.is_some_and(|hit| hit.voxel.kind == VoxelKind::WATER)
+                            })
+                            .flat_map(move |z| {
+                                (-1..=1).flat_map(move |dx| {
+                                    (-1..=1).map(move |dz| IVec2::new(x + dx, z + dz))
+                                })
+                            })
+                    })
+                    .collect();
+                let mut wet: Vec<IVec2> = wet.into_iter().collect();
+                wet.sort_by_key(|column| column.to_array());
+
+                // photons start at random points of the squares of a grid over each voxel, so they land evenly
+                // where the water is calm and every one stands for the same share of the light
+                let side = settings.side();
+                let power = incoming.color * incoming.dir.y / side.pow(2) as f32;
+                wet.into_par_iter()
+                    .flat_map_iter(|column| {
+                        let mut rng = Rng::new(column.x as usize, column.y as usize, seed);
+                        (0..side.pow(2)).filter_map(move |i| {
+                            let square = Vec2::new((i % side) as f32, (i / side) as f32);
+                            let offset = (square + Vec2::new(rng.next(), rng.next())) / side as f32;
+                            let ray = ray(column.x as f32 + offset.x, column.y as f32 + offset.y);
+                            let hit = self.scene.trace_hit(ray, false)?;
+                            let (pos, light) = self.through_surface(&ray, hit)?;
+                            let clouds = self.cloud_light(pos, &incoming);
+                            Some(Photon {
+                                pos,
+                                power: clouds * light * power,
+                            })
+                        })
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        PhotonMap::new(photons, settings.radius)
+    }
+
+    /// Where a ray that hit the scene lands on the bed of water after going through its top, if it hit the top of
+    /// water and the bed is in sight, with the share of light that gets there.
+    fn through_surface(&self, ray: &Ray, hit: Hit) -> Option<(Vec3A, f32)> {
+        let point = ray.origin + hit.distance * ray.dir;
+        if hit.voxel.kind != VoxelKind::WATER
+            || normal::hard(point, ray.dir, self.epsilon) != Vec3A::Y
+        {
+            return None;
+        }
+        let normal = self.waves.normal(point, self.config.time);
+        let dir = ray.dir.refract(normal, 1.0 / WATER_IOR);
+        let (bed, _, distance) = self.under_water(point, dir)?;
+        let light = (1.0 - fresnel(-ray.dir.dot(normal))) * clear(distance);
+        Some((bed, light))
+    }
+
+    /// Color of the top of water of a color at a point where a ray going in direction `dir` reached it, with
+    /// [`Waves`](super::waves::Waves) bending its normal, blended over the bed below as seen through it if caustics
+    /// were shot, see [`Config::caustics`](super::Config::caustics).
+    ///
+    /// The bed is lit by the photons that reached it instead of straight from directional lights, whose light only
+    /// gets there through the waves. Little of it shows at grazing angles, which the surface reflects, or through
+    /// deep water, which fades it out.
+    pub(super) fn shade_water(&self, color: Vec3A, point: Vec3A, dir: Vec3A) -> Vec3A {
+        let normal = self.waves.normal(point, self.config.time);
+        let (water, glints) = self
+            .incoming(point)
+            .map(|incoming| {
+                let light = self.cloud_light(point, &incoming) * incoming.color;
+                (
+                    light
+                        * (self.surface_light(VoxelKind::WATER, point, dir, normal, incoming.dir)
+                            * color),
+                    light * 255.0 * glint(normal, dir, incoming.dir),
+                )
+            })
+            .fold((Vec3A::ZERO, Vec3A::ZERO), |(a, b), (c, d)| (a + c, b + d));
+        let Some(photons) = &self.photons else {
+            return water + glints;
+        };
+        let refracted = dir.refract(normal, 1.0 / WATER_IOR);
+        let Some((bed, voxel, distance)) = self.under_water(point, refracted) else {
+            return water + glints;
+        };
+        let bed_normal = self.normal(bed, refracted);
+        let light = self
+            .incoming(bed)
+            .map(|incoming| match incoming.distance {
+                f32::INFINITY => AMBIENT * self.cloud_light(bed, &incoming) * incoming.color,
+                _ => self.light(bed_normal, incoming.dir) * incoming.color,
+            })
+            .sum::<Vec3A>()
+            + (1.0 - AMBIENT) * photons.gather(bed);
+        let seen = (1.0 - fresnel(-dir.dot(normal))) * clear(distance);
+        water.lerp(light * voxel.color.as_vec3a(), seen) + glints
+    }
+
+    /// Where a ray going in direction `dir` from a point in water first reaches something other than water, with the
+    /// voxel there and how far the ray went, see [`RayTracer::through`].
+    ///
+    /// Rays that leave the scene or come back out of the water, or go further than [`MAX_DEPTH`], don't reach a bed.
+    fn under_water(&self, point: Vec3A, dir: Vec3A) -> Option<(Vec3A, Voxel, f32)> {
+        match self.through(point, dir, VoxelKind::WATER, MAX_DEPTH)? {
+            (Some(voxel), distance) => Some((point + distance * dir, voxel, distance)),
+            (None, _) => None,
+        }
+    }
+}
+
+/// Share of light reflected off the surface of water at an angle with a cosine of `cos` to its normal, by Schlick's
+/// approximation.
+fn fresnel(cos: f32) -> f32 {
+    let head_on = ((WATER_IOR - 1.0) / (WATER_IOR + 1.0)).powi(2);
+    head_on + (1.0 - head_on) * (1.0 - cos.clamp(0.0, 1.0)).powi(5)
+}
+
+/// Share of light that gets through a distance of water.
+fn clear(distance: f32) -> f32 {
+    (-distance / CLARITY).exp()
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::U8Vec3;
+
+    use super::*;
+    use crate::{
+        ray_tracer::{dense::DenseStorage, Config},
+        voxel::{grid::VoxelGrid, water::WATER},
+    };
+
+    #[test]
+    fn photons_are_gathered_within_the_radius() {
+        let photon = |x: f32| Photon {
+            pos: Vec3A::new(x, 0.0, 0.0),
+            power: Vec3A::ONE,
+        };
+        let map = PhotonMap::new(
+            vec![photon(0.1), photon(0.45), photon(-0.3), photon(2.0)],
+            0.5,
+        );
+        assert_eq!(map.len(), 4);
+
+        // the three within half a voxel of the origin, spread over the disk around it
+        let area = PI * 0.25;
+        assert!(map
+            .gather(Vec3A::ZERO)
+            .abs_diff_eq(Vec3A::splat(3.0 / area), 1e-4));
+        assert!(map
+            .gather(Vec3A::new(0.8, 0.0, 0.0))
+            .abs_diff_eq(Vec3A::splat(1.0 / area), 1e-4));
+        assert_eq!(map.gather(Vec3A::new(0.0, 5.0, 0.0)), Vec3A::ZERO);
+    }
+
+    #[test]
+    fn caustics_focus_the_light_through_the_waves() {
+        // a pool 2 deep on a sand bed
+        let sand = Voxel::new(U8Vec3::new(220, 200, 150), VoxelKind::SAND);
+        let mut grid = VoxelGrid::new(IVec3::new(24, 4, 24));
+        for x in 0..24 {
+            for z in 0..24 {
+                grid.set(IVec3::new(x, 0, z), Some(sand));
+                grid.set(IVec3::new(x, 1, z), Some(WATER));
+                grid.set(IVec3::new(x, 2, z), Some(WATER));
+            }
+        }
+        let config = Config {
+            size: 24,
+            height: Some(4),
+            shade: true,
+            caustics: Some(CausticSettings::default()),
+            ..Default::default()
+        };
+        let ray_tracer = RayTracer::<DenseStorage>::from_source(config, &grid);
+        let photons = ray_tracer.photons().unwrap();
+        assert!(photons.len() > 20 * 20 * 64);
+
+        // on average the bed gets the sunlight that isn't reflected or soaked up on the way down, but more of it
+        // in some places than others
+        let sun = ray_tracer.lights()[0].incoming(Vec3A::ZERO).unwrap().dir;
+        let down = (-sun).refract(Vec3A::Y, 1.0 / WATER_IOR);
+        let expected = sun.y * (1.0 - fresnel(sun.y)) * clear(2.0 / -down.y);
+        let light: Vec<f32> = (0..64)
+            .flat_map(|x| (0..64).map(move |z| Vec3A::new(x as f32, 4.0, z as f32) / 4.0 + 4.0))
+            .map(|point| photons.gather(point.with_y(1.0)).x)
+            .collect();
+        let mean = light.iter().sum::<f32>() / light.len() as f32;
+        assert!((mean / expected - 1.0).abs() < 0.1);
+        assert!(light.iter().copied().fold(0.0, f32::max) > 1.5 * mean);
+
+        // no photons are kept without water to go through
+        let mut dry = grid.clone();
+        for x in 0..24 {
+            for z in 0..24 {
+                dry.set(IVec3::new(x, 1, z), None);
+                dry.set(IVec3::new(x, 2, z), None);
+            }
+        }
+        let ray_tracer = RayTracer::<DenseStorage>::from_source(config, &dry);
+        assert!(ray_tracer.photons().unwrap().is_empty());
+
+        // or when the config doesn't ask for them
+        let ray_tracer = RayTracer::<DenseStorage>::from_source(
+            Config {
+                caustics: None,
+                ..config
+            },
+            &grid,
+        );
+        assert!(ray_tracer.photons().is_none());
+    }
+}
@@ -0,0 +1,272 @@
+use std::mem;
+
+use glam::Vec3A;
+
+#[cfg(feature = "trace")]
+use tracing::*;
+
+use crate::voxel::VoxelSource;
+
+use super::{
+    palette::{PaletteIndex, VoxelPalette},
+    types::{DRay, Hit, IAabb, Ray},
+    MemoryUsage, Scene,
+};
+
+/// Run of identical voxels in a column, from `start` up to (but not including) `end`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Span {
+    start: u16,
+    end: u16,
+    voxel: PaletteIndex,
+}
+
+/// Vertical columns of voxels stored as runs of identical voxels, with air left out.
+///
+/// Terrain is mostly a few long runs per column (stone, dirt, grass), so this takes far less memory than a grid.
+/// Rays step through the columns like a 2D DDA and find the first run each one crosses from the heights the ray
+/// enters and leaves it at, rather than stepping voxel by voxel.
+pub struct RleStorage {
+    /// Index of the first span of each column, followed by the total number of spans.
+    offsets: Box<[u32]>,
+    spans: Box<[Span]>,
+    palette: VoxelPalette,
+    bb: IAabb,
+}
+
+impl RleStorage {
+    /// Number of runs in all columns.
+    pub fn span_count(&self) -> usize {
+        self.spans.len()
+    }
+
+    fn column(&self, i: usize, k: usize) -> &[Span] {
+        let column = i * self.bb.length() + k;
+        &self.spans[self.offsets[column] as usize..self.offsets[column + 1] as usize]
+    }
+
+    /// Traces a ray through the columns as if they filled `bb`, a box of the same size.
+    fn trace_hit_in(&self, bb: IAabb, ray: Ray) -> Option<Hit> {
+        let range = bb.intersection(ray, ray.near..ray.far)?;
+
+        let min = bb.min().as_vec3a();
+        let size = (bb.max() - bb.min()).as_vec3a();
+        let entry = ray.origin + ray.dir * range.start - min;
+
+        // the column the ray enters, and the distances to its next boundaries on x and z
+        let mut cell = ray.cell(entry).clamp(Vec3A::ZERO, size - 1.0).as_ivec3();
+        let step = ray.dir.signum().as_ivec3();
+        let boundary = |axis: usize, cell: i32| {
+            if ray.dir[axis] == 0.0 {
+                return f32::INFINITY;
+            }
+            let next = cell + (step[axis] + 1) / 2;
+            (min[axis] + next as f32 - ray.origin[axis]) / ray.dir[axis]
+        };
+        let delta = (1.0 / ray.dir).abs();
+        let mut next_x = boundary(0, cell.x);
+        let mut next_z = boundary(2, cell.z);
+
+        let mut enter = range.start;
+        loop {
+            let exit = next_x.min(next_z).min(range.end);
+
+            // heights the ray crosses this column between, relative to the bottom of the box
+            let a = ray.origin.y + ray.dir.y * enter - min.y;
+            let b = ray.origin.y + ray.dir.y * exit - min.y;
+            let (low, high) = (a.min(b), a.max(b));
+
+            // spans don't overlap, so the first one the ray meets is the first one it crosses going up or down
+            let column = self.column(cell.x as usize, cell.z as usize);
+            // neighbouring columns share the height the ray crosses between them at, so counting the tops of spans but
+            // not their bottoms leaves exactly one of two spans meeting there to hit
+            let crosses = |span: &&Span| (span.start as f32) < high && (span.end as f32) >= low;
+            let hit = if ray.dir.y < 0.0 {
+                column.iter().rev().find(crosses)
+            } else {
+                column.iter().find(crosses)
+            };
+            if let Some(span) = hit {
+                // the ray enters the span through its top or bottom if it starts the column above or below it
+                let edge = if ray.dir.y < 0.0 {
+                    span.end
+                } else {
+                    span.start
+                };
+                let through_edge = (min.y + edge as f32 - ray.origin.y) / ray.dir.y;
+                return Some(Hit {
+                    voxel: self.palette.get(span.voxel),
+                    distance: if (span.start as f32) <= a && a <= span.end as f32 {
+                        enter
+                    } else {
+                        through_edge
+                    },
+                });
+            }
+
+            if exit >= range.end {
+                return None;
+            }
+
+            if next_x < next_z {
+                cell.x += step.x;
+                enter = next_x.max(enter);
+                next_x += delta.x;
+            } else {
+                cell.z += step.z;
+                enter = next_z.max(enter);
+                next_z += delta.z;
+            }
+            if cell.x < 0 || cell.z < 0 || cell.x >= size.x as i32 || cell.z >= size.z as i32 {
+                return None;
+            }
+        }
+    }
+}
+
+impl Scene for RleStorage {
+    fn from_voxels<S: VoxelSource + ?Sized>(source: &S, bb: IAabb) -> Self {
+        #[cfg(feature = "trace")]
+        let _span = trace_span!("rle_from_voxels").entered();
+
+        assert!(
+            bb.height() <= u16::MAX as usize,
+            "columns can be at most {} voxels tall",
+            u16::MAX
+        );
+
+        let mut palette = VoxelPalette::new();
+        let mut offsets = Vec::with_capacity(bb.width() * bb.length() + 1);
+        let mut spans: Vec<Span> = Vec::new();
+        let mut column = vec![None; bb.height()];
+
+        for x in bb.iter_x() {
+            for z in bb.iter_z() {
+                offsets.push(spans.len() as u32);
+                let first = spans.len();
+
+                source.column(x, z, bb.iter_y(), &mut column);
+                for (j, voxel) in column.iter().enumerate() {
+                    let Some(voxel) = voxel else {
+                        continue;
+                    };
+                    let voxel = palette.insert(*voxel);
+                    let j = j as u16;
+
+                    match spans[first..].last_mut() {
+                        Some(span) if span.end == j && span.voxel == voxel => span.end += 1,
+                        _ => spans.push(Span {
+                            start: j,
+                            end: j + 1,
+                            voxel,
+                        }),
+                    }
+                }
+            }
+        }
+        offsets.push(u32::try_from(spans.len()).expect("too many spans"));
+
+        #[cfg(feature = "trace")]
+        debug!("spans" = spans.len());
+
+        Self {
+            offsets: offsets.into(),
+            spans: spans.into(),
+            palette,
+            bb,
+        }
+    }
+
+    fn trace_hit(&self, ray: Ray, _debug: bool) -> Option<Hit> {
+        #[cfg(feature = "trace")]
+        let _span = trace_span!("rle_trace").entered();
+
+        self.trace_hit_in(self.bb, ray)
+    }
+
+    fn trace_hit_f64(&self, ray: DRay, _debug: bool) -> Option<Hit> {
+        #[cfg(feature = "trace")]
+        let _span = trace_span!("rle_trace").entered();
+
+        let (start, ray) = ray.enter(self.bb)?;
+        self.trace_hit_in(self.bb.at_origin(), ray)
+            .map(|hit| hit.after(start))
+    }
+
+    fn memory_usage(&self) -> MemoryUsage {
+        MemoryUsage {
+            nodes: self.spans.len(),
+            voxel_bytes: mem::size_of_val(&*self.spans),
+            overhead_bytes: mem::size_of_val(&*self.offsets),
+            palette_bytes: self.palette.heap_bytes(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{IVec3, U8Vec3};
+
+    use super::*;
+    use crate::{
+        ray_tracer::dense::DenseStorage,
+        voxel::{grid::VoxelGrid, Voxel, VoxelGenerator},
+    };
+
+    #[test]
+    fn matches_dense() {
+        let source = VoxelGenerator::new_from_seed(3);
+        let bb = IAabb::new(IVec3::ZERO, 20 * IVec3::ONE);
+        let rle = RleStorage::from_voxels(&source, bb);
+        let dense = DenseStorage::from_voxels(&source, bb);
+
+        let mut hits = 0;
+        for i in 0..200 {
+            let angle = i as f32 * 0.1;
+            let origin = Vec3A::new(50.0 * angle.cos(), 30.0, 50.0 * angle.sin());
+            let target = Vec3A::new((i % 7) as f32 - 3.0, -4.0, (i % 5) as f32 - 2.0);
+            let ray = Ray::new(origin, target - origin);
+            let voxel = rle.trace(ray, false);
+            assert_eq!(voxel, dense.trace(ray, false), "ray {i}");
+            hits += voxel.is_some() as usize;
+        }
+        assert!(hits > 0);
+    }
+
+    #[test]
+    fn spans() {
+        // a column of stone under dirt, with a floating block of stone above
+        let size = IVec3::new(2, 10, 2);
+        let bb = IAabb::new(size / 2, size / 2);
+        let stone = Voxel::from(U8Vec3::splat(128));
+        let dirt = Voxel::from(U8Vec3::new(100, 60, 20));
+        let mut grid = VoxelGrid::new(size);
+        for y in 0..3 {
+            grid.set(IVec3::new(0, y, 0), Some(stone));
+        }
+        grid.set(IVec3::new(0, 3, 0), Some(dirt));
+        grid.set(IVec3::new(0, 4, 0), Some(dirt));
+        grid.set(IVec3::new(0, 8, 0), Some(stone));
+
+        let storage = RleStorage::from_voxels(&grid, bb);
+        assert_eq!(storage.span_count(), 3);
+        assert_eq!(storage.column(0, 0).len(), 3);
+        assert!(storage.column(1, 1).is_empty());
+
+        // straight down hits the floating block, straight up hits the bottom of the column
+        let ray = Ray::new(Vec3A::new(0.5, 20.0, 0.5), Vec3A::NEG_Y);
+        assert_eq!(storage.trace(ray, false), Some(stone));
+        let ray = Ray::new(Vec3A::new(0.5, -20.0, 0.5), Vec3A::Y);
+        assert_eq!(storage.trace(ray, false), Some(stone));
+
+        // sideways through the dirt, and through the gap above it
+        let ray = Ray::new(Vec3A::new(-5.0, 4.5, 0.5), Vec3A::X);
+        assert_eq!(storage.trace(ray, false), Some(dirt));
+        let ray = Ray::new(Vec3A::new(5.0, 6.5, 0.5), Vec3A::NEG_X);
+        assert_eq!(storage.trace(ray, false), None);
+
+        // down at an angle, crossing from the dirt into the stone below it
+        let ray = Ray::new(Vec3A::new(-3.0, 10.5, 0.5), Vec3A::new(1.0, -2.0, 0.0));
+        assert_eq!(storage.trace(ray, false), Some(dirt));
+    }
+}
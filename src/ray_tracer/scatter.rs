@@ -0,0 +1,159 @@
+use glam::Vec3A;
+
+use super::{normal, RayTracer, Scene, AMBIENT};
+use crate::voxel::{Voxel, VoxelKind};
+
+/// How light scatters inside a material it gets through, see [`Config::subsurface`](super::Config::subsurface).
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Scattering {
+    /// Distance, in voxels, over which the material takes away all but a third of the light going through it.
+    distance: f32,
+    /// How far past the edge of the lit side of a surface light wraps around it, as a share of the cosine to the
+    /// light.
+    wrap: f32,
+    /// Color the light scattered in the material takes on, from 0 to 1.
+    tint: Vec3A,
+}
+
+/// Scattering of snow, which lets light a short way in and gives it back a little blue.
+const SNOW: Scattering = Scattering {
+    distance: 1.5,
+    wrap: 0.5,
+    tint: Vec3A::new(0.85, 0.93, 1.0),
+};
+
+/// Scattering of water, clearer than snow and turning the light through it green and blue.
+const WATER: Scattering = Scattering {
+    distance: 3.0,
+    wrap: 0.3,
+    tint: Vec3A::new(0.7, 0.95, 1.0),
+};
+
+/// Furthest, in voxels, that the light getting through a material is followed, past which it is taken to be too
+/// thick for any to get through.
+const MAX_THICKNESS: f32 = 4.0;
+
+/// Scattering of the materials that let light through, see [`Config::subsurface`](super::Config::subsurface).
+fn scattering(kind: VoxelKind) -> Option<Scattering> {
+    match kind {
+        VoxelKind::SNOW => Some(SNOW),
+        VoxelKind::WATER => Some(WATER),
+        _ => None,
+    }
+}
+
+impl<T: Scene + Sync> RayTracer<T> {
+    /// How much of a light from direction `light` falls on a voxel of a kind where a ray going in direction `dir`
+    /// reached it at a point, on a surface with a normal, see [`RayTracer::light`].
+    ///
+    /// With [`Config::subsurface`](super::Config::subsurface), snow and water also let the light in, which scatters
+    /// and comes back out tinted. It wraps a little past the edge of the lit side of their surfaces, softening it,
+    /// and on faces turned away from the light, adds as much as got through the material from the light to half a
+    /// voxel under the face, so thin layers of them glow with the sun behind them.
+    pub(super) fn surface_light(
+        &self,
+        kind: VoxelKind,
+        point: Vec3A,
+        dir: Vec3A,
+        normal: Vec3A,
+        light: Vec3A,
+    ) -> Vec3A {
+        let surface = self.light(normal, light);
+        let Some(scattering) = scattering(kind).filter(|_| self.config.subsurface) else {
+            return Vec3A::splat(surface);
+        };
+        let cos = normal.dot(light);
+        let wrapped = AMBIENT
+            + (1.0 - AMBIENT) * ((cos + scattering.wrap) / (1.0 + scattering.wrap)).max(0.0);
+        let inside = point - 0.5 * normal::hard(point, dir, self.epsilon);
+        let through = match self.through(inside, light, kind, MAX_THICKNESS) {
+            // only light from outside the material gets in, not from behind other voxels
+            Some((None, thickness)) => (-thickness / scattering.distance).exp(),
+            _ => 0.0,
+        };
+        let glow = (1.0 - AMBIENT) * through * (1.0 - cos.max(0.0));
+        Vec3A::splat(surface) + ((wrapped - surface).max(0.0) + glow) * scattering.tint
+    }
+
+    /// How far a ray going in direction `dir` from a point in a voxel of a kind goes before reaching something
+    /// else, with the voxel there or `None` if it came out into empty space, stepping through the cells one at a
+    /// time.
+    ///
+    /// Rays that leave the scene count as coming out into empty space, and those that go further than `max` don't
+    /// come out.
+    pub(super) fn through(
+        &self,
+        point: Vec3A,
+        dir: Vec3A,
+        kind: VoxelKind,
+        max: f32,
+    ) -> Option<(Option<Voxel>, f32)> {
+        let mut cell = (point + self.epsilon * dir).floor().as_ivec3();
+        let step = dir.signum().as_ivec3();
+        let next = cell.as_vec3a() + Vec3A::select(dir.cmpgt(Vec3A::ZERO), Vec3A::ONE, Vec3A::ZERO);
+        let mut crossing = Vec3A::select(
+            dir.cmpeq(Vec3A::ZERO),
+            Vec3A::INFINITY,
+            (next - point) / dir,
+        );
+        let across = (1.0 / dir).abs();
+        let mut distance = 0.0;
+        while distance < max {
+            match self.scene.voxel_at(cell) {
+                Some(voxel) if voxel.kind == kind => {}
+                voxel => return Some((voxel, distance)),
+            }
+            let axis = crossing.min_position();
+            distance = crossing[axis];
+            cell[axis] += step[axis];
+            crossing[axis] += across[axis];
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::IVec3;
+
+    use super::*;
+    use crate::{
+        ray_tracer::{dense::DenseStorage, Config},
+        voxel::{grid::VoxelGrid, snow::SNOW},
+    };
+
+    #[test]
+    fn thin_snow_glows_with_the_light_behind_it() {
+        // a snow wall one voxel thick, and another five thick
+        let mut grid = VoxelGrid::new(IVec3::splat(10));
+        for x in 0..10 {
+            for y in 0..10 {
+                grid.set(IVec3::new(x, y, 2), Some(SNOW));
+                for z in 5..10 {
+                    grid.set(IVec3::new(x, y, z), Some(SNOW));
+                }
+            }
+        }
+        let light = |subsurface: bool, z: f32| {
+            let config = Config {
+                size: 10,
+                height: Some(10),
+                shade: true,
+                subsurface,
+                ..Default::default()
+            };
+            RayTracer::<DenseStorage>::from_source(config, &grid).surface_light(
+                VoxelKind::SNOW,
+                Vec3A::new(5.5, 5.5, z),
+                Vec3A::Z,
+                Vec3A::NEG_Z,
+                Vec3A::Z,
+            )
+        };
+
+        // facing away from the light, only the thin wall lets some of it through
+        assert_eq!(light(false, 2.0), Vec3A::splat(AMBIENT));
+        assert!(light(true, 2.0).min_element() > AMBIENT + 0.1);
+        assert_eq!(light(true, 5.0), Vec3A::splat(AMBIENT));
+    }
+}
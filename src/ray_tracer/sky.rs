@@ -0,0 +1,203 @@
+use glam::{IVec3, Vec3A};
+
+use super::{light::Light, SUN};
+
+/// Direction toward the moon (normalized), low over the far corner of the scene from the default camera so it is
+/// in the frame.
+pub const MOON: Vec3A = Vec3A::new(-8.0 / 9.0, 1.0 / 9.0, -4.0 / 9.0);
+
+/// Color of moonlight, which multiplies the color of everything it lights.
+const MOONLIGHT: Vec3A = Vec3A::new(0.3, 0.38, 0.6);
+
+/// Light from the blue sky by day, from 0 to 1, dimmer than the sun so shadows stay darker than sunlit ground.
+const DAY_SKY_LIGHT: Vec3A = Vec3A::new(0.2, 0.25, 0.35);
+
+/// Color of the night sky straight overhead, from 0 to 255.
+const ZENITH: Vec3A = Vec3A::new(2.0, 4.0, 14.0);
+
+/// Color of the night sky at the horizon and below it, from 0 to 255.
+const HORIZON: Vec3A = Vec3A::new(12.0, 20.0, 45.0);
+
+/// Color of the middle of the moon's disk, from 0 to 255.
+const MOON_COLOR: Vec3A = Vec3A::new(235.0, 235.0, 215.0);
+
+/// Angle from the middle of the moon's disk to its edge, in radians, several times the real moon's so it shows at
+/// the resolutions scenes are rendered at.
+const MOON_SIZE: f32 = 0.04;
+
+/// How much darker the edge of the moon is than its middle.
+const LIMB: f32 = 0.3;
+
+/// Cells across the radius of the sphere stars are scattered over, each of which may hold a star.
+const STAR_GRID: f32 = 300.0;
+
+/// One in this many cells holds a star.
+const STAR_RARITY: u32 = 40;
+
+/// Radius of a star in cells, over which it fades out.
+const STAR_RADIUS: f32 = 0.6;
+
+/// Time of day a scene is rendered at, which sets the light and what is seen in the sky.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum TimeOfDay {
+    /// Lit by the sun, with the sky left transparent.
+    #[default]
+    Day,
+    /// Lit dimly in blue by the moon, under a dark sky with stars and the moon's disk.
+    Night,
+}
+
+impl TimeOfDay {
+    /// Every time of day, in the order they are listed on the command line.
+    pub const ALL: [TimeOfDay; 2] = [Self::Day, Self::Night];
+
+    /// Time of day with a name, ignoring case, see [`TimeOfDay::name`].
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|time| time.name().eq_ignore_ascii_case(name))
+    }
+
+    /// Lower case name, as on the command line and in scene files.
+    pub fn name(self) -> &'static str {
+        match self {
+            TimeOfDay::Day => "day",
+            TimeOfDay::Night => "night",
+        }
+    }
+
+    /// Direction toward the light the scene is lit and shadowed from (normalized).
+    pub fn light_dir(self) -> Vec3A {
+        match self {
+            TimeOfDay::Day => SUN,
+            TimeOfDay::Night => MOON,
+        }
+    }
+
+    /// Color of the light, which multiplies the color of everything in the scene.
+    pub fn light_color(self) -> Vec3A {
+        match self {
+            TimeOfDay::Day => Vec3A::ONE,
+            TimeOfDay::Night => MOONLIGHT,
+        }
+    }
+
+    /// The sun or moon, which lights scenes unless they are given lights of their own.
+    pub fn light(self) -> Light {
+        Light {
+            color: self.light_color(),
+            ..Light::directional(self.light_dir())
+        }
+    }
+
+    /// Light from the whole sky, from 0 to 1, which lights paths that bounce off into it, see
+    /// [`path`](super::path).
+    pub fn sky_light(self) -> Vec3A {
+        match self {
+            TimeOfDay::Day => DAY_SKY_LIGHT,
+            TimeOfDay::Night => (ZENITH + HORIZON) / 2.0 / 255.0,
+        }
+    }
+
+    /// Color from 0 to 255 and opacity of the sky seen in a direction, which is left transparent by day.
+    pub fn sky(self, dir: Vec3A) -> (Vec3A, f32) {
+        match self {
+            TimeOfDay::Day => (Vec3A::ZERO, 0.0),
+            TimeOfDay::Night => (night_sky(dir.normalize()), 1.0),
+        }
+    }
+}
+
+/// Color of the night sky in a direction (normalized): darker overhead than at the horizon, with the moon and stars
+/// above the horizon.
+fn night_sky(dir: Vec3A) -> Vec3A {
+    let sky = HORIZON.lerp(ZENITH, dir.y.max(0.0).sqrt());
+    let from_moon = dir.dot(MOON).clamp(-1.0, 1.0).acos() / MOON_SIZE;
+    if from_moon < 1.0 {
+        return MOON_COLOR * (1.0 - LIMB * from_moon * from_moon);
+    }
+    if dir.y > 0.0 {
+        sky.lerp(Vec3A::splat(255.0), star(dir))
+    } else {
+        sky
+    }
+}
+
+/// Brightness from 0 to 1 of the star seen in a direction (normalized), if any.
+///
+/// The sphere around the camera is divided into cells of a grid, and a few of them, picked by a hash of the cell,
+/// hold a star of their own brightness somewhere inside.
+fn star(dir: Vec3A) -> f32 {
+    let point = dir * STAR_GRID;
+    let cell = point.floor();
+    let hash = hash(cell.as_ivec3());
+    if hash % STAR_RARITY != 0 {
+        return 0.0;
+    }
+    let unit = |shift: u32| (hash >> shift & 0xff) as f32 / 255.0;
+    let offset = Vec3A::new(unit(8), unit(16), unit(24));
+    // moved onto the sphere, since the cell's corner can be far from it
+    let center = (cell + offset).normalize() * STAR_GRID;
+    let brightness = 0.3 + 0.7 * unit(4) * unit(4);
+    brightness * (1.0 - point.distance(center) / STAR_RADIUS).max(0.0)
+}
+
+/// Well mixed bits of a cell's position.
+fn hash(cell: IVec3) -> u32 {
+    let mut h = (cell.x as u32).wrapping_mul(0x27d4_eb2d)
+        ^ (cell.y as u32).wrapping_mul(0x1656_67b1)
+        ^ (cell.z as u32).wrapping_mul(0x9e37_79b9);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x85eb_ca6b);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0xc2b2_ae35);
+    h ^ h >> 16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn night_sky_has_moon_and_stars() {
+        assert!(MOON.is_normalized());
+        assert_eq!(TimeOfDay::Day.sky(MOON), (Vec3A::ZERO, 0.0));
+
+        // the moon is seen in the sky at night
+        let (moon, opacity) = TimeOfDay::Night.sky(3.0 * MOON);
+        assert!(moon.abs_diff_eq(MOON_COLOR, 1.0) && opacity == 1.0);
+
+        // elsewhere the sky is dark, apart from a few stars
+        let directions = (0..10_000).map(|i| {
+            let (yaw, pitch) = (i as f32 * 0.0137, 0.1 + (i % 100) as f32 * 0.014);
+            Vec3A::new(
+                yaw.cos() * pitch.cos(),
+                pitch.sin(),
+                yaw.sin() * pitch.cos(),
+            )
+        });
+        let mut stars = 0;
+        for dir in directions.filter(|dir| dir.dot(MOON) < 0.99) {
+            let (color, opacity) = TimeOfDay::Night.sky(dir);
+            assert_eq!(opacity, 1.0);
+            if color.x > HORIZON.x {
+                stars += 1;
+            } else {
+                assert!(color.z > color.x && color.z <= HORIZON.z);
+            }
+        }
+        assert!((10..1000).contains(&stars), "{stars}");
+        // but none below the horizon
+        let (below, _) = TimeOfDay::Night.sky(Vec3A::new(1.0, -0.2, 0.3));
+        assert_eq!(below, HORIZON);
+    }
+
+    #[test]
+    fn moonlight_is_dim_and_blue() {
+        assert_eq!(TimeOfDay::Day.light_dir(), SUN);
+        assert_eq!(TimeOfDay::Day.light_color(), Vec3A::ONE);
+        let light = TimeOfDay::Night.light_color();
+        assert!(light.max_element() < 1.0 && light.z > light.x);
+    }
+}
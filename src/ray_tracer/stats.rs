@@ -0,0 +1,152 @@
+use std::{cell::Cell, fmt};
+
+thread_local! {
+    /// Work done by the ray being traced on this thread since the last [`take`].
+    static RAY: Cell<RayStats> = const { Cell::new(RayStats { nodes: 0, steps: 0, depth: 0 }) };
+}
+
+/// Counts a branch or brick of an octree entered by the ray being traced, `depth` levels below the root.
+pub(super) fn node(depth: u32) {
+    RAY.with(|ray| {
+        let mut stats = ray.get();
+        stats.nodes += 1;
+        stats.depth = stats.depth.max(depth);
+        ray.set(stats);
+    });
+}
+
+/// Counts a cell of a grid stepped through by the ray being traced.
+pub(super) fn step() {
+    RAY.with(|ray| {
+        let mut stats = ray.get();
+        stats.steps += 1;
+        ray.set(stats);
+    });
+}
+
+/// Work done tracing on this thread since the last call, which is the work of one ray if it is called after each.
+pub(super) fn take() -> RayStats {
+    RAY.take()
+}
+
+/// Work done tracing a ray through a [`Scene`](super::Scene), or a packet or beam of rays traced together.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RayStats {
+    /// Octree branches and bricks entered.
+    pub nodes: u32,
+    /// Grid cells stepped through, in bricks, chunks and grids of chunks or octrees.
+    pub steps: u32,
+    /// Deepest octree level entered, counting the root as zero.
+    pub depth: u32,
+}
+
+/// Work done tracing the rays of a tile or a whole frame.
+///
+/// Tiles are the groups of pixels rendered together: the squares traced as packets or beams, rows of blocks in
+/// progressive renders, and single pixels otherwise.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TraversalStats {
+    pub rays: u64,
+    pub nodes: u64,
+    pub steps: u64,
+    /// Deepest octree level entered by any ray.
+    pub depth: u32,
+    pub tiles: u64,
+    /// Most nodes and steps taken in any one tile.
+    pub busiest_tile: u64,
+}
+
+impl TraversalStats {
+    /// Adds the work of rays traced together as part of the same tile, such as a single ray or a packet.
+    pub(super) fn add_rays(&mut self, rays: u64, work: RayStats) {
+        self.rays += rays;
+        self.nodes += work.nodes as u64;
+        self.steps += work.steps as u64;
+        self.depth = self.depth.max(work.depth);
+    }
+
+    /// Adds the work of a whole tile.
+    pub(super) fn add_tile(&mut self, tile: TraversalStats) {
+        self.rays += tile.rays;
+        self.nodes += tile.nodes;
+        self.steps += tile.steps;
+        self.depth = self.depth.max(tile.depth);
+        self.tiles += 1;
+        self.busiest_tile = self.busiest_tile.max(tile.nodes + tile.steps);
+    }
+
+    /// Average nodes and steps per ray, or zero before any rays.
+    pub fn per_ray(&self) -> (f64, f64) {
+        match self.rays {
+            0 => (0.0, 0.0),
+            rays => (
+                self.nodes as f64 / rays as f64,
+                self.steps as f64 / rays as f64,
+            ),
+        }
+    }
+}
+
+impl fmt::Display for TraversalStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (nodes, steps) = self.per_ray();
+        write!(
+            f,
+            "{} rays, {:.1} nodes and {:.1} steps per ray, depth {}, busiest of {} tiles took {}",
+            self.rays, nodes, steps, self.depth, self.tiles, self.busiest_tile
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_per_ray_and_tile() {
+        take();
+        node(0);
+        node(3);
+        step();
+        step();
+        let first = take();
+        assert_eq!(
+            first,
+            RayStats {
+                nodes: 2,
+                steps: 2,
+                depth: 3
+            }
+        );
+        assert_eq!(take(), RayStats::default());
+
+        let mut tile = TraversalStats::default();
+        tile.add_rays(1, first);
+        tile.add_rays(
+            1,
+            RayStats {
+                nodes: 1,
+                steps: 5,
+                depth: 1,
+            },
+        );
+        let mut frame = TraversalStats::default();
+        frame.add_tile(tile);
+        frame.add_tile(TraversalStats::default());
+        assert_eq!(
+            frame,
+            TraversalStats {
+                rays: 2,
+                nodes: 3,
+                steps: 7,
+                depth: 3,
+                tiles: 2,
+                busiest_tile: 10,
+            }
+        );
+        assert_eq!(
+            frame.to_string(),
+            "2 rays, 1.5 nodes and 3.5 steps per ray, depth 3, busiest of 2 tiles took 10"
+        );
+    }
+}
@@ -0,0 +1,420 @@
+use std::{
+    env,
+    fs::{self, File},
+    io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    mem,
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use glam::IVec3;
+
+#[cfg(feature = "trace")]
+use tracing::*;
+
+use crate::{archive::ArchiveError, voxel::VoxelSource};
+
+use super::{
+    binary::{
+        read_header, read_palette, read_u32, read_u64, write_header, write_palette, write_u32,
+    },
+    cache::{CacheStats, ChunkCache},
+    chunked::{chunk_index, march_chunks, march_chunks_f64, CHUNK_SIZE},
+    palette::{PaletteIndex, VoxelPalette},
+    types::{DRay, Hit, IAabb, Ray},
+    MemoryUsage, Scene,
+};
+
+const MAGIC: &[u8; 8] = b"VOXSTREM";
+/// Version of the streamed chunk file format.
+const VERSION: u32 = 1;
+
+const CHUNK_VOLUME: usize = (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as usize;
+
+/// Where the offset of the chunk table is kept in the file, after the magic, version and bounding box.
+const TABLE_OFFSET_POS: u64 = 8 + 4 + 24;
+
+/// Temporary files created so far by this process, to give each one a new name.
+static TEMPORARY_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// Chunk of voxels read from the file, as palette indices in [`chunk_index`] order.
+type ChunkData = Arc<[Option<PaletteIndex>]>;
+
+/// Dense chunks kept in a file and only read once a ray first enters them, so scenes larger than memory can be
+/// rendered.
+///
+/// Chunks are laid out like [`ChunkedStorage`](super::chunked::ChunkedStorage), but each one is loaded from the file
+/// the first time it is needed and kept in a cache, which can be given a memory budget to drop the least recently used
+/// chunks and read them again when they are next needed.
+///
+/// Files start with a header (magic, version, bounding box) and the offset of the chunk table, followed by every
+/// chunk with voxels compressed with zlib. The table at the end holds the palette, then the offset and length of
+/// every chunk of the grid with x changing fastest, where empty chunks have a length of zero.
+pub struct StreamingStorage {
+    file: Mutex<File>,
+    /// Offset and length in the file of each chunk, or `None` for empty chunks.
+    table: Box<[Option<(u64, u32)>]>,
+    /// Chunks read from the file, by their index in the table.
+    chunks: ChunkCache<usize, ChunkData>,
+    /// Number of chunks along each axis.
+    cells: IVec3,
+    palette: VoxelPalette,
+    bb: IAabb,
+    /// File written by [`Scene::from_voxels`], which is deleted along with the storage.
+    temporary: Option<PathBuf>,
+}
+
+impl StreamingStorage {
+    /// Writes the voxels of a source into a file that can be opened with [`StreamingStorage::open`].
+    ///
+    /// Only one column of chunks is kept in memory at a time.
+    pub fn create<S: VoxelSource + ?Sized>(
+        source: &S,
+        bb: IAabb,
+        path: impl AsRef<Path>,
+    ) -> Result<(), ArchiveError> {
+        #[cfg(feature = "trace")]
+        let _span = trace_span!("streaming_create").entered();
+
+        let mut file = BufWriter::new(File::create(path)?);
+        write_header(&mut file, MAGIC, VERSION, bb)?;
+        // filled in once the chunks are written
+        file.write_all(&0u64.to_le_bytes())?;
+        let mut offset = TABLE_OFFSET_POS + 8;
+
+        let cells = chunk_cells(bb);
+        let mut table = vec![None; cells.element_product() as usize];
+        let mut palette = VoxelPalette::new();
+        let mut column = vec![None; bb.height()];
+        let mut stack = vec![vec![None; CHUNK_VOLUME]; cells.y as usize];
+
+        for cz in 0..cells.z {
+            for cx in 0..cells.x {
+                stack.iter_mut().for_each(|chunk| chunk.fill(None));
+
+                let min = bb.min() + CHUNK_SIZE * IVec3::new(cx, 0, cz);
+                for lz in 0..CHUNK_SIZE.min(bb.max().z - min.z) {
+                    for lx in 0..CHUNK_SIZE.min(bb.max().x - min.x) {
+                        source.column(min.x + lx, min.z + lz, bb.iter_y(), &mut column);
+                        for (j, voxel) in column.iter().enumerate() {
+                            if let Some(voxel) = voxel {
+                                let local = IVec3::new(lx, j as i32 % CHUNK_SIZE, lz);
+                                stack[j / CHUNK_SIZE as usize][chunk_index(local, CHUNK_SIZE)] =
+                                    Some(palette.insert(*voxel));
+                            }
+                        }
+                    }
+                }
+
+                for (cy, chunk) in stack.iter().enumerate() {
+                    if chunk.iter().all(Option::is_none) {
+                        continue;
+                    }
+
+                    let bytes: Vec<u8> = chunk
+                        .iter()
+                        .flat_map(|index| index.map_or(0, |index| index.get()).to_le_bytes())
+                        .collect();
+                    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::fast());
+                    encoder.write_all(&bytes)?;
+                    let data = encoder.finish()?;
+                    file.write_all(&data)?;
+
+                    let cell = IVec3::new(cx, cy as i32, cz);
+                    table[cell_index(cell, cells)] = Some((offset, data.len() as u32));
+                    offset += data.len() as u64;
+                }
+            }
+        }
+
+        write_palette(&mut file, &palette)?;
+        for (offset, len) in table.iter().map(|entry| entry.unwrap_or_default()) {
+            file.write_all(&offset.to_le_bytes())?;
+            write_u32(&mut file, len)?;
+        }
+
+        let mut file = file.into_inner().map_err(|err| err.into_error())?;
+        file.seek(SeekFrom::Start(TABLE_OFFSET_POS))?;
+        file.write_all(&offset.to_le_bytes())?;
+        Ok(())
+    }
+
+    /// Opens a file written by [`StreamingStorage::create`], reading only its chunk table.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, ArchiveError> {
+        let mut file = BufReader::new(File::open(path)?);
+        let (_, bb) = read_header(&mut file, MAGIC, VERSION)?;
+        let table_offset = read_u64(&mut file)?;
+        file.seek(SeekFrom::Start(table_offset))?;
+        let palette = read_palette(&mut file)?;
+
+        let cells = chunk_cells(bb);
+        let count = cells.element_product() as usize;
+        // the count comes from the file, so only trust it so far before anything is read
+        let mut table = Vec::with_capacity(count.min(1 << 20));
+        for _ in 0..count {
+            let offset = read_u64(&mut file)?;
+            let len = read_u32(&mut file)?;
+            table.push((len > 0).then_some((offset, len)));
+        }
+
+        Ok(Self {
+            file: Mutex::new(file.into_inner()),
+            table: table.into(),
+            chunks: ChunkCache::new(None),
+            cells,
+            palette,
+            bb,
+            temporary: None,
+        })
+    }
+
+    /// Keeps at most this many bytes of chunks in memory, dropping the least recently used ones past it, or every
+    /// chunk without a budget.
+    pub fn with_cache_budget(mut self, budget: Option<usize>) -> Self {
+        self.chunks = ChunkCache::new(budget);
+        self
+    }
+
+    /// Number of chunks read from the file and still in memory.
+    pub fn loaded_count(&self) -> usize {
+        self.chunks.len()
+    }
+
+    /// Voxels of a chunk, reading them from the file if they aren't in memory.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the chunk can't be read, since traces have no way to report it.
+    fn chunk(&self, cell: IVec3) -> Option<ChunkData> {
+        if cell.cmpge(self.cells).any() {
+            return None;
+        }
+        let i = cell_index(cell, self.cells);
+        let (offset, len) = self.table[i]?;
+        let chunk = self.chunks.get_or_load(
+            i,
+            || {
+                self.load(offset, len)
+                    .unwrap_or_else(|err| panic!("failed to stream chunk {cell}: {err}"))
+            },
+            |chunk| mem::size_of_val(&**chunk),
+        );
+        Some(chunk)
+    }
+
+    fn load(&self, offset: u64, len: u32) -> Result<ChunkData, ArchiveError> {
+        #[cfg(feature = "trace")]
+        let _span = trace_span!("streaming_load").entered();
+
+        let mut data = vec![0; len as usize];
+        {
+            let mut file = self.file.lock().expect("chunk file lock was poisoned");
+            file.seek(SeekFrom::Start(offset))?;
+            file.read_exact(&mut data)?;
+        }
+
+        let mut bytes = Vec::with_capacity(2 * CHUNK_VOLUME);
+        ZlibDecoder::new(data.as_slice()).read_to_end(&mut bytes)?;
+        if bytes.len() != 2 * CHUNK_VOLUME {
+            return Err(ArchiveError::Format("chunk has the wrong size".into()));
+        }
+
+        bytes
+            .chunks_exact(2)
+            .map(|bytes| {
+                let index = u16::from_le_bytes([bytes[0], bytes[1]]);
+                if index as usize > self.palette.len() {
+                    return Err(ArchiveError::Format(format!(
+                        "palette index {index} out of range"
+                    )));
+                }
+                Ok(PaletteIndex::new(index))
+            })
+            .collect()
+    }
+}
+
+impl Scene for StreamingStorage {
+    /// Writes the voxels to a temporary file and streams them back from it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the temporary file can't be written.
+    fn from_voxels<S: VoxelSource + ?Sized>(source: &S, bb: IAabb) -> Self {
+        #[cfg(feature = "trace")]
+        let _span = trace_span!("streaming_from_voxels").entered();
+
+        let id = TEMPORARY_FILES.fetch_add(1, Ordering::Relaxed);
+        let path = env::temp_dir().join(format!("voxel_ray_tracer_{}_{id}.vxc", process::id()));
+        let storage = Self::create(source, bb, &path).and_then(|()| Self::open(&path));
+        let mut storage = storage.unwrap_or_else(|err| {
+            let _ = fs::remove_file(&path);
+            panic!("failed to stream chunks through {}: {err}", path.display())
+        });
+        storage.temporary = Some(path);
+
+        #[cfg(feature = "trace")]
+        debug!("chunks" = storage.table.iter().flatten().count());
+
+        storage
+    }
+
+    fn trace_hit(&self, ray: Ray, _debug: bool) -> Option<Hit> {
+        #[cfg(feature = "trace")]
+        let _span = trace_span!("streaming_trace").entered();
+
+        march_chunks(self.bb, CHUNK_SIZE, ray, |cell| {
+            Some((self.chunk(cell)?, &self.palette))
+        })
+        .map(|(hit, _)| hit)
+    }
+
+    fn trace_hit_f64(&self, ray: DRay, _debug: bool) -> Option<Hit> {
+        march_chunks_f64(self.bb, CHUNK_SIZE, ray, |cell| {
+            Some((self.chunk(cell)?, &self.palette))
+        })
+        .map(|(hit, _)| hit)
+    }
+
+    /// Counts the chunks in memory, which grow as more of the scene is rendered up to the cache budget.
+    fn memory_usage(&self) -> MemoryUsage {
+        let loaded = self.loaded_count();
+        let voxel_bytes = loaded * CHUNK_VOLUME * mem::size_of::<Option<PaletteIndex>>();
+        MemoryUsage {
+            nodes: loaded,
+            voxel_bytes,
+            overhead_bytes: mem::size_of_val(&*self.table) + self.chunks.bytes() - voxel_bytes,
+            palette_bytes: self.palette.heap_bytes(),
+        }
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        Some(self.chunks.stats())
+    }
+}
+
+impl Drop for StreamingStorage {
+    fn drop(&mut self) {
+        if let Some(path) = &self.temporary {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Number of chunks along each axis of a bounding box, counting partial chunks at the far edges.
+fn chunk_cells(bb: IAabb) -> IVec3 {
+    (bb.max() - bb.min() + CHUNK_SIZE - 1) / CHUNK_SIZE
+}
+
+/// Index of a chunk in the table, with x changing fastest.
+fn cell_index(cell: IVec3, cells: IVec3) -> usize {
+    (cell.x + cells.x * (cell.y + cells.y * cell.z)) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{U8Vec3, Vec3A};
+
+    use super::*;
+    use crate::{
+        ray_tracer::chunked::ChunkedStorage,
+        voxel::{grid::VoxelGrid, Voxel, VoxelGenerator},
+    };
+
+    #[test]
+    fn matches_chunked() {
+        let source = VoxelGenerator::new_from_seed(3);
+        let bb = IAabb::new(IVec3::ZERO, 20 * IVec3::ONE);
+        let streaming = StreamingStorage::from_voxels(&source, bb);
+        let chunked = ChunkedStorage::from_voxels(&source, bb);
+        assert_eq!(streaming.loaded_count(), 0);
+
+        let mut hits = 0;
+        for i in 0..200 {
+            let angle = i as f32 * 0.1;
+            let origin = Vec3A::new(50.0 * angle.cos(), 30.0, 50.0 * angle.sin());
+            let target = Vec3A::new((i % 7) as f32 - 3.0, -4.0, (i % 5) as f32 - 2.0);
+            let ray = Ray::new(origin, target - origin);
+            let voxel = streaming.trace(ray, false);
+            assert_eq!(voxel, chunked.trace(ray, false), "ray {i}");
+            hits += voxel.is_some() as usize;
+        }
+        assert!(hits > 0);
+        assert!(streaming.loaded_count() <= chunked.chunk_count());
+
+        let path = streaming
+            .temporary
+            .clone()
+            .expect("streamed through a file");
+        assert!(path.exists());
+        drop(streaming);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn evicts_over_budget() {
+        let source = VoxelGenerator::new_from_seed(3);
+        let bb = IAabb::new(IVec3::ZERO, 20 * IVec3::ONE);
+        // room for two chunks
+        let budget = 2 * (CHUNK_VOLUME * mem::size_of::<Option<PaletteIndex>>() + 64);
+        let streaming = StreamingStorage::from_voxels(&source, bb).with_cache_budget(Some(budget));
+        let chunked = ChunkedStorage::from_voxels(&source, bb);
+
+        for i in 0..200 {
+            let angle = i as f32 * 0.1;
+            let origin = Vec3A::new(50.0 * angle.cos(), 30.0, 50.0 * angle.sin());
+            let target = Vec3A::new((i % 7) as f32 - 3.0, -4.0, (i % 5) as f32 - 2.0);
+            let ray = Ray::new(origin, target - origin);
+            assert_eq!(
+                streaming.trace(ray, false),
+                chunked.trace(ray, false),
+                "ray {i}"
+            );
+            assert!(streaming.loaded_count() <= 2);
+        }
+
+        let stats = streaming.cache_stats().expect("streaming has a cache");
+        assert!(stats.evictions > 0);
+        assert_eq!(
+            stats.misses,
+            stats.evictions + streaming.loaded_count() as u64
+        );
+        assert!(streaming.chunks.bytes() <= budget);
+    }
+
+    #[test]
+    fn loads_chunks_lazily() {
+        // voxels in two chunks at opposite corners of a 48 wide box
+        let size = IVec3::new(48, 36, 40);
+        let bb = IAabb::new(size / 2, size / 2);
+        let red = Voxel::from(U8Vec3::new(255, 0, 0));
+        let blue = Voxel::from(U8Vec3::new(0, 0, 255));
+        let mut grid = VoxelGrid::new(size);
+        grid.set(IVec3::ZERO, Some(red));
+        grid.set(size - 1, Some(blue));
+
+        let path = env::temp_dir().join(format!("voxel_ray_tracer_test_{}.vxc", process::id()));
+        StreamingStorage::create(&grid, bb, &path).expect("failed to write");
+        let storage = StreamingStorage::open(&path).expect("failed to open");
+
+        assert_eq!(storage.table.iter().flatten().count(), 2);
+        let ray = Ray::new(Vec3A::new(0.5, 0.5, -10.0), Vec3A::Z);
+        assert_eq!(storage.trace(ray, false), Some(red));
+        assert_eq!(storage.loaded_count(), 1);
+        assert_eq!(storage.trace(ray, false), Some(red));
+        assert_eq!(storage.loaded_count(), 1);
+
+        let ray = Ray::new(Vec3A::new(47.5, 35.5, 50.0), Vec3A::NEG_Z);
+        assert_eq!(storage.trace(ray, false), Some(blue));
+        assert_eq!(storage.loaded_count(), 2);
+        assert_eq!(storage.memory_usage().nodes, 2);
+
+        drop(storage);
+        fs::remove_file(&path).expect("failed to remove");
+    }
+}
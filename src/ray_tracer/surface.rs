@@ -0,0 +1,126 @@
+use glam::{IVec3, Vec3A};
+
+use crate::voxel::Voxel;
+
+use super::types::{Hit, Ray};
+
+/// Distance between the points a ray is sampled at while looking for the surface, in voxels.
+const STEP: f32 = 0.25;
+
+/// Times the step the surface was crossed in is halved to find where.
+const BISECTIONS: usize = 8;
+
+/// Density at a point, from 0 outside the voxels to 1 inside, interpolated trilinearly between the centers of the 8
+/// cells around it, each 1 if it holds a voxel. The smooth surface is where it is a half.
+pub(super) fn density(point: Vec3A, voxel_at: impl Fn(IVec3) -> Option<Voxel>) -> f32 {
+    corners(point)
+        .filter(|&(cell, _)| voxel_at(cell).is_some())
+        .map(|(_, weight)| weight)
+        .sum()
+}
+
+/// First point from `start` to `end` along a ray where it enters the smooth surface around the voxels, given the
+/// voxel at each cell, with the voxel that weighs the most in the density there.
+///
+/// The ray is sampled every [`STEP`], so parts of the surface thinner than that can be stepped over.
+pub(super) fn intersect(
+    ray: &Ray,
+    start: f32,
+    end: f32,
+    voxel_at: impl Fn(IVec3) -> Option<Voxel>,
+) -> Option<Hit> {
+    let inside = |distance: f32| density(ray.origin + distance * ray.dir, &voxel_at) >= 0.5;
+
+    let (mut before, mut after) = (start, start);
+    while !inside(after) {
+        if after >= end {
+            return None;
+        }
+        before = after;
+        after = (after + STEP).min(end);
+    }
+    for _ in 0..BISECTIONS {
+        let middle = (before + after) / 2.0;
+        if inside(middle) {
+            after = middle;
+        } else {
+            before = middle;
+        }
+    }
+
+    let point = ray.origin + after * ray.dir;
+    let (cell, _) = corners(point)
+        .filter(|&(cell, _)| voxel_at(cell).is_some())
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))?;
+    Some(Hit {
+        voxel: voxel_at(cell)?,
+        distance: after,
+    })
+}
+
+/// The 8 cells whose centers surround a point, with the weight of each in trilinear interpolation.
+fn corners(point: Vec3A) -> impl Iterator<Item = (IVec3, f32)> {
+    let corner = point - 0.5;
+    let min = corner.floor();
+    let t = corner - min;
+    let min = min.as_ivec3();
+    (0..8).map(move |i| {
+        let offset = IVec3::new(i & 1, i >> 1 & 1, i >> 2 & 1);
+        let side = offset.as_vec3a();
+        let weight = (side * t + (1.0 - side) * (1.0 - t)).element_product();
+        (min + offset, weight)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::U8Vec3;
+
+    use super::*;
+
+    fn voxel(red: u8) -> Option<Voxel> {
+        Some(Voxel::from(U8Vec3::new(red, 0, 0)))
+    }
+
+    #[test]
+    fn flat_ground_keeps_its_height() {
+        let ground = |cell: IVec3| (cell.y < 0).then(|| voxel(1)).flatten();
+        assert_eq!(density(Vec3A::new(0.3, 0.0, 7.9), ground), 0.5);
+        assert_eq!(density(Vec3A::new(0.3, -0.5, 7.9), ground), 1.0);
+        assert_eq!(density(Vec3A::new(0.3, 0.5, 7.9), ground), 0.0);
+
+        let ray = Ray::new(Vec3A::new(0.2, 3.0, 0.4), Vec3A::new(0.3, -1.0, 0.2));
+        let hit = intersect(&ray, 0.0, 10.0, ground).unwrap();
+        let point = ray.origin + hit.distance * ray.dir;
+        assert!(point.y.abs() < 1e-2, "{point}");
+        assert_eq!(hit.voxel, voxel(1).unwrap());
+        assert_eq!(intersect(&ray, 0.0, 2.0, ground), None);
+    }
+
+    #[test]
+    fn corners_are_rounded() {
+        let cube = |cell: IVec3| (cell == IVec3::ZERO).then(|| voxel(2)).flatten();
+        // the middle of each face is still on the surface
+        let ray = Ray::new(Vec3A::new(-3.0, 0.5, 0.5), Vec3A::X);
+        let hit = intersect(&ray, 0.0, 10.0, cube).unwrap();
+        assert!((hit.distance - 3.0).abs() < 1e-2, "{}", hit.distance);
+        // a ray clipping the corner of the cube misses it
+        let ray = Ray::new(Vec3A::new(-3.0, 0.95, 0.95), Vec3A::X);
+        assert_eq!(intersect(&ray, 0.0, 10.0, cube), None);
+
+        // the inner corner of a step is filled in
+        let step = |cell: IVec3| match cell {
+            IVec3 { x: 0, y: 0, z: 0 } => voxel(3),
+            IVec3 {
+                x: 0 | 1,
+                y: -1,
+                z: 0,
+            } => voxel(4),
+            _ => None,
+        };
+        assert!(density(Vec3A::new(1.05, 0.05, 0.5), step) > 0.5);
+        let ray = Ray::new(Vec3A::new(1.2, 2.0, 0.5), -Vec3A::Y);
+        let hit = intersect(&ray, 0.0, 10.0, step).unwrap();
+        assert!(hit.distance < 2.0);
+    }
+}
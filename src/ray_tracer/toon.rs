@@ -0,0 +1,127 @@
+use glam::Vec3A;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+/// Depth and normal of what every pixel of a render hit, for effects that need more than its color, such as the
+/// outlines of [`Config::toon`](super::Config::toon).
+#[derive(Clone, Debug, PartialEq)]
+pub struct GBuffer {
+    pub width: usize,
+    pub height: usize,
+    /// Distance along each pixel's ray to what it hit, row by row, and infinity where it hit nothing.
+    pub depth: Vec<f32>,
+    /// Normal of the surface each pixel hit, row by row, and zero where it hit nothing.
+    pub normals: Vec<Vec3A>,
+}
+
+impl GBuffer {
+    /// Pixels on an outline, row by row: the edge of anything in front of the sky, a jump in depth between
+    /// surfaces one in front of the other, or a crease where the surface turns by more than [`CREASE`].
+    ///
+    /// Jumps are found from how far the pixel's inverse depth is from the average of its neighbors', which stays
+    /// close to zero across a plane however steeply it is seen, so only the nearer side of a jump is outlined.
+    pub fn outlines(&self) -> Vec<bool> {
+        let (width, height) = (self.width, self.height);
+        (0..width * height)
+            .into_par_iter()
+            .map(|i| {
+                let (x, y) = (i % width, i / width);
+                if self.depth[i].is_infinite() {
+                    return false;
+                }
+                let inverse = |j: usize| 1.0 / self.depth[j];
+                let neighbors = |dx: usize, dy: usize| {
+                    let before = (x >= dx && y >= dy).then(|| i - dx - dy * width);
+                    let after = (x + dx < width && y + dy < height).then(|| i + dx + dy * width);
+                    [before, after]
+                };
+
+                for [before, after] in [neighbors(1, 0), neighbors(0, 1)] {
+                    for j in [before, after].into_iter().flatten() {
+                        if self.depth[j].is_infinite()
+                            || self.normals[i].dot(self.normals[j]) < CREASE
+                        {
+                            return true;
+                        }
+                    }
+                    if let (Some(before), Some(after)) = (before, after) {
+                        let curve = inverse(before) + inverse(after) - 2.0 * inverse(i);
+                        if curve < -JUMP * inverse(i) {
+                            return true;
+                        }
+                    }
+                }
+                false
+            })
+            .collect()
+    }
+}
+
+/// Rounds light down to one of [`BANDS`] levels between `ambient` and full light, so a surface is lit in flat bands
+/// like a cartoon instead of a smooth gradient.
+pub(super) fn band(light: f32, ambient: f32) -> f32 {
+    let steps = (BANDS - 1) as f32;
+    let level = ((light - ambient) / (1.0 - ambient) * steps + 1e-4).floor();
+    ambient + (1.0 - ambient) * level.clamp(0.0, steps) / steps
+}
+
+/// Levels of light in toon shading.
+const BANDS: usize = 3;
+
+/// Cosine of the angle between the normals of neighboring pixels past which there is a crease between them.
+pub const CREASE: f32 = 0.5;
+
+/// How far a pixel's inverse depth can fall below the average of its neighbors', as a fraction of it, before it is
+/// in front of one of them.
+pub const JUMP: f32 = 0.05;
+
+/// How much of its color a pixel on an outline keeps.
+pub(super) const OUTLINE: f32 = 0.25;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bands_are_flat() {
+        assert_eq!(band(0.5, 0.5), 0.5);
+        assert_eq!(band(0.7, 0.5), 0.5);
+        assert_eq!(band(0.75, 0.5), 0.75);
+        assert_eq!(band(0.99, 0.5), 0.75);
+        assert_eq!(band(1.0, 0.5), 1.0);
+    }
+
+    #[test]
+    fn outlines_follow_edges() {
+        // a floor seen at an angle with the sky above, a raised platform in the middle and a wall on the right
+        let (width, height) = (7, 6);
+        let mut buffer = GBuffer {
+            width,
+            height,
+            depth: vec![f32::INFINITY; width * height],
+            normals: vec![Vec3A::ZERO; width * height],
+        };
+        for y in 1..height {
+            for x in 0..width {
+                let i = y * width + x;
+                let (depth, normal) = match (x, y) {
+                    (2..=4, 2..=4) => (2.0, Vec3A::Y),
+                    (6, _) => (10.0 / y as f32, -Vec3A::X),
+                    _ => (10.0 / y as f32, Vec3A::Y),
+                };
+                buffer.depth[i] = depth;
+                buffer.normals[i] = normal;
+            }
+        }
+
+        let outlines = buffer.outlines();
+        let outline = |x: usize, y: usize| outlines[y * width + x];
+        // along the sky
+        assert!((0..width).all(|x| !outline(x, 0) && outline(x, 1)));
+        // around the platform, on its side of the jump in depth
+        assert!(outline(2, 2) && outline(4, 4) && outline(2, 3));
+        assert!(!outline(3, 3) && !outline(1, 3) && !outline(3, 5));
+        // and along the crease where the wall meets the floor
+        assert!(outline(5, 4) && outline(6, 4));
+        assert!(!outline(0, 3) && !outline(0, 5));
+    }
+}
@@ -0,0 +1,273 @@
+use glam::Vec3A;
+
+use super::{
+    types::{Hit, Ray},
+    RayTracer, Scene,
+};
+use crate::voxel::{Voxel, VoxelKind};
+
+/// Opacity past which what is behind the voxels a ray went through no longer shows.
+const OPAQUE: f32 = 0.99;
+
+/// Most runs of voxels that let light through a ray is followed through, past which the last one it reached counts as
+/// stopping all of the light.
+const MAX_LAYERS: usize = 8;
+
+impl<T: Scene + Sync> RayTracer<T> {
+    /// Color from 0 to 255 and opacity from 0 to 1 of what a ray sees from where it hit the scene, with voxels that let
+    /// light through blended over what lies behind them, see [`Config::transparency`](super::Config::transparency).
+    ///
+    /// The run of voxels of the same kind the ray goes through from each hit is lit where it went in, and stops
+    /// [`VoxelKind::opacity`] of the light for each voxel's width the ray goes through it, so shallow water shows its
+    /// bed and thick glass tints what is behind it. Past the run, the ray is traced on, up to [`MAX_LAYERS`] runs, and
+    /// whatever is left of it that reaches the sky takes its color.
+    pub(super) fn see_through(&self, ray: &Ray, hit: Hit) -> (Vec3A, f32) {
+        // blended front to back, premultiplied by the opacity of each layer
+        let (mut color, mut opacity) = (Vec3A::ZERO, 0.0);
+        let mut next = Some(hit);
+        for layer in 0..MAX_LAYERS {
+            let Some(hit) = next else {
+                let (sky, sky_opacity) = self.time_of_day().sky(ray.dir);
+                let behind = (1.0 - opacity) * sky_opacity;
+                color += behind * sky;
+                opacity += behind;
+                break;
+            };
+            let point = ray.origin + hit.distance * ray.dir;
+            let lit = self.hit_light(hit.voxel, point, ray.dir);
+            let run = match self
+                .opacity(hit.voxel.kind)
+                .filter(|_| layer + 1 < MAX_LAYERS)
+            {
+                Some(stops) => self
+                    .run(point, ray.dir, hit.voxel.kind, 1.0 - stops)
+                    .map(|(voxel, length)| (voxel, length, 1.0 - (1.0 - stops).powf(length))),
+                None => None,
+            };
+            let Some((voxel, length, layer_opacity)) = run else {
+                color += (1.0 - opacity) * lit;
+                opacity = 1.0;
+                break;
+            };
+            color += (1.0 - opacity) * layer_opacity * lit;
+            opacity += (1.0 - opacity) * layer_opacity;
+            if opacity > OPAQUE {
+                break;
+            }
+            let distance = hit.distance + length;
+            next = match voxel {
+                Some(voxel) => Some(Hit { voxel, distance }),
+                None => self.scene.trace_hit(
+                    Ray {
+                        near: distance + self.epsilon,
+                        ..*ray
+                    },
+                    false,
+                ),
+            };
+        }
+        match opacity {
+            0.0 => (Vec3A::ZERO, 0.0),
+            _ => (color / opacity, opacity),
+        }
+    }
+
+    /// Share of each channel of a light that gets to a point from `distance` away in direction `dir`, none if
+    /// anything is in the way.
+    ///
+    /// With [`Config::transparency`](super::Config::transparency), voxels that let light through let it on, tinted
+    /// by their color, for up to [`MAX_LAYERS`] runs of them. Each voxel's width of them lets `1 -`
+    /// [`VoxelKind::opacity`] of the light through untouched, and of the rest only the share of each channel in their
+    /// color, so the shadows of water and glass are lighter than those of stone and take on their color.
+    pub(super) fn light_through(&self, point: Vec3A, dir: Vec3A, distance: f32) -> Vec3A {
+        let ray = Ray {
+            near: 0.0,
+            far: distance,
+            epsilon: self.epsilon,
+            ..Ray::new(point, dir)
+        };
+        let mut light = Vec3A::ONE;
+        let mut next = self.scene.trace_hit(ray, false);
+        for _ in 0..MAX_LAYERS {
+            let Some(hit) = next else {
+                return light;
+            };
+            let Some(opacity) = self.opacity(hit.voxel.kind) else {
+                return Vec3A::ZERO;
+            };
+            let tint = tint(hit.voxel, opacity);
+            let start = point + hit.distance * dir;
+            let Some((voxel, length)) = self.run(start, dir, hit.voxel.kind, tint.max_element())
+            else {
+                return Vec3A::ZERO;
+            };
+            // lights inside the run only shine through the part of it in front of them
+            let length = length.min(ray.far - hit.distance);
+            light *= tint.powf(length);
+            let distance = hit.distance + length;
+            next = match voxel {
+                _ if distance >= ray.far => None,
+                Some(voxel) => Some(Hit { voxel, distance }),
+                None => self.scene.trace_hit(
+                    Ray {
+                        near: distance + self.epsilon,
+                        ..ray
+                    },
+                    false,
+                ),
+            };
+        }
+        Vec3A::ZERO
+    }
+
+    /// Where a ray going in direction `dir` from a point in a voxel of a kind that lets light through comes out of
+    /// the run of them, with the voxel there and how far it went, see [`RayTracer::through`].
+    ///
+    /// `keep` is the share of the light that a voxel's width of them lets through, and the ray isn't followed past
+    /// where less than 1% of it is left, see [`OPAQUE`], which counts as not coming out.
+    fn run(
+        &self,
+        point: Vec3A,
+        dir: Vec3A,
+        kind: VoxelKind,
+        keep: f32,
+    ) -> Option<(Option<Voxel>, f32)> {
+        let max = if keep < 1.0 {
+            (1.0 - OPAQUE).ln() / keep.ln()
+        } else {
+            f32::INFINITY
+        };
+        self.through(point, dir, kind, max)
+    }
+
+    /// Share of the light going through each voxel's width of a kind that it stops, if it lets any through and
+    /// renders see through it, see [`Config::transparency`](super::Config::transparency).
+    ///
+    /// Water drawn with caustics already shows its bed through its top, see
+    /// [`RayTracer::shade_water`], so it is left opaque.
+    fn opacity(&self, kind: VoxelKind) -> Option<f32> {
+        let opacity = kind.opacity();
+        let caustics = kind == VoxelKind::WATER && self.photons.is_some();
+        (self.transparency() && opacity < 1.0 && !caustics).then_some(opacity)
+    }
+}
+
+/// Share of each channel of the light going through a voxel's width of a voxel that stops `opacity` of it: all of
+/// the rest, and the share of the channel in its color of what it stops.
+fn tint(voxel: Voxel, opacity: f32) -> Vec3A {
+    1.0 - opacity * (1.0 - voxel.color.as_vec3a() / 255.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{IVec3, U8Vec3};
+
+    use super::*;
+    use crate::{
+        ray_tracer::{dense::DenseStorage, Config},
+        voxel::{grid::VoxelGrid, water::WATER, Voxel},
+    };
+
+    #[test]
+    fn glass_shows_what_is_behind_it() {
+        // a pane of glass 2 voxels thick in front of a red wall, with a hole in the wall
+        let glass = Voxel::new(U8Vec3::new(200, 220, 225), VoxelKind::GLASS);
+        let wall = Voxel::new(U8Vec3::new(255, 0, 0), VoxelKind::STONE);
+        let mut grid = VoxelGrid::new(IVec3::splat(16));
+        for x in 0..16 {
+            for y in 0..16 {
+                grid.set(IVec3::new(x, y, 4), Some(glass));
+                grid.set(IVec3::new(x, y, 5), Some(glass));
+                if x < 8 {
+                    grid.set(IVec3::new(x, y, 10), Some(wall));
+                }
+            }
+        }
+        let config = Config {
+            size: 16,
+            transparency: true,
+            ..Default::default()
+        };
+        let ray_tracer = RayTracer::<DenseStorage>::from_source(config, &grid);
+        let see = |x: f32| {
+            let ray = Ray::new(Vec3A::new(x, 8.5, 0.5), Vec3A::Z);
+            let hit = ray_tracer.scene.trace_hit(ray, false).unwrap();
+            assert_eq!(hit.voxel, glass);
+            ray_tracer.see_through(&ray, hit)
+        };
+
+        // the wall shows through the glass, tinted by it
+        let stops = 1.0 - (1.0 - VoxelKind::GLASS.opacity()).powi(2);
+        let (color, opacity) = see(4.5);
+        assert_eq!(opacity, 1.0);
+        let red = glass
+            .color
+            .as_vec3a()
+            .lerp(wall.color.as_vec3a(), 1.0 - stops);
+        assert!(color.abs_diff_eq(red, 1e-3), "{color}");
+
+        // and through the hole in it, the glass is all that is seen, as the day sky is left transparent
+        let (color, opacity) = see(12.5);
+        assert!((opacity - stops).abs() < 1e-5);
+        assert!(color.abs_diff_eq(glass.color.as_vec3a(), 1e-3));
+
+        // stone doesn't let any light through
+        let ray = Ray::new(Vec3A::new(4.5, 8.5, 7.0), Vec3A::Z);
+        let hit = ray_tracer.scene.trace_hit(ray, false).unwrap();
+        assert_eq!(
+            ray_tracer.see_through(&ray, hit),
+            (wall.color.as_vec3a(), 1.0)
+        );
+    }
+
+    #[test]
+    fn light_through_glass_and_water_is_tinted() {
+        // a pane of glass 2 voxels thick, then a wall where x is under 8, or a voxel of water where y is under 4
+        let glass = Voxel::new(U8Vec3::new(200, 220, 225), VoxelKind::GLASS);
+        let wall = Voxel::new(U8Vec3::new(255, 0, 0), VoxelKind::STONE);
+        let mut grid = VoxelGrid::new(IVec3::splat(16));
+        for x in 0..16 {
+            for y in 0..16 {
+                grid.set(IVec3::new(x, y, 4), Some(glass));
+                grid.set(IVec3::new(x, y, 5), Some(glass));
+                if x < 8 {
+                    grid.set(IVec3::new(x, y, 10), Some(wall));
+                } else if y < 4 {
+                    grid.set(IVec3::new(x, y, 10), Some(WATER));
+                }
+            }
+        }
+        let config = Config {
+            size: 16,
+            transparency: true,
+            ..Default::default()
+        };
+        let ray_tracer = RayTracer::<DenseStorage>::from_source(config, &grid);
+        let light = |x: f32, y: f32, distance: f32| {
+            ray_tracer.light_through(Vec3A::new(x, y, 0.5), Vec3A::Z, distance)
+        };
+
+        // glass lets most of the light through, but nothing gets through the wall
+        let pane = tint(glass, VoxelKind::GLASS.opacity());
+        assert!(light(12.5, 8.5, f32::INFINITY).abs_diff_eq(pane.powf(2.0), 1e-5));
+        assert!(light(4.5, 8.5, 8.0).abs_diff_eq(pane.powf(2.0), 1e-5));
+        assert_eq!(light(4.5, 8.5, f32::INFINITY), Vec3A::ZERO);
+        // or from a light inside the pane, only through the part in front of it
+        assert!(light(12.5, 8.5, 5.0).abs_diff_eq(pane.powf(1.5), 1e-5));
+
+        // water takes more of the red light than the blue
+        let water = light(12.5, 2.5, f32::INFINITY);
+        assert!(water.x < 0.8 * pane.x && water.z > 0.9 * pane.z);
+
+        // and everything casts whole shadows when renders don't see through it
+        let ray_tracer = RayTracer::<DenseStorage>::from_source(
+            Config {
+                transparency: false,
+                ..config
+            },
+            &grid,
+        );
+        let light = ray_tracer.light_through(Vec3A::new(12.5, 8.5, 0.5), Vec3A::Z, f32::INFINITY);
+        assert_eq!(light, Vec3A::ZERO);
+    }
+}
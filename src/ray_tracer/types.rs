@@ -1,8 +1,10 @@
 use std::ops::Range;
 
-use glam::{BVec2, BVec3, IVec3, Vec3A, Vec3Swizzles};
+use glam::{BVec2, BVec3, DVec3, IVec3, U8Vec3, Vec3A, Vec3Swizzles, Vec4};
 use itertools::Itertools;
 
+use crate::voxel::Voxel;
+
 /// Ray-casting primitive.
 #[derive(Clone, Copy, Debug)]
 pub struct Ray {
@@ -10,6 +12,17 @@ pub struct Ray {
     pub origin: Vec3A,
     /// Direction of the ray (normalized).
     pub dir: Vec3A,
+    /// Width covered by the ray per unit of distance from where it was cast, so scenes can skip details smaller
+    /// than a pixel. Zero for rays that cover a single point.
+    pub spread: f32,
+    /// Distance along the ray before which nothing is hit, so rays cast from a surface don't hit it again.
+    pub near: f32,
+    /// Distance along the ray past which nothing is hit.
+    pub far: f32,
+    /// Distance, in voxels, within which the ray counts as being on a boundary between cells, which covers the
+    /// rounding in finding where it crosses one. Scenes far from the origin need more, see
+    /// [`Config::ray_epsilon`](super::Config::ray_epsilon).
+    pub epsilon: f32,
 }
 
 impl Ray {
@@ -17,10 +30,289 @@ impl Ray {
         Self {
             origin,
             dir: dir.normalize(),
+            spread: 0.0,
+            near: NEAR,
+            far: f32::INFINITY,
+            epsilon: ON_BOUNDARY,
+        }
+    }
+
+    /// The same ray in a grid whose cells are `size` voxels wide and start at `min`, where distances along it shrink
+    /// to match.
+    pub fn scaled(self, min: Vec3A, size: f32) -> Self {
+        Self {
+            origin: (self.origin - min) / size,
+            near: self.near / size,
+            far: self.far / size,
+            epsilon: self.epsilon / size,
+            ..self
+        }
+    }
+
+    /// Cell of a grid with its boundaries on whole numbers that the ray is in at a position, given by its minimum
+    /// corner.
+    ///
+    /// Positions on an edge or corner between cells the ray crosses, or within [`Ray::epsilon`] of one, count as being
+    /// in the cell the ray comes from. Stepping through the grid from there crosses each boundary into a cell sharing
+    /// a face with the last, so rays can't slip diagonally past an edge or corner between two voxels that only touch
+    /// there. Crossing a single face leads to a neighbour either way, so those start in the cell ahead, and rays
+    /// running along a boundary stay on its lower side.
+    pub fn cell(&self, pos: Vec3A) -> Vec3A {
+        let positive = self.dir.cmpgt(Vec3A::ZERO);
+        let negative = self.dir.cmplt(Vec3A::ZERO);
+        let crossing =
+            (pos - pos.round()).abs().cmplt(Vec3A::splat(self.epsilon)) & (positive | negative);
+        let upper = match crossing.bitmask().count_ones() {
+            0 | 1 => positive,
+            _ => negative,
+        };
+        Vec3A::select(
+            upper,
+            (pos + self.epsilon).floor(),
+            (pos - self.epsilon).ceil() - 1.0,
+        )
+    }
+}
+
+/// Default for [`Ray::epsilon`], which covers the rounding of coordinates up to a few hundred voxels from the origin.
+pub const ON_BOUNDARY: f32 = 1e-4;
+
+/// Default for [`Ray::near`].
+pub const NEAR: f32 = 0.01;
+
+/// Ray in double precision, for scenes or cameras so far from the origin, or boxes so large, that `f32` can't place a
+/// ray or step along it finely enough.
+///
+/// Scenes find where it enters them and step through their grids in `f64`, and only round positions relative to
+/// where it entered a small enough box to trace it as a [`Ray`].
+#[derive(Clone, Copy, Debug)]
+pub struct DRay {
+    /// Starting position of the ray.
+    pub origin: DVec3,
+    /// Direction of the ray (normalized).
+    pub dir: DVec3,
+    /// Same as [`Ray::spread`].
+    pub spread: f32,
+    /// Same as [`Ray::near`].
+    pub near: f64,
+    /// Same as [`Ray::far`].
+    pub far: f64,
+    /// Same as [`Ray::epsilon`].
+    pub epsilon: f64,
+}
+
+impl DRay {
+    pub fn new(origin: DVec3, dir: DVec3) -> Self {
+        Self {
+            origin,
+            dir: dir.normalize(),
+            spread: 0.0,
+            near: NEAR as f64,
+            far: f64::INFINITY,
+            epsilon: ON_BOUNDARY as f64,
+        }
+    }
+
+    /// The same ray in `f32`, with its origin moved to `min`, so positions along it are as exact as `f32` allows
+    /// close to `min` however far that is from the origin of the scene. Distances along it start from `start`.
+    pub fn relative(self, min: DVec3, start: f64) -> Ray {
+        Ray {
+            origin: (self.origin + start * self.dir - min).as_vec3a(),
+            dir: self.dir.as_vec3a(),
+            spread: self.spread,
+            near: (self.near - start).max(0.0) as f32,
+            far: (self.far - start) as f32,
+            epsilon: self.epsilon as f32,
+        }
+    }
+
+    /// Where the ray enters a bounding box, as the distance along it to there and the same ray in `f32` starting
+    /// there, relative to the box moved to the origin by [`IAabb::at_origin`].
+    pub fn enter(self, bb: IAabb) -> Option<(f64, Ray)> {
+        let range = bb.intersection_f64(self, self.near..self.far)?;
+        Some((range.start, self.relative(bb.min().as_dvec3(), range.start)))
+    }
+
+    /// Same as [`Ray::cell`].
+    pub fn cell(&self, pos: DVec3) -> DVec3 {
+        let positive = self.dir.cmpgt(DVec3::ZERO);
+        let negative = self.dir.cmplt(DVec3::ZERO);
+        let crossing =
+            (pos - pos.round()).abs().cmplt(DVec3::splat(self.epsilon)) & (positive | negative);
+        let upper = match crossing.bitmask().count_ones() {
+            0 | 1 => positive,
+            _ => negative,
+        };
+        DVec3::select(
+            upper,
+            (pos + self.epsilon).floor(),
+            (pos - self.epsilon).ceil() - 1.0,
+        )
+    }
+
+    /// Same as [`Ray::scaled`].
+    pub fn scaled(self, min: DVec3, size: f64) -> Self {
+        Self {
+            origin: (self.origin - min) / size,
+            near: self.near / size,
+            far: self.far / size,
+            epsilon: self.epsilon / size,
+            ..self
+        }
+    }
+}
+
+impl From<Ray> for DRay {
+    fn from(ray: Ray) -> Self {
+        Self {
+            spread: ray.spread,
+            near: ray.near as f64,
+            far: ray.far as f64,
+            epsilon: ray.epsilon as f64,
+            ..Self::new(ray.origin.as_dvec3(), ray.dir.as_dvec3())
         }
     }
 }
 
+/// Cone holding a group of rays cast from the same point, such as the rays through a tile of pixels.
+#[derive(Clone, Copy, Debug)]
+pub struct Beam {
+    /// Point the rays are cast from.
+    pub origin: Vec3A,
+    /// Axis of the cone (normalized).
+    pub dir: Vec3A,
+    /// Radius of the cone per unit of distance along its axis.
+    pub spread: f32,
+}
+
+impl Beam {
+    /// Cone around rays cast from the same point, which also holds every ray between them.
+    pub fn around(rays: &[Ray]) -> Self {
+        let dir = rays.iter().map(|ray| ray.dir).sum::<Vec3A>().normalize();
+        let cos = rays
+            .iter()
+            .map(|ray| ray.dir.dot(dir))
+            .fold(1.0, f32::min)
+            .max(f32::EPSILON);
+        Self {
+            origin: rays[0].origin,
+            dir,
+            spread: (1.0 - cos * cos).max(0.0).sqrt() / cos,
+        }
+    }
+
+    /// Closest distance from the origin at which the cone might reach into a bounding box, if it does at all.
+    ///
+    /// Boxes are approximated by the sphere around them, so the distance is never too far but may be too close.
+    pub fn near(&self, bb: IAabb) -> Option<f32> {
+        let radius = bb.extents.as_vec3a().length();
+        let offset = bb.origin.as_vec3a() - self.origin;
+        let along = offset.dot(self.dir);
+        let across = (offset - along * self.dir).length();
+        (along + radius >= 0.0 && across <= radius + self.spread * (along + radius))
+            .then(|| (offset.length() - radius).max(0.0))
+    }
+}
+
+/// Number of rays traced together as a packet, one for each lane of a [`Vec4`].
+pub const PACKET: usize = 4;
+
+/// Octants of a bounding box along a ray, with the distance along the ray to where it enters each, from
+/// [`IAabb::octants`].
+#[derive(Clone, Copy, Debug)]
+pub struct Octants {
+    /// Octant the ray is in, until it leaves the box.
+    idx: Option<usize>,
+    /// Distance along the ray to where it entered that octant.
+    entered: f32,
+    /// Distance along the ray to each middle plane it has yet to cross, or infinity for the others.
+    mid: Vec3A,
+    /// Distance along the ray to where it leaves the box.
+    exit: f32,
+}
+
+impl Iterator for Octants {
+    type Item = (usize, f32);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let idx = self.idx?;
+        let octant = (idx, self.entered);
+
+        let axis = self.mid.min_position();
+        self.entered = self.mid[axis];
+        self.idx = (self.entered < self.exit).then_some(idx ^ 1 << axis);
+        self.mid[axis] = f32::INFINITY;
+        Some(octant)
+    }
+}
+
+/// Voxel found by a ray.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Hit {
+    pub voxel: Voxel,
+    /// Distance along the ray to where it enters the voxel.
+    pub distance: f32,
+}
+
+impl Hit {
+    /// The same hit for a ray that started `start` further back, as when [`DRay::enter`] moved it to where it enters
+    /// a box.
+    pub fn after(self, start: f64) -> Self {
+        Self {
+            distance: (start + self.distance as f64) as f32,
+            ..self
+        }
+    }
+}
+
+/// Colors found along a cone, blended front to back by how much of the cone each of them covers.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ConeHit {
+    /// Sum of the colors found, each weighted by how much of the cone it covers.
+    pub color: Vec3A,
+    /// How much of the cone is covered, from 0 to 1.
+    pub opacity: f32,
+    /// Distance along the cone's axis to the first color found.
+    pub distance: f32,
+}
+
+impl ConeHit {
+    /// Opacity past which a cone is treated as fully covered and stops.
+    const OPAQUE: f32 = 0.99;
+
+    /// A hit covering the whole cone, as found by a ray.
+    pub fn opaque(hit: Hit) -> Self {
+        Self {
+            color: hit.voxel.color.as_vec3a(),
+            opacity: 1.0,
+            distance: hit.distance,
+        }
+    }
+
+    /// Blends in a color behind the ones found so far, covering part of the cone.
+    pub fn add(&mut self, color: U8Vec3, coverage: f32, distance: f32) {
+        if self.opacity == 0.0 {
+            self.distance = distance;
+        }
+        let weight = (1.0 - self.opacity) * coverage;
+        self.color += weight * color.as_vec3a();
+        self.opacity += weight;
+    }
+
+    /// Whether nothing behind the colors found so far would show.
+    pub fn is_opaque(&self) -> bool {
+        self.opacity >= Self::OPAQUE
+    }
+
+    /// Average of the colors found, without the parts of the cone that are uncovered.
+    pub fn average(&self) -> U8Vec3 {
+        (self.color / self.opacity.max(f32::MIN_POSITIVE))
+            .round()
+            .min(Vec3A::splat(255.0))
+            .as_u8vec3()
+    }
+}
+
 /// Signed-integer axis-aligned bounding box.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct IAabb {
@@ -38,6 +330,17 @@ impl IAabb {
         Self { origin, extents }
     }
 
+    /// Create a bounding box holding the voxels between two opposite corners (both inclusive).
+    ///
+    /// Sides with an odd number of voxels are grown by one past the highest corner, since boxes are measured in half
+    /// extents.
+    pub fn from_corners(a: IVec3, b: IVec3) -> Self {
+        let min = a.min(b);
+        let size = a.max(b) + 1 - min;
+        let extents = (size + 1) / 2;
+        Self::new(min + extents, extents)
+    }
+
     /// Width of box (x).
     pub fn width(&self) -> usize {
         (self.extents.x * 2) as usize
@@ -86,6 +389,16 @@ impl IAabb {
         self.origin + self.extents
     }
 
+    /// The same box moved to have its minimum corner at the origin.
+    pub fn at_origin(&self) -> IAabb {
+        IAabb::new(self.extents, self.extents)
+    }
+
+    /// Checks if a position is one of the ones visited by [`IAabb::iter`].
+    pub fn contains(&self, pos: IVec3) -> bool {
+        pos.cmpge(self.min()).all() && pos.cmplt(self.max()).all()
+    }
+
     /// Returns the next power of two extent.
     ///
     /// Takes the maximum dimension and uses that to make a cube with sides that are a power of two.
@@ -269,141 +582,217 @@ impl IAabb {
             None
         };
 
-        max_z.is_some()
+        if max_z.is_some()
             && (cmp_points(max_z, max_y)
                 || cmp_points(max_z, max_x)
                 || cmp_points(max_z, min_y)
                 || cmp_points(max_z, min_x))
+        {
+            return true;
+        }
+
+        // a ray running along a face never crosses it, but wherever it crosses the faces around it is on an edge
+        let epsilon = Vec3A::splat(ray.epsilon);
+        let along = ray.dir.cmpeq(Vec3A::ZERO)
+            & ((ray.origin - min).abs().cmplt(epsilon) | (ray.origin - max).abs().cmplt(epsilon));
+        along.any()
+            && [min_x, min_y, min_z, max_x, max_y, max_z]
+                .iter()
+                .any(Option::is_some)
+    }
+
+    /// Octants of the bounding box a ray passes through, in the order it visits them.
+    ///
+    /// The order comes from the distances along the ray to the three middle planes, as in the parametric traversal
+    /// of Revelles et al.: the ray starts in the octant on the near side of every plane ahead of it, and then
+    /// crosses the planes it reaches before leaving the box from closest to furthest. Rays starting on an edge, where
+    /// two planes or a plane and the face the ray enters through meet, start behind the planes and cross them straight
+    /// away, like [`Ray::cell`], so they can't skip past the edge. Rays running along a plane stay on its lower side.
+    ///
+    /// The ray should start on the edge of or inside the bounding box.
+    pub fn octants(&self, ray: Ray) -> Octants {
+        let center = self.origin.as_vec3a();
+        let moving = ray.dir.cmpne(Vec3A::ZERO);
+        let positive = ray.dir.cmpgt(Vec3A::ZERO);
+        let mid = (center - ray.origin) / ray.dir;
+        let past = Vec3A::select(positive, ray.origin - center, center - ray.origin);
+        let near = Vec3A::select(positive, self.min().as_vec3a(), self.max().as_vec3a());
+        let far = Vec3A::select(positive, self.max().as_vec3a(), self.min().as_vec3a());
+        let exits = Vec3A::select(moving, (far - ray.origin) / ray.dir, Vec3A::INFINITY);
+
+        // starting on two of the middle planes and faces the ray crosses puts it on an edge between octants, where it
+        // starts behind the planes so it can't skip past the edge
+        let epsilon = Vec3A::splat(ray.epsilon);
+        let crossing =
+            (past.abs().cmplt(epsilon) | (ray.origin - near).abs().cmplt(epsilon)) & moving;
+        let behind = match crossing.bitmask().count_ones() {
+            0 | 1 => 0.0,
+            _ => ray.epsilon,
+        };
+        let ahead = past.cmplt(Vec3A::splat(behind)) & moving;
+        let upper =
+            ((ahead ^ positive) & moving) | (!moving & (ray.origin - ray.epsilon).cmpgt(center));
+
+        Octants {
+            idx: Some(upper.bitmask() as usize),
+            entered: 0.0,
+            mid: Vec3A::select(ahead, mid.max(Vec3A::ZERO), Vec3A::INFINITY),
+            exit: exits.min_element(),
+        }
     }
 
-    /// Checks for ray intersections across planes inside of the bounding box.
-    /// Returns the distance if found.
+    /// Same as [`IAabb::octants`] for a packet of rays at once, with each ray in a lane of a [`Vec4`].
     ///
-    /// (x, y, z) axes -> (yz, xz, xy) planes
-    pub fn plane_intersections(&self, ray: Ray) -> [Option<f32>; 3] {
+    /// The arithmetic follows the single ray version step by step, so both give the exact same distances.
+    pub fn packet_octants(&self, rays: &[Ray; PACKET]) -> [Octants; PACKET] {
+        let lanes = |f: fn(&Ray) -> f32| Vec4::from_array(rays.each_ref().map(f));
+        let origin = [
+            lanes(|ray| ray.origin.x),
+            lanes(|ray| ray.origin.y),
+            lanes(|ray| ray.origin.z),
+        ];
+        let dir = [
+            lanes(|ray| ray.dir.x),
+            lanes(|ray| ray.dir.y),
+            lanes(|ray| ray.dir.z),
+        ];
+        let epsilon = lanes(|ray| ray.epsilon);
         let min = self.min().as_vec3a();
         let max = self.max().as_vec3a();
-
-        let origin = self.origin.as_vec3a();
-
-        let dir_x = ray.dir.dot(Vec3A::X);
-        let x = if dir_x != 0.0
-            && dir_x.is_sign_positive() == (origin.x - ray.origin.x).is_sign_positive()
-        {
-            let t = (origin.dot(Vec3A::X) - ray.origin.dot(Vec3A::X)) / dir_x;
-            let i = ray.origin + t * ray.dir;
-            let p = i.yz();
-            if p.cmpge(min.yz()) == BVec2::TRUE && p.cmple(max.yz()) == BVec2::TRUE {
-                Some(i.distance(ray.origin))
-            } else {
-                None
-            }
-        } else {
-            None
+        let center = self.origin.as_vec3a();
+
+        let mut octants = [Octants {
+            idx: Some(0),
+            entered: 0.0,
+            mid: Vec3A::INFINITY,
+            exit: f32::INFINITY,
+        }; PACKET];
+        let past = |axis: usize| {
+            let center = Vec4::splat(center[axis]);
+            let positive = dir[axis].cmpgt(Vec4::ZERO);
+            Vec4::select(positive, origin[axis] - center, center - origin[axis])
         };
-
-        let dir_y = ray.dir.dot(Vec3A::Y);
-        let y = if dir_y != 0.0
-            && dir_y.is_sign_positive() == (origin.y - ray.origin.y).is_sign_positive()
-        {
-            let t = (origin.dot(Vec3A::Y) - ray.origin.dot(Vec3A::Y)) / dir_y;
-            let i = ray.origin + t * ray.dir;
-            let p = i.xz();
-            if p.cmpge(min.xz()) == BVec2::TRUE && p.cmple(max.xz()) == BVec2::TRUE {
-                Some(i.distance(ray.origin))
-            } else {
-                None
+        let mut crossings = Vec4::ZERO;
+        for axis in 0..3 {
+            let moving = dir[axis].cmpne(Vec4::ZERO);
+            let positive = dir[axis].cmpgt(Vec4::ZERO);
+            let near = Vec4::select(positive, Vec4::splat(min[axis]), Vec4::splat(max[axis]));
+            let crossing = (past(axis).abs().cmplt(epsilon)
+                | (origin[axis] - near).abs().cmplt(epsilon))
+                & moving;
+            crossings += Vec4::select(crossing, Vec4::ONE, Vec4::ZERO);
+        }
+        let behind = Vec4::select(crossings.cmpge(Vec4::splat(2.0)), epsilon, Vec4::ZERO);
+
+        for axis in 0..3 {
+            let moving = dir[axis].cmpne(Vec4::ZERO);
+            let positive = dir[axis].cmpgt(Vec4::ZERO);
+            let center = Vec4::splat(center[axis]);
+            let mid = (center - origin[axis]) / dir[axis];
+            let ahead = past(axis).cmplt(behind) & moving;
+            let far = Vec4::select(positive, Vec4::splat(max[axis]), Vec4::splat(min[axis]));
+            let exits = Vec4::select(moving, (far - origin[axis]) / dir[axis], Vec4::INFINITY);
+            let upper =
+                ((ahead ^ positive) & moving) | (!moving & (origin[axis] - epsilon).cmpgt(center));
+            let mid = Vec4::select(ahead, mid.max(Vec4::ZERO), Vec4::INFINITY);
+
+            for (lane, octants) in octants.iter_mut().enumerate() {
+                octants.idx = octants
+                    .idx
+                    .map(|idx| idx | ((upper.bitmask() as usize >> lane) & 1) << axis);
+                octants.mid[axis] = mid[lane];
+                octants.exit = octants.exit.min(exits[lane]);
             }
-        } else {
-            None
-        };
+        }
+        octants
+    }
 
-        let dir_z = ray.dir.dot(Vec3A::Z);
-        let z = if dir_z != 0.0
-            && dir_z.is_sign_positive() == (origin.z - ray.origin.z).is_sign_positive()
-        {
-            let t = (origin.dot(Vec3A::Z) - ray.origin.dot(Vec3A::Z)) / dir_z;
-            let i = ray.origin + t * ray.dir;
-            let p = i.xy();
-            if p.cmpge(min.xy()) == BVec2::TRUE && p.cmple(max.xy()) == BVec2::TRUE {
-                Some(i.distance(ray.origin))
-            } else {
-                None
-            }
-        } else {
-            None
-        };
+    /// Distance along a ray to where it enters the bounding box, or to where it would if it passes just beside it.
+    pub fn entry(&self, ray: Ray) -> f32 {
+        let near = Vec3A::select(
+            ray.dir.cmpgt(Vec3A::ZERO),
+            self.min().as_vec3a(),
+            self.max().as_vec3a(),
+        );
+        Vec3A::select(
+            ray.dir.cmpeq(Vec3A::ZERO),
+            Vec3A::NEG_INFINITY,
+            (near - ray.origin) / ray.dir,
+        )
+        .max_element()
+    }
 
-        [x, y, z]
+    /// Same as [`IAabb::entry`] for a ray in double precision.
+    pub fn entry_f64(&self, ray: DRay) -> f64 {
+        let near = DVec3::select(
+            ray.dir.cmpgt(DVec3::ZERO),
+            self.min().as_dvec3(),
+            self.max().as_dvec3(),
+        );
+        DVec3::select(
+            ray.dir.cmpeq(DVec3::ZERO),
+            DVec3::NEG_INFINITY,
+            (near - ray.origin) / ray.dir,
+        )
+        .max_element()
     }
 
     /// Checks for an intersection with the bounding box along a range of a ray.
     /// Returns the range in which the ray intersects the bounding box if so.
     ///
+    /// Rays touching the box along an edge or a face count as intersecting it, with room for the rounding in finding
+    /// the distances, so a ray passing between two boxes next to each other is never found to miss both. Rays
+    /// parallel to a pair of faces, with either sign of zero in that direction, are inside the box along that axis for
+    /// their whole length or not at all, including when they run along one of the faces.
+    ///
     /// See: https://web.archive.org/web/20170329072729/http://www.cs.utah.edu/~awilliam/box/box.pdf
+    /// See: https://jcgt.org/published/0002/02/02/paper.pdf
     pub fn intersection(&self, ray: Ray, range: Range<f32>) -> Option<Range<f32>> {
+        const ROUNDING: f32 = 1.0 + 3.0 * f32::EPSILON;
+
         let min = self.min().as_vec3a();
         let max = self.max().as_vec3a();
 
-        let x_dir_inv = 1.0 / ray.dir.x;
-
-        let (x_min, x_max) = if x_dir_inv >= 0.0 {
-            (
-                (min.x - ray.origin.x) * x_dir_inv,
-                (max.x - ray.origin.x) * x_dir_inv,
-            )
-        } else {
-            (
-                (max.x - ray.origin.x) * x_dir_inv,
-                (min.x - ray.origin.x) * x_dir_inv,
-            )
-        };
-
-        let y_dir_inv = 1.0 / ray.dir.y;
-
-        let (y_min, y_max) = if y_dir_inv >= 0.0 {
-            (
-                (min.y - ray.origin.y) * y_dir_inv,
-                (max.y - ray.origin.y) * y_dir_inv,
-            )
-        } else {
-            (
-                (max.y - ray.origin.y) * y_dir_inv,
-                (min.y - ray.origin.y) * y_dir_inv,
-            )
-        };
+        // rays along a slab (with either sign of zero) are inside it all the way or never, and dividing by their zero
+        // would give infinities or NaN on its faces
+        let parallel = ray.dir.cmpeq(Vec3A::ZERO);
+        if (parallel & (ray.origin.cmplt(min) | ray.origin.cmpgt(max))).any() {
+            return None;
+        }
+        let to_min = (min - ray.origin) / ray.dir;
+        let to_max = (max - ray.origin) / ray.dir;
+        let start = Vec3A::select(parallel, Vec3A::NEG_INFINITY, to_min.min(to_max)).max_element();
+        let end = Vec3A::select(parallel, Vec3A::INFINITY, to_min.max(to_max)).min_element();
 
-        if x_min > y_max || y_min > x_max {
+        if start > end * ROUNDING || start > range.end || end * ROUNDING < range.start {
             return None;
         }
 
-        let (t_min, t_max) = (x_min.max(y_min), x_max.min(y_max));
+        Some(start.max(range.start)..end.min(range.end))
+    }
 
-        let z_dir_inv = 1.0 / ray.dir.z;
+    /// Same as [`IAabb::intersection`] for a ray in double precision.
+    pub fn intersection_f64(&self, ray: DRay, range: Range<f64>) -> Option<Range<f64>> {
+        const ROUNDING: f64 = 1.0 + 3.0 * f64::EPSILON;
 
-        let (z_min, z_max) = if z_dir_inv >= 0.0 {
-            (
-                (min.z - ray.origin.z) * z_dir_inv,
-                (max.z - ray.origin.z) * z_dir_inv,
-            )
-        } else {
-            (
-                (max.z - ray.origin.z) * z_dir_inv,
-                (min.z - ray.origin.z) * z_dir_inv,
-            )
-        };
+        let min = self.min().as_dvec3();
+        let max = self.max().as_dvec3();
 
-        if t_min > z_max || z_min > t_max {
+        // rays along a slab are inside it all the way or never
+        let parallel = ray.dir.cmpeq(DVec3::ZERO);
+        if (parallel & (ray.origin.cmplt(min) | ray.origin.cmpgt(max))).any() {
             return None;
         }
+        let to_min = (min - ray.origin) / ray.dir;
+        let to_max = (max - ray.origin) / ray.dir;
+        let start = DVec3::select(parallel, DVec3::NEG_INFINITY, to_min.min(to_max)).max_element();
+        let end = DVec3::select(parallel, DVec3::INFINITY, to_min.max(to_max)).min_element();
 
-        let (start, end) = (t_min.max(z_min), t_max.min(z_max));
-
-        if start > range.end || end < range.start {
+        if start > end * ROUNDING || start > range.end || end * ROUNDING < range.start {
             return None;
         }
 
-        Some(start..end)
+        Some(start.max(range.start)..end.min(range.end))
     }
 }
 
@@ -435,56 +824,150 @@ mod tests {
     }
 
     #[test]
-    /// Check for plane intersections.
-    fn planes_intersect() {
-        {
-            let bb = IAabb::new(IVec3::ZERO, IVec3::ONE * 5);
-
-            assert!(matches!(
-                bb.plane_intersections(Ray::new(Vec3A::NEG_ONE * 6.0, Vec3A::ONE)),
-                [Some(_), Some(_), Some(_)]
-            ));
+    fn intersections_touch_edges() {
+        // boxes around an edge, two on either side of rays crossing it diagonally
+        let boxes = [IVec3::new(-1, -1, 0), IVec3::new(1, 1, 0)]
+            .map(|origin| IAabb::new(origin, IVec3::ONE));
+        for back in [0.3, 7.1, 60.7, 333.3] {
+            for dir in [Vec3A::new(1.0, -1.0, 0.0), Vec3A::new(-3.0, 1.0, 0.2)] {
+                let dir = dir.normalize();
+                let ray = Ray::new(Vec3A::new(0.0, 0.0, 0.3) - back * dir, dir);
+                assert!(
+                    boxes
+                        .iter()
+                        .any(|bb| bb.intersection(ray, 0.0..f32::INFINITY).is_some()),
+                    "{back} {dir}"
+                );
+            }
         }
+    }
 
-        {
-            let bb = IAabb::new(IVec3::ZERO, IVec3::ONE * 5);
-
-            assert!(matches!(
-                bb.plane_intersections(Ray::new(Vec3A::NEG_ONE * 2.0, Vec3A::Y)),
-                [None, Some(_), None]
-            ));
-        }
+    #[test]
+    fn cells_on_boundaries() {
+        let ray = Ray::new(Vec3A::ZERO, Vec3A::new(1.0, -1.0, 0.0));
+        assert_eq!(ray.cell(Vec3A::splat(2.5)), Vec3A::splat(2.0));
+        assert_eq!(
+            ray.cell(Vec3A::new(3.0, 2.5, 3.0)),
+            Vec3A::new(3.0, 2.0, 2.0)
+        );
+        // on an edge, or within rounding of one, is in the cell the ray comes from, and along a boundary is below it
+        assert_eq!(ray.cell(Vec3A::splat(3.0)), Vec3A::new(2.0, 3.0, 2.0));
+        assert_eq!(
+            ray.cell(Vec3A::new(3.00001, 2.99999, 2.99999)),
+            Vec3A::new(2.0, 3.0, 2.0)
+        );
+    }
 
-        {
-            let bb = IAabb::new(IVec3::ZERO, IVec3::ONE * 5);
+    #[test]
+    fn intersections_start_in_range() {
+        let bb = IAabb::new(IVec3::ZERO, IVec3::ONE * 5);
+        let range = bb
+            .intersection(Ray::new(Vec3A::ZERO, Vec3A::X), 0.01..f32::INFINITY)
+            .unwrap();
+        assert_eq!(range, 0.01..5.0);
+    }
 
-            assert!(matches!(
-                bb.plane_intersections(Ray::new(Vec3A::ONE * 2.0, Vec3A::NEG_Y)),
-                [None, Some(_), None]
-            ));
-        }
+    #[test]
+    fn double_rays_enter_boxes() {
+        // a box too far out for f32 to tell apart positions half a voxel apart
+        let bb = IAabb::new(IVec3::splat(100_000_000), IVec3::ONE * 5);
+        let origin = DVec3::new(99_999_990.5, 100_000_000.5, 100_000_001.25);
+        let ray = DRay::new(origin, DVec3::X);
+        let range = bb.intersection_f64(ray, 0.0..f64::INFINITY).unwrap();
+        assert_eq!(range, 4.5..14.5);
+
+        // and is entered at the same point relative to its corner, with the distance to it kept
+        let (start, local) = ray.enter(bb).unwrap();
+        assert_eq!(start, 4.5);
+        assert_eq!(local.origin, Vec3A::new(0.0, 5.5, 6.25));
+        assert_eq!(local.far, f32::INFINITY);
+
+        let parallel = DRay::new(origin + DVec3::Y * 10.0, DVec3::X);
+        assert_eq!(bb.intersection_f64(parallel, 0.0..f64::INFINITY), None);
     }
 
     #[test]
-    /// Check for plane intersections.
-    fn planes_not_intersect() {
-        {
-            let bb = IAabb::new(IVec3::ZERO, IVec3::ONE * 5);
+    fn cones_blend_front_to_back() {
+        let mut cone = ConeHit::default();
+        cone.add(U8Vec3::new(200, 0, 0), 0.5, 10.0);
+        cone.add(U8Vec3::new(0, 0, 100), 0.5, 12.0);
+        assert_eq!(cone.distance, 10.0);
+        assert_eq!(cone.opacity, 0.75);
+        assert_eq!(cone.average(), U8Vec3::new(133, 0, 33));
+        assert!(!cone.is_opaque());
+
+        cone.add(U8Vec3::ZERO, 1.0, 20.0);
+        assert!(cone.is_opaque());
+    }
 
-            assert!(matches!(
-                bb.plane_intersections(Ray::new(Vec3A::NEG_ONE * 6.0, Vec3A::Y)),
-                [None, None, None]
-            ));
-        }
+    #[test]
+    /// Check the octants rays pass through.
+    fn octants_along_rays() {
+        let bb = IAabb::new(IVec3::ZERO, IVec3::ONE * 5);
+        let path = |origin, dir| {
+            bb.octants(Ray::new(origin, dir))
+                .map(|(idx, entered)| (idx, (entered * 10.0).round() / 10.0))
+                .collect::<Vec<_>>()
+        };
 
-        {
-            let bb = IAabb::new(IVec3::ZERO, IVec3::ONE * 5);
+        // through the middle, crossing all three planes at once (in order of their axes)
+        assert_eq!(
+            path(Vec3A::NEG_ONE * 5.0, Vec3A::ONE),
+            [(0b000, 0.0), (0b001, 8.7), (0b011, 8.7), (0b111, 8.7)]
+        );
+        assert_eq!(
+            path(Vec3A::NEG_ONE * 2.0, Vec3A::Y),
+            [(0b000, 0.0), (0b010, 2.0)]
+        );
+        assert_eq!(
+            path(Vec3A::ONE * 2.0, Vec3A::NEG_Y),
+            [(0b111, 0.0), (0b101, 2.0)]
+        );
+        // crossing y at 5.625 and then x and z at 9, before leaving through the top at 11.25
+        assert_eq!(
+            path(Vec3A::new(4.0, -5.0, -1.0), Vec3A::new(-4.0, 8.0, 1.0)),
+            [(0b001, 0.0), (0b011, 5.6), (0b010, 9.0), (0b110, 9.0)]
+        );
+    }
 
-            assert!(matches!(
-                bb.plane_intersections(Ray::new(Vec3A::NEG_Y * 6.0, Vec3A::NEG_Y)),
-                [None, None, None]
-            ));
-        }
+    #[test]
+    /// Check rays that cross no planes, or start on or run along them.
+    fn octants_at_planes() {
+        let bb = IAabb::new(IVec3::ZERO, IVec3::ONE * 5);
+        let path = |origin, dir| bb.octants(Ray::new(origin, dir)).collect::<Vec<_>>();
+
+        assert_eq!(path(Vec3A::ONE * 2.0, Vec3A::Y), [(0b111, 0.0)]);
+        assert_eq!(
+            path(Vec3A::new(-2.0, 3.0, -1.0), Vec3A::NEG_Z),
+            [(0b010, 0.0)]
+        );
+        // starting on a plane goes straight into the octant ahead
+        assert_eq!(path(Vec3A::new(1.0, 0.0, 1.0), Vec3A::Y), [(0b111, 0.0)]);
+        assert_eq!(
+            path(Vec3A::new(1.0, 0.0, 1.0), Vec3A::NEG_Y),
+            [(0b101, 0.0)]
+        );
+        // starting on an edge, where two planes or a plane and the face the ray enters through meet, or within rounding
+        // of one, starts behind the planes and crosses them straight away
+        let octants = |origin, dir| {
+            path(origin, dir)
+                .into_iter()
+                .map(|(idx, _)| idx)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            octants(Vec3A::new(-5.0, 0.00001, 1.0), Vec3A::new(2.0, 1.0, 0.0)),
+            [0b100, 0b110, 0b111]
+        );
+        assert_eq!(
+            path(Vec3A::new(0.0, -0.00001, 1.0), Vec3A::new(1.0, -1.0, 0.0)),
+            [(0b110, 0.0), (0b111, 0.0), (0b101, 0.0)]
+        );
+        // running along a plane keeps to its lower side
+        assert_eq!(
+            path(Vec3A::new(0.0, -5.0, 2.0), Vec3A::Y),
+            [(0b100, 0.0), (0b110, 5.0)]
+        );
     }
 
     #[test]
@@ -506,4 +989,124 @@ mod tests {
         assert_eq!(bb.octant(0b000), IAabb::new(IVec3::NEG_ONE, IVec3::ONE));
         assert_eq!(bb.octant(0b111), IAabb::new(IVec3::ONE, IVec3::ONE));
     }
+
+    #[test]
+    fn from_corners() {
+        let bb = IAabb::from_corners(IVec3::new(-10, 5, 3), IVec3::new(9, -4, -4));
+        assert_eq!(bb.min(), IVec3::new(-10, -4, -4));
+        assert_eq!(bb.max(), IVec3::new(10, 6, 4));
+
+        // odd sides grow past the highest corner
+        let bb = IAabb::from_corners(IVec3::new(-3, 0, 7), IVec3::new(-3, 2, 10));
+        assert_eq!(bb.min(), IVec3::new(-3, 0, 7));
+        assert_eq!(bb.max(), IVec3::new(-1, 4, 11));
+    }
+
+    #[test]
+    fn packet_octants_match() {
+        let bb = IAabb::new(IVec3::new(3, -2, 1), IVec3::splat(8));
+        let dirs = [
+            Vec3A::X,
+            Vec3A::NEG_Y,
+            Vec3A::new(0.3, -0.7, 0.2),
+            Vec3A::new(-1.0, 0.5, -0.25),
+        ];
+        for i in 0..40 {
+            let origin = Vec3A::new(
+                (i * 7 % 19) as f32 - 6.5,
+                (i * 3 % 13) as f32 - 8.0,
+                (i * 11 % 17) as f32 - 7.25,
+            );
+            let rays = dirs.map(|dir| Ray::new(origin + 0.1 * i as f32 * dir, dir));
+            let octants = bb.packet_octants(&rays);
+            for (ray, octants) in rays.iter().zip(octants) {
+                assert_eq!(
+                    octants.collect::<Vec<_>>(),
+                    bb.octants(*ray).collect::<Vec<_>>(),
+                    "{ray:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn beams() {
+        let origin = Vec3A::new(0.0, 0.0, -20.0);
+        let rays = [
+            Vec3A::new(-1.0, -1.0, 10.0),
+            Vec3A::new(1.0, -1.0, 10.0),
+            Vec3A::new(-1.0, 1.0, 10.0),
+            Vec3A::new(1.0, 1.0, 10.0),
+        ]
+        .map(|dir| Ray::new(origin, dir));
+        let beam = Beam::around(&rays);
+        assert!((beam.dir - Vec3A::Z).length() < 1e-6);
+        assert!((beam.spread - 2f32.sqrt() / 10.0).abs() < 1e-5);
+
+        // the sphere around a box ahead starts before the box does
+        let ahead = IAabb::new(IVec3::ZERO, IVec3::splat(2));
+        let near = beam.near(ahead).unwrap();
+        assert!(near <= 18.0 && near > 16.0, "{near}");
+        // boxes off to the side or behind are never reached
+        assert_eq!(
+            beam.near(IAabb::new(IVec3::new(20, 0, 0), IVec3::splat(2))),
+            None
+        );
+        assert_eq!(
+            beam.near(IAabb::new(IVec3::new(0, 0, -40), IVec3::splat(2))),
+            None
+        );
+        // and the cone widens to reach boxes further away
+        assert!(beam
+            .near(IAabb::new(IVec3::new(0, 12, 80), IVec3::splat(2)))
+            .is_some());
+    }
+
+    #[test]
+    fn axis_parallel_rays_intersect() {
+        let bb = IAabb::new(IVec3::ZERO, IVec3::splat(4));
+        for zero in [0.0, -0.0] {
+            // inside, along a face and along an edge, with a direction of either sign of zero across the slabs
+            for origin in [
+                Vec3A::new(-10.0, 1.5, -2.5),
+                Vec3A::new(-10.0, 4.0, 1.0),
+                Vec3A::new(-10.0, -4.0, -4.0),
+            ] {
+                let ray = Ray::new(origin, Vec3A::new(1.0, zero, zero));
+                let range = bb.intersection(ray, 0.0..f32::INFINITY);
+                assert_eq!(range, Some(6.0..14.0), "{origin} {zero}");
+            }
+            // outside the slab it runs along
+            let ray = Ray::new(Vec3A::new(-10.0, 4.5, 0.0), Vec3A::new(1.0, zero, zero));
+            assert!(bb.intersection(ray, 0.0..f32::INFINITY).is_none());
+        }
+        // negative zero against a ray going backwards
+        let ray = Ray::new(Vec3A::new(10.0, -0.0, 4.0), Vec3A::new(-1.0, -0.0, 0.0));
+        assert_eq!(bb.intersection(ray, 0.0..f32::INFINITY), Some(6.0..14.0));
+    }
+
+    #[test]
+    fn nearly_parallel_rays_intersect() {
+        let bb = IAabb::new(IVec3::ZERO, IVec3::splat(4));
+        // directions small enough that the distances to the slab overflow to infinity
+        for tiny in [1e-30, -1e-30] {
+            let ray = Ray::new(Vec3A::new(-10.0, 1.0, 1.0), Vec3A::new(1.0, tiny, tiny));
+            assert_eq!(bb.intersection(ray, 0.0..f32::INFINITY), Some(6.0..14.0));
+            let ray = Ray::new(Vec3A::new(-10.0, 5.0, 1.0), Vec3A::new(1.0, tiny, 0.0));
+            assert!(bb.intersection(ray, 0.0..f32::INFINITY).is_none());
+        }
+    }
+
+    #[test]
+    fn axis_parallel_rays_touch_edges() {
+        let bb = IAabb::new(IVec3::ZERO, IVec3::splat(4));
+        for zero in [0.0, -0.0] {
+            let ray = Ray::new(Vec3A::new(-10.0, 4.0, 4.0), Vec3A::new(1.0, zero, zero));
+            assert!(bb.intersects_edge(ray));
+            let ray = Ray::new(Vec3A::new(-10.0, 4.0, 0.0), Vec3A::new(1.0, zero, 0.3));
+            assert!(bb.intersects_edge(ray));
+            let ray = Ray::new(Vec3A::new(-10.0, 1.0, 1.0), Vec3A::new(1.0, zero, zero));
+            assert!(!bb.intersects_edge(ray));
+        }
+    }
 }
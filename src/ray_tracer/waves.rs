@@ -0,0 +1,107 @@
+use glam::Vec3A;
+use noise::{NoiseFn, Perlin};
+
+/// Width of a wave in voxels.
+const SCALE: f64 = 4.0;
+
+/// Height of the waves in voxels, from trough to crest of the larger octave.
+const AMPLITUDE: f32 = 0.6;
+
+/// How fast the waves change, in units of noise per second.
+const SPEED: f64 = 0.5;
+
+/// Distance between the heights the slope of the waves is taken from, in voxels.
+const DELTA: f32 = 0.05;
+
+/// Exponent of the glint of the sun, higher for smaller and sharper glints.
+const SHININESS: f32 = 64.0;
+
+/// Brightness of the glint of the sun in the middle, as a fraction of white.
+const GLINT: f32 = 0.8;
+
+/// Offset of the waves' noise seed from the terrain's.
+const WAVE_SEED: u32 = 11;
+
+/// Waves of 3D noise drawn on the surface of water, moving with time instead of being stored as voxels.
+///
+/// The surface stays flat, and only its normal is bent by the slope of the waves, so they show in the light and in
+/// glints of the sun or moon, see [`glint`].
+#[derive(Clone)]
+pub(super) struct Waves {
+    noise: Perlin,
+}
+
+impl Waves {
+    pub(super) fn new(seed: u32) -> Self {
+        Self {
+            noise: Perlin::new(seed.wrapping_add(WAVE_SEED)),
+        }
+    }
+
+    /// Height of the waves at a point of the surface at `time` seconds, in two octaves of noise drifting across
+    /// each other.
+    fn height(&self, x: f32, z: f32, time: f32) -> f32 {
+        let (x, z, t) = (x as f64 / SCALE, z as f64 / SCALE, time as f64 * SPEED);
+        let height = self.noise.get([x + 0.3 * t, z, t])
+            + 0.5 * self.noise.get([2.0 * z + 5.0, 2.0 * x - 0.4 * t, 1.5 * t]);
+        AMPLITUDE * height as f32 / 2.0
+    }
+
+    /// Normal of the surface of water at a point at `time` seconds, leaning away from the slope of the waves.
+    pub(super) fn normal(&self, point: Vec3A, time: f32) -> Vec3A {
+        let slope = |dx: f32, dz: f32| {
+            (self.height(point.x + dx, point.z + dz, time)
+                - self.height(point.x - dx, point.z - dz, time))
+                / (2.0 * DELTA)
+        };
+        Vec3A::new(-slope(DELTA, 0.0), 1.0, -slope(0.0, DELTA)).normalize()
+    }
+}
+
+/// Brightness of a light in direction `light` reflected off a surface with a normal toward a ray going in direction
+/// `dir`, as a fraction of white, brightest where the reflected ray points straight at the light.
+pub(super) fn glint(normal: Vec3A, dir: Vec3A, light: Vec3A) -> f32 {
+    let reflected = dir - 2.0 * dir.dot(normal) * normal;
+    GLINT * reflected.dot(light).max(0.0).powf(SHININESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ray_tracer::SUN;
+
+    #[test]
+    fn waves_move_with_time() {
+        let waves = Waves::new(0);
+        let points = (0..50).map(|i| Vec3A::new(i as f32 * 0.7, 3.0, i as f32 * 0.3));
+        let mut moved = 0;
+        for point in points {
+            let normal = waves.normal(point, 0.0);
+            assert!(normal.is_normalized() && normal.y > 0.7, "{normal}");
+            // continuous across the surface and over time
+            let near = waves.normal(point + 0.01 * Vec3A::X, 0.0);
+            assert!(normal.abs_diff_eq(near, 0.05), "{normal} {near}");
+            assert!(normal.abs_diff_eq(waves.normal(point, 0.01), 0.05));
+            moved += (!normal.abs_diff_eq(waves.normal(point, 2.0), 0.01)) as usize;
+        }
+        assert!(moved > 40, "{moved}");
+        // the same at any height of the surface
+        assert_eq!(
+            waves.normal(Vec3A::new(1.3, 0.0, 2.1), 1.5),
+            waves.normal(Vec3A::new(1.3, 9.0, 2.1), 1.5)
+        );
+    }
+
+    #[test]
+    fn glints_reflect_the_sun() {
+        // seen along the reflection of the sun, the glint is brightest
+        let toward = Vec3A::new(-SUN.x, SUN.y, -SUN.z);
+        assert!((glint(Vec3A::Y, -toward, SUN) - GLINT).abs() < 1e-4);
+        // and fades quickly away from it
+        assert!(glint(Vec3A::Y, Vec3A::new(-0.5, -0.7, 0.2).normalize(), SUN) < 0.01);
+        assert_eq!(
+            glint(Vec3A::Y, -Vec3A::Y, SUN),
+            GLINT * SUN.y.powf(SHININESS)
+        );
+    }
+}
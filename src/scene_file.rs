@@ -0,0 +1,1931 @@
+use std::{
+    error::Error,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use glam::{DVec3, IVec3, U8Vec3, Vec3A};
+use serde::Deserialize;
+
+use crate::{
+    compose::{LayerBlend, LayerFile, ObjectFile, PrefabFile},
+    import::anvil::Window,
+    post::{
+        bloom::Bloom,
+        flare::Flare,
+        lut::{Lut, LutError},
+        tone::{Dither, Exposure, Tonemap, Vignette},
+        Pipeline,
+    },
+    ray_tracer::{
+        clouds::CloudSettings,
+        dynamic::Backend,
+        fog::FogSettings,
+        light::{Light, LightKind},
+        path::PathSettings,
+        photon::CausticSettings,
+        sky::TimeOfDay,
+        types::{IAabb, NEAR, ON_BOUNDARY},
+        Config,
+    },
+    voxel::{
+        erosion::ErosionSettings, snow::SnowSettings, structure::Ground, water::WaterSettings,
+        FbmSettings, TerrainSettings, WarpSettings,
+    },
+};
+
+/// Render settings loaded from a TOML scene file.
+///
+/// Every field is optional, and command-line arguments take priority over the file.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SceneFile {
+    /// Built-in scene (e.g. `mountains`) filling in any settings this file leaves out.
+    pub preset: Option<String>,
+    /// Storage backend name, such as `sparse` or `dense`.
+    pub backend: Option<String>,
+    /// Voxel generator name (e.g. `terrain` or `sdf-shapes`).
+    pub generator: Option<String>,
+    /// Model file to render instead of the generator.
+    pub import: Option<PathBuf>,
+    /// Block color table for imported Minecraft files.
+    pub palette: Option<PathBuf>,
+    /// Corners of the world to import from Minecraft regions (x1, y1, z1, x2, y2, z2).
+    pub window: Option<[i32; 6]>,
+    /// Voxels along the longest side of imported meshes.
+    pub resolution: Option<u32>,
+    /// Fill the inside of imported meshes.
+    pub solid: Option<bool>,
+    /// Rhai script defining the voxels instead of the generator.
+    pub script: Option<PathBuf>,
+    /// WebAssembly module defining the voxels instead of the generator.
+    pub plugin: Option<PathBuf>,
+    /// Scene archive to render instead of a generator.
+    pub load_scene: Option<PathBuf>,
+    /// Where to save the scene's voxels as an archive.
+    pub save_scene: Option<PathBuf>,
+    pub size: Option<u32>,
+    /// Scene size along y, for flat scenes.
+    pub scene_height: Option<u32>,
+    /// Corners of the scene (x1, y1, z1, x2, y2, z2) instead of a box around the origin.
+    pub bounds: Option<[i32; 6]>,
+    /// Camera position.
+    pub position: Option<[i32; 3]>,
+    /// Camera angle around the scene in degrees, used when there is no position.
+    pub orbit: Option<f64>,
+    pub seed: Option<u32>,
+    /// Image output path.
+    pub out: Option<String>,
+    /// Color lookup table (`.cube`) grading the image before it is saved.
+    pub lut: Option<PathBuf>,
+    /// Stops to brighten the image by before any other post-processing.
+    pub exposure: Option<f32>,
+    /// Color that should come out white.
+    pub white_balance: Option<[u8; 3]>,
+    /// Strength of the glow added around the brightest parts of the image.
+    pub bloom: Option<f32>,
+    /// Brightness from 0 to 1 that pixels have to pass to glow.
+    pub bloom_threshold: Option<f32>,
+    /// Strength of the lens flare from the brightest parts of the image.
+    pub flare: Option<f32>,
+    /// Ghosts of each bright spot in the lens flare.
+    pub flare_ghosts: Option<usize>,
+    pub width: Option<usize>,
+    pub height: Option<usize>,
+    pub debug: Option<bool>,
+    /// Use the averages of octree nodes smaller than a pixel.
+    pub lod: Option<bool>,
+    /// Trace quads of pixels together as packets of rays.
+    pub packets: Option<bool>,
+    /// Start the rays of each tile of pixels where a beam around them first reaches a voxel.
+    pub beams: Option<bool>,
+    /// Trace cones as wide as each pixel, shaded with soft shadows.
+    pub cones: Option<bool>,
+    /// Distance past which rays hit nothing, and that infinite terrain fades into fog toward.
+    pub far: Option<f32>,
+    /// Distance from the camera before which rays hit nothing.
+    pub near: Option<f32>,
+    /// Distance within which rays count as being on a boundary between voxels.
+    pub epsilon: Option<f32>,
+    /// Trace rays in double precision, for scenes far from the origin.
+    pub double: Option<bool>,
+    /// Trace more rays through pixels on the edges of voxels and blend them.
+    pub antialias: Option<bool>,
+    /// Dither shaded colors so smooth gradients don't band.
+    pub dither: Option<bool>,
+    /// Light voxels from the sun by the direction their surface faces.
+    pub shade: Option<bool>,
+    /// Shade voxels by the faces of their cubes instead of smooth normals.
+    pub hard_normals: Option<bool>,
+    /// Draw a smooth surface through the voxels instead of the faces of the cubes.
+    pub smooth_surface: Option<bool>,
+    /// Shade in flat bands of light and outline the edges of surfaces.
+    pub toon: Option<bool>,
+    /// Seconds into an animation, which moves the waves on water.
+    pub time: Option<f32>,
+    /// `day` or `night`, which lights the scene by the moon under a sky of stars.
+    pub time_of_day: Option<String>,
+    /// Paths traced through each pixel, rendering by path tracing instead of shading.
+    pub path: Option<u32>,
+    /// Surfaces a path can hit before it is ended.
+    pub path_bounces: Option<u32>,
+    /// Photons shot at each voxel of the scene's area to draw caustics on the beds of water when shading.
+    pub caustics: Option<u32>,
+    /// Distance in voxels within which photons are gathered for caustics.
+    pub caustic_radius: Option<f32>,
+    /// Let light through thin layers of snow and water when shading.
+    pub subsurface: Option<bool>,
+    /// See through water, leaves and glass by how much of the light each voxel of them stops.
+    pub transparency: Option<bool>,
+    /// MiB of chunks kept in memory by the streaming and infinite backends.
+    pub cache_budget: Option<usize>,
+    /// Settings for the terrain generator.
+    pub terrain: TerrainSection,
+    /// Settings for the layer of clouds, drawn if it has a coverage.
+    pub clouds: CloudSection,
+    /// Settings for the fog in valleys and caves, drawn if it has a density.
+    pub fog: FogSection,
+    /// Models placed in the scene next to whatever it is built from.
+    pub objects: Vec<ObjectSection>,
+    /// Sources stacked on top of whatever the scene is built from, in order.
+    pub layers: Vec<LayerSection>,
+    /// Effects applied to the image before it is saved, in order.
+    pub post: Vec<PostSection>,
+    /// Lights the scene is lit by instead of the sun or moon.
+    pub lights: Vec<LightSection>,
+}
+
+/// The `[clouds]` table of a scene file.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CloudSection {
+    /// Fraction of the sky covered by clouds.
+    pub coverage: Option<f32>,
+    /// Height of the bottom of the clouds, defaults to the top of the scene.
+    pub altitude: Option<f32>,
+    /// Height of the layer of clouds.
+    pub thickness: Option<f32>,
+    /// Share of the light a voxel of the thickest cloud stops.
+    pub density: Option<f32>,
+    /// Rough width of a cloud.
+    pub scale: Option<f32>,
+}
+
+/// The `[fog]` table of a scene file.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FogSection {
+    /// Share of the light a voxel of the thickest mist stops.
+    pub density: Option<f32>,
+    /// Height up to which mist fills the valleys.
+    pub altitude: Option<f32>,
+    /// Share of the density of mist that haze in caves has.
+    pub haze: Option<f32>,
+    /// Rough width of a bank of fog.
+    pub scale: Option<f32>,
+}
+
+/// An `[[objects]]` entry, a model and where it is placed.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ObjectSection {
+    /// Model file, loaded like `--import`.
+    pub path: PathBuf,
+    /// Position of the middle of the model's base, defaults to the origin.
+    pub position: Option<[i32; 3]>,
+    /// Degrees the model is turned around the y-axis, counterclockwise seen from above.
+    pub rotation: Option<f32>,
+}
+
+/// A `[[layers]]` entry, a source stacked on the ones before it.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LayerSection {
+    /// Model file loaded like `--import`, or a script (`.rhai`) or plugin (`.wasm` or `.wat`).
+    pub path: PathBuf,
+    /// Offset of the layer, where the middle of a model's base goes, defaults to the origin.
+    pub position: Option<[i32; 3]>,
+    /// How the layer changes the voxels below it (`cover`, `replace` or `carve`), defaults to cover.
+    pub blend: Option<String>,
+}
+
+/// A `[[lights]]` entry, one of the lights the scene is lit by.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LightSection {
+    /// Name of the light, shown when rendering and in errors about it.
+    pub name: String,
+    /// `directional`, `point` or `spot`.
+    pub kind: String,
+    /// Direction toward a directional light, or that a spot light shines in.
+    pub direction: Option<[f32; 3]>,
+    /// Position of a point or spot light.
+    pub position: Option<[f32; 3]>,
+    /// Color of the light, defaults to white.
+    pub color: Option<[u8; 3]>,
+    /// Brightness, a voxel away for point and spot lights, defaults to 1.
+    pub intensity: Option<f32>,
+    /// Degrees from the middle of a spot light's cone to its edge.
+    pub angle: Option<f32>,
+    /// Cast soft shadows in cone renders, defaults to true.
+    pub shadows: Option<bool>,
+}
+
+/// A `[[post]]` entry, an effect applied to the image after the ones before it.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PostSection {
+    /// `exposure`, `tonemap`, `bloom`, `flare`, `vignette`, `dither` or `lut`.
+    pub effect: String,
+    /// Stops of exposure.
+    pub ev: Option<f32>,
+    /// Color that exposure balances to white.
+    pub white: Option<[u8; 3]>,
+    /// Strength of bloom, flare or vignetting.
+    pub intensity: Option<f32>,
+    /// Brightness from 0 to 1 that pixels have to pass to glow with bloom or flare.
+    pub threshold: Option<f32>,
+    /// Ghosts of each bright spot in lens flare.
+    pub ghosts: Option<usize>,
+    /// Brightness from 0 to 1 above which tonemapping compresses colors.
+    pub knee: Option<f32>,
+    /// Lookup table file (`.cube`) for color grading.
+    pub path: Option<PathBuf>,
+}
+
+/// The `[terrain]` table of a scene file.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TerrainSection {
+    /// Carve caves and overhangs with 3D noise.
+    pub caves: Option<bool>,
+    /// Height up to which caves are flooded with lava.
+    pub lava_level: Option<i32>,
+    /// Blend biomes with their own terrain shape and colors.
+    pub biomes: Option<bool>,
+    /// Grow trees and bushes on grassy ground.
+    pub vegetation: Option<bool>,
+    /// Scatter ore and crystal clusters underground.
+    pub ores: Option<bool>,
+    /// Flood the terrain below sea level and carve rivers.
+    pub water: Option<bool>,
+    /// Sea level height, enables water when set.
+    pub sea_level: Option<i32>,
+    /// Domain warp strength in voxels, enables warping when set.
+    pub warp: Option<f64>,
+    /// Max height of the terrain.
+    pub height: Option<i32>,
+    /// Roughness of the height noise.
+    pub roughness: Option<f64>,
+    /// Fraction of the height below which the ground is water colored.
+    pub water_level: Option<f64>,
+    /// Fraction of the height where grass turns to mountain.
+    pub mountain_level: Option<f64>,
+    /// Fraction of the height where mountain turns to snow.
+    pub snow_level: Option<f64>,
+    /// Pile snow on open ground instead of coloring the ground by height.
+    pub snow: Option<bool>,
+    /// Height of the ground snow settles on, defaults to the snow level.
+    pub snow_altitude: Option<i32>,
+    /// Voxels of snow piled on the ground.
+    pub snow_depth: Option<i32>,
+    /// Layers of height noise.
+    pub octaves: Option<u32>,
+    /// Frequency multiplier between noise octaves.
+    pub lacunarity: Option<f64>,
+    /// Amplitude multiplier between noise octaves.
+    pub persistence: Option<f64>,
+    /// Steps of simulated rain eroding the terrain.
+    pub erosion: Option<u32>,
+    /// Rain falling on every column per erosion step.
+    pub rain: Option<f64>,
+    /// Place structures on the terrain.
+    pub structures: Option<bool>,
+    /// Structure models to place instead of the built-in ones, enables structures when set.
+    pub prefabs: Vec<PrefabSection>,
+}
+
+/// A `[[terrain.prefabs]]` entry, a structure model and the rules for placing it.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PrefabSection {
+    /// Model file, e.g. a MagicaVoxel `.vox` file.
+    pub path: PathBuf,
+    /// Terrain band it is built on (`grass`, `mountain` or `snow`), defaults to grass.
+    pub ground: Option<String>,
+    /// Largest difference in ground height under it, defaults to 1.
+    pub max_slope: Option<i32>,
+    /// Voxels it is sunk into the ground, defaults to 0.
+    pub depth: Option<i32>,
+}
+
+impl SceneFile {
+    /// Reads and parses a scene file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SceneFileError> {
+        let contents = fs::read_to_string(path).map_err(SceneFileError::Io)?;
+        Self::parse(&contents)
+    }
+
+    /// Parses a scene file from a string.
+    pub fn parse(contents: &str) -> Result<Self, SceneFileError> {
+        toml::from_str(contents).map_err(SceneFileError::Parse)
+    }
+
+    /// Fills in the settings this file leaves out from another file, such as a preset.
+    pub fn or(self, defaults: SceneFile) -> Self {
+        // a position or orbit replaces both in the defaults
+        let (position, orbit) = match (self.position, self.orbit) {
+            (None, None) => (defaults.position, defaults.orbit),
+            camera => camera,
+        };
+        Self {
+            preset: self.preset.or(defaults.preset),
+            backend: self.backend.or(defaults.backend),
+            generator: self.generator.or(defaults.generator),
+            import: self.import.or(defaults.import),
+            palette: self.palette.or(defaults.palette),
+            window: self.window.or(defaults.window),
+            resolution: self.resolution.or(defaults.resolution),
+            solid: self.solid.or(defaults.solid),
+            script: self.script.or(defaults.script),
+            plugin: self.plugin.or(defaults.plugin),
+            load_scene: self.load_scene.or(defaults.load_scene),
+            save_scene: self.save_scene.or(defaults.save_scene),
+            size: self.size.or(defaults.size),
+            scene_height: self.scene_height.or(defaults.scene_height),
+            bounds: self.bounds.or(defaults.bounds),
+            position,
+            orbit,
+            seed: self.seed.or(defaults.seed),
+            out: self.out.or(defaults.out),
+            lut: self.lut.or(defaults.lut),
+            exposure: self.exposure.or(defaults.exposure),
+            white_balance: self.white_balance.or(defaults.white_balance),
+            bloom: self.bloom.or(defaults.bloom),
+            bloom_threshold: self.bloom_threshold.or(defaults.bloom_threshold),
+            flare: self.flare.or(defaults.flare),
+            flare_ghosts: self.flare_ghosts.or(defaults.flare_ghosts),
+            width: self.width.or(defaults.width),
+            height: self.height.or(defaults.height),
+            debug: self.debug.or(defaults.debug),
+            lod: self.lod.or(defaults.lod),
+            packets: self.packets.or(defaults.packets),
+            beams: self.beams.or(defaults.beams),
+            cones: self.cones.or(defaults.cones),
+            far: self.far.or(defaults.far),
+            near: self.near.or(defaults.near),
+            epsilon: self.epsilon.or(defaults.epsilon),
+            double: self.double.or(defaults.double),
+            antialias: self.antialias.or(defaults.antialias),
+            dither: self.dither.or(defaults.dither),
+            shade: self.shade.or(defaults.shade),
+            hard_normals: self.hard_normals.or(defaults.hard_normals),
+            smooth_surface: self.smooth_surface.or(defaults.smooth_surface),
+            toon: self.toon.or(defaults.toon),
+            time: self.time.or(defaults.time),
+            time_of_day: self.time_of_day.or(defaults.time_of_day),
+            path: self.path.or(defaults.path),
+            path_bounces: self.path_bounces.or(defaults.path_bounces),
+            caustics: self.caustics.or(defaults.caustics),
+            caustic_radius: self.caustic_radius.or(defaults.caustic_radius),
+            subsurface: self.subsurface.or(defaults.subsurface),
+            transparency: self.transparency.or(defaults.transparency),
+            cache_budget: self.cache_budget.or(defaults.cache_budget),
+            terrain: self.terrain.or(defaults.terrain),
+            clouds: self.clouds.or(defaults.clouds),
+            fog: self.fog.or(defaults.fog),
+            objects: if self.objects.is_empty() {
+                defaults.objects
+            } else {
+                self.objects
+            },
+            layers: if self.layers.is_empty() {
+                defaults.layers
+            } else {
+                self.layers
+            },
+            post: if self.post.is_empty() {
+                defaults.post
+            } else {
+                self.post
+            },
+            lights: if self.lights.is_empty() {
+                defaults.lights
+            } else {
+                self.lights
+            },
+        }
+    }
+}
+
+impl CloudSection {
+    /// Fills in the settings this table leaves out from another one.
+    pub fn or(self, defaults: CloudSection) -> Self {
+        Self {
+            coverage: self.coverage.or(defaults.coverage),
+            altitude: self.altitude.or(defaults.altitude),
+            thickness: self.thickness.or(defaults.thickness),
+            density: self.density.or(defaults.density),
+            scale: self.scale.or(defaults.scale),
+        }
+    }
+}
+
+impl FogSection {
+    /// Fills in the settings this table leaves out from another one.
+    pub fn or(self, defaults: FogSection) -> Self {
+        Self {
+            density: self.density.or(defaults.density),
+            altitude: self.altitude.or(defaults.altitude),
+            haze: self.haze.or(defaults.haze),
+            scale: self.scale.or(defaults.scale),
+        }
+    }
+}
+
+impl TerrainSection {
+    /// Fills in the settings this table leaves out from another one.
+    pub fn or(self, defaults: TerrainSection) -> Self {
+        Self {
+            caves: self.caves.or(defaults.caves),
+            lava_level: self.lava_level.or(defaults.lava_level),
+            biomes: self.biomes.or(defaults.biomes),
+            vegetation: self.vegetation.or(defaults.vegetation),
+            ores: self.ores.or(defaults.ores),
+            water: self.water.or(defaults.water),
+            sea_level: self.sea_level.or(defaults.sea_level),
+            warp: self.warp.or(defaults.warp),
+            height: self.height.or(defaults.height),
+            roughness: self.roughness.or(defaults.roughness),
+            water_level: self.water_level.or(defaults.water_level),
+            mountain_level: self.mountain_level.or(defaults.mountain_level),
+            snow_level: self.snow_level.or(defaults.snow_level),
+            snow: self.snow.or(defaults.snow),
+            snow_altitude: self.snow_altitude.or(defaults.snow_altitude),
+            snow_depth: self.snow_depth.or(defaults.snow_depth),
+            octaves: self.octaves.or(defaults.octaves),
+            lacunarity: self.lacunarity.or(defaults.lacunarity),
+            persistence: self.persistence.or(defaults.persistence),
+            erosion: self.erosion.or(defaults.erosion),
+            rain: self.rain.or(defaults.rain),
+            structures: self.structures.or(defaults.structures),
+            prefabs: if self.prefabs.is_empty() {
+                defaults.prefabs
+            } else {
+                self.prefabs
+            },
+        }
+    }
+}
+
+/// Angle of orbit shots above the horizon, in degrees.
+const ORBIT_ELEVATION: f64 = 25.0;
+
+/// Angle from the middle of a spot light's cone to its edge when the scene file doesn't give one, in degrees.
+const SPOT_ANGLE: f32 = 30.0;
+
+/// Camera position on a circle around the vertical line through the center of the scene, far enough away to see a
+/// ball as large as the scene.
+pub fn orbit_position(degrees: f64, bounds: IAabb) -> IVec3 {
+    // leaves a margin around the ball in the 90 degree field of view
+    let distance = bounds.extents.max_element() as f64 * 1.6;
+    let (yaw, pitch) = (degrees.to_radians(), ORBIT_ELEVATION.to_radians());
+    let dir = DVec3::new(
+        yaw.cos() * pitch.cos(),
+        pitch.sin(),
+        yaw.sin() * pitch.cos(),
+    );
+    bounds.origin + (dir * distance).round().as_ivec3()
+}
+
+/// Settings resolved from the file, with defaults for anything it leaves out. Command-line flags are laid over the
+/// file with [`SceneFile::or`] before these are called.
+impl SceneFile {
+    /// The built-in scene the file starts from, if it names one.
+    pub fn preset(&self) -> Result<Option<Preset>, SceneFileError> {
+        self.preset
+            .as_deref()
+            .map(|name| {
+                Preset::from_name(name)
+                    .ok_or_else(|| invalid(format!("Invalid preset `{name}` in scene file")))
+            })
+            .transpose()
+    }
+
+    /// Storage backend, sparse unless the file names another.
+    pub fn backend(&self) -> Result<Backend, SceneFileError> {
+        match &self.backend {
+            Some(name) => Backend::from_name(name)
+                .ok_or_else(|| invalid(format!("Invalid backend `{name}` in scene file"))),
+            None => Ok(Backend::default()),
+        }
+    }
+
+    /// Time of day, day unless the file names another.
+    pub fn time_of_day(&self) -> Result<TimeOfDay, SceneFileError> {
+        match &self.time_of_day {
+            Some(name) => TimeOfDay::from_name(name)
+                .ok_or_else(|| invalid(format!("Invalid time of day `{name}` in scene file"))),
+            None => Ok(TimeOfDay::default()),
+        }
+    }
+
+    /// World coordinates to import from Minecraft regions.
+    pub fn window(&self) -> Option<Window> {
+        self.window
+            .map(|w| Window::from_corners(IVec3::from_slice(&w[..3]), IVec3::from_slice(&w[3..])))
+    }
+
+    /// Box the scene is built in, with every other setting left at its default.
+    ///
+    /// Without a size, the scene is as large as its bounds, or 200 voxels.
+    pub fn scene(&self) -> Result<Config, SceneFileError> {
+        let world = self
+            .bounds
+            .map(|b| IAabb::from_corners(IVec3::from_slice(&b[..3]), IVec3::from_slice(&b[3..])));
+        let size = self
+            .size
+            .or(world.map(|world| world.extents.max_element() as u32))
+            .unwrap_or(200);
+        if self.scene_height == Some(0) {
+            return Err(invalid("Scene height must be positive"));
+        }
+        Ok(Config {
+            size,
+            height: self.scene_height,
+            world,
+            ..Default::default()
+        })
+    }
+
+    /// Camera position, on the orbit around the scene if there is no position, or at its highest corner if neither
+    /// is given.
+    pub fn camera(&self, bounds: IAabb) -> IVec3 {
+        match (self.position, self.orbit) {
+            (Some(position), _) => IVec3::from_array(position),
+            (None, Some(degrees)) => orbit_position(degrees, bounds),
+            (None, None) => bounds.origin + bounds.extents.max_element() * IVec3::ONE,
+        }
+    }
+
+    /// Render settings: the scene's box and camera, the size of the image, and how rays are traced and shaded.
+    ///
+    /// Images are 7680x4320 unless the file gives a size.
+    pub fn config(&self) -> Result<Config, SceneFileError> {
+        let scene = self.scene()?;
+        let switch = |value: Option<bool>| value.unwrap_or(false);
+        let (shade, toon) = (switch(self.shade), switch(self.toon));
+
+        let near = self.near.unwrap_or(NEAR);
+        let far = self.far.unwrap_or(f32::INFINITY);
+        if !(0.0..far).contains(&near) {
+            return Err(invalid(
+                "Near distance must be at least zero and less than the far distance",
+            ));
+        }
+        let epsilon = self.epsilon.unwrap_or(ON_BOUNDARY);
+        if !(0.0..0.5).contains(&epsilon) {
+            return Err(invalid(
+                "Epsilon must be at least zero and less than half a voxel",
+            ));
+        }
+        let subsurface = switch(self.subsurface);
+        if subsurface && !(shade || toon) {
+            return Err(invalid("Subsurface scattering needs shading, add --shade"));
+        }
+
+        Ok(Config {
+            seed: self.seed,
+            res_width: self.width.unwrap_or(7680),
+            res_height: self.height.unwrap_or(4320),
+            camera_pos: self.camera(scene.bounds()).as_vec3a(),
+            debug: switch(self.debug),
+            lod: switch(self.lod),
+            packets: switch(self.packets),
+            beams: switch(self.beams),
+            cones: switch(self.cones),
+            near,
+            far,
+            epsilon,
+            double: switch(self.double),
+            antialias: switch(self.antialias),
+            dither: switch(self.dither),
+            shade,
+            hard_normals: switch(self.hard_normals),
+            smooth_surface: switch(self.smooth_surface),
+            toon,
+            clouds: self.clouds.settings(scene.bounds())?,
+            time: self.time.unwrap_or(0.0),
+            time_of_day: self.time_of_day()?,
+            path: self.path_settings()?,
+            caustics: self.caustic_settings()?,
+            subsurface,
+            fog: self.fog.settings()?,
+            transparency: switch(self.transparency),
+            ..scene
+        })
+    }
+
+    /// Path tracing, if the file gives a number of samples.
+    pub fn path_settings(&self) -> Result<Option<PathSettings>, SceneFileError> {
+        let Some(samples) = self.path else {
+            return Ok(None);
+        };
+        let bounces = self.path_bounces.unwrap_or(PathSettings::default().bounces);
+        if samples == 0 {
+            return Err(invalid("Path tracing needs at least one sample per pixel"));
+        }
+        if bounces == 0 {
+            return Err(invalid("Path tracing needs at least one bounce"));
+        }
+        Ok(Some(PathSettings { samples, bounces }))
+    }
+
+    /// Caustics, if the file gives a number of photons, which are only drawn when shading.
+    pub fn caustic_settings(&self) -> Result<Option<CausticSettings>, SceneFileError> {
+        let Some(photons) = self.caustics else {
+            return Ok(None);
+        };
+        let radius = self
+            .caustic_radius
+            .unwrap_or(CausticSettings::default().radius);
+        if !(self.shade.unwrap_or(false) || self.toon.unwrap_or(false)) {
+            return Err(invalid("Caustics need shading, add --shade"));
+        }
+        if photons == 0 {
+            return Err(invalid("Caustics need at least one photon per voxel"));
+        }
+        if radius <= 0.0 {
+            return Err(invalid("Caustic radius must be positive"));
+        }
+        Ok(Some(CausticSettings { photons, radius }))
+    }
+
+    /// Models from the `[[objects]]` tables.
+    pub fn objects(&self) -> Vec<ObjectFile> {
+        self.objects
+            .iter()
+            .map(|object| ObjectFile {
+                path: object.path.clone(),
+                position: IVec3::from_array(object.position.unwrap_or_default()),
+                rotation: object.rotation.unwrap_or(0.0),
+            })
+            .collect()
+    }
+
+    /// Sources from the `[[layers]]` tables, in order.
+    pub fn layers(&self) -> Result<Vec<LayerFile>, SceneFileError> {
+        self.layers
+            .iter()
+            .map(|layer| {
+                Ok(LayerFile {
+                    path: layer.path.clone(),
+                    position: IVec3::from_array(layer.position.unwrap_or_default()),
+                    blend: match &layer.blend {
+                        Some(name) => LayerBlend::from_name(name).ok_or_else(|| {
+                            invalid(format!("Invalid layer blend `{name}` in scene file"))
+                        })?,
+                        None => LayerBlend::default(),
+                    },
+                })
+            })
+            .collect()
+    }
+
+    /// Lights from the `[[lights]]` tables, each of which needs a name of its own.
+    pub fn lights(&self) -> Result<Vec<Light>, SceneFileError> {
+        for (i, section) in self.lights.iter().enumerate() {
+            if self.lights[..i]
+                .iter()
+                .any(|other| other.name == section.name)
+            {
+                return Err(invalid(format!(
+                    "Light `{}` is defined twice in scene file",
+                    section.name
+                )));
+            }
+        }
+        self.lights.iter().map(LightSection::light).collect()
+    }
+
+    /// Effects from the `[[post]]` tables, in order.
+    ///
+    /// Exposure, bloom, flare and color grading given by the file's top-level keys replace the same effect in the
+    /// list or are added to it, exposure before the rest, and dithered renders dither the result of any effects too.
+    pub fn post(&self) -> Result<Pipeline, SceneFileError> {
+        let bloom = |intensity: Option<f32>, threshold: Option<f32>| {
+            let defaults = Bloom::default();
+            let bloom = Bloom {
+                threshold: threshold.unwrap_or(defaults.threshold),
+                intensity: intensity.unwrap_or(defaults.intensity),
+            };
+            if bloom.intensity < 0.0 {
+                return Err(invalid("Bloom intensity must be at least zero"));
+            }
+            if !(0.0..1.0).contains(&bloom.threshold) {
+                return Err(invalid(
+                    "Bloom threshold must be at least zero and less than one",
+                ));
+            }
+            Ok(bloom)
+        };
+
+        let flare = |intensity: Option<f32>, threshold: Option<f32>, ghosts: Option<usize>| {
+            let defaults = Flare::default();
+            let flare = Flare {
+                threshold: threshold.unwrap_or(defaults.threshold),
+                intensity: intensity.unwrap_or(defaults.intensity),
+                ghosts: ghosts.unwrap_or(defaults.ghosts),
+            };
+            if flare.intensity < 0.0 {
+                return Err(invalid("Flare intensity must be at least zero"));
+            }
+            if !(0.0..1.0).contains(&flare.threshold) {
+                return Err(invalid(
+                    "Flare threshold must be at least zero and less than one",
+                ));
+            }
+            Ok(flare)
+        };
+
+        let exposure = |ev: Option<f32>, white: Option<[u8; 3]>| Exposure {
+            ev: ev.unwrap_or(0.0),
+            white: white.map_or(Vec3A::ONE, |white| {
+                U8Vec3::from_array(white).as_vec3a() / 255.0
+            }),
+        };
+
+        let mut post = Pipeline::new();
+        for section in &self.post {
+            match section.effect.as_str() {
+                "exposure" => post.push(exposure(section.ev, section.white)),
+                "tonemap" => {
+                    let knee = section.knee.unwrap_or(Tonemap::default().knee);
+                    if !(0.0..1.0).contains(&knee) {
+                        return Err(invalid(
+                            "Tonemap knee must be at least zero and less than one",
+                        ));
+                    }
+                    post.push(Tonemap { knee });
+                }
+                "bloom" => post.push(bloom(section.intensity, section.threshold)?),
+                "flare" => post.push(flare(section.intensity, section.threshold, section.ghosts)?),
+                "vignette" => {
+                    let intensity = section.intensity.unwrap_or(Vignette::default().intensity);
+                    if !(0.0..=1.0).contains(&intensity) {
+                        return Err(invalid("Vignette intensity must be from zero to one"));
+                    }
+                    post.push(Vignette { intensity });
+                }
+                "dither" => post.push(Dither),
+                "lut" => {
+                    let path = section
+                        .path
+                        .as_ref()
+                        .ok_or_else(|| invalid("Color grading in the scene file needs a path"))?;
+                    post.push(Lut::load(path).map_err(SceneFileError::Lut)?);
+                }
+                name => {
+                    return Err(invalid(format!(
+                        "Invalid post-processing effect `{name}` in scene file"
+                    )))
+                }
+            }
+        }
+
+        if self.exposure.is_some() || self.white_balance.is_some() {
+            post.set_front(exposure(self.exposure, self.white_balance));
+        }
+        if let Some(intensity) = self.bloom {
+            post.set(bloom(Some(intensity), self.bloom_threshold)?);
+        }
+        if let Some(intensity) = self.flare {
+            post.set(flare(Some(intensity), None, self.flare_ghosts)?);
+        }
+        if let Some(path) = &self.lut {
+            post.set(Lut::load(path).map_err(SceneFileError::Lut)?);
+        }
+        if self.dither.unwrap_or(false) && !post.is_empty() && !post.names().contains(&"dither") {
+            post.push(Dither);
+        }
+        Ok(post)
+    }
+}
+
+impl CloudSection {
+    /// Cloud layer, drawn if the table gives a coverage, with the bottom of the layer at the top of the scene's
+    /// bounds unless set.
+    pub fn settings(&self, bounds: IAabb) -> Result<Option<CloudSettings>, SceneFileError> {
+        let Some(coverage) = self.coverage else {
+            return Ok(None);
+        };
+        let defaults = CloudSettings::default();
+        let settings = CloudSettings {
+            altitude: self.altitude.unwrap_or(bounds.max().y as f32),
+            thickness: self.thickness.unwrap_or(defaults.thickness),
+            coverage,
+            density: self.density.unwrap_or(defaults.density),
+            scale: self.scale.unwrap_or(defaults.scale),
+        };
+        if !(0.0..=1.0).contains(&settings.coverage) || !(0.0..=1.0).contains(&settings.density) {
+            return Err(invalid(
+                "Cloud coverage and density must be between 0 and 1",
+            ));
+        }
+        if settings.thickness <= 0.0 || settings.scale <= 0.0 {
+            return Err(invalid("Cloud thickness and scale must be positive"));
+        }
+        Ok(Some(settings))
+    }
+}
+
+impl FogSection {
+    /// Fog, drawn if the table gives a density.
+    pub fn settings(&self) -> Result<Option<FogSettings>, SceneFileError> {
+        let Some(density) = self.density else {
+            return Ok(None);
+        };
+        let defaults = FogSettings::default();
+        let settings = FogSettings {
+            density,
+            altitude: self.altitude.unwrap_or(defaults.altitude),
+            haze: self.haze.unwrap_or(defaults.haze),
+            scale: self.scale.unwrap_or(defaults.scale),
+        };
+        if !(0.0..=1.0).contains(&settings.density) || !(0.0..=1.0).contains(&settings.haze) {
+            return Err(invalid("Fog density and haze must be between 0 and 1"));
+        }
+        if settings.scale <= 0.0 {
+            return Err(invalid("Fog scale must be positive"));
+        }
+        Ok(Some(settings))
+    }
+}
+
+impl TerrainSection {
+    /// Height, roughness and color bands of the terrain.
+    pub fn shape(&self) -> Result<TerrainSettings, SceneFileError> {
+        let defaults = TerrainSettings::default();
+        let terrain = TerrainSettings {
+            height: self.height.unwrap_or(defaults.height),
+            roughness: self.roughness.unwrap_or(defaults.roughness),
+            water_level: self.water_level.unwrap_or(defaults.water_level),
+            mountain_level: self.mountain_level.unwrap_or(defaults.mountain_level),
+            snow_level: self.snow_level.unwrap_or(defaults.snow_level),
+        };
+        if terrain.height <= 0 {
+            return Err(invalid("Terrain height must be positive"));
+        }
+        if !(terrain.water_level <= terrain.mountain_level
+            && terrain.mountain_level <= terrain.snow_level)
+        {
+            return Err(invalid(
+                "Terrain levels must be ordered: water <= mountain <= snow",
+            ));
+        }
+        Ok(terrain)
+    }
+
+    /// Octaves of the terrain's height noise.
+    pub fn fbm(&self) -> Result<FbmSettings, SceneFileError> {
+        let defaults = FbmSettings::default();
+        let fbm = FbmSettings {
+            octaves: self.octaves.unwrap_or(defaults.octaves),
+            lacunarity: self.lacunarity.unwrap_or(defaults.lacunarity),
+            persistence: self.persistence.unwrap_or(defaults.persistence),
+        };
+        if fbm.octaves == 0 {
+            return Err(invalid("Octaves must be at least 1"));
+        }
+        Ok(fbm)
+    }
+
+    /// Height up to which caves are flooded with lava, which needs caves to flood.
+    pub fn lava_level(&self) -> Result<Option<i32>, SceneFileError> {
+        if self.lava_level.is_some() && !self.caves.unwrap_or(false) {
+            return Err(invalid("Lava needs caves to flood, add --caves"));
+        }
+        Ok(self.lava_level)
+    }
+
+    /// Seas, lakes and rivers, if the table turns on water or gives a sea level, which defaults to the terrain's.
+    pub fn water(&self, terrain: &TerrainSettings) -> Option<WaterSettings> {
+        (self.water.unwrap_or(false) || self.sea_level.is_some()).then(|| WaterSettings {
+            sea_level: self.sea_level.unwrap_or(terrain.sea_level()),
+            ..Default::default()
+        })
+    }
+
+    /// Domain warping, if the table gives a strength.
+    pub fn warp(&self) -> Option<WarpSettings> {
+        self.warp.map(|strength| WarpSettings {
+            strength,
+            ..Default::default()
+        })
+    }
+
+    /// Erosion, if the table gives a number of iterations or the rain.
+    pub fn erosion(&self) -> Result<Option<ErosionSettings>, SceneFileError> {
+        if self.erosion.is_none() && self.rain.is_none() {
+            return Ok(None);
+        }
+        let defaults = ErosionSettings::default();
+        let erosion = ErosionSettings {
+            iterations: self.erosion.unwrap_or(defaults.iterations),
+            rain: self.rain.unwrap_or(defaults.rain),
+        };
+        if erosion.rain < 0.0 {
+            return Err(invalid("Rain must not be negative"));
+        }
+        Ok(Some(erosion))
+    }
+
+    /// Snow piled on the ground, settling from the terrain's snow level up unless the table gives an altitude.
+    pub fn snow(&self, terrain: &TerrainSettings) -> Result<Option<SnowSettings>, SceneFileError> {
+        if !self.snow.unwrap_or(false) {
+            return Ok(None);
+        }
+        let snow = SnowSettings {
+            altitude: self
+                .snow_altitude
+                .unwrap_or((terrain.snow_level * terrain.height as f64) as i32),
+            depth: self.snow_depth.unwrap_or(SnowSettings::default().depth),
+            ..Default::default()
+        };
+        if snow.depth <= 0 {
+            return Err(invalid("Snow depth must be positive"));
+        }
+        Ok(Some(snow))
+    }
+
+    /// Structure models to place on the terrain, if the table turns structures on or lists any, with none meaning
+    /// the built-in ones.
+    pub fn structures(&self) -> Result<Option<Vec<PrefabFile>>, SceneFileError> {
+        if !self.structures.unwrap_or(false) && self.prefabs.is_empty() {
+            return Ok(None);
+        }
+        self.prefabs
+            .iter()
+            .map(|prefab| {
+                Ok(PrefabFile {
+                    path: prefab.path.clone(),
+                    ground: match &prefab.ground {
+                        Some(ground) => ground.parse().map_err(SceneFileError::Invalid)?,
+                        None => Ground::Grass,
+                    },
+                    max_slope: prefab.max_slope,
+                    depth: prefab.depth.unwrap_or(0),
+                })
+            })
+            .collect::<Result<_, _>>()
+            .map(Some)
+    }
+}
+
+impl LightSection {
+    /// The light the table describes.
+    pub fn light(&self) -> Result<Light, SceneFileError> {
+        let name = &self.name;
+        let vector = |value: Option<[f32; 3]>, key: &str| {
+            value
+                .map(Vec3A::from_array)
+                .ok_or_else(|| invalid(format!("Light `{name}` in scene file needs a {key}")))
+        };
+        let direction = || {
+            vector(self.direction, "direction")?
+                .try_normalize()
+                .ok_or_else(|| invalid(format!("Light `{name}` in scene file has no direction")))
+        };
+        let kind = match self.kind.as_str() {
+            "directional" => LightKind::Directional { dir: direction()? },
+            "point" => LightKind::Point {
+                position: vector(self.position, "position")?,
+            },
+            "spot" => {
+                let angle = self.angle.unwrap_or(SPOT_ANGLE);
+                if !(angle > 0.0 && angle < 180.0) {
+                    return Err(invalid(format!(
+                        "Light `{name}` in scene file needs an angle above 0 and below 180 degrees"
+                    )));
+                }
+                LightKind::Spot {
+                    position: vector(self.position, "position")?,
+                    // the direction it shines in, the opposite of a directional light's
+                    dir: direction()?,
+                    angle: angle.to_radians(),
+                }
+            }
+            kind => {
+                return Err(invalid(format!(
+                    "Invalid light kind `{kind}` for light `{name}` in scene file"
+                )))
+            }
+        };
+        let intensity = self.intensity.unwrap_or(1.0);
+        if intensity < 0.0 {
+            return Err(invalid(format!(
+                "Light `{name}` in scene file needs an intensity of at least zero"
+            )));
+        }
+        Ok(Light {
+            kind,
+            color: self.color.map_or(Vec3A::ONE, |color| {
+                U8Vec3::from_array(color).as_vec3a() / 255.0
+            }),
+            intensity,
+            shadows: self.shadows.unwrap_or(true),
+        })
+    }
+}
+
+/// Built-in scenes that bundle a generator, its settings, and a camera position
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Preset {
+    /// Snowy, eroded peaks with lakes in the valleys
+    Mountains,
+    /// Floating islands seen from the side
+    Islands,
+    /// Hills riddled with caves, with ore in the cave walls
+    Caves,
+    /// Winding rock walls with a river along the bottom
+    Canyon,
+}
+
+impl Preset {
+    /// Every preset, in the order they are listed on the command line.
+    pub const ALL: [Preset; 4] = [Self::Mountains, Self::Islands, Self::Caves, Self::Canyon];
+
+    /// Preset with a name, ignoring case, see [`Preset::name`].
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|preset| preset.name().eq_ignore_ascii_case(name))
+    }
+
+    /// Lower case name, as on the command line and in scene files.
+    pub fn name(self) -> &'static str {
+        match self {
+            Preset::Mountains => "mountains",
+            Preset::Islands => "islands",
+            Preset::Caves => "caves",
+            Preset::Canyon => "canyon",
+        }
+    }
+
+    /// Scene file with the preset's settings.
+    pub fn scene_file(self) -> SceneFile {
+        let contents = match self {
+            Preset::Mountains => include_str!("../presets/mountains.toml"),
+            Preset::Islands => include_str!("../presets/islands.toml"),
+            Preset::Caves => include_str!("../presets/caves.toml"),
+            Preset::Canyon => include_str!("../presets/canyon.toml"),
+        };
+        SceneFile::parse(contents).expect("built-in preset should parse")
+    }
+}
+
+/// Errors from loading a scene file.
+#[derive(Debug)]
+pub enum SceneFileError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+    /// A setting is out of range or doesn't go with the others.
+    Invalid(String),
+    /// A color lookup table the file names failed to load.
+    Lut(LutError),
+}
+
+fn invalid(message: impl Into<String>) -> SceneFileError {
+    SceneFileError::Invalid(message.into())
+}
+
+impl fmt::Display for SceneFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneFileError::Io(err) => write!(f, "failed to read scene file: {err}"),
+            SceneFileError::Parse(err) => write!(f, "failed to parse scene file: {err}"),
+            SceneFileError::Invalid(err) => write!(f, "{err}"),
+            SceneFileError::Lut(err) => write!(f, "failed to load color lookup table: {err}"),
+        }
+    }
+}
+
+impl Error for SceneFileError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_full() {
+        let file = SceneFile::parse(
+            r#"
+            preset = "canyon"
+            backend = "dense"
+            generator = "sdf-csg"
+            import = "castle.schem"
+            palette = "blocks.toml"
+            window = [0, -64, 0, 127, 319, 127]
+            resolution = 64
+            solid = true
+            script = "world.rhai"
+            plugin = "world.wasm"
+            load_scene = "terrain.vxs"
+            save_scene = "copy.vxs"
+            size = 50
+            scene_height = 20
+            bounds = [-40, 0, -30, 39, 63, 29]
+            position = [60, 70, 80]
+            orbit = 45.0
+            seed = 7
+            out = "scene.png"
+            lut = "film.cube"
+            exposure = -0.5
+            white_balance = [255, 240, 220]
+            bloom = 0.5
+            bloom_threshold = 0.7
+            flare = 0.3
+            flare_ghosts = 5
+            width = 640
+            height = 360
+            debug = true
+            lod = true
+            packets = true
+            beams = true
+            cones = true
+            far = 800.0
+            near = 0.5
+            epsilon = 0.001
+            double = true
+            antialias = true
+            dither = true
+            shade = true
+            hard_normals = true
+            smooth_surface = true
+            toon = true
+            time = 2.5
+            time_of_day = "night"
+            path = 32
+            path_bounces = 6
+            caustics = 48
+            caustic_radius = 0.75
+            subsurface = true
+            transparency = true
+            cache_budget = 256
+
+            [terrain]
+            caves = true
+            lava_level = 12
+            biomes = true
+            vegetation = true
+            ores = true
+            water = true
+            sea_level = 25
+            warp = 35.5
+            height = 150
+            roughness = 1.5
+            water_level = 0.2
+            mountain_level = 0.5
+            snow_level = 0.9
+            snow = true
+            snow_altitude = 120
+            snow_depth = 2
+            octaves = 4
+            lacunarity = 2.5
+            persistence = 0.4
+            erosion = 30
+            rain = 0.2
+            structures = true
+
+            [[terrain.prefabs]]
+            path = "tower.vox"
+            ground = "mountain"
+            max_slope = 3
+            depth = 1
+
+            [[terrain.prefabs]]
+            path = "hut.vox"
+
+            [clouds]
+            coverage = 0.5
+            altitude = 120.0
+            thickness = 16.0
+            density = 0.2
+            scale = 48.0
+
+            [fog]
+            density = 0.05
+            altitude = 30.0
+            haze = 0.25
+            scale = 24.0
+
+            [[objects]]
+            path = "boat.vox"
+            position = [10, 30, -20]
+            rotation = 90.0
+
+            [[objects]]
+            path = "tree.vox"
+
+            [[layers]]
+            path = "house.vox"
+            position = [5, 20, 5]
+            blend = "replace"
+
+            [[layers]]
+            path = "tunnels.rhai"
+
+            [[post]]
+            effect = "exposure"
+            ev = 1.5
+            white = [250, 250, 255]
+
+            [[post]]
+            effect = "bloom"
+            intensity = 0.4
+            threshold = 0.9
+
+            [[post]]
+            effect = "flare"
+            ghosts = 2
+
+            [[post]]
+            effect = "lut"
+            path = "film.cube"
+
+            [[lights]]
+            name = "sun"
+            kind = "directional"
+            direction = [1.0, 2.0, 0.5]
+            color = [255, 240, 220]
+            intensity = 0.8
+            shadows = false
+
+            [[lights]]
+            name = "torch"
+            kind = "spot"
+            position = [10.0, 30.0, -5.0]
+            direction = [0.0, -1.0, 0.0]
+            intensity = 200.0
+            angle = 25.0
+            "#,
+        )
+        .expect("failed to parse");
+
+        assert_eq!(
+            file,
+            SceneFile {
+                preset: Some("canyon".into()),
+                backend: Some("dense".into()),
+                generator: Some("sdf-csg".into()),
+                import: Some("castle.schem".into()),
+                palette: Some("blocks.toml".into()),
+                window: Some([0, -64, 0, 127, 319, 127]),
+                resolution: Some(64),
+                solid: Some(true),
+                script: Some("world.rhai".into()),
+                plugin: Some("world.wasm".into()),
+                load_scene: Some("terrain.vxs".into()),
+                save_scene: Some("copy.vxs".into()),
+                size: Some(50),
+                scene_height: Some(20),
+                bounds: Some([-40, 0, -30, 39, 63, 29]),
+                position: Some([60, 70, 80]),
+                orbit: Some(45.0),
+                seed: Some(7),
+                out: Some("scene.png".into()),
+                lut: Some("film.cube".into()),
+                exposure: Some(-0.5),
+                white_balance: Some([255, 240, 220]),
+                bloom: Some(0.5),
+                bloom_threshold: Some(0.7),
+                flare: Some(0.3),
+                flare_ghosts: Some(5),
+                width: Some(640),
+                height: Some(360),
+                debug: Some(true),
+                lod: Some(true),
+                packets: Some(true),
+                beams: Some(true),
+                cones: Some(true),
+                far: Some(800.0),
+                near: Some(0.5),
+                epsilon: Some(0.001),
+                double: Some(true),
+                antialias: Some(true),
+                dither: Some(true),
+                shade: Some(true),
+                hard_normals: Some(true),
+                smooth_surface: Some(true),
+                toon: Some(true),
+                time: Some(2.5),
+                time_of_day: Some("night".into()),
+                path: Some(32),
+                path_bounces: Some(6),
+                caustics: Some(48),
+                caustic_radius: Some(0.75),
+                subsurface: Some(true),
+                transparency: Some(true),
+                cache_budget: Some(256),
+                terrain: TerrainSection {
+                    caves: Some(true),
+                    lava_level: Some(12),
+                    biomes: Some(true),
+                    vegetation: Some(true),
+                    ores: Some(true),
+                    water: Some(true),
+                    sea_level: Some(25),
+                    warp: Some(35.5),
+                    height: Some(150),
+                    roughness: Some(1.5),
+                    water_level: Some(0.2),
+                    mountain_level: Some(0.5),
+                    snow_level: Some(0.9),
+                    snow: Some(true),
+                    snow_altitude: Some(120),
+                    snow_depth: Some(2),
+                    octaves: Some(4),
+                    lacunarity: Some(2.5),
+                    persistence: Some(0.4),
+                    erosion: Some(30),
+                    rain: Some(0.2),
+                    structures: Some(true),
+                    prefabs: vec![
+                        PrefabSection {
+                            path: "tower.vox".into(),
+                            ground: Some("mountain".into()),
+                            max_slope: Some(3),
+                            depth: Some(1),
+                        },
+                        PrefabSection {
+                            path: "hut.vox".into(),
+                            ..Default::default()
+                        },
+                    ],
+                },
+                clouds: CloudSection {
+                    coverage: Some(0.5),
+                    altitude: Some(120.0),
+                    thickness: Some(16.0),
+                    density: Some(0.2),
+                    scale: Some(48.0),
+                },
+                fog: FogSection {
+                    density: Some(0.05),
+                    altitude: Some(30.0),
+                    haze: Some(0.25),
+                    scale: Some(24.0),
+                },
+                objects: vec![
+                    ObjectSection {
+                        path: "boat.vox".into(),
+                        position: Some([10, 30, -20]),
+                        rotation: Some(90.0),
+                    },
+                    ObjectSection {
+                        path: "tree.vox".into(),
+                        ..Default::default()
+                    },
+                ],
+                layers: vec![
+                    LayerSection {
+                        path: "house.vox".into(),
+                        position: Some([5, 20, 5]),
+                        blend: Some("replace".into()),
+                    },
+                    LayerSection {
+                        path: "tunnels.rhai".into(),
+                        ..Default::default()
+                    },
+                ],
+                post: vec![
+                    PostSection {
+                        effect: "exposure".into(),
+                        ev: Some(1.5),
+                        white: Some([250, 250, 255]),
+                        ..Default::default()
+                    },
+                    PostSection {
+                        effect: "bloom".into(),
+                        intensity: Some(0.4),
+                        threshold: Some(0.9),
+                        ..Default::default()
+                    },
+                    PostSection {
+                        effect: "flare".into(),
+                        ghosts: Some(2),
+                        ..Default::default()
+                    },
+                    PostSection {
+                        effect: "lut".into(),
+                        path: Some("film.cube".into()),
+                        ..Default::default()
+                    },
+                ],
+                lights: vec![
+                    LightSection {
+                        name: "sun".into(),
+                        kind: "directional".into(),
+                        direction: Some([1.0, 2.0, 0.5]),
+                        color: Some([255, 240, 220]),
+                        intensity: Some(0.8),
+                        shadows: Some(false),
+                        ..Default::default()
+                    },
+                    LightSection {
+                        name: "torch".into(),
+                        kind: "spot".into(),
+                        position: Some([10.0, 30.0, -5.0]),
+                        direction: Some([0.0, -1.0, 0.0]),
+                        intensity: Some(200.0),
+                        angle: Some(25.0),
+                        ..Default::default()
+                    },
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn parse_empty() {
+        let file = SceneFile::parse("").expect("failed to parse");
+        assert_eq!(file, SceneFile::default());
+    }
+
+    #[test]
+    fn parse_unknown_field() {
+        assert!(matches!(
+            SceneFile::parse("colour = 5"),
+            Err(SceneFileError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn or() {
+        let file = SceneFile::parse(
+            r#"
+            size = 50
+            [terrain]
+            caves = false
+            "#,
+        )
+        .expect("failed to parse");
+        let defaults = SceneFile::parse(
+            r#"
+            size = 100
+            seed = 3
+            [terrain]
+            caves = true
+            octaves = 4
+            [[terrain.prefabs]]
+            path = "hut.vox"
+            "#,
+        )
+        .expect("failed to parse");
+
+        let merged = file.clone().or(defaults.clone());
+        assert_eq!(merged.size, Some(50));
+        assert_eq!(merged.seed, Some(3));
+        assert_eq!(merged.terrain.caves, Some(false));
+        assert_eq!(merged.terrain.octaves, Some(4));
+        assert_eq!(merged.terrain.prefabs, defaults.terrain.prefabs);
+        assert_eq!(SceneFile::default().or(defaults.clone()), defaults);
+
+        // presets only fill in what the file leaves out
+        let islands = file.clone().or(Preset::Islands.scene_file());
+        assert_eq!(islands.size, Some(50));
+        assert_eq!(islands.generator.as_deref(), Some("islands"));
+
+        let orbit = SceneFile {
+            orbit: Some(45.0),
+            ..Default::default()
+        };
+        let position = SceneFile {
+            position: Some([1, 2, 3]),
+            ..Default::default()
+        };
+        assert_eq!(orbit.clone().or(position.clone()), orbit);
+        assert_eq!(position.clone().or(orbit), position);
+    }
+
+    #[test]
+    fn presets() {
+        for preset in Preset::ALL {
+            let file = preset.scene_file();
+            // presets leave the output to the user
+            assert!(file.generator.is_some());
+            assert_eq!((file.out, file.width, file.height), (None, None, None));
+        }
+    }
+
+    fn parse(contents: &str) -> SceneFile {
+        SceneFile::parse(contents).expect("failed to parse")
+    }
+
+    /// Message of the error a setting is rejected with.
+    fn rejected<T: fmt::Debug>(result: Result<T, SceneFileError>) -> String {
+        result.expect_err("should be rejected").to_string()
+    }
+
+    #[test]
+    fn names() {
+        let file = parse(r#"backend = "dag""#);
+        assert_eq!(file.backend().unwrap(), Backend::Dag);
+        assert_eq!(SceneFile::default().backend().unwrap(), Backend::default());
+        assert_eq!(SceneFile::default().preset().unwrap(), None);
+        assert_eq!(
+            parse(r#"preset = "Canyon""#).preset().unwrap(),
+            Some(Preset::Canyon)
+        );
+        assert_eq!(
+            rejected(parse(r#"backend = "sparce""#).backend()),
+            "Invalid backend `sparce` in scene file"
+        );
+        assert_eq!(
+            rejected(parse(r#"preset = "desert""#).preset()),
+            "Invalid preset `desert` in scene file"
+        );
+        assert_eq!(
+            rejected(parse(r#"time_of_day = "noon""#).time_of_day()),
+            "Invalid time of day `noon` in scene file"
+        );
+    }
+
+    #[test]
+    fn scene() {
+        let scene = SceneFile::default().scene().unwrap();
+        assert_eq!((scene.size, scene.height, scene.world), (200, None, None));
+
+        // the bounds give the size unless it is set
+        let bounds = parse("bounds = [-40, 0, -30, 39, 63, 29]");
+        let world = IAabb::from_corners(IVec3::new(-40, 0, -30), IVec3::new(39, 63, 29));
+        let scene = bounds.scene().unwrap();
+        assert_eq!((scene.size, scene.world), (40, Some(world)));
+        let sized = SceneFile {
+            size: Some(50),
+            ..bounds
+        };
+        assert_eq!(sized.scene().unwrap().size, 50);
+
+        assert_eq!(
+            rejected(parse("scene_height = 0").scene()),
+            "Scene height must be positive"
+        );
+    }
+
+    #[test]
+    fn camera() {
+        let bounds = SceneFile::default().scene().unwrap().bounds();
+        let corner = bounds.origin + bounds.extents.max_element() * IVec3::ONE;
+        assert_eq!(SceneFile::default().camera(bounds), corner);
+        assert_eq!(
+            parse("orbit = 90.0").camera(bounds),
+            orbit_position(90.0, bounds)
+        );
+        assert_eq!(
+            parse("position = [1, 2, 3]\norbit = 90.0").camera(bounds),
+            IVec3::new(1, 2, 3)
+        );
+
+        // the orbit circles the middle of the scene, above it
+        let orbit = orbit_position(0.0, bounds) - bounds.origin;
+        assert!(orbit.x > 0 && orbit.y > 0);
+        assert_eq!(orbit.z, 0);
+    }
+
+    #[test]
+    fn config() {
+        let config = SceneFile::default().config().unwrap();
+        assert_eq!((config.res_width, config.res_height), (7680, 4320));
+        assert_eq!((config.near, config.far), (NEAR, f32::INFINITY));
+        assert_eq!(config.epsilon, ON_BOUNDARY);
+        assert!(!config.shade && config.clouds.is_none() && config.fog.is_none());
+
+        let config = parse("width = 640\nheight = 480\nshade = true\nsubsurface = true")
+            .config()
+            .unwrap();
+        assert_eq!((config.res_width, config.res_height), (640, 480));
+        assert!(config.shade && config.subsurface);
+
+        for (contents, message) in [
+            (
+                "near = 10.0\nfar = 5.0",
+                "Near distance must be at least zero and less than the far distance",
+            ),
+            (
+                "epsilon = 0.5",
+                "Epsilon must be at least zero and less than half a voxel",
+            ),
+            (
+                "subsurface = true",
+                "Subsurface scattering needs shading, add --shade",
+            ),
+            ("scene_height = 0", "Scene height must be positive"),
+        ] {
+            assert_eq!(rejected(parse(contents).config()), message, "{contents}");
+        }
+    }
+
+    #[test]
+    fn path_and_caustics() {
+        let file = parse("path = 16\ncaustics = 8\nshade = true");
+        let path = file.path_settings().unwrap().unwrap();
+        assert_eq!(
+            (path.samples, path.bounces),
+            (16, PathSettings::default().bounces)
+        );
+        let caustics = file.caustic_settings().unwrap().unwrap();
+        assert_eq!(caustics.radius, CausticSettings::default().radius);
+        assert_eq!(SceneFile::default().path_settings().unwrap(), None);
+        assert_eq!(SceneFile::default().caustic_settings().unwrap(), None);
+
+        assert_eq!(
+            rejected(parse("path = 0").path_settings()),
+            "Path tracing needs at least one sample per pixel"
+        );
+        assert_eq!(
+            rejected(parse("path = 4\npath_bounces = 0").path_settings()),
+            "Path tracing needs at least one bounce"
+        );
+        assert_eq!(
+            rejected(parse("caustics = 8").caustic_settings()),
+            "Caustics need shading, add --shade"
+        );
+        assert_eq!(
+            rejected(parse("caustics = 8\ntoon = true\ncaustic_radius = 0.0").caustic_settings()),
+            "Caustic radius must be positive"
+        );
+    }
+
+    #[test]
+    fn clouds_and_fog() {
+        let bounds = SceneFile::default().scene().unwrap().bounds();
+        let file = parse("[clouds]\ncoverage = 0.5\n[fog]\ndensity = 0.2");
+        let clouds = file.clouds.settings(bounds).unwrap().unwrap();
+        assert_eq!(clouds.coverage, 0.5);
+        // the layer starts at the top of the scene
+        assert_eq!(clouds.altitude, bounds.max().y as f32);
+        assert_eq!(file.fog.settings().unwrap().unwrap().density, 0.2);
+        assert_eq!(CloudSection::default().settings(bounds).unwrap(), None);
+        assert_eq!(FogSection::default().settings().unwrap(), None);
+
+        assert_eq!(
+            rejected(parse("[clouds]\ncoverage = 1.5").clouds.settings(bounds)),
+            "Cloud coverage and density must be between 0 and 1"
+        );
+        assert_eq!(
+            rejected(
+                parse("[clouds]\ncoverage = 0.5\nscale = 0.0")
+                    .clouds
+                    .settings(bounds)
+            ),
+            "Cloud thickness and scale must be positive"
+        );
+        assert_eq!(
+            rejected(parse("[fog]\ndensity = 0.2\nscale = -1.0").fog.settings()),
+            "Fog scale must be positive"
+        );
+    }
+
+    #[test]
+    fn terrain() {
+        let terrain = TerrainSection::default();
+        assert_eq!(terrain.shape().unwrap(), TerrainSettings::default());
+        assert_eq!(terrain.fbm().unwrap(), FbmSettings::default());
+        assert_eq!(terrain.lava_level().unwrap(), None);
+        assert_eq!(terrain.water(&TerrainSettings::default()), None);
+        assert_eq!(terrain.warp(), None);
+        assert_eq!(terrain.erosion().unwrap(), None);
+        assert_eq!(terrain.snow(&TerrainSettings::default()).unwrap(), None);
+        assert_eq!(terrain.structures().unwrap(), None);
+
+        let terrain = parse(
+            r#"
+            [terrain]
+            height = 60
+            caves = true
+            lava_level = 10
+            water = true
+            rain = 0.5
+            snow = true
+            "#,
+        )
+        .terrain;
+        let shape = terrain.shape().unwrap();
+        assert_eq!(shape.height, 60);
+        assert_eq!(terrain.lava_level().unwrap(), Some(10));
+        // water and snow default to the terrain's levels
+        assert_eq!(terrain.water(&shape).unwrap().sea_level, shape.sea_level());
+        assert_eq!(terrain.snow(&shape).unwrap().unwrap().altitude, 48);
+        let erosion = terrain.erosion().unwrap().unwrap();
+        assert_eq!(
+            (erosion.iterations, erosion.rain),
+            (ErosionSettings::default().iterations, 0.5)
+        );
+
+        for (contents, message) in [
+            ("height = 0", "Terrain height must be positive"),
+            (
+                "water_level = 0.9",
+                "Terrain levels must be ordered: water <= mountain <= snow",
+            ),
+            ("octaves = 0", "Octaves must be at least 1"),
+            ("lava_level = 10", "Lava needs caves to flood, add --caves"),
+            ("rain = -1.0", "Rain must not be negative"),
+            ("snow = true\nsnow_depth = 0", "Snow depth must be positive"),
+        ] {
+            let terrain = parse(&format!("[terrain]\n{contents}")).terrain;
+            let resolve = || -> Result<(), SceneFileError> {
+                let shape = terrain.shape()?;
+                terrain.fbm()?;
+                terrain.lava_level()?;
+                terrain.erosion()?;
+                terrain.snow(&shape)?;
+                Ok(())
+            };
+            assert_eq!(rejected(resolve()), message, "{contents}");
+        }
+    }
+
+    #[test]
+    fn structures() {
+        let terrain = parse("[terrain]\nstructures = true").terrain;
+        assert_eq!(terrain.structures().unwrap(), Some(Vec::new()));
+
+        // listing prefabs turns structures on
+        let terrain = parse(
+            r#"
+            [[terrain.prefabs]]
+            path = "hut.vox"
+            [[terrain.prefabs]]
+            path = "dock.vox"
+            ground = "snow"
+            max_slope = 2
+            depth = 1
+            "#,
+        )
+        .terrain;
+        assert_eq!(
+            terrain.structures().unwrap(),
+            Some(vec![
+                PrefabFile {
+                    path: "hut.vox".into(),
+                    ground: Ground::Grass,
+                    max_slope: None,
+                    depth: 0,
+                },
+                PrefabFile {
+                    path: "dock.vox".into(),
+                    ground: Ground::Snow,
+                    max_slope: Some(2),
+                    depth: 1,
+                },
+            ])
+        );
+
+        let terrain = parse("[[terrain.prefabs]]\npath = \"hut.vox\"\nground = \"lava\"").terrain;
+        assert!(matches!(
+            terrain.structures(),
+            Err(SceneFileError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn lights() {
+        let lights = parse(
+            r#"
+            [[lights]]
+            name = "sun"
+            kind = "directional"
+            direction = [0.0, 2.0, 0.0]
+            [[lights]]
+            name = "lamp"
+            kind = "point"
+            position = [1.0, 2.0, 3.0]
+            intensity = 4.0
+            [[lights]]
+            name = "torch"
+            kind = "spot"
+            position = [0.0, 0.0, 0.0]
+            direction = [1.0, 0.0, 0.0]
+            "#,
+        )
+        .lights()
+        .unwrap();
+        assert_eq!(lights[0].kind, LightKind::Directional { dir: Vec3A::Y });
+        assert_eq!(
+            lights[1].kind,
+            LightKind::Point {
+                position: Vec3A::new(1.0, 2.0, 3.0)
+            }
+        );
+        assert_eq!(lights[1].intensity, 4.0);
+        assert!(
+            matches!(lights[2].kind, LightKind::Spot { angle, .. } if angle == SPOT_ANGLE.to_radians())
+        );
+
+        for (contents, message) in [
+            (
+                "name = \"sun\"\nkind = \"directional\"\ndirection = [0.0, 1.0, 0.0]\n[[lights]]\nname = \"sun\"\nkind = \"directional\"\ndirection = [1.0, 0.0, 0.0]",
+                "Light `sun` is defined twice in scene file",
+            ),
+            (
+                "name = \"lamp\"\nkind = \"point\"",
+                "Light `lamp` in scene file needs a position",
+            ),
+            (
+                "name = \"sun\"\nkind = \"directional\"\ndirection = [0.0, 0.0, 0.0]",
+                "Light `sun` in scene file has no direction",
+            ),
+        ] {
+            let file = parse(&format!("[[lights]]\n{contents}"));
+            assert_eq!(rejected(file.lights()), message, "{contents}");
+        }
+    }
+
+    #[test]
+    fn layers() {
+        let layers = parse(
+            r#"
+            [[layers]]
+            path = "base.vxs"
+            [[layers]]
+            path = "cave.rhai"
+            position = [0, -10, 0]
+            blend = "carve"
+            "#,
+        )
+        .layers()
+        .unwrap();
+        assert_eq!(
+            layers,
+            vec![
+                LayerFile {
+                    path: "base.vxs".into(),
+                    position: IVec3::ZERO,
+                    blend: LayerBlend::default(),
+                },
+                LayerFile {
+                    path: "cave.rhai".into(),
+                    position: IVec3::new(0, -10, 0),
+                    blend: LayerBlend::Carve,
+                },
+            ]
+        );
+        assert_eq!(
+            rejected(parse("[[layers]]\npath = \"a.vxs\"\nblend = \"mix\"").layers()),
+            "Invalid layer blend `mix` in scene file"
+        );
+    }
+
+    #[test]
+    fn post() {
+        assert!(SceneFile::default().post().unwrap().is_empty());
+        // dithering alone has nothing to dither after
+        assert!(parse("dither = true").post().unwrap().is_empty());
+
+        let post = parse(
+            r#"
+            bloom = 0.5
+            exposure = 1.0
+            dither = true
+            [[post]]
+            effect = "tonemap"
+            [[post]]
+            effect = "bloom"
+            intensity = 2.0
+            "#,
+        )
+        .post()
+        .unwrap();
+        // top-level keys replace the same effect in the list, and exposure goes first
+        assert_eq!(post.names(), ["exposure", "tonemap", "bloom", "dither"]);
+
+        for (contents, message) in [
+            (
+                "[[post]]\neffect = \"blur\"",
+                "Invalid post-processing effect `blur` in scene file",
+            ),
+            (
+                "[[post]]\neffect = \"vignette\"\nintensity = 2.0",
+                "Vignette intensity must be from zero to one",
+            ),
+            (
+                "[[post]]\neffect = \"lut\"",
+                "Color grading in the scene file needs a path",
+            ),
+            (
+                "bloom = 0.5\nbloom_threshold = 1.0",
+                "Bloom threshold must be at least zero and less than one",
+            ),
+            ("flare = -1.0", "Flare intensity must be at least zero"),
+        ] {
+            assert_eq!(rejected(parse(contents).post()), message, "{contents}");
+        }
+        assert!(matches!(
+            parse("lut = \"missing.cube\"").post(),
+            Err(SceneFileError::Lut(_))
+        ));
+    }
+}
@@ -0,0 +1,287 @@
+use glam::{DVec2, U8Vec3, Vec3A};
+use noise::{NoiseFn, Perlin};
+
+use super::{
+    TerrainSettings, Voxel, VoxelKind, BAND_KINDS, GRASS_GREEN, MOUNTAIN_GRAY, SNOW_WHITE,
+    WATER_BLUE,
+};
+
+/// Size of biome regions in voxels (noise frequency is the inverse).
+const BIOME_SCALE: f64 = 300.0;
+
+/// Spread of each biome in climate space, larger values make wider borders.
+const BLEND_WIDTH: f64 = 0.35;
+
+/// A region of terrain with its own shape and colors.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Biome {
+    Plains,
+    Desert,
+    Forest,
+    Tundra,
+}
+
+impl Biome {
+    pub const ALL: [Biome; 4] = [Biome::Plains, Biome::Desert, Biome::Forest, Biome::Tundra];
+
+    /// Climate (temperature, moisture) where this biome is strongest.
+    fn climate(self) -> DVec2 {
+        match self {
+            Biome::Plains => DVec2::new(0.1, -0.1),
+            Biome::Desert => DVec2::new(0.6, -0.6),
+            Biome::Forest => DVec2::new(0.1, 0.6),
+            Biome::Tundra => DVec2::new(-0.6, 0.0),
+        }
+    }
+
+    /// Shape of the terrain in this biome.
+    fn shape(self) -> Shape {
+        match self {
+            Biome::Plains => Shape {
+                base: 0.0,
+                amplitude: 1.0,
+                roughness: 1.0,
+            },
+            Biome::Desert => Shape {
+                base: 0.3,
+                amplitude: 0.4,
+                roughness: 2.5,
+            },
+            Biome::Forest => Shape {
+                base: 0.1,
+                amplitude: 0.8,
+                roughness: 1.3,
+            },
+            Biome::Tundra => Shape {
+                base: 0.05,
+                amplitude: 0.7,
+                roughness: 0.7,
+            },
+        }
+    }
+
+    /// Multiplier on how many plants grow in this biome.
+    fn vegetation(self) -> f64 {
+        match self {
+            Biome::Plains => 0.4,
+            Biome::Desert => 0.0,
+            Biome::Forest => 1.0,
+            Biome::Tundra => 0.15,
+        }
+    }
+
+    /// Colors from lowest to highest band.
+    fn palette(self) -> [U8Vec3; 4] {
+        match self {
+            Biome::Plains => [WATER_BLUE, GRASS_GREEN, MOUNTAIN_GRAY, SNOW_WHITE],
+            Biome::Desert => [
+                U8Vec3::new(40, 120, 170),
+                U8Vec3::new(220, 200, 130),
+                U8Vec3::new(200, 150, 90),
+                U8Vec3::new(170, 110, 70),
+            ],
+            Biome::Forest => [
+                U8Vec3::new(20, 70, 140),
+                U8Vec3::new(30, 110, 40),
+                U8Vec3::new(70, 90, 60),
+                U8Vec3::new(200, 210, 200),
+            ],
+            Biome::Tundra => [
+                U8Vec3::new(150, 190, 220),
+                U8Vec3::new(150, 160, 130),
+                U8Vec3::new(120, 120, 130),
+                SNOW_WHITE,
+            ],
+        }
+    }
+
+    /// Kinds of ground from lowest to highest band.
+    fn kinds(self) -> [VoxelKind; 4] {
+        match self {
+            Biome::Desert => [
+                VoxelKind::WATER,
+                VoxelKind::SAND,
+                VoxelKind::STONE,
+                VoxelKind::STONE,
+            ],
+            _ => BAND_KINDS,
+        }
+    }
+}
+
+/// Height curve of a biome, as fractions of the max height.
+#[derive(Clone, Copy, Debug)]
+struct Shape {
+    /// Lowest point of the terrain.
+    base: f64,
+    /// Range of heights above the base.
+    amplitude: f64,
+    /// Multiplier on the noise frequency.
+    roughness: f64,
+}
+
+/// Temperature and moisture noise used to place and blend biomes.
+#[derive(Clone)]
+pub struct Biomes {
+    temperature: Perlin,
+    moisture: Perlin,
+}
+
+impl Biomes {
+    pub fn new(seed: u32) -> Self {
+        Self {
+            temperature: Perlin::new(seed.wrapping_add(3)),
+            moisture: Perlin::new(seed.wrapping_add(4)),
+        }
+    }
+
+    /// Blend weight of each biome (in the order of `Biome::ALL`) at a column, summing to one.
+    pub fn weights(&self, x: i32, z: i32) -> [f64; 4] {
+        let p = [x as f64 / BIOME_SCALE, z as f64 / BIOME_SCALE];
+        let climate = DVec2::new(self.temperature.get(p), self.moisture.get(p));
+
+        let mut weights = Biome::ALL.map(|biome| {
+            let dist = climate.distance_squared(biome.climate());
+            (-dist / (BLEND_WIDTH * BLEND_WIDTH)).exp()
+        });
+
+        let total: f64 = weights.iter().sum();
+        weights.iter_mut().for_each(|w| *w /= total);
+        weights
+    }
+
+    /// Biome with the highest weight at a column.
+    pub fn biome(&self, x: i32, z: i32) -> Biome {
+        strongest(self.weights(x, z))
+    }
+
+    /// Blended plant density multiplier at a column.
+    pub fn vegetation(&self, x: i32, z: i32) -> f64 {
+        Biome::ALL
+            .iter()
+            .zip(self.weights(x, z))
+            .map(|(biome, w)| w * biome.vegetation())
+            .sum()
+    }
+
+    /// Calculates the blended terrain height and surface voxel of a column.
+    ///
+    /// `terrain` samples the height noise (-1 to 1) of the column with its frequency multiplied by a roughness.
+    pub fn column(
+        &self,
+        x: i32,
+        z: i32,
+        settings: &TerrainSettings,
+        terrain: impl Fn(f64) -> f64,
+    ) -> (i32, Voxel) {
+        let weights = self.weights(x, z);
+
+        let height: f64 = Biome::ALL
+            .iter()
+            .zip(weights)
+            .filter(|(_, w)| *w > 1e-4)
+            .map(|(biome, w)| {
+                let shape = biome.shape();
+                let noise_value = (terrain(shape.roughness) + 1.0) / 2.0;
+                w * (shape.base + shape.amplitude * noise_value)
+            })
+            .sum();
+
+        (
+            (height * settings.height as f64) as i32,
+            blend(weights, settings, height),
+        )
+    }
+
+    /// Calculates the blended surface voxel of a column with a height given as a fraction of the max height.
+    pub fn voxel(&self, x: i32, z: i32, settings: &TerrainSettings, normalized: f64) -> Voxel {
+        blend(self.weights(x, z), settings, normalized)
+    }
+}
+
+/// Blends the palette colors of each biome for a height given as a fraction of the max height.
+///
+/// Kinds can't be blended, so the voxel takes the kind of the strongest biome.
+fn blend(weights: [f64; 4], settings: &TerrainSettings, normalized: f64) -> Voxel {
+    let band = settings.band(normalized);
+    let color = Biome::ALL
+        .iter()
+        .zip(weights)
+        .map(|(biome, w)| w as f32 * biome.palette()[band].as_vec3a())
+        .sum::<Vec3A>()
+        .round()
+        .as_u8vec3();
+    Voxel::new(color, strongest(weights).kinds()[band])
+}
+
+/// Biome with the highest weight.
+fn strongest(weights: [f64; 4]) -> Biome {
+    let (idx, _) = weights
+        .iter()
+        .enumerate()
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .expect("there are biomes");
+    Biome::ALL[idx]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn weights_are_normalized() {
+        let biomes = Biomes::new(1);
+        for (x, z) in [(0, 0), (500, -300), (-1200, 40)] {
+            let total: f64 = biomes.weights(x, z).iter().sum();
+            assert!((total - 1.0).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn all_biomes_appear() {
+        let biomes = Biomes::new(1);
+        let found: HashSet<_> = (-40..40)
+            .flat_map(|x| (-40..40).map(move |z| (x * 100, z * 100)))
+            .map(|(x, z)| biomes.biome(x, z))
+            .collect();
+
+        assert_eq!(found.len(), Biome::ALL.len(), "found {found:?}");
+    }
+
+    #[test]
+    fn kind_of_strongest_biome() {
+        let biomes = Biomes::new(1);
+        let settings = TerrainSettings::default();
+        for x in -40..40 {
+            // grassy band, which is sand in the desert
+            let voxel = biomes.voxel(x * 100, 0, &settings, 0.5);
+            let kind = match biomes.biome(x * 100, 0) {
+                Biome::Desert => VoxelKind::SAND,
+                _ => VoxelKind::GRASS,
+            };
+            assert_eq!(voxel.kind, kind, "{x}");
+        }
+    }
+
+    #[test]
+    fn borders_are_smooth() {
+        let biomes = Biomes::new(1);
+        let terrain = Perlin::new(1);
+        let settings = TerrainSettings::default();
+        let scale = settings.scale();
+
+        // neighbouring columns should never jump by more than a few voxels
+        for x in -500..500 {
+            let noise = |x: i32| {
+                move |roughness| {
+                    terrain.get([x as f64 * scale * roughness, 7.0 * scale * roughness])
+                }
+            };
+            let (a, _) = biomes.column(x, 7, &settings, noise(x));
+            let (b, _) = biomes.column(x + 1, 7, &settings, noise(x + 1));
+            assert!((a - b).abs() <= 3, "jump of {} at x = {x}", (a - b).abs());
+        }
+    }
+}
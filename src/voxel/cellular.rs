@@ -0,0 +1,238 @@
+use std::ops::Range;
+
+use glam::{IVec3, U8Vec3, Vec3};
+
+use super::{
+    vegetation::{hash, unit},
+    Voxel, VoxelKind, VoxelSource,
+};
+
+const STONE_GRAY: U8Vec3 = U8Vec3::new(120, 115, 110);
+const DEEP_GRAY: U8Vec3 = U8Vec3::new(70, 65, 75);
+
+/// Settings for growing caves with a 3D cellular automaton.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CellularSettings {
+    /// Chance (0 to 1) that a cell starts out solid.
+    pub fill: f64,
+    /// Number of smoothing steps, more steps make rounder caves.
+    pub iterations: u32,
+    /// A cell becomes solid with more than this many solid neighbours (out of 26), and stays solid with exactly this many.
+    pub threshold: u32,
+}
+
+impl Default for CellularSettings {
+    fn default() -> Self {
+        Self {
+            fill: 0.5,
+            iterations: 5,
+            threshold: 13,
+        }
+    }
+}
+
+/// Caves grown in a box by smoothing random noise with a cellular automaton.
+///
+/// The whole box is simulated up front, so it has a very different occupancy from the heightfield terrain:
+/// solid almost everywhere, with winding tunnels and chambers inside that open up at the top. Everything outside the
+/// box is empty.
+#[derive(Clone, Debug)]
+pub struct CellularCaves {
+    min: IVec3,
+    size: IVec3,
+    solid: Vec<bool>,
+    seed: u32,
+}
+
+impl CellularCaves {
+    /// Grows caves in the box from `min` to `max` (exclusive).
+    pub fn new(seed: u32, min: IVec3, max: IVec3, settings: CellularSettings) -> Self {
+        let size = (max - min).max(IVec3::ZERO);
+        let mut caves = Self {
+            min,
+            size,
+            solid: Vec::with_capacity(size.element_product() as usize),
+            seed,
+        };
+
+        for y in 0..size.y {
+            for z in 0..size.z {
+                for x in 0..size.x {
+                    let roll = unit(hash(seed, x, z, y as u32));
+                    caves.solid.push(roll < settings.fill);
+                }
+            }
+        }
+        for _ in 0..settings.iterations {
+            caves.step(settings.threshold);
+        }
+        caves
+    }
+
+    /// Runs one step of the automaton.
+    ///
+    /// Cells below and beside the box count as solid so the caves are closed off there, while the open sky above
+    /// wears the top down into pits and shafts.
+    fn step(&mut self, threshold: u32) {
+        let size = self.size;
+        let len = self.solid.len();
+        let strides = [1, size.x as usize, (size.x * size.z) as usize];
+        let lens = [size.x, size.z, size.y];
+
+        // sum each 3x3x3 neighbourhood one axis at a time, so solid cells outside the box add 1, then 3, then 9
+        let mut sums: Vec<u32> = self.solid.iter().map(|&s| s as u32).collect();
+        let mut outside = 1;
+        for axis in 0..3 {
+            let stride = strides[axis];
+            let n = lens[axis] as usize;
+            let mut next = vec![0; len];
+            for (i, sum) in next.iter_mut().enumerate() {
+                let coord = i / stride % n;
+                let before = if coord > 0 { sums[i - stride] } else { outside };
+                let after = if coord + 1 < n {
+                    sums[i + stride]
+                } else if axis == 2 {
+                    0
+                } else {
+                    outside
+                };
+                *sum = before + sums[i] + after;
+            }
+            sums = next;
+            outside *= 3;
+        }
+
+        for (solid, sum) in self.solid.iter_mut().zip(sums) {
+            let neighbours = sum - *solid as u32;
+            *solid = neighbours > threshold || (*solid && neighbours == threshold);
+        }
+    }
+
+    fn index(&self, pos: IVec3) -> Option<usize> {
+        let local = pos - self.min;
+        if local.cmplt(IVec3::ZERO).any() || local.cmpge(self.size).any() {
+            return None;
+        }
+        Some((local.x + self.size.x * (local.z + self.size.z * local.y)) as usize)
+    }
+
+    /// Fraction of the box that is solid.
+    pub fn occupancy(&self) -> f64 {
+        self.solid.iter().filter(|&&s| s).count() as f64 / self.solid.len().max(1) as f64
+    }
+
+    /// Stone that darkens with depth, with some speckles so the walls have texture.
+    fn color(&self, pos: IVec3) -> U8Vec3 {
+        let height = (pos.y - self.min.y) as f32 / self.size.y.max(1) as f32;
+        let base = DEEP_GRAY.as_vec3().lerp(STONE_GRAY.as_vec3(), height);
+        let speckle = unit(hash(self.seed, pos.x, pos.z, pos.y as u32 ^ 0x5bd1)) as f32;
+        (base * (0.9 + 0.2 * speckle))
+            .min(Vec3::splat(255.0))
+            .as_u8vec3()
+    }
+}
+
+impl VoxelSource for CellularCaves {
+    fn lookup(&self, pos: IVec3) -> Option<Voxel> {
+        let i = self.index(pos)?;
+        self.solid[i].then(|| Voxel::new(self.color(pos), VoxelKind::STONE))
+    }
+
+    fn column(&self, x: i32, z: i32, ys: Range<i32>, out: &mut [Option<Voxel>]) {
+        debug_assert_eq!(out.len(), ys.len(), "column length mismatch");
+
+        let inside = (self.min.x..self.min.x + self.size.x).contains(&x)
+            && (self.min.z..self.min.z + self.size.z).contains(&z);
+        if !inside {
+            out.fill(None);
+            return;
+        }
+        for (y, voxel) in ys.zip(out) {
+            *voxel = self.lookup(IVec3::new(x, y, z));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn caves(settings: CellularSettings) -> CellularCaves {
+        CellularCaves::new(9, IVec3::splat(-16), IVec3::splat(16), settings)
+    }
+
+    #[test]
+    fn smoothing() {
+        let noise = caves(CellularSettings {
+            iterations: 0,
+            ..Default::default()
+        });
+        let smooth = caves(CellularSettings::default());
+        assert!((0.45..0.55).contains(&noise.occupancy()));
+        assert!(
+            (0.4..0.9).contains(&smooth.occupancy()),
+            "{}",
+            smooth.occupancy()
+        );
+
+        // smoothing joins cells into blobs, so far fewer cells differ from their neighbour
+        let changes = |caves: &CellularCaves| {
+            caves
+                .solid
+                .windows(2)
+                .filter(|pair| pair[0] != pair[1])
+                .count()
+        };
+        assert!(changes(&smooth) * 3 < changes(&noise));
+    }
+
+    #[test]
+    fn step_rule() {
+        let mut single = CellularCaves::new(
+            0,
+            IVec3::ZERO,
+            IVec3::splat(5),
+            CellularSettings {
+                fill: 0.0,
+                iterations: 0,
+                ..Default::default()
+            },
+        );
+        let middle = single.index(IVec3::splat(2)).unwrap();
+        single.solid[middle] = true;
+        single.step(13);
+        // an isolated cell in the middle dies
+        assert!(single.lookup(IVec3::splat(2)).is_none());
+
+        let mut walls = CellularCaves::new(
+            0,
+            IVec3::ZERO,
+            IVec3::splat(5),
+            CellularSettings {
+                fill: 0.0,
+                iterations: 0,
+                ..Default::default()
+            },
+        );
+        walls.step(13);
+        // cells outside count as solid, so only the edges (15 solid neighbours) and corners (19) fill in
+        assert!(walls.lookup(IVec3::ZERO).is_some());
+        assert!(walls.lookup(IVec3::new(0, 2, 2)).is_none());
+        assert!(walls.lookup(IVec3::new(0, 0, 2)).is_some());
+        // except at the top, which is open
+        assert!(walls.lookup(IVec3::new(0, 4, 0)).is_none());
+    }
+
+    #[test]
+    fn column_matches_lookup() {
+        let caves = caves(CellularSettings::default());
+        let ys = -20..20;
+        let mut column = vec![None; ys.len()];
+        for (x, z) in [(0, 0), (-16, 15), (15, -3), (16, 0), (-17, 4)] {
+            caves.column(x, z, ys.clone(), &mut column);
+            for (y, voxel) in ys.clone().zip(&column) {
+                assert_eq!(*voxel, caves.lookup(IVec3::new(x, y, z)), "{x} {y} {z}");
+            }
+        }
+    }
+}
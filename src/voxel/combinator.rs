@@ -0,0 +1,319 @@
+use std::ops::Range;
+
+use glam::IVec3;
+
+use super::{Voxel, VoxelSource};
+
+/// Adapters for composing voxel sources, e.g. terrain with a tunnel cut through it and a model placed on top.
+///
+/// Solid voxels keep the color of the source they came from.
+pub trait VoxelSourceExt: VoxelSource + Sized {
+    /// Voxels from either source, with this source's voxels covering the other's.
+    fn union<B: VoxelSource>(self, other: B) -> Union<Self, B> {
+        Union { a: self, b: other }
+    }
+
+    /// Voxels of this source where the other is also solid.
+    fn intersect<B: VoxelSource>(self, other: B) -> Intersection<Self, B> {
+        Intersection { a: self, b: other }
+    }
+
+    /// Voxels of this source where the other is empty.
+    fn subtract<B: VoxelSource>(self, other: B) -> Difference<Self, B> {
+        Difference { a: self, b: other }
+    }
+
+    /// Moves the source by an offset.
+    fn translate(self, offset: IVec3) -> Translate<Self> {
+        Translate {
+            source: self,
+            offset,
+        }
+    }
+
+    /// Rotates the source by quarter turns about the y-axis, counterclockwise when looking down.
+    fn rotate(self, turns: i32) -> Rotate<Self> {
+        Rotate {
+            source: self,
+            turns: turns.rem_euclid(4),
+        }
+    }
+
+    /// Raises each column of the source by a height given by its x and z.
+    fn offset_height<F: Fn(i32, i32) -> i32>(self, height: F) -> HeightOffset<Self, F> {
+        HeightOffset {
+            source: self,
+            height,
+        }
+    }
+
+    /// Keeps only the voxels at positions accepted by the mask.
+    fn mask<F: Fn(IVec3) -> bool>(self, mask: F) -> Mask<Self, F> {
+        Mask { source: self, mask }
+    }
+}
+
+impl<T: VoxelSource> VoxelSourceExt for T {}
+
+/// See [`VoxelSourceExt::union`].
+#[derive(Clone, Debug)]
+pub struct Union<A, B> {
+    a: A,
+    b: B,
+}
+
+impl<A: VoxelSource, B: VoxelSource> VoxelSource for Union<A, B> {
+    fn lookup(&self, pos: IVec3) -> Option<Voxel> {
+        self.a.lookup(pos).or_else(|| self.b.lookup(pos))
+    }
+
+    fn column(&self, x: i32, z: i32, ys: Range<i32>, out: &mut [Option<Voxel>]) {
+        self.a.column(x, z, ys.clone(), out);
+        if out.iter().any(Option::is_none) {
+            let mut other = vec![None; out.len()];
+            self.b.column(x, z, ys, &mut other);
+            for (voxel, other) in out.iter_mut().zip(other) {
+                *voxel = voxel.or(other);
+            }
+        }
+    }
+}
+
+/// See [`VoxelSourceExt::intersect`].
+#[derive(Clone, Debug)]
+pub struct Intersection<A, B> {
+    a: A,
+    b: B,
+}
+
+impl<A: VoxelSource, B: VoxelSource> VoxelSource for Intersection<A, B> {
+    fn lookup(&self, pos: IVec3) -> Option<Voxel> {
+        self.a.lookup(pos).filter(|_| self.b.lookup(pos).is_some())
+    }
+
+    fn column(&self, x: i32, z: i32, ys: Range<i32>, out: &mut [Option<Voxel>]) {
+        self.a.column(x, z, ys.clone(), out);
+        if out.iter().any(Option::is_some) {
+            let mut other = vec![None; out.len()];
+            self.b.column(x, z, ys, &mut other);
+            for (voxel, other) in out.iter_mut().zip(other) {
+                *voxel = voxel.filter(|_| other.is_some());
+            }
+        }
+    }
+}
+
+/// See [`VoxelSourceExt::subtract`].
+#[derive(Clone, Debug)]
+pub struct Difference<A, B> {
+    a: A,
+    b: B,
+}
+
+impl<A: VoxelSource, B: VoxelSource> VoxelSource for Difference<A, B> {
+    fn lookup(&self, pos: IVec3) -> Option<Voxel> {
+        self.a.lookup(pos).filter(|_| self.b.lookup(pos).is_none())
+    }
+
+    fn column(&self, x: i32, z: i32, ys: Range<i32>, out: &mut [Option<Voxel>]) {
+        self.a.column(x, z, ys.clone(), out);
+        if out.iter().any(Option::is_some) {
+            let mut other = vec![None; out.len()];
+            self.b.column(x, z, ys, &mut other);
+            for (voxel, other) in out.iter_mut().zip(other) {
+                *voxel = voxel.filter(|_| other.is_none());
+            }
+        }
+    }
+}
+
+/// See [`VoxelSourceExt::translate`].
+#[derive(Clone, Debug)]
+pub struct Translate<S> {
+    source: S,
+    offset: IVec3,
+}
+
+impl<S: VoxelSource> VoxelSource for Translate<S> {
+    fn lookup(&self, pos: IVec3) -> Option<Voxel> {
+        self.source.lookup(pos - self.offset)
+    }
+
+    fn column(&self, x: i32, z: i32, ys: Range<i32>, out: &mut [Option<Voxel>]) {
+        let o = self.offset;
+        self.source
+            .column(x - o.x, z - o.z, ys.start - o.y..ys.end - o.y, out);
+    }
+}
+
+/// See [`VoxelSourceExt::rotate`].
+#[derive(Clone, Debug)]
+pub struct Rotate<S> {
+    source: S,
+    /// Quarter turns, from 0 to 3.
+    turns: i32,
+}
+
+impl<S> Rotate<S> {
+    /// Column of the source that ends up at `(x, z)`.
+    ///
+    /// Voxels turn about the corner at the origin, so one turn takes the voxel at `(x, z)` to `(-z - 1, x)`.
+    fn source_column(&self, mut x: i32, mut z: i32) -> (i32, i32) {
+        for _ in 0..self.turns {
+            (x, z) = (z, -x - 1);
+        }
+        (x, z)
+    }
+}
+
+impl<S: VoxelSource> VoxelSource for Rotate<S> {
+    fn lookup(&self, pos: IVec3) -> Option<Voxel> {
+        let (x, z) = self.source_column(pos.x, pos.z);
+        self.source.lookup(IVec3::new(x, pos.y, z))
+    }
+
+    fn column(&self, x: i32, z: i32, ys: Range<i32>, out: &mut [Option<Voxel>]) {
+        let (x, z) = self.source_column(x, z);
+        self.source.column(x, z, ys, out);
+    }
+}
+
+/// See [`VoxelSourceExt::offset_height`].
+#[derive(Clone, Debug)]
+pub struct HeightOffset<S, F> {
+    source: S,
+    height: F,
+}
+
+impl<S: VoxelSource, F: Fn(i32, i32) -> i32> VoxelSource for HeightOffset<S, F> {
+    fn lookup(&self, pos: IVec3) -> Option<Voxel> {
+        let dy = (self.height)(pos.x, pos.z);
+        self.source.lookup(pos - IVec3::Y * dy)
+    }
+
+    fn column(&self, x: i32, z: i32, ys: Range<i32>, out: &mut [Option<Voxel>]) {
+        let dy = (self.height)(x, z);
+        self.source.column(x, z, ys.start - dy..ys.end - dy, out);
+    }
+}
+
+/// See [`VoxelSourceExt::mask`].
+#[derive(Clone, Debug)]
+pub struct Mask<S, F> {
+    source: S,
+    mask: F,
+}
+
+impl<S: VoxelSource, F: Fn(IVec3) -> bool> VoxelSource for Mask<S, F> {
+    fn lookup(&self, pos: IVec3) -> Option<Voxel> {
+        self.source.lookup(pos).filter(|_| (self.mask)(pos))
+    }
+
+    fn column(&self, x: i32, z: i32, ys: Range<i32>, out: &mut [Option<Voxel>]) {
+        self.source.column(x, z, ys.clone(), out);
+        for (y, voxel) in ys.zip(out) {
+            if voxel.is_some() && !(self.mask)(IVec3::new(x, y, z)) {
+                *voxel = None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{U8Vec3, Vec3A};
+
+    use super::*;
+    use crate::voxel::{
+        sdf::{Sdf, SdfSource},
+        VoxelGenerator,
+    };
+
+    fn cuboid(center: Vec3A, half_extents: Vec3A, color: U8Vec3) -> SdfSource {
+        SdfSource::new(Sdf::cuboid(center, half_extents).color(color))
+    }
+
+    /// Checks the column of a source against its lookups.
+    fn assert_columns_match(source: &impl VoxelSource) {
+        let ys = -20..20;
+        let mut column = vec![None; ys.len()];
+        for x in -12..12 {
+            for z in -12..12 {
+                source.column(x, z, ys.clone(), &mut column);
+                for (y, voxel) in ys.clone().zip(&column) {
+                    assert_eq!(*voxel, source.lookup(IVec3::new(x, y, z)), "{x} {y} {z}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn boolean_operators() {
+        let a = || cuboid(Vec3A::ZERO, Vec3A::splat(4.0), U8Vec3::X);
+        let b = || cuboid(Vec3A::X * 4.0, Vec3A::splat(4.0), U8Vec3::Y);
+        let inside_both = IVec3::new(2, 0, 0);
+        let only_a = IVec3::new(-3, 0, 0);
+        let only_b = IVec3::new(6, 0, 0);
+
+        let union = a().union(b());
+        assert_eq!(union.lookup(inside_both).unwrap().color, U8Vec3::X);
+        assert_eq!(union.lookup(only_b).unwrap().color, U8Vec3::Y);
+
+        let intersection = a().intersect(b());
+        assert_eq!(intersection.lookup(inside_both).unwrap().color, U8Vec3::X);
+        assert_eq!(intersection.lookup(only_a), None);
+        assert_eq!(intersection.lookup(only_b), None);
+
+        let difference = a().subtract(b());
+        assert_eq!(difference.lookup(inside_both), None);
+        assert!(difference.lookup(only_a).is_some());
+        assert_eq!(difference.lookup(only_b), None);
+
+        assert_columns_match(&union);
+        assert_columns_match(&intersection);
+        assert_columns_match(&difference);
+    }
+
+    #[test]
+    fn transforms() {
+        // a single voxel at (1, 0, 0)
+        let voxel = || cuboid(Vec3A::new(1.5, 0.5, 0.5), Vec3A::splat(0.5), U8Vec3::ONE);
+
+        let moved = voxel().translate(IVec3::new(2, 3, -1));
+        assert!(moved.lookup(IVec3::new(3, 3, -1)).is_some());
+        assert_eq!(moved.lookup(IVec3::new(1, 0, 0)), None);
+
+        for (turns, pos) in [
+            (1, IVec3::new(-1, 0, 1)),
+            (2, IVec3::new(-2, 0, -1)),
+            (3, IVec3::new(0, 0, -2)),
+            (4, IVec3::new(1, 0, 0)),
+            (-1, IVec3::new(0, 0, -2)),
+        ] {
+            assert!(voxel().rotate(turns).lookup(pos).is_some(), "{turns}");
+        }
+
+        let raised = voxel().offset_height(|x, _| x * 2);
+        assert!(raised.lookup(IVec3::new(1, 2, 0)).is_some());
+        assert_eq!(raised.lookup(IVec3::new(1, 0, 0)), None);
+
+        let masked = voxel().mask(|pos| pos.x < 1);
+        assert_eq!(masked.lookup(IVec3::new(1, 0, 0)), None);
+
+        assert_columns_match(&moved);
+        assert_columns_match(&voxel().rotate(3));
+        assert_columns_match(&raised);
+    }
+
+    #[test]
+    fn composed() {
+        let tunnel = SdfSource::new(Sdf::cuboid(Vec3A::ZERO, Vec3A::new(30.0, 3.0, 3.0)));
+        let tower = cuboid(Vec3A::ZERO, Vec3A::new(2.0, 6.0, 2.0), U8Vec3::ONE);
+        let world = VoxelGenerator::new_from_seed(0)
+            .subtract(tunnel)
+            .union(tower.translate(IVec3::new(5, 4, 5)).rotate(1))
+            .mask(|pos| pos.y > -15);
+
+        assert_columns_match(&world);
+    }
+}
@@ -0,0 +1,197 @@
+use glam::IVec3;
+
+use super::{Voxel, VoxelSource};
+
+/// A box of voxels stored in memory, used to hold imported models.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VoxelGrid {
+    size: IVec3,
+    /// Scene position of the grid's first voxel.
+    origin: IVec3,
+    voxels: Vec<Option<Voxel>>,
+}
+
+impl VoxelGrid {
+    /// Creates an empty grid with its first voxel at the scene origin.
+    pub fn new(size: IVec3) -> Self {
+        assert!(size.cmpge(IVec3::ZERO).all(), "grid size must be positive");
+        let len = Self::volume(size).expect("grid is too large");
+        Self {
+            size,
+            origin: IVec3::ZERO,
+            voxels: vec![None; len],
+        }
+    }
+
+    /// Number of voxels in a grid of a size, or `None` if a side is negative or the count doesn't fit in a `usize`.
+    pub fn volume(size: IVec3) -> Option<usize> {
+        let [x, y, z] = size.to_array().map(|side| usize::try_from(side).ok());
+        x?.checked_mul(y?)?.checked_mul(z?)
+    }
+
+    /// Dimensions of the grid in voxels.
+    pub fn size(&self) -> IVec3 {
+        self.size
+    }
+
+    /// Scene position of the grid's first voxel.
+    pub fn origin(&self) -> IVec3 {
+        self.origin
+    }
+
+    /// Moves the grid so its first voxel is at a scene position.
+    pub fn with_origin(mut self, origin: IVec3) -> Self {
+        self.origin = origin;
+        self
+    }
+
+    /// Moves the grid so it sits on the y = 0 plane centered on the y-axis.
+    pub fn centered(self) -> Self {
+        self.centered_on(IVec3::ZERO)
+    }
+
+    /// Moves the grid so the middle of its base is at a scene position, such as the middle of the scene's bounds.
+    pub fn centered_on(mut self, pos: IVec3) -> Self {
+        self.origin = pos - IVec3::new(self.size.x / 2, 0, self.size.z / 2);
+        self
+    }
+
+    /// Shrinks the grid to the bounding box of its solid voxels.
+    pub fn cropped(self) -> Self {
+        let mut min = self.size;
+        let mut max = IVec3::ZERO;
+        for y in 0..self.size.y {
+            for z in 0..self.size.z {
+                for x in 0..self.size.x {
+                    let pos = IVec3::new(x, y, z);
+                    if self.get(pos).is_some() {
+                        min = min.min(pos);
+                        max = max.max(pos + 1);
+                    }
+                }
+            }
+        }
+
+        let mut grid = Self::new((max - min).max(IVec3::ZERO));
+        grid.origin = self.origin + min.min(max);
+        for y in 0..grid.size.y {
+            for z in 0..grid.size.z {
+                for x in 0..grid.size.x {
+                    let pos = IVec3::new(x, y, z);
+                    grid.set(pos, self.get(min + pos));
+                }
+            }
+        }
+        grid
+    }
+
+    /// Number of solid voxels.
+    pub fn count(&self) -> usize {
+        self.voxels.iter().filter(|v| v.is_some()).count()
+    }
+
+    fn index(&self, local: IVec3) -> Option<usize> {
+        if local.cmplt(IVec3::ZERO).any() || local.cmpge(self.size).any() {
+            return None;
+        }
+        let [x, y, z] = local.to_array().map(|v| v as usize);
+        let [width, _, length] = self.size.to_array().map(|v| v as usize);
+        Some(x + width * (z + length * y))
+    }
+
+    /// Gets a voxel by its position within the grid.
+    pub fn get(&self, local: IVec3) -> Option<Voxel> {
+        self.index(local).and_then(|i| self.voxels[i])
+    }
+
+    /// Sets a voxel by its position within the grid, ignoring positions outside it.
+    pub fn set(&mut self, local: IVec3, voxel: Option<Voxel>) {
+        if let Some(i) = self.index(local) {
+            self.voxels[i] = voxel;
+        }
+    }
+}
+
+impl VoxelSource for VoxelGrid {
+    fn lookup(&self, pos: IVec3) -> Option<Voxel> {
+        self.get(pos - self.origin)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::U8Vec3;
+
+    use super::*;
+    use crate::ray_tracer::Config;
+
+    #[test]
+    fn set_and_lookup() {
+        let voxel = Voxel::from(U8Vec3::ONE);
+        let mut grid = VoxelGrid::new(IVec3::new(4, 2, 6));
+        grid.set(IVec3::new(3, 1, 5), Some(voxel));
+        grid.set(IVec3::new(4, 0, 0), Some(voxel));
+
+        assert_eq!(grid.count(), 1);
+        assert_eq!(grid.get(IVec3::new(3, 1, 5)), Some(voxel));
+        assert_eq!(grid.lookup(IVec3::new(3, 1, 5)), Some(voxel));
+
+        let grid = grid.centered();
+        assert_eq!(grid.lookup(IVec3::new(1, 1, 2)), Some(voxel));
+        assert_eq!(grid.lookup(IVec3::new(3, 1, 5)), None);
+    }
+
+    #[test]
+    fn volume() {
+        assert_eq!(VoxelGrid::volume(IVec3::new(4, 2, 6)), Some(48));
+        assert_eq!(VoxelGrid::volume(IVec3::new(4, 0, 6)), Some(0));
+        assert_eq!(VoxelGrid::volume(IVec3::new(4, -2, 6)), None);
+        assert_eq!(VoxelGrid::volume(IVec3::splat(i32::MAX)), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "grid is too large")]
+    fn too_large() {
+        VoxelGrid::new(IVec3::splat(i32::MAX));
+    }
+
+    #[test]
+    fn centered_in_default_bounds() {
+        // as large as a model can be in each direction for the scene size
+        let config = Config {
+            size: 64,
+            ..Default::default()
+        };
+        let bounds = config.bounds();
+        let grid = VoxelGrid::new(IVec3::new(128, 64, 127)).centered_on(bounds.origin);
+
+        let (min, max) = (grid.origin(), grid.origin() + grid.size());
+        assert!(min.cmpge(bounds.min()).all() && max.cmple(bounds.max()).all());
+        assert!(bounds.contains(min) && bounds.contains(max - 1));
+    }
+
+    #[test]
+    fn cropped() {
+        let voxel = Voxel::from(U8Vec3::ONE);
+        let mut grid = VoxelGrid::new(IVec3::splat(8));
+        grid.set(IVec3::new(2, 3, 4), Some(voxel));
+        grid.set(IVec3::new(5, 3, 6), Some(voxel));
+
+        let cropped = grid.clone().cropped();
+        assert_eq!(cropped.size(), IVec3::new(4, 1, 3));
+        assert_eq!(cropped.count(), 2);
+        assert_eq!(cropped.get(IVec3::ZERO), Some(voxel));
+
+        // voxels stay at the same scene position
+        for pos in [
+            IVec3::new(2, 3, 4),
+            IVec3::new(5, 3, 6),
+            IVec3::new(3, 3, 4),
+        ] {
+            assert_eq!(cropped.lookup(pos), grid.lookup(pos));
+        }
+
+        let empty = VoxelGrid::new(IVec3::splat(4)).cropped();
+        assert_eq!(empty.size(), IVec3::ZERO);
+    }
+}
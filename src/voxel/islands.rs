@@ -0,0 +1,287 @@
+use std::ops::Range;
+
+use glam::{DVec3, IVec3, U8Vec3};
+use noise::{NoiseFn, Perlin};
+
+use super::{Voxel, VoxelKind, VoxelSource, GRASS_GREEN, MOUNTAIN_GRAY};
+
+const GRASS: Voxel = Voxel::new(GRASS_GREEN, VoxelKind::GRASS);
+const DIRT: Voxel = Voxel::new(U8Vec3::new(125, 90, 55), VoxelKind::DIRT);
+const STONE: Voxel = Voxel::new(MOUNTAIN_GRAY, VoxelKind::STONE);
+
+/// Layers of dirt between the grass and the rock.
+const DIRT_DEPTH: i32 = 3;
+
+/// Settings for masses of land floating in the sky.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct IslandSettings {
+    /// Height of the island tops in voxels.
+    pub altitude: f64,
+    /// How far the tops rise above and dip below `altitude`.
+    pub relief: f64,
+    /// How far the undersides hang below `altitude`.
+    pub depth: f64,
+    /// Rough width of an island in voxels.
+    pub scale: f64,
+    /// Fraction (0 to 1) of the sky covered by islands.
+    pub coverage: f64,
+}
+
+impl Default for IslandSettings {
+    fn default() -> Self {
+        Self {
+            altitude: 0.0,
+            relief: 12.0,
+            depth: 60.0,
+            scale: 60.0,
+            coverage: 0.4,
+        }
+    }
+}
+
+impl IslandSettings {
+    /// Islands for a scene reaching `extent` voxels above and below a height, with all of the heights that can hold
+    /// land centered on it, the tops above it and the undersides hanging below.
+    pub fn around(middle: i32, extent: f64) -> Self {
+        let (relief, depth) = (extent * 0.1, extent * 0.4);
+        Self {
+            // halfway between the lowest and highest land, see `Islands::heights`
+            altitude: middle as f64 + (depth - relief) * 2.5f64.sqrt() / 2.0,
+            relief,
+            depth,
+            scale: extent * 0.4,
+            ..Default::default()
+        }
+    }
+}
+
+/// Floating islands carved from 3D noise.
+///
+/// A 2D noise field decides where the islands are, and 3D noise roughens their shape. The land fades out quickly
+/// above the island tops and slowly below them, so each island has a flat top and a long, craggy underside.
+#[derive(Clone)]
+pub struct Islands {
+    settings: IslandSettings,
+    mask_noise: Perlin,
+    shape_noise: Perlin,
+}
+
+impl Islands {
+    pub fn new(seed: u32, settings: IslandSettings) -> Self {
+        Self {
+            settings,
+            mask_noise: Perlin::new(seed),
+            shape_noise: Perlin::new(seed.wrapping_add(1)),
+        }
+    }
+
+    /// Density of the land at a position, positive inside an island.
+    fn density(&self, pos: IVec3) -> f64 {
+        let IslandSettings {
+            altitude,
+            relief,
+            depth,
+            scale,
+            coverage,
+        } = self.settings;
+        let p = pos.as_dvec3() / scale;
+
+        // islands are where the mask noise is in the top `coverage` part of its range
+        let mask = self.mask_noise.get([p.x, p.z]) + 1.0 - 2.0 * (1.0 - coverage);
+        let shape = self
+            .shape_noise
+            .get((p * DVec3::new(2.0, 1.0, 2.0)).to_array());
+
+        // thin out towards the top of the relief, and taper down to a point below
+        let above = pos.y as f64 - altitude;
+        let falloff = if above > 0.0 {
+            above / relief
+        } else {
+            -above / depth
+        };
+        mask + 0.5 * shape - falloff * falloff
+    }
+
+    /// Whether there is land at a position.
+    fn solid(&self, pos: IVec3) -> bool {
+        self.heights().contains(&pos.y) && self.density(pos) > 0.0
+    }
+
+    /// Range of heights that can hold land.
+    fn heights(&self) -> Range<i32> {
+        let IslandSettings {
+            altitude,
+            relief,
+            depth,
+            ..
+        } = self.settings;
+        // past these the falloff outweighs the largest possible mask and shape noise
+        let low = altitude - depth * 2.5f64.sqrt();
+        let high = altitude + relief * 2.5f64.sqrt();
+        low.floor() as i32..high.ceil() as i32 + 1
+    }
+}
+
+/// Picks a solid voxel by how many solid voxels are above it.
+fn voxel(below_surface: i32) -> Voxel {
+    match below_surface {
+        0 => GRASS,
+        d if d <= DIRT_DEPTH => DIRT,
+        _ => STONE,
+    }
+}
+
+impl VoxelSource for Islands {
+    fn lookup(&self, pos: IVec3) -> Option<Voxel> {
+        if !self.solid(pos) {
+            return None;
+        }
+        let below_surface = (1..=DIRT_DEPTH + 1)
+            .take_while(|&d| self.solid(pos + IVec3::Y * d))
+            .count() as i32;
+        Some(voxel(below_surface))
+    }
+
+    fn column(&self, x: i32, z: i32, ys: Range<i32>, out: &mut [Option<Voxel>]) {
+        debug_assert_eq!(out.len(), ys.len(), "column length mismatch");
+        out.fill(None);
+
+        // walk down from the top so the depth below the surface is counted along the way
+        let heights = self.heights();
+        let top = ys.end.min(heights.end);
+        let bottom = ys.start.max(heights.start);
+        let mut below_surface = 0;
+        for y in (bottom..top + DIRT_DEPTH + 1).rev() {
+            if !self.solid(IVec3::new(x, y, z)) {
+                below_surface = 0;
+                continue;
+            }
+            if y < top {
+                out[(y - ys.start) as usize] = Some(voxel(below_surface));
+            }
+            below_surface += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ray_tracer::{types::IAabb, Config};
+
+    fn islands() -> Islands {
+        Islands::new(
+            3,
+            IslandSettings {
+                relief: 6.0,
+                depth: 24.0,
+                scale: 24.0,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Fraction of the voxels in a box around the islands that are solid.
+    fn occupancy(islands: &Islands) -> f64 {
+        let mut solid = 0;
+        let mut total = 0;
+        for x in -48..48 {
+            for z in -48..48 {
+                for y in -48..16 {
+                    solid += islands.lookup(IVec3::new(x, y, z)).is_some() as usize;
+                    total += 1;
+                }
+            }
+        }
+        solid as f64 / total as f64
+    }
+
+    #[test]
+    fn floating() {
+        let islands = islands();
+        let occupancy = occupancy(&islands);
+        assert!((0.01..0.3).contains(&occupancy), "{occupancy}");
+
+        // nothing outside the band of heights, and there are open columns between the islands
+        let heights = islands.heights();
+        let mut open = 0;
+        for x in -48..48 {
+            assert!(islands.lookup(IVec3::new(x, heights.end, 0)).is_none());
+            assert!(islands
+                .lookup(IVec3::new(x, heights.start - 1, 0))
+                .is_none());
+            open +=
+                (heights.clone()).all(|y| islands.lookup(IVec3::new(x, y, 7)).is_none()) as usize;
+        }
+        assert!(open > 0);
+    }
+
+    #[test]
+    fn inside_the_scene() {
+        let default = Config {
+            size: 200,
+            ..Default::default()
+        }
+        .bounds();
+        // a scene sitting on the ground, like a Minecraft world
+        let raised = IAabb::from_corners(IVec3::new(-100, 0, -100), IVec3::new(99, 63, 99));
+        for bounds in [default, raised] {
+            let extent = bounds.extents.y;
+            let islands = Islands::new(3, IslandSettings::around(bounds.origin.y, extent as f64));
+            let heights = islands.heights();
+            assert!(
+                heights.start >= bounds.min().y && heights.end <= bounds.max().y,
+                "{heights:?}"
+            );
+            // centered in the scene, filling most of its height
+            assert!((heights.start + heights.end - 2 * bounds.origin.y).abs() <= 2);
+            assert!(heights.len() as i32 > extent * 3 / 4, "{heights:?}");
+        }
+    }
+
+    #[test]
+    fn hanging_undersides() {
+        let islands = islands();
+        // land reaches much further below the tops than above them
+        let mut lowest = i32::MAX;
+        let mut highest = i32::MIN;
+        for x in -48..48 {
+            for z in -48..48 {
+                for y in -60..20 {
+                    if islands.lookup(IVec3::new(x, y, z)).is_some() {
+                        lowest = lowest.min(y);
+                        highest = highest.max(y);
+                    }
+                }
+            }
+        }
+        assert!(-lowest > highest * 2, "{lowest} {highest}");
+    }
+
+    #[test]
+    fn column_matches_lookup() {
+        let islands = islands();
+        let mut grass = 0;
+        let ys = -50..20;
+        let mut column = vec![None; ys.len()];
+        for x in -20..20 {
+            let z = x * 3 % 17;
+            islands.column(x, z, ys.clone(), &mut column);
+            for (y, voxel) in ys.clone().zip(&column) {
+                assert_eq!(*voxel, islands.lookup(IVec3::new(x, y, z)), "{x} {y} {z}");
+                grass += (*voxel == Some(GRASS)) as usize;
+            }
+        }
+        assert!(grass > 0);
+
+        // a column cut off in the middle of an island
+        let ys = -3..1;
+        let mut column = vec![None; ys.len()];
+        for x in -20..20 {
+            islands.column(x, 0, ys.clone(), &mut column);
+            for (y, voxel) in ys.clone().zip(&column) {
+                assert_eq!(*voxel, islands.lookup(IVec3::new(x, y, 0)), "{x} {y}");
+            }
+        }
+    }
+}
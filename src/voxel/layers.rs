@@ -0,0 +1,195 @@
+use std::ops::Range;
+
+use glam::IVec3;
+
+use super::{Voxel, VoxelSource};
+
+/// How a layer of a [`Layers`] stack changes the voxels of the layers below it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Blend {
+    /// Solid voxels cover the ones below, and empty space leaves them showing.
+    #[default]
+    Cover,
+    /// Every position from `min` up to but not including `max` takes the layer's voxel, so empty space clears
+    /// whatever was below, e.g. the ground inside a building.
+    Replace { min: IVec3, max: IVec3 },
+    /// Solid voxels clear the ones below instead of being drawn, e.g. a script digging tunnels.
+    Carve,
+}
+
+impl Blend {
+    /// Whether the layer decides the voxels of a column, or leaves every voxel in it as it was.
+    fn touches(&self, x: i32, z: i32) -> bool {
+        match self {
+            Blend::Replace { min, max } => {
+                (min.x..max.x).contains(&x) && (min.z..max.z).contains(&z)
+            }
+            Blend::Cover | Blend::Carve => true,
+        }
+    }
+
+    /// Voxel at a position, from the one below it and the layer's own.
+    fn apply(&self, pos: IVec3, below: Option<Voxel>, layer: Option<Voxel>) -> Option<Voxel> {
+        match self {
+            Blend::Cover => layer.or(below),
+            Blend::Replace { min, max } if pos.cmpge(*min).all() && pos.cmplt(*max).all() => layer,
+            Blend::Replace { .. } => below,
+            Blend::Carve => below.filter(|_| layer.is_none()),
+        }
+    }
+}
+
+/// Sources stacked on top of each other, such as terrain, then an imported building, then scripted edits.
+///
+/// Each position is resolved from the bottom layer up, and every layer changes the voxel the ones below left there
+/// by its [`Blend`]. Like any source the stack is only looked up while a scene is built, so layers are never
+/// collected into memory of their own.
+pub struct Layers {
+    base: Box<dyn VoxelSource>,
+    layers: Vec<(Box<dyn VoxelSource>, Blend)>,
+}
+
+impl Layers {
+    /// Starts a stack with a source at the bottom.
+    pub fn new(base: impl VoxelSource + 'static) -> Self {
+        Self {
+            base: Box::new(base),
+            layers: Vec::new(),
+        }
+    }
+
+    /// Adds a layer on top of the stack.
+    pub fn with_layer(mut self, source: impl VoxelSource + 'static, blend: Blend) -> Self {
+        self.push(Box::new(source), blend);
+        self
+    }
+
+    /// Adds a layer on top of the stack.
+    pub fn push(&mut self, source: Box<dyn VoxelSource>, blend: Blend) {
+        self.layers.push((source, blend));
+    }
+
+    /// Number of layers above the bottom one.
+    pub fn len(&self) -> usize {
+        self.layers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty()
+    }
+}
+
+impl VoxelSource for Layers {
+    fn lookup(&self, pos: IVec3) -> Option<Voxel> {
+        self.layers
+            .iter()
+            .filter(|(_, blend)| blend.touches(pos.x, pos.z))
+            .fold(self.base.lookup(pos), |below, (source, blend)| {
+                blend.apply(pos, below, source.lookup(pos))
+            })
+    }
+
+    fn column(&self, x: i32, z: i32, ys: Range<i32>, out: &mut [Option<Voxel>]) {
+        self.base.column(x, z, ys.clone(), out);
+
+        let mut layer = vec![None; out.len()];
+        for (source, blend) in &self.layers {
+            if !blend.touches(x, z) {
+                continue;
+            }
+            source.column(x, z, ys.clone(), &mut layer);
+            for ((y, voxel), layer) in ys.clone().zip(out.iter_mut()).zip(&layer) {
+                *voxel = blend.apply(IVec3::new(x, y, z), *voxel, *layer);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{U8Vec3, Vec3A};
+
+    use super::*;
+    use crate::voxel::{
+        grid::VoxelGrid,
+        sdf::{Sdf, SdfSource},
+        VoxelGenerator,
+    };
+
+    fn cuboid(center: Vec3A, half_extents: Vec3A, color: U8Vec3) -> SdfSource {
+        SdfSource::new(Sdf::cuboid(center, half_extents).color(color))
+    }
+
+    #[test]
+    fn later_layers_win() {
+        let red = U8Vec3::new(255, 0, 0);
+        let blue = U8Vec3::new(0, 0, 255);
+        // a slab of ground up to y = 0, a red wall on it, and a hollow box with a blue floor
+        let ground = cuboid(
+            Vec3A::new(0.0, -4.0, 0.0),
+            Vec3A::new(20.0, 4.0, 20.0),
+            U8Vec3::ONE,
+        );
+        let wall = cuboid(Vec3A::new(0.0, 0.0, 0.0), Vec3A::new(1.0, 6.0, 10.0), red);
+        let mut room = VoxelGrid::new(IVec3::new(4, 4, 4));
+        for x in 0..4 {
+            for z in 0..4 {
+                room.set(IVec3::new(x, 0, z), Some(Voxel::from(blue)));
+            }
+        }
+        let room = room.with_origin(IVec3::new(4, -2, 4));
+
+        let layers = Layers::new(ground)
+            .with_layer(wall, Blend::Cover)
+            .with_layer(
+                room.clone(),
+                Blend::Replace {
+                    min: room.origin(),
+                    max: room.origin() + room.size(),
+                },
+            )
+            .with_layer(
+                cuboid(Vec3A::new(-10.0, 0.0, -10.0), Vec3A::splat(3.0), red),
+                Blend::Carve,
+            );
+        assert_eq!(layers.len(), 3);
+
+        let color = |pos| layers.lookup(pos).map(|voxel| voxel.color);
+        // the wall covers the ground and the air above it
+        assert_eq!(color(IVec3::new(0, -1, 0)), Some(red));
+        assert_eq!(color(IVec3::new(0, 3, 0)), Some(red));
+        assert_eq!(color(IVec3::new(5, -1, 0)), Some(U8Vec3::ONE));
+        // the room clears the ground inside its box and lays its floor
+        assert_eq!(color(IVec3::new(5, -2, 5)), Some(blue));
+        assert_eq!(color(IVec3::new(5, -1, 5)), None);
+        assert_eq!(color(IVec3::new(5, -3, 5)), Some(U8Vec3::ONE));
+        // and the carved box leaves a hole in the ground without drawing anything
+        assert_eq!(color(IVec3::new(-10, -1, -10)), None);
+        assert_eq!(color(IVec3::new(-10, 1, -10)), None);
+        assert_eq!(color(IVec3::new(-10, -5, -10)), Some(U8Vec3::ONE));
+
+        let ys = -10..10;
+        let mut column = vec![None; ys.len()];
+        for x in -14..10 {
+            for z in -14..10 {
+                layers.column(x, z, ys.clone(), &mut column);
+                for (y, voxel) in ys.clone().zip(&column) {
+                    assert_eq!(*voxel, layers.lookup(IVec3::new(x, y, z)), "{x} {y} {z}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn base_alone() {
+        let layers = Layers::new(VoxelGenerator::new_from_seed(2));
+        let generator = VoxelGenerator::new_from_seed(2);
+        assert!(layers.is_empty());
+        for x in -5..5 {
+            for y in -20..40 {
+                let pos = IVec3::new(x, y, 3);
+                assert_eq!(layers.lookup(pos), generator.lookup(pos));
+            }
+        }
+    }
+}
@@ -1,91 +1,784 @@
-use glam::{IVec3, U8Vec3};
-use noise::{NoiseFn, Perlin};
+use std::{fmt, ops::Range, sync::OnceLock};
+
+use glam::{DVec2, IVec2, IVec3, U8Vec3};
+use noise::{NoiseFn, Perlin, Seedable};
 use rand::Rng;
 
+use biome::Biomes;
+use erosion::{ErosionSettings, Heightfield};
+use ore::{OreSettings, Ores};
+use structure::{Prefab, Structure, StructureSettings, Structures};
+use vegetation::{Plant, Vegetation, VegetationSettings};
+use water::{Water, WaterSettings, WATER};
+
+pub mod biome;
+pub mod cellular;
+pub mod combinator;
+pub mod erosion;
+pub mod grid;
+pub mod islands;
+pub mod layers;
+pub mod ore;
+pub mod planet;
+#[cfg(feature = "wasm")]
+pub mod plugin;
+#[cfg(feature = "scripting")]
+pub mod script;
+pub mod sdf;
+pub mod snow;
+pub mod structure;
+pub mod vegetation;
+pub mod water;
+pub mod wfc;
+
+/// What a voxel is made of, so materials, exporters and queries can tell voxels apart beyond their color.
+///
+/// The built-in kinds are constants, other ids are free for scripts to use.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub struct VoxelKind(pub u16);
+
+impl VoxelKind {
+    /// A voxel whose material is not known, like those of imported meshes.
+    pub const UNKNOWN: Self = Self(0);
+    pub const STONE: Self = Self(1);
+    pub const DIRT: Self = Self(2);
+    pub const GRASS: Self = Self(3);
+    pub const SAND: Self = Self(4);
+    pub const SNOW: Self = Self(5);
+    pub const WATER: Self = Self(6);
+    /// Tree trunks and logs.
+    pub const WOOD: Self = Self(7);
+    pub const LEAVES: Self = Self(8);
+    /// Sawn wood in buildings.
+    pub const PLANKS: Self = Self(9);
+    /// Roof tiles.
+    pub const TILE: Self = Self(10);
+    pub const GRAVEL: Self = Self(11);
+    /// Iron ore, which shines like metal where shaded.
+    pub const IRON: Self = Self(12);
+    /// Gold ore, which shines like metal where shaded.
+    pub const GOLD: Self = Self(13);
+    /// Crystals, which glow like lava, only more dimly.
+    pub const CRYSTAL: Self = Self(14);
+    /// Molten rock, which glows, lighting what is around it in path-traced renders.
+    pub const LAVA: Self = Self(15);
+    /// Windows and panes, which let almost all light through.
+    pub const GLASS: Self = Self(16);
+
+    /// Share of the light going through a voxel's width of this kind that it stops, from 0 to 1, where renders see
+    /// through voxels that let light through, see
+    /// [`Config::transparency`](crate::ray_tracer::Config::transparency).
+    pub fn opacity(self) -> f32 {
+        match self {
+            Self::WATER => 0.3,
+            Self::LEAVES => 0.6,
+            Self::GLASS => 0.1,
+            _ => 1.0,
+        }
+    }
+
+    /// Share of the light falling on a voxel of this kind that it reflects like a mirror, tinted by its color, from
+    /// 0 for most kinds to 1, see [`Config::shade`](crate::ray_tracer::Config::shade).
+    pub fn shine(self) -> f32 {
+        match self {
+            Self::IRON => 0.4,
+            Self::GOLD => 0.8,
+            _ => 0.0,
+        }
+    }
+}
+
+impl fmt::Display for VoxelKind {
+    /// Lower case name of a built-in kind, or its id for the others.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match *self {
+            Self::UNKNOWN => "unknown",
+            Self::STONE => "stone",
+            Self::DIRT => "dirt",
+            Self::GRASS => "grass",
+            Self::SAND => "sand",
+            Self::SNOW => "snow",
+            Self::WATER => "water",
+            Self::WOOD => "wood",
+            Self::LEAVES => "leaves",
+            Self::PLANKS => "planks",
+            Self::TILE => "tile",
+            Self::GRAVEL => "gravel",
+            Self::IRON => "iron",
+            Self::GOLD => "gold",
+            Self::CRYSTAL => "crystal",
+            Self::LAVA => "lava",
+            Self::GLASS => "glass",
+            Self(id) => return write!(f, "kind {id}"),
+        };
+        f.write_str(name)
+    }
+}
+
 /// Data associated with a single voxel.
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub struct Voxel {
     pub color: U8Vec3,
+    pub kind: VoxelKind,
+}
+
+impl Voxel {
+    pub const fn new(color: U8Vec3, kind: VoxelKind) -> Self {
+        Self { color, kind }
+    }
+}
+
+impl From<U8Vec3> for Voxel {
+    /// A voxel of unknown kind.
+    fn from(color: U8Vec3) -> Self {
+        Self::new(color, VoxelKind::UNKNOWN)
+    }
+}
+
+/// A source of voxel data that storage backends are built from.
+///
+/// Only `lookup` is required, the bulk queries can be overridden when a source can answer them faster.
+pub trait VoxelSource {
+    /// Lookup a voxel value at some position.
+    fn lookup(&self, pos: IVec3) -> Option<Voxel>;
+
+    /// Lookup a column of voxels along the y-axis, writing the voxel at `y` into `out[y - ys.start]`.
+    ///
+    /// `out` must be the same length as `ys`.
+    fn column(&self, x: i32, z: i32, ys: Range<i32>, out: &mut [Option<Voxel>]) {
+        debug_assert_eq!(out.len(), ys.len(), "column length mismatch");
+
+        for (y, voxel) in ys.zip(out) {
+            *voxel = self.lookup(IVec3::new(x, y, z));
+        }
+    }
+}
+
+impl<T: VoxelSource + ?Sized> VoxelSource for &T {
+    fn lookup(&self, pos: IVec3) -> Option<Voxel> {
+        (**self).lookup(pos)
+    }
+
+    fn column(&self, x: i32, z: i32, ys: Range<i32>, out: &mut [Option<Voxel>]) {
+        (**self).column(x, z, ys, out)
+    }
+}
+
+impl<T: VoxelSource + ?Sized> VoxelSource for Box<T> {
+    fn lookup(&self, pos: IVec3) -> Option<Voxel> {
+        (**self).lookup(pos)
+    }
+
+    fn column(&self, x: i32, z: i32, ys: Range<i32>, out: &mut [Option<Voxel>]) {
+        (**self).column(x, z, ys, out)
+    }
 }
 
 /// A generator that produces voxels with y coordinate calculated by Perlin noise function mapped over x and z coordinates, and voxel color is mapped from max voxel height at its x and z coordinate
 #[derive(Clone)]
 pub struct VoxelGenerator {
     perlin: Perlin,
+    caves: Option<Caves>,
+    biomes: Option<Biomes>,
+    vegetation: Option<Vegetation>,
+    water: Option<Water>,
+    warp: Option<Warp>,
+    fbm: FbmSettings,
+    terrain: TerrainSettings,
+    erosion: Option<Erosion>,
+    structures: Option<Structures>,
+    ores: Option<Ores>,
+    height_cache: Option<HeightCache>,
+}
+
+/// Settings for carving caves and overhangs out of the terrain with 3D noise.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CaveSettings {
+    /// Voxels where the cave noise is above this are carved out (-1 to 1, lower values carve more).
+    pub threshold: f64,
+    /// Rough size of caves and overhangs in voxels.
+    pub scale: f64,
+    /// Maximum distance the surface is pushed in or out to form overhangs and arches.
+    pub overhang: f64,
+    /// Height up to which caves are flooded with [`LAVA`], 0 for none.
+    pub lava_level: i32,
+}
+
+impl Default for CaveSettings {
+    fn default() -> Self {
+        Self {
+            threshold: 0.3,
+            scale: 20.0,
+            overhang: 8.0,
+            lava_level: 0,
+        }
+    }
+}
+
+/// Settings for layering octaves of the height noise as fractal Brownian motion.
+///
+/// Each octave adds finer detail on top of the previous ones. A single octave is plain Perlin noise.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FbmSettings {
+    /// Number of noise layers.
+    pub octaves: u32,
+    /// Frequency multiplier from one octave to the next.
+    pub lacunarity: f64,
+    /// Amplitude multiplier from one octave to the next (below 1 keeps finer octaves smaller).
+    pub persistence: f64,
+}
+
+impl Default for FbmSettings {
+    fn default() -> Self {
+        Self {
+            octaves: 1,
+            lacunarity: 2.0,
+            persistence: 0.5,
+        }
+    }
+}
+
+/// Offset between the sample positions of successive octaves, so they don't all line up at the origin.
+const OCTAVE_OFFSET: DVec2 = DVec2::new(17.31, -43.87);
+
+/// Settings for domain warping, which offsets where the height noise is sampled by more noise.
+///
+/// Warping twists the rounded hills of plain Perlin noise into winding ridges and valleys.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WarpSettings {
+    /// Maximum distance in voxels that sample positions are pushed (0 disables warping).
+    pub strength: f64,
+    /// Rough size of the warped features in voxels.
+    pub scale: f64,
+}
+
+impl Default for WarpSettings {
+    fn default() -> Self {
+        Self {
+            strength: 40.0,
+            scale: 80.0,
+        }
+    }
 }
 
-/// Max height of the voxel
-const HEIGHT: i32 = 100;
+/// 2D noise fields that push the height noise's sample positions along x and z.
+#[derive(Clone)]
+struct Warp {
+    settings: WarpSettings,
+    x_noise: Perlin,
+    z_noise: Perlin,
+}
 
-/// Roughness for the Perlin Noise Function. Lower values represent smoother terrain, higher values represent rougher terrain
-const ROUGHNESS: f64 = 1.0;
+/// Heightfield of an area that is eroded on first use, once the rest of the generator is set up.
+#[derive(Clone)]
+struct Erosion {
+    settings: ErosionSettings,
+    min: IVec2,
+    max: IVec2,
+    field: OnceLock<Heightfield>,
+}
 
-/// Scales the Roughness to the max height of the voxel (to keep the roughness consistent across different max heights)
-const SCALE: f64 = ROUGHNESS / HEIGHT as f64;
+/// Surfaces of an area's columns, computed together on first use so repeated lookups down a column don't
+/// evaluate the noise again.
+#[derive(Clone)]
+struct HeightCache {
+    min: IVec2,
+    max: IVec2,
+    surfaces: OnceLock<Box<[(i32, Voxel, i32)]>>,
+}
 
-// Colors for use in height_to_color function
+/// 3D noise fields used to turn the heightfield into a density field.
+#[derive(Clone)]
+struct Caves {
+    settings: CaveSettings,
+    cave_noise: Perlin,
+    overhang_noise: Perlin,
+}
+
+/// Settings for the overall shape and coloring of the terrain.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TerrainSettings {
+    /// Max height of the terrain in voxels.
+    pub height: i32,
+    /// Roughness for the Perlin noise function. Lower values represent smoother terrain, higher values represent rougher terrain.
+    pub roughness: f64,
+    /// Fraction of the height below which the ground is colored as water, which is also the default sea level.
+    pub water_level: f64,
+    /// Fraction of the height where grass turns to mountain.
+    pub mountain_level: f64,
+    /// Fraction of the height where mountain turns to snow.
+    pub snow_level: f64,
+}
+
+impl Default for TerrainSettings {
+    fn default() -> Self {
+        Self {
+            height: 100,
+            roughness: 1.0,
+            water_level: 0.3,
+            mountain_level: 0.6,
+            snow_level: 0.8,
+        }
+    }
+}
+
+impl TerrainSettings {
+    /// Frequency of the height noise, which scales the roughness to the max height (to keep the roughness consistent across different max heights).
+    pub fn scale(&self) -> f64 {
+        self.roughness / self.height as f64
+    }
+
+    /// Height of the water level in voxels.
+    pub fn sea_level(&self) -> i32 {
+        (self.water_level * self.height as f64) as i32
+    }
+
+    /// Color band (water, grass, mountain, snow) of a height given as a fraction of the max height.
+    pub fn band(&self, normalized: f64) -> usize {
+        if normalized < self.water_level {
+            0
+        } else if normalized < self.mountain_level {
+            1
+        } else if normalized < self.snow_level {
+            2
+        } else {
+            3
+        }
+    }
+}
+
+// Colors for use in height_to_voxel function
 const WATER_BLUE: U8Vec3 = U8Vec3::new(0, 80, 200);
 const GRASS_GREEN: U8Vec3 = U8Vec3::new(50, 170, 50);
 const MOUNTAIN_GRAY: U8Vec3 = U8Vec3::new(130, 130, 130);
 const SNOW_WHITE: U8Vec3 = U8Vec3::new(240, 240, 255);
+const SEDIMENT_BROWN: U8Vec3 = U8Vec3::new(150, 120, 80);
+
+/// Lava flooding the bottom of caves, see [`CaveSettings::lava_level`].
+pub const LAVA: Voxel = Voxel::new(U8Vec3::new(255, 110, 20), VoxelKind::LAVA);
+
+/// Kinds of the ground in each band of the terrain, the lowest of which is colored like water.
+const BAND_KINDS: [VoxelKind; 4] = [
+    VoxelKind::WATER,
+    VoxelKind::GRASS,
+    VoxelKind::STONE,
+    VoxelKind::SNOW,
+];
+
+impl Default for VoxelGenerator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl VoxelGenerator {
     /// Create a new voxel generator with random seed.
     pub fn new() -> Self {
         let seed: u32 = rand::rng().random::<u32>();
-        let perlin = Perlin::new(seed);
-        Self { perlin }
+        Self::new_from_seed(seed)
     }
 
     /// Creates a new voxel generator with set seed (for testing purposes)
     pub fn new_from_seed(seed: u32) -> Self {
         let perlin = Perlin::new(seed);
-        Self { perlin }
+        Self {
+            perlin,
+            caves: None,
+            biomes: None,
+            vegetation: None,
+            water: None,
+            warp: None,
+            fbm: FbmSettings::default(),
+            terrain: TerrainSettings::default(),
+            erosion: None,
+            structures: None,
+            ores: None,
+            height_cache: None,
+        }
     }
 
-    /// Lookup a voxel value at some position.
-    pub fn lookup(&self, pos: IVec3) -> Option<Voxel> {
-        // Calculate the Perlin noise value at (x, z)
-        let nx = pos.x as f64 * SCALE;
-        let nz = pos.z as f64 * SCALE;
-        let noise_value = self.perlin.get([nx, nz]);
+    /// Varies the shape and colors of the terrain by biome (desert, forest, tundra, plains).
+    pub fn with_biomes(mut self) -> Self {
+        self.biomes = Some(Biomes::new(self.perlin.seed()));
+        self
+    }
 
-        // Calculate the terrain height based on the noise value
-        let terrain_y = ((noise_value + 1.0) / 2.0 * HEIGHT as f64) as i32;
+    /// Scatters trees and bushes over grassy ground.
+    pub fn with_vegetation(mut self, settings: VegetationSettings) -> Self {
+        self.vegetation = Some(Vegetation::new(self.perlin.seed(), settings));
+        self
+    }
+
+    /// Scatters clusters of ore and crystals through the ground, which caves can expose.
+    pub fn with_ores(mut self, settings: OreSettings) -> Self {
+        self.ores = Some(Ores::new(self.perlin.seed(), settings));
+        self
+    }
+
+    /// Places prefab structures on the parts of the terrain their rules allow.
+    pub fn with_structures(mut self, settings: StructureSettings, prefabs: Vec<Prefab>) -> Self {
+        let seed = self.perlin.seed().wrapping_add(9);
+        self.structures = Some(Structures::new(seed, settings, prefabs));
+        self
+    }
+
+    /// Floods the terrain below sea level and carves rivers through the land above it.
+    pub fn with_water(mut self, settings: WaterSettings) -> Self {
+        self.water = Some(Water::new(self.perlin.seed(), settings));
+        self
+    }
+
+    /// Changes the height, roughness, and color bands of the terrain.
+    pub fn with_terrain(mut self, settings: TerrainSettings) -> Self {
+        self.terrain = settings;
+        self
+    }
+
+    /// Wears down the terrain of the columns from `min` to `max` (exclusive) with simulated rain.
+    ///
+    /// The whole area is simulated on the first lookup, which takes longer for larger areas.
+    /// Columns outside of it are not eroded.
+    pub fn with_erosion(mut self, settings: ErosionSettings, min: IVec2, max: IVec2) -> Self {
+        self.erosion = Some(Erosion {
+            settings,
+            min,
+            max,
+            field: OnceLock::new(),
+        });
+        self
+    }
+
+    /// Caches the surface of the columns from `min` to `max` (exclusive), so looking up voxels one by one doesn't
+    /// evaluate the height noise for every voxel.
+    ///
+    /// The whole area is computed on the first lookup, so the other settings have to be in place by then.
+    pub fn with_height_cache(mut self, min: IVec2, max: IVec2) -> Self {
+        self.height_cache = Some(HeightCache {
+            min,
+            max,
+            surfaces: OnceLock::new(),
+        });
+        self
+    }
+
+    /// Builds the terrain's height noise from several octaves for finer detail.
+    pub fn with_fbm(mut self, settings: FbmSettings) -> Self {
+        self.fbm = settings;
+        self
+    }
+
+    /// Warps the terrain's height noise into more organic ridges and valleys.
+    pub fn with_warp(mut self, settings: WarpSettings) -> Self {
+        let seed = self.perlin.seed();
+        self.warp = Some(Warp {
+            settings,
+            x_noise: Perlin::new(seed.wrapping_add(7)),
+            z_noise: Perlin::new(seed.wrapping_add(8)),
+        });
+        self
+    }
+
+    /// Carves caves into the terrain and displaces the surface to form overhangs.
+    pub fn with_caves(mut self, settings: CaveSettings) -> Self {
+        let seed = self.perlin.seed();
+        self.caves = Some(Caves {
+            settings,
+            cave_noise: Perlin::new(seed.wrapping_add(1)),
+            overhang_noise: Perlin::new(seed.wrapping_add(2)),
+        });
+        self
+    }
+
+    /// Calculates the terrain height of a column from the Perlin noise value at (x, z).
+    fn terrain_height(&self, x: i32, z: i32) -> i32 {
+        let noise_value = self.height_noise(self.sample_pos(x, z), 1.0);
+
+        ((noise_value + 1.0) / 2.0 * self.terrain.height as f64) as i32
+    }
+
+    /// Position where the height noise of a column is sampled, pushed around by the warp noise.
+    fn sample_pos(&self, x: i32, z: i32) -> DVec2 {
+        let pos = DVec2::new(x as f64, z as f64);
+        let Some(warp) = &self.warp else {
+            return pos;
+        };
+
+        let p = (pos / warp.settings.scale).to_array();
+        let offset = DVec2::new(warp.x_noise.get(p), warp.z_noise.get(p));
+        pos + offset * warp.settings.strength
+    }
+
+    /// Height noise (-1 to 1) at a sample position, with its frequency multiplied by `roughness`.
+    fn height_noise(&self, pos: DVec2, roughness: f64) -> f64 {
+        let FbmSettings {
+            octaves,
+            lacunarity,
+            persistence,
+        } = self.fbm;
+
+        let mut total = 0.0;
+        let mut max = 0.0;
+        let mut frequency = 1.0;
+        let mut amplitude = 1.0;
+        for octave in 0..octaves.max(1) {
+            let p =
+                pos * self.terrain.scale() * roughness * frequency + OCTAVE_OFFSET * octave as f64;
+            total += self.perlin.get(p.to_array()) * amplitude;
+            max += amplitude;
+            frequency *= lacunarity;
+            amplitude *= persistence;
+        }
+
+        // normalize so the height range doesn't depend on the number of octaves
+        total / max
+    }
+
+    /// Calculates the terrain height, surface voxel, and water height of a column.
+    ///
+    /// The column holds water between the terrain and the water height when the water is higher.
+    fn surface(&self, x: i32, z: i32) -> (i32, Voxel, i32) {
+        let Some(cache) = &self.height_cache else {
+            return self.compute_surface(x, z);
+        };
+
+        let pos = IVec2::new(x, z);
+        if pos.cmplt(cache.min).any() || pos.cmpge(cache.max).any() {
+            return self.compute_surface(x, z);
+        }
+
+        let size = cache.max - cache.min;
+        let surfaces = cache.surfaces.get_or_init(|| {
+            (cache.min.y..cache.max.y)
+                .flat_map(|z| (cache.min.x..cache.max.x).map(move |x| (x, z)))
+                .map(|(x, z)| self.compute_surface(x, z))
+                .collect()
+        });
+        let local = pos - cache.min;
+        surfaces[(local.x + size.x * local.y) as usize]
+    }
+
+    /// Calculates the surface of a column without the cache.
+    fn compute_surface(&self, x: i32, z: i32) -> (i32, Voxel, i32) {
+        let (terrain_y, voxel) = match self.eroded_height(x, z) {
+            Some((terrain_y, true)) if self.terrain.band(self.normalized(terrain_y)) > 0 => {
+                (terrain_y, Voxel::new(SEDIMENT_BROWN, VoxelKind::DIRT))
+            }
+            Some((terrain_y, _)) => (terrain_y, self.surface_voxel(x, z, terrain_y)),
+            None => self.ground(x, z),
+        };
 
-        // Check if the voxel exists at the requested position (y should be <= terrain_y)
-        if pos.y >= 0 && pos.y <= terrain_y {
-            Some(Voxel {
-                color: Self::height_to_color(terrain_y),
-            })
+        match &self.water {
+            Some(water) => {
+                let (ground_y, water_y) = water.column(x, z, terrain_y, self.terrain.height);
+                (ground_y, voxel, water_y)
+            }
+            None => (terrain_y, voxel, i32::MIN),
+        }
+    }
+
+    /// Calculates the terrain height and surface voxel of a column before erosion and water.
+    fn ground(&self, x: i32, z: i32) -> (i32, Voxel) {
+        match &self.biomes {
+            Some(biomes) => {
+                let pos = self.sample_pos(x, z);
+                biomes.column(x, z, &self.terrain, |roughness| {
+                    self.height_noise(pos, roughness)
+                })
+            }
+            None => {
+                let terrain_y = self.terrain_height(x, z);
+                (terrain_y, self.height_to_voxel(terrain_y))
+            }
+        }
+    }
+
+    /// Terrain height of a column after erosion and whether sediment built up on it, if it is in the eroded area.
+    fn eroded_height(&self, x: i32, z: i32) -> Option<(i32, bool)> {
+        let erosion = self.erosion.as_ref()?;
+        let field = erosion.field.get_or_init(|| {
+            let mut field = Heightfield::new(erosion.min, erosion.max, |x, z| self.ground(x, z).0);
+            field.erode(erosion.settings);
+            field
+        });
+        Some((field.height(x, z)?, field.is_sediment(x, z)))
+    }
+
+    /// Surface voxel of a column with the terrain at height `y`.
+    fn surface_voxel(&self, x: i32, z: i32, y: i32) -> Voxel {
+        match &self.biomes {
+            Some(biomes) => biomes.voxel(x, z, &self.terrain, self.normalized(y)),
+            None => self.height_to_voxel(y),
+        }
+    }
+
+    /// Height as a fraction of the max terrain height.
+    fn normalized(&self, y: i32) -> f64 {
+        y as f64 / self.terrain.height as f64
+    }
+
+    /// Looks up a terrain or water voxel given the surface of its column.
+    fn voxel(&self, pos: IVec3, (terrain_y, voxel, water_y): (i32, Voxel, i32)) -> Option<Voxel> {
+        if self.is_solid(pos, terrain_y) {
+            let ore = self
+                .ores
+                .as_ref()
+                .and_then(|ores| ores.voxel(pos, terrain_y - pos.y));
+            Some(ore.unwrap_or(voxel))
+        } else if self.is_lava(pos, terrain_y) {
+            Some(LAVA)
+        } else if pos.y > terrain_y && pos.y <= water_y {
+            Some(WATER)
         } else {
             None
         }
     }
 
-    fn height_to_color(y: i32) -> U8Vec3 {
-        let normalized = y as f32 / HEIGHT as f32;
+    /// Checks if a position is solid given the terrain height of its column.
+    fn is_solid(&self, pos: IVec3, terrain_y: i32) -> bool {
+        if pos.y < 0 {
+            return false;
+        }
+
+        let Some(caves) = &self.caves else {
+            return pos.y <= terrain_y;
+        };
 
-        if normalized < 0.3 {
-            WATER_BLUE
-        } else if normalized < 0.6 {
-            GRASS_GREEN
-        } else if normalized < 0.8 {
-            MOUNTAIN_GRAY
-        } else {
-            SNOW_WHITE
+        let settings = caves.settings;
+        if pos.y as f64 > terrain_y as f64 + settings.overhang {
+            return false;
+        }
+
+        // keep the floor closed so caves don't open into the void below
+        if pos.y == 0 {
+            return true;
+        }
+
+        let p = (pos.as_dvec3() / settings.scale).to_array();
+
+        // push the surface in or out, which varies with y to create overhangs
+        let surface = terrain_y as f64 + caves.overhang_noise.get(p) * settings.overhang;
+
+        pos.y as f64 <= surface && caves.cave_noise.get(p) <= settings.threshold
+    }
+
+    /// Checks if a position that isn't solid is in a cave flooded with lava, given the terrain height of its column.
+    fn is_lava(&self, pos: IVec3, terrain_y: i32) -> bool {
+        self.caves
+            .as_ref()
+            .is_some_and(|caves| pos.y >= 0 && pos.y <= terrain_y.min(caves.settings.lava_level))
+    }
+
+    /// Finds the plant growing in the vegetation cell of a column.
+    fn plant(&self, x: i32, z: i32) -> Option<Plant> {
+        let vegetation = self.vegetation.as_ref()?;
+        vegetation.plant(x, z, |x, z| {
+            let (ground_y, _, water_y) = self.surface(x, z);
+            let ground = IVec3::new(x, ground_y, z);
+
+            // only grow on exposed grass
+            if self.terrain.band(self.normalized(ground_y)) != 1
+                || water_y > ground_y
+                || !self.is_solid(ground, ground_y)
+                || self.is_solid(ground + IVec3::Y, ground_y)
+                || self.structure(x, z).is_some()
+            {
+                return None;
+            }
+
+            let density = self.biomes.as_ref().map_or(1.0, |b| b.vegetation(x, z));
+            Some((ground_y, density))
+        })
+    }
+
+    /// Finds the structure standing on a column.
+    fn structure(&self, x: i32, z: i32) -> Option<Structure<'_>> {
+        let structures = self.structures.as_ref()?;
+        structures.structure(x, z, |x, z| {
+            let (ground_y, _, water_y) = self.surface(x, z);
+
+            // only build on dry land
+            (water_y <= ground_y).then(|| (ground_y, self.terrain.band(self.normalized(ground_y))))
+        })
+    }
+
+    fn height_to_voxel(&self, y: i32) -> Voxel {
+        let band = self.terrain.band(self.normalized(y));
+        let color = [WATER_BLUE, GRASS_GREEN, MOUNTAIN_GRAY, SNOW_WHITE][band];
+        Voxel::new(color, BAND_KINDS[band])
+    }
+}
+
+impl VoxelSource for VoxelGenerator {
+    fn lookup(&self, pos: IVec3) -> Option<Voxel> {
+        // Calculate the terrain height based on the noise value
+        let surface = self.surface(pos.x, pos.z);
+
+        let structure = self.structure(pos.x, pos.z);
+        if let Some(structure) = structure.filter(|structure| structure.clears(pos)) {
+            return structure.voxel(pos);
+        }
+
+        // Check if the voxel exists at the requested position (y should be <= terrain_y without caves)
+        structure
+            .and_then(|structure| structure.voxel(pos))
+            .or_else(|| self.voxel(pos, surface))
+            .or_else(|| self.plant(pos.x, pos.z)?.voxel(pos))
+    }
+
+    fn column(&self, x: i32, z: i32, ys: Range<i32>, out: &mut [Option<Voxel>]) {
+        debug_assert_eq!(out.len(), ys.len(), "column length mismatch");
+
+        // the noise only depends on x and z, so it is evaluated once for the whole column
+        let surface = self.surface(x, z);
+        let plant = self.plant(x, z);
+        let structure = self.structure(x, z);
+
+        // everything above the highest voxel the column could have is sky, which is most of a scene
+        let (terrain_y, _, water_y) = surface;
+        let overhang = self
+            .caves
+            .as_ref()
+            .map_or(0.0, |caves| caves.settings.overhang);
+        let top = [
+            terrain_y + overhang.ceil() as i32,
+            water_y,
+            plant.map_or(i32::MIN, |plant| plant.top()),
+            structure.map_or(i32::MIN, |structure| structure.top()),
+        ]
+        .into_iter()
+        .max()
+        .expect("list is not empty");
+        let ground = (top.saturating_add(1).saturating_sub(ys.start)).clamp(0, ys.len() as i32);
+        let (out, sky) = out.split_at_mut(ground as usize);
+        sky.fill(None);
+
+        for (y, slot) in ys.zip(out) {
+            let pos = IVec3::new(x, y, z);
+            *slot = match structure {
+                // structures are built into the terrain, which is cut away around them
+                Some(structure) if structure.clears(pos) => structure.voxel(pos),
+                _ => structure
+                    .and_then(|structure| structure.voxel(pos))
+                    .or_else(|| self.voxel(pos, surface))
+                    .or_else(|| plant.and_then(|plant| plant.voxel(pos))),
+            };
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use noise::Seedable;
-
     use super::*;
 
     const TEST_SEED: u32 = 12345;
 
+    #[test]
+    fn kinds_are_named() {
+        assert_eq!(VoxelKind::STONE.to_string(), "stone");
+        assert_eq!(VoxelKind::GLASS.to_string(), "glass");
+        assert_eq!(VoxelKind(200).to_string(), "kind 200");
+    }
+
     #[test]
     fn test_voxel_generator_lookup_with_seed() {
         let voxel_generator = VoxelGenerator::new_from_seed(TEST_SEED);
@@ -96,12 +789,13 @@ mod tests {
 
         // Calculate the Perlin noise value at (5, 5)
         let perlin = Perlin::new(TEST_SEED);
-        let nx = x as f64 * SCALE;
-        let nz = z as f64 * SCALE;
+        let settings = TerrainSettings::default();
+        let nx = x as f64 * settings.scale();
+        let nz = z as f64 * settings.scale();
         let noise_value = perlin.get([nx, nz]);
 
         // Calculate the terrain height based on the noise value
-        let terrain_y = ((noise_value + 1.0) / 2.0 * HEIGHT as f64) as i32;
+        let terrain_y = ((noise_value + 1.0) / 2.0 * settings.height as f64) as i32;
 
         // Check if the voxel exists at the calculated height
         let voxel_correct_height = voxel_generator.lookup(IVec3::new(x, terrain_y, z));
@@ -135,18 +829,388 @@ mod tests {
 
     #[test]
     fn test_voxel_color_mapping() {
-        let low_voxel = VoxelGenerator::height_to_color(2);
-        let mid_voxel = VoxelGenerator::height_to_color(HEIGHT / 2);
-        let high_voxel = VoxelGenerator::height_to_color(HEIGHT - 1);
+        let voxel_generator = VoxelGenerator::new_from_seed(TEST_SEED);
+        let height = TerrainSettings::default().height;
+        let low_voxel = voxel_generator.height_to_voxel(2);
+        let mid_voxel = voxel_generator.height_to_voxel(height / 2);
+        let high_voxel = voxel_generator.height_to_voxel(height - 1);
 
-        assert_eq!(low_voxel, WATER_BLUE, "Low altitude should be blue (water)");
         assert_eq!(
-            mid_voxel, GRASS_GREEN,
+            low_voxel,
+            Voxel::new(WATER_BLUE, VoxelKind::WATER),
+            "Low altitude should be blue (water)"
+        );
+        assert_eq!(
+            mid_voxel,
+            Voxel::new(GRASS_GREEN, VoxelKind::GRASS),
             "Mid altitude should be green (grass)"
         );
         assert_eq!(
-            high_voxel, SNOW_WHITE,
+            high_voxel,
+            Voxel::new(SNOW_WHITE, VoxelKind::SNOW),
             "High altitude should be white (snow)"
         );
     }
+
+    #[test]
+    fn test_column_matches_lookup() {
+        for voxel_generator in [
+            VoxelGenerator::new_from_seed(TEST_SEED),
+            VoxelGenerator::new_from_seed(TEST_SEED).with_caves(CaveSettings::default()),
+            VoxelGenerator::new_from_seed(TEST_SEED).with_biomes(),
+            VoxelGenerator::new_from_seed(TEST_SEED)
+                .with_biomes()
+                .with_warp(WarpSettings::default()),
+            VoxelGenerator::new_from_seed(TEST_SEED)
+                .with_biomes()
+                .with_erosion(
+                    ErosionSettings::default(),
+                    IVec2::splat(-50),
+                    IVec2::splat(50),
+                )
+                .with_vegetation(VegetationSettings::default()),
+            VoxelGenerator::new_from_seed(TEST_SEED).with_fbm(FbmSettings {
+                octaves: 5,
+                ..Default::default()
+            }),
+            VoxelGenerator::new_from_seed(TEST_SEED).with_vegetation(VegetationSettings::default()),
+            VoxelGenerator::new_from_seed(TEST_SEED)
+                .with_caves(CaveSettings::default())
+                .with_ores(OreSettings::default()),
+            VoxelGenerator::new_from_seed(TEST_SEED)
+                .with_water(WaterSettings::default())
+                .with_caves(CaveSettings::default())
+                .with_vegetation(VegetationSettings::default()),
+            VoxelGenerator::new_from_seed(TEST_SEED)
+                .with_vegetation(VegetationSettings::default())
+                .with_structures(
+                    StructureSettings { density: 1.0 },
+                    vec![Prefab::hut(), Prefab::ruin()],
+                ),
+        ] {
+            // enough columns to cross trees and structures, whose tops the column skips past
+            let grid = (-40..40)
+                .step_by(7)
+                .flat_map(|x| (-40..40).step_by(7).map(move |z| (x, z)));
+            for (x, z) in [(0, 0), (13, -7), (-40, 25)].into_iter().chain(grid) {
+                let mut column = vec![None; 120];
+                voxel_generator.column(x, z, -10..110, &mut column);
+
+                for (y, voxel) in (-10..110).zip(column) {
+                    assert_eq!(voxel, voxel_generator.lookup(IVec3::new(x, y, z)));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_height_cache() {
+        let uncached = VoxelGenerator::new_from_seed(TEST_SEED)
+            .with_water(WaterSettings::default())
+            .with_vegetation(VegetationSettings::default())
+            .with_structures(
+                StructureSettings { density: 1.0 },
+                vec![Prefab::hut(), Prefab::ruin()],
+            );
+        let cached = uncached
+            .clone()
+            .with_height_cache(IVec2::splat(-20), IVec2::new(30, 10));
+
+        // columns on both sides of the cached area's edges
+        for x in (-30..40).step_by(3) {
+            for z in (-30..20).step_by(3) {
+                assert_eq!(cached.surface(x, z), uncached.surface(x, z));
+                for y in 0..60 {
+                    let pos = IVec3::new(x, y, z);
+                    assert_eq!(cached.lookup(pos), uncached.lookup(pos));
+                }
+            }
+        }
+        let surfaces = cached
+            .height_cache
+            .as_ref()
+            .unwrap()
+            .surfaces
+            .get()
+            .unwrap();
+        assert_eq!(surfaces.len(), 50 * 30);
+    }
+
+    #[test]
+    fn test_structures() {
+        let voxel_generator = VoxelGenerator::new_from_seed(TEST_SEED)
+            .with_vegetation(VegetationSettings::default())
+            .with_structures(
+                StructureSettings { density: 1.0 },
+                vec![Prefab::hut(), Prefab::ruin()],
+            );
+
+        let mut built = 0;
+        for x in (-200..200).step_by(3) {
+            for z in (-200..200).step_by(3) {
+                let Some(structure) = voxel_generator.structure(x, z) else {
+                    continue;
+                };
+                built += 1;
+
+                // structures stand on dry ground with nothing growing through them
+                let (ground_y, _, _) = voxel_generator.surface(x, z);
+                assert!((ground_y - structure.base.y).abs() <= 10);
+                assert!(voxel_generator.plant(x, z).is_none_or(|plant| {
+                    voxel_generator
+                        .structure(plant.base.x, plant.base.z)
+                        .is_none()
+                }));
+
+                let ys = ground_y - 5..ground_y + 15;
+                let mut column = vec![None; ys.len()];
+                voxel_generator.column(x, z, ys.clone(), &mut column);
+                for (y, voxel) in ys.zip(column) {
+                    let pos = IVec3::new(x, y, z);
+                    assert_eq!(voxel, voxel_generator.lookup(pos));
+                    if let Some(voxel) = structure.voxel(pos) {
+                        assert_eq!(voxel_generator.lookup(pos), Some(voxel));
+                    }
+                }
+            }
+        }
+        assert!(built > 0, "no structures were built");
+    }
+
+    #[test]
+    fn test_warp() {
+        let plain = VoxelGenerator::new_from_seed(TEST_SEED);
+        let still = VoxelGenerator::new_from_seed(TEST_SEED).with_warp(WarpSettings {
+            strength: 0.0,
+            ..Default::default()
+        });
+        let warped = VoxelGenerator::new_from_seed(TEST_SEED).with_warp(WarpSettings::default());
+
+        let mut moved = 0;
+        for x in -50..50 {
+            for z in -50..50 {
+                let height = plain.terrain_height(x, z);
+                assert_eq!(still.terrain_height(x, z), height);
+                if warped.terrain_height(x, z) != height {
+                    moved += 1;
+                }
+
+                // warping stays continuous
+                let step = warped.terrain_height(x + 1, z) - warped.terrain_height(x, z);
+                assert!(step.abs() <= 4, "jump of {step} at ({x}, {z})");
+            }
+        }
+
+        assert!(moved > 5000, "warping barely changed the terrain");
+    }
+
+    #[test]
+    fn test_terrain_settings() {
+        let plain = VoxelGenerator::new_from_seed(TEST_SEED);
+        let tall = VoxelGenerator::new_from_seed(TEST_SEED).with_terrain(TerrainSettings {
+            height: 200,
+            roughness: 2.0,
+            ..Default::default()
+        });
+        let snowy = VoxelGenerator::new_from_seed(TEST_SEED).with_terrain(TerrainSettings {
+            mountain_level: 0.0,
+            snow_level: 0.0,
+            ..Default::default()
+        });
+
+        for x in -50..50 {
+            // the same noise frequency, stretched to twice the height
+            let height = plain.terrain_height(x, 3);
+            assert!((tall.terrain_height(x, 3) - 2 * height).abs() <= 1);
+
+            let pos = IVec3::new(x, height, 3);
+            if plain.terrain_height(x, 3) >= plain.terrain.sea_level() {
+                assert_eq!(
+                    snowy.lookup(pos),
+                    Some(Voxel::new(SNOW_WHITE, VoxelKind::SNOW))
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_erosion() {
+        let plain = VoxelGenerator::new_from_seed(TEST_SEED);
+        let eroded = VoxelGenerator::new_from_seed(TEST_SEED).with_erosion(
+            ErosionSettings::default(),
+            IVec2::ZERO,
+            IVec2::splat(64),
+        );
+
+        let mut changed = 0;
+        for x in -10..74 {
+            for z in -10..74 {
+                let (plain_y, _, _) = plain.surface(x, z);
+                let (eroded_y, _, _) = eroded.surface(x, z);
+                if !(0..64).contains(&x) || !(0..64).contains(&z) {
+                    assert_eq!(eroded_y, plain_y, "outside of the area at ({x}, {z})");
+                } else if eroded_y != plain_y {
+                    changed += 1;
+                }
+            }
+        }
+        assert!(changed > 0, "erosion didn't change the terrain");
+    }
+
+    #[test]
+    fn test_fbm() {
+        let plain = VoxelGenerator::new_from_seed(TEST_SEED);
+        let single = VoxelGenerator::new_from_seed(TEST_SEED).with_fbm(FbmSettings {
+            octaves: 1,
+            lacunarity: 3.0,
+            persistence: 0.9,
+        });
+        let detailed = VoxelGenerator::new_from_seed(TEST_SEED).with_fbm(FbmSettings {
+            octaves: 6,
+            ..Default::default()
+        });
+
+        // finer octaves make neighbouring columns differ more often
+        let mut plain_steps = 0;
+        let mut detailed_steps = 0;
+        for x in -100..100 {
+            let z = 11;
+            assert_eq!(single.terrain_height(x, z), plain.terrain_height(x, z));
+
+            let height = detailed.terrain_height(x, z);
+            assert!((0..=TerrainSettings::default().height).contains(&height));
+
+            plain_steps += (plain.terrain_height(x + 1, z) - plain.terrain_height(x, z)).abs();
+            detailed_steps += (detailed.terrain_height(x + 1, z) - height).abs();
+        }
+        assert!(
+            detailed_steps > plain_steps,
+            "{detailed_steps} <= {plain_steps}"
+        );
+    }
+
+    #[test]
+    fn test_vegetation() {
+        let plain = VoxelGenerator::new_from_seed(TEST_SEED);
+        let trees =
+            VoxelGenerator::new_from_seed(TEST_SEED).with_vegetation(VegetationSettings::default());
+
+        let mut plants = 0;
+        for x in 0..100 {
+            for z in 0..100 {
+                let terrain_y = plain.terrain_height(x, z);
+                let pos = IVec3::new(x, terrain_y, z);
+
+                // the terrain itself is untouched
+                assert_eq!(plain.lookup(pos), trees.lookup(pos));
+
+                if let Some(plant) = trees.plant(x, z) {
+                    if plant.base.x == x && plant.base.z == z {
+                        plants += 1;
+                    }
+
+                    let normalized = plant.base.y as f64 / TerrainSettings::default().height as f64;
+                    assert!(
+                        (0.3..0.6).contains(&normalized),
+                        "plant not on grass at {}",
+                        plant.base
+                    );
+                    assert_eq!(
+                        plant.base.y,
+                        plain.terrain_height(plant.base.x, plant.base.z)
+                    );
+                }
+            }
+        }
+
+        assert!(plants > 0, "no plants were placed");
+    }
+
+    #[test]
+    fn test_water() {
+        let plain = VoxelGenerator::new_from_seed(TEST_SEED);
+        let water = VoxelGenerator::new_from_seed(TEST_SEED).with_water(WaterSettings::default());
+        let sea_level = WaterSettings::default().sea_level;
+
+        let mut flooded = 0;
+        for x in -100..100 {
+            for z in -100..100 {
+                let terrain_y = plain.terrain_height(x, z);
+                if terrain_y >= sea_level {
+                    continue;
+                }
+
+                // low ground is unchanged and covered with water up to sea level
+                flooded += 1;
+                assert!(water
+                    .lookup(IVec3::new(x, terrain_y, z))
+                    .is_some_and(|v| v != WATER));
+                assert_eq!(water.lookup(IVec3::new(x, sea_level, z)), Some(WATER));
+                assert_eq!(water.lookup(IVec3::new(x, sea_level + 1, z)), None);
+            }
+        }
+
+        assert!(flooded > 0, "nothing was below sea level");
+    }
+
+    #[test]
+    fn test_caves() {
+        let plain = VoxelGenerator::new_from_seed(TEST_SEED);
+        let caves = VoxelGenerator::new_from_seed(TEST_SEED).with_caves(CaveSettings::default());
+        let overhang = CaveSettings::default().overhang as i32;
+
+        let mut carved = 0;
+        let mut added = 0;
+        for x in 0..50 {
+            for z in 0..50 {
+                let terrain_y = plain.terrain_height(x, z);
+
+                // the floor is never carved and nothing exists past the overhang distance
+                assert!(caves.lookup(IVec3::new(x, 0, z)).is_some());
+                assert!(caves
+                    .lookup(IVec3::new(x, terrain_y + overhang + 1, z))
+                    .is_none());
+
+                for y in 0..=terrain_y + overhang {
+                    let pos = IVec3::new(x, y, z);
+                    match (plain.lookup(pos), caves.lookup(pos)) {
+                        (Some(_), None) => carved += 1,
+                        (None, Some(_)) => added += 1,
+                        _ => {}
+                    }
+                }
+            }
+        }
+
+        assert!(carved > 0, "no caves were carved");
+        assert!(added > 0, "no overhangs were added");
+    }
+
+    #[test]
+    fn test_lava_floods_caves() {
+        let settings = CaveSettings {
+            lava_level: 30,
+            ..Default::default()
+        };
+        let dry = VoxelGenerator::new_from_seed(TEST_SEED).with_caves(CaveSettings::default());
+        let lava = VoxelGenerator::new_from_seed(TEST_SEED).with_caves(settings);
+
+        let mut flooded = 0;
+        for x in 0..50 {
+            for z in 0..50 {
+                for y in 0..80 {
+                    let pos = IVec3::new(x, y, z);
+                    match (dry.lookup(pos), lava.lookup(pos)) {
+                        // only the hollows of caves fill, up to the lava level
+                        (None, Some(LAVA)) => {
+                            assert!(y <= settings.lava_level);
+                            flooded += 1;
+                        }
+                        (dry, lava) => assert_eq!(dry, lava),
+                    }
+                }
+            }
+        }
+        assert!(flooded > 0, "no caves were flooded");
+    }
 }
@@ -0,0 +1,138 @@
+use glam::{IVec3, U8Vec3};
+use noise::{NoiseFn, Perlin};
+
+use super::{Voxel, VoxelKind};
+
+/// Rusty iron ore, common just below the surface.
+pub const IRON: Voxel = Voxel::new(U8Vec3::new(195, 130, 95), VoxelKind::IRON);
+
+/// Gold ore, found deeper down.
+pub const GOLD: Voxel = Voxel::new(U8Vec3::new(255, 205, 40), VoxelKind::GOLD);
+
+/// Glowing crystals, rare and deep, lighting the caves around them in path-traced renders.
+pub const CRYSTAL: Voxel = Voxel::new(U8Vec3::new(110, 245, 255), VoxelKind::CRYSTAL);
+
+/// A kind of ore and the noise clusters it forms.
+struct Deposit {
+    voxel: Voxel,
+    /// Voxels where the noise is above this are ore (-1 to 1, lower values make more ore).
+    threshold: f64,
+    /// Rough size of clusters in voxels.
+    scale: f64,
+    /// Shallowest depth below the surface where the ore is found.
+    min_depth: i32,
+}
+
+/// Ores from the rarest to the most common, so rare ores aren't covered up by common ones.
+const DEPOSITS: [Deposit; 3] = [
+    Deposit {
+        voxel: CRYSTAL,
+        threshold: 0.7,
+        scale: 5.0,
+        min_depth: 20,
+    },
+    Deposit {
+        voxel: GOLD,
+        threshold: 0.65,
+        scale: 4.0,
+        min_depth: 10,
+    },
+    Deposit {
+        voxel: IRON,
+        threshold: 0.6,
+        scale: 6.0,
+        min_depth: 3,
+    },
+];
+
+/// Settings for clusters of ore inside the terrain.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OreSettings {
+    /// Multiplier for the amount of ore (0 disables ore, higher values make more and larger clusters).
+    pub abundance: f64,
+}
+
+impl Default for OreSettings {
+    fn default() -> Self {
+        Self { abundance: 1.0 }
+    }
+}
+
+/// 3D noise fields, one per kind of ore, that place ore clusters underground.
+#[derive(Clone)]
+pub struct Ores {
+    settings: OreSettings,
+    noise: [Perlin; DEPOSITS.len()],
+}
+
+impl Ores {
+    pub fn new(seed: u32, settings: OreSettings) -> Self {
+        Self {
+            settings,
+            noise: [10, 11, 12].map(|offset| Perlin::new(seed.wrapping_add(offset))),
+        }
+    }
+
+    /// Finds the ore at a solid position `depth` voxels below the surface of its column.
+    pub fn voxel(&self, pos: IVec3, depth: i32) -> Option<Voxel> {
+        DEPOSITS
+            .iter()
+            .zip(&self.noise)
+            .filter(|(deposit, _)| depth >= deposit.min_depth)
+            .find(|(deposit, noise)| {
+                let threshold = 1.0 - (1.0 - deposit.threshold) * self.settings.abundance;
+                let p = (pos.as_dvec3() / deposit.scale).to_array();
+                noise.get(p) > threshold
+            })
+            .map(|(deposit, _)| deposit.voxel)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Counts each kind of ore in a block of voxels at some depth.
+    fn count(ores: &Ores, depth: i32) -> [usize; 3] {
+        let mut counts = [0; 3];
+        for x in 0..60 {
+            for y in 0..20 {
+                for z in 0..60 {
+                    if let Some(voxel) = ores.voxel(IVec3::new(x, y, z), depth) {
+                        let i = [IRON, GOLD, CRYSTAL]
+                            .iter()
+                            .position(|ore| *ore == voxel)
+                            .expect("unknown ore");
+                        counts[i] += 1;
+                    }
+                }
+            }
+        }
+        counts
+    }
+
+    #[test]
+    fn ores_by_depth() {
+        let ores = Ores::new(4, OreSettings::default());
+
+        assert_eq!(count(&ores, 0), [0, 0, 0]);
+        let shallow = count(&ores, 5);
+        assert!(shallow[0] > 0 && shallow[1] == 0 && shallow[2] == 0);
+        let deep = count(&ores, 30);
+        assert!(deep.iter().all(|&n| n > 0), "{deep:?}");
+
+        // ore is a small part of the underground
+        let total: usize = deep.iter().sum();
+        assert!(total < 60 * 20 * 60 / 20, "{total}");
+    }
+
+    #[test]
+    fn abundance() {
+        let none = Ores::new(4, OreSettings { abundance: 0.0 });
+        assert_eq!(count(&none, 30), [0, 0, 0]);
+
+        let normal = count(&Ores::new(4, OreSettings::default()), 30);
+        let more = count(&Ores::new(4, OreSettings { abundance: 2.0 }), 30);
+        assert!(more.iter().sum::<usize>() > normal.iter().sum::<usize>());
+    }
+}
@@ -0,0 +1,258 @@
+use glam::{DVec3, IVec3};
+use noise::{NoiseFn, Perlin};
+
+use super::{
+    water::WATER, FbmSettings, TerrainSettings, Voxel, VoxelKind, VoxelSource, BAND_KINDS,
+    GRASS_GREEN, MOUNTAIN_GRAY, OCTAVE_OFFSET, SNOW_WHITE, WATER_BLUE,
+};
+
+/// Settings for the shape of a planet.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PlanetSettings {
+    /// Scene position of the planet's center.
+    pub center: IVec3,
+    /// Radius of the lowest ground in voxels.
+    pub radius: f64,
+    /// Height of the tallest mountains above the lowest ground.
+    pub height: f64,
+    /// Surround the planet with a sphere of water up to the terrain's water level.
+    pub sea: bool,
+}
+
+impl Default for PlanetSettings {
+    fn default() -> Self {
+        Self {
+            center: IVec3::ZERO,
+            radius: 120.0,
+            height: 40.0,
+            sea: false,
+        }
+    }
+}
+
+impl PlanetSettings {
+    /// A planet centered on a point, such as the middle of the scene, whose tallest mountains reach just inside
+    /// `extent` voxels of it along each axis.
+    pub fn fitting(center: IVec3, extent: f64) -> Self {
+        Self {
+            center,
+            radius: extent * 0.7,
+            height: extent * 0.25,
+            ..Default::default()
+        }
+    }
+}
+
+/// A round planet whose terrain is pushed out from a sphere by noise on its surface.
+///
+/// The terrain settings color the planet the same way as the flat terrain, but the height of the mountains
+/// comes from [`PlanetSettings::height`].
+#[derive(Clone)]
+pub struct Planet {
+    settings: PlanetSettings,
+    terrain: TerrainSettings,
+    fbm: FbmSettings,
+    perlin: Perlin,
+}
+
+impl Planet {
+    pub fn new(seed: u32, settings: PlanetSettings) -> Self {
+        Self {
+            settings,
+            terrain: TerrainSettings::default(),
+            fbm: FbmSettings::default(),
+            perlin: Perlin::new(seed),
+        }
+    }
+
+    /// Sets the roughness and color bands of the terrain.
+    pub fn with_terrain(mut self, terrain: TerrainSettings) -> Self {
+        self.terrain = terrain;
+        self
+    }
+
+    /// Sets how the height noise is layered.
+    pub fn with_fbm(mut self, fbm: FbmSettings) -> Self {
+        self.fbm = fbm;
+        self
+    }
+
+    /// Height of the ground above the lowest ground (0 to the max height) in a direction from the center.
+    pub fn height(&self, dir: DVec3) -> f64 {
+        let FbmSettings {
+            octaves,
+            lacunarity,
+            persistence,
+        } = self.fbm;
+
+        // sample on the planet's surface so features are about as large as on the flat terrain
+        let scale = self.terrain.roughness / self.settings.height.max(1.0);
+        let pos = dir * self.settings.radius * scale;
+        let mut total = 0.0;
+        let mut max = 0.0;
+        let mut frequency = 1.0;
+        let mut amplitude = 1.0;
+        for octave in 0..octaves.max(1) {
+            let p = pos * frequency + OCTAVE_OFFSET.extend(0.0) * octave as f64;
+            total += self.perlin.get(p.to_array()) * amplitude;
+            max += amplitude;
+            frequency *= lacunarity;
+            amplitude *= persistence;
+        }
+
+        (total / max + 1.0) / 2.0 * self.settings.height
+    }
+
+    /// Radius of the sea's surface, if the planet has one.
+    pub fn sea_radius(&self) -> Option<f64> {
+        self.settings
+            .sea
+            .then_some(self.settings.radius + self.terrain.water_level * self.settings.height)
+    }
+}
+
+impl VoxelSource for Planet {
+    fn lookup(&self, pos: IVec3) -> Option<Voxel> {
+        let pos = (pos - self.settings.center).as_dvec3();
+        let distance = pos.length();
+        let radius = self.settings.radius;
+
+        // skip the noise deep inside and far outside the terrain
+        let water = self
+            .sea_radius()
+            .is_some_and(|sea| distance <= sea)
+            .then_some(WATER);
+        if distance < radius {
+            return Some(Voxel::new(MOUNTAIN_GRAY, VoxelKind::STONE));
+        }
+        if distance > radius + self.settings.height {
+            return water;
+        }
+
+        let height = self.height(pos / distance);
+        if distance > radius + height {
+            return water;
+        }
+        let normalized = height / self.settings.height;
+        let band = self.terrain.band(normalized);
+        let color = [WATER_BLUE, GRASS_GREEN, MOUNTAIN_GRAY, SNOW_WHITE][band];
+        Some(Voxel::new(color, BAND_KINDS[band]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ray_tracer::Config;
+
+    fn planet(sea: bool) -> Planet {
+        Planet::new(
+            6,
+            PlanetSettings {
+                radius: 40.0,
+                height: 12.0,
+                sea,
+                ..Default::default()
+            },
+        )
+    }
+
+    #[test]
+    fn round() {
+        let planet = planet(false);
+        for dir in [IVec3::X, IVec3::NEG_Y, IVec3::Z, IVec3::new(1, 1, -1)] {
+            let dir = dir.as_dvec3().normalize();
+            // solid up to the lowest ground, empty past the highest
+            assert!(planet.lookup((dir * 39.0).round().as_ivec3()).is_some());
+            assert!(planet.lookup((dir * 53.0).round().as_ivec3()).is_none());
+
+            let height = planet.height(dir);
+            assert!((0.0..=12.0).contains(&height));
+        }
+        assert!(planet.lookup(IVec3::ZERO).is_some());
+    }
+
+    #[test]
+    fn fits_in_the_scene() {
+        let bounds = Config {
+            size: 32,
+            ..Default::default()
+        }
+        .bounds();
+        let settings = PlanetSettings::fitting(bounds.origin, bounds.extents.min_element() as f64);
+        let planet = Planet::new(6, settings);
+
+        // every voxel of it is inside the bounds, which it reaches past the middle of on every side
+        let (mut min, mut max) = (IVec3::MAX, IVec3::MIN);
+        for x in -40..40 {
+            for y in -40..40 {
+                for z in -40..40 {
+                    let pos = IVec3::new(x, y, z);
+                    if planet.lookup(pos).is_some() {
+                        assert!(bounds.contains(pos), "{pos}");
+                        (min, max) = (min.min(pos), max.max(pos));
+                    }
+                }
+            }
+        }
+        assert!(min.cmplt(IVec3::splat(-20)).all() && max.cmpgt(IVec3::splat(20)).all());
+
+        // and it moves with the center
+        let moved = Planet::new(6, PlanetSettings::fitting(IVec3::new(100, -50, 7), 32.0));
+        assert_eq!(
+            moved.lookup(IVec3::new(100, -50, 7)),
+            planet.lookup(IVec3::ZERO)
+        );
+        assert_eq!(
+            moved.lookup(IVec3::new(125, -50, 7)),
+            planet.lookup(IVec3::X * 25)
+        );
+    }
+
+    const DIRECTIONS: usize = 200;
+
+    /// Directions spread evenly over the sphere along a spiral from pole to pole.
+    fn spiral(i: usize) -> DVec3 {
+        let y = 1.0 - 2.0 * (i as f64 + 0.5) / DIRECTIONS as f64;
+        let angle = i as f64 * 2.4;
+        let ring = (1.0 - y * y).sqrt();
+        DVec3::new(ring * angle.cos(), y, ring * angle.sin())
+    }
+
+    #[test]
+    fn surface_varies() {
+        let planet = planet(false);
+        let heights: Vec<f64> = (0..DIRECTIONS).map(|i| planet.height(spiral(i))).collect();
+        let low = heights.iter().copied().fold(f64::MAX, f64::min);
+        let high = heights.iter().copied().fold(f64::MIN, f64::max);
+        assert!(high - low > 3.0, "{low} {high}");
+    }
+
+    #[test]
+    fn sea() {
+        let dry = planet(false);
+        let wet = planet(true);
+        assert_eq!(dry.sea_radius(), None);
+        let sea = wet.sea_radius().unwrap();
+        assert_eq!(sea, 40.0 + 0.3 * 12.0);
+
+        let mut flooded = 0;
+        for i in 0..DIRECTIONS {
+            let dir = spiral(i);
+            for distance in 30..60 {
+                let pos = (dir * distance as f64).round().as_ivec3();
+                match (dry.lookup(pos), wet.lookup(pos)) {
+                    (None, Some(voxel)) => {
+                        assert_eq!(voxel, WATER);
+                        assert!(pos.as_dvec3().length() <= sea);
+                        flooded += 1;
+                    }
+                    (dry, wet) => assert_eq!(dry, wet),
+                }
+            }
+        }
+        // only the lowlands are flooded
+        assert!(flooded > 0);
+        assert!(flooded < DIRECTIONS * 4, "{flooded}");
+    }
+}
@@ -0,0 +1,306 @@
+use std::{cell::RefCell, error::Error, fmt, ops::Range, path::Path};
+
+use glam::{IVec3, U8Vec3};
+use wasmtime::{Config, Engine, Instance, Memory, Module, Store, TypedFunc};
+
+use super::{Voxel, VoxelSource};
+
+/// A module's `column(x, z, y, len)` export, returning an address in its memory.
+type ColumnFunc = TypedFunc<(i32, i32, i32, i32), i32>;
+
+/// Fuel a module can burn, about one per instruction, for each voxel it is asked for before the call is stopped, so
+/// a module stuck in a loop can't hang the render.
+const VOXEL_FUEL: u64 = 1_000_000;
+
+/// Fuel a module's `init` can burn, enough to fill tables for the whole scene.
+const INIT_FUEL: u64 = 1_000_000_000;
+
+/// Voxels defined by a WebAssembly module, so generators can be written in any language that compiles to it.
+///
+/// The module exports `lookup(x: i32, y: i32, z: i32) -> i32`, returning the voxel's color as `0xRRGGBB` or a
+/// negative number for empty space. It can also export:
+///
+/// - `init(seed: i32, size: i32)`, called once after loading.
+/// - `column(x: i32, z: i32, y: i32, len: i32) -> i32`, filling a column of `len` voxels from `y` upwards in one
+///   call. It returns the address in the exported `memory` of `len` little-endian colors, encoded like `lookup`'s.
+///
+/// Modules are sandboxed: they are given no imports, so they can only compute voxels, and each call can only run
+/// for so long, see [`VOXEL_FUEL`]. Traps while building the scene, including running out of time, panic, since
+/// voxel lookups cannot fail.
+pub struct PluginSource {
+    store: RefCell<Store<()>>,
+    lookup: TypedFunc<(i32, i32, i32), i32>,
+    column: Option<(ColumnFunc, Memory)>,
+}
+
+impl PluginSource {
+    /// Compiles a module, from binary or text format, and calls its `init` function.
+    pub fn new(wasm: impl AsRef<[u8]>, seed: u32, size: u32) -> Result<Self, PluginError> {
+        let engine = engine()?;
+        let module = Module::new(&engine, wasm)?;
+        Self::instantiate(&engine, &module, seed, size)
+    }
+
+    /// Reads and compiles a module file.
+    pub fn load(path: impl AsRef<Path>, seed: u32, size: u32) -> Result<Self, PluginError> {
+        let engine = engine()?;
+        let module = Module::from_file(&engine, path)?;
+        Self::instantiate(&engine, &module, seed, size)
+    }
+
+    fn instantiate(
+        engine: &Engine,
+        module: &Module,
+        seed: u32,
+        size: u32,
+    ) -> Result<Self, PluginError> {
+        if let Some(import) = module.imports().next() {
+            return Err(PluginError::Format(format!(
+                "modules cannot import anything, but it imports `{}::{}`",
+                import.module(),
+                import.name()
+            )));
+        }
+
+        let mut store = Store::new(engine, ());
+        let instance = Instance::new(&mut store, module, &[])?;
+        let lookup = instance.get_typed_func(&mut store, "lookup")?;
+        let column = match instance.get_func(&mut store, "column") {
+            Some(column) => {
+                let memory = instance.get_memory(&mut store, "memory").ok_or_else(|| {
+                    PluginError::Format("`column` needs an exported `memory`".into())
+                })?;
+                Some((column.typed(&store)?, memory))
+            }
+            None => None,
+        };
+        if let Some(init) = instance.get_func(&mut store, "init") {
+            let init: TypedFunc<(i32, i32), ()> = init.typed(&store)?;
+            store.set_fuel(INIT_FUEL)?;
+            init.call(&mut store, (seed as i32, size as i32))?;
+        }
+
+        Ok(Self {
+            store: RefCell::new(store),
+            lookup,
+            column,
+        })
+    }
+}
+
+/// Engine compiling modules that count the fuel they burn.
+fn engine() -> Result<Engine, PluginError> {
+    Ok(Engine::new(Config::new().consume_fuel(true))?)
+}
+
+/// Decodes a color returned by a module.
+fn to_voxel(color: i32) -> Option<Voxel> {
+    (color >= 0).then(|| {
+        let [_, r, g, b] = color.to_be_bytes();
+        Voxel::from(U8Vec3::new(r, g, b))
+    })
+}
+
+impl VoxelSource for PluginSource {
+    fn lookup(&self, pos: IVec3) -> Option<Voxel> {
+        let mut store = self.store.borrow_mut();
+        let color = store
+            .set_fuel(VOXEL_FUEL)
+            .and_then(|_| self.lookup.call(&mut *store, pos.to_array().into()))
+            .unwrap_or_else(|err| panic!("plugin failed at {pos}: {err}"));
+        to_voxel(color)
+    }
+
+    fn column(&self, x: i32, z: i32, ys: Range<i32>, out: &mut [Option<Voxel>]) {
+        debug_assert_eq!(out.len(), ys.len(), "column length mismatch");
+
+        let Some((column, memory)) = &self.column else {
+            for (y, voxel) in ys.zip(out) {
+                *voxel = self.lookup(IVec3::new(x, y, z));
+            }
+            return;
+        };
+
+        let mut store = self.store.borrow_mut();
+        let len = out.len() as i32;
+        let address = store
+            .set_fuel(VOXEL_FUEL * out.len() as u64)
+            .and_then(|_| column.call(&mut *store, (x, z, ys.start, len)))
+            .unwrap_or_else(|err| panic!("plugin failed at column {x}, {z}: {err}"));
+        let colors = (address as u32 as usize)
+            .checked_add(out.len() * 4)
+            .and_then(|end| memory.data(&*store).get(address as u32 as usize..end))
+            .unwrap_or_else(|| panic!("plugin returned column {x}, {z} outside its memory"));
+        for (voxel, color) in out.iter_mut().zip(colors.chunks_exact(4)) {
+            *voxel = to_voxel(i32::from_le_bytes(color.try_into().unwrap()));
+        }
+    }
+}
+
+/// Errors from loading a plugin.
+#[derive(Debug)]
+pub enum PluginError {
+    /// The module failed to compile, is missing an export, or trapped in `init`.
+    Wasm(wasmtime::Error),
+    /// The module does not follow the plugin interface.
+    Format(String),
+}
+
+impl fmt::Display for PluginError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PluginError::Wasm(err) => write!(f, "plugin error: {err:#}"),
+            PluginError::Format(err) => write!(f, "invalid plugin: {err}"),
+        }
+    }
+}
+
+impl Error for PluginError {}
+
+impl From<wasmtime::Error> for PluginError {
+    fn from(err: wasmtime::Error) -> Self {
+        PluginError::Wasm(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lookup() {
+        let plugin = PluginSource::new(
+            r#"
+            (module
+              (func (export "lookup") (param $x i32) (param $y i32) (param $z i32) (result i32)
+                (if (result i32) (i32.lt_s (local.get $y) (i32.const 0))
+                  (then (i32.add (i32.const 0x336600) (local.get $x)))
+                  (else (i32.const -1)))))
+            "#,
+            0,
+            10,
+        )
+        .expect("failed to load");
+        assert_eq!(plugin.lookup(IVec3::new(5, 0, 0)), None);
+        assert_eq!(
+            plugin.lookup(IVec3::new(5, -1, 0)).map(|v| v.color),
+            Some(U8Vec3::new(0x33, 0x66, 5))
+        );
+    }
+
+    #[test]
+    fn init() {
+        // solid below the seed, which `init` stores in a global
+        let plugin = PluginSource::new(
+            r#"
+            (module
+              (global $seed (mut i32) (i32.const 0))
+              (func (export "init") (param $seed i32) (param $size i32)
+                (global.set $seed (local.get $seed)))
+              (func (export "lookup") (param i32 i32 i32) (result i32)
+                (select (i32.const 0xffffff) (i32.const -1)
+                  (i32.lt_s (local.get 1) (global.get $seed)))))
+            "#,
+            7,
+            10,
+        )
+        .expect("failed to load");
+        assert!(plugin.lookup(IVec3::new(0, 6, 0)).is_some());
+        assert!(plugin.lookup(IVec3::new(0, 7, 0)).is_none());
+    }
+
+    #[test]
+    fn column_matches_lookup() {
+        let plugin = PluginSource::new(include_str!("../../plugins/pillars.wat"), 3, 16)
+            .expect("failed to load");
+        assert!(plugin.column.is_some());
+
+        let ys = -20..20;
+        let mut column = vec![None; ys.len()];
+        let mut solid = 0;
+        for x in -16..16 {
+            for z in [-9, 0, 4] {
+                plugin.column(x, z, ys.clone(), &mut column);
+                for (y, voxel) in ys.clone().zip(&column) {
+                    assert_eq!(*voxel, plugin.lookup(IVec3::new(x, y, z)), "{x} {y} {z}");
+                    solid += voxel.is_some() as usize;
+                }
+            }
+        }
+        assert!(solid > 0);
+    }
+
+    #[test]
+    fn errors() {
+        let load = |wat: &str| PluginSource::new(wat, 0, 10);
+        assert!(matches!(load("(module"), Err(PluginError::Wasm(_))));
+        assert!(matches!(load("(module)"), Err(PluginError::Wasm(_))));
+        // wrong signature
+        assert!(matches!(
+            load(r#"(module (func (export "lookup") (param i32 i32) (result i32) i32.const 0))"#),
+            Err(PluginError::Wasm(_))
+        ));
+        // no access to the host
+        assert!(matches!(
+            load(
+                r#"(module
+                  (import "env" "print" (func))
+                  (func (export "lookup") (param i32 i32 i32) (result i32) i32.const 0))"#
+            ),
+            Err(PluginError::Format(_))
+        ));
+        // init that never returns
+        assert!(matches!(
+            load(
+                r#"(module
+                  (func (export "init") (param i32 i32) (loop (br 0)))
+                  (func (export "lookup") (param i32 i32 i32) (result i32) i32.const 0))"#
+            ),
+            Err(PluginError::Wasm(_))
+        ));
+        // trap in init
+        assert!(matches!(
+            load(
+                r#"(module
+                  (func (export "init") (param i32 i32) unreachable)
+                  (func (export "lookup") (param i32 i32 i32) (result i32) i32.const 0))"#
+            ),
+            Err(PluginError::Wasm(_))
+        ));
+    }
+
+    /// A module that loops forever below the ground.
+    fn endless() -> PluginSource {
+        PluginSource::new(
+            r#"
+            (module
+              (memory (export "memory") 1)
+              (func (export "lookup") (param i32 i32 i32) (result i32)
+                (if (i32.lt_s (local.get 1) (i32.const 0)) (then (loop (br 0))))
+                (i32.const -1))
+              (func (export "column") (param i32 i32 i32 i32) (result i32)
+                (if (i32.lt_s (local.get 2) (i32.const 0)) (then (loop (br 0))))
+                (i32.const 0)))
+            "#,
+            0,
+            10,
+        )
+        .expect("failed to load")
+    }
+
+    #[test]
+    #[should_panic(expected = "plugin failed at")]
+    fn endless_lookup_is_stopped() {
+        let plugin = endless();
+        assert_eq!(plugin.lookup(IVec3::ZERO), None);
+        plugin.lookup(IVec3::NEG_Y);
+    }
+
+    #[test]
+    #[should_panic(expected = "plugin failed at column")]
+    fn endless_column_is_stopped() {
+        let plugin = endless();
+        plugin.column(0, 0, 0..4, &mut [None; 4]);
+        plugin.column(0, 0, -4..0, &mut [None; 4]);
+    }
+}
@@ -0,0 +1,331 @@
+use std::{cell::RefCell, error::Error, fmt, fs, io, path::Path};
+
+use glam::{IVec2, IVec3, U8Vec3};
+use noise::{NoiseFn, Perlin};
+use rhai::{
+    Array, CallFnOptions, Dynamic, Engine, EvalAltResult, FuncArgs, Module, Scope, AST, FLOAT, INT,
+};
+
+use super::{Voxel, VoxelKind, VoxelSource};
+
+/// Operations a script can run, counting each expression and statement, for each call to `lookup` or `column` before
+/// the call is stopped, so a script stuck in a loop can't hang the render.
+const VOXEL_OPERATIONS: u64 = 1_000_000;
+
+/// Operations a script's top level statements can run, enough to fill tables for the whole scene.
+const INIT_OPERATIONS: u64 = 100_000_000;
+
+/// Voxels defined by a [Rhai](https://rhai.rs) script.
+///
+/// The script defines `fn lookup(x, y, z)`, returning the voxel's color as `[r, g, b]` or `()` for empty space.
+/// A fourth element, `[r, g, b, kind]`, sets the voxel's [`VoxelKind`] by number.
+/// Work shared by a whole column can go in an optional `fn column(x, z)`, whose result is passed to
+/// `fn lookup(x, y, z, column)` and computed once per column. Scripts can sample seeded noise with
+/// `perlin(x, y)` and `perlin(x, y, z)`, and get the scene size with `size()`.
+///
+/// The script's top level statements run once when it is loaded, and the constants they define can be read as
+/// `global::NAME` from its functions. Each call can only run for so long, see [`VOXEL_OPERATIONS`]. Errors while the
+/// script is running, including running out of time, panic, since voxel lookups cannot fail. Loading a script calls
+/// it once at the origin so most mistakes are reported before rendering.
+pub struct ScriptSource {
+    engine: Engine,
+    ast: AST,
+    /// Variables defined by the script's top level statements.
+    scope: RefCell<Scope<'static>>,
+    /// Whether the script has a `column` function.
+    columns: bool,
+    /// The result of `column` for the last column looked up.
+    cache: RefCell<Option<(IVec2, Dynamic)>>,
+}
+
+impl ScriptSource {
+    /// Compiles a script, using the seed for its noise.
+    pub fn new(script: &str, seed: u32, size: u32) -> Result<Self, ScriptError> {
+        let mut engine = Engine::new();
+        let perlin = Perlin::new(seed);
+        engine.register_fn("perlin", move |x: FLOAT, y: FLOAT| perlin.get([x, y]));
+        let perlin = Perlin::new(seed);
+        engine.register_fn("perlin", move |x: FLOAT, y: FLOAT, z: FLOAT| {
+            perlin.get([x, y, z])
+        });
+        engine.register_fn("size", move || size as INT);
+
+        let ast = engine.compile(script)?;
+
+        let arity = |name| {
+            ast.iter_functions()
+                .find(|f| f.name == name)
+                .map(|f| f.params.len())
+        };
+        let columns = match (arity("lookup"), arity("column")) {
+            (Some(3), None) => false,
+            (Some(4), Some(2)) => true,
+            (None, _) => return Err(ScriptError::Format("missing `fn lookup(x, y, z)`".into())),
+            _ => {
+                return Err(ScriptError::Format(
+                    "expected `fn lookup(x, y, z)`, or `fn column(x, z)` and `fn lookup(x, y, z, column)`".into(),
+                ))
+            }
+        };
+
+        engine.set_max_operations(INIT_OPERATIONS);
+        let mut scope = Scope::new();
+        engine.run_ast_with_scope(&mut scope, &ast)?;
+        let mut constants = Module::new();
+        for (name, constant, value) in scope.iter() {
+            if constant {
+                constants.set_var(name, value);
+            }
+        }
+        engine.register_static_module("global", constants.into());
+        engine.set_max_operations(VOXEL_OPERATIONS);
+
+        let source = Self {
+            engine,
+            ast,
+            scope: RefCell::new(scope),
+            columns,
+            cache: RefCell::new(None),
+        };
+        source.try_lookup(IVec3::ZERO)?;
+        Ok(source)
+    }
+
+    /// Reads and compiles a script file.
+    pub fn load(path: impl AsRef<Path>, seed: u32, size: u32) -> Result<Self, ScriptError> {
+        let script = fs::read_to_string(path)?;
+        Self::new(&script, seed, size)
+    }
+
+    /// Calls a function in the script, without running its top level statements again.
+    fn call(&self, name: &str, args: impl FuncArgs) -> Result<Dynamic, ScriptError> {
+        let result = self.engine.call_fn_with_options(
+            CallFnOptions::new().eval_ast(false),
+            &mut self.scope.borrow_mut(),
+            &self.ast,
+            name,
+            args,
+        )?;
+        Ok(result)
+    }
+
+    /// Result of the script's `column` function, reusing the last one if it was for the same column.
+    fn column_value(&self, column: IVec2) -> Result<Dynamic, ScriptError> {
+        if let Some((cached, value)) = &*self.cache.borrow() {
+            if *cached == column {
+                return Ok(value.clone());
+            }
+        }
+        let value = self.call("column", (column.x as INT, column.y as INT))?;
+        *self.cache.borrow_mut() = Some((column, value.clone()));
+        Ok(value)
+    }
+
+    fn try_lookup(&self, pos: IVec3) -> Result<Option<Voxel>, ScriptError> {
+        let (x, y, z) = (pos.x as INT, pos.y as INT, pos.z as INT);
+        let result = if self.columns {
+            let column = self.column_value(IVec2::new(pos.x, pos.z))?;
+            self.call("lookup", (x, y, z, column))?
+        } else {
+            self.call("lookup", (x, y, z))?
+        };
+        to_voxel(result)
+    }
+}
+
+/// Converts the result of a script's `lookup` to a voxel.
+fn to_voxel(result: Dynamic) -> Result<Option<Voxel>, ScriptError> {
+    if result.is_unit() {
+        return Ok(None);
+    }
+    let invalid = || {
+        ScriptError::Format(format!(
+            "`lookup` returned {result}, expected [r, g, b], [r, g, b, kind] or ()"
+        ))
+    };
+    let rgb: Array = result.clone().try_cast().ok_or_else(invalid)?;
+    let channels: Vec<INT> = rgb.iter().filter_map(|c| c.as_int().ok()).collect();
+    let (r, g, b, kind) = match channels[..] {
+        [r, g, b] => (r, g, b, 0),
+        [r, g, b, kind] => (r, g, b, kind),
+        _ => return Err(invalid()),
+    };
+    let kind = u16::try_from(kind).map_err(|_| invalid())?;
+    let color = U8Vec3::new(
+        r.clamp(0, 255) as u8,
+        g.clamp(0, 255) as u8,
+        b.clamp(0, 255) as u8,
+    );
+    Ok(Some(Voxel::new(color, VoxelKind(kind))))
+}
+
+impl VoxelSource for ScriptSource {
+    fn lookup(&self, pos: IVec3) -> Option<Voxel> {
+        self.try_lookup(pos)
+            .unwrap_or_else(|err| panic!("script failed at {pos}: {err}"))
+    }
+}
+
+/// Errors from loading a script.
+#[derive(Debug)]
+pub enum ScriptError {
+    Io(io::Error),
+    /// The script failed to compile or run.
+    Script(Box<EvalAltResult>),
+    /// The script is missing a function or returned something that is not a voxel.
+    Format(String),
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScriptError::Io(err) => write!(f, "failed to read script: {err}"),
+            ScriptError::Script(err) => write!(f, "script error: {err}"),
+            ScriptError::Format(err) => write!(f, "invalid script: {err}"),
+        }
+    }
+}
+
+impl Error for ScriptError {}
+
+impl From<io::Error> for ScriptError {
+    fn from(err: io::Error) -> Self {
+        ScriptError::Io(err)
+    }
+}
+
+impl From<Box<EvalAltResult>> for ScriptError {
+    fn from(err: Box<EvalAltResult>) -> Self {
+        ScriptError::Script(err)
+    }
+}
+
+impl From<rhai::ParseError> for ScriptError {
+    fn from(err: rhai::ParseError) -> Self {
+        ScriptError::Script(err.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lookup() {
+        let source = ScriptSource::new(
+            r#"
+            fn lookup(x, y, z) {
+                if y < 0 { [x, 300, -5] } else { () }
+            }
+            "#,
+            0,
+            10,
+        )
+        .expect("failed to load");
+        assert_eq!(source.lookup(IVec3::new(1, 0, 0)), None);
+        // channels are clamped to 0..=255
+        assert_eq!(
+            source.lookup(IVec3::new(7, -1, 0)),
+            Some(Voxel::from(U8Vec3::new(7, 255, 0)))
+        );
+
+        let source = ScriptSource::new("fn lookup(x, y, z) { [0, 0, 255, 6] }", 0, 10)
+            .expect("failed to load");
+        assert_eq!(source.lookup(IVec3::ZERO).unwrap().kind, VoxelKind::WATER);
+    }
+
+    #[test]
+    fn column_matches_lookup() {
+        let source = ScriptSource::new(
+            r#"
+            const GREEN = [60, 180, 60];
+            fn column(x, z) {
+                (perlin(x * 0.1, z * 0.1) * size()).to_int()
+            }
+            fn lookup(x, y, z, height) {
+                if y <= height { global::GREEN } else { () }
+            }
+            "#,
+            4,
+            20,
+        )
+        .expect("failed to load");
+
+        let ys = -20..20;
+        let mut column = vec![None; ys.len()];
+        let mut heights = Vec::new();
+        for x in -10..10 {
+            source.column(x, 3, ys.clone(), &mut column);
+            for (y, voxel) in ys.clone().zip(&column) {
+                assert_eq!(*voxel, source.lookup(IVec3::new(x, y, 3)), "{x} {y}");
+            }
+            heights.push(column.iter().filter(|v| v.is_some()).count());
+        }
+        // the noise makes hills
+        assert!(heights.iter().min() < heights.iter().max(), "{heights:?}");
+    }
+
+    #[test]
+    fn example() {
+        let source = ScriptSource::new(include_str!("../../scripts/terraces.rhai"), 1, 50)
+            .expect("failed to load");
+        assert!(source.lookup(IVec3::new(0, -1, 0)).is_some());
+        assert!(source.lookup(IVec3::new(0, 50, 0)).is_none());
+    }
+
+    #[test]
+    fn errors() {
+        let load = |script| ScriptSource::new(script, 0, 10);
+        assert!(matches!(load("fn lookup("), Err(ScriptError::Script(_))));
+        assert!(matches!(load("fn other() {}"), Err(ScriptError::Format(_))));
+        assert!(matches!(
+            load("fn lookup(x, y, z, column) { () }"),
+            Err(ScriptError::Format(_))
+        ));
+        assert!(matches!(
+            load("fn lookup(x, y, z) { [1, 2] }"),
+            Err(ScriptError::Format(_))
+        ));
+        assert!(matches!(
+            load("fn lookup(x, y, z) { [1, 2, 3, -1] }"),
+            Err(ScriptError::Format(_))
+        ));
+        assert!(matches!(
+            load("fn lookup(x, y, z) { x / y }"),
+            Err(ScriptError::Script(_))
+        ));
+    }
+
+    #[test]
+    fn top_level_runs_once() {
+        // filling the table takes more operations than a single call may run
+        let source = ScriptSource::new(
+            r#"
+            const TABLE = {
+                let table = [];
+                for i in 0..300000 { table.push(i % 256); }
+                table
+            };
+            fn lookup(x, y, z) { [global::TABLE[x], 0, 0] }
+            "#,
+            0,
+            10,
+        )
+        .expect("failed to load");
+        for x in 0..4 {
+            assert_eq!(
+                source.lookup(IVec3::new(x, 0, 0)),
+                Some(Voxel::from(U8Vec3::new(x as u8, 0, 0)))
+            );
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "script failed at")]
+    fn endless_lookup_is_stopped() {
+        let source = ScriptSource::new("fn lookup(x, y, z) { if y < 0 { loop {} } () }", 0, 10)
+            .expect("failed to load");
+        assert_eq!(source.lookup(IVec3::ZERO), None);
+        source.lookup(IVec3::NEG_Y);
+    }
+}
@@ -0,0 +1,362 @@
+use glam::{IVec3, U8Vec3, Vec2, Vec3A, Vec3Swizzles};
+
+use super::{Voxel, VoxelSource};
+
+/// Color used for shapes that were not given one.
+const DEFAULT_COLOR: U8Vec3 = U8Vec3::new(200, 200, 200);
+
+/// A signed distance field built from primitives and CSG operators.
+///
+/// See: https://iquilezles.org/articles/distfunctions/
+#[derive(Clone, Debug, PartialEq)]
+pub enum Sdf {
+    Sphere {
+        center: Vec3A,
+        radius: f32,
+    },
+    Cuboid {
+        center: Vec3A,
+        half_extents: Vec3A,
+    },
+    /// Torus lying flat on the xz-plane.
+    Torus {
+        center: Vec3A,
+        major_radius: f32,
+        minor_radius: f32,
+    },
+    /// Half space below the plane (`dot(p, normal) <= offset`).
+    Plane {
+        normal: Vec3A,
+        offset: f32,
+    },
+    Colored(Box<Sdf>, U8Vec3),
+    Union(Box<Sdf>, Box<Sdf>),
+    /// First shape with the second removed.
+    Subtract(Box<Sdf>, Box<Sdf>),
+    Intersect(Box<Sdf>, Box<Sdf>),
+    /// Union with edges rounded over a distance `k`.
+    SmoothUnion(Box<Sdf>, Box<Sdf>, f32),
+    /// Subtraction with edges rounded over a distance `k`.
+    SmoothSubtract(Box<Sdf>, Box<Sdf>, f32),
+    /// Intersection with edges rounded over a distance `k`.
+    SmoothIntersect(Box<Sdf>, Box<Sdf>, f32),
+}
+
+impl Sdf {
+    pub fn sphere(center: Vec3A, radius: f32) -> Self {
+        Self::Sphere { center, radius }
+    }
+
+    pub fn cuboid(center: Vec3A, half_extents: Vec3A) -> Self {
+        Self::Cuboid {
+            center,
+            half_extents,
+        }
+    }
+
+    pub fn torus(center: Vec3A, major_radius: f32, minor_radius: f32) -> Self {
+        Self::Torus {
+            center,
+            major_radius,
+            minor_radius,
+        }
+    }
+
+    /// Creates a plane from a normal (normalized here) and its distance from the origin.
+    pub fn plane(normal: Vec3A, offset: f32) -> Self {
+        Self::Plane {
+            normal: normal.normalize(),
+            offset,
+        }
+    }
+
+    /// Sets the color of the shape.
+    pub fn color(self, color: U8Vec3) -> Self {
+        Self::Colored(Box::new(self), color)
+    }
+
+    pub fn union(self, other: Sdf) -> Self {
+        Self::Union(Box::new(self), Box::new(other))
+    }
+
+    pub fn subtract(self, other: Sdf) -> Self {
+        Self::Subtract(Box::new(self), Box::new(other))
+    }
+
+    pub fn intersect(self, other: Sdf) -> Self {
+        Self::Intersect(Box::new(self), Box::new(other))
+    }
+
+    pub fn smooth_union(self, other: Sdf, k: f32) -> Self {
+        Self::SmoothUnion(Box::new(self), Box::new(other), k)
+    }
+
+    pub fn smooth_subtract(self, other: Sdf, k: f32) -> Self {
+        Self::SmoothSubtract(Box::new(self), Box::new(other), k)
+    }
+
+    pub fn smooth_intersect(self, other: Sdf, k: f32) -> Self {
+        Self::SmoothIntersect(Box::new(self), Box::new(other), k)
+    }
+
+    /// Signed distance from the surface (negative inside).
+    pub fn distance(&self, p: Vec3A) -> f32 {
+        self.eval(p).0
+    }
+
+    /// Evaluates the signed distance and surface color at a point.
+    pub fn eval(&self, p: Vec3A) -> (f32, U8Vec3) {
+        match self {
+            Sdf::Sphere { center, radius } => ((p - *center).length() - radius, DEFAULT_COLOR),
+            Sdf::Cuboid {
+                center,
+                half_extents,
+            } => {
+                let q = (p - *center).abs() - *half_extents;
+                let d = q.max(Vec3A::ZERO).length() + q.max_element().min(0.0);
+                (d, DEFAULT_COLOR)
+            }
+            Sdf::Torus {
+                center,
+                major_radius,
+                minor_radius,
+            } => {
+                let p = p - *center;
+                let q = Vec2::new(p.xz().length() - major_radius, p.y);
+                (q.length() - minor_radius, DEFAULT_COLOR)
+            }
+            Sdf::Plane { normal, offset } => (p.dot(*normal) - offset, DEFAULT_COLOR),
+            Sdf::Colored(sdf, color) => (sdf.distance(p), *color),
+            Sdf::Union(a, b) => {
+                let (a, b) = (a.eval(p), b.eval(p));
+                if a.0 <= b.0 {
+                    a
+                } else {
+                    b
+                }
+            }
+            Sdf::Subtract(a, b) => {
+                let ((a, color), b) = (a.eval(p), b.distance(p));
+                (a.max(-b), color)
+            }
+            Sdf::Intersect(a, b) => {
+                let (a, b) = (a.eval(p), b.eval(p));
+                if a.0 >= b.0 {
+                    a
+                } else {
+                    b
+                }
+            }
+            Sdf::SmoothUnion(a, b, k) => {
+                let ((a, ca), (b, cb)) = (a.eval(p), b.eval(p));
+                let h = blend(b - a, *k);
+                (lerp(b, a, h) - k * h * (1.0 - h), lerp_color(cb, ca, h))
+            }
+            Sdf::SmoothSubtract(a, b, k) => {
+                let ((a, color), b) = (a.eval(p), b.distance(p));
+                let h = blend(-(b + a), *k);
+                (lerp(a, -b, h) + k * h * (1.0 - h), color)
+            }
+            Sdf::SmoothIntersect(a, b, k) => {
+                let ((a, ca), (b, cb)) = (a.eval(p), b.eval(p));
+                let h = blend(a - b, *k);
+                (lerp(b, a, h) + k * h * (1.0 - h), lerp_color(cb, ca, h))
+            }
+        }
+    }
+}
+
+/// How far to blend towards the second of two shapes `x` further from a point than the first, over a distance `k`, or
+/// a hard switch between them where `k` isn't positive.
+fn blend(x: f32, k: f32) -> f32 {
+    if k > 0.0 {
+        (0.5 + 0.5 * x / k).clamp(0.0, 1.0)
+    } else {
+        if x >= 0.0 {
+            1.0
+        } else {
+            0.0
+        }
+    }
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+fn lerp_color(a: U8Vec3, b: U8Vec3, t: f32) -> U8Vec3 {
+    a.as_vec3a().lerp(b.as_vec3a(), t).round().as_u8vec3()
+}
+
+/// Voxelizes a signed distance field, filling voxels whose centers are inside of the surface.
+#[derive(Clone, Debug)]
+pub struct SdfSource {
+    sdf: Sdf,
+}
+
+impl SdfSource {
+    pub fn new(sdf: Sdf) -> Self {
+        Self { sdf }
+    }
+}
+
+impl VoxelSource for SdfSource {
+    fn lookup(&self, pos: IVec3) -> Option<Voxel> {
+        let (d, color) = self.sdf.eval(pos.as_vec3a() + 0.5);
+        (d <= 0.0).then_some(Voxel::from(color))
+    }
+}
+
+/// Demo scene of each primitive on a ground plane, scaled to a scene size.
+pub fn shapes(size: f32) -> Sdf {
+    let r = size / 5.0;
+
+    Sdf::plane(Vec3A::Y, 0.0)
+        .color(U8Vec3::new(90, 90, 100))
+        .union(Sdf::sphere(Vec3A::new(-2.0 * r, r, -2.0 * r), r).color(U8Vec3::new(220, 60, 60)))
+        .union(
+            Sdf::cuboid(Vec3A::new(2.0 * r, r, -2.0 * r), Vec3A::splat(0.8 * r))
+                .color(U8Vec3::new(60, 200, 90)),
+        )
+        .union(
+            Sdf::torus(Vec3A::new(-2.0 * r, 0.4 * r, 2.0 * r), r, 0.4 * r)
+                .color(U8Vec3::new(70, 110, 230)),
+        )
+}
+
+/// Demo scene of the hard CSG operators: a rounded die with holes drilled through it.
+pub fn csg(size: f32) -> Sdf {
+    let r = size / 2.0;
+    let drill = |half_extents: Vec3A| Sdf::cuboid(Vec3A::ZERO, half_extents);
+
+    let die = Sdf::cuboid(Vec3A::ZERO, Vec3A::splat(r))
+        .intersect(Sdf::sphere(Vec3A::ZERO, 1.35 * r))
+        .color(U8Vec3::new(230, 180, 60));
+    let holes = drill(Vec3A::new(1.5 * r, 0.5 * r, 0.5 * r))
+        .union(drill(Vec3A::new(0.5 * r, 1.5 * r, 0.5 * r)))
+        .union(drill(Vec3A::new(0.5 * r, 0.5 * r, 1.5 * r)));
+
+    die.subtract(holes)
+}
+
+/// Demo scene of the smooth operators: blended blobs with a smooth bite taken out.
+pub fn blobs(size: f32) -> Sdf {
+    let r = size / 4.0;
+    let k = r / 2.0;
+
+    Sdf::sphere(Vec3A::ZERO, r)
+        .color(U8Vec3::new(230, 90, 160))
+        .smooth_union(
+            Sdf::sphere(Vec3A::new(1.5 * r, 0.5 * r, 0.0), 0.8 * r)
+                .color(U8Vec3::new(90, 160, 230)),
+            k,
+        )
+        .smooth_union(
+            Sdf::sphere(Vec3A::new(-0.5 * r, 1.2 * r, 0.8 * r), 0.7 * r)
+                .color(U8Vec3::new(250, 220, 80)),
+            k,
+        )
+        .smooth_subtract(Sdf::sphere(Vec3A::new(0.3 * r, 0.3 * r, r), 0.6 * r), k)
+        .smooth_intersect(Sdf::plane(Vec3A::NEG_Y, r), k)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn primitive_distances() {
+        let sphere = Sdf::sphere(Vec3A::ZERO, 2.0);
+        assert_eq!(sphere.distance(Vec3A::ZERO), -2.0);
+        assert_eq!(sphere.distance(Vec3A::X * 3.0), 1.0);
+
+        let cuboid = Sdf::cuboid(Vec3A::ZERO, Vec3A::ONE);
+        assert_eq!(cuboid.distance(Vec3A::ZERO), -1.0);
+        assert_eq!(cuboid.distance(Vec3A::Y * 3.0), 2.0);
+
+        let torus = Sdf::torus(Vec3A::ZERO, 3.0, 1.0);
+        assert_eq!(torus.distance(Vec3A::X * 3.0), -1.0);
+        assert_eq!(torus.distance(Vec3A::ZERO), 2.0);
+
+        let plane = Sdf::plane(Vec3A::Y * 2.0, 1.0);
+        assert_eq!(plane.distance(Vec3A::ZERO), -1.0);
+        assert_eq!(plane.distance(Vec3A::Y * 4.0), 3.0);
+    }
+
+    #[test]
+    fn csg_operators() {
+        let a = || Sdf::sphere(Vec3A::ZERO, 2.0).color(U8Vec3::X);
+        let b = || Sdf::sphere(Vec3A::X * 2.0, 2.0).color(U8Vec3::Y);
+
+        let union = a().union(b());
+        assert!(union.distance(Vec3A::X * -1.5) < 0.0);
+        assert!(union.distance(Vec3A::X * 3.5) < 0.0);
+        assert_eq!(union.eval(Vec3A::X * 3.5).1, U8Vec3::Y);
+
+        let subtract = a().subtract(b());
+        assert!(subtract.distance(Vec3A::X * -1.5) < 0.0);
+        assert!(subtract.distance(Vec3A::X) > 0.0);
+
+        let intersect = a().intersect(b());
+        assert!(intersect.distance(Vec3A::X) < 0.0);
+        assert!(intersect.distance(Vec3A::X * -1.5) > 0.0);
+    }
+
+    #[test]
+    fn smooth_operators_match_hard_far_from_seam() {
+        let a = || Sdf::sphere(Vec3A::ZERO, 2.0);
+        let b = || Sdf::sphere(Vec3A::X * 10.0, 2.0);
+        let p = Vec3A::X * -1.0;
+
+        assert_eq!(
+            a().smooth_union(b(), 1.0).distance(p),
+            a().union(b()).distance(p)
+        );
+        assert_eq!(
+            a().smooth_subtract(b(), 1.0).distance(p),
+            a().subtract(b()).distance(p)
+        );
+    }
+
+    #[test]
+    fn smooth_operators_are_hard_without_rounding() {
+        let a = || Sdf::sphere(Vec3A::ZERO, 2.0);
+        let b = || Sdf::sphere(Vec3A::X * 3.0, 2.0);
+
+        for p in [Vec3A::ZERO, Vec3A::X * 1.5, Vec3A::new(1.5, 1.0, 0.0)] {
+            for k in [0.0, -1.0] {
+                assert_eq!(
+                    a().smooth_union(b(), k).distance(p),
+                    a().union(b()).distance(p)
+                );
+                assert_eq!(
+                    a().smooth_subtract(b(), k).distance(p),
+                    a().subtract(b()).distance(p)
+                );
+                assert_eq!(
+                    a().smooth_intersect(b(), k).distance(p),
+                    a().intersect(b()).distance(p)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn smooth_union_fills_seam() {
+        let a = || Sdf::sphere(Vec3A::X * -2.0, 1.9);
+        let b = || Sdf::sphere(Vec3A::X * 2.0, 1.9);
+
+        // the spheres do not touch, but blending closes the gap between them
+        assert!(a().union(b()).distance(Vec3A::ZERO) > 0.0);
+        assert!(a().smooth_union(b(), 1.0).distance(Vec3A::ZERO) < 0.0);
+    }
+
+    #[test]
+    fn voxelize() {
+        let source = SdfSource::new(Sdf::sphere(Vec3A::ZERO, 3.0).color(U8Vec3::X));
+
+        assert_eq!(source.lookup(IVec3::ZERO), Some(Voxel::from(U8Vec3::X)));
+        assert_eq!(source.lookup(IVec3::new(-3, 1, -2)), None);
+        assert_eq!(source.lookup(IVec3::new(5, 0, 0)), None);
+    }
+}
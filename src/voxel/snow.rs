@@ -0,0 +1,203 @@
+use std::ops::Range;
+
+use glam::IVec3;
+
+use super::{TerrainSettings, Voxel, VoxelKind, VoxelSource, SNOW_WHITE};
+
+/// Snow laid on the ground by [`Snow`].
+pub const SNOW: Voxel = Voxel::new(SNOW_WHITE, VoxelKind::SNOW);
+
+/// Settings for laying snow on the ground of a source.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SnowSettings {
+    /// Snow settles on ground at or above this height.
+    pub altitude: i32,
+    /// Voxels of snow piled on the ground.
+    pub depth: i32,
+    /// How far above the ground a voxel keeps the snow off it, like an overhang or a cave ceiling.
+    pub reach: i32,
+}
+
+impl Default for SnowSettings {
+    fn default() -> Self {
+        let terrain = TerrainSettings::default();
+        Self {
+            altitude: (terrain.snow_level * terrain.height as f64) as i32,
+            depth: 1,
+            reach: 32,
+        }
+    }
+}
+
+/// A source with snow piled on every patch of ground that faces the open sky above an altitude.
+///
+/// Ground is the top of a solid voxel other than water, and it faces the sky if nothing lies within
+/// [`SnowSettings::reach`] straight above it, so overhangs, cave ceilings and trees keep the ground below them
+/// clear while their own tops are covered. Each column is looked up that much further up than asked for, so it is
+/// cheapest on sources that answer whole columns quickly.
+#[derive(Clone, Debug)]
+pub struct Snow<S> {
+    source: S,
+    settings: SnowSettings,
+}
+
+impl<S: VoxelSource> Snow<S> {
+    pub fn new(source: S, settings: SnowSettings) -> Self {
+        Self { source, settings }
+    }
+
+    pub fn settings(&self) -> SnowSettings {
+        self.settings
+    }
+}
+
+impl<S: VoxelSource> VoxelSource for Snow<S> {
+    fn lookup(&self, pos: IVec3) -> Option<Voxel> {
+        let mut voxel = [None];
+        self.column(pos.x, pos.z, pos.y..pos.y + 1, &mut voxel);
+        voxel[0]
+    }
+
+    fn column(&self, x: i32, z: i32, ys: Range<i32>, out: &mut [Option<Voxel>]) {
+        debug_assert_eq!(out.len(), ys.len(), "column length mismatch");
+
+        let SnowSettings {
+            altitude,
+            depth,
+            reach,
+        } = self.settings;
+        // no snow reaches down into the column
+        if ys.end <= altitude + 1 || depth <= 0 {
+            self.source.column(x, z, ys, out);
+            return;
+        }
+
+        // from the lowest ground the snow could lie on to the highest voxel that could keep it off
+        let start = ys.start - depth;
+        let mut column = vec![None; (ys.end + depth + reach - start) as usize];
+        self.source
+            .column(x, z, start..start + column.len() as i32, &mut column);
+        let at = |y: i32| column[(y - start) as usize];
+
+        for (y, slot) in ys.zip(out) {
+            *slot = at(y);
+            if slot.is_some() {
+                continue;
+            }
+            let ground = (y - depth..y).rev().find(|&y| at(y).is_some());
+            let Some(ground) = ground.filter(|&ground| ground >= altitude) else {
+                continue;
+            };
+            if at(ground).is_some_and(|voxel| voxel.kind == VoxelKind::WATER) {
+                continue;
+            }
+            // like a short ray straight up from the ground, which anything in the way keeps the snow from falling along
+            if (ground + 1..=ground + depth + reach).all(|y| at(y).is_none()) {
+                *slot = Some(SNOW);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{U8Vec3, Vec3A};
+
+    use super::*;
+    use crate::voxel::{
+        combinator::VoxelSourceExt,
+        grid::VoxelGrid,
+        sdf::{Sdf, SdfSource},
+        water::WATER,
+        VoxelGenerator,
+    };
+
+    fn cuboid(center: Vec3A, half_extents: Vec3A) -> SdfSource {
+        SdfSource::new(Sdf::cuboid(center, half_extents).color(U8Vec3::new(130, 130, 130)))
+    }
+
+    #[test]
+    fn snow_settles_on_open_ground() {
+        // a slab of rock from y 0 to 10, with a ledge hanging over part of it from y 14 to 16
+        let rock = || {
+            cuboid(Vec3A::new(0.0, 5.0, 0.0), Vec3A::new(10.0, 5.0, 10.0)).union(cuboid(
+                Vec3A::new(5.0, 15.0, 0.0),
+                Vec3A::new(5.0, 1.0, 10.0),
+            ))
+        };
+        let snow = Snow::new(
+            rock(),
+            SnowSettings {
+                altitude: 5,
+                depth: 2,
+                reach: 8,
+            },
+        );
+
+        // in the open, the ground is covered
+        assert_eq!(
+            snow.lookup(IVec3::new(-5, 9, 0)),
+            rock().lookup(IVec3::new(-5, 9, 0))
+        );
+        assert_eq!(snow.lookup(IVec3::new(-5, 10, 0)), Some(SNOW));
+        assert_eq!(snow.lookup(IVec3::new(-5, 11, 0)), Some(SNOW));
+        assert_eq!(snow.lookup(IVec3::new(-5, 12, 0)), None);
+        // under the ledge it stays clear, and the top of the ledge is covered
+        assert_eq!(snow.lookup(IVec3::new(5, 10, 0)), None);
+        assert_eq!(snow.lookup(IVec3::new(5, 16, 0)), Some(SNOW));
+
+        // ground below the altitude stays clear
+        let high = Snow::new(
+            rock(),
+            SnowSettings {
+                altitude: 12,
+                ..snow.settings()
+            },
+        );
+        assert_eq!(high.lookup(IVec3::new(-5, 10, 0)), None);
+        assert_eq!(high.lookup(IVec3::new(5, 16, 0)), Some(SNOW));
+    }
+
+    #[test]
+    fn snow_skips_water() {
+        let mut lake = VoxelGrid::new(IVec3::new(3, 2, 1));
+        for x in 0..3 {
+            lake.set(IVec3::new(x, 0, 0), Some(Voxel::from(U8Vec3::splat(130))));
+        }
+        lake.set(IVec3::new(1, 1, 0), Some(WATER));
+        let snow = Snow::new(
+            lake,
+            SnowSettings {
+                altitude: 0,
+                ..Default::default()
+            },
+        );
+        assert_eq!(snow.lookup(IVec3::new(0, 1, 0)), Some(SNOW));
+        assert_eq!(snow.lookup(IVec3::new(1, 2, 0)), None);
+    }
+
+    #[test]
+    fn columns_match_lookups() {
+        let snow = Snow::new(
+            VoxelGenerator::new_from_seed(0),
+            SnowSettings {
+                altitude: 50,
+                depth: 2,
+                reach: 8,
+            },
+        );
+        let ys = 0..100;
+        let mut column = vec![None; ys.len()];
+        let mut covered = 0;
+        for x in -8..8 {
+            for z in -8..8 {
+                snow.column(x, z, ys.clone(), &mut column);
+                for (y, voxel) in ys.clone().zip(&column) {
+                    assert_eq!(*voxel, snow.lookup(IVec3::new(x, y, z)), "{x} {y} {z}");
+                    covered += (*voxel == Some(SNOW)) as usize;
+                }
+            }
+        }
+        assert!(covered > 0);
+    }
+}
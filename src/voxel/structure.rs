@@ -0,0 +1,378 @@
+use std::str::FromStr;
+
+use glam::{IVec2, IVec3, U8Vec3};
+
+use super::{
+    grid::VoxelGrid,
+    vegetation::{hash, unit},
+    Voxel, VoxelKind,
+};
+
+/// Size of the grid cells structures are placed in, each cell holds at most one structure.
+const CELL_SIZE: i32 = 48;
+
+/// Widest a prefab can be along x or z, as it has to fit in one of the cells structures are placed in.
+pub const MAX_PREFAB_WIDTH: i32 = CELL_SIZE;
+
+const PLANKS: Voxel = Voxel::new(U8Vec3::new(160, 115, 65), VoxelKind::PLANKS);
+const LOGS: Voxel = Voxel::new(U8Vec3::new(95, 65, 35), VoxelKind::WOOD);
+const ROOF: Voxel = Voxel::new(U8Vec3::new(150, 50, 40), VoxelKind::TILE);
+const STONE: Voxel = Voxel::new(U8Vec3::new(115, 110, 105), VoxelKind::STONE);
+const MOSS: Voxel = Voxel::new(U8Vec3::new(85, 110, 60), VoxelKind::GRASS);
+
+/// Color band of the terrain a structure can be built on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Ground {
+    Grass,
+    Mountain,
+    Snow,
+}
+
+impl Ground {
+    /// Index of the band in [`super::TerrainSettings::band`].
+    fn band(self) -> usize {
+        match self {
+            Ground::Grass => 1,
+            Ground::Mountain => 2,
+            Ground::Snow => 3,
+        }
+    }
+}
+
+impl FromStr for Ground {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "grass" => Ok(Ground::Grass),
+            "mountain" => Ok(Ground::Mountain),
+            "snow" => Ok(Ground::Snow),
+            _ => Err(format!(
+                "invalid ground `{s}`, expected grass, mountain, or snow"
+            )),
+        }
+    }
+}
+
+/// A structure model and the rules for where it fits on the terrain.
+#[derive(Clone, Debug)]
+pub struct Prefab {
+    /// Voxels of the structure, standing on the bottom of the grid.
+    pub grid: VoxelGrid,
+    /// Terrain the structure is built on.
+    pub ground: Ground,
+    /// Largest difference in ground height under the structure.
+    ///
+    /// The ground is leveled to the middle height, cut away above the floor and
+    /// filled in below it with the structure's bottom layer.
+    pub max_slope: i32,
+    /// Number of voxels the structure is sunk into the ground.
+    pub depth: i32,
+}
+
+impl Prefab {
+    /// Creates a prefab for nearly flat ground, cropping the grid to its voxels.
+    pub fn new(grid: VoxelGrid, ground: Ground) -> Self {
+        Self {
+            grid: grid.cropped(),
+            ground,
+            max_slope: 1,
+            depth: 0,
+        }
+    }
+
+    /// A wooden hut with a pointed roof, built on flat grass.
+    pub fn hut() -> Self {
+        let size = IVec3::new(7, 8, 7);
+        let mut grid = VoxelGrid::new(size);
+        for y in 0..size.y {
+            for z in 0..size.z {
+                for x in 0..size.x {
+                    let edge_x = x == 0 || x == size.x - 1;
+                    let edge_z = z == 0 || z == size.z - 1;
+                    let voxel = match y {
+                        0 => Some(PLANKS),
+                        1..=3 if edge_x && edge_z => Some(LOGS),
+                        // door in the front wall and windows in the sides
+                        1..=2 if x == 3 && z == 0 => None,
+                        2 if edge_x && z == 3 => None,
+                        1..=3 if edge_x || edge_z => Some(PLANKS),
+                        1..=3 => None,
+                        _ => {
+                            // hollow pyramid, shrinking by one voxel per layer
+                            let inset = y - 4;
+                            let ring = x == inset
+                                || x == size.x - 1 - inset
+                                || z == inset
+                                || z == size.z - 1 - inset;
+                            let inside = (inset..size.x - inset).contains(&x)
+                                && (inset..size.z - inset).contains(&z);
+                            (inside && ring).then_some(ROOF)
+                        }
+                    };
+                    grid.set(IVec3::new(x, y, z), voxel);
+                }
+            }
+        }
+
+        Self {
+            max_slope: 6,
+            ..Self::new(grid, Ground::Grass)
+        }
+    }
+
+    /// Crumbling, mossy stone walls half buried on a mountainside.
+    pub fn ruin() -> Self {
+        let size = IVec3::new(9, 6, 9);
+        let mut grid = VoxelGrid::new(size);
+        for z in 0..size.z {
+            for x in 0..size.x {
+                let edge_x = x == 0 || x == size.x - 1;
+                let edge_z = z == 0 || z == size.z - 1;
+                let height = match (edge_x, edge_z) {
+                    (true, true) => size.y,
+                    (true, false) | (false, true) => 1 + (x * 7 + z * 13) % 4,
+                    (false, false) => 1,
+                };
+                for y in 0..height {
+                    // knock a few stones out of the walls
+                    if y > 0 && (x * 3 + y * 5 + z * 11) % 7 == 0 {
+                        continue;
+                    }
+                    let voxel = if (x + y * 2 + z) % 3 == 0 {
+                        MOSS
+                    } else {
+                        STONE
+                    };
+                    grid.set(IVec3::new(x, y, z), Some(voxel));
+                }
+            }
+        }
+
+        Self {
+            max_slope: 10,
+            depth: 2,
+            ..Self::new(grid, Ground::Mountain)
+        }
+    }
+}
+
+/// Settings for placing structures on the terrain.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StructureSettings {
+    /// Chance (0 to 1) that a grid cell gets a structure, if the chosen prefab fits there.
+    pub density: f64,
+}
+
+impl Default for StructureSettings {
+    fn default() -> Self {
+        Self { density: 0.5 }
+    }
+}
+
+/// A prefab stamped onto the terrain with its lowest corner at `base`.
+#[derive(Clone, Copy, Debug)]
+pub struct Structure<'a> {
+    prefab: &'a Prefab,
+    pub base: IVec3,
+}
+
+impl Structure<'_> {
+    /// Lookup the structure voxel at some position, including the foundation below it.
+    pub fn voxel(&self, pos: IVec3) -> Option<Voxel> {
+        let local = pos - self.base;
+        if (-self.prefab.max_slope..0).contains(&local.y) {
+            return self.prefab.grid.get(local.with_y(0));
+        }
+        self.prefab.grid.get(local)
+    }
+
+    /// Highest y coordinate covered by the structure.
+    pub fn top(&self) -> i32 {
+        self.base.y + self.prefab.grid.size().y - 1
+    }
+
+    /// Checks if the terrain is cut away at a position to make room for the structure.
+    pub fn clears(&self, pos: IVec3) -> bool {
+        let local = pos - self.base;
+        let size = self.prefab.grid.size();
+        local.cmpge(IVec3::new(0, self.prefab.depth, 0)).all() && local.cmplt(size).all()
+    }
+}
+
+/// Seeded placement of prefabs on a grid over the (x, z) plane.
+#[derive(Clone)]
+pub struct Structures {
+    settings: StructureSettings,
+    prefabs: Vec<Prefab>,
+    seed: u32,
+}
+
+impl Structures {
+    pub fn new(seed: u32, settings: StructureSettings, prefabs: Vec<Prefab>) -> Self {
+        Self {
+            settings,
+            prefabs,
+            seed,
+        }
+    }
+
+    /// Finds the structure (if any) covering the column at (x, z).
+    ///
+    /// Each cell picks a prefab and a spot for it, which is kept if the ground under the corners and middle of the
+    /// structure matches its rules.
+    /// `ground` returns the ground height and color band of a column, or `None` if nothing can be built there.
+    pub fn structure(
+        &self,
+        x: i32,
+        z: i32,
+        ground: impl Fn(i32, i32) -> Option<(i32, usize)>,
+    ) -> Option<Structure<'_>> {
+        let cell = IVec2::new(x, z).div_euclid(IVec2::splat(CELL_SIZE));
+        let roll = |salt| unit(hash(self.seed, cell.x, cell.y, salt));
+        if self.prefabs.is_empty() || roll(0) >= self.settings.density {
+            return None;
+        }
+
+        let i = (roll(1) * self.prefabs.len() as f64) as usize;
+        let prefab = &self.prefabs[i.min(self.prefabs.len() - 1)];
+        let size = prefab.grid.size();
+        let span = IVec2::splat(CELL_SIZE) - IVec2::new(size.x, size.z);
+        if span.min_element() < 0 {
+            return None;
+        }
+
+        let min = cell * CELL_SIZE
+            + IVec2::new(
+                (roll(2) * (span.x + 1) as f64) as i32,
+                (roll(3) * (span.y + 1) as f64) as i32,
+            );
+        let max = min + IVec2::new(size.x, size.z) - 1;
+        if x < min.x || x > max.x || z < min.y || z > max.y {
+            return None;
+        }
+
+        let middle = (min + max) / 2;
+        let mut low = i32::MAX;
+        let mut high = i32::MIN;
+        for (sx, sz) in [
+            (min.x, min.y),
+            (max.x, min.y),
+            (min.x, max.y),
+            (max.x, max.y),
+            (middle.x, middle.y),
+        ] {
+            let (height, band) = ground(sx, sz)?;
+            if band != prefab.ground.band() {
+                return None;
+            }
+            low = low.min(height);
+            high = high.max(height);
+        }
+        if high - low > prefab.max_slope {
+            return None;
+        }
+
+        let level = (low + high) / 2;
+        Some(Structure {
+            prefab,
+            base: IVec3::new(min.x, level + 1 - prefab.depth, min.y),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn everywhere() -> Structures {
+        Structures::new(
+            3,
+            StructureSettings { density: 1.0 },
+            vec![Prefab::hut(), Prefab::ruin()],
+        )
+    }
+
+    #[test]
+    fn prefab_shapes() {
+        let hut = Prefab::hut();
+        assert_eq!(hut.grid.size(), IVec3::new(7, 8, 7));
+        // the door is open and the roof is closed at the top
+        assert_eq!(hut.grid.get(IVec3::new(3, 1, 0)), None);
+        assert!(hut.grid.get(IVec3::new(2, 1, 0)).is_some());
+        assert_eq!(hut.grid.get(IVec3::new(3, 7, 3)), Some(ROOF));
+
+        let ruin = Prefab::ruin();
+        assert_eq!(ruin.ground, Ground::Mountain);
+        assert!(ruin.grid.get(IVec3::new(0, 5, 0)).is_some());
+    }
+
+    #[test]
+    fn placed_on_matching_ground() {
+        let structures = everywhere();
+        let mut found = [0; 2];
+        for x in (0..CELL_SIZE * 8).step_by(2) {
+            for z in (0..CELL_SIZE * 8).step_by(2) {
+                let grass = structures.structure(x, z, |_, _| Some((20, 1)));
+                let mountain = structures.structure(x, z, |_, _| Some((20, 2)));
+                // gentle slopes are leveled
+                let sloped = structures.structure(x, z, |x, _| Some((20 + x % 3, 1)));
+                assert_eq!(sloped.is_some(), grass.is_some());
+                assert!(grass.is_none() || mountain.is_none());
+
+                if let Some(hut) = grass {
+                    assert_eq!(hut.prefab.ground, Ground::Grass);
+                    assert_eq!(hut.base.y, 21);
+                    assert!(hut.clears(hut.base + IVec3::new(3, 1, 3)));
+                    assert!(!hut.clears(hut.base - IVec3::Y));
+                    // the foundation fills in below the floor
+                    assert!(hut.voxel(hut.base - IVec3::Y).is_some());
+                    found[0] += 1;
+                }
+                if let Some(ruin) = mountain {
+                    assert_eq!(ruin.prefab.ground, Ground::Mountain);
+                    // sunk into the ground
+                    assert_eq!(ruin.base.y, 19);
+                    assert!(!ruin.clears(ruin.base + IVec3::new(4, 1, 4)));
+                    found[1] += 1;
+                }
+            }
+        }
+        assert!(found[0] > 0 && found[1] > 0, "{found:?}");
+    }
+
+    #[test]
+    fn unsuitable_ground() {
+        let structures = everywhere();
+        for x in 0..CELL_SIZE * 4 {
+            let z = x / 2;
+            assert!(structures.structure(x, z, |_, _| None).is_none());
+            assert!(structures.structure(x, z, |_, _| Some((20, 0))).is_none());
+            // too steep for either prefab
+            for band in [1, 2] {
+                assert!(structures
+                    .structure(x, z, |x, _| Some((x * 2, band)))
+                    .is_none());
+            }
+        }
+    }
+
+    #[test]
+    fn widest_prefab_is_placed() {
+        let mut grid = VoxelGrid::new(IVec3::new(MAX_PREFAB_WIDTH, 1, MAX_PREFAB_WIDTH));
+        grid.set(IVec3::ZERO, Some(STONE));
+        grid.set(
+            IVec3::new(MAX_PREFAB_WIDTH - 1, 0, MAX_PREFAB_WIDTH - 1),
+            Some(STONE),
+        );
+        let prefab = Prefab::new(grid, Ground::Grass);
+        let structures = Structures::new(1, StructureSettings { density: 1.0 }, vec![prefab]);
+        assert!(structures.structure(5, 5, |_, _| Some((20, 1))).is_some());
+    }
+
+    #[test]
+    fn parse_ground() {
+        assert_eq!("Mountain".parse(), Ok(Ground::Mountain));
+        assert!("sand".parse::<Ground>().is_err());
+    }
+}
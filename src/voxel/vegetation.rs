@@ -0,0 +1,201 @@
+use glam::{IVec3, U8Vec3};
+
+use super::{Voxel, VoxelKind};
+
+/// Size of the grid cells plants are placed in, each cell holds at most one plant.
+const CELL_SIZE: i32 = 12;
+
+/// Largest canopy radius, plants are kept this far from the cell edges so they never cross into a neighbour.
+const MAX_RADIUS: i32 = 3;
+
+const TRUNK: Voxel = Voxel::new(U8Vec3::new(100, 70, 40), VoxelKind::WOOD);
+const LEAVES: Voxel = Voxel::new(U8Vec3::new(35, 115, 35), VoxelKind::LEAVES);
+const BUSH: Voxel = Voxel::new(U8Vec3::new(70, 140, 45), VoxelKind::LEAVES);
+
+/// Settings for scattering trees and bushes over the terrain.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VegetationSettings {
+    /// Chance (0 to 1) that a grid cell with suitable ground gets a plant.
+    pub density: f64,
+    /// Fraction of plants that are bushes instead of trees.
+    pub bushes: f64,
+}
+
+impl Default for VegetationSettings {
+    fn default() -> Self {
+        Self {
+            density: 0.6,
+            bushes: 0.3,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlantKind {
+    Tree,
+    Bush,
+}
+
+/// A single tree or bush standing on the ground voxel at `base`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Plant {
+    pub kind: PlantKind,
+    pub base: IVec3,
+    /// Trunk length above the ground (zero for bushes).
+    trunk: i32,
+    /// Canopy radius.
+    radius: i32,
+}
+
+impl Plant {
+    /// Lookup the plant voxel at some position.
+    pub fn voxel(&self, pos: IVec3) -> Option<Voxel> {
+        let above = pos.y - self.base.y;
+        if above <= 0 {
+            return None;
+        }
+
+        let center = self.base + IVec3::Y * self.trunk.max(1);
+        if (pos - center).length_squared() <= self.radius * (self.radius + 1) {
+            return Some(match self.kind {
+                PlantKind::Tree => LEAVES,
+                PlantKind::Bush => BUSH,
+            });
+        }
+
+        (pos.x == self.base.x && pos.z == self.base.z && above <= self.trunk).then_some(TRUNK)
+    }
+
+    /// Highest y coordinate covered by the plant.
+    pub fn top(&self) -> i32 {
+        self.base.y + self.trunk.max(1) + self.radius
+    }
+}
+
+/// Seeded placement of plants on a grid over the (x, z) plane.
+#[derive(Clone)]
+pub struct Vegetation {
+    settings: VegetationSettings,
+    seed: u32,
+}
+
+impl Vegetation {
+    pub fn new(seed: u32, settings: VegetationSettings) -> Self {
+        Self { settings, seed }
+    }
+
+    /// Finds the plant (if any) whose cell contains the column at (x, z).
+    ///
+    /// `ground` returns the ground height and a density multiplier for a column, or `None` if nothing can grow there.
+    pub fn plant(
+        &self,
+        x: i32,
+        z: i32,
+        ground: impl FnOnce(i32, i32) -> Option<(i32, f64)>,
+    ) -> Option<Plant> {
+        let cell_x = x.div_euclid(CELL_SIZE);
+        let cell_z = z.div_euclid(CELL_SIZE);
+        let roll = |salt| unit(hash(self.seed, cell_x, cell_z, salt));
+
+        let span = CELL_SIZE - 2 * MAX_RADIUS;
+        let px = cell_x * CELL_SIZE + MAX_RADIUS + (roll(1) * span as f64) as i32;
+        let pz = cell_z * CELL_SIZE + MAX_RADIUS + (roll(2) * span as f64) as i32;
+
+        let (ground_y, density) = ground(px, pz)?;
+        if roll(0) >= self.settings.density * density {
+            return None;
+        }
+
+        let plant = if roll(3) < self.settings.bushes {
+            Plant {
+                kind: PlantKind::Bush,
+                base: IVec3::new(px, ground_y, pz),
+                trunk: 0,
+                radius: 1 + (roll(4) * 2.0) as i32,
+            }
+        } else {
+            Plant {
+                kind: PlantKind::Tree,
+                base: IVec3::new(px, ground_y, pz),
+                trunk: 4 + (roll(4) * 3.0) as i32,
+                radius: 2 + (roll(5) * 2.0) as i32,
+            }
+        };
+        Some(plant)
+    }
+}
+
+/// Hashes a grid cell into a random value.
+pub(super) fn hash(seed: u32, x: i32, z: i32, salt: u32) -> u32 {
+    let mut h = seed
+        ^ (x as u32).wrapping_mul(0x27d4_eb2d)
+        ^ (z as u32).wrapping_mul(0x1656_67b1)
+        ^ salt.wrapping_mul(0x9e37_79b9);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x85eb_ca6b);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0xc2b2_ae35);
+    h ^ (h >> 16)
+}
+
+/// Maps a hash to [0, 1).
+pub(super) fn unit(h: u32) -> f64 {
+    h as f64 / (u32::MAX as f64 + 1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plants_stay_in_their_cell() {
+        let vegetation = Vegetation::new(
+            7,
+            VegetationSettings {
+                density: 1.0,
+                bushes: 0.5,
+            },
+        );
+
+        for x in -50..50 {
+            for z in -50..50 {
+                let plant = vegetation
+                    .plant(x, z, |_, _| Some((10, 1.0)))
+                    .expect("every cell should have a plant");
+
+                // every column of a cell finds the same plant, which fits inside the cell
+                assert_eq!(plant.base.x.div_euclid(CELL_SIZE), x.div_euclid(CELL_SIZE));
+                assert_eq!(plant.base.z.div_euclid(CELL_SIZE), z.div_euclid(CELL_SIZE));
+                assert!((plant.base.x - x).abs() < CELL_SIZE);
+                assert!(plant.radius <= MAX_RADIUS);
+            }
+        }
+    }
+
+    #[test]
+    fn unsuitable_ground_has_no_plants() {
+        let vegetation = Vegetation::new(7, VegetationSettings::default());
+        for x in (0..200).step_by(CELL_SIZE as usize) {
+            assert!(vegetation.plant(x, 0, |_, _| None).is_none());
+            assert!(vegetation.plant(x, 0, |_, _| Some((10, 0.0))).is_none());
+        }
+    }
+
+    #[test]
+    fn tree_shape() {
+        let tree = Plant {
+            kind: PlantKind::Tree,
+            base: IVec3::new(0, 10, 0),
+            trunk: 5,
+            radius: 2,
+        };
+
+        assert_eq!(tree.voxel(IVec3::new(0, 10, 0)), None);
+        assert_eq!(tree.voxel(IVec3::new(0, 11, 0)), Some(TRUNK));
+        assert_eq!(tree.voxel(IVec3::new(0, 15, 0)), Some(LEAVES));
+        assert_eq!(tree.voxel(IVec3::new(2, 15, 0)), Some(LEAVES));
+        assert_eq!(tree.voxel(IVec3::new(0, tree.top(), 0)), Some(LEAVES));
+        assert_eq!(tree.voxel(IVec3::new(0, tree.top() + 1, 0)), None);
+        assert_eq!(tree.voxel(IVec3::new(1, 11, 0)), None);
+    }
+}
@@ -0,0 +1,135 @@
+use glam::U8Vec3;
+use noise::{NoiseFn, Perlin};
+
+use super::{TerrainSettings, Voxel, VoxelKind};
+
+/// Color of water voxels, kept distinct from every terrain color so water can be told apart later.
+pub const WATER: Voxel = Voxel::new(U8Vec3::new(30, 110, 220), VoxelKind::WATER);
+
+/// Size of the river network in voxels (noise frequency is the inverse).
+const RIVER_SCALE: f64 = 250.0;
+
+/// Rivers narrow and stop this far above sea level (as a fraction of the terrain height), so they don't run over mountain tops.
+const RIVER_HEIGHT: f64 = 0.4;
+
+/// Strength of the domain warp that makes rivers meander.
+const MEANDER: f64 = 0.4;
+
+/// Settings for filling the terrain with seas, lakes, and rivers.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WaterSettings {
+    /// Everything below this height is filled with water.
+    pub sea_level: i32,
+    /// Carve river channels into the land above sea level.
+    pub rivers: bool,
+    /// Half width of rivers in noise units (larger values make wider rivers).
+    pub river_width: f64,
+    /// Depth of river channels at their center.
+    pub river_depth: f64,
+}
+
+impl Default for WaterSettings {
+    fn default() -> Self {
+        Self {
+            sea_level: TerrainSettings::default().sea_level(),
+            rivers: true,
+            river_width: 0.08,
+            river_depth: 4.0,
+        }
+    }
+}
+
+/// Noise fields used to lay out rivers.
+#[derive(Clone)]
+pub struct Water {
+    settings: WaterSettings,
+    river_noise: Perlin,
+    warp_noise: Perlin,
+}
+
+impl Water {
+    pub fn new(seed: u32, settings: WaterSettings) -> Self {
+        Self {
+            settings,
+            river_noise: Perlin::new(seed.wrapping_add(5)),
+            warp_noise: Perlin::new(seed.wrapping_add(6)),
+        }
+    }
+
+    /// Carves rivers into a column of height `terrain_y`, returning the new ground height and the height of the water on top.
+    ///
+    /// `max_height` is the max height of the terrain.
+    /// There is water in the column if the returned water height is above the ground.
+    pub fn column(&self, x: i32, z: i32, terrain_y: i32, max_height: i32) -> (i32, i32) {
+        let settings = self.settings;
+        if terrain_y < settings.sea_level || !settings.rivers {
+            return (terrain_y, settings.sea_level);
+        }
+
+        // rivers follow the zero crossings of the noise, the warp bends them into meanders
+        let p = [x as f64 / RIVER_SCALE, z as f64 / RIVER_SCALE];
+        let warp = self.warp_noise.get(p) * MEANDER;
+        let distance = self.river_noise.get([p[0] + warp, p[1] - warp]).abs();
+        let fade =
+            1.0 - (terrain_y - settings.sea_level) as f64 / (RIVER_HEIGHT * max_height as f64);
+        let width = settings.river_width * fade;
+        if distance >= width {
+            return (terrain_y, settings.sea_level);
+        }
+
+        // deepest in the middle of the channel, and the water stays a voxel below the banks
+        let center = 1.0 - distance / width;
+        let ground_y = terrain_y - (center.sqrt() * settings.river_depth).round() as i32;
+        (ground_y, terrain_y - 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn below_sea_level_is_flooded() {
+        let water = Water::new(1, WaterSettings::default());
+        let sea_level = WaterSettings::default().sea_level;
+        let height = TerrainSettings::default().height;
+
+        for terrain_y in 0..sea_level {
+            assert_eq!(
+                water.column(3, 4, terrain_y, height),
+                (terrain_y, sea_level)
+            );
+        }
+    }
+
+    #[test]
+    fn rivers_are_carved() {
+        let water = Water::new(1, WaterSettings::default());
+        let land = 40;
+        let height = TerrainSettings::default().height;
+
+        let mut river = 0;
+        for x in -300..300 {
+            for z in (-300..300).step_by(10) {
+                let (ground_y, water_y) = water.column(x, z, land, height);
+                assert!(ground_y <= land);
+                if ground_y < land {
+                    river += 1;
+                    assert_eq!(water_y, land - 1);
+                }
+            }
+        }
+        assert!(river > 0, "no rivers were carved");
+
+        let dry = Water::new(
+            1,
+            WaterSettings {
+                rivers: false,
+                ..Default::default()
+            },
+        );
+        for x in -300..300 {
+            assert_eq!(dry.column(x, 0, land, height).0, land);
+        }
+    }
+}
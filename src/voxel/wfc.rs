@@ -0,0 +1,484 @@
+use std::{error::Error, fmt, ops::Range};
+
+use glam::{IVec2, IVec3, U8Vec3};
+
+use super::{
+    grid::VoxelGrid,
+    structure::Prefab,
+    vegetation::{hash, unit},
+    Voxel, VoxelKind, VoxelSource,
+};
+
+/// Width of a tile in voxels.
+pub const TILE_SIZE: i32 = 8;
+
+/// Height of the tile models in voxels.
+const TILE_HEIGHT: i32 = 16;
+
+/// Layers of ground at the bottom of every tile, the top one is the surface at y = 0.
+const GROUND: i32 = 3;
+
+/// Number of times the collapse is restarted with a new seed after running into a contradiction.
+const ATTEMPTS: u32 = 10;
+
+const DIRT: Voxel = Voxel::new(U8Vec3::new(120, 85, 55), VoxelKind::DIRT);
+const GRASS: Voxel = Voxel::new(U8Vec3::new(90, 160, 60), VoxelKind::GRASS);
+const ROAD: Voxel = Voxel::new(U8Vec3::new(140, 135, 125), VoxelKind::GRAVEL);
+const TRUNK: Voxel = Voxel::new(U8Vec3::new(100, 70, 40), VoxelKind::WOOD);
+const LEAVES: Voxel = Voxel::new(U8Vec3::new(35, 115, 35), VoxelKind::LEAVES);
+
+/// What runs across the edge of a tile, neighbouring tiles must agree on their shared edge.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Socket {
+    Grass,
+    /// A road through the middle of the edge.
+    Road,
+}
+
+/// A piece of the scene that is fitted together with others by their edges.
+#[derive(Clone, Debug)]
+pub struct Tile {
+    pub name: &'static str,
+    /// Voxels of the tile, `TILE_SIZE` wide with the ground at the bottom.
+    pub model: VoxelGrid,
+    /// Sockets on the +x, +z, -x and -z edges, in that order.
+    pub edges: [Socket; 4],
+    /// How often the tile is picked compared to the others.
+    pub weight: f64,
+}
+
+impl Tile {
+    /// Rotates the tile a quarter turn around the y-axis, turning +x into +z.
+    fn rotated(&self) -> Self {
+        let size = self.model.size();
+        let mut model = VoxelGrid::new(size);
+        for y in 0..size.y {
+            for z in 0..size.z {
+                for x in 0..size.x {
+                    let voxel = self.model.get(IVec3::new(x, y, z));
+                    model.set(IVec3::new(size.z - 1 - z, y, x), voxel);
+                }
+            }
+        }
+
+        let mut edges = self.edges;
+        edges.rotate_right(1);
+        Self {
+            model,
+            edges,
+            ..self.clone()
+        }
+    }
+
+    /// All distinct quarter turns of the tile, sharing its weight between them.
+    fn rotations(self, count: usize) -> Vec<Self> {
+        let mut tiles = vec![Self {
+            weight: self.weight / count as f64,
+            ..self
+        }];
+        for i in 1..count {
+            tiles.push(tiles[i - 1].rotated());
+        }
+        tiles
+    }
+}
+
+/// A tile model with the ground filled in and roads running from the middle to `edges`.
+fn ground(edges: [Socket; 4]) -> VoxelGrid {
+    let mut model = VoxelGrid::new(IVec3::new(TILE_SIZE, TILE_HEIGHT, TILE_SIZE));
+    let road = TILE_SIZE / 2 - 1..TILE_SIZE / 2 + 1;
+    for z in 0..TILE_SIZE {
+        for x in 0..TILE_SIZE {
+            let on_road = |edge: usize, along: i32, across: i32| {
+                edges[edge] == Socket::Road && road.contains(&across) && along >= road.start
+            };
+            let center = road.contains(&x) && road.contains(&z);
+            let paved = edges.contains(&Socket::Road) && center
+                || on_road(0, x, z)
+                || on_road(1, z, x)
+                || on_road(2, TILE_SIZE - 1 - x, z)
+                || on_road(3, TILE_SIZE - 1 - z, x);
+
+            for y in 0..GROUND {
+                let voxel = match y {
+                    _ if y < GROUND - 1 => DIRT,
+                    _ if paved => ROAD,
+                    _ => GRASS,
+                };
+                model.set(IVec3::new(x, y, z), Some(voxel));
+            }
+        }
+    }
+    model
+}
+
+/// The built-in tiles: roads winding between houses, trees and grass.
+///
+/// Every combination of road and grass edges has a tile, so the collapse never runs into a contradiction.
+pub fn town() -> Vec<Tile> {
+    use Socket::{Grass, Road};
+
+    let plain = |name, edges, weight, rotations| {
+        Tile {
+            name,
+            model: ground(edges),
+            edges,
+            weight,
+        }
+        .rotations(rotations)
+    };
+
+    let mut tree = ground([Grass; 4]);
+    let middle = IVec3::new(TILE_SIZE / 2, GROUND, TILE_SIZE / 2);
+    for y in 0..TILE_HEIGHT - GROUND {
+        for z in 0..TILE_SIZE {
+            for x in 0..TILE_SIZE {
+                let pos = IVec3::new(x, GROUND + y, z);
+                let canopy = (pos - middle - IVec3::Y * 7).length_squared() <= 10;
+                let trunk = x == middle.x && z == middle.z && y < 7;
+                let voxel = canopy.then_some(LEAVES).or(trunk.then_some(TRUNK));
+                if voxel.is_some() {
+                    tree.set(pos, voxel);
+                }
+            }
+        }
+    }
+
+    // the hut's door is on its -z side, which is turned to face the road
+    let mut house = ground([Grass, Grass, Grass, Road]);
+    let hut = Prefab::hut().grid;
+    let size = hut.size();
+    for y in 0..size.y {
+        for z in 0..size.z {
+            for x in 0..size.x {
+                if let Some(voxel) = hut.get(IVec3::new(x, y, z)) {
+                    house.set(IVec3::new(x + 1, y + GROUND - 1, z + 1), Some(voxel));
+                }
+            }
+        }
+    }
+
+    let mut tiles = plain("grass", [Grass; 4], 4.0, 1);
+    tiles.push(Tile {
+        name: "tree",
+        model: tree,
+        edges: [Grass; 4],
+        weight: 2.0,
+    });
+    tiles.extend(
+        Tile {
+            name: "house",
+            model: house,
+            edges: [Grass, Grass, Grass, Road],
+            weight: 2.0,
+        }
+        .rotations(4),
+    );
+    tiles.extend(plain("straight", [Road, Grass, Road, Grass], 2.0, 2));
+    tiles.extend(plain("corner", [Road, Road, Grass, Grass], 1.0, 4));
+    tiles.extend(plain("junction", [Road, Road, Road, Grass], 0.5, 4));
+    tiles.extend(plain("crossing", [Road; 4], 0.2, 1));
+    tiles
+}
+
+/// The collapse found no way to fit the tiles together.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Contradiction;
+
+impl fmt::Display for Contradiction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "tiles could not be fitted together after {ATTEMPTS} attempts"
+        )
+    }
+}
+
+impl Error for Contradiction {}
+
+/// Offsets to the neighbouring cell across each edge, in the same order as [`Tile::edges`].
+const DIRECTIONS: [IVec2; 4] = [IVec2::X, IVec2::Y, IVec2::NEG_X, IVec2::NEG_Y];
+
+/// A grid of tiles fitted together with wave function collapse.
+///
+/// Every cell starts out able to hold any tile. The cell with the fewest choices left is collapsed to a random one
+/// of them, and the choices of its neighbours are narrowed down to tiles with matching edges, until every cell is
+/// decided.
+#[derive(Clone, Debug)]
+pub struct TileMap {
+    tiles: Vec<Tile>,
+    /// Index of the tile in each cell.
+    cells: Vec<usize>,
+    /// Scene position of the first cell's lowest corner.
+    origin: IVec3,
+    size: IVec2,
+}
+
+impl TileMap {
+    /// Fills the area from `min` to `max` (in voxels) with tiles, with their ground surface at y = 0.
+    pub fn new(seed: u32, tiles: Vec<Tile>, min: IVec2, max: IVec2) -> Result<Self, Contradiction> {
+        assert!(
+            (1..=64).contains(&tiles.len()),
+            "tile sets hold 1 to 64 tiles"
+        );
+        let size = (max - min).max(IVec2::ZERO) + TILE_SIZE - 1;
+        let size = size / TILE_SIZE;
+
+        let cells = (0..ATTEMPTS)
+            .find_map(|attempt| collapse(seed.wrapping_add(attempt), &tiles, size))
+            .ok_or(Contradiction)?;
+        Ok(Self {
+            tiles,
+            cells,
+            origin: IVec3::new(min.x, 1 - GROUND, min.y),
+            size,
+        })
+    }
+
+    /// Tile chosen for the cell covering the column at (x, z).
+    fn tile(&self, x: i32, z: i32) -> Option<(&Tile, IVec2)> {
+        let local = IVec2::new(x - self.origin.x, z - self.origin.z);
+        let cell = local.div_euclid(IVec2::splat(TILE_SIZE));
+        if cell.cmplt(IVec2::ZERO).any() || cell.cmpge(self.size).any() {
+            return None;
+        }
+        let tile = &self.tiles[self.cells[(cell.x + cell.y * self.size.x) as usize]];
+        Some((tile, local - cell * TILE_SIZE))
+    }
+
+    /// Number of cells holding each tile, by name.
+    pub fn counts(&self) -> Vec<(&'static str, usize)> {
+        let mut counts: Vec<(&'static str, usize)> = Vec::new();
+        for &cell in &self.cells {
+            let name = self.tiles[cell].name;
+            match counts.iter_mut().find(|(n, _)| *n == name) {
+                Some((_, count)) => *count += 1,
+                None => counts.push((name, 1)),
+            }
+        }
+        counts
+    }
+}
+
+/// Runs one attempt at collapsing a grid of cells, returning the tile of each cell.
+fn collapse(seed: u32, tiles: &[Tile], size: IVec2) -> Option<Vec<usize>> {
+    // tiles allowed next to each tile, across each of its edges
+    let fits: Vec<[u64; 4]> = tiles
+        .iter()
+        .map(|tile| {
+            std::array::from_fn(|edge| {
+                let opposite = (edge + 2) % 4;
+                tiles
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, other)| other.edges[opposite] == tile.edges[edge])
+                    .fold(0, |mask, (i, _)| mask | 1 << i)
+            })
+        })
+        .collect();
+
+    let all = u64::MAX >> (64 - tiles.len());
+    let mut cells = vec![all; size.element_product() as usize];
+    // rule out tiles that can't fit anywhere before choosing any
+    let mut stack: Vec<usize> = (0..cells.len()).collect();
+    if !propagate(&mut cells, &mut stack, &fits, size) {
+        return None;
+    }
+    for step in 0.. {
+        let roll = |salt| unit(hash(seed, step, 0, salt));
+
+        // the undecided cell with the fewest choices, ties are broken randomly
+        let Some((next, _)) = cells
+            .iter()
+            .enumerate()
+            .filter(|(_, &mask)| mask.count_ones() > 1)
+            .map(|(i, &mask)| {
+                let noise = unit(hash(seed, step, i as i32, 1));
+                (i, entropy(tiles, mask) + noise * 1e-3)
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+        else {
+            break;
+        };
+
+        let options: Vec<usize> = (0..tiles.len())
+            .filter(|&i| cells[next] & 1 << i != 0)
+            .collect();
+        let total: f64 = options.iter().map(|&i| tiles[i].weight).sum();
+        let mut pick = roll(2) * total;
+        let chosen = options
+            .iter()
+            .copied()
+            .find(|&i| {
+                pick -= tiles[i].weight;
+                pick < 0.0
+            })
+            .unwrap_or(options[options.len() - 1]);
+        cells[next] = 1 << chosen;
+
+        stack.push(next);
+        if !propagate(&mut cells, &mut stack, &fits, size) {
+            return None;
+        }
+    }
+
+    Some(
+        cells
+            .into_iter()
+            .map(|mask| mask.trailing_zeros() as usize)
+            .collect(),
+    )
+}
+
+/// Narrows down the choices of the neighbours of the cells on the stack, and of their neighbours in turn, until
+/// every remaining choice fits next to its neighbours. Returns false if some cell is left without any choices.
+fn propagate(cells: &mut [u64], stack: &mut Vec<usize>, fits: &[[u64; 4]], size: IVec2) -> bool {
+    while let Some(i) = stack.pop() {
+        let cell = IVec2::new(i as i32 % size.x, i as i32 / size.x);
+        let allowed: [u64; 4] = std::array::from_fn(|edge| {
+            (0..fits.len())
+                .filter(|&t| cells[i] & 1 << t != 0)
+                .fold(0, |mask, t| mask | fits[t][edge])
+        });
+        for (edge, dir) in DIRECTIONS.iter().enumerate() {
+            let neighbour = cell + *dir;
+            if neighbour.cmplt(IVec2::ZERO).any() || neighbour.cmpge(size).any() {
+                continue;
+            }
+            let n = (neighbour.x + neighbour.y * size.x) as usize;
+            let narrowed = cells[n] & allowed[edge];
+            if narrowed == 0 {
+                return false;
+            }
+            if narrowed != cells[n] {
+                cells[n] = narrowed;
+                stack.push(n);
+            }
+        }
+    }
+    true
+}
+
+/// Shannon entropy of the choice between the tiles left in a cell, weighted by how often each is picked.
+fn entropy(tiles: &[Tile], mask: u64) -> f64 {
+    let weights = tiles
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| mask & 1 << i != 0)
+        .map(|(_, tile)| tile.weight);
+    let total: f64 = weights.clone().sum();
+    let sum: f64 = weights.map(|w| w * w.ln()).sum();
+    total.ln() - sum / total
+}
+
+impl VoxelSource for TileMap {
+    fn lookup(&self, pos: IVec3) -> Option<Voxel> {
+        let (tile, local) = self.tile(pos.x, pos.z)?;
+        tile.model
+            .get(IVec3::new(local.x, pos.y - self.origin.y, local.y))
+    }
+
+    fn column(&self, x: i32, z: i32, ys: Range<i32>, out: &mut [Option<Voxel>]) {
+        debug_assert_eq!(out.len(), ys.len(), "column length mismatch");
+
+        let Some((tile, local)) = self.tile(x, z) else {
+            out.fill(None);
+            return;
+        };
+        for (y, voxel) in ys.zip(out) {
+            *voxel = tile
+                .model
+                .get(IVec3::new(local.x, y - self.origin.y, local.y));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn town_map(seed: u32) -> TileMap {
+        TileMap::new(seed, town(), IVec2::splat(-64), IVec2::splat(64)).expect("town tiles fit")
+    }
+
+    /// Checks if the middle of a tile's edge is paved.
+    fn paved(tile: &Tile, edge: usize) -> bool {
+        let middle = TILE_SIZE / 2;
+        let end = TILE_SIZE - 1;
+        let (x, z) = [(end, middle), (middle, end), (0, middle), (middle, 0)][edge];
+        tile.model.get(IVec3::new(x, GROUND - 1, z)).unwrap() == ROAD
+    }
+
+    #[test]
+    fn models_match_edges() {
+        let tiles = town();
+        assert_eq!(tiles.len(), 17);
+        for tile in &tiles {
+            for edge in 0..4 {
+                assert_eq!(
+                    paved(tile, edge),
+                    tile.edges[edge] == Socket::Road,
+                    "{} {edge}",
+                    tile.name
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn neighbours_fit() {
+        let map = town_map(5);
+        assert_eq!(map.size, IVec2::splat(16));
+        for z in 0..map.size.y {
+            for x in 0..map.size.x {
+                let tile = |x, z| &map.tiles[map.cells[(x + z * map.size.x) as usize]];
+                if x + 1 < map.size.x {
+                    assert_eq!(tile(x, z).edges[0], tile(x + 1, z).edges[2]);
+                }
+                if z + 1 < map.size.y {
+                    assert_eq!(tile(x, z).edges[1], tile(x, z + 1).edges[3]);
+                }
+            }
+        }
+
+        let counts = map.counts();
+        for name in ["grass", "tree", "house", "straight", "corner"] {
+            assert!(counts.iter().any(|(n, _)| *n == name), "{counts:?}");
+        }
+    }
+
+    #[test]
+    fn deterministic() {
+        assert_eq!(town_map(7).cells, town_map(7).cells);
+        assert_ne!(town_map(7).cells, town_map(8).cells);
+    }
+
+    #[test]
+    fn contradiction() {
+        // a lone road end can never be matched
+        let tiles = vec![Tile {
+            name: "dead end",
+            model: ground([Socket::Road, Socket::Grass, Socket::Grass, Socket::Grass]),
+            edges: [Socket::Road, Socket::Grass, Socket::Grass, Socket::Grass],
+            weight: 1.0,
+        }];
+        let map = TileMap::new(0, tiles, IVec2::ZERO, IVec2::splat(32));
+        assert!(matches!(map, Err(Contradiction)));
+    }
+
+    #[test]
+    fn column_matches_lookup() {
+        let map = town_map(2);
+        assert!(map.lookup(IVec3::ZERO).is_some());
+        assert!(map.lookup(IVec3::new(0, -GROUND, 0)).is_none());
+
+        let ys = -5..20;
+        let mut column = vec![None; ys.len()];
+        for (x, z) in [(0, 0), (-64, 63), (10, -30), (64, 0), (-65, 4)] {
+            map.column(x, z, ys.clone(), &mut column);
+            for (y, voxel) in ys.clone().zip(&column) {
+                assert_eq!(*voxel, map.lookup(IVec3::new(x, y, z)), "{x} {y} {z}");
+            }
+        }
+    }
+}